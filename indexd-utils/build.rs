@@ -0,0 +1,20 @@
+fn main() {
+    // the gRPC service is generated from its definition, which needs protoc
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/transfers.proto").expect("compiling proto/transfers.proto");
+
+    // the C header is generated from the bindings themselves so the two
+    // can't drift apart
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo::rerun-if-changed=src/ffi.rs");
+        println!("cargo::rerun-if-changed=cbindgen.toml");
+        let config = cbindgen::Config::from_file("cbindgen.toml").expect("reading cbindgen.toml");
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src("src/ffi.rs")
+            .generate()
+            .expect("generating the C header")
+            .write_to_file("include/indexd_utils.h");
+    }
+}
@@ -0,0 +1,96 @@
+//! Uploads and downloads files through a daemon started with
+//! `upload-rs daemon --grpc 127.0.0.1:7071`, sending its token from
+//! `INDEXD_DAEMON_TOKEN` if it has one:
+//!
+//! ```sh
+//! cargo run --features grpc --example grpc-client -- upload notes.txt notes
+//! cargo run --features grpc --example grpc-client -- download notes > notes.txt
+//! ```
+
+use std::env;
+use std::error::Error;
+
+use indexd_utils::grpc::proto::transfers_client::TransfersClient;
+use indexd_utils::grpc::proto::{DownloadRequest, UploadRequest, upload_request, upload_response};
+use tokio::fs::File;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+
+const ADDR: &str = "http://127.0.0.1:7071";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let channel = Channel::from_static(ADDR).connect().await?;
+    let token = env::var("INDEXD_DAEMON_TOKEN").ok();
+    let mut client = TransfersClient::with_interceptor(channel, move |mut request: Request<()>| {
+        if let Some(token) = &token {
+            let value = format!("Bearer {token}")
+                .parse()
+                .map_err(|_| Status::invalid_argument("invalid token"))?;
+            request.metadata_mut().insert("authorization", value);
+        }
+        Ok(request)
+    });
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["upload", path, name] => {
+            let mut file = File::open(path).await?;
+            let (tx, rx) = mpsc::channel(4);
+            let name = name.to_string();
+            tokio::spawn(async move {
+                let first = UploadRequest {
+                    message: Some(upload_request::Message::Name(name)),
+                };
+                if tx.send(first).await.is_err() {
+                    return;
+                }
+                let mut buf = vec![0; 1 << 20];
+                while let Ok(n) = file.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                    let data = UploadRequest {
+                        message: Some(upload_request::Message::Data(buf[..n].to_vec())),
+                    };
+                    if tx.send(data).await.is_err() {
+                        return;
+                    }
+                }
+            });
+
+            let mut responses = client.upload(ReceiverStream::new(rx)).await?.into_inner();
+            while let Some(response) = responses.message().await? {
+                match response.message {
+                    Some(upload_response::Message::Progress(read)) => {
+                        eprintln!("{read} bytes read")
+                    }
+                    Some(upload_response::Message::Done(done)) => {
+                        eprintln!("stored {} ({} bytes)", done.name, done.size)
+                    }
+                    None => {}
+                }
+            }
+        }
+        ["download", name] => {
+            let request = DownloadRequest {
+                name: name.to_string(),
+            };
+            let mut chunks = client.download(request).await?.into_inner();
+            let mut stdout = io::stdout();
+            while let Some(chunk) = chunks.message().await? {
+                stdout.write_all(&chunk.data).await?;
+            }
+            stdout.flush().await?;
+        }
+        _ => return Err("usage: grpc-client upload <path> <name> | download <name>".into()),
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+#ifndef INDEXD_UTILS_H
+#define INDEXD_UTILS_H
+
+/* Generated from src/ffi.rs by build.rs; don't edit it by hand. */
+
+#include <stdint.h>
+
+/**
+ * How a call went. The failures match the exit statuses of the command
+ * line tool.
+ */
+typedef enum IndexdUtilsStatus {
+  INDEXD_UTILS_STATUS_OK = 0,
+  INDEXD_UTILS_STATUS_FAILURE = 1,
+  /**
+   * An argument was missing or invalid, or the object isn't catalogued.
+   */
+  INDEXD_UTILS_STATUS_USAGE = 2,
+  INDEXD_UTILS_STATUS_CONFIG = 3,
+  /**
+   * The app has to be approved or the account funded.
+   */
+  INDEXD_UTILS_STATUS_ACCOUNT = 4,
+  /**
+   * The app or the hosts couldn't be reached; trying again may help.
+   */
+  INDEXD_UTILS_STATUS_NETWORK = 5,
+  INDEXD_UTILS_STATUS_INTEGRITY = 6,
+  INDEXD_UTILS_STATUS_PARTIAL = 7,
+  INDEXD_UTILS_STATUS_INTERRUPTED = 130,
+} IndexdUtilsStatus;
+
+/**
+ * What a transfer runs with. Every field may be null.
+ */
+typedef struct IndexdUtilsOptions {
+  /**
+   * The config file; null for the default path.
+   */
+  const char *config;
+  /**
+   * The profile in the config; null for its default profile.
+   */
+  const char *profile;
+  /**
+   * Seals uploaded manifests under this passphrase, and opens sealed
+   * ones on download; null to store them plain.
+   */
+  const char *passphrase;
+} IndexdUtilsOptions;
+
+/**
+ * Called with the bytes transferred so far and the size of the object,
+ * on the thread that started the transfer, along with the `user_data`
+ * given to it.
+ */
+typedef void (*IndexdUtilsProgress)(void *user_data, uint64_t transferred, uint64_t total);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Uploads the file at `input` and catalogues it as `name`, or as the
+ * file's name if `name` is null. `options` may be null to use the
+ * defaults, and `progress` null to not report progress.
+ *
+ * # Safety
+ *
+ * Every non-null pointer must point to a NUL-terminated string or, for
+ * `options`, to an `IndexdUtilsOptions`, valid for the whole call.
+ */
+IndexdUtilsStatus indexd_utils_upload(const struct IndexdUtilsOptions *options,
+                                      const char *input,
+                                      const char *name,
+                                      IndexdUtilsProgress progress,
+                                      void *user_data);
+
+/**
+ * Downloads the file catalogued as `name` to `output`, which is only
+ * replaced once the whole file has been downloaded and checked. `options`
+ * and `progress` may be null as for `indexd_utils_upload`.
+ *
+ * # Safety
+ *
+ * As for `indexd_utils_upload`.
+ */
+IndexdUtilsStatus indexd_utils_download(const struct IndexdUtilsOptions *options,
+                                        const char *name,
+                                        const char *output,
+                                        IndexdUtilsProgress progress,
+                                        void *user_data);
+
+/**
+ * Returns the message of the last failed call on this thread, or null if
+ * none failed. It stays valid until the next call on the thread.
+ */
+const char *indexd_utils_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* INDEXD_UTILS_H */
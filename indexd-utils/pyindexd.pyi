@@ -0,0 +1,38 @@
+from os import PathLike
+from typing import Callable, Optional, Union
+
+Path = Union[str, PathLike[str]]
+Progress = Callable[[int, int], object]
+
+class IndexdError(Exception): ...
+class UsageError(IndexdError): ...
+class ConfigError(IndexdError): ...
+class AccountError(IndexdError): ...
+class NetworkError(IndexdError): ...
+class IntegrityError(IndexdError): ...
+
+class VerifyReport:
+    healthy: int
+    degraded: int
+    unrecoverable: int
+    checksum_ok: bool
+    ok: bool
+
+class Client:
+    async def upload(
+        self,
+        path: Path,
+        name: Optional[str] = None,
+        passphrase: Optional[str] = None,
+        progress: Optional[Progress] = None,
+    ) -> str: ...
+    async def download(
+        self,
+        name: str,
+        output: Path,
+        passphrase: Optional[str] = None,
+        progress: Optional[Progress] = None,
+    ) -> None: ...
+    async def verify(self, name: str, passphrase: Optional[str] = None) -> VerifyReport: ...
+
+async def connect(config: Optional[Path] = None, profile: Optional[str] = None) -> Client: ...
@@ -0,0 +1,570 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The plaintext bytes sealed together by default.
+pub const DEFAULT_BLOCK_SIZE: u32 = 1 << 16;
+
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+
+/// The bytes every block adds: its nonce and its tag.
+const OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    #[serde(rename = "xchacha20poly1305")]
+    XChaCha20Poly1305,
+}
+
+/// Seals and opens the blocks of an object.
+///
+/// [`Cipher`] implements it in software with the key it carries. Other
+/// implementations can keep their key elsewhere, such as in an HSM, and
+/// stream through [`SealReader::new`] and [`OpenWriter::new`].
+pub trait ObjectCipher: Send + Sync {
+    /// The bytes sealing adds to every block.
+    fn overhead(&self) -> usize;
+
+    /// Seals block `index`, which must be bound to it and to whether it is
+    /// the last block, so that the block fails to open anywhere else.
+    fn seal_block(&self, index: u64, last: bool, block: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Opens block `index`, failing with [`io::ErrorKind::InvalidData`] if
+    /// it was altered or moved.
+    fn open_block(&self, index: u64, last: bool, sealed: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Whether streams end with a block sealed as the last one, which
+    /// opening a whole stream then requires.
+    fn marks_last(&self) -> bool {
+        true
+    }
+}
+
+/// How an object's plaintext was sealed before the SDK encrypted it.
+///
+/// The SDK's own encryption of shards isn't authenticated, so a corrupt or
+/// swapped shard that still decodes would come back as wrong data. Sealed
+/// objects are split into blocks of `block_size` bytes, each encrypted with
+/// a random nonce and its index in the object as associated data, so a
+/// block that was altered or moved fails to open instead.
+///
+/// The associated data also says whether the block is the last one, which
+/// is always shorter than `block_size`: a stream whose length is a whole
+/// number of blocks ends with an empty one. Dropping the blocks at the end
+/// of a stream then leaves it without its last block, so it fails to open
+/// rather than coming back short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cipher {
+    pub algorithm: Algorithm,
+    pub block_size: u32,
+    #[serde(with = "hex")]
+    pub key: [u8; 32],
+    /// Whether the last block is marked. Objects sealed before it was
+    /// aren't, and open as they were sealed, without the check.
+    #[serde(default)]
+    pub marks_last: bool,
+}
+
+impl Cipher {
+    /// Returns a cipher with a fresh random key.
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            block_size: DEFAULT_BLOCK_SIZE,
+            key: rand::random(),
+            marks_last: true,
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size as usize
+    }
+
+    fn sealed_block_size(&self) -> usize {
+        self.block_size() + self.overhead()
+    }
+
+    /// Returns the block holding plaintext byte `offset`.
+    pub fn block(&self, offset: u64) -> u64 {
+        offset / self.block_size as u64
+    }
+
+    /// Returns where block `index` starts in the stored bytes.
+    pub fn stored_offset(&self, index: u64) -> u64 {
+        index * self.sealed_block_size() as u64
+    }
+
+    /// Wraps `reader` so that it yields the sealed stream, numbering the
+    /// blocks from `first_block`.
+    pub fn seal<R>(&self, reader: R, first_block: u64) -> SealReader<R> {
+        SealReader::new(reader, Arc::new(*self), self.block_size(), first_block)
+    }
+
+    /// Seals a whole buffer as a stream of its own.
+    pub fn seal_bytes(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let blocks = data.len().div_ceil(self.block_size());
+        let mut sealed = Vec::with_capacity(data.len() + (blocks + 1) * OVERHEAD);
+        for (index, block) in data.chunks(self.block_size()).enumerate() {
+            let last = block.len() < self.block_size();
+            sealed.extend(self.seal_block(index as u64, last, block)?);
+        }
+        if self.marks_last && !data.is_empty() && data.len().is_multiple_of(self.block_size()) {
+            sealed.extend(self.seal_block(blocks as u64, true, &[])?);
+        }
+        Ok(sealed)
+    }
+
+    /// Wraps `writer` so that the sealed stream written to it, starting at
+    /// block `first_block`, comes out as plaintext.
+    pub fn open<W>(&self, writer: W, first_block: u64) -> OpenWriter<W> {
+        OpenWriter::new(writer, Arc::new(*self), self.block_size(), first_block)
+    }
+
+    fn aead(&self) -> XChaCha20Poly1305 {
+        match self.algorithm {
+            Algorithm::XChaCha20Poly1305 => XChaCha20Poly1305::new(Key::from_slice(&self.key)),
+        }
+    }
+
+    /// Returns the associated data of a block: its index, and whether it is
+    /// the last one unless this cipher predates marking it.
+    fn aad(&self, index: u64, last: bool) -> Vec<u8> {
+        let mut aad = index.to_le_bytes().to_vec();
+        if self.marks_last {
+            aad.push(last as u8);
+        }
+        aad
+    }
+}
+
+impl ObjectCipher for Cipher {
+    fn overhead(&self) -> usize {
+        OVERHEAD
+    }
+
+    fn seal_block(&self, index: u64, last: bool, block: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let payload = Payload {
+            msg: block,
+            aad: &self.aad(index, last),
+        };
+        let ciphertext = self
+            .aead()
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| io::Error::other(format!("failed to seal block {index}")))?;
+        let mut sealed = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    fn open_block(&self, index: u64, last: bool, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        if sealed.len() < OVERHEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("block {index} is truncated"),
+            ));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &self.aad(index, last),
+        };
+        self.aead()
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("block {index} failed authentication: it was altered or moved"),
+                )
+            })
+    }
+
+    fn marks_last(&self) -> bool {
+        self.marks_last
+    }
+}
+
+/// Seals everything read through it, one block at a time.
+pub struct SealReader<R> {
+    inner: R,
+    cipher: Arc<dyn ObjectCipher>,
+    index: u64,
+    plain: Box<[u8]>,
+    filled: usize,
+    eof: bool,
+    /// Whether the input ends the stream, so it is followed by the last
+    /// block if its own last block is full.
+    end: bool,
+    /// Whether the last block sealed was full.
+    full: bool,
+    sealed: Vec<u8>,
+    pos: usize,
+}
+
+impl<R> SealReader<R> {
+    /// Seals `reader` in blocks of `block_size` bytes, numbering them from
+    /// `first_block`.
+    pub fn new(
+        reader: R,
+        cipher: Arc<dyn ObjectCipher>,
+        block_size: usize,
+        first_block: u64,
+    ) -> Self {
+        Self {
+            inner: reader,
+            cipher,
+            index: first_block,
+            plain: vec![0; block_size].into_boxed_slice(),
+            filled: 0,
+            eof: false,
+            end: true,
+            full: false,
+            sealed: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Sets whether the input runs to the end of the stream, as it does
+    /// unless a stream is sealed in parts. Parts before the last must be a
+    /// whole number of blocks.
+    pub fn with_end(mut self, end: bool) -> Self {
+        self.end = end;
+        self
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for SealReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos == this.sealed.len() {
+            while this.filled < this.plain.len() && !this.eof {
+                let mut input = ReadBuf::new(&mut this.plain[this.filled..]);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut input))?;
+                let n = input.filled().len();
+                this.eof = n == 0;
+                this.filled += n;
+            }
+            // a stream ending on a full block ends with an empty last one
+            let terminate = this.full && this.end && this.cipher.marks_last();
+            if this.filled == 0 && !terminate {
+                return Poll::Ready(Ok(()));
+            }
+            this.full = this.filled == this.plain.len();
+            this.sealed =
+                this.cipher
+                    .seal_block(this.index, !this.full, &this.plain[..this.filled])?;
+            this.index += 1;
+            this.filled = 0;
+            this.pos = 0;
+        }
+        let n = buf.remaining().min(this.sealed.len() - this.pos);
+        buf.put_slice(&this.sealed[this.pos..this.pos + n]);
+        this.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Opens a sealed stream written through it. The last block is short, so
+/// it is only opened by `finish` or `poll_shutdown`.
+pub struct OpenWriter<W> {
+    inner: W,
+    cipher: Arc<dyn ObjectCipher>,
+    index: u64,
+    /// Whether the bytes written run to the end of the stream, so they
+    /// must end with its last block.
+    end: bool,
+    /// Whether the last block opened was full, so the stream's last block
+    /// is still to come.
+    awaiting_last: bool,
+    sealed: Vec<u8>,
+    sealed_block_size: usize,
+    plain: Vec<u8>,
+    pos: usize,
+}
+
+impl<W> OpenWriter<W> {
+    /// Opens the stream of blocks of `block_size` plaintext bytes written to
+    /// it, numbered from `first_block`, into `writer`.
+    pub fn new(
+        writer: W,
+        cipher: Arc<dyn ObjectCipher>,
+        block_size: usize,
+        first_block: u64,
+    ) -> Self {
+        let sealed_block_size = block_size + cipher.overhead();
+        Self {
+            inner: writer,
+            cipher,
+            index: first_block,
+            end: true,
+            awaiting_last: false,
+            sealed: Vec::with_capacity(sealed_block_size),
+            sealed_block_size,
+            plain: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Sets whether the bytes written run to the end of the stream, as they
+    /// do unless only a range of it is opened.
+    pub fn with_end(mut self, end: bool) -> Self {
+        self.end = end;
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let last = self.sealed.len() < self.sealed_block_size;
+        self.plain = self.cipher.open_block(self.index, last, &self.sealed)?;
+        self.awaiting_last = !last;
+        self.sealed.clear();
+        self.index += 1;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> OpenWriter<W> {
+    /// Writes the opened bytes waiting in the buffer to `inner`.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.pos < self.plain.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.plain[self.pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pos += n;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_finish(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        if !self.sealed.is_empty() {
+            self.open()?;
+            ready!(self.poll_drain(cx))?;
+        } else if self.end && self.awaiting_last && self.cipher.marks_last() {
+            // the last block is short, so it is always left to open here
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the stream is truncated: block {} is missing", self.index),
+            )));
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    /// Opens the last block and flushes everything to `inner`.
+    pub async fn finish(&mut self) -> io::Result<()> {
+        std::future::poll_fn(|cx| self.poll_finish(cx)).await
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for OpenWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        let n = buf.len().min(this.sealed_block_size - this.sealed.len());
+        this.sealed.extend_from_slice(&buf[..n]);
+        if this.sealed.len() == this.sealed_block_size {
+            this.open()?;
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_finish(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// A cipher with small blocks, so a short buffer spans several.
+    fn cipher() -> Cipher {
+        Cipher {
+            block_size: 16,
+            ..Cipher::new(Algorithm::XChaCha20Poly1305)
+        }
+    }
+
+    async fn open(cipher: &Cipher, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut writer = cipher.open(Vec::new(), 0);
+        writer.write_all(sealed).await?;
+        writer.finish().await?;
+        Ok(writer.into_inner())
+    }
+
+    #[tokio::test]
+    async fn seal_and_open() {
+        let cipher = cipher();
+        let data: Vec<u8> = (0..100).collect();
+        let sealed = cipher.seal_bytes(&data).unwrap();
+        // seven blocks, the last one short
+        assert_eq!(sealed.len(), data.len() + 7 * OVERHEAD);
+        assert_eq!(open(&cipher, &sealed).await.unwrap(), data);
+
+        let mut streamed = Vec::new();
+        cipher
+            .seal(&data[..], 0)
+            .read_to_end(&mut streamed)
+            .await
+            .unwrap();
+        assert_eq!(streamed.len(), sealed.len());
+        assert_eq!(open(&cipher, &streamed).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn empty() {
+        let cipher = cipher();
+        let sealed = cipher.seal_bytes(&[]).unwrap();
+        assert!(sealed.is_empty());
+        assert!(open(&cipher, &sealed).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn altered_block_fails() {
+        let cipher = cipher();
+        let mut sealed = cipher.seal_bytes(&[7; 40]).unwrap();
+        sealed[NONCE_SIZE + 3] ^= 1;
+        let err = open(&cipher, &sealed).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn moved_block_fails() {
+        let cipher = cipher();
+        let sealed = cipher.seal_bytes(&[7; 40]).unwrap();
+        let (first, rest) = sealed.split_at(cipher.sealed_block_size());
+        let (second, third) = rest.split_at(cipher.sealed_block_size());
+        let swapped = [second, first, third].concat();
+        let err = open(&cipher, &swapped).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // a block opened as part of another object fails too
+        let err = cipher.open_block(1, false, first).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // as does one opened as the last when it isn't, or the other way
+        let err = cipher.open_block(0, true, first).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = cipher.open_block(2, false, third).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn truncated_streams_fail() {
+        let cipher = cipher();
+        for len in [40, 48] {
+            let data = vec![7; len];
+            let sealed = cipher.seal_bytes(&data).unwrap();
+            // whole blocks end with an empty last one
+            assert_eq!(sealed.len(), len + (len / 16 + 1) * OVERHEAD);
+            assert_eq!(open(&cipher, &sealed).await.unwrap(), data);
+            for blocks in 1..=len / 16 {
+                let cut = &sealed[..blocks * cipher.sealed_block_size()];
+                let err = open(&cipher, cut).await.unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            }
+            // a range short of the end doesn't need the last block
+            let mut writer = cipher.open(Vec::new(), 0).with_end(false);
+            writer
+                .write_all(&sealed[..cipher.sealed_block_size()])
+                .await
+                .unwrap();
+            writer.finish().await.unwrap();
+            assert_eq!(writer.into_inner(), [7; 16]);
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_sealed_in_parts() {
+        let cipher = cipher();
+        let data: Vec<u8> = (0..64).collect();
+        let mut sealed = Vec::new();
+        cipher
+            .seal(&data[..32], 0)
+            .with_end(false)
+            .read_to_end(&mut sealed)
+            .await
+            .unwrap();
+        cipher
+            .seal(&data[32..], 2)
+            .read_to_end(&mut sealed)
+            .await
+            .unwrap();
+        // only the last part ends with the empty last block
+        assert_eq!(sealed.len(), cipher.seal_bytes(&data).unwrap().len());
+        assert_eq!(open(&cipher, &sealed).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn unmarked_streams_open() {
+        let cipher = Cipher {
+            marks_last: false,
+            ..cipher()
+        };
+        let data = [7; 32];
+        let sealed = cipher.seal_bytes(&data).unwrap();
+        assert_eq!(sealed.len(), data.len() + 2 * OVERHEAD);
+        assert_eq!(open(&cipher, &sealed).await.unwrap(), data);
+        let mut streamed = Vec::new();
+        cipher
+            .seal(&data[..], 0)
+            .read_to_end(&mut streamed)
+            .await
+            .unwrap();
+        assert_eq!(open(&cipher, &streamed).await.unwrap(), data);
+        // and manifests from before the marking load as unmarked
+        let mut value = serde_json::to_value(cipher).unwrap();
+        value.as_object_mut().unwrap().remove("marks_last");
+        let loaded: Cipher = serde_json::from_value(value).unwrap();
+        assert_eq!(loaded, cipher);
+    }
+
+    #[tokio::test]
+    async fn wrong_key_fails() {
+        let sealed = cipher().seal_bytes(b"secret").unwrap();
+        let err = open(&cipher(), &sealed).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn offsets() {
+        let cipher = cipher();
+        assert_eq!(cipher.block(15), 0);
+        assert_eq!(cipher.block(16), 1);
+        assert_eq!(cipher.stored_offset(2), 2 * (16 + OVERHEAD as u64));
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::error::Result;
+use crate::manifest;
+
+/// Returns `$XDG_DATA_HOME/indexd-utils/approvals.json`, falling back to
+/// `~/.local/share`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(base.join("indexd-utils").join("approvals.json"))
+}
+
+/// When an app identity's key was last approved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Approved {
+    pub app_url: String,
+    /// Identifies the key without revealing it.
+    pub key_fingerprint: String,
+    /// Seconds since the Unix epoch.
+    pub approved_at: u64,
+}
+
+/// The app identities known to have been approved, by name.
+///
+/// The SDK asks for approval whenever a key isn't approved, so the cache
+/// tells apart a key connecting for the first time from one that was
+/// approved before and has since been revoked or replaced.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Approvals {
+    #[serde(flatten)]
+    apps: BTreeMap<String, Approved>,
+}
+
+impl Approvals {
+    /// Loads the cache, treating a missing file as an empty one.
+    pub async fn load(path: &Path) -> Result<Self> {
+        match fs::read(path).await {
+            Ok(buf) => Ok(serde_json::from_slice(&buf)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?).await?;
+        fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    pub fn get(&self, identity: &str) -> Option<&Approved> {
+        self.apps.get(identity)
+    }
+
+    /// Returns whether `seed` was approved for `app_url` as `identity`.
+    pub fn is_approved(&self, identity: &str, app_url: &str, seed: &[u8; 32]) -> bool {
+        self.get(identity).is_some_and(|approved| {
+            approved.app_url == app_url
+                && approved.key_fingerprint == manifest::key_fingerprint(seed)
+        })
+    }
+
+    /// Records that `seed` is approved for `app_url` as `identity`.
+    pub fn record(&mut self, identity: &str, app_url: &str, seed: &[u8; 32]) {
+        let approved_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let approved = Approved {
+            app_url: app_url.to_string(),
+            key_fingerprint: manifest::key_fingerprint(seed),
+            approved_at,
+        };
+        self.apps.insert(identity.to_string(), approved);
+    }
+}
@@ -0,0 +1,50 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use tokio::io::{DuplexStream, duplex};
+use tokio::task::JoinHandle;
+use tokio_util::io::SyncIoBridge;
+
+use crate::error::Result;
+
+/// How much of a tar stream is buffered between the task packing or
+/// unpacking it and the transfer.
+const PIPE_SIZE: usize = 1 << 20;
+
+/// Packs the `paths` under `root` into a tar, named relative to it, and
+/// streams it through the returned pipe. Symbolic links are archived as
+/// links. The task fails if a path can't be read, in which case everything
+/// read from the pipe has to be discarded: the pipe just ends early.
+pub fn tar(root: PathBuf, paths: Vec<PathBuf>) -> (DuplexStream, JoinHandle<Result<()>>) {
+    let (reader, writer) = duplex(PIPE_SIZE);
+    let writer = SyncIoBridge::new(writer);
+    let task = tokio::task::spawn_blocking(move || {
+        let mut builder = tar::Builder::new(writer);
+        builder.follow_symlinks(false);
+        for rel in paths {
+            builder.append_path_with_name(root.join(&rel), &rel)?;
+        }
+        builder.into_inner()?.flush()?;
+        Ok(())
+    });
+    (reader, task)
+}
+
+/// Unpacks the tar written to the returned pipe into `dir`, restoring
+/// modification times and permissions. Entries that would land outside
+/// `dir` are skipped.
+pub fn untar(dir: PathBuf) -> (DuplexStream, JoinHandle<Result<()>>) {
+    let (writer, reader) = duplex(PIPE_SIZE);
+    let reader = SyncIoBridge::new(reader);
+    let task = tokio::task::spawn_blocking(move || {
+        let mut archive = tar::Archive::new(reader);
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_mtime(true);
+        archive.unpack(&dir)?;
+        // whatever follows the end of the archive is read too, so the
+        // writer isn't cut off
+        io::copy(&mut archive.into_inner(), &mut io::sink())?;
+        Ok(())
+    });
+    (writer, task)
+}
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use indexd::{Connected, SDK, Slab};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::error::Result;
+
+/// What an upload reads the data it stores from.
+pub type UploadReader = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Where a [`Client`](crate::client::Client) stores and fetches slabs: the
+/// SDK connected to the app, or a stand-in for it such as the in-memory
+/// backend of the `test-util` feature.
+///
+/// A backend only moves data. Retries, timeouts, throttling, host policies
+/// and telemetry are applied by the client on top of it, so they behave the
+/// same whatever the backend.
+pub trait Backend: Send + Sync {
+    /// Encrypts, erasure codes and uploads everything read from `reader`.
+    fn upload(
+        &self,
+        reader: UploadReader,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> BoxFuture<'_, Result<Vec<Slab>>>;
+
+    /// Downloads and decrypts the slabs into `w`, in order.
+    fn download<'a>(
+        &'a self,
+        w: &'a mut (dyn AsyncWrite + Unpin + Send),
+        slabs: &'a [Slab],
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+impl Backend for SDK<Connected> {
+    fn upload(
+        &self,
+        reader: UploadReader,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> BoxFuture<'_, Result<Vec<Slab>>> {
+        Box::pin(async move {
+            Ok(SDK::upload(self, reader, encryption_key, data_shards, parity_shards).await?)
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        mut w: &'a mut (dyn AsyncWrite + Unpin + Send),
+        slabs: &'a [Slab],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { Ok(SDK::download(self, &mut w, slabs).await?) })
+    }
+}
+
+/// A shared backend, which stays in reach after a client is given it, so
+/// the mock's hosts can be taken down while a client uses them.
+impl<B: Backend + ?Sized> Backend for Arc<B> {
+    fn upload(
+        &self,
+        reader: UploadReader,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> BoxFuture<'_, Result<Vec<Slab>>> {
+        (**self).upload(reader, encryption_key, data_shards, parity_shards)
+    }
+
+    fn download<'a>(
+        &'a self,
+        w: &'a mut (dyn AsyncWrite + Unpin + Send),
+        slabs: &'a [Slab],
+    ) -> BoxFuture<'a, Result<()>> {
+        (**self).download(w, slabs)
+    }
+}
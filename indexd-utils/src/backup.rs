@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use futures::{StreamExt, TryStreamExt, stream};
+use log::{debug, info};
+use tokio::fs::{self, File};
+
+use crate::catalog::Catalog;
+use crate::client::Client;
+use crate::dedup::{self, DedupStats};
+use crate::directory;
+use crate::error::{Error, Result};
+use crate::manifest::{DirectoryManifest, FileEntry};
+use crate::progress::{Event, Progress};
+use crate::upload::UploadOptions;
+
+/// What a backup stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackupStats {
+    pub files: usize,
+    /// Files whose size and modification time matched the previous
+    /// snapshot and were not read again.
+    pub unchanged_files: usize,
+    /// Totals over the chunks of the files that were read.
+    pub chunks: DedupStats,
+}
+
+/// Captures the files under `root` into a directory manifest.
+///
+/// Files with the same path, size and modification time as in `previous`
+/// keep their previous entry without being read. Every other file is
+/// uploaded with deduplication, so only its changed chunks are stored.
+pub async fn backup(
+    sdk: &Client,
+    catalog: &Catalog,
+    root: &Path,
+    previous: Option<&DirectoryManifest>,
+    options: UploadOptions,
+    progress: Progress,
+) -> Result<(DirectoryManifest, BackupStats)> {
+    let previous: HashMap<&str, &FileEntry> = previous
+        .map(|m| m.files.iter().map(|e| (e.path.as_str(), e)).collect())
+        .unwrap_or_default();
+    let files = directory::walk(root).await?;
+    info!("backing up {} files from {}", files.len(), root.display());
+
+    let results: Vec<(FileEntry, Option<DedupStats>)> = stream::iter(files)
+        .map(|rel| {
+            let previous = &previous;
+            let progress = progress.clone();
+            async move {
+                let path = directory::to_manifest_path(&rel)?;
+                let metadata = fs::metadata(root.join(&rel)).await?;
+                let modified = directory::modified(&metadata);
+                let unchanged = previous.get(path.as_str()).filter(|entry| {
+                    entry.manifest.size == metadata.len()
+                        && modified.is_some()
+                        && entry.modified == modified
+                });
+                if let Some(entry) = unchanged {
+                    debug!("{path} is unchanged");
+                    progress.emit(Event::BytesTransferred {
+                        bytes: metadata.len(),
+                    });
+                    return Ok(((*entry).clone(), None));
+                }
+
+                let file = File::open(root.join(&rel)).await?;
+                let (manifest, stats) =
+                    dedup::upload_dedup(sdk, catalog, file, rand::random(), options, progress)
+                        .await?;
+                info!("backed up {path}");
+                let mut entry = FileEntry::new(path, manifest, modified);
+                directory::record_attributes(&root.join(&rel), &mut entry).await?;
+                Ok::<_, Error>((entry, Some(stats)))
+            }
+        })
+        .buffered(options.inflight_segments())
+        .try_collect()
+        .await?;
+
+    let mut stats = BackupStats::default();
+    let mut entries = Vec::with_capacity(results.len());
+    for (entry, chunks) in results {
+        stats.files += 1;
+        match chunks {
+            Some(chunks) => {
+                stats.chunks.chunks += chunks.chunks;
+                stats.chunks.new_chunks += chunks.new_chunks;
+                stats.chunks.new_bytes += chunks.new_bytes;
+                stats.chunks.reused_bytes += chunks.reused_bytes;
+            }
+            None => stats.unchanged_files += 1,
+        }
+        entries.push(entry);
+    }
+    Ok((DirectoryManifest::new(entries), stats))
+}
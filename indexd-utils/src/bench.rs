@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt, stream};
+use indexd::Slab;
+use rand::RngCore;
+use serde::Serialize;
+
+use crate::client::Client;
+use crate::doctor;
+use crate::download;
+use crate::error::{Error, Result};
+use crate::progress::{Event, Progress};
+use crate::redundancy::Redundancy;
+use crate::telemetry::{HOSTS, HostStats};
+use crate::upload::SECTOR_SIZE;
+
+/// How long erasure coding is measured for.
+const ENCODE_DURATION: Duration = Duration::from_secs(1);
+
+/// What a benchmark measured.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub size: u64,
+    pub data_shards: u8,
+    pub parity_shards: u8,
+    /// Bytes of data erasure coded per second on one thread.
+    pub encode_bytes_per_sec: f64,
+    pub upload: Phase,
+    pub download: Phase,
+    /// The hosts that took part, fastest downloads first.
+    pub hosts: Vec<HostRate>,
+}
+
+/// How one direction of the transfer went.
+#[derive(Debug, Clone, Serialize)]
+pub struct Phase {
+    pub elapsed_ms: u64,
+    pub bytes_per_sec: f64,
+    /// How long each slab took from start to finish, in milliseconds.
+    pub latency_ms: Latency,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Latency {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl Latency {
+    fn new(mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        let at = |q: f64| {
+            let i = ((samples.len() as f64 * q).ceil() as usize).saturating_sub(1);
+            samples.get(i).map_or(0, |d| d.as_millis() as u64)
+        };
+        Self {
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            max: at(1.0),
+        }
+    }
+}
+
+/// What a host did during the benchmark. Per slab, as the SDK reports
+/// them, so a host's rate is that of the slabs it was part of.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostRate {
+    pub host: String,
+    pub upload_bytes_per_sec: f64,
+    pub download_bytes_per_sec: f64,
+    pub failures: u64,
+}
+
+/// Uploads `size` bytes of random data with the given redundancy and
+/// downloads it again, `jobs` slabs at a time, timing each slab. The
+/// downloaded data is compared with what was uploaded. The slabs aren't
+/// recorded anywhere, so they can't be downloaded again afterwards.
+pub async fn run(
+    sdk: &Client,
+    size: u64,
+    redundancy: Redundancy,
+    jobs: usize,
+    progress: &Progress,
+) -> Result<Report> {
+    let Redundancy {
+        data_shards,
+        parity_shards,
+    } = redundancy;
+    let encode_bytes_per_sec =
+        doctor::encode_throughput(data_shards, parity_shards, ENCODE_DURATION).await?;
+
+    // every slab is the same data sealed with its own key
+    let slab_size = data_shards as u64 * SECTOR_SIZE;
+    let mut data = vec![0u8; slab_size.min(size) as usize];
+    rand::rng().fill_bytes(&mut data);
+    let data = Bytes::from(data);
+    let lengths: Vec<u64> = (0..size.div_ceil(slab_size))
+        .map(|i| slab_size.min(size - i * slab_size))
+        .collect();
+
+    let before = HOSTS.snapshot();
+    let start = Instant::now();
+    let uploaded: Vec<(Vec<Slab>, Duration)> = stream::iter(lengths)
+        .map(|length| {
+            let data = data.slice(..length as usize);
+            async move {
+                let start = Instant::now();
+                let slabs = sdk
+                    .upload_bytes(data, rand::random(), data_shards, parity_shards)
+                    .await?;
+                progress.emit(Event::BytesTransferred { bytes: length });
+                Ok::<_, Error>((slabs, start.elapsed()))
+            }
+        })
+        .buffered(jobs.max(1))
+        .try_collect()
+        .await?;
+    let upload = phase(size, start.elapsed(), uploaded.iter().map(|(_, d)| *d));
+    let uploading = HOSTS.snapshot();
+
+    let slabs: Vec<Slab> = uploaded.into_iter().flat_map(|(slabs, _)| slabs).collect();
+    let start = Instant::now();
+    let latencies: Vec<Duration> = stream::iter(&slabs)
+        .map(|slab| {
+            let data = &data;
+            async move {
+                let start = Instant::now();
+                let (result, _) = download::fetch_slab(sdk, slab).await;
+                let fetched = result?;
+                if fetched.len() != slab.length as usize || fetched[..] != data[..fetched.len()] {
+                    return Err(Error::Integrity(
+                        "a downloaded slab doesn't match what was uploaded".into(),
+                    ));
+                }
+                progress.emit(Event::BytesTransferred {
+                    bytes: slab.length as u64,
+                });
+                Ok::<_, Error>(start.elapsed())
+            }
+        })
+        .buffer_unordered(jobs.max(1))
+        .try_collect()
+        .await?;
+    let download = phase(size, start.elapsed(), latencies);
+    let downloading = HOSTS.snapshot();
+
+    Ok(Report {
+        size,
+        data_shards,
+        parity_shards,
+        encode_bytes_per_sec,
+        upload,
+        download,
+        hosts: host_rates(&before, &uploading, &downloading),
+    })
+}
+
+fn phase(size: u64, elapsed: Duration, latencies: impl IntoIterator<Item = Duration>) -> Phase {
+    Phase {
+        elapsed_ms: elapsed.as_millis() as u64,
+        bytes_per_sec: size as f64 / elapsed.as_secs_f64().max(0.001),
+        latency_ms: Latency::new(latencies.into_iter().collect()),
+    }
+}
+
+/// Splits the host stats recorded over the benchmark into its upload and
+/// download.
+fn host_rates(
+    before: &BTreeMap<String, HostStats>,
+    uploading: &BTreeMap<String, HostStats>,
+    downloading: &BTreeMap<String, HostStats>,
+) -> Vec<HostRate> {
+    let empty = HostStats::default();
+    let mut hosts: Vec<HostRate> = downloading
+        .iter()
+        .filter_map(|(host, after)| {
+            let start = before.get(host).unwrap_or(&empty);
+            let middle = uploading.get(host).unwrap_or(start);
+            let (up, down) = (middle.since(start), after.since(middle));
+            (up.transfers + down.transfers > 0).then(|| HostRate {
+                host: host.clone(),
+                upload_bytes_per_sec: rate(&up),
+                download_bytes_per_sec: rate(&down),
+                failures: up.failures + down.failures,
+            })
+        })
+        .collect();
+    hosts.sort_by(|a, b| {
+        b.download_bytes_per_sec
+            .total_cmp(&a.download_bytes_per_sec)
+    });
+    hosts
+}
+
+fn rate(stats: &HostStats) -> f64 {
+    if stats.transfers == 0 {
+        0.0
+    } else {
+        stats.bytes_per_sec()
+    }
+}
@@ -0,0 +1,290 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, LineGauge, List, ListItem, ListState, Paragraph};
+
+use crate::catalog::Entry;
+use crate::manifest::{AnyManifest, Sealing};
+use crate::verify::{Health, Report};
+
+/// The most transfers shown at once; finished ones make way for newer
+/// ones.
+const MAX_TRANSFERS: usize = 6;
+
+/// A catalogued object as the browser shows it.
+pub struct Object {
+    pub entry: Entry,
+    /// `None` for a sealed object the passphrase didn't open.
+    pub manifest: Option<AnyManifest>,
+    /// The key the object's manifest is sealed with, so a repaired
+    /// manifest can be sealed again.
+    pub sealing: Option<Sealing>,
+    /// What the last verify found, if it was verified while browsing.
+    pub health: Option<Summary>,
+}
+
+/// The slab health of an object, summed over its files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub healthy: usize,
+    pub degraded: usize,
+    pub unrecoverable: usize,
+    pub checksum_ok: bool,
+}
+
+impl Summary {
+    /// Returns the summary of an object whose files were reported on.
+    pub fn new<'a>(reports: impl IntoIterator<Item = &'a Report>) -> Self {
+        let mut summary = Self {
+            checksum_ok: true,
+            ..Self::default()
+        };
+        for report in reports {
+            summary.healthy += report.count(Health::Healthy);
+            summary.degraded += report.count(Health::Degraded);
+            summary.unrecoverable += report.count(Health::Unrecoverable);
+            summary.checksum_ok &= report.checksum_ok;
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Done(String),
+    Failed(String),
+}
+
+/// A verify, repair or download started from the browser.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub label: String,
+    pub total: u64,
+    pub done: u64,
+    pub retries: u32,
+    pub state: State,
+}
+
+/// The state of the browser: the catalogued objects, the one selected and
+/// the transfers started from it.
+pub struct Browser {
+    pub objects: Vec<Object>,
+    transfers: Vec<Transfer>,
+    list: ListState,
+    /// A one-line message shown above the key help.
+    pub status: String,
+}
+
+impl Browser {
+    pub fn new(objects: Vec<Object>) -> Self {
+        let selected = (!objects.is_empty()).then_some(0);
+        Self {
+            objects,
+            transfers: Vec::new(),
+            list: ListState::default().with_selected(selected),
+            status: String::new(),
+        }
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.list.selected().filter(|&i| i < self.objects.len())
+    }
+
+    pub fn select_next(&mut self) {
+        self.list.select_next();
+    }
+
+    pub fn select_previous(&mut self) {
+        self.list.select_previous();
+    }
+
+    /// Returns the object catalogued under `name`.
+    pub fn object_mut(&mut self, name: &str) -> Option<&mut Object> {
+        self.objects.iter_mut().find(|o| o.entry.name == name)
+    }
+
+    /// Adds a running transfer of `total` bytes and returns its id.
+    pub fn start(&mut self, label: String, total: u64) -> usize {
+        self.transfers.push(Transfer {
+            label,
+            total,
+            done: 0,
+            retries: 0,
+            state: State::Running,
+        });
+        self.transfers.len() - 1
+    }
+
+    pub fn advance(&mut self, id: usize, bytes: u64) {
+        if let Some(transfer) = self.transfers.get_mut(id) {
+            transfer.done = (transfer.done + bytes).min(transfer.total);
+        }
+    }
+
+    pub fn retrying(&mut self, id: usize) {
+        if let Some(transfer) = self.transfers.get_mut(id) {
+            transfer.retries += 1;
+        }
+    }
+
+    pub fn finish(&mut self, id: usize, result: Result<String, String>) {
+        if let Some(transfer) = self.transfers.get_mut(id) {
+            transfer.state = match result {
+                Ok(message) => {
+                    transfer.done = transfer.total;
+                    State::Done(message)
+                }
+                Err(e) => State::Failed(e),
+            };
+        }
+    }
+
+    pub fn running(&self) -> usize {
+        self.transfers
+            .iter()
+            .filter(|t| t.state == State::Running)
+            .count()
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let shown = self.transfers.len().min(MAX_TRANSFERS) as u16;
+        let [main, transfers, status, help] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(shown + 2),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list, details] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let items: Vec<ListItem> = self
+            .objects
+            .iter()
+            .map(|object| ListItem::new(object.entry.name.as_str()))
+            .collect();
+        let catalog = List::new(items)
+            .block(Block::bordered().title(format!(" Catalog ({}) ", self.objects.len())))
+            .highlight_style(Style::new().reversed())
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(catalog, list, &mut self.list);
+
+        let lines = self
+            .selected()
+            .map(|i| details_lines(&self.objects[i]))
+            .unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Details ")),
+            details,
+        );
+
+        self.draw_transfers(frame, transfers);
+        frame.render_widget(Paragraph::new(self.status.as_str()), status);
+        frame.render_widget(
+            Paragraph::new("↑/↓ select  v verify  r repair  d download  q quit").dim(),
+            help,
+        );
+    }
+
+    fn draw_transfers(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(format!(" Transfers ({} running) ", self.running()));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        let start = self.transfers.len().saturating_sub(MAX_TRANSFERS);
+        let rows = Layout::vertical(vec![Constraint::Length(1); MAX_TRANSFERS]).split(inner);
+        for (transfer, row) in self.transfers[start..].iter().zip(rows.iter()) {
+            let (label, color) = match &transfer.state {
+                State::Running if transfer.retries > 0 => (
+                    format!("{} ({} retries)", transfer.label, transfer.retries),
+                    Color::Yellow,
+                ),
+                State::Running => (transfer.label.clone(), Color::Blue),
+                State::Done(message) => (format!("{}: {message}", transfer.label), Color::Green),
+                State::Failed(e) => (format!("{}: {e}", transfer.label), Color::Red),
+            };
+            let ratio = transfer.done as f64 / transfer.total.max(1) as f64;
+            let gauge = LineGauge::default()
+                .label(label)
+                .ratio(ratio.clamp(0.0, 1.0))
+                .filled_style(Style::new().fg(color));
+            frame.render_widget(gauge, *row);
+        }
+    }
+}
+
+fn details_lines(object: &Object) -> Vec<Line<'static>> {
+    let field = |name: &str, value: String| {
+        Line::from(vec![
+            Span::from(format!("{name:<12}")).bold(),
+            Span::from(value),
+        ])
+    };
+    let entry = &object.entry;
+    let mut lines = vec![
+        field("Name", entry.name.clone()),
+        field("Size", format_size(entry.size)),
+        field(
+            "Updated",
+            httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(entry.updated_at)),
+        ),
+    ];
+    if let Some(files) = entry.files {
+        lines.push(field("Files", files.to_string()));
+    }
+    if let Some(source) = &entry.source {
+        lines.push(field("Source", source.clone()));
+    }
+    if entry.sealed {
+        lines.push(field("Sealed", "yes".into()));
+    }
+    match &object.manifest {
+        Some(AnyManifest::File(manifest)) => {
+            lines.push(field(
+                "Redundancy",
+                format!("{}+{}", manifest.data_shards, manifest.parity_shards),
+            ));
+            lines.push(field("Slabs", manifest.slabs.len().to_string()));
+        }
+        Some(AnyManifest::Directory(manifest)) => {
+            let slabs: usize = manifest.files.iter().map(|f| f.manifest.slabs.len()).sum();
+            lines.push(field("Slabs", slabs.to_string()));
+        }
+        None => lines.push(field("Manifest", "sealed with another passphrase".into())),
+    }
+    let health = match object.health {
+        None => Span::from("not verified yet").dim(),
+        Some(s) if s.unrecoverable > 0 => {
+            Span::from(format!("{} slabs unrecoverable", s.unrecoverable)).red()
+        }
+        Some(s) if !s.checksum_ok => Span::from("checksum mismatch").red(),
+        Some(s) if s.degraded > 0 => {
+            Span::from(format!("{} healthy, {} degraded", s.healthy, s.degraded)).yellow()
+        }
+        Some(s) => Span::from(format!("{} slabs healthy", s.healthy)).green(),
+    };
+    lines.push(Line::from(vec![
+        Span::from(format!("{:<12}", "Health")).bold(),
+        health,
+    ]));
+    lines
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::catalog::Catalog;
+use crate::checksum::ChecksumWriter;
+use crate::client::Client;
+use crate::download;
+use crate::error::{Error, Result};
+use crate::keys::{self, Kdf};
+use crate::manifest::{self, Manifest};
+use crate::progress::Progress;
+use crate::upload::{self, UploadOptions};
+
+pub const BUCKET_VERSION: u32 = 1;
+
+/// Points at the latest index of a bucket.
+///
+/// The index is itself an object on indexd, encrypted with the bucket key,
+/// so a reference reveals nothing about the bucket's contents and can be
+/// copied to other machines freely; the passphrase is still needed to read
+/// it. The SDK has no way to look objects up by name, so the reference is
+/// the one thing that has to be shared out of band.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BucketRef {
+    pub version: u32,
+    pub name: String,
+    /// The parameters the bucket key is derived from the passphrase with.
+    pub kdf: Kdf,
+    /// Incremented on every commit.
+    pub generation: u64,
+    /// The encrypted index, or `None` until the first commit.
+    pub index: Option<Manifest>,
+}
+
+impl BucketRef {
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let reference: Self = manifest::read(path.as_ref()).await?;
+        if reference.version != BUCKET_VERSION {
+            return Err(Error::Manifest(format!(
+                "unsupported bucket version {}",
+                reference.version
+            )));
+        }
+        Ok(reference)
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        manifest::write(path.as_ref(), self).await
+    }
+}
+
+/// An object in a bucket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Object {
+    pub manifest: Manifest,
+    /// When the object was last written, in seconds since the Unix epoch.
+    pub modified: u64,
+}
+
+/// Maps object keys to their objects.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    objects: BTreeMap<String, Object>,
+}
+
+/// A namespace of named objects stored on indexd.
+///
+/// Changes are made to an in-memory copy of the index and only become
+/// visible to other machines once `commit` uploads it. The catalog refuses a
+/// commit over a reference that moved on since the bucket was opened, but
+/// machines only learn of each other's commits through `bucket import`: if
+/// two machines commit the same generation, whichever reference is shared
+/// last wins.
+///
+/// Every commit uploads the whole index again. The SDK can't release slabs
+/// yet, so the index it replaces stays stored.
+pub struct Bucket {
+    key: [u8; 32],
+    reference: BucketRef,
+    index: Index,
+    options: UploadOptions,
+    /// The changes made to the index, and how many of them are committed.
+    changes: u64,
+    committed: u64,
+    /// Held while a commit through [`Buckets`] uploads the index.
+    committing: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Bucket {
+    /// Returns a new, empty bucket. Nothing is stored until `commit`.
+    pub fn create(name: &str, kdf: Kdf, key: [u8; 32], options: UploadOptions) -> Self {
+        Self {
+            key,
+            reference: BucketRef {
+                version: BUCKET_VERSION,
+                name: name.to_string(),
+                kdf,
+                generation: 0,
+                index: None,
+            },
+            index: Index::default(),
+            options,
+            // creating the bucket is the change its first commit stores
+            changes: 1,
+            committed: 0,
+            committing: Default::default(),
+        }
+    }
+
+    /// Opens the bucket `reference` points at, downloading its index.
+    /// `key` must have been derived with the reference's parameters.
+    pub async fn open(
+        sdk: &Client,
+        reference: BucketRef,
+        key: [u8; 32],
+        options: UploadOptions,
+    ) -> Result<Self> {
+        let index = match &reference.index {
+            Some(manifest) => {
+                let mut w = ChecksumWriter::new(Vec::new());
+                download::download_object(sdk, &mut w, manifest, 0, manifest.size).await?;
+                if w.checksum() != manifest.checksum {
+                    return Err(Error::Manifest(
+                        "bucket index does not match its checksum".into(),
+                    ));
+                }
+                let sealed = w.into_inner();
+                if sealed.len() < 24 {
+                    return Err(Error::Manifest("bucket index is truncated".into()));
+                }
+                let (nonce, ciphertext) = sealed.split_at(24);
+                let plaintext = cipher(&key)
+                    .decrypt(XNonce::from_slice(nonce), ciphertext)
+                    .map_err(|_| {
+                        Error::Crypto("failed to open bucket index: wrong passphrase?".into())
+                    })?;
+                serde_json::from_slice(&plaintext)?
+            }
+            None => Index::default(),
+        };
+        Ok(Self {
+            key,
+            reference,
+            index,
+            options,
+            changes: 0,
+            committed: 0,
+            committing: Default::default(),
+        })
+    }
+
+    pub fn reference(&self) -> &BucketRef {
+        &self.reference
+    }
+
+    pub fn options(&self) -> UploadOptions {
+        self.options
+    }
+
+    /// Uploads `reader` as the object `key`, replacing any existing object
+    /// with that key.
+    pub async fn put<R>(&mut self, sdk: &Client, key: &str, reader: R) -> Result<&Object>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let manifest = upload::upload_reader(
+            sdk,
+            reader,
+            rand::random(),
+            self.options,
+            Progress::default(),
+        )
+        .await?;
+        Ok(self.insert(key, manifest))
+    }
+
+    /// Adds an object that was uploaded separately, replacing any existing
+    /// object with that key. This lets callers upload without holding the
+    /// bucket.
+    pub fn insert(&mut self, key: &str, manifest: Manifest) -> &Object {
+        let modified = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.index
+            .objects
+            .insert(key.to_string(), Object { manifest, modified });
+        self.changes += 1;
+        &self.index.objects[key]
+    }
+
+    /// Downloads the object `key` into `w`.
+    pub async fn get<W>(&self, sdk: &Client, key: &str, w: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let object = self.stat(key)?;
+        let manifest = &object.manifest;
+        download::download_object(sdk, w, manifest, 0, manifest.size).await?;
+        Ok(())
+    }
+
+    pub fn stat(&self, key: &str) -> Result<&Object> {
+        self.index
+            .objects
+            .get(key)
+            .ok_or_else(|| Error::NotFound(format!("{}/{key}", self.reference.name)))
+    }
+
+    /// Lists the objects whose keys start with `prefix`, in key order.
+    pub fn list<'b>(&'b self, prefix: &'b str) -> impl Iterator<Item = (&'b str, &'b Object)> {
+        self.index
+            .objects
+            .range(prefix.to_string()..)
+            .take_while(move |(key, _)| key.starts_with(prefix))
+            .map(|(key, object)| (key.as_str(), object))
+    }
+
+    /// Removes the object `key` from the index. Its slabs stay stored; the
+    /// SDK can't release them yet.
+    pub fn delete(&mut self, key: &str) -> Result<Object> {
+        let object = self
+            .index
+            .objects
+            .remove(key)
+            .ok_or_else(|| Error::NotFound(format!("{}/{key}", self.reference.name)))?;
+        self.changes += 1;
+        Ok(object)
+    }
+
+    /// Encrypts and uploads the index, returning the reference to the new
+    /// generation. The caller records it, with [`Catalog::swap_bucket`] to
+    /// keep from overwriting a commit made since the bucket was opened.
+    pub async fn commit(&mut self, sdk: &Client) -> Result<&BucketRef> {
+        let sealed = self.seal()?;
+        let index = upload_index(sdk, sealed, self.options).await?;
+        let next = self.next(index);
+        self.advance(next, self.changes);
+        Ok(&self.reference)
+    }
+
+    /// Returns the index encrypted under the bucket key.
+    fn seal(&self) -> Result<Vec<u8>> {
+        let plaintext = serde_json::to_vec(&self.index)?;
+        let nonce: [u8; 24] = rand::random();
+        let ciphertext = cipher(&self.key)
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| Error::Crypto("failed to seal bucket index".into()))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    /// Returns the reference of the generation after this one, pointing at
+    /// `index`.
+    fn next(&self, index: Manifest) -> BucketRef {
+        BucketRef {
+            generation: self.reference.generation + 1,
+            index: Some(index),
+            ..self.reference.clone()
+        }
+    }
+
+    /// Moves on to the generation `next`, which holds the first `changes`
+    /// changes.
+    fn advance(&mut self, next: BucketRef, changes: u64) {
+        let superseded = std::mem::replace(&mut self.reference, next).index;
+        self.committed = changes;
+        if let Some(superseded) = superseded {
+            debug!(
+                "the superseded index of bucket {} keeps {} slabs stored; the SDK can't \
+                 release them yet",
+                self.reference.name,
+                superseded.slabs.len()
+            );
+        }
+    }
+}
+
+fn cipher(key: &[u8; 32]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(Key::from_slice(&keys::subkey(key, "bucket-index")))
+}
+
+async fn upload_index(sdk: &Client, sealed: Vec<u8>, options: UploadOptions) -> Result<Manifest> {
+    upload::upload_reader(
+        sdk,
+        Cursor::new(sealed),
+        rand::random(),
+        options,
+        Progress::default(),
+    )
+    .await
+}
+
+/// The error for a commit over a bucket reference that moved on since the
+/// bucket was opened.
+pub fn stale(name: &str) -> Error {
+    Error::Manifest(format!(
+        "bucket {name} was committed to since it was opened; open it again to see the \
+         other changes"
+    ))
+}
+
+/// Buckets shared between concurrent requests, opened on first use and
+/// unlocked with a single passphrase.
+///
+/// Each bucket is locked while its index is changed. Callers should upload
+/// objects before taking the lock, `insert` them after, and release the lock
+/// again before calling [`Buckets::commit`], so that concurrent changes
+/// share commits.
+pub struct Buckets {
+    catalog: std::sync::Mutex<Catalog>,
+    passphrase: String,
+    options: UploadOptions,
+    open: tokio::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<Bucket>>>>,
+}
+
+impl Buckets {
+    /// `options` apply to buckets created through this set and to the
+    /// objects put into any of them.
+    pub fn new(catalog: Catalog, passphrase: String, options: UploadOptions) -> Self {
+        Self {
+            catalog: std::sync::Mutex::new(catalog),
+            passphrase,
+            options,
+            open: Default::default(),
+        }
+    }
+
+    /// Returns the names of the catalogued buckets in order.
+    pub fn names(&self) -> Result<Vec<String>> {
+        self.catalog.lock().unwrap().list_buckets()
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.catalog.lock().unwrap().get_bucket(name)?.is_some())
+    }
+
+    /// Returns the named bucket, downloading its index on first use.
+    pub async fn get(&self, sdk: &Client, name: &str) -> Result<Arc<tokio::sync::Mutex<Bucket>>> {
+        let mut open = self.open.lock().await;
+        if let Some(bucket) = open.get(name) {
+            return Ok(bucket.clone());
+        }
+        let reference = self
+            .catalog
+            .lock()
+            .unwrap()
+            .get_bucket(name)?
+            .ok_or_else(|| Error::NotFound(format!("bucket {name}")))?;
+        let key = reference.kdf.derive(&self.passphrase).await?;
+        let bucket = Bucket::open(sdk, reference, key, self.options).await?;
+        let bucket = Arc::new(tokio::sync::Mutex::new(bucket));
+        open.insert(name.to_string(), bucket.clone());
+        Ok(bucket)
+    }
+
+    /// Creates and commits an empty bucket.
+    pub async fn create(&self, sdk: &Client, name: &str) -> Result<()> {
+        let mut open = self.open.lock().await;
+        if self.exists(name)? {
+            return Err(Error::Usage(format!("bucket {name} already exists")));
+        }
+        let kdf = Kdf::default();
+        let key = kdf.derive(&self.passphrase).await?;
+        let bucket = Bucket::create(name, kdf, key, self.options);
+        let bucket = Arc::new(tokio::sync::Mutex::new(bucket));
+        open.insert(name.to_string(), bucket.clone());
+        // committing may have to forget the bucket again
+        drop(open);
+        if let Err(e) = self.commit(sdk, &bucket).await {
+            self.forget(&bucket, name).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Waits until the changes made to `bucket` so far are committed and the
+    /// new reference is in the catalog.
+    ///
+    /// One commit of a bucket runs at a time, and it takes in every change
+    /// made before it started, so a caller whose change an earlier commit
+    /// already stored returns without uploading anything. A bucket whose
+    /// catalogued reference moved on since it was opened, by `bucket import`
+    /// or another process, is not committed over: the commit fails with
+    /// [`stale`] and the bucket is opened again on next use.
+    pub async fn commit(&self, sdk: &Client, bucket: &tokio::sync::Mutex<Bucket>) -> Result<()> {
+        let (wanted, committing) = {
+            let bucket = bucket.lock().await;
+            (bucket.changes, bucket.committing.clone())
+        };
+        let _turn = committing.lock().await;
+        let (changes, sealed, name, generation, options) = {
+            let bucket = bucket.lock().await;
+            if bucket.committed >= wanted {
+                return Ok(());
+            }
+            let reference = &bucket.reference;
+            let sealed = bucket.seal()?;
+            let name = reference.name.clone();
+            (
+                bucket.changes,
+                sealed,
+                name,
+                reference.generation,
+                bucket.options,
+            )
+        };
+        // checked before uploading too, so a stale bucket costs no upload
+        let current = self
+            .catalog
+            .lock()
+            .unwrap()
+            .bucket_is_current(&name, generation)?;
+        if !current {
+            self.forget(bucket, &name).await;
+            return Err(stale(&name));
+        }
+        let index = upload_index(sdk, sealed, options).await?;
+        let mut locked = bucket.lock().await;
+        let next = locked.next(index);
+        if !self.catalog.lock().unwrap().swap_bucket(&next)? {
+            drop(locked);
+            self.forget(bucket, &name).await;
+            return Err(stale(&name));
+        }
+        locked.advance(next, changes);
+        Ok(())
+    }
+
+    /// Stops serving `bucket` as `name`, unless it was replaced already.
+    async fn forget(&self, bucket: &tokio::sync::Mutex<Bucket>, name: &str) {
+        let mut open = self.open.lock().await;
+        if open
+            .get(name)
+            .is_some_and(|open| std::ptr::eq(Arc::as_ptr(open), bucket))
+        {
+            open.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockBackend};
+
+    fn options() -> UploadOptions {
+        UploadOptions::new(1, 1)
+    }
+
+    async fn get(bucket: &Bucket, sdk: &Client, key: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        bucket.get(sdk, key, &mut data).await.unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn commits_survive_reopening() {
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let key = [7; 32];
+        let mut bucket = Bucket::create("photos", Kdf::default(), key, options());
+        for (name, data) in [
+            ("2024/a.jpg", b"a".to_vec()),
+            ("2024/b.jpg", mock::pattern(1000)),
+            ("2025/c.jpg", b"c".to_vec()),
+        ] {
+            bucket.put(&sdk, name, Cursor::new(data)).await.unwrap();
+        }
+        let reference = bucket.commit(&sdk).await.unwrap().clone();
+        assert_eq!(reference.generation, 1);
+
+        let mut bucket = Bucket::open(&sdk, reference, key, options()).await.unwrap();
+        let keys: Vec<&str> = bucket.list("2024/").map(|(key, _)| key).collect();
+        assert_eq!(keys, ["2024/a.jpg", "2024/b.jpg"]);
+        assert_eq!(get(&bucket, &sdk, "2024/b.jpg").await, mock::pattern(1000));
+        bucket.delete("2024/a.jpg").unwrap();
+        assert!(matches!(
+            bucket.delete("2024/a.jpg"),
+            Err(Error::NotFound(_))
+        ));
+        let reference = bucket.commit(&sdk).await.unwrap().clone();
+        assert_eq!(reference.generation, 2);
+
+        let bucket = Bucket::open(&sdk, reference, key, options()).await.unwrap();
+        assert!(matches!(bucket.stat("2024/a.jpg"), Err(Error::NotFound(_))));
+        assert_eq!(bucket.list("").count(), 2);
+        assert_eq!(get(&bucket, &sdk, "2025/c.jpg").await, b"c");
+    }
+
+    #[tokio::test]
+    async fn the_index_needs_the_key() {
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let mut bucket = Bucket::create("photos", Kdf::default(), [7; 32], options());
+        let reference = bucket.commit(&sdk).await.unwrap().clone();
+        let opened = Bucket::open(&sdk, reference, [8; 32], options()).await;
+        assert!(matches!(opened, Err(Error::Crypto(_))));
+    }
+
+    #[tokio::test]
+    async fn concurrent_changes_share_a_commit() {
+        let dir = mock::temp_dir();
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let buckets = Buckets::new(catalog, "passphrase".into(), options());
+        buckets.create(&sdk, "photos").await.unwrap();
+        let manifest = upload_index(&sdk, b"object".to_vec(), options())
+            .await
+            .unwrap();
+
+        let bucket = buckets.get(&sdk, "photos").await.unwrap();
+        {
+            let mut bucket = bucket.lock().await;
+            bucket.insert("a", manifest.clone());
+            bucket.insert("b", manifest.clone());
+        }
+        let (a, b) = tokio::join!(buckets.commit(&sdk, &bucket), buckets.commit(&sdk, &bucket));
+        a.unwrap();
+        b.unwrap();
+        assert_eq!(bucket.lock().await.reference().generation, 2);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stale_commits_are_refused() {
+        let dir = mock::temp_dir();
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let buckets = Buckets::new(catalog, "passphrase".into(), options());
+        buckets.create(&sdk, "photos").await.unwrap();
+        let bucket = buckets.get(&sdk, "photos").await.unwrap();
+        let manifest = upload_index(&sdk, b"object".to_vec(), options())
+            .await
+            .unwrap();
+        bucket.lock().await.insert("a", manifest);
+
+        // another machine's commit is imported meanwhile
+        let other = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let mut reference = other.get_bucket("photos").unwrap().unwrap();
+        reference.generation += 1;
+        other.put_bucket(&reference).unwrap();
+
+        let committed = buckets.commit(&sdk, &bucket).await;
+        assert!(matches!(committed, Err(Error::Manifest(_))));
+        assert_eq!(other.get_bucket("photos").unwrap().unwrap().generation, 2);
+        // the bucket is opened again at the imported generation
+        let reopened = buckets.get(&sdk, "photos").await.unwrap();
+        assert_eq!(reopened.lock().await.reference().generation, 2);
+        assert!(reopened.lock().await.stat("a").is_err());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
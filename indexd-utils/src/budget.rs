@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use crate::catalog::Catalog;
+use crate::error::Result;
+use crate::estimate::{Estimate, Pricing};
+
+/// The window monthly spending is summed over.
+pub const MONTH: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Spending limits, in the currency of the prices they are checked with.
+///
+/// An upload is charged what sending it costs plus its first month of
+/// storage, estimated before compression or deduplication, and the charges
+/// are recorded in the catalog so the monthly limit covers every upload
+/// made with it over the last 30 days.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    pub pricing: Pricing,
+    pub per_upload: Option<f64>,
+    pub per_month: Option<f64>,
+}
+
+impl Budget {
+    /// Returns what an upload is charged.
+    pub fn charge(&self, estimate: &Estimate) -> f64 {
+        let cost = estimate.cost(&self.pricing);
+        cost.upload + cost.storage_per_month
+    }
+
+    /// Returns why an upload charged `charge` would go over the budget,
+    /// if it would.
+    pub fn exceeded(&self, catalog: &Catalog, charge: f64) -> Result<Option<String>> {
+        if let Some(limit) = self.per_upload {
+            if charge > limit {
+                return Ok(Some(format!(
+                    "the upload costs an estimated {charge:.4}, over the budget of {limit:.4} per upload"
+                )));
+            }
+        }
+        if let Some(limit) = self.per_month {
+            let spent = catalog.spent_since(MONTH)?;
+            if spent + charge > limit {
+                return Ok(Some(format!(
+                    "{spent:.4} was spent in the last 30 days and the upload costs an estimated {charge:.4}, over the budget of {limit:.4} per month"
+                )));
+            }
+        }
+        Ok(None)
+    }
+}
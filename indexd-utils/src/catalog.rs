@@ -0,0 +1,1460 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use indexd::Slab;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::bucket::BucketRef;
+use crate::error::{Error, Result};
+use crate::manifest::{AnyManifest, DirectoryManifest, StoredManifest};
+use crate::tags::Tag;
+use crate::telemetry::HostStats;
+use crate::upload::SECTOR_SIZE;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS objects (
+    name TEXT PRIMARY KEY,
+    source TEXT,
+    size INTEGER NOT NULL,
+    files INTEGER,
+    sealed INTEGER NOT NULL,
+    key_ref TEXT,
+    manifest TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    modified INTEGER,
+    deleted_at INTEGER
+);
+CREATE TABLE IF NOT EXISTS buckets (
+    name TEXT PRIMARY KEY,
+    reference TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS snapshots (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    source TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    files INTEGER NOT NULL,
+    sealed INTEGER NOT NULL,
+    manifest TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS chunks (
+    hash TEXT PRIMARY KEY,
+    length INTEGER NOT NULL,
+    slabs TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS spending (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    amount REAL NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS versions (
+    name TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    source TEXT,
+    size INTEGER NOT NULL,
+    files INTEGER,
+    sealed INTEGER NOT NULL,
+    key_ref TEXT,
+    manifest TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    replaced_at INTEGER NOT NULL,
+    PRIMARY KEY (name, version)
+);
+CREATE TABLE IF NOT EXISTS host_stats (
+    host TEXT PRIMARY KEY,
+    transfers INTEGER NOT NULL,
+    failures INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    busy_ms INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS slab_refs (
+    slab TEXT NOT NULL,
+    stored INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    PRIMARY KEY (kind, owner, version, slab)
+);
+CREATE INDEX IF NOT EXISTS slab_refs_slab ON slab_refs (slab);
+";
+
+/// Matches the references held for objects, versions, snapshots and chunks
+/// that are gone.
+const DANGLING_REFS: &str = "
+    (kind = 'object' AND owner NOT IN (SELECT name FROM objects))
+    OR (kind = 'version' AND NOT EXISTS
+        (SELECT 1 FROM versions v
+         WHERE v.name = slab_refs.owner AND v.version = slab_refs.version))
+    OR (kind = 'snapshot' AND CAST(owner AS INTEGER) NOT IN (SELECT id FROM snapshots))
+    OR (kind = 'chunk' AND owner NOT IN (SELECT hash FROM chunks))";
+
+/// Columns added after the table was first created, applied to older
+/// catalogs on open.
+const MIGRATIONS: &[(&str, &str, &str)] = &[
+    ("objects", "modified", "INTEGER"),
+    ("objects", "deleted_at", "INTEGER"),
+    ("objects", "checked_at", "INTEGER"),
+    ("objects", "damaged", "INTEGER"),
+    ("objects", "tags", "TEXT"),
+    ("objects", "metadata", "TEXT"),
+];
+
+/// Returns `$XDG_DATA_HOME/indexd-utils/catalog.db`, falling back to
+/// `~/.local/share`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(base.join("indexd-utils").join("catalog.db"))
+}
+
+/// A catalogued upload, without its manifest.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub name: String,
+    /// The path the object was uploaded from, if any.
+    pub source: Option<String>,
+    pub size: u64,
+    /// The number of files for directory uploads.
+    pub files: Option<u64>,
+    pub sealed: bool,
+    /// Identifies the key the object is encrypted with: the key fingerprint
+    /// for file uploads or `passphrase` for sealed uploads.
+    pub key_ref: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub updated_at: u64,
+    /// The modification time of the source file in nanoseconds since the
+    /// Unix epoch, recorded by `sync`.
+    pub modified: Option<u64>,
+    /// What the object can be selected by, such as `project=alpha`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// Free-form key/value pairs recorded with the object.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Entry {
+    /// Whether the object carries every one of `tags`.
+    pub fn has_tags(&self, tags: &[Tag]) -> bool {
+        tags.iter().all(|tag| tag.matches(&self.tags))
+    }
+}
+
+/// What the last check of an object found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Check {
+    /// Seconds since the Unix epoch.
+    pub checked_at: u64,
+    /// The slabs left unrecoverable or not yet replaced.
+    pub damaged: u64,
+}
+
+/// An object in the trash.
+#[derive(Debug, Clone, Serialize)]
+pub struct Trashed {
+    pub name: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch.
+    pub deleted_at: u64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// A version of a catalogued object, without its manifest. Versions are
+/// numbered from 1 in upload order; the latest is the object itself.
+#[derive(Debug, Clone, Serialize)]
+pub struct Version {
+    pub name: String,
+    pub version: u64,
+    pub size: u64,
+    pub sealed: bool,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    /// When a newer upload replaced it, unset for the latest version.
+    pub replaced_at: Option<u64>,
+}
+
+/// How many replaced versions of each object are kept, and for how long.
+/// Either limit prunes the oldest versions when an object is uploaded
+/// again; without either every version is kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Retention {
+    /// The most replaced versions kept of an object, `0` to keep none.
+    pub keep: Option<usize>,
+    /// Replaced versions older than this many days are dropped.
+    pub max_age_days: Option<u64>,
+}
+
+/// A backup of a directory at a point in time, without its manifest.
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub id: i64,
+    /// The backup set the snapshot belongs to.
+    pub name: String,
+    pub source: String,
+    pub size: u64,
+    pub files: u64,
+    pub sealed: bool,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+/// What refers to slabs in the catalog: an object, trashed or not, one of
+/// its previous versions, a snapshot or a deduplicated chunk.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Owner {
+    Object(String),
+    Version(String, u64),
+    Snapshot(i64),
+    Chunk(String),
+}
+
+impl Owner {
+    fn columns(&self) -> (&'static str, String, i64) {
+        match self {
+            Owner::Object(name) => ("object", name.clone(), 0),
+            Owner::Version(name, version) => ("version", name.clone(), *version as i64),
+            Owner::Snapshot(id) => ("snapshot", id.to_string(), 0),
+            Owner::Chunk(hash) => ("chunk", hash.clone(), 0),
+        }
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::Object(name) => write!(f, "{name}"),
+            Owner::Version(name, version) => write!(f, "{name} version {version}"),
+            Owner::Snapshot(id) => write!(f, "snapshot {id}"),
+            Owner::Chunk(hash) => write!(f, "chunk {hash}"),
+        }
+    }
+}
+
+/// What removing an object and its versions for good would leave behind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Release {
+    /// The slabs nothing else in the catalog refers to, which could be
+    /// released.
+    pub released: u64,
+    /// The bytes hosts store for them, parity included.
+    pub released_bytes: u64,
+    /// The slabs other objects, versions, snapshots or chunks still refer
+    /// to, which must be kept.
+    pub shared: u64,
+}
+
+/// A local database of uploads, keyed by name, holding each upload's
+/// manifest so it can be downloaded without keeping manifest files around.
+pub struct Catalog {
+    conn: Connection,
+    retention: Retention,
+}
+
+impl Catalog {
+    /// Opens the catalog at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        // the catalog holds the keys of unsealed uploads
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+        conn.execute_batch(SCHEMA)?;
+        for (table, column, kind) in MIGRATIONS {
+            let exists = conn
+                .prepare(&format!(
+                    "SELECT 1 FROM pragma_table_info('{table}') WHERE name = ?1"
+                ))?
+                .exists([column])?;
+            if !exists {
+                conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {kind}"))?;
+            }
+        }
+        Ok(Self {
+            conn,
+            retention: Retention::default(),
+        })
+    }
+
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Records an upload. An object with the same name becomes its previous
+    /// version, pruned as the retention allows. `stored` is the manifest as
+    /// written to disk, sealed or not; plain manifests hold the object's
+    /// keys, so the catalog should be protected like them.
+    pub fn put(
+        &self,
+        name: &str,
+        source: Option<&str>,
+        manifest: &AnyManifest,
+        stored: &StoredManifest,
+    ) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.keep_version(name)?;
+        self.replace(name, source, manifest, stored)?;
+        self.prune_versions(name)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Catalogues the object called `src` under `dst` as well, without
+    /// uploading anything: the copy refers to the same slabs, with the same
+    /// keys. An object already called `dst` becomes its previous version,
+    /// as with [`Catalog::put`]. Returns whether `src` exists.
+    pub fn copy(&self, src: &str, dst: &str) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        if !self.contains(src)? {
+            return Ok(false);
+        }
+        self.keep_version(dst)?;
+        self.conn.execute(
+            "INSERT INTO objects
+                (name, source, size, files, sealed, key_ref, manifest, created_at, updated_at,
+                 modified, checked_at, damaged, tags, metadata)
+             SELECT ?2, source, size, files, sealed, key_ref, manifest, ?3, ?3,
+                    modified, checked_at, damaged, tags, metadata
+             FROM objects WHERE name = ?1
+             ON CONFLICT (name) DO UPDATE SET
+                source = excluded.source,
+                size = excluded.size,
+                files = excluded.files,
+                sealed = excluded.sealed,
+                key_ref = excluded.key_ref,
+                manifest = excluded.manifest,
+                updated_at = excluded.updated_at,
+                modified = excluded.modified,
+                deleted_at = NULL,
+                checked_at = excluded.checked_at,
+                damaged = excluded.damaged,
+                tags = excluded.tags,
+                metadata = excluded.metadata",
+            params![src, dst, now() as i64],
+        )?;
+        self.copy_refs(
+            &Owner::Object(src.to_string()),
+            &Owner::Object(dst.to_string()),
+        )?;
+        self.prune_versions(dst)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Renames the object called `src` and its versions to `dst`, returning
+    /// whether `src` exists. Nothing may be catalogued as `dst` yet, not
+    /// even in the trash.
+    pub fn rename(&self, src: &str, dst: &str) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        let taken = self
+            .conn
+            .query_row("SELECT 1 FROM objects WHERE name = ?1", [dst], |_| Ok(()))
+            .optional()?;
+        if taken.is_some() {
+            return Err(Error::Usage(format!(
+                "{dst} is already catalogued; remove it first or copy over it with cp"
+            )));
+        }
+        if !self.contains(src)? {
+            return Ok(false);
+        }
+        self.conn.execute(
+            "UPDATE versions SET name = ?2 WHERE name = ?1",
+            params![src, dst],
+        )?;
+        self.conn.execute(
+            "UPDATE objects SET name = ?2, updated_at = ?3 WHERE name = ?1",
+            params![src, dst, now() as i64],
+        )?;
+        self.conn.execute(
+            "UPDATE slab_refs SET owner = ?2 WHERE kind IN ('object', 'version') AND owner = ?1",
+            params![src, dst],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Counts the slabs that removing the object called `name` and its
+    /// versions would leave without a reference, and those others still
+    /// share, such as copies made with [`Catalog::copy`] or later snapshots
+    /// of the same files.
+    pub fn release(&self, name: &str) -> Result<Release> {
+        let (released, released_bytes, total): (i64, i64, i64) = self.conn.query_row(
+            "WITH mine AS (
+                SELECT slab, MAX(stored) AS stored FROM slab_refs
+                WHERE kind IN ('object', 'version') AND owner = ?1
+                GROUP BY slab
+             ),
+             released AS (
+                SELECT * FROM mine WHERE NOT EXISTS
+                    (SELECT 1 FROM slab_refs r WHERE r.slab = mine.slab
+                     AND NOT (r.kind IN ('object', 'version') AND r.owner = ?1))
+             )
+             SELECT (SELECT count(*) FROM released),
+                    (SELECT COALESCE(SUM(stored), 0) FROM released),
+                    (SELECT count(*) FROM mine)",
+            [name],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        Ok(Release {
+            released: released as u64,
+            released_bytes: released_bytes as u64,
+            shared: (total - released) as u64,
+        })
+    }
+
+    /// Returns every manifest that refers to slabs, with what holds it.
+    pub fn owners(&self) -> Result<Vec<(Owner, StoredManifest)>> {
+        let mut owners = Vec::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, manifest FROM objects ORDER BY name")?;
+        for row in stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))? {
+            let (name, manifest) = row?;
+            owners.push((Owner::Object(name), parse_stored(&manifest)?));
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, version, manifest FROM versions ORDER BY name, version")?;
+        for row in stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get(2)?))
+        })? {
+            let (name, version, manifest) = row?;
+            owners.push((
+                Owner::Version(name, version as u64),
+                parse_stored(&manifest)?,
+            ));
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, manifest FROM snapshots ORDER BY id")?;
+        for row in stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get(1)?)))? {
+            let (id, manifest) = row?;
+            owners.push((Owner::Snapshot(id), parse_stored(&manifest)?));
+        }
+        Ok(owners)
+    }
+
+    /// Returns the slabs of every deduplicated chunk.
+    pub fn chunk_owners(&self) -> Result<Vec<(Owner, Vec<Slab>)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT hash, slabs FROM chunks ORDER BY hash")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter()
+            .map(|(hash, slabs)| Ok((Owner::Chunk(hash), serde_json::from_str(&slabs)?)))
+            .collect()
+    }
+
+    /// Returns the slabs recorded as referred to by `owner`.
+    pub fn refs(&self, owner: &Owner) -> Result<BTreeSet<String>> {
+        let (kind, name, version) = owner.columns();
+        let mut stmt = self.conn.prepare(
+            "SELECT slab FROM slab_refs WHERE kind = ?1 AND owner = ?2 AND version = ?3",
+        )?;
+        let slabs = stmt
+            .query_map(params![kind, name, version], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(slabs)
+    }
+
+    /// Records that `owner` refers to exactly `slabs`.
+    pub fn set_refs<'a>(
+        &self,
+        owner: &Owner,
+        slabs: impl IntoIterator<Item = &'a Slab>,
+    ) -> Result<()> {
+        let (kind, name, version) = owner.columns();
+        self.conn.execute(
+            "DELETE FROM slab_refs WHERE kind = ?1 AND owner = ?2 AND version = ?3",
+            params![kind, name, version],
+        )?;
+        let mut stmt = self.conn.prepare(
+            "INSERT OR IGNORE INTO slab_refs (slab, stored, kind, owner, version)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for slab in slabs {
+            let stored = slab.sectors.len() as u64 * SECTOR_SIZE;
+            stmt.execute(params![slab_key(slab)?, stored as i64, kind, name, version])?;
+        }
+        Ok(())
+    }
+
+    /// Counts the references held for objects, versions, snapshots and
+    /// chunks that are gone.
+    pub fn dangling_refs(&self) -> Result<u64> {
+        let n: i64 = self.conn.query_row(
+            &format!("SELECT count(*) FROM slab_refs WHERE {DANGLING_REFS}"),
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(n as u64)
+    }
+
+    /// Drops the references [`Catalog::dangling_refs`] counts.
+    pub fn drop_dangling_refs(&self) -> Result<u64> {
+        let n = self
+            .conn
+            .execute(&format!("DELETE FROM slab_refs WHERE {DANGLING_REFS}"), [])?;
+        Ok(n as u64)
+    }
+
+    fn copy_refs(&self, from: &Owner, to: &Owner) -> Result<()> {
+        let (from_kind, from_name, from_version) = from.columns();
+        let (kind, name, version) = to.columns();
+        self.conn.execute(
+            "DELETE FROM slab_refs WHERE kind = ?1 AND owner = ?2 AND version = ?3",
+            params![kind, name, version],
+        )?;
+        self.conn.execute(
+            "INSERT OR IGNORE INTO slab_refs (slab, stored, kind, owner, version)
+             SELECT slab, stored, ?4, ?5, ?6 FROM slab_refs
+             WHERE kind = ?1 AND owner = ?2 AND version = ?3",
+            params![from_kind, from_name, from_version, kind, name, version],
+        )?;
+        Ok(())
+    }
+
+    /// Keeps the object called `name`, if any, as its newest previous
+    /// version.
+    fn keep_version(&self, name: &str) -> Result<()> {
+        let kept = self.conn.execute(
+            "INSERT INTO versions
+                (name, version, source, size, files, sealed, key_ref, manifest,
+                 created_at, replaced_at)
+             SELECT name,
+                    (SELECT COALESCE(MAX(version), 0) + 1 FROM versions WHERE name = ?1),
+                    source, size, files, sealed, key_ref, manifest, updated_at, ?2
+             FROM objects WHERE name = ?1",
+            params![name, now() as i64],
+        )?;
+        if kept > 0 {
+            let version: i64 = self.conn.query_row(
+                "SELECT MAX(version) FROM versions WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )?;
+            self.copy_refs(
+                &Owner::Object(name.to_string()),
+                &Owner::Version(name.to_string(), version as u64),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the latest version of an object in place, as repair and
+    /// rekey do, without keeping the manifest it replaces. What the last
+    /// check found no longer applies and is forgotten.
+    pub fn replace(
+        &self,
+        name: &str,
+        source: Option<&str>,
+        manifest: &AnyManifest,
+        stored: &StoredManifest,
+    ) -> Result<()> {
+        let sealed = matches!(stored, StoredManifest::Sealed(_));
+        let (size, files, key_ref) = match manifest {
+            AnyManifest::File(m) => (m.size, None, Some(m.key_fingerprint.clone())),
+            AnyManifest::Directory(m) => (m.size(), Some(m.files.len() as i64), None),
+        };
+        let key_ref = if sealed {
+            Some("passphrase".to_string())
+        } else {
+            key_ref
+        };
+        self.conn.execute(
+            "INSERT INTO objects
+                (name, source, size, files, sealed, key_ref, manifest, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)
+             ON CONFLICT (name) DO UPDATE SET
+                source = coalesce(excluded.source, objects.source),
+                size = excluded.size,
+                files = excluded.files,
+                sealed = excluded.sealed,
+                key_ref = excluded.key_ref,
+                manifest = excluded.manifest,
+                updated_at = excluded.updated_at,
+                deleted_at = NULL,
+                checked_at = NULL,
+                damaged = NULL",
+            params![
+                name,
+                source,
+                size as i64,
+                files,
+                sealed,
+                key_ref,
+                serde_json::to_string(stored)?,
+                now() as i64,
+            ],
+        )?;
+        self.set_refs(&Owner::Object(name.to_string()), slabs(manifest))?;
+        Ok(())
+    }
+
+    /// Drops the replaced versions of `name` the retention doesn't keep.
+    fn prune_versions(&self, name: &str) -> Result<()> {
+        if let Some(days) = self.retention.max_age_days {
+            let cutoff = now().saturating_sub(days * 24 * 60 * 60);
+            self.conn.execute(
+                "DELETE FROM versions WHERE name = ?1 AND replaced_at < ?2",
+                params![name, cutoff as i64],
+            )?;
+        }
+        if let Some(keep) = self.retention.keep {
+            self.conn.execute(
+                "DELETE FROM versions WHERE name = ?1 AND version NOT IN
+                    (SELECT version FROM versions WHERE name = ?1
+                     ORDER BY version DESC LIMIT ?2)",
+                params![name, keep as i64],
+            )?;
+        }
+        self.conn.execute(
+            "DELETE FROM slab_refs WHERE kind = 'version' AND owner = ?1
+             AND version NOT IN (SELECT version FROM versions WHERE name = ?1)",
+            [name],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the manifest of version `version` of the object called
+    /// `name`, which may be its latest.
+    pub fn get_version(&self, name: &str, version: u64) -> Result<Option<StoredManifest>> {
+        if self.latest_version(name)? == Some(version) {
+            return self.get(name);
+        }
+        let manifest: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT manifest FROM versions WHERE name = ?1 AND version = ?2",
+                params![name, version as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(manifest) = manifest else {
+            return Ok(None);
+        };
+        let manifest: StoredManifest = serde_json::from_str(&manifest)?;
+        manifest.check_version()?;
+        Ok(Some(manifest))
+    }
+
+    /// Returns the number of the latest version of `name`, if it is
+    /// catalogued.
+    fn latest_version(&self, name: &str) -> Result<Option<u64>> {
+        if !self.contains(name)? {
+            return Ok(None);
+        }
+        let replaced: i64 = self.conn.query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM versions WHERE name = ?1",
+            [name],
+            |row| row.get(0),
+        )?;
+        Ok(Some(replaced as u64 + 1))
+    }
+
+    /// Lists every kept version of the objects whose names start with
+    /// `prefix`, in name and then version order.
+    pub fn list_versions(&self, prefix: &str) -> Result<Vec<Version>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, version, size, sealed, created_at, replaced_at FROM (
+                SELECT name, version, size, sealed, created_at, replaced_at FROM versions
+                UNION ALL
+                SELECT o.name,
+                       (SELECT COALESCE(MAX(v.version), 0) + 1 FROM versions v
+                        WHERE v.name = o.name),
+                       o.size, o.sealed, o.updated_at, NULL
+                FROM objects o WHERE o.deleted_at IS NULL
+             )
+             WHERE substr(name, 1, length(?1)) = ?1 ORDER BY name, version",
+        )?;
+        let versions = stmt
+            .query_map([prefix], |row| {
+                Ok(Version {
+                    name: row.get(0)?,
+                    version: row.get::<_, i64>(1)? as u64,
+                    size: row.get::<_, i64>(2)? as u64,
+                    sealed: row.get(3)?,
+                    created_at: row.get::<_, i64>(4)? as u64,
+                    replaced_at: row.get::<_, Option<i64>>(5)?.map(|t| t as u64),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(versions)
+    }
+
+    /// Returns the manifest of the object called `name`.
+    pub fn get(&self, name: &str) -> Result<Option<StoredManifest>> {
+        let manifest: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT manifest FROM objects WHERE name = ?1 AND deleted_at IS NULL",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(manifest) = manifest else {
+            return Ok(None);
+        };
+        let manifest: StoredManifest = serde_json::from_str(&manifest)?;
+        manifest.check_version()?;
+        Ok(Some(manifest))
+    }
+
+    /// Lists the catalogued objects whose names start with `prefix`, in name
+    /// order.
+    pub fn list(&self, prefix: &str) -> Result<Vec<Entry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, source, size, files, sealed, key_ref, created_at, updated_at, modified,
+                    tags, metadata
+             FROM objects
+             WHERE substr(name, 1, length(?1)) = ?1 AND deleted_at IS NULL
+             ORDER BY name",
+        )?;
+        let entries = stmt
+            .query_map([prefix], |row| {
+                Ok(Entry {
+                    name: row.get(0)?,
+                    source: row.get(1)?,
+                    size: row.get::<_, i64>(2)? as u64,
+                    files: row.get::<_, Option<i64>>(3)?.map(|f| f as u64),
+                    sealed: row.get(4)?,
+                    key_ref: row.get(5)?,
+                    created_at: row.get::<_, i64>(6)? as u64,
+                    updated_at: row.get::<_, i64>(7)? as u64,
+                    modified: row.get::<_, Option<i64>>(8)?.map(|m| m as u64),
+                    tags: labels(row.get(9)?),
+                    metadata: labels(row.get(10)?),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    /// Lists the catalogued objects under `prefix` carrying every one of
+    /// `tags`, in name order.
+    pub fn list_tagged(&self, prefix: &str, tags: &[Tag]) -> Result<Vec<Entry>> {
+        let mut entries = self.list(prefix)?;
+        entries.retain(|entry| entry.has_tags(tags));
+        Ok(entries)
+    }
+
+    /// Adds tags and metadata to the object called `name`, replacing the
+    /// values of keys it already has. They are kept when the object is
+    /// uploaded again, repaired or copied.
+    pub fn add_labels(
+        &self,
+        name: &str,
+        tags: &BTreeMap<String, String>,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let current: Option<(Option<String>, Option<String>)> = self
+            .conn
+            .query_row(
+                "SELECT tags, metadata FROM objects WHERE name = ?1",
+                [name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((current_tags, current_metadata)) = current else {
+            return Ok(());
+        };
+        let mut all_tags = labels(current_tags);
+        all_tags.extend(tags.clone());
+        let mut all_metadata = labels(current_metadata);
+        all_metadata.extend(metadata.clone());
+        self.conn.execute(
+            "UPDATE objects SET tags = ?2, metadata = ?3 WHERE name = ?1",
+            params![
+                name,
+                serde_json::to_string(&all_tags)?,
+                serde_json::to_string(&all_metadata)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> Result<bool> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM objects WHERE name = ?1 AND deleted_at IS NULL",
+                [name],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    /// Records the modification time of the file an object was uploaded
+    /// from.
+    pub fn set_modified(&self, name: &str, modified: Option<u64>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE objects SET modified = ?2 WHERE name = ?1",
+            params![name, modified.map(|m| m as i64)],
+        )?;
+        Ok(())
+    }
+
+    /// Records that `name` was just checked and how many of its slabs still
+    /// need attention.
+    pub fn record_check(&self, name: &str, damaged: u64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE objects SET checked_at = ?2, damaged = ?3 WHERE name = ?1",
+            params![name, now() as i64, damaged as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Returns what the last check of each checked object found.
+    pub fn checks(&self) -> Result<BTreeMap<String, Check>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, checked_at, damaged FROM objects
+             WHERE checked_at IS NOT NULL AND deleted_at IS NULL",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Check {
+                    checked_at: row.get::<_, i64>(1)? as u64,
+                    damaged: row.get::<_, Option<i64>>(2)?.unwrap_or(0) as u64,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Moves the object called `name` to the trash, returning whether it
+    /// existed. A trashed object is hidden from everything but the trash
+    /// until it is restored, purged or uploaded again.
+    pub fn trash(&self, name: &str) -> Result<bool> {
+        let n = self.conn.execute(
+            "UPDATE objects SET deleted_at = ?2 WHERE name = ?1 AND deleted_at IS NULL",
+            params![name, now() as i64],
+        )?;
+        Ok(n > 0)
+    }
+
+    /// Brings a trashed object back, returning whether it was in the trash.
+    pub fn untrash(&self, name: &str) -> Result<bool> {
+        let n = self.conn.execute(
+            "UPDATE objects SET deleted_at = NULL WHERE name = ?1 AND deleted_at IS NOT NULL",
+            [name],
+        )?;
+        Ok(n > 0)
+    }
+
+    /// Lists the trashed objects, oldest deletion first.
+    pub fn list_trash(&self) -> Result<Vec<Trashed>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, size, deleted_at, tags FROM objects
+             WHERE deleted_at IS NOT NULL ORDER BY deleted_at, name",
+        )?;
+        let trashed = stmt
+            .query_map([], |row| {
+                Ok(Trashed {
+                    name: row.get(0)?,
+                    size: row.get::<_, i64>(1)? as u64,
+                    deleted_at: row.get::<_, i64>(2)? as u64,
+                    tags: labels(row.get(3)?),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(trashed)
+    }
+
+    /// Purges the objects trashed longer than `retention` ago, along with
+    /// their versions, and returns their names.
+    pub fn expire_trash(&self, retention: Duration) -> Result<Vec<String>> {
+        let cutoff = now().saturating_sub(retention.as_secs());
+        let expired: Vec<String> = self
+            .conn
+            .prepare("SELECT name FROM objects WHERE deleted_at <= ?1")?
+            .query_map([cutoff as i64], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        for name in &expired {
+            self.remove(name)?;
+        }
+        Ok(expired)
+    }
+
+    /// Removes a replaced version of an object, returning whether it was
+    /// kept.
+    pub fn remove_version(&self, name: &str, version: u64) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        let n = self.conn.execute(
+            "DELETE FROM versions WHERE name = ?1 AND version = ?2",
+            params![name, version as i64],
+        )?;
+        self.conn.execute(
+            "DELETE FROM slab_refs WHERE kind = 'version' AND owner = ?1 AND version = ?2",
+            params![name, version as i64],
+        )?;
+        tx.commit()?;
+        Ok(n > 0)
+    }
+
+    /// Removes the object called `name` and its versions for good, trashed
+    /// or not, returning whether it existed.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.conn.execute(
+            "DELETE FROM slab_refs WHERE kind IN ('object', 'version') AND owner = ?1",
+            [name],
+        )?;
+        self.conn
+            .execute("DELETE FROM versions WHERE name = ?1", [name])?;
+        let n = self
+            .conn
+            .execute("DELETE FROM objects WHERE name = ?1", [name])?;
+        tx.commit()?;
+        Ok(n > 0)
+    }
+
+    /// Records the latest reference of a bucket, replacing the previous one.
+    pub fn put_bucket(&self, reference: &BucketRef) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO buckets (name, reference, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (name) DO UPDATE SET
+                reference = excluded.reference,
+                updated_at = excluded.updated_at",
+            params![
+                reference.name,
+                serde_json::to_string(reference)?,
+                now() as i64
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Records a bucket's new reference over the generation before it,
+    /// returning `false` without recording anything if the catalog holds
+    /// another generation, so a commit never overwrites one it didn't see.
+    /// A bucket the catalog doesn't know is at generation 0.
+    pub fn swap_bucket(&self, reference: &BucketRef) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        let previous = reference.generation.saturating_sub(1);
+        if !self.bucket_is_current(&reference.name, previous)? {
+            return Ok(false);
+        }
+        self.put_bucket(reference)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Whether the catalog's reference of the bucket `name` is still at
+    /// `generation`.
+    pub fn bucket_is_current(&self, name: &str, generation: u64) -> Result<bool> {
+        let catalogued = self
+            .get_bucket(name)?
+            .map_or(0, |reference| reference.generation);
+        Ok(catalogued == generation)
+    }
+
+    pub fn get_bucket(&self, name: &str) -> Result<Option<BucketRef>> {
+        let reference: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT reference FROM buckets WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match reference {
+            Some(reference) => Ok(Some(serde_json::from_str(&reference)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the names of the known buckets in order.
+    pub fn list_buckets(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM buckets ORDER BY name")?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(names)
+    }
+
+    /// Forgets a bucket, returning whether it was known.
+    pub fn remove_bucket(&self, name: &str) -> Result<bool> {
+        let n = self
+            .conn
+            .execute("DELETE FROM buckets WHERE name = ?1", [name])?;
+        Ok(n > 0)
+    }
+
+    /// Records a snapshot of `source` in the backup set `name` and returns
+    /// its id.
+    pub fn put_snapshot(
+        &self,
+        name: &str,
+        source: &str,
+        manifest: &DirectoryManifest,
+        stored: &StoredManifest,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO snapshots (name, source, size, files, sealed, manifest, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                name,
+                source,
+                manifest.size() as i64,
+                manifest.files.len() as i64,
+                matches!(stored, StoredManifest::Sealed(_)),
+                serde_json::to_string(stored)?,
+                now() as i64,
+            ],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        let files = manifest.files.iter().flat_map(|f| &f.manifest.slabs);
+        self.set_refs(&Owner::Snapshot(id), files)?;
+        Ok(id)
+    }
+
+    /// Returns the manifest of the snapshot with `id`.
+    pub fn get_snapshot(&self, id: i64) -> Result<Option<StoredManifest>> {
+        let manifest: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT manifest FROM snapshots WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match manifest {
+            Some(manifest) => {
+                let stored: StoredManifest = serde_json::from_str(&manifest)?;
+                stored.check_version()?;
+                Ok(Some(stored))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the id of the most recent snapshot in the backup set `name`.
+    pub fn latest_snapshot(&self, name: &str) -> Result<Option<i64>> {
+        let id = self.conn.query_row(
+            "SELECT max(id) FROM snapshots WHERE name = ?1",
+            [name],
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
+
+    /// Lists the snapshots of the backup set `name`, or of every set, oldest
+    /// first.
+    pub fn list_snapshots(&self, name: Option<&str>) -> Result<Vec<Snapshot>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, source, size, files, sealed, created_at FROM snapshots
+             WHERE ?1 IS NULL OR name = ?1 ORDER BY id",
+        )?;
+        let snapshots = stmt
+            .query_map([name], |row| {
+                Ok(Snapshot {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    source: row.get(2)?,
+                    size: row.get::<_, i64>(3)? as u64,
+                    files: row.get::<_, i64>(4)? as u64,
+                    sealed: row.get(5)?,
+                    created_at: row.get::<_, i64>(6)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(snapshots)
+    }
+
+    /// Records where a deduplicated chunk is stored. `slabs` hold the
+    /// chunk's keys, like a plain manifest.
+    pub fn put_chunk(&self, hash: &[u8; 32], length: u64, slabs: &[Slab]) -> Result<()> {
+        let added = self.conn.execute(
+            "INSERT OR IGNORE INTO chunks (hash, length, slabs, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                hex::encode(hash),
+                length as i64,
+                serde_json::to_string(slabs)?,
+                now() as i64
+            ],
+        )?;
+        if added > 0 {
+            self.set_refs(&Owner::Chunk(hex::encode(hash)), slabs)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the slab slices holding the chunk with `hash`, if it has
+    /// been stored.
+    pub fn get_chunk(&self, hash: &[u8; 32]) -> Result<Option<Vec<Slab>>> {
+        let slabs: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT slabs FROM chunks WHERE hash = ?1",
+                [hex::encode(hash)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match slabs {
+            Some(slabs) => Ok(Some(serde_json::from_str(&slabs)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a consistent copy of the whole catalog database. The copy is
+    /// written next to the catalog first, so it is only as exposed as the
+    /// catalog itself.
+    pub fn dump(&self) -> Result<Vec<u8>> {
+        let path = self.conn.path().unwrap_or_default();
+        let copy = format!("{path}.dump");
+        let _ = fs::remove_file(&copy);
+        self.conn.execute("VACUUM INTO ?1", [&copy])?;
+        let db = fs::read(&copy);
+        fs::remove_file(&copy)?;
+        Ok(db?)
+    }
+
+    /// Returns every manifest the catalog holds: those of its objects,
+    /// trashed or not, their previous versions and the snapshots, along
+    /// with the slabs of the deduplicated chunks.
+    pub fn all_manifests(&self) -> Result<(Vec<StoredManifest>, Vec<Slab>)> {
+        let manifests: Vec<String> = self
+            .conn
+            .prepare(
+                "SELECT manifest FROM objects
+                 UNION ALL SELECT manifest FROM versions
+                 UNION ALL SELECT manifest FROM snapshots",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let manifests = manifests
+            .iter()
+            .map(|manifest| {
+                let stored: StoredManifest = serde_json::from_str(manifest)?;
+                stored.check_version()?;
+                Ok(stored)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let chunks: Vec<String> = self
+            .conn
+            .prepare("SELECT slabs FROM chunks")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut slabs = Vec::new();
+        for chunk in &chunks {
+            slabs.extend(serde_json::from_str::<Vec<Slab>>(chunk)?);
+        }
+        Ok((manifests, slabs))
+    }
+
+    /// Records what the upload of `name` was charged against the budget.
+    pub fn record_spending(&self, name: &str, amount: f64) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO spending (name, amount, created_at) VALUES (?1, ?2, ?3)",
+            params![name, amount, now() as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the size of the objects catalogued within the last `window`.
+    pub fn uploaded_since(&self, window: Duration) -> Result<u64> {
+        let since = now().saturating_sub(window.as_secs());
+        let size: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(size), 0) FROM objects WHERE created_at >= ?1",
+            [since as i64],
+            |row| row.get(0),
+        )?;
+        Ok(size as u64)
+    }
+
+    /// Adds the transfers seen of each host to what earlier sessions saw.
+    pub fn record_host_stats(&self, hosts: &BTreeMap<String, HostStats>) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for (host, stats) in hosts {
+            tx.execute(
+                "INSERT INTO host_stats (host, transfers, failures, bytes, busy_ms, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT(host) DO UPDATE SET
+                     transfers = transfers + excluded.transfers,
+                     failures = failures + excluded.failures,
+                     bytes = bytes + excluded.bytes,
+                     busy_ms = busy_ms + excluded.busy_ms,
+                     updated_at = excluded.updated_at",
+                params![
+                    host,
+                    stats.transfers as i64,
+                    stats.failures as i64,
+                    stats.bytes as i64,
+                    stats.busy_ms as i64,
+                    now() as i64
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns what every session so far has seen of each host.
+    pub fn host_stats(&self) -> Result<BTreeMap<String, HostStats>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT host, transfers, failures, bytes, busy_ms FROM host_stats")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                HostStats {
+                    transfers: row.get::<_, i64>(1)? as u64,
+                    failures: row.get::<_, i64>(2)? as u64,
+                    bytes: row.get::<_, i64>(3)? as u64,
+                    busy_ms: row.get::<_, i64>(4)? as u64,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Returns the total charged within the last `window`.
+    pub fn spent_since(&self, window: Duration) -> Result<f64> {
+        let since = now().saturating_sub(window.as_secs());
+        Ok(self.conn.query_row(
+            "SELECT COALESCE(SUM(amount), 0.0) FROM spending WHERE created_at >= ?1",
+            [since as i64],
+            |row| row.get(0),
+        )?)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Reads a tags or metadata column, empty when unset or unreadable.
+fn labels(column: Option<String>) -> BTreeMap<String, String> {
+    column
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn parse_stored(manifest: &str) -> Result<StoredManifest> {
+    let stored: StoredManifest = serde_json::from_str(manifest)?;
+    stored.check_version()?;
+    Ok(stored)
+}
+
+/// Returns the slabs, or slices of them, a manifest refers to.
+pub(crate) fn slabs(manifest: &AnyManifest) -> Vec<&Slab> {
+    match manifest {
+        AnyManifest::File(manifest) => manifest.slabs.iter().collect(),
+        AnyManifest::Directory(manifest) => manifest
+            .files
+            .iter()
+            .flat_map(|f| &f.manifest.slabs)
+            .collect(),
+    }
+}
+
+/// Identifies a slab by its sectors, which slices of it keep.
+pub fn slab_key(slab: &Slab) -> Result<String> {
+    let sectors = serde_json::to_vec(&slab.sectors)?;
+    Ok(hex::encode(Sha256::digest(sectors)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::client::Client;
+    use crate::mock::{self, MockBackend};
+    use crate::progress::Progress;
+    use crate::upload::{self, UploadOptions};
+
+    fn open(dir: &Path) -> Catalog {
+        Catalog::open(&dir.join("catalog.db")).unwrap()
+    }
+
+    async fn upload(sdk: &Client, data: &[u8]) -> AnyManifest {
+        let reader = Cursor::new(data.to_vec());
+        let options = UploadOptions::new(1, 1);
+        let manifest = upload::upload_reader(sdk, reader, [1; 32], options, Progress::default())
+            .await
+            .unwrap();
+        AnyManifest::File(manifest)
+    }
+
+    fn put(catalog: &Catalog, name: &str, manifest: &AnyManifest) {
+        let stored = StoredManifest::Plain(manifest.clone());
+        catalog.put(name, None, manifest, &stored).unwrap();
+    }
+
+    fn size(stored: Option<StoredManifest>) -> Option<u64> {
+        match stored? {
+            StoredManifest::Plain(AnyManifest::File(manifest)) => Some(manifest.size),
+            _ => panic!("expected a plain file manifest"),
+        }
+    }
+
+    fn names(entries: Vec<Entry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[tokio::test]
+    async fn replaced_objects_become_versions() {
+        let dir = mock::temp_dir();
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let catalog = open(&dir);
+        for data in [&b"one"[..], b"two!", b"three"] {
+            put(&catalog, "notes", &upload(&sdk, data).await);
+        }
+
+        let versions: Vec<(u64, u64, bool)> = catalog
+            .list_versions("notes")
+            .unwrap()
+            .iter()
+            .map(|v| (v.version, v.size, v.replaced_at.is_some()))
+            .collect();
+        assert_eq!(versions, [(1, 3, true), (2, 4, true), (3, 5, false)]);
+        assert_eq!(size(catalog.get_version("notes", 1).unwrap()), Some(3));
+        assert_eq!(size(catalog.get_version("notes", 3).unwrap()), Some(5));
+        assert_eq!(size(catalog.get_version("notes", 4).unwrap()), None);
+        assert_eq!(size(catalog.get("notes").unwrap()), Some(5));
+
+        assert!(catalog.remove_version("notes", 1).unwrap());
+        assert!(!catalog.remove_version("notes", 1).unwrap());
+        assert_eq!(catalog.list_versions("").unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn retention_prunes_the_oldest_versions() {
+        let dir = mock::temp_dir();
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let catalog = open(&dir).with_retention(Retention {
+            keep: Some(1),
+            max_age_days: None,
+        });
+        for data in [&b"one"[..], b"two!", b"three"] {
+            put(&catalog, "notes", &upload(&sdk, data).await);
+        }
+        let versions: Vec<u64> = catalog
+            .list_versions("notes")
+            .unwrap()
+            .iter()
+            .map(|v| v.version)
+            .collect();
+        assert_eq!(versions, [2, 3]);
+        // the pruned version's slabs are no longer held for it
+        let version = |n| Owner::Version("notes".into(), n);
+        assert!(catalog.refs(&version(1)).unwrap().is_empty());
+        assert!(!catalog.refs(&version(2)).unwrap().is_empty());
+
+        let catalog = catalog.with_retention(Retention {
+            keep: Some(0),
+            max_age_days: None,
+        });
+        put(&catalog, "notes", &upload(&sdk, b"four").await);
+        assert_eq!(catalog.list_versions("notes").unwrap().len(), 1);
+        assert_eq!(catalog.dangling_refs().unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn trashed_objects_can_be_restored() {
+        let dir = mock::temp_dir();
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let catalog = open(&dir);
+        put(&catalog, "a", &upload(&sdk, b"a").await);
+        put(&catalog, "b", &upload(&sdk, b"b").await);
+
+        assert!(catalog.trash("a").unwrap());
+        assert!(!catalog.trash("a").unwrap());
+        assert!(!catalog.contains("a").unwrap());
+        assert!(catalog.get("a").unwrap().is_none());
+        assert_eq!(names(catalog.list("").unwrap()), ["b"]);
+        let trashed = catalog.list_trash().unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].name, "a");
+        assert!(catalog.untrash("a").unwrap());
+        assert!(!catalog.untrash("a").unwrap());
+        assert_eq!(names(catalog.list("").unwrap()), ["a", "b"]);
+
+        // uploading a trashed object again brings it back too
+        catalog.trash("a").unwrap();
+        put(&catalog, "a", &upload(&sdk, b"aa").await);
+        assert!(catalog.list_trash().unwrap().is_empty());
+        assert_eq!(size(catalog.get("a").unwrap()), Some(2));
+
+        catalog.trash("b").unwrap();
+        assert!(
+            catalog
+                .expire_trash(Duration::from_secs(3600))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(catalog.expire_trash(Duration::ZERO).unwrap(), ["b"]);
+        assert!(catalog.list_trash().unwrap().is_empty());
+        assert!(!catalog.untrash("b").unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn shared_slabs_are_kept() {
+        let dir = mock::temp_dir();
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let catalog = open(&dir);
+        let data = mock::pattern(2 * SECTOR_SIZE as usize);
+        put(&catalog, "a", &upload(&sdk, &data).await);
+        let alone = Release {
+            released: 2,
+            released_bytes: 2 * 2 * SECTOR_SIZE,
+            shared: 0,
+        };
+        assert_eq!(catalog.release("a").unwrap(), alone);
+
+        assert!(catalog.copy("a", "b").unwrap());
+        let shared = Release {
+            released: 0,
+            released_bytes: 0,
+            shared: 2,
+        };
+        assert_eq!(catalog.release("a").unwrap(), shared);
+        // the copy replaced by a new upload still holds them as a version
+        put(&catalog, "b", &upload(&sdk, b"b").await);
+        assert_eq!(catalog.release("a").unwrap(), shared);
+        assert_eq!(catalog.release("b").unwrap().released, 1);
+
+        assert!(catalog.remove("b").unwrap());
+        assert!(!catalog.remove("b").unwrap());
+        assert_eq!(catalog.release("a").unwrap(), alone);
+        assert_eq!(catalog.dangling_refs().unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use indexd::Slab;
+use log::debug;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::backend::{Backend, UploadReader};
+use crate::error::{Error, Result};
+
+/// How long a delayed transfer is held up unless `delay_ms` says otherwise.
+const DEFAULT_DELAY: Duration = Duration::from_secs(5);
+
+/// Faults injected into a fraction of transfers, to check that retries,
+/// hedging, timeouts and integrity checks catch them before real data is
+/// trusted to the tool.
+///
+/// Each upload or download the SDK is asked for is failed, delayed or
+/// corrupted independently with the given odds. The SDK moves a slab's
+/// shards together, so a fault hits a whole transfer rather than a single
+/// shard: a failure looks like too many hosts being unreachable, a delay
+/// like slow hosts, and corruption like a host returning bad data, which
+/// only the checksums can catch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chaos {
+    /// The fraction of transfers that fail before moving any data.
+    pub fail: f64,
+    /// The fraction of transfers held up by `delay_for` before starting.
+    pub delay: f64,
+    pub delay_for: Duration,
+    /// The fraction of transfers with one byte flipped.
+    pub corrupt: f64,
+}
+
+impl Chaos {
+    fn roll(odds: f64) -> bool {
+        odds > 0.0 && rand::random::<f64>() < odds
+    }
+}
+
+/// Parses comma separated settings such as `fail=0.05,delay=0.1,
+/// delay_ms=2000,corrupt=0.01`. Settings left out are 0, except `delay_ms`,
+/// which defaults to 5 seconds.
+impl FromStr for Chaos {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut chaos = Chaos {
+            fail: 0.0,
+            delay: 0.0,
+            delay_for: DEFAULT_DELAY,
+            corrupt: 0.0,
+        };
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let invalid = || {
+                Error::Usage(format!(
+                    "invalid chaos setting {setting:?}; expected fail, delay, corrupt or delay_ms, such as fail=0.05"
+                ))
+            };
+            let (key, value) = setting.split_once('=').ok_or_else(invalid)?;
+            if key == "delay_ms" {
+                chaos.delay_for = Duration::from_millis(value.parse().map_err(|_| invalid())?);
+                continue;
+            }
+            let odds: f64 = value.parse().map_err(|_| invalid())?;
+            if !(0.0..=1.0).contains(&odds) {
+                return Err(Error::Usage(format!(
+                    "chaos {key} must be a fraction between 0 and 1, not {value}"
+                )));
+            }
+            match key {
+                "fail" => chaos.fail = odds,
+                "delay" => chaos.delay = odds,
+                "corrupt" => chaos.corrupt = odds,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(chaos)
+    }
+}
+
+impl fmt::Display for Chaos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0}% failed, {:.0}% delayed by {}ms, {:.0}% corrupted",
+            self.fail * 100.0,
+            self.delay * 100.0,
+            self.delay_for.as_millis(),
+            self.corrupt * 100.0
+        )
+    }
+}
+
+/// Injects the faults of a [`Chaos`] into the transfers of another
+/// backend.
+pub struct ChaosBackend {
+    inner: Arc<dyn Backend>,
+    chaos: Chaos,
+}
+
+impl ChaosBackend {
+    pub fn new(inner: Arc<dyn Backend>, chaos: Chaos) -> Self {
+        Self { inner, chaos }
+    }
+
+    /// Fails or holds up a transfer as the odds say, returning whether its
+    /// data should be corrupted.
+    async fn inject(&self, what: &str) -> Result<bool> {
+        if Chaos::roll(self.chaos.fail) {
+            debug!("chaos: failing {what}");
+            return Err(Error::Host(format!("chaos: injected {what} failure")));
+        }
+        if Chaos::roll(self.chaos.delay) {
+            debug!("chaos: delaying {what}");
+            tokio::time::sleep(self.chaos.delay_for).await;
+        }
+        let corrupt = Chaos::roll(self.chaos.corrupt);
+        if corrupt {
+            debug!("chaos: corrupting {what}");
+        }
+        Ok(corrupt)
+    }
+}
+
+impl Backend for ChaosBackend {
+    fn upload(
+        &self,
+        reader: UploadReader,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> BoxFuture<'_, Result<Vec<Slab>>> {
+        Box::pin(async move {
+            let reader: UploadReader = if self.inject("upload").await? {
+                Box::new(Corrupt::new(reader, 0))
+            } else {
+                reader
+            };
+            self.inner
+                .upload(reader, encryption_key, data_shards, parity_shards)
+                .await
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        w: &'a mut (dyn AsyncWrite + Unpin + Send),
+        slabs: &'a [Slab],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if !self.inject("download").await? {
+                return self.inner.download(w, slabs).await;
+            }
+            let total: u64 = slabs.iter().map(|slab| slab.length as u64).sum();
+            let mut w = Corrupt::new(w, rand::random_range(0..total.max(1)));
+            self.inner.download(&mut w, slabs).await
+        })
+    }
+}
+
+/// Flips the byte at offset `at` of what's read or written through it.
+/// Uploads don't know how much they will read, so they flip their first
+/// byte.
+struct Corrupt<T> {
+    inner: T,
+    at: u64,
+    offset: u64,
+}
+
+impl<T> Corrupt<T> {
+    fn new(inner: T, at: u64) -> Self {
+        Self {
+            inner,
+            at,
+            offset: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Corrupt<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = &mut buf.filled_mut()[before..];
+        let end = this.offset + read.len() as u64;
+        if this.offset <= this.at && this.at < end {
+            read[(this.at - this.offset) as usize] ^= 0xff;
+        }
+        this.offset = end;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Corrupt<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let end = this.offset + buf.len() as u64;
+        if this.offset > this.at || this.at >= end {
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+            this.offset += n as u64;
+            return Poll::Ready(Ok(n));
+        }
+        // only the flipped byte is copied, in a write of its own
+        let i = (this.at - this.offset) as usize;
+        let n = if i > 0 {
+            ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..i]))?
+        } else {
+            ready!(Pin::new(&mut this.inner).poll_write(cx, &[buf[0] ^ 0xff]))?
+        };
+        this.offset += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
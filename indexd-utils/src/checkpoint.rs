@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+
+use indexd::Slab;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::aead::Cipher;
+use crate::compression::Compression;
+use crate::error::{Error, Result};
+use crate::keys::Kdf;
+use crate::sparse::{self, Hole};
+
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// The persisted state of an in-progress upload.
+///
+/// A checkpoint is only ever written after a segment has been fully
+/// uploaded, so `offset` always points at the first byte that has not been
+/// stored and `slabs` covers exactly `[0, offset)` of the input.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub input: PathBuf,
+    pub input_size: u64,
+    pub data_shards: u8,
+    pub parity_shards: u8,
+    /// The master key segment keys are derived from.
+    #[serde(with = "hex")]
+    pub encryption_key: [u8; 32],
+    /// The index of the next segment to upload. Segment keys are derived
+    /// from the master key and this index, so it is the only nonce state
+    /// needed to continue an upload without reusing a key.
+    pub next_segment: u64,
+    pub offset: u64,
+    pub slabs: Vec<Slab>,
+    /// Set when the master key was derived from a passphrase, so the
+    /// resulting manifest can be sealed after a resumed upload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<Kdf>,
+    /// Every segment is compressed with these settings, including those
+    /// uploaded after resuming.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// Every segment is sealed with this cipher and key, including those
+    /// uploaded after resuming.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<Cipher>,
+    /// Whether the manifest records the input's SHA-256.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sha256: bool,
+    /// The holes of a sparse input, left out of the upload. Segments and
+    /// `offset` count only the data around them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holes: Vec<Hole>,
+}
+
+impl Checkpoint {
+    pub fn new(
+        input: PathBuf,
+        input_size: u64,
+        encryption_key: [u8; 32],
+        kdf: Option<Kdf>,
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> Self {
+        Self {
+            version: CHECKPOINT_VERSION,
+            input,
+            input_size,
+            data_shards,
+            parity_shards,
+            encryption_key,
+            next_segment: 0,
+            offset: 0,
+            slabs: Vec::new(),
+            kdf,
+            compression: None,
+            cipher: None,
+            sha256: false,
+            holes: Vec::new(),
+        }
+    }
+
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_cipher(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    pub fn with_sha256(mut self, sha256: bool) -> Self {
+        self.sha256 = sha256;
+        self
+    }
+
+    pub fn with_holes(mut self, holes: Vec<Hole>) -> Self {
+        self.holes = holes;
+        self
+    }
+
+    /// Returns the number of bytes of the input to upload.
+    pub fn stored_size(&self) -> u64 {
+        sparse::stored_size(&self.holes, self.input_size)
+    }
+
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let buf = fs::read(path).await?;
+        let checkpoint: Self = serde_json::from_slice(&buf)?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(Error::Checkpoint(format!(
+                "unsupported version {}",
+                checkpoint.version
+            )));
+        }
+        Ok(checkpoint)
+    }
+
+    /// Writes the checkpoint to a temporary file and renames it into place
+    /// so an interruption never leaves a truncated checkpoint behind.
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let buf = serde_json::to_vec_pretty(self)?;
+        // the checkpoint contains the encryption key, so it is never
+        // readable by anyone but its owner, even before it is complete
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&tmp).await?;
+        file.write_all(&buf).await?;
+        file.flush().await?;
+        drop(file);
+        // a temporary file left by an earlier run keeps the mode it was made with
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600)).await?;
+        }
+        fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.input_size
+    }
+}
@@ -0,0 +1,220 @@
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+use crate::error::{Error, Result};
+use crate::manifest::Manifest;
+
+fn new_state() -> blake2b_simd::State {
+    blake2b_simd::Params::new().hash_length(32).to_state()
+}
+
+fn finish(state: &blake2b_simd::State) -> [u8; 32] {
+    let mut checksum = [0u8; 32];
+    checksum.copy_from_slice(state.finalize().as_bytes());
+    checksum
+}
+
+/// Computes the BLAKE2b-256 checksum of a file.
+pub async fn checksum_file(path: impl AsRef<Path>) -> Result<[u8; 32]> {
+    let mut file = File::open(path).await?;
+    let mut state = new_state();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        state.update(&buf[..n]);
+    }
+    Ok(finish(&state))
+}
+
+/// Computes the SHA-256 of a file.
+pub async fn sha256_file(path: impl AsRef<Path>) -> Result<[u8; 32]> {
+    let mut file = File::open(path).await?;
+    let mut state = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        state.update(&buf[..n]);
+    }
+    Ok(state.finalize().into())
+}
+
+/// Checks a downloaded file against the size and checksums its manifest
+/// recorded at upload.
+pub async fn verify_file(path: impl AsRef<Path>, manifest: &Manifest) -> Result<()> {
+    let path = path.as_ref();
+    let size = tokio::fs::metadata(path).await?.len();
+    if size != manifest.size {
+        return Err(Error::Integrity(format!(
+            "{} is {size} bytes but the manifest recorded {}",
+            path.display(),
+            manifest.size
+        )));
+    }
+    if checksum_file(path).await? != manifest.checksum {
+        return Err(mismatch(path, "BLAKE2b"));
+    }
+    if manifest.sha256.is_some() && manifest.sha256 != Some(sha256_file(path).await?) {
+        return Err(mismatch(path, "SHA-256"));
+    }
+    Ok(())
+}
+
+/// Checks the size and checksum of everything written through `w` against
+/// a manifest.
+pub fn verify_written<W>(w: &ChecksumWriter<W>, manifest: &Manifest) -> Result<()> {
+    if w.written() != manifest.size || w.checksum() != manifest.checksum {
+        return Err(Error::Integrity(
+            "downloaded data does not match the manifest's checksum".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn mismatch(path: &Path, algorithm: &str) -> Error {
+    Error::Integrity(format!(
+        "{} does not match the {algorithm} checksum in its manifest",
+        path.display()
+    ))
+}
+
+/// Computes the BLAKE2b-256 checksum of a buffer.
+pub fn checksum_bytes(data: &[u8]) -> [u8; 32] {
+    let mut state = new_state();
+    state.update(data);
+    finish(&state)
+}
+
+/// Wraps a writer, hashing everything written through it.
+pub struct ChecksumWriter<W> {
+    inner: W,
+    state: blake2b_simd::State,
+    written: u64,
+}
+
+impl<W> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            state: new_state(),
+            written: 0,
+        }
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn checksum(&self) -> [u8; 32] {
+        finish(&self.state)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ChecksumWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = std::task::ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.state.update(&buf[..n]);
+        this.written += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+struct Hashes {
+    blake2b: blake2b_simd::State,
+    sha256: Option<Sha256>,
+    read: u64,
+}
+
+/// The running checksum of a `ChecksumReader`, readable after the reader
+/// has been handed off.
+#[derive(Clone)]
+pub struct ChecksumHandle(Arc<Mutex<Hashes>>);
+
+impl ChecksumHandle {
+    pub fn read(&self) -> u64 {
+        self.0.lock().unwrap().read
+    }
+
+    pub fn checksum(&self) -> [u8; 32] {
+        finish(&self.0.lock().unwrap().blake2b)
+    }
+
+    /// The SHA-256 of what was read, if the reader was asked to compute it.
+    pub fn sha256(&self) -> Option<[u8; 32]> {
+        let hashes = self.0.lock().unwrap();
+        hashes.sha256.clone().map(|state| state.finalize().into())
+    }
+}
+
+/// Wraps a reader, hashing everything read through it, with SHA-256 as
+/// well if `sha256` is set.
+pub struct ChecksumReader<R> {
+    inner: R,
+    state: ChecksumHandle,
+}
+
+impl<R> ChecksumReader<R> {
+    pub fn new(inner: R, sha256: bool) -> (Self, ChecksumHandle) {
+        let state = ChecksumHandle(Arc::new(Mutex::new(Hashes {
+            blake2b: new_state(),
+            sha256: sha256.then(Sha256::new),
+            read: 0,
+        })));
+        (
+            Self {
+                inner,
+                state: state.clone(),
+            },
+            state,
+        )
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChecksumReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        std::task::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = &buf.filled()[before..];
+        let mut hashes = this.state.0.lock().unwrap();
+        hashes.blake2b.update(read);
+        if let Some(sha256) = &mut hashes.sha256 {
+            sha256.update(read);
+        }
+        hashes.read += read.len() as u64;
+        Poll::Ready(Ok(()))
+    }
+}
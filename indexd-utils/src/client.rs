@@ -0,0 +1,803 @@
+use std::future::Future;
+use std::io::{self, Cursor};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use bytes::Bytes;
+use indexd::{SDK, Slab};
+use log::{debug, info, warn};
+use sia::signing::PrivateKey;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::Instant;
+
+use crate::approvals::{self, Approvals};
+use crate::backend::Backend;
+use crate::catalog;
+use crate::chaos::{Chaos, ChaosBackend};
+use crate::config::Settings;
+use crate::download;
+use crate::error::{Error, Result};
+use crate::hosts::HostPolicy;
+use crate::journal::{self, Journal};
+use crate::metrics::METRICS;
+use crate::middleware::{Direction, Middleware, Outcome, Transfer};
+use crate::progress::{Event, Progress};
+use crate::retry::{ErrorClass, Paused, RetryPolicy};
+use crate::scheduler::Lane;
+use crate::telemetry::HOSTS;
+use crate::throttle::{RateLimiter, ThrottledReader, ThrottledWriter};
+
+/// How long an operation may go without making progress before it is
+/// abandoned. `None` waits forever.
+///
+/// The SDK transfers a slab's shards together, so the transfer timeouts
+/// bound how long a slab may go without any of its bytes moving, which
+/// is as long as its slowest shard takes. A timed out transfer fails with
+/// [`Error::Timeout`], which the retry policy retries, letting the SDK pick
+/// hosts for the shards again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub upload: Option<Duration>,
+    pub download: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Some(Duration::from_secs(30)),
+            upload: Some(Duration::from_secs(120)),
+            download: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// How `connect` waits for the app to be approved.
+///
+/// The SDK checks for approval itself while it waits, so `interval` only
+/// sets how often the wait is reported, as [`Event::AwaitingApproval`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Approval {
+    pub interval: Duration,
+    /// How long to wait before failing with [`Error::ApprovalTimedOut`],
+    /// `None` to wait forever.
+    pub deadline: Option<Duration>,
+}
+
+impl Default for Approval {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            deadline: None,
+        }
+    }
+}
+
+/// A connected SDK handle along with the policies applied to every
+/// transfer made through it.
+///
+/// Clones share the connection, so a transfer can be moved onto a task of
+/// its own.
+#[derive(Clone)]
+pub struct Client {
+    sdk: Arc<dyn Backend>,
+    upload_limit: Option<Arc<RateLimiter>>,
+    download_limit: Option<Arc<RateLimiter>>,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    hosts: Option<HostPolicy>,
+    /// How many times a buffer is uploaded before its slabs landing on
+    /// ruled out hosts fails it, 0 to keep misplaced slabs.
+    placement_attempts: u32,
+    /// How long a slab may take before a second request races it.
+    hedge_after: Option<Duration>,
+    /// Where pauses for the app's rate limit are reported.
+    progress: Progress,
+    middleware: Vec<Arc<dyn Middleware>>,
+    /// Where transfers wait their turn when sharing a limit with others.
+    lane: Option<Lane>,
+}
+
+impl Client {
+    pub fn new(sdk: indexd::SDK<indexd::Connected>) -> Self {
+        Self::from_backend(sdk)
+    }
+
+    /// Returns a client that transfers through `backend` rather than the
+    /// SDK, with the same defaults as [`Client::new`].
+    pub fn from_backend(backend: impl Backend + 'static) -> Self {
+        Self {
+            sdk: Arc::new(backend),
+            upload_limit: None,
+            download_limit: None,
+            retry: RetryPolicy::none(),
+            timeouts: Timeouts::default(),
+            hosts: None,
+            placement_attempts: 0,
+            hedge_after: None,
+            progress: Progress::default(),
+            middleware: Vec::new(),
+            lane: None,
+        }
+    }
+
+    /// Hedges downloads: a slab that hasn't arrived after `hedge_after` is
+    /// requested a second time and whichever copy arrives first is used.
+    /// Each request picks the hosts for the slab's shards anew, so a slab
+    /// held up by a slow host can be served by a faster set, at the cost of
+    /// fetching some slabs twice.
+    ///
+    /// Hedged downloads fetch one slab at a time into memory, so they are
+    /// only worth it where slow hosts dominate.
+    pub fn with_hedging(mut self, hedge_after: Option<Duration>) -> Self {
+        self.hedge_after = hedge_after;
+        self
+    }
+
+    /// Injects faults into a fraction of the transfers, to see that the
+    /// rest of the client copes with them.
+    pub fn with_chaos(mut self, chaos: Option<Chaos>) -> Self {
+        if let Some(chaos) = chaos {
+            warn!("injecting faults into transfers: {chaos}");
+            self.sdk = Arc::new(ChaosBackend::new(self.sdk, chaos));
+        }
+        self
+    }
+
+    pub fn with_host_policy(mut self, hosts: Option<HostPolicy>) -> Self {
+        self.hosts = hosts;
+        self
+    }
+
+    /// Rejects uploads that land on hosts the host policy rules out: a
+    /// buffer is uploaded up to `attempts` times before failing with
+    /// [`Error::Placement`], and a stream, which can't be read twice, fails
+    /// at once. With 0, the default, misplaced slabs are kept and logged for
+    /// `repair` to move.
+    ///
+    /// The SDK can't be told which hosts to use, so every rejected attempt
+    /// leaves its slabs stored. They are logged, and listed in the error.
+    pub fn with_placement_attempts(mut self, attempts: u32) -> Self {
+        self.placement_attempts = attempts;
+        self
+    }
+
+    /// The hosts uploads are restricted to, recorded in the manifests of
+    /// the objects uploaded through this client.
+    pub fn host_policy(&self) -> Option<&HostPolicy> {
+        self.hosts.as_ref()
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Reports the pauses transfers make for the app's rate limit to
+    /// `progress`, as [`Event::RateLimited`].
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// Runs `middleware`'s hooks around every transfer, after those of any
+    /// middleware added before it.
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Waits for a turn in `lane` before every transfer, and has downloads
+    /// fetch one slab at a time so long files take turns with the others.
+    pub fn with_lane(mut self, lane: Lane) -> Self {
+        self.lane = Some(lane);
+        self
+    }
+
+    /// Limits uploads to `bytes_per_sec`, counting the bytes of every
+    /// shard sent to hosts, parity included.
+    pub fn with_upload_limit(mut self, bytes_per_sec: Option<u64>) -> Self {
+        self.upload_limit = bytes_per_sec.map(|rate| Arc::new(RateLimiter::new(rate)));
+        self
+    }
+
+    /// Limits downloads to `bytes_per_sec`.
+    pub fn with_download_limit(mut self, bytes_per_sec: Option<u64>) -> Self {
+        self.download_limit = bytes_per_sec.map(|rate| Arc::new(RateLimiter::new(rate)));
+        self
+    }
+
+    /// Encrypts, erasure codes and uploads everything read from `reader`,
+    /// failing if the slabs landed on hosts the host policy rules out and
+    /// placement is enforced.
+    ///
+    /// A stream can't be read twice, so this is never retried; see
+    /// [`Client::upload_bytes`].
+    pub async fn upload<R>(
+        &self,
+        reader: R,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> Result<Vec<Slab>>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let slabs = self
+            .upload_anywhere(reader, encryption_key, data_shards, parity_shards)
+            .await?;
+        if self.rejects(self.hosts.as_ref(), &slabs) {
+            return Err(Error::Placement(format!(
+                "the upload landed on hosts the host policy rules out, {}",
+                left_stored(&slabs)
+            )));
+        }
+        Ok(slabs)
+    }
+
+    /// Returns whether `hosts` rules out where `slabs` landed and placement
+    /// is enforced. Misplaced slabs that are kept are logged instead.
+    fn rejects(&self, hosts: Option<&HostPolicy>, slabs: &[Slab]) -> bool {
+        let misplaced = hosts.map_or(0, |hosts| hosts.misplaced(slabs));
+        if misplaced > 0 && self.placement_attempts == 0 {
+            warn!(
+                "{misplaced} shards were stored on hosts the host policy rules out; repair moves them"
+            );
+        }
+        misplaced > 0 && self.placement_attempts > 0
+    }
+
+    async fn upload_anywhere<R>(
+        &self,
+        reader: R,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> Result<Vec<Slab>>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        // every byte read becomes this many bytes of shards
+        let transfer = Transfer::new(Direction::Upload);
+        for middleware in &self.middleware {
+            middleware.before_upload(transfer)?;
+        }
+        let shards = data_shards as usize + parity_shards as usize;
+        let _permit = match &self.lane {
+            Some(lane) => Some(lane.acquire(shards).await),
+            None => None,
+        };
+        let weight = shards as f64 / data_shards.max(1) as f64;
+        let reader = ThrottledReader::new(reader, self.upload_limit.clone(), weight);
+        let moved = Arc::new(AtomicU64::new(0));
+        let reader = Watched {
+            inner: reader,
+            moved: moved.clone(),
+        };
+        let upload = async {
+            Ok(self
+                .sdk
+                .upload(Box::new(reader), encryption_key, data_shards, parity_shards)
+                .await?)
+        };
+        let start = Instant::now();
+        let result = stall_timeout(upload, &moved, self.timeouts.upload, "upload").await;
+        match &result {
+            Ok(slabs) => {
+                METRICS.uploaded(moved.load(Ordering::Relaxed));
+                HOSTS.record(slabs, start.elapsed(), true);
+            }
+            Err(_) => METRICS.host_error(),
+        }
+        if let (Some(lane), Ok(_)) = (&self.lane, &result) {
+            lane.record(moved.load(Ordering::Relaxed));
+        }
+        let outcome = Outcome {
+            bytes: moved.load(Ordering::Relaxed),
+            elapsed: start.elapsed(),
+            error: result.as_ref().err(),
+        };
+        let slabs = result.as_deref().unwrap_or_default();
+        for middleware in &self.middleware {
+            middleware.after_upload(transfer, slabs, outcome);
+        }
+        result
+    }
+
+    /// Uploads a buffer, retrying failures with the retry policy.
+    pub async fn upload_bytes(
+        &self,
+        data: Bytes,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> Result<Vec<Slab>> {
+        let hosts = self.hosts.as_ref();
+        self.upload_bytes_within(data, encryption_key, data_shards, parity_shards, hosts)
+            .await
+    }
+
+    /// Like [`Client::upload_bytes`], but places the slabs according to
+    /// `hosts` instead of the client's host policy.
+    ///
+    /// Uploads that land on ruled out hosts are attempted again up to the
+    /// client's placement attempts, apart from the retry policy.
+    pub async fn upload_bytes_within(
+        &self,
+        data: Bytes,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+        hosts: Option<&HostPolicy>,
+    ) -> Result<Vec<Slab>> {
+        let mut rejected = Vec::new();
+        let mut attempt = 1;
+        loop {
+            let slabs = self
+                .retry
+                .run(ErrorClass::Host, "upload", &self.progress, |_| {
+                    self.upload_anywhere(
+                        Cursor::new(data.clone()),
+                        encryption_key,
+                        data_shards,
+                        parity_shards,
+                    )
+                })
+                .await?;
+            if !self.rejects(hosts, &slabs) {
+                return Ok(slabs);
+            }
+            warn!(
+                "upload attempt {attempt} landed on hosts the host policy rules out, {}",
+                left_stored(&slabs)
+            );
+            rejected.extend(slabs);
+            if attempt >= self.placement_attempts {
+                return Err(Error::Placement(format!(
+                    "{attempt} uploads landed on hosts the host policy rules out, {}",
+                    left_stored(&rejected)
+                )));
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Downloads and decrypts the slabs into `w`, in order.
+    ///
+    /// Failures are retried with the retry policy, picking up after the
+    /// last byte written so nothing is written twice.
+    pub async fn download<W>(&self, w: &mut W, slabs: &[Slab]) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        if self.lane.is_some() && self.hedge_after.is_none() {
+            for slab in slabs {
+                self.download_run(w, std::slice::from_ref(slab)).await?;
+            }
+            return Ok(());
+        }
+        self.download_run(w, slabs).await
+    }
+
+    async fn download_run<W>(&self, w: &mut W, slabs: &[Slab]) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        if let Some(hedge_after) = self.hedge_after {
+            for slab in slabs {
+                let data = self
+                    .retry
+                    .run(ErrorClass::Host, "download", &self.progress, |_| {
+                        self.fetch_hedged(slab, hedge_after)
+                    })
+                    .await?;
+                w.write_all(&data).await?;
+            }
+            return Ok(());
+        }
+        let total: u64 = slabs.iter().map(|slab| slab.length as u64).sum();
+        let mut w = Counter {
+            inner: w,
+            written: 0,
+        };
+        let mut attempt = 1;
+        let mut paused = Paused::default();
+        loop {
+            let remaining = download::slice_slabs(slabs, w.written, total - w.written);
+            let result = self.download_once(&mut w, &remaining).await;
+            if self
+                .retry
+                .pause_for_rate_limit("download", &result, &mut paused, &self.progress)
+                .await
+            {
+                continue;
+            }
+            match result {
+                Err(e) if self.retry.should_retry(ErrorClass::Host, attempt, &e) => {
+                    warn!(
+                        "download failed on attempt {attempt} after {} bytes: {e}",
+                        w.written
+                    );
+                    self.retry.backoff(ErrorClass::Host, attempt).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Downloads the slabs into `w` in a single attempt.
+    pub async fn download_once<W>(&self, w: &mut W, slabs: &[Slab]) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        if slabs.is_empty() {
+            return Ok(());
+        }
+        let transfer = Transfer::new(Direction::Download);
+        for middleware in &self.middleware {
+            middleware.before_download(transfer, slabs)?;
+        }
+        let _permit = match &self.lane {
+            Some(lane) => {
+                let shards = slabs.iter().map(|slab| slab.sectors.len()).sum();
+                Some(lane.acquire(shards).await)
+            }
+            None => None,
+        };
+        let moved = Arc::new(AtomicU64::new(0));
+        let mut w = Watched {
+            inner: ThrottledWriter::new(w, self.download_limit.clone()),
+            moved: moved.clone(),
+        };
+        let download = async {
+            self.sdk.download(&mut w, slabs).await?;
+            Ok(())
+        };
+        let start = Instant::now();
+        let result = stall_timeout(download, &moved, self.timeouts.download, "download").await;
+        METRICS.downloaded(moved.load(Ordering::Relaxed));
+        if result.is_err() {
+            METRICS.host_error();
+        }
+        HOSTS.record(slabs, start.elapsed(), result.is_ok());
+        if let Some(lane) = &self.lane {
+            lane.record(moved.load(Ordering::Relaxed));
+        }
+        let outcome = Outcome {
+            bytes: moved.load(Ordering::Relaxed),
+            elapsed: start.elapsed(),
+            error: result.as_ref().err(),
+        };
+        for middleware in &self.middleware {
+            middleware.after_download(transfer, slabs, outcome);
+        }
+        result
+    }
+
+    /// Fetches a slab into memory, racing a second request against the
+    /// first once `hedge_after` has passed.
+    async fn fetch_hedged(&self, slab: &Slab, hedge_after: Duration) -> Result<Vec<u8>> {
+        let first = self.fetch_once(slab);
+        tokio::pin!(first);
+        tokio::select! {
+            result = &mut first => return result,
+            _ = tokio::time::sleep(hedge_after) => {}
+        }
+        debug!("slab is slow after {}ms, hedging", hedge_after.as_millis());
+        let second = self.fetch_once(slab);
+        tokio::pin!(second);
+        // a failed request leaves the other to finish
+        tokio::select! {
+            result = &mut first => match result {
+                Ok(data) => Ok(data),
+                Err(_) => second.await,
+            },
+            result = &mut second => match result {
+                Ok(data) => Ok(data),
+                Err(_) => first.await,
+            },
+        }
+    }
+
+    async fn fetch_once(&self, slab: &Slab) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(slab.length as usize);
+        self.download_once(&mut buf, std::slice::from_ref(slab))
+            .await?;
+        Ok(buf)
+    }
+}
+
+/// Connects to the app, waiting for approval if necessary.
+pub async fn connect(settings: &Settings) -> Result<Client> {
+    connect_with_progress(settings, &Progress::default()).await
+}
+
+/// Like [`connect`], reporting the approval URL and the wait for approval
+/// to `progress`.
+pub async fn connect_with_progress(settings: &Settings, progress: &Progress) -> Result<Client> {
+    let seed = settings.key_source()?.seed().await?;
+    if let Some(proxy) = &settings.network.proxy {
+        warn!(
+            "the proxy {proxy} only carries webhooks, URL sources and doctor's checks; \
+             the indexd SDK connects to the app and hosts directly"
+        );
+    }
+    if !settings.network.tls.is_default() {
+        warn!(
+            "the TLS settings only apply to webhooks, URL sources and doctor's checks; \
+             the indexd SDK verifies the app against the system's roots"
+        );
+    }
+
+    let sdk = settings
+        .retry
+        .run(
+            ErrorClass::App,
+            "connecting",
+            progress,
+            move |_| async move {
+                let app_key = PrivateKey::from_seed(&seed);
+                let connect = SDK::connect(
+                    &settings.app_url,
+                    app_key,
+                    "upload-rs".into(),
+                    "A simple upload tool ".into(),
+                    "https://foo.bar".parse().unwrap(),
+                );
+                match settings.timeouts.connect {
+                    Some(timeout) => tokio::time::timeout(timeout, connect)
+                        .await
+                        .map_err(|_| Error::Timeout(format!("connecting to {}", settings.app_url)))?
+                        .map_err(Error::from),
+                    None => Ok(connect.await?),
+                }
+            },
+        )
+        .await?;
+
+    // the cache only explains approval prompts, so it never fails a connect
+    let identity = settings.identity();
+    let cache_path = approvals::default_path();
+    let mut cache = match &cache_path {
+        Some(path) => Approvals::load(path).await.unwrap_or_else(|e| {
+            warn!("ignoring the approval cache: {e}");
+            Approvals::default()
+        }),
+        None => Approvals::default(),
+    };
+    let approved = cache.is_approved(identity, &settings.app_url, &seed);
+
+    let sdk = if sdk.needs_approval() {
+        if approved {
+            warn!(
+                "{identity} was approved before but needs approval again; it may have been revoked"
+            );
+        }
+        let url = sdk.approval_url().unwrap().to_string();
+        // printed outside the logs so a headless run always shows it
+        eprintln!("approve the app at: {url}");
+        progress.emit(Event::ApprovalRequired { url: url.clone() });
+        await_approval(sdk.connected(None), settings.approval, &url, progress).await?
+    } else {
+        sdk.connected(None).await?
+    };
+    info!("app connected as {identity}");
+    if let Some(path) = cache_path.filter(|_| !approved) {
+        cache.record(identity, &settings.app_url, &seed);
+        if let Err(e) = cache.save(&path).await {
+            warn!("failed to save the approval cache: {e}");
+        }
+    }
+    let mut client = Client::new(sdk)
+        .with_upload_limit(settings.upload_limit)
+        .with_download_limit(settings.download_limit)
+        .with_retry_policy(settings.retry.clone())
+        .with_timeouts(settings.timeouts)
+        .with_host_policy(settings.hosts.clone())
+        .with_placement_attempts(settings.placement_attempts)
+        .with_hedging(settings.hedge_after)
+        .with_chaos(settings.chaos)
+        .with_progress(progress.clone());
+    if let Some(journal) = journal(settings) {
+        client = client.with_middleware(journal);
+    }
+    Ok(client)
+}
+
+/// Names the slabs a rejected upload left stored, which nothing references
+/// and the SDK offers no way to release.
+fn left_stored(slabs: &[Slab]) -> String {
+    let keys: Vec<String> = slabs
+        .iter()
+        .filter_map(|slab| catalog::slab_key(slab).ok())
+        .collect();
+    format!("leaving {} slabs stored: {}", slabs.len(), keys.join(", "))
+}
+
+/// Starts the journal of this session, making room for it among the
+/// kept ones. Journaling never fails a connect.
+fn journal(settings: &Settings) -> Option<Journal> {
+    if settings.journal_sessions == 0 {
+        return None;
+    }
+    let dir = journal::default_dir()?;
+    if let Err(e) = journal::prune(&dir, settings.journal_sessions - 1) {
+        warn!("failed to prune the transfer journals: {e}");
+    }
+    Some(Journal::new(&dir, std::env::args().collect()))
+}
+
+/// Waits for `connected` to finish, reporting the wait every interval and
+/// giving up at the deadline.
+async fn await_approval<T, E>(
+    connected: impl Future<Output = std::result::Result<T, E>>,
+    approval: Approval,
+    url: &str,
+    progress: &Progress,
+) -> Result<T>
+where
+    Error: From<E>,
+{
+    let start = Instant::now();
+    let deadline = async {
+        match approval.deadline {
+            Some(deadline) => tokio::time::sleep(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(connected, deadline);
+    let mut ticks = tokio::time::interval_at(start + approval.interval, approval.interval);
+    loop {
+        tokio::select! {
+            result = &mut connected => return Ok(result?),
+            _ = &mut deadline => return Err(Error::ApprovalTimedOut(url.to_string())),
+            _ = ticks.tick() => {
+                let waited = start.elapsed().as_secs();
+                info!("still waiting for approval after {waited}s at: {url}");
+                progress.emit(Event::AwaitingApproval { waited_secs: waited });
+            }
+        }
+    }
+}
+
+/// Runs `op`, failing it once `moved` hasn't changed for `timeout`.
+async fn stall_timeout<T>(
+    op: impl Future<Output = Result<T>>,
+    moved: &AtomicU64,
+    timeout: Option<Duration>,
+    what: &str,
+) -> Result<T> {
+    let Some(timeout) = timeout else {
+        return op.await;
+    };
+    tokio::pin!(op);
+    let mut last = moved.load(Ordering::Relaxed);
+    loop {
+        tokio::select! {
+            result = &mut op => return result,
+            _ = tokio::time::sleep(timeout) => {
+                let now = moved.load(Ordering::Relaxed);
+                if now == last {
+                    return Err(Error::Timeout(format!(
+                        "{what} made no progress for {}s",
+                        timeout.as_secs()
+                    )));
+                }
+                last = now;
+            }
+        }
+    }
+}
+
+/// Counts the bytes moved through it into a shared counter, so a stalled
+/// transfer can be spotted from outside.
+struct Watched<T> {
+    inner: T,
+    moved: Arc<AtomicU64>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Watched<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let n = buf.filled().len() - before;
+        this.moved.fetch_add(n as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Watched<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.moved.fetch_add(n as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Counts the bytes written through it.
+struct Counter<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Counter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.written += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mock::{self, MockBackend};
+
+    #[tokio::test]
+    async fn rejected_placements_are_bounded() {
+        let mock = Arc::new(MockBackend::new(4));
+        let hosts = HostPolicy::new(Vec::new(), mock.host_keys(), None).unwrap();
+        let sdk = Client::from_backend(mock.clone()).with_host_policy(hosts);
+        let data = Bytes::from(mock::pattern(1024));
+
+        // kept by default, for repair to move
+        sdk.upload_bytes(data.clone(), [1; 32], 1, 1).await.unwrap();
+        assert_eq!(mock.slabs(), 1);
+
+        let sdk = sdk.with_placement_attempts(3);
+        let err = sdk.upload_bytes(data, [1; 32], 1, 1).await.unwrap_err();
+        assert!(matches!(err, Error::Placement(_)), "{err}");
+        assert!(err.to_string().contains("leaving 3 slabs stored"), "{err}");
+        assert_eq!(mock.slabs(), 4);
+    }
+}
@@ -0,0 +1,324 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf, Sink};
+use zstd::stream::raw::{Decoder, Encoder, InBuffer, Operation, OutBuffer};
+
+use crate::aead::OpenWriter;
+use crate::checksum::ChecksumWriter;
+use crate::error::{Error, Result};
+use crate::manifest::Manifest;
+
+/// The size of the buffers between the codec and the stream it wraps.
+const BUFFER_SIZE: usize = 1 << 17;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    Zstd,
+}
+
+/// How an object's plaintext was compressed before it was encrypted.
+///
+/// A compressed object is a sequence of zstd frames, one per uploaded
+/// segment, so a resumed upload can start a new frame without the state of
+/// the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compression {
+    pub algorithm: Algorithm,
+    pub level: i32,
+}
+
+impl Compression {
+    pub fn zstd(level: i32) -> Self {
+        Self {
+            algorithm: Algorithm::Zstd,
+            level,
+        }
+    }
+
+    /// Wraps `reader` so that it yields the compressed stream.
+    pub fn compress<R: AsyncRead + Unpin>(&self, reader: R) -> Result<ZstdReader<R>> {
+        ZstdReader::new(reader, self.level)
+    }
+}
+
+/// Compresses everything read through it into a single zstd frame.
+pub struct ZstdReader<R> {
+    inner: R,
+    encoder: Encoder<'static>,
+    buf: Box<[u8]>,
+    pos: usize,
+    filled: usize,
+    eof: bool,
+    done: bool,
+}
+
+impl<R> ZstdReader<R> {
+    pub fn new(inner: R, level: i32) -> Result<Self> {
+        Ok(Self {
+            inner,
+            encoder: Encoder::new(level)?,
+            buf: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            pos: 0,
+            filled: 0,
+            eof: false,
+            done: false,
+        })
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ZstdReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while !this.done && buf.remaining() > 0 {
+            if this.pos == this.filled && !this.eof {
+                let mut input = ReadBuf::new(&mut this.buf);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut input))?;
+                this.pos = 0;
+                this.filled = input.filled().len();
+                this.eof = this.filled == 0;
+            }
+
+            let mut output = OutBuffer::around(buf.initialize_unfilled());
+            if this.pos < this.filled {
+                let mut input = InBuffer::around(&this.buf[this.pos..this.filled]);
+                this.encoder.run(&mut input, &mut output)?;
+                this.pos += input.pos();
+            } else {
+                this.done = this.encoder.finish(&mut output, true)? == 0;
+            }
+            let n = output.pos();
+            buf.advance(n);
+            if n > 0 {
+                break;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Decompresses a sequence of zstd frames written through it.
+pub struct ZstdWriter<W> {
+    inner: W,
+    decoder: Decoder<'static>,
+    buf: Box<[u8]>,
+    pos: usize,
+    filled: usize,
+}
+
+impl<W> ZstdWriter<W> {
+    pub fn new(inner: W) -> Result<Self> {
+        Ok(Self {
+            inner,
+            decoder: Decoder::new()?,
+            buf: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            pos: 0,
+            filled: 0,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> ZstdWriter<W> {
+    /// Writes the decompressed bytes waiting in the buffer to `inner`.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.pos < self.filled {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.buf[self.pos..self.filled]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pos += n;
+        }
+        self.pos = 0;
+        self.filled = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Runs the decoder over `input` into the empty buffer and returns how
+    /// much of it was consumed.
+    fn decode(&mut self, input: &[u8]) -> io::Result<usize> {
+        let mut input = InBuffer::around(input);
+        let mut output = OutBuffer::around(&mut self.buf[..]);
+        self.decoder.run(&mut input, &mut output)?;
+        self.filled = output.pos();
+        Ok(input.pos())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ZstdWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_drain(cx))?;
+            let n = this.decode(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Poll::Ready(Ok(n));
+            } else if this.filled == 0 {
+                return Poll::Ready(Err(io::Error::other("zstd decoder made no progress")));
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_drain(cx))?;
+            // the decoder may hold output it had no room for
+            this.decode(&[])?;
+            if this.filled == 0 {
+                break;
+            }
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Hashes an object's plaintext from its stored bytes, decompressing or
+/// opening them first if needed.
+pub struct PlaintextChecksum {
+    hasher: Hasher,
+    /// Cleared once the stored bytes fail to decompress or open.
+    valid: bool,
+}
+
+enum Hasher {
+    Plain(ChecksumWriter<Sink>),
+    Compressed(ZstdWriter<ChecksumWriter<Sink>>),
+    Sealed(OpenWriter<ChecksumWriter<Sink>>),
+}
+
+impl PlaintextChecksum {
+    pub fn new(manifest: &Manifest) -> Result<Self> {
+        let hasher = ChecksumWriter::new(tokio::io::sink());
+        let hasher = match (manifest.compression, manifest.cipher) {
+            (Some(_), Some(_)) => {
+                return Err(Error::Manifest(
+                    "compressed and sealed objects are not supported".into(),
+                ));
+            }
+            (Some(_), None) => Hasher::Compressed(ZstdWriter::new(hasher)?),
+            (None, Some(cipher)) => Hasher::Sealed(cipher.open(hasher, 0)),
+            (None, None) => Hasher::Plain(hasher),
+        };
+        Ok(Self {
+            hasher,
+            valid: true,
+        })
+    }
+
+    /// Hashes the next stored bytes of the object.
+    pub async fn write(&mut self, data: &[u8]) {
+        let result = match &mut self.hasher {
+            Hasher::Plain(hasher) => hasher.write_all(data).await,
+            Hasher::Compressed(decoder) => decoder.write_all(data).await,
+            Hasher::Sealed(opener) => opener.write_all(data).await,
+        };
+        if result.is_err() {
+            self.valid = false;
+        }
+    }
+
+    /// Returns the number of plaintext bytes and their checksum, or `None`
+    /// if the stored bytes could not be decompressed.
+    pub async fn finish(mut self) -> Option<(u64, [u8; 32])> {
+        if !self.valid {
+            return None;
+        }
+        let hasher = match self.hasher {
+            Hasher::Plain(hasher) => hasher,
+            Hasher::Compressed(mut decoder) => {
+                decoder.flush().await.ok()?;
+                decoder.into_inner()
+            }
+            Hasher::Sealed(mut opener) => {
+                opener.finish().await.ok()?;
+                opener.into_inner()
+            }
+        };
+        Some((hasher.written(), hasher.checksum()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::mock;
+
+    async fn compress(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        Compression::zstd(3)
+            .compress(data)
+            .unwrap()
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+        compressed
+    }
+
+    async fn decompress(compressed: &[u8], write_size: usize) -> io::Result<Vec<u8>> {
+        let mut writer = ZstdWriter::new(Vec::new()).unwrap();
+        for chunk in compressed.chunks(write_size) {
+            writer.write_all(chunk).await?;
+        }
+        writer.flush().await?;
+        Ok(writer.into_inner())
+    }
+
+    #[tokio::test]
+    async fn round_trips() {
+        // larger than the buffers, and compressible
+        let mut data = mock::pattern(3 * BUFFER_SIZE);
+        data.extend(vec![0; 2 * BUFFER_SIZE]);
+        let compressed = compress(&data).await;
+        assert!(compressed.len() < data.len());
+        assert_eq!(
+            decompress(&compressed, compressed.len()).await.unwrap(),
+            data
+        );
+        assert_eq!(decompress(&compressed, 7).await.unwrap(), data);
+
+        let empty = compress(&[]).await;
+        assert!(!empty.is_empty());
+        assert!(decompress(&empty, 1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn segments_are_separate_frames() {
+        let (first, second) = (mock::pattern(5000), vec![1; 5000]);
+        let mut compressed = compress(&first).await;
+        compressed.extend(compress(&second).await);
+        let data = decompress(&compressed, 1000).await.unwrap();
+        assert_eq!(data, [first, second].concat());
+    }
+
+    #[tokio::test]
+    async fn garbage_fails() {
+        assert!(decompress(&mock::pattern(1000), 100).await.is_err());
+    }
+}
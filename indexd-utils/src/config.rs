@@ -0,0 +1,576 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::budget::Budget;
+use crate::catalog::{self, Catalog, Retention};
+use crate::chaos::Chaos;
+use crate::client::{Approval, Timeouts};
+use crate::compression::Compression;
+use crate::error::{Error, Result};
+use crate::estimate::Pricing;
+use crate::health::{Threshold, Thresholds};
+use crate::hosts::{HostPolicy, HostStrategy, Latency, Spread};
+use crate::lifecycle::Rule;
+use crate::net::{Network, Tls};
+use crate::output::Output;
+use crate::redundancy::{Redundancy, SlabSizing};
+use crate::retry::{ErrorClass, RetryPolicy};
+use crate::throttle;
+use crate::upload::UploadOptions;
+use crate::webhook::Webhook;
+
+pub const DEFAULT_APP_URL: &str = "https://app.indexd.zeus.sia.dev";
+pub const DEFAULT_CONCURRENCY: usize = 1;
+const DEFAULT_TRASH_RETENTION_DAYS: u64 = 30;
+const DEFAULT_JOURNAL_SESSIONS: usize = 100;
+
+/// The contents of `config.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    /// App identities profiles and `--app` can select.
+    #[serde(default)]
+    pub apps: HashMap<String, App>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+/// An app identity: the indexd app and the key to connect to it with.
+/// Unset fields fall back to the profile's.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct App {
+    pub app_url: Option<String>,
+    /// The secret the app key is derived from.
+    pub app_secret: Option<String>,
+    /// A file containing the secret the app key is derived from.
+    pub app_secret_file: Option<PathBuf>,
+}
+
+/// A named set of defaults. Every field is optional and falls back to the
+/// built-in default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// The app identity to connect as, from `[apps]`.
+    pub app: Option<String>,
+    pub app_url: Option<String>,
+    /// The secret the app key is derived from.
+    pub app_secret: Option<String>,
+    /// A file containing the secret the app key is derived from.
+    pub app_secret_file: Option<PathBuf>,
+    /// A redundancy preset such as `standard`, `archival` or `1.5x`,
+    /// defaults to `standard` (10 data and 20 parity shards).
+    pub redundancy: Option<String>,
+    /// Overrides the preset's data shards.
+    pub data_shards: Option<u8>,
+    /// Overrides the preset's parity shards.
+    pub parity_shards: Option<u8>,
+    /// The maximum number of shards in flight during uploads.
+    pub jobs: Option<usize>,
+    /// The number of slabs read ahead of each one uploading.
+    pub read_ahead: Option<usize>,
+    /// The memory uploads may buffer, e.g. `2G`.
+    pub max_memory: Option<String>,
+    /// The slab sizes uploads of local files choose from, e.g. `4M-120M`,
+    /// instead of always using the data shards.
+    pub slab_size: Option<String>,
+    /// The number of files downloaded concurrently.
+    pub concurrency: Option<usize>,
+    /// Compress uploads with zstd at this level.
+    pub compression_level: Option<i32>,
+    /// Record the SHA-256 of uploads alongside their BLAKE2b checksum.
+    #[serde(default)]
+    pub sha256: bool,
+    /// Limit uploads to this rate, e.g. `5M` for 5 MiB/s.
+    pub bwlimit_up: Option<String>,
+    /// Limit downloads to this rate.
+    pub bwlimit_down: Option<String>,
+    /// The number of attempts before a failed request is given up on.
+    pub retry_attempts: Option<u32>,
+    /// The delay before the first retry, doubling with every attempt.
+    pub retry_delay_ms: Option<u64>,
+    /// What failed requests are retried: `app`, `host` or both.
+    pub retry_on: Option<Vec<ErrorClass>>,
+    /// How long a request may be paused in total while the app rate limits
+    /// it, before it fails.
+    pub rate_limit_wait_secs: Option<u64>,
+    /// How long connecting to the app may take, 0 to wait forever.
+    pub connect_timeout_secs: Option<u64>,
+    /// How long to wait for the app to be approved, forever by default.
+    pub approval_timeout_secs: Option<u64>,
+    /// How often to report that approval is still pending.
+    pub approval_interval_secs: Option<u64>,
+    /// How long an upload may go without progress before it is retried.
+    pub upload_timeout_secs: Option<u64>,
+    /// How long a download may go without progress before it is retried.
+    pub download_timeout_secs: Option<u64>,
+    /// Request a slab a second time if it hasn't arrived after this long.
+    pub hedge_after_ms: Option<u64>,
+    /// The public keys of the only hosts shards may be stored on.
+    #[serde(default)]
+    pub host_allow: Vec<String>,
+    /// The public keys of hosts shards may never be stored on.
+    #[serde(default)]
+    pub host_block: Vec<String>,
+    /// How widely each slab's shards have to be spread across hosts or
+    /// groups of them.
+    pub spread: Option<Spread>,
+    /// Upload a buffer up to this many times while its slabs land on hosts
+    /// the policy rules out, then fail. Unset keeps misplaced slabs for
+    /// `repair` to move.
+    pub placement_attempts: Option<u32>,
+    /// Host prices to estimate costs with.
+    pub pricing: Option<Pricing>,
+    /// The most a single upload may cost, which needs `pricing`.
+    pub budget_per_upload: Option<f64>,
+    /// The most uploads may cost over 30 days, which needs `pricing`.
+    pub budget_per_month: Option<f64>,
+    /// The fewest healthy shards every slab should keep, such as `data+3`,
+    /// below which verify fails and the daemon alerts.
+    pub min_healthy_shards: Option<Threshold>,
+    /// Thresholds for the objects whose names start with a prefix,
+    /// overriding `min_healthy_shards`; the longest prefix wins.
+    #[serde(default)]
+    pub min_healthy_shards_by_prefix: BTreeMap<String, Threshold>,
+    /// Endpoints the daemon notifies of finished transfers, data needing
+    /// repair and refused uploads.
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    /// The catalog database, defaults to
+    /// `~/.local/share/indexd-utils/catalog.db`.
+    pub catalog: Option<PathBuf>,
+    /// How many replaced versions of catalogued objects are kept.
+    #[serde(default)]
+    pub versions: Retention,
+    /// How long removed objects stay in the trash, 30 days by default.
+    pub trash_retention_days: Option<u64>,
+    /// What happens to catalogued objects as they age, applied by
+    /// `maintain`.
+    #[serde(default)]
+    pub lifecycle: Vec<Rule>,
+    /// A recipient key, to open the sealed manifests shared with it
+    /// without their passphrase.
+    pub recipient_key_file: Option<PathBuf>,
+    /// How many transfer journals to keep, 100 by default; 0 turns
+    /// journaling off.
+    pub journal_sessions: Option<usize>,
+    /// The proxy to connect through, such as `socks5://127.0.0.1:1080`.
+    pub proxy: Option<String>,
+    /// PEM certificates to trust in addition to the system's roots.
+    pub ca_bundle: Option<PathBuf>,
+    /// A PEM file holding a client certificate and its private key.
+    pub client_cert: Option<PathBuf>,
+    /// Accept any server certificate.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
+/// Where the app key comes from.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// A secret the key is derived from.
+    Secret(String),
+    /// A file containing a secret the key is derived from.
+    SecretFile(PathBuf),
+    /// The key itself, stored in the OS keychain under this account.
+    #[cfg(feature = "keyring")]
+    Keychain(String),
+}
+
+impl KeySource {
+    /// Returns the seed of the app's private key.
+    pub async fn seed(&self) -> Result<[u8; 32]> {
+        match self {
+            KeySource::Secret(secret) => Ok(seed_from_secret(secret)),
+            KeySource::SecretFile(path) => {
+                let secret = fs::read_to_string(path).await?;
+                Ok(seed_from_secret(secret.trim()))
+            }
+            #[cfg(feature = "keyring")]
+            KeySource::Keychain(account) => {
+                let account = account.clone();
+                tokio::task::spawn_blocking(move || {
+                    crate::keychain::load(&account)?.ok_or_else(|| {
+                        Error::Config(format!(
+                            "no app key in the keychain for {account}; \
+                             run `key generate` or `key import`"
+                        ))
+                    })
+                })
+                .await
+                .expect("keychain access panicked")
+            }
+        }
+    }
+}
+
+/// Derives the seed of an app key from a secret string.
+pub fn seed_from_secret(secret: &str) -> [u8; 32] {
+    blake2b_simd::Params::new()
+        .hash_length(32)
+        .to_state()
+        .update(secret.as_bytes())
+        .finalize()
+        .as_bytes()
+        .try_into()
+        .expect("32-byte hash")
+}
+
+/// The settings shared by all subcommands after applying the profile and
+/// environment overrides.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub profile: Option<String>,
+    /// The app identity selected, if any.
+    pub app: Option<String>,
+    pub app_url: String,
+    pub key_source: Option<KeySource>,
+    pub data_shards: u8,
+    pub parity_shards: u8,
+    pub jobs: Option<usize>,
+    pub read_ahead: Option<usize>,
+    pub max_memory: Option<u64>,
+    pub slab_sizing: Option<SlabSizing>,
+    pub concurrency: usize,
+    pub compression: Option<Compression>,
+    pub sha256: bool,
+    /// Upload and download limits in bytes per second.
+    pub upload_limit: Option<u64>,
+    pub download_limit: Option<u64>,
+    pub retry: RetryPolicy,
+    pub timeouts: Timeouts,
+    pub approval: Approval,
+    pub hosts: Option<HostPolicy>,
+    pub placement_attempts: u32,
+    /// Ranks hosts for `hosts stats`, by latency unless an application sets
+    /// its own.
+    pub host_strategy: Arc<dyn HostStrategy>,
+    pub hedge_after: Option<Duration>,
+    pub pricing: Option<Pricing>,
+    pub budget: Option<Budget>,
+    pub thresholds: Thresholds,
+    pub webhooks: Vec<Webhook>,
+    pub catalog: Option<PathBuf>,
+    pub versions: Retention,
+    pub trash_retention: Duration,
+    pub lifecycle: Vec<Rule>,
+    /// From `INDEXD_RECIPIENT_KEY_FILE` or the profile.
+    pub recipient_key_file: Option<PathBuf>,
+    pub journal_sessions: usize,
+    /// Faults to inject into transfers, from `INDEXD_CHAOS` or `--chaos`.
+    pub chaos: Option<Chaos>,
+    pub network: Network,
+    pub output: Output,
+}
+
+impl Settings {
+    /// Names the app key: the selected app identity, or the profile when
+    /// none is.
+    pub fn identity(&self) -> &str {
+        self.app
+            .as_deref()
+            .or(self.profile.as_deref())
+            .unwrap_or("default")
+    }
+
+    /// Returns the configured key source or an error explaining how to set
+    /// one.
+    pub fn key_source(&self) -> Result<&KeySource> {
+        self.key_source.as_ref().ok_or_else(|| {
+            Error::Config(
+                "no app key configured; set app_secret in the profile or INDEXD_APP_SECRET".into(),
+            )
+        })
+    }
+
+    /// Opens the configured catalog, or the one at the default path.
+    pub fn catalog(&self) -> Result<Catalog> {
+        Ok(Catalog::open(&self.catalog_path()?)?.with_retention(self.versions))
+    }
+
+    /// Returns where the catalog is kept.
+    pub fn catalog_path(&self) -> Result<PathBuf> {
+        match &self.catalog {
+            Some(path) => Ok(path.clone()),
+            None => catalog::default_path().ok_or_else(|| {
+                Error::Config("no catalog path; set catalog in the profile or HOME".into())
+            }),
+        }
+    }
+
+    /// Returns the profile's redundancy, shard and memory budgets,
+    /// read-ahead, compression and checksums as upload options.
+    pub fn upload_options(&self) -> UploadOptions {
+        let mut options = UploadOptions::new(self.data_shards, self.parity_shards)
+            .with_compression(self.compression)
+            .with_sha256(self.sha256)
+            .with_max_memory(self.max_memory)
+            .with_slab_sizing(self.slab_sizing);
+        if let Some(read_ahead) = self.read_ahead {
+            options = options.with_read_ahead(read_ahead);
+        }
+        match self.jobs {
+            Some(jobs) => options.with_max_inflight_shards(jobs),
+            None => options,
+        }
+    }
+}
+
+/// Returns `$XDG_CONFIG_HOME/indexd-utils/config.toml`, falling back to
+/// `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("indexd-utils").join("config.toml"))
+}
+
+impl Config {
+    /// Loads the config file at `path`. A missing file at the default path
+    /// is treated as an empty config; a missing explicit path is an error.
+    pub async fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let buf = match fs::read_to_string(&path).await {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {
+                return Ok(Config::default());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        toml::from_str(&buf).map_err(|e| Error::Config(format!("{}: {e}", path.display())))
+    }
+
+    /// Resolves the settings for `profile`, or the default profile if none
+    /// is given, connecting as `app` instead of the profile's app identity
+    /// if given. Environment variables take precedence over the app
+    /// identity, which takes precedence over the profile.
+    pub fn settings(&self, profile: Option<&str>, app: Option<&str>) -> Result<Settings> {
+        let name = profile.or(self.default_profile.as_deref());
+        let mut profile = match name {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::Config(format!("unknown profile {name:?}")))?,
+            None => Profile::default(),
+        };
+        let app_name = app.map(str::to_string).or(profile.app.take());
+        if let Some(app_name) = &app_name {
+            let app = self
+                .apps
+                .get(app_name)
+                .cloned()
+                .ok_or_else(|| Error::Config(format!("unknown app {app_name:?}")))?;
+            profile.app_url = app.app_url.or(profile.app_url);
+            // an app's key replaces the profile's, however it is given
+            if app.app_secret.is_some() || app.app_secret_file.is_some() {
+                profile.app_secret = app.app_secret;
+                profile.app_secret_file = app.app_secret_file;
+            }
+        }
+
+        let key_source = match (
+            env::var("INDEXD_APP_SECRET"),
+            env::var_os("INDEXD_APP_SECRET_FILE"),
+        ) {
+            (Ok(secret), _) => Some(KeySource::Secret(secret)),
+            (_, Some(path)) => Some(KeySource::SecretFile(path.into())),
+            _ => profile
+                .app_secret
+                .clone()
+                .map(KeySource::Secret)
+                .or(profile.app_secret_file.clone().map(KeySource::SecretFile)),
+        };
+        // otherwise the key is looked up in the keychain when connecting
+        #[cfg(feature = "keyring")]
+        let key_source = key_source.or_else(|| {
+            let identity = app_name.as_deref().or(name).unwrap_or("default");
+            Some(KeySource::Keychain(crate::keychain::app_account(identity)))
+        });
+
+        let redundancy = redundancy(&profile).map_err(|e| match e {
+            Error::Usage(message) => Error::Config(message),
+            e => e,
+        })?;
+
+        Ok(Settings {
+            profile: name.map(str::to_string),
+            app: app_name,
+            app_url: env::var("INDEXD_APP_URL")
+                .ok()
+                .or(profile.app_url.clone())
+                .unwrap_or_else(|| DEFAULT_APP_URL.to_string()),
+            key_source,
+            data_shards: redundancy.data_shards,
+            parity_shards: redundancy.parity_shards,
+            jobs: profile.jobs,
+            read_ahead: profile.read_ahead,
+            max_memory: profile
+                .max_memory
+                .as_deref()
+                .map(|s| throttle::parse_size(s).map_err(Error::Config))
+                .transpose()?,
+            slab_sizing: profile.slab_size.as_deref().map(str::parse).transpose()?,
+            concurrency: profile.concurrency.unwrap_or(DEFAULT_CONCURRENCY),
+            compression: profile.compression_level.map(Compression::zstd),
+            sha256: profile.sha256,
+            upload_limit: profile.bwlimit_up.as_deref().map(rate).transpose()?,
+            download_limit: profile.bwlimit_down.as_deref().map(rate).transpose()?,
+            retry: retry_policy(&profile),
+            timeouts: timeouts(&profile),
+            approval: approval(&profile),
+            hosts: HostPolicy::new(
+                profile.host_allow.clone(),
+                profile.host_block.clone(),
+                profile.spread.clone(),
+            )
+            .map_err(|e| match e {
+                Error::Usage(message) => Error::Config(message),
+                e => e,
+            })?,
+            placement_attempts: profile.placement_attempts.unwrap_or(0),
+            host_strategy: Arc::new(Latency),
+            hedge_after: profile.hedge_after_ms.map(Duration::from_millis),
+            budget: budget(&profile)?,
+            pricing: profile.pricing,
+            thresholds: Thresholds {
+                default: profile.min_healthy_shards,
+                by_prefix: profile.min_healthy_shards_by_prefix,
+            },
+            webhooks: profile.webhooks,
+            catalog: profile.catalog,
+            versions: profile.versions,
+            trash_retention: Duration::from_secs(
+                profile
+                    .trash_retention_days
+                    .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS)
+                    * 24
+                    * 60
+                    * 60,
+            ),
+            lifecycle: profile.lifecycle,
+            recipient_key_file: env::var_os("INDEXD_RECIPIENT_KEY_FILE")
+                .map(PathBuf::from)
+                .or(profile.recipient_key_file),
+            journal_sessions: profile.journal_sessions.unwrap_or(DEFAULT_JOURNAL_SESSIONS),
+            chaos: env::var("INDEXD_CHAOS")
+                .ok()
+                .map(|s| s.parse())
+                .transpose()
+                .map_err(|e| match e {
+                    Error::Usage(message) => Error::Config(format!("INDEXD_CHAOS: {message}")),
+                    e => e,
+                })?,
+            network: Network {
+                proxy: env::var("INDEXD_PROXY")
+                    .ok()
+                    .or(profile.proxy)
+                    .map(|s| s.parse())
+                    .transpose()
+                    .map_err(|e| match e {
+                        Error::Usage(message) => Error::Config(message),
+                        e => e,
+                    })?,
+                tls: Tls {
+                    ca_bundle: profile.ca_bundle,
+                    client_cert: profile.client_cert,
+                    insecure_skip_verify: profile.insecure_skip_verify,
+                },
+            },
+            output: Output::default(),
+        })
+    }
+}
+
+fn rate(s: &str) -> Result<u64> {
+    throttle::parse_rate(s).map_err(Error::Config)
+}
+
+fn retry_policy(profile: &Profile) -> RetryPolicy {
+    let default = RetryPolicy::default();
+    RetryPolicy {
+        max_attempts: profile
+            .retry_attempts
+            .unwrap_or(default.max_attempts)
+            .max(1),
+        base_delay: profile
+            .retry_delay_ms
+            .map(Duration::from_millis)
+            .unwrap_or(default.base_delay),
+        retry_on: profile
+            .retry_on
+            .clone()
+            .unwrap_or_else(|| default.retry_on.clone()),
+        max_rate_limit_wait: profile
+            .rate_limit_wait_secs
+            .map(Duration::from_secs)
+            .unwrap_or(default.max_rate_limit_wait),
+        ..default
+    }
+}
+
+fn timeouts(profile: &Profile) -> Timeouts {
+    let default = Timeouts::default();
+    let secs = |secs: Option<u64>, default: Option<Duration>| match secs {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => default,
+    };
+    Timeouts {
+        connect: secs(profile.connect_timeout_secs, default.connect),
+        upload: secs(profile.upload_timeout_secs, default.upload),
+        download: secs(profile.download_timeout_secs, default.download),
+    }
+}
+
+fn approval(profile: &Profile) -> Approval {
+    let default = Approval::default();
+    Approval {
+        interval: profile
+            .approval_interval_secs
+            .map_or(default.interval, |secs| Duration::from_secs(secs.max(1))),
+        deadline: match profile.approval_timeout_secs {
+            Some(0) | None => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+        },
+    }
+}
+
+fn budget(profile: &Profile) -> Result<Option<Budget>> {
+    if profile.budget_per_upload.is_none() && profile.budget_per_month.is_none() {
+        return Ok(None);
+    }
+    let pricing = profile
+        .pricing
+        .ok_or_else(|| Error::Config("a budget needs pricing to estimate costs with".into()))?;
+    Ok(Some(Budget {
+        pricing,
+        per_upload: profile.budget_per_upload,
+        per_month: profile.budget_per_month,
+    }))
+}
+
+fn redundancy(profile: &Profile) -> Result<Redundancy> {
+    let preset = match &profile.redundancy {
+        Some(preset) => preset.parse()?,
+        None => Redundancy::default(),
+    };
+    Redundancy::new(
+        profile.data_shards.unwrap_or(preset.data_shards),
+        profile.parity_shards.unwrap_or(preset.parity_shards),
+    )
+}
@@ -0,0 +1,525 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::budget::Budget;
+use crate::catalog::{Catalog, Entry};
+use crate::checksum::{self, ChecksumWriter};
+use crate::download;
+use crate::error::{Error, Kind, Result};
+use crate::estimate::Estimate;
+use crate::health::{self, Assessment, Thresholds};
+use crate::manifest::{AnyManifest, Manifest, StoredManifest};
+use crate::progress::Progress;
+use crate::redundancy::Redundancy;
+use crate::upload;
+use crate::uploader::{Job, Uploader};
+use crate::verify::{self, Health};
+use crate::webhook::{Event, Webhooks};
+
+/// What `POST /verify/{name}` found of a catalogued file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub name: String,
+    #[serde(flatten)]
+    pub report: verify::Report,
+    pub health: Assessment,
+}
+
+/// The body of `POST /uploads`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadRequest {
+    /// The file to upload, which the daemon must be able to read.
+    pub path: PathBuf,
+    /// The name to catalog the file under, defaults to its file name.
+    pub name: Option<String>,
+}
+
+/// Keeps one connection to the app open and uploads files for local
+/// clients, so they don't have to connect, or wait for approval, each time.
+///
+/// Uploaded files are catalogued with plain manifests, like `upload`
+/// without a passphrase. Enqueued files must be under the daemon's root.
+/// With a token every request must carry it as a bearer token; without
+/// one the API only listens on a loopback address and answers requests
+/// naming that address as their `Host`, so a web page can't reach it by
+/// rebinding its own name to the address:
+///
+/// - `POST /uploads` enqueues `{"path": ..., "name": ...}` and returns the
+///   job, whose `id` identifies it
+/// - `GET /uploads` and `GET /uploads/{id}` return the jobs
+/// - `POST /uploads/{id}/pause`, `/resume` and `/cancel` control a job and
+///   return it
+/// - `GET /objects?prefix=...` lists the catalog
+/// - `POST /verify/{name}` verifies a catalogued file and returns what it
+///   found
+///
+/// The profile's webhooks are told of every finished upload and download,
+/// of downloads and verifications that found data needing repair, of
+/// files left with fewer healthy shards than their threshold and of
+/// uploads refused for going over the budget.
+pub struct Daemon {
+    uploader: Arc<Uploader>,
+    catalog: Arc<Mutex<Catalog>>,
+    /// What every upload is charged against.
+    budget: Option<Budget>,
+    webhooks: Webhooks,
+    thresholds: Thresholds,
+    /// The bearer token requests must carry, if any.
+    token: Option<String>,
+    /// The directory enqueued files must be under, canonicalized.
+    root: Option<PathBuf>,
+}
+
+impl Daemon {
+    /// Runs the files enqueued with `uploader`, catalogs them, charges
+    /// them against `budget` and notifies `webhooks`.
+    pub fn new(
+        uploader: Uploader,
+        catalog: Catalog,
+        budget: Option<Budget>,
+        webhooks: Webhooks,
+    ) -> Self {
+        let catalog = Arc::new(Mutex::new(catalog));
+        let uploader = {
+            let catalog = catalog.clone();
+            let webhooks = webhooks.clone();
+            uploader.with_finish(Box::new(move |job, manifest| {
+                let source = job.path.display().to_string();
+                record(
+                    &catalog,
+                    budget.as_ref(),
+                    &webhooks,
+                    &job.name,
+                    Some(&source),
+                    manifest,
+                )
+            }))
+        };
+        Self {
+            uploader: Arc::new(uploader),
+            catalog,
+            budget,
+            webhooks,
+            thresholds: Thresholds::default(),
+            token: None,
+            root: None,
+        }
+    }
+
+    /// Checks verified files against `thresholds`.
+    pub fn with_thresholds(mut self, thresholds: Thresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// Requires every request to carry `token` as a bearer token.
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token.filter(|token| !token.is_empty());
+        self
+    }
+
+    /// Only accepts files under `root`, once symbolic links are resolved.
+    pub async fn with_root(mut self, root: PathBuf) -> Result<Self> {
+        self.root = Some(
+            fs::canonicalize(&root)
+                .await
+                .map_err(|e| Error::Config(format!("invalid root {}: {e}", root.display())))?,
+        );
+        Ok(self)
+    }
+
+    /// Refuses to serve on `addr` without a token, unless it is a loopback
+    /// address that only local processes can reach.
+    pub fn check_listen(&self, addr: SocketAddr) -> Result<()> {
+        if self.token.is_none() && !addr.ip().is_loopback() {
+            return Err(Error::Usage(format!(
+                "{addr} isn't a loopback address, so the daemon needs a token to serve on it"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns whether a request's `Authorization` header has the token.
+    pub fn authorized(&self, authorization: Option<&str>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+    }
+
+    /// Returns the HTTP API served on `addr`.
+    pub fn router(self: Arc<Self>, addr: SocketAddr) -> Result<Router> {
+        self.check_listen(addr)?;
+        let guard = Arc::new(Guard {
+            daemon: self.clone(),
+            hosts: loopback_hosts(addr),
+        });
+        Ok(Router::new()
+            .route("/uploads", post(enqueue).get(list_jobs))
+            .route("/uploads/{id}", get(get_job))
+            .route("/uploads/{id}/pause", post(pause_job))
+            .route("/uploads/{id}/resume", post(resume_job))
+            .route("/uploads/{id}/cancel", post(cancel_job))
+            .route("/objects", get(list_objects))
+            .route("/verify/{*name}", post(verify_object))
+            .layer(middleware::from_fn_with_state(guard, authorize))
+            .with_state(self))
+    }
+
+    /// Checks an upload request and queues it.
+    pub async fn enqueue(&self, request: UploadRequest) -> Result<Job> {
+        // the file checked is the one uploaded, wherever a link pointed
+        let path = fs::canonicalize(&request.path).await?;
+        if let Some(root) = self.root.as_ref().filter(|root| !path.starts_with(root)) {
+            return Err(Error::Usage(format!(
+                "{} is not under {}",
+                request.path.display(),
+                root.display()
+            )));
+        }
+        let metadata = fs::metadata(&path).await?;
+        if !metadata.is_file() {
+            return Err(Error::Usage(format!(
+                "{} is not a file",
+                request.path.display()
+            )));
+        }
+        let name = match request.name {
+            Some(name) => name,
+            None => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| Error::Usage("name is required".into()))?,
+        };
+        self.check(&name, metadata.len())?;
+        Ok(self.uploader.add(path, name, metadata.len()))
+    }
+
+    /// The queue running the enqueued files.
+    pub fn uploader(&self) -> &Arc<Uploader> {
+        &self.uploader
+    }
+
+    /// Refuses to upload `name` if it is already catalogued, or if `size`
+    /// bytes would go over the budget.
+    pub fn check(&self, name: &str, size: u64) -> Result<()> {
+        let catalog = self.catalog.lock().unwrap();
+        if catalog.contains(name)? {
+            return Err(Error::Usage(format!("{name} is already in the catalog")));
+        }
+        if let Some(budget) = &self.budget {
+            let charge = budget.charge(&self.estimate(size));
+            if let Some(reason) = budget.exceeded(&catalog, charge)? {
+                self.webhooks.notify(
+                    Event::BudgetExceeded,
+                    json!({ "name": name, "size": size, "reason": reason }),
+                );
+                return Err(Error::Budget(reason));
+            }
+        }
+        Ok(())
+    }
+
+    /// Uploads everything read from `reader` and catalogs it as `name`.
+    pub async fn store<R>(
+        &self,
+        name: &str,
+        source: Option<&str>,
+        reader: R,
+        progress: Progress,
+    ) -> Result<Manifest>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let sdk = self.uploader.client();
+        let options = self.uploader.options();
+        let manifest =
+            upload::upload_reader(sdk, reader, rand::random(), options, progress).await?;
+        record(
+            &self.catalog,
+            self.budget.as_ref(),
+            &self.webhooks,
+            name,
+            source,
+            &manifest,
+        )?;
+        Ok(manifest)
+    }
+
+    /// Returns the manifest of a catalogued file.
+    pub fn manifest(&self, name: &str) -> Result<Manifest> {
+        match self.catalog.lock().unwrap().get(name)? {
+            Some(StoredManifest::Plain(AnyManifest::File(manifest))) => Ok(manifest),
+            Some(StoredManifest::Plain(AnyManifest::Directory(_))) => {
+                Err(Error::Usage(format!("{name} is a directory")))
+            }
+            Some(StoredManifest::Sealed(_)) => {
+                Err(Error::Usage(format!("{name} is sealed with a passphrase")))
+            }
+            None => Err(Error::NotFound(name.to_string())),
+        }
+    }
+
+    /// Downloads the plaintext of the file catalogued as `name` into `w`,
+    /// failing at the end if it doesn't match the manifest's checksum.
+    pub async fn download<W>(&self, name: &str, manifest: &Manifest, w: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let mut w = ChecksumWriter::new(w);
+        let result =
+            download::download_object(self.uploader.client(), &mut w, manifest, 0, manifest.size)
+                .await
+                .and_then(|()| checksum::verify_written(&w, manifest));
+        match &result {
+            Ok(()) => self.webhooks.notify(
+                Event::DownloadComplete,
+                json!({ "name": name, "size": manifest.size }),
+            ),
+            Err(e) if e.kind() == Kind::Integrity => self.webhooks.notify(
+                Event::RepairNeeded,
+                json!({ "name": name, "error": e.to_string() }),
+            ),
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// Recovers every slab of a catalogued file without storing it and
+    /// checks it against its threshold, alerting the webhooks if it needs
+    /// repair or has fewer healthy shards than required.
+    pub async fn verify(&self, name: &str) -> Result<Verification> {
+        let manifest = self.manifest(name)?;
+        let report =
+            verify::verify(self.uploader.client(), &manifest, &Progress::default()).await?;
+        let hosts = self.catalog.lock().unwrap().host_stats()?;
+        let health = health::assess(&manifest, &report, &hosts, self.thresholds.get(name));
+        if !report.is_ok() || report.count(Health::Degraded) > 0 {
+            self.webhooks.notify(
+                Event::RepairNeeded,
+                json!({
+                    "name": name,
+                    "degraded": report.count(Health::Degraded),
+                    "unrecoverable": report.count(Health::Unrecoverable),
+                    "checksum_ok": report.checksum_ok,
+                }),
+            );
+        }
+        if health.breached {
+            self.webhooks.notify(
+                Event::ThresholdBreached,
+                json!({ "name": name, "health": health }),
+            );
+        }
+        Ok(Verification {
+            name: name.to_string(),
+            report,
+            health,
+        })
+    }
+
+    /// Lists the catalogued objects whose names start with `prefix`.
+    pub fn objects(&self, prefix: &str) -> Result<Vec<Entry>> {
+        self.catalog.lock().unwrap().list(prefix)
+    }
+
+    pub fn job(&self, id: u64) -> Option<Job> {
+        self.uploader.job(id)
+    }
+
+    pub fn jobs(&self) -> Vec<Job> {
+        self.uploader.jobs()
+    }
+
+    fn estimate(&self, size: u64) -> Estimate {
+        let options = self.uploader.options();
+        let redundancy = Redundancy {
+            data_shards: options.data_shards,
+            parity_shards: options.parity_shards,
+        };
+        Estimate::new(&[size], redundancy, 0)
+    }
+}
+
+/// Catalogs an uploaded file as `name` with a plain manifest and records
+/// what it cost.
+fn record(
+    catalog: &Mutex<Catalog>,
+    budget: Option<&Budget>,
+    webhooks: &Webhooks,
+    name: &str,
+    source: Option<&str>,
+    manifest: &Manifest,
+) -> Result<()> {
+    let any = AnyManifest::File(manifest.clone());
+    let stored = StoredManifest::new(any.clone(), None)?;
+    let catalog = catalog.lock().unwrap();
+    catalog.put(name, source, &any, &stored)?;
+    if let Some(budget) = budget {
+        let redundancy = Redundancy {
+            data_shards: manifest.data_shards,
+            parity_shards: manifest.parity_shards,
+        };
+        let charge = budget.charge(&Estimate::new(&[manifest.size], redundancy, 0));
+        catalog.record_spending(name, charge)?;
+    }
+    info!("uploaded {name}");
+    webhooks.notify(
+        Event::UploadComplete,
+        json!({
+            "name": name,
+            "source": source,
+            "size": manifest.size,
+            "slabs": manifest.slabs.len(),
+        }),
+    );
+    Ok(())
+}
+
+/// What the HTTP API checks of every request before routing it.
+struct Guard {
+    daemon: Arc<Daemon>,
+    /// The `Host` headers accepted without a token.
+    hosts: Vec<String>,
+}
+
+/// Refuses requests without the token, or without one naming a host the
+/// daemon doesn't serve as. A browser sends the name a page was loaded
+/// from, so a page rebinding its name to a loopback address is refused.
+async fn authorize(State(guard): State<Arc<Guard>>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    if !guard.daemon.authorized(authorization) {
+        let body = json!({ "error": "missing or wrong token" });
+        return (StatusCode::UNAUTHORIZED, Json(body)).into_response();
+    }
+    if guard.daemon.token.is_none() {
+        let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+        if !host.is_some_and(|host| guard.hosts.iter().any(|h| h.eq_ignore_ascii_case(host))) {
+            let body = json!({ "error": "unknown host" });
+            return (StatusCode::FORBIDDEN, Json(body)).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// The names a loopback address on `addr`'s port goes by.
+fn loopback_hosts(addr: SocketAddr) -> Vec<String> {
+    let port = addr.port();
+    vec![
+        addr.to_string(),
+        format!("localhost:{port}"),
+        format!("127.0.0.1:{port}"),
+        format!("[::1]:{port}"),
+    ]
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// An error reported as a status code and a JSON body.
+struct ApiError(Error);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Usage(_) => StatusCode::BAD_REQUEST,
+            Error::Budget(_) | Error::InsufficientFunds(_) => StatusCode::PAYMENT_REQUIRED,
+            Error::ApprovalRequired(_) => StatusCode::FORBIDDEN,
+            Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound => StatusCode::BAD_REQUEST,
+            e if e.is_retryable() => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let body = serde_json::json!({ "error": self.0.to_string() });
+        (status, Json(body)).into_response()
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        ApiError(e)
+    }
+}
+
+async fn enqueue(
+    State(daemon): State<Arc<Daemon>>,
+    Json(request): Json<UploadRequest>,
+) -> std::result::Result<(StatusCode, Json<Job>), ApiError> {
+    let job = daemon.enqueue(request).await?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+async fn list_jobs(State(daemon): State<Arc<Daemon>>) -> Json<Vec<Job>> {
+    Json(daemon.jobs())
+}
+
+async fn get_job(
+    State(daemon): State<Arc<Daemon>>,
+    Path(id): Path<u64>,
+) -> std::result::Result<Json<Job>, ApiError> {
+    daemon
+        .job(id)
+        .map(Json)
+        .ok_or_else(|| ApiError(Error::NotFound(format!("upload {id}"))))
+}
+
+async fn pause_job(
+    State(daemon): State<Arc<Daemon>>,
+    Path(id): Path<u64>,
+) -> std::result::Result<Json<Job>, ApiError> {
+    Ok(Json(daemon.uploader.pause(id)?))
+}
+
+async fn resume_job(
+    State(daemon): State<Arc<Daemon>>,
+    Path(id): Path<u64>,
+) -> std::result::Result<Json<Job>, ApiError> {
+    Ok(Json(daemon.uploader.resume(id)?))
+}
+
+async fn cancel_job(
+    State(daemon): State<Arc<Daemon>>,
+    Path(id): Path<u64>,
+) -> std::result::Result<Json<Job>, ApiError> {
+    Ok(Json(daemon.uploader.cancel(id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct ObjectsQuery {
+    #[serde(default)]
+    prefix: String,
+}
+
+async fn list_objects(
+    State(daemon): State<Arc<Daemon>>,
+    Query(query): Query<ObjectsQuery>,
+) -> std::result::Result<Json<Vec<Entry>>, ApiError> {
+    Ok(Json(daemon.objects(&query.prefix)?))
+}
+
+async fn verify_object(
+    State(daemon): State<Arc<Daemon>>,
+    Path(name): Path<String>,
+) -> std::result::Result<Json<Verification>, ApiError> {
+    Ok(Json(daemon.verify(&name).await?))
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::io;
+
+use fastcdc::v2020::AsyncStreamCDC;
+use futures::StreamExt;
+use indexd::Slab;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncRead;
+
+use crate::catalog::Catalog;
+use crate::checksum::ChecksumReader;
+use crate::client::Client;
+use crate::download;
+use crate::error::Result;
+use crate::manifest::Manifest;
+use crate::progress::{Progress, ProgressReader};
+use crate::upload::{SECTOR_SIZE, UploadOptions};
+
+/// The bounds FastCDC cuts chunks within, the largest it supports.
+const MIN_CHUNK: u32 = 1 << 20;
+const AVG_CHUNK: u32 = 1 << 22;
+const MAX_CHUNK: u32 = 1 << 24;
+
+/// A content-defined chunk of an object.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    /// The BLAKE2b-256 hash of the chunk's plaintext.
+    #[serde(with = "hex")]
+    pub hash: [u8; 32],
+    pub length: u64,
+}
+
+/// What a deduplicated upload stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    pub chunks: usize,
+    /// The chunks that were not in the catalog and had to be uploaded.
+    pub new_chunks: usize,
+    pub new_bytes: u64,
+    pub reused_bytes: u64,
+}
+
+/// Where a chunk of the object being uploaded is stored.
+enum Pending {
+    Stored(Vec<Slab>),
+    /// A range of the batch that hasn't been uploaded yet.
+    Batched {
+        offset: u64,
+        length: u64,
+    },
+}
+
+/// Chunks waiting to be uploaded together.
+#[derive(Default)]
+struct Batch {
+    data: Vec<u8>,
+    /// The offset of each chunk in `data`, so a chunk repeated within the
+    /// batch is only stored once.
+    chunks: HashMap<[u8; 32], u64>,
+    pending: Vec<Pending>,
+}
+
+/// Uploads a stream, storing only the chunks not already in the catalog.
+///
+/// The input is split with FastCDC so an insertion only changes the chunks
+/// around it. New chunks are packed together into slab-sized uploads and
+/// each is recorded in the catalog as the slab slices that hold it; the
+/// manifest lists the slices of every chunk in order, so it downloads like
+/// any other. Reused chunks keep the redundancy they were first stored
+/// with.
+pub async fn upload_dedup<R>(
+    sdk: &Client,
+    catalog: &Catalog,
+    reader: R,
+    encryption_key: [u8; 32],
+    options: UploadOptions,
+    progress: Progress,
+) -> Result<(Manifest, DedupStats)>
+where
+    R: AsyncRead + Unpin,
+{
+    let (reader, checksum) = ChecksumReader::new(reader, options.sha256);
+    let reader = ProgressReader::new(reader, progress.clone());
+    let mut chunker = AsyncStreamCDC::new(reader, MIN_CHUNK, AVG_CHUNK, MAX_CHUNK);
+    let mut stream = Box::pin(chunker.as_stream());
+
+    let batch_size = options.data_shards as usize * SECTOR_SIZE as usize;
+    let mut batch = Batch::default();
+    let mut stats = DedupStats::default();
+    let mut chunks = Vec::new();
+    let mut slabs = Vec::new();
+    while let Some(data) = stream.next().await {
+        let data = data.map_err(|e| io::Error::other(e.to_string()))?.data;
+        let hash = hash(&data);
+        let length = data.len() as u64;
+        chunks.push(Chunk { hash, length });
+        stats.chunks += 1;
+
+        // a chunk stored on hosts the policy rules out is stored again
+        let stored = catalog
+            .get_chunk(&hash)?
+            .filter(|slabs| match sdk.host_policy() {
+                Some(hosts) => slabs.iter().all(|slab| hosts.violations(slab) == 0),
+                None => true,
+            });
+        if let Some(stored) = stored {
+            stats.reused_bytes += length;
+            batch.pending.push(Pending::Stored(stored));
+        } else if let Some(&offset) = batch.chunks.get(&hash) {
+            stats.reused_bytes += length;
+            batch.pending.push(Pending::Batched { offset, length });
+        } else {
+            let offset = batch.data.len() as u64;
+            batch.data.extend_from_slice(&data);
+            batch.chunks.insert(hash, offset);
+            batch.pending.push(Pending::Batched { offset, length });
+            stats.new_chunks += 1;
+            stats.new_bytes += length;
+        }
+        if batch.data.len() >= batch_size {
+            let batch = std::mem::take(&mut batch);
+            slabs.extend(store_batch(sdk, catalog, batch, options).await?);
+        }
+    }
+    drop(stream);
+    slabs.extend(store_batch(sdk, catalog, batch, options).await?);
+
+    let mut manifest = Manifest::new(
+        checksum.read(),
+        checksum.checksum(),
+        &encryption_key,
+        options.data_shards,
+        options.parity_shards,
+        slabs,
+    )
+    .with_sha256(checksum.sha256())
+    .with_hosts(sdk.host_policy().cloned());
+    manifest.chunks = chunks;
+    Ok((manifest, stats))
+}
+
+/// Uploads the new chunks of a batch and returns the slab slices of every
+/// pending chunk in order.
+async fn store_batch(
+    sdk: &Client,
+    catalog: &Catalog,
+    batch: Batch,
+    options: UploadOptions,
+) -> Result<Vec<Slab>> {
+    let uploaded = if batch.data.is_empty() {
+        Vec::new()
+    } else {
+        debug!("uploading {} bytes of new chunks", batch.data.len());
+        sdk.upload_bytes(
+            batch.data.into(),
+            rand::random(),
+            options.data_shards,
+            options.parity_shards,
+        )
+        .await?
+    };
+    let offsets: HashMap<u64, [u8; 32]> = batch.chunks.into_iter().map(|(h, o)| (o, h)).collect();
+
+    let mut slabs = Vec::new();
+    for pending in batch.pending {
+        match pending {
+            Pending::Stored(stored) => slabs.extend(stored),
+            Pending::Batched { offset, length } => {
+                let slices = download::slice_slabs(&uploaded, offset, length);
+                if let Some(hash) = offsets.get(&offset) {
+                    catalog.put_chunk(hash, length, &slices)?;
+                }
+                slabs.extend(slices);
+            }
+        }
+    }
+    Ok(slabs)
+}
+
+fn hash(data: &[u8]) -> [u8; 32] {
+    let h = blake2b_simd::Params::new().hash_length(32).hash(data);
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(h.as_bytes());
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mock::{self, MockBackend};
+
+    /// Returns `length` bytes without the repeats that would make chunks
+    /// deduplicate within the object itself.
+    fn noise(length: usize, mut state: u64) -> Vec<u8> {
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    async fn upload(sdk: &Client, catalog: &Catalog, data: &[u8]) -> (Manifest, DedupStats) {
+        let reader = Cursor::new(data.to_vec());
+        let options = UploadOptions::new(1, 1);
+        upload_dedup(sdk, catalog, reader, [1; 32], options, Progress::default())
+            .await
+            .unwrap()
+    }
+
+    async fn download(sdk: &Client, manifest: &Manifest) -> Vec<u8> {
+        let mut data = Vec::new();
+        download::download_object(sdk, &mut data, manifest, 0, manifest.size)
+            .await
+            .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn repeated_uploads_store_nothing_new() {
+        let dir = mock::temp_dir();
+        let catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let mock = Arc::new(MockBackend::new(2));
+        let sdk = Client::from_backend(mock.clone());
+        let data = noise(12 << 20, 1);
+
+        let (manifest, stats) = upload(&sdk, &catalog, &data).await;
+        assert!(stats.chunks > 1);
+        assert_eq!(stats.new_chunks, stats.chunks);
+        assert_eq!(stats.new_bytes, data.len() as u64);
+        assert_eq!(manifest.chunks.len(), stats.chunks);
+        assert_eq!(download(&sdk, &manifest).await, data);
+
+        let stored = mock.slabs();
+        let (manifest, stats) = upload(&sdk, &catalog, &data).await;
+        assert_eq!(stats.new_chunks, 0);
+        assert_eq!(stats.reused_bytes, data.len() as u64);
+        assert_eq!(mock.slabs(), stored);
+        assert_eq!(download(&sdk, &manifest).await, data);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_insertion_only_changes_the_chunks_around_it() {
+        let dir = mock::temp_dir();
+        let catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let data = noise(32 << 20, 2);
+        upload(&sdk, &catalog, &data).await;
+
+        let mut edited = data.clone();
+        edited.splice(16 << 20..16 << 20, noise(100, 3));
+        let (manifest, stats) = upload(&sdk, &catalog, &edited).await;
+        assert!(stats.new_chunks < stats.chunks);
+        assert!(stats.new_bytes < edited.len() as u64 / 2);
+        assert_eq!(download(&sdk, &manifest).await, edited);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+use tokio::fs;
+
+use crate::checksum;
+use crate::directory;
+use crate::error::Result;
+use crate::manifest::{DirectoryManifest, FileEntry};
+
+/// How a file differs between two trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A file that differs between two trees.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub change: Change,
+    /// The path relative to the tree, `/` separated.
+    pub path: String,
+    /// The size before, unset for added files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_size: Option<u64>,
+    /// The size after, unset for removed files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_size: Option<u64>,
+}
+
+impl FileChange {
+    /// How many bytes the file grew by, negative if it shrank.
+    pub fn delta(&self) -> i64 {
+        self.new_size.unwrap_or(0) as i64 - self.old_size.unwrap_or(0) as i64
+    }
+}
+
+impl fmt::Display for FileChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.change {
+            Change::Added => '+',
+            Change::Removed => '-',
+            Change::Modified => '~',
+        };
+        write!(f, "{sign} {} ({:+} bytes)", self.path, self.delta())
+    }
+}
+
+/// What changed from one tree to another, in path order.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Diff {
+    pub changes: Vec<FileChange>,
+    pub unchanged: usize,
+    /// How many bytes the tree grew by, negative if it shrank.
+    pub delta: i64,
+    /// The size of the added and modified files, the most a backup or sync
+    /// of the newer tree would read and upload; deduplicated backups store
+    /// only the chunks that changed.
+    pub transfer: u64,
+}
+
+impl Diff {
+    fn new(mut changes: Vec<FileChange>, unchanged: usize) -> Self {
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        Self {
+            delta: changes.iter().map(FileChange::delta).sum(),
+            transfer: changes.iter().filter_map(|c| c.new_size).sum(),
+            changes,
+            unchanged,
+        }
+    }
+
+    pub fn count(&self, change: Change) -> usize {
+        self.changes.iter().filter(|c| c.change == change).count()
+    }
+}
+
+/// Compares two directory manifests, such as two snapshots of a backup
+/// set. A file is modified when its size or checksum differs; attributes
+/// alone don't count.
+pub fn diff(old: &DirectoryManifest, new: &DirectoryManifest) -> Diff {
+    let mut before = by_path(old);
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+    for entry in &new.files {
+        match before.remove(entry.path.as_str()) {
+            None => changes.push(added(entry.path.clone(), entry.manifest.size)),
+            Some(old)
+                if old.manifest.size != entry.manifest.size
+                    || old.manifest.checksum != entry.manifest.checksum =>
+            {
+                changes.push(modified(old, entry.manifest.size));
+            }
+            Some(_) => unchanged += 1,
+        }
+    }
+    changes.extend(before.into_values().map(removed));
+    Diff::new(changes, unchanged)
+}
+
+/// Compares the files under `root` with a directory manifest, previewing
+/// what backing it up against the manifest would read.
+///
+/// As in a backup, a file with the manifest's size and modification time is
+/// taken as unchanged without being read; when only the time differs the
+/// file is hashed and compared with the manifest's checksum.
+pub async fn diff_local(root: &Path, old: &DirectoryManifest) -> Result<Diff> {
+    let mut before = by_path(old);
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+    for rel in directory::walk(root).await? {
+        let path = directory::to_manifest_path(&rel)?;
+        let metadata = fs::metadata(root.join(&rel)).await?;
+        let size = metadata.len();
+        let Some(old) = before.remove(path.as_str()) else {
+            changes.push(added(path, size));
+            continue;
+        };
+        let modified_at = directory::modified(&metadata);
+        let same = if old.manifest.size != size {
+            false
+        } else if modified_at.is_some() && old.modified == modified_at {
+            true
+        } else {
+            checksum::checksum_file(root.join(&rel)).await? == old.manifest.checksum
+        };
+        if same {
+            unchanged += 1;
+        } else {
+            changes.push(modified(old, size));
+        }
+    }
+    changes.extend(before.into_values().map(removed));
+    Ok(Diff::new(changes, unchanged))
+}
+
+fn by_path(manifest: &DirectoryManifest) -> HashMap<&str, &FileEntry> {
+    manifest
+        .files
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect()
+}
+
+fn added(path: String, size: u64) -> FileChange {
+    FileChange {
+        change: Change::Added,
+        path,
+        old_size: None,
+        new_size: Some(size),
+    }
+}
+
+fn removed(old: &FileEntry) -> FileChange {
+    FileChange {
+        change: Change::Removed,
+        path: old.path.clone(),
+        old_size: Some(old.manifest.size),
+        new_size: None,
+    }
+}
+
+fn modified(old: &FileEntry, size: u64) -> FileChange {
+    FileChange {
+        change: Change::Modified,
+        path: old.path.clone(),
+        old_size: Some(old.manifest.size),
+        new_size: Some(size),
+    }
+}
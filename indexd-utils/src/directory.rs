@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::Metadata;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, info, warn};
+use tokio::fs;
+
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::manifest::{FileEntry, LinkEntry};
+use crate::normalize;
+
+/// Recursively lists the regular files under `root`, returning their paths
+/// relative to `root` in sorted order.
+pub async fn walk(root: &Path) -> Result<Vec<PathBuf>> {
+    walk_filtered(root, &Filter::default()).await
+}
+
+/// Like `walk`, but skips the files and directories `filter` excludes
+/// without reading them.
+pub async fn walk_filtered(root: &Path, filter: &Filter) -> Result<Vec<PathBuf>> {
+    Ok(walk_tree(root, filter, Links::Skip).await?.files)
+}
+
+/// What a directory walk does with symbolic links.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Links {
+    /// Walk into the directories and read the files they point to.
+    Follow,
+    /// Record the links themselves, to be recreated on download.
+    Preserve,
+    /// Leave them out.
+    #[default]
+    Skip,
+}
+
+impl FromStr for Links {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "follow" => Ok(Self::Follow),
+            "preserve" => Ok(Self::Preserve),
+            "skip" => Ok(Self::Skip),
+            _ => Err(Error::Usage(format!(
+                "invalid link handling {s:?}; expected follow, preserve or skip"
+            ))),
+        }
+    }
+}
+
+/// The paths found by a directory walk, relative to its root, in sorted
+/// order.
+#[derive(Debug, Default)]
+pub struct Tree {
+    pub files: Vec<PathBuf>,
+    /// The symbolic links to preserve.
+    pub links: Vec<PathBuf>,
+}
+
+/// Like `walk_filtered`, but handles symbolic links as `links` says.
+/// Sockets, FIFOs and devices are skipped with a warning. When following
+/// links, a directory already walked through another path is skipped, so
+/// links that form a cycle end the walk rather than loop.
+pub async fn walk_tree(root: &Path, filter: &Filter, links: Links) -> Result<Tree> {
+    let mut tree = Tree::default();
+    let mut dirs = vec![PathBuf::new()];
+    let mut walked = HashSet::new();
+    if links == Links::Follow {
+        walked.insert(fs::canonicalize(root).await?);
+    }
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(root.join(&dir)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let rel = dir.join(entry.file_name());
+            let mut file_type = entry.file_type().await?;
+            if file_type.is_symlink() {
+                match links {
+                    Links::Skip => {
+                        info!("skipping symbolic link {}", rel.display());
+                        continue;
+                    }
+                    Links::Preserve => {
+                        if filter.excludes(&rel, false) {
+                            debug!("excluding {}", rel.display());
+                        } else {
+                            tree.links.push(rel);
+                        }
+                        continue;
+                    }
+                    Links::Follow => match fs::metadata(entry.path()).await {
+                        Ok(metadata) => file_type = metadata.file_type(),
+                        Err(err) => {
+                            warn!("skipping {}: broken link: {err}", rel.display());
+                            continue;
+                        }
+                    },
+                }
+            }
+            if filter.excludes(&rel, file_type.is_dir()) {
+                debug!("excluding {}", rel.display());
+            } else if file_type.is_dir() {
+                if links == Links::Follow
+                    && !walked.insert(fs::canonicalize(root.join(&rel)).await?)
+                {
+                    warn!(
+                        "skipping {}: already walked through another path",
+                        rel.display()
+                    );
+                } else {
+                    dirs.push(rel);
+                }
+            } else if file_type.is_file() {
+                tree.files.push(rel);
+            } else {
+                warn!("skipping {}: not a regular file", rel.display());
+            }
+        }
+    }
+    tree.files.sort();
+    tree.links.sort();
+    Ok(tree)
+}
+
+/// Reads the target of the symbolic link at `rel` under `root`.
+pub async fn read_link(root: &Path, rel: &Path) -> Result<LinkEntry> {
+    let path = to_manifest_path(rel)?;
+    let target = fs::read_link(root.join(rel)).await?;
+    let target = target
+        .to_str()
+        .ok_or_else(|| Error::Manifest(format!("the target of {path} is not valid UTF-8")))?
+        .to_string();
+    Ok(LinkEntry { path, target })
+}
+
+/// Recreates a preserved symbolic link under `root`, replacing a link
+/// already there.
+#[cfg(unix)]
+pub async fn create_link(root: &Path, link: &LinkEntry) -> Result<()> {
+    let path = resolve(root, &link.path)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let existing = fs::symlink_metadata(&path).await;
+    if existing.is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        fs::remove_file(&path).await?;
+    }
+    fs::symlink(&link.target, &path).await?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn create_link(root: &Path, link: &LinkEntry) -> Result<()> {
+    warn!(
+        "not recreating the link {}: unsupported on this platform",
+        resolve(root, &link.path)?.display()
+    );
+    Ok(())
+}
+
+/// Converts a relative path to the `/` separated form stored in manifests,
+/// with every name in the composed Unicode form.
+pub fn to_manifest_path(rel: &Path) -> Result<String> {
+    let parts =
+        rel.components()
+            .map(|c| match c {
+                Component::Normal(part) => part.to_str().map(normalize::name).ok_or_else(|| {
+                    Error::Manifest(format!("{} is not valid UTF-8", rel.display()))
+                }),
+                _ => Err(Error::Manifest(format!(
+                    "{} is not relative",
+                    rel.display()
+                ))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+    Ok(parts.join("/"))
+}
+
+/// Resolves a manifest path under `root`, rejecting paths that would escape
+/// it.
+pub fn resolve(root: &Path, path: &str) -> Result<PathBuf> {
+    let mut resolved = root.to_path_buf();
+    for part in path.split('/') {
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(part)), None) => resolved.push(part),
+            _ => return Err(Error::Manifest(format!("invalid path {path:?}"))),
+        }
+    }
+    Ok(resolved)
+}
+
+/// Returns a file's modification time in nanoseconds since the Unix epoch,
+/// if the platform records one.
+pub fn modified(metadata: &Metadata) -> Option<u64> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(since_epoch.as_nanos()).ok()
+}
+
+/// Sets the modification time of the file at `path` to `nanos` since the
+/// Unix epoch.
+pub async fn set_modified(path: &Path, nanos: u64) -> Result<()> {
+    let file = fs::OpenOptions::new().write(true).open(path).await?;
+    let time = SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos);
+    file.into_std().await.set_modified(time)?;
+    Ok(())
+}
+
+/// Records the permission bits and extended attributes of the file at
+/// `path` in its entry. Attributes that can't be read are left out with a
+/// warning rather than failing the upload.
+pub async fn record_attributes(path: &Path, entry: &mut FileEntry) -> Result<()> {
+    entry.mode = mode(&fs::metadata(path).await?);
+    let owned = path.to_path_buf();
+    entry.xattrs = tokio::task::spawn_blocking(move || read_xattrs(&owned))
+        .await
+        .map_err(io::Error::other)?;
+    Ok(())
+}
+
+/// Restores what `entry` recorded of a file's metadata: its extended
+/// attributes, its modification time and, last so a read-only mode doesn't
+/// get in the way of the rest, its permission bits.
+pub async fn restore_attributes(path: &Path, entry: &FileEntry) -> Result<()> {
+    if !entry.xattrs.is_empty() {
+        let owned = path.to_path_buf();
+        let xattrs = entry.xattrs.clone();
+        tokio::task::spawn_blocking(move || write_xattrs(&owned, &xattrs))
+            .await
+            .map_err(io::Error::other)??;
+    }
+    if let Some(modified) = entry.modified {
+        set_modified(path, modified).await?;
+    }
+    if let Some(mode) = entry.mode {
+        set_mode(path, mode).await?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+async fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn set_mode(path: &Path, _mode: u32) -> Result<()> {
+    debug!("not restoring the permissions of {}", path.display());
+    Ok(())
+}
+
+#[cfg(unix)]
+fn read_xattrs(path: &Path) -> BTreeMap<String, String> {
+    let mut xattrs = BTreeMap::new();
+    let names = match xattr::list(path) {
+        Ok(names) => names,
+        // the filesystem has no extended attributes
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => return xattrs,
+        Err(err) => {
+            warn!(
+                "can't list the extended attributes of {}: {err}",
+                path.display()
+            );
+            return xattrs;
+        }
+    };
+    for name in names {
+        let Some(key) = name.to_str() else {
+            warn!(
+                "skipping an extended attribute of {}: its name is not valid UTF-8",
+                path.display()
+            );
+            continue;
+        };
+        match xattr::get(path, &name) {
+            Ok(Some(value)) => {
+                xattrs.insert(key.to_string(), hex::encode(value));
+            }
+            Ok(None) => {}
+            Err(err) => warn!("can't read {key} of {}: {err}", path.display()),
+        }
+    }
+    xattrs
+}
+
+#[cfg(not(unix))]
+fn read_xattrs(_path: &Path) -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+#[cfg(unix)]
+fn write_xattrs(path: &Path, xattrs: &BTreeMap<String, String>) -> Result<()> {
+    for (name, value) in xattrs {
+        let value = hex::decode(value)
+            .map_err(|err| Error::Manifest(format!("invalid value of {name}: {err}")))?;
+        // attributes like security.* need privileges the user may not have
+        if let Err(err) = xattr::set(path, name, &value) {
+            warn!("can't set {name} on {}: {err}", path.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_xattrs(path: &Path, _xattrs: &BTreeMap<String, String>) -> Result<()> {
+    warn!(
+        "not restoring the extended attributes of {}: unsupported on this platform",
+        path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,375 @@
+use std::collections::HashSet;
+use std::io;
+use std::time::{Duration, Instant};
+
+use indexd::Slab;
+use rand::RngCore;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::Serialize;
+
+use crate::catalog::Catalog;
+use crate::client::{self, Client};
+use crate::config::Settings;
+use crate::download;
+use crate::error::{Error, Result};
+use crate::manifest::{AnyManifest, StoredManifest};
+use crate::retry::RetryPolicy;
+
+/// How long the app URL and the connection to the app may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The bytes fetched from each sampled slab. Enough to make every host
+/// involved read and send a segment, without downloading whole slabs.
+const PING_LENGTH: u32 = 4096;
+
+/// Below this, erasure coding is slower than a fast connection and
+/// uploads are held back by the CPU.
+const SLOW_ENCODING: f64 = 50.0 * (1 << 20) as f64;
+
+/// Shards are encoded at this size; the cost per byte doesn't depend on
+/// it, so this keeps the measurement from needing whole sectors.
+const SHARD_SIZE: usize = 256 << 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Ok,
+    Warn,
+    Fail,
+    /// The check couldn't run because an earlier one failed.
+    Skipped,
+}
+
+/// The result of one diagnostic.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+    pub detail: String,
+    /// What to do about a warning or failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Ok,
+            detail,
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: String, hint: String) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Warn,
+            detail,
+            hint: Some(hint),
+        }
+    }
+
+    fn fail(name: &'static str, detail: String, hint: String) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Fail,
+            detail,
+            hint: Some(hint),
+        }
+    }
+
+    fn skipped(name: &'static str, detail: &str) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Skipped,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+}
+
+/// Runs every diagnostic in order, reaching at most `hosts` hosts through
+/// the catalogued slabs. Checks that depend on a failed one are skipped
+/// rather than failing again for the same reason.
+pub async fn diagnose(settings: &Settings, hosts: usize) -> Vec<Check> {
+    let mut checks = vec![check_app_url(settings).await];
+    let reachable = checks[0].outcome != Outcome::Fail;
+    checks.push(check_app_key(settings).await);
+    let sdk = if !reachable {
+        checks.push(Check::skipped("approval", "the app URL can't be reached"));
+        None
+    } else if checks[1].outcome == Outcome::Fail {
+        checks.push(Check::skipped("approval", "no usable app key"));
+        None
+    } else {
+        let (check, sdk) = check_approval(settings).await;
+        checks.push(check);
+        sdk
+    };
+    let catalog = settings.catalog();
+    checks.push(match &catalog {
+        Ok(catalog) => check_catalog(catalog, settings),
+        Err(e) => Check::fail(
+            "catalog",
+            e.to_string(),
+            "set catalog in the profile to a writable path".into(),
+        ),
+    });
+    checks.push(match (&sdk, &catalog) {
+        (Some(sdk), Ok(catalog)) => check_hosts(sdk, catalog, hosts).await,
+        (None, _) => Check::skipped("hosts", "not connected to the app"),
+        (_, Err(_)) => Check::skipped("hosts", "the catalog can't be opened"),
+    });
+    checks.push(check_encoding(settings.data_shards, settings.parity_shards).await);
+    checks
+}
+
+async fn check_app_url(settings: &Settings) -> Check {
+    let url = &settings.app_url;
+    let client = match settings
+        .network
+        .http_client()
+        .and_then(|builder| Ok(builder.timeout(TIMEOUT).build()?))
+    {
+        Ok(client) => client,
+        Err(e) => {
+            return Check::fail(
+                "app url",
+                e.to_string(),
+                "check the proxy in --proxy, INDEXD_PROXY or the profile".into(),
+            );
+        }
+    };
+    let start = Instant::now();
+    match client.get(url).send().await {
+        Ok(response) if response.status().is_server_error() => Check::warn(
+            "app url",
+            format!("{url} answered {}", response.status()),
+            "the indexer is having trouble; try again later or contact its operator".into(),
+        ),
+        Ok(response) => Check::ok(
+            "app url",
+            format!(
+                "{url} answered {} in {}ms",
+                response.status(),
+                start.elapsed().as_millis()
+            ),
+        ),
+        Err(e) => Check::fail(
+            "app url",
+            format!("{url} can't be reached: {e}"),
+            "check app_url in the profile (or INDEXD_APP_URL) and that this machine can reach it"
+                .into(),
+        ),
+    }
+}
+
+async fn check_app_key(settings: &Settings) -> Check {
+    let seed = match settings.key_source() {
+        Ok(source) => source.seed().await,
+        Err(e) => Err(e),
+    };
+    match seed {
+        Ok(_) => Check::ok("app key", format!("loaded for {}", settings.identity())),
+        Err(e) => Check::fail(
+            "app key",
+            e.to_string(),
+            "configure an app key; `upload-rs key list` shows the identities configured".into(),
+        ),
+    }
+}
+
+/// Connects without waiting for approval, returning the client if the app
+/// is approved.
+async fn check_approval(settings: &Settings) -> (Check, Option<Client>) {
+    let mut settings = settings.clone();
+    settings.approval.deadline = Some(Duration::ZERO);
+    settings.timeouts.connect = Some(TIMEOUT);
+    settings.retry = RetryPolicy {
+        max_attempts: 1,
+        max_rate_limit_wait: Duration::ZERO,
+        ..settings.retry
+    };
+    match client::connect(&settings).await {
+        Ok(sdk) => (
+            Check::ok("approval", format!("{} is approved", settings.identity())),
+            Some(sdk),
+        ),
+        Err(Error::ApprovalTimedOut(url)) => (
+            Check::fail(
+                "approval",
+                format!("{} is not approved", settings.identity()),
+                format!("approve the app at {url}, then run doctor again"),
+            ),
+            None,
+        ),
+        Err(e) => (
+            Check::fail(
+                "approval",
+                format!("connecting failed: {e}"),
+                "check that the app URL points at an indexer and the app key is the one it knows"
+                    .into(),
+            ),
+            None,
+        ),
+    }
+}
+
+fn check_catalog(catalog: &Catalog, settings: &Settings) -> Check {
+    match catalog.list("") {
+        Ok(entries) if settings.catalog.is_none() => Check::ok(
+            "catalog",
+            format!("{} objects in the default catalog", entries.len()),
+        ),
+        Ok(entries) => Check::ok("catalog", format!("{} objects", entries.len())),
+        Err(e) => Check::fail(
+            "catalog",
+            e.to_string(),
+            "the catalog may be corrupt; move it aside to start a new one".into(),
+        ),
+    }
+}
+
+/// Fetches a few bytes of catalogued slabs chosen to reach as many hosts
+/// as possible. The SDK only reports transfers per slab, so a failed fetch
+/// names the slab's hosts without saying which of them failed.
+async fn check_hosts(sdk: &Client, catalog: &Catalog, hosts: usize) -> Check {
+    let slabs = match sample_slabs(catalog, hosts) {
+        Ok(slabs) if slabs.is_empty() => {
+            return Check::skipped("hosts", "nothing catalogued to reach hosts through");
+        }
+        Ok(slabs) => slabs,
+        Err(e) => return Check::skipped("hosts", &e.to_string()),
+    };
+    let mut reached = HashSet::new();
+    let (mut failed, mut retried, mut latencies) = (0, 0, Vec::new());
+    for slab in &slabs {
+        let start = Instant::now();
+        let (result, attempts) = download::fetch_slab(sdk, slab).await;
+        match result {
+            Ok(_) => {
+                latencies.push(start.elapsed());
+                retried += usize::from(attempts > 1);
+                reached.extend(slab.sectors.iter().map(|s| s.host_key.to_string()));
+            }
+            Err(_) => failed += 1,
+        }
+    }
+    latencies.sort();
+    let median = latencies
+        .get(latencies.len() / 2)
+        .map_or(0, |d| d.as_millis());
+    let detail = format!(
+        "{} of {} sample slabs fetched from {} hosts, median {median}ms",
+        slabs.len() - failed,
+        slabs.len(),
+        reached.len()
+    );
+    let hint = "run `upload-rs hosts stats` to see which hosts fail often, and add \
+                those that should never hold shards to host_block";
+    if failed == slabs.len() {
+        Check::fail(
+            "hosts",
+            detail,
+            "no host could be reached; check that outbound connections aren't blocked".into(),
+        )
+    } else if failed > 0 || retried > 0 {
+        Check::warn("hosts", detail, hint.into())
+    } else {
+        Check::ok("hosts", detail)
+    }
+}
+
+/// Picks catalogued slabs, smallest first, until `hosts` distinct hosts
+/// are covered, each narrowed to its first few bytes. Sealed manifests
+/// can't be read without their passphrase and are passed over.
+fn sample_slabs(catalog: &Catalog, hosts: usize) -> Result<Vec<Slab>> {
+    let mut candidates = Vec::new();
+    for entry in catalog.list("")? {
+        let Some(StoredManifest::Plain(manifest)) = catalog.get(&entry.name)? else {
+            continue;
+        };
+        match manifest {
+            AnyManifest::File(manifest) => candidates.extend(manifest.slabs),
+            AnyManifest::Directory(dir) => {
+                candidates.extend(dir.files.into_iter().flat_map(|f| f.manifest.slabs))
+            }
+        }
+    }
+    candidates.sort_by_key(|slab| slab.length);
+    let mut covered = HashSet::new();
+    let mut sample = Vec::new();
+    for slab in candidates {
+        if covered.len() >= hosts {
+            break;
+        }
+        let before = covered.len();
+        covered.extend(slab.sectors.iter().map(|s| s.host_key.to_string()));
+        if covered.len() > before {
+            sample.extend(download::slice_slabs(
+                std::slice::from_ref(&slab),
+                0,
+                PING_LENGTH as u64,
+            ));
+        }
+    }
+    Ok(sample)
+}
+
+async fn check_encoding(data_shards: u8, parity_shards: u8) -> Check {
+    let redundancy = format!("{data_shards}+{parity_shards}");
+    match encode_throughput(data_shards, parity_shards, Duration::from_millis(500)).await {
+        Ok(rate) if rate < SLOW_ENCODING => Check::warn(
+            "encoding",
+            format!(
+                "{redundancy} encodes at {:.1} MiB/s",
+                rate / (1 << 20) as f64
+            ),
+            "uploads will be held back by the CPU; fewer parity shards encode faster".into(),
+        ),
+        Ok(rate) => Check::ok(
+            "encoding",
+            format!(
+                "{redundancy} encodes at {:.1} MiB/s",
+                rate / (1 << 20) as f64
+            ),
+        ),
+        Err(e) => Check::fail(
+            "encoding",
+            e.to_string(),
+            "set data_shards and parity_shards in the profile to a supported redundancy".into(),
+        ),
+    }
+}
+
+/// Measures how many bytes of data per second this machine erasure codes
+/// with the given shards, encoding on one thread for about `duration`.
+pub async fn encode_throughput(
+    data_shards: u8,
+    parity_shards: u8,
+    duration: Duration,
+) -> Result<f64> {
+    let rs = ReedSolomon::new(data_shards as usize, parity_shards as usize).map_err(|e| {
+        Error::Usage(format!(
+            "can't encode {data_shards}+{parity_shards} shards: {e:?}"
+        ))
+    })?;
+    tokio::task::spawn_blocking(move || {
+        let mut shards = vec![vec![0u8; SHARD_SIZE]; rs.total_shard_count()];
+        for shard in &mut shards[..rs.data_shard_count()] {
+            rand::rng().fill_bytes(shard);
+        }
+        let start = Instant::now();
+        let mut rounds = 0u64;
+        while rounds == 0 || start.elapsed() < duration {
+            rs.encode(&mut shards)
+                .map_err(|e| io::Error::other(format!("encoding failed: {e:?}")))?;
+            rounds += 1;
+        }
+        let bytes = rounds * (rs.data_shard_count() * SHARD_SIZE) as u64;
+        Ok(bytes as f64 / start.elapsed().as_secs_f64())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
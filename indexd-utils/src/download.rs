@@ -0,0 +1,607 @@
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{StreamExt, TryStreamExt, stream};
+use indexd::Slab;
+use log::warn;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use crate::aead::Cipher;
+use crate::client::Client;
+use crate::compression::ZstdWriter;
+use crate::error::{Error, Result};
+use crate::fileio::PositionedFile;
+use crate::manifest::Manifest;
+use crate::progress::{Event, Progress};
+use crate::retry::{ErrorClass, Paused};
+use crate::sparse;
+
+/// Returns the slab slices covering `length` bytes of an object starting at
+/// `offset`. Each slice keeps the slab's key and sectors but narrows its
+/// `offset` and `length`, so only the segments of each sector that hold the
+/// range are fetched and decrypted.
+pub fn slice_slabs(slabs: &[Slab], offset: u64, length: u64) -> Vec<Slab> {
+    let end = offset.saturating_add(length);
+    let mut slices = Vec::new();
+    let mut slab_start = 0u64;
+    for slab in slabs {
+        let slab_len = slab.length as u64;
+        let slab_end = slab_start + slab_len;
+        if slab_end <= offset {
+            slab_start = slab_end;
+            continue;
+        } else if slab_start >= end {
+            break;
+        }
+
+        let skip = offset.saturating_sub(slab_start);
+        let take = slab_end.min(end) - slab_start - skip;
+        let mut slice = slab.clone();
+        slice.offset += skip as u32;
+        slice.length = take as u32;
+        slices.push(slice);
+        slab_start = slab_end;
+    }
+    slices
+}
+
+/// Downloads `length` bytes of an object starting at `offset`. Ranges past
+/// the end of the object are truncated.
+pub async fn download_range<W>(
+    sdk: &Client,
+    w: &mut W,
+    slabs: &[Slab],
+    offset: u64,
+    length: u64,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let slices = slice_slabs(slabs, offset, length);
+    if slices.is_empty() {
+        return Ok(());
+    }
+    sdk.download(w, &slices).await?;
+    Ok(())
+}
+
+/// Downloads `length` bytes of an object's plaintext starting at `offset`,
+/// decompressing or opening it if needed.
+///
+/// A compressed object can only be decoded from its start, so everything up
+/// to the end of the range is fetched; the download stops once the range
+/// has been written. A sealed object only needs the blocks overlapping the
+/// range. The holes of a sparse object are written as zeros.
+pub async fn download_object<W>(
+    sdk: &Client,
+    w: &mut W,
+    manifest: &Manifest,
+    offset: u64,
+    length: u64,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    if !manifest.holes.is_empty() {
+        for run in sparse::runs(&manifest.holes, manifest.size, offset, length) {
+            match run.stored {
+                Some(stored) => download_range(sdk, w, &manifest.slabs, stored, run.length).await?,
+                None => {
+                    tokio::io::copy(&mut sparse::zeros(run.length), w).await?;
+                }
+            }
+        }
+        w.flush().await?;
+        return Ok(());
+    }
+    match (manifest.compression, manifest.cipher) {
+        (None, None) => return download_range(sdk, w, &manifest.slabs, offset, length).await,
+        (Some(_), Some(_)) => {
+            return Err(Error::Manifest(
+                "compressed and sealed objects are not supported".into(),
+            ));
+        }
+        (None, Some(cipher)) => {
+            return download_sealed(sdk, w, manifest, cipher, offset, length).await;
+        }
+        (Some(_), None) => {}
+    }
+    let length = length.min(manifest.size.saturating_sub(offset));
+    if length == 0 {
+        return Ok(());
+    }
+    let mut decoder = ZstdWriter::new(Window {
+        inner: w,
+        skip: offset,
+        remaining: length,
+    })?;
+    let result: Result<()> = async {
+        sdk.download(&mut decoder, &manifest.slabs).await?;
+        decoder.flush().await?;
+        Ok(())
+    }
+    .await;
+    match result {
+        // the window refuses anything past the range to end the download
+        Err(_) if decoder.get_ref().remaining == 0 => {}
+        result => result?,
+    }
+    drop(decoder);
+    w.flush().await?;
+    Ok(())
+}
+
+/// Fetches and opens the blocks of a sealed object overlapping the range.
+async fn download_sealed<W>(
+    sdk: &Client,
+    w: &mut W,
+    manifest: &Manifest,
+    cipher: Cipher,
+    offset: u64,
+    length: u64,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let length = length.min(manifest.size.saturating_sub(offset));
+    if length == 0 {
+        return Ok(());
+    }
+    let first = cipher.block(offset);
+    let last = cipher.block(offset + length - 1);
+    let start = cipher.stored_offset(first);
+    let end = cipher.stored_offset(last + 1).min(manifest.stored_size());
+    // a range short of the end of the stored bytes stops before the last
+    // block, so it isn't required
+    let mut opener = cipher
+        .open(
+            Window {
+                inner: &mut *w,
+                skip: offset - first * cipher.block_size as u64,
+                remaining: length,
+            },
+            first,
+        )
+        .with_end(end == manifest.stored_size());
+    let result: Result<()> = async {
+        download_range(sdk, &mut opener, &manifest.slabs, start, end - start).await?;
+        opener.finish().await?;
+        Ok(())
+    }
+    .await;
+    match result {
+        Err(_) if opener.get_ref().remaining == 0 => {}
+        result => result?,
+    }
+    drop(opener);
+    w.flush().await?;
+    Ok(())
+}
+
+/// Passes through `remaining` bytes after discarding the first `skip`.
+struct Window<W> {
+    inner: W,
+    skip: u64,
+    remaining: u64,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Window<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.skip > 0 {
+            let n = this.skip.min(buf.len() as u64);
+            this.skip -= n;
+            return Poll::Ready(Ok(n as usize));
+        } else if this.remaining == 0 {
+            return Poll::Ready(Err(io::Error::other("range complete")));
+        }
+        let take = this.remaining.min(buf.len() as u64) as usize;
+        let n = std::task::ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..take]))?;
+        this.remaining -= n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Parses a single HTTP `bytes=` range against an object of `size` bytes
+/// into an offset and length. Returns `None` if the range is malformed or
+/// can't be satisfied.
+pub fn parse_http_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let last = size.checked_sub(1)?;
+    let (start, end) = match (start, end) {
+        ("", suffix) => (size.saturating_sub(suffix.parse().ok()?), last),
+        (start, "") => (start.parse().ok()?, last),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(last)),
+    };
+    if start > end {
+        return None;
+    }
+    Some((start, end - start + 1))
+}
+
+/// Returns the index of the first slab that is not fully covered by
+/// `written` bytes and the offset it starts at.
+pub fn resume_point(slabs: &[Slab], written: u64) -> (usize, u64) {
+    let mut offset = 0u64;
+    for (i, slab) in slabs.iter().enumerate() {
+        let end = offset + slab.length as u64;
+        if end > written {
+            return (i, offset);
+        }
+        offset = end;
+    }
+    (slabs.len(), offset)
+}
+
+/// Downloads an object one slab at a time, skipping the slabs already
+/// covered by the first `written` bytes of `w`.
+///
+/// A failed slab is retried from its start, so each attempt can be served
+/// by a different set of hosts holding its shards; the writer is rewound
+/// before every retry so no partial slab is left behind.
+pub async fn download_slabs<W>(
+    sdk: &Client,
+    w: &mut W,
+    slabs: &[Slab],
+    written: u64,
+    progress: &Progress,
+) -> Result<()>
+where
+    W: AsyncWrite + AsyncSeek + Unpin,
+{
+    let (first, mut offset) = resume_point(slabs, written);
+    if offset > 0 {
+        progress.emit(Event::BytesTransferred { bytes: offset });
+    }
+    w.seek(SeekFrom::Start(offset)).await?;
+
+    let retry = sdk.retry_policy();
+    for (i, slab) in slabs.iter().enumerate().skip(first) {
+        let mut attempt = 1;
+        let mut paused = Paused::default();
+        loop {
+            let result = sdk.download_once(w, std::slice::from_ref(slab)).await;
+            if retry
+                .pause_for_rate_limit(&format!("slab {i}"), &result, &mut paused, progress)
+                .await
+            {
+                w.seek(SeekFrom::Start(offset)).await?;
+                continue;
+            }
+            match result {
+                Ok(_) => break,
+                Err(e) if retry.should_retry(ErrorClass::Host, attempt, &e) => {
+                    warn!("slab {i} failed on attempt {attempt}: {e}");
+                    progress.emit(Event::Retrying {
+                        attempt,
+                        error: e.to_string(),
+                    });
+                    retry.backoff(ErrorClass::Host, attempt).await;
+                    w.seek(SeekFrom::Start(offset)).await?;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        offset += slab.length as u64;
+        progress.emit(Event::BytesTransferred {
+            bytes: slab.length as u64,
+        });
+    }
+    Ok(())
+}
+
+/// Downloads an object into the file at `path` as `parts` runs of slabs
+/// fetched concurrently, each through its own handle at its own offset.
+/// The file is sized up front, so the runs can complete in any order.
+pub async fn download_parts(
+    sdk: &Client,
+    path: &Path,
+    slabs: &[Slab],
+    parts: usize,
+    progress: &Progress,
+) -> Result<()> {
+    let size: u64 = slabs.iter().map(|slab| slab.length as u64).sum();
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .await?;
+    file.set_len(size).await?;
+    drop(file);
+
+    let per_part = slabs.len().div_ceil(parts.max(1)).max(1);
+    let mut runs = Vec::new();
+    let mut offset = 0u64;
+    for run in slabs.chunks(per_part) {
+        runs.push((offset, run));
+        offset += run.iter().map(|slab| slab.length as u64).sum::<u64>();
+    }
+    stream::iter(runs)
+        .map(|(base, run)| async move {
+            let file = OpenOptions::new().write(true).open(path).await?;
+            let file = PositionedFile::new(file.into_std().await);
+            let mut w = Shifted { inner: file, base };
+            download_slabs(sdk, &mut w, run, 0, progress).await?;
+            w.inner.flush().await?;
+            Ok(())
+        })
+        .buffer_unordered(parts.max(1))
+        .try_collect::<()>()
+        .await
+}
+
+/// Downloads a sparse object into the file at `path`, up to `parts` of its
+/// data runs at a time. The holes are never written, so the filesystem
+/// keeps them as holes rather than filling them with zeros.
+pub async fn download_sparse(
+    sdk: &Client,
+    path: &Path,
+    manifest: &Manifest,
+    parts: usize,
+    progress: &Progress,
+) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .await?;
+    file.set_len(manifest.size).await?;
+    drop(file);
+
+    let runs = sparse::runs(&manifest.holes, manifest.size, 0, manifest.size);
+    stream::iter(runs)
+        .map(|run| async move {
+            let Some(stored) = run.stored else {
+                progress.emit(Event::BytesTransferred { bytes: run.length });
+                return Ok(());
+            };
+            let slices = slice_slabs(&manifest.slabs, stored, run.length);
+            let file = OpenOptions::new().write(true).open(path).await?;
+            let file = PositionedFile::new(file.into_std().await);
+            let mut w = Shifted {
+                inner: file,
+                base: run.offset,
+            };
+            download_slabs(sdk, &mut w, &slices, 0, progress).await?;
+            w.inner.flush().await?;
+            Ok(())
+        })
+        .buffer_unordered(parts.max(1))
+        .try_collect::<()>()
+        .await
+}
+
+/// Offsets every seek by `base`, so a run of slabs can be written as if it
+/// started the file.
+struct Shifted<W> {
+    inner: W,
+    base: u64,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Shifted<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<W: AsyncSeek + Unpin> AsyncSeek for Shifted<W> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let position = match position {
+            SeekFrom::Start(offset) => SeekFrom::Start(this.base + offset),
+            position => position,
+        };
+        Pin::new(&mut this.inner).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        let position = std::task::ready!(Pin::new(&mut this.inner).poll_complete(cx))?;
+        Poll::Ready(Ok(position.saturating_sub(this.base)))
+    }
+}
+
+/// Downloads a single slab into memory, retrying failures with the client's
+/// retry policy. Returns the slab's data and the number of attempts it
+/// took, or the last error.
+pub async fn fetch_slab(sdk: &Client, slab: &Slab) -> (std::result::Result<Vec<u8>, Error>, u32) {
+    let retry = sdk.retry_policy();
+    let mut attempt = 1;
+    let mut paused = Paused::default();
+    loop {
+        let mut buf = Vec::with_capacity(slab.length as usize);
+        let result = sdk
+            .download_once(&mut buf, std::slice::from_ref(slab))
+            .await;
+        if retry
+            .pause_for_rate_limit("slab fetch", &result, &mut paused, sdk.progress())
+            .await
+        {
+            continue;
+        }
+        match result {
+            Ok(_) => return (Ok(buf), attempt),
+            Err(e) if retry.should_retry(ErrorClass::Host, attempt, &e) => {
+                warn!("slab fetch failed on attempt {attempt}: {e}");
+                retry.backoff(ErrorClass::Host, attempt).await;
+                attempt += 1;
+            }
+            Err(e) => return (Err(e), attempt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::aead;
+    use crate::compression::Compression;
+    use crate::mock::{self, MockBackend};
+    use crate::upload::{self, SECTOR_SIZE, UploadOptions};
+
+    async fn upload(sdk: &Client, data: &[u8], options: UploadOptions) -> Manifest {
+        let reader = Cursor::new(data.to_vec());
+        upload::upload_reader(sdk, reader, [1; 32], options, Progress::default())
+            .await
+            .unwrap()
+    }
+
+    async fn download(
+        sdk: &Client,
+        manifest: &Manifest,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        download_object(sdk, &mut data, manifest, offset, length).await?;
+        Ok(data)
+    }
+
+    #[test]
+    fn http_ranges() {
+        assert_eq!(parse_http_range("bytes=0-9", 100), Some((0, 10)));
+        assert_eq!(parse_http_range("bytes=-10", 100), Some((90, 10)));
+        assert_eq!(parse_http_range("bytes=95-", 100), Some((95, 5)));
+        assert_eq!(parse_http_range("bytes=50-200", 100), Some((50, 50)));
+        assert_eq!(parse_http_range("bytes=10-5", 100), None);
+        assert_eq!(parse_http_range("bytes=0-0", 0), None);
+        assert_eq!(parse_http_range("items=0-9", 100), None);
+        assert_eq!(parse_http_range("bytes=a-9", 100), None);
+    }
+
+    #[tokio::test]
+    async fn slices_and_resume_points() {
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let data = mock::pattern(3 * SECTOR_SIZE as usize);
+        let manifest = upload(&sdk, &data, UploadOptions::new(1, 1)).await;
+        let slabs = &manifest.slabs;
+
+        let slices = slice_slabs(slabs, SECTOR_SIZE - 10, 20);
+        assert_eq!(slices.len(), 2);
+        assert_eq!(
+            (slices[0].offset, slices[0].length),
+            (SECTOR_SIZE as u32 - 10, 10)
+        );
+        assert_eq!((slices[1].offset, slices[1].length), (0, 10));
+        assert!(slice_slabs(slabs, 3 * SECTOR_SIZE, 10).is_empty());
+
+        assert_eq!(resume_point(slabs, 0), (0, 0));
+        assert_eq!(resume_point(slabs, SECTOR_SIZE + 1), (1, SECTOR_SIZE));
+        assert_eq!(resume_point(slabs, 3 * SECTOR_SIZE), (3, 3 * SECTOR_SIZE));
+    }
+
+    #[tokio::test]
+    async fn ranges_of_every_encoding() {
+        let data = mock::pattern(2 * SECTOR_SIZE as usize + 1000);
+        let size = data.len() as u64;
+        for options in [
+            UploadOptions::new(1, 1),
+            UploadOptions::new(1, 1).with_compression(Some(Compression::zstd(1))),
+            UploadOptions::new(1, 1).with_aead(Some(aead::Algorithm::XChaCha20Poly1305)),
+        ] {
+            let sdk = Client::from_backend(MockBackend::new(2));
+            let manifest = upload(&sdk, &data, options).await;
+            for (offset, length) in [
+                (0, size),
+                (0, 1),
+                (SECTOR_SIZE - 5, 10),
+                (70_000, 200_000),
+                (size - 1, 1),
+                (size - 10, 100),
+                (size, 10),
+            ] {
+                let end = (offset + length).min(size) as usize;
+                let range = download(&sdk, &manifest, offset, length).await.unwrap();
+                assert_eq!(range, &data[offset as usize..end], "{offset}+{length}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_downloads_are_retried() {
+        let mock = Arc::new(MockBackend::new(2));
+        let sdk = Client::from_backend(mock.clone()).with_retry_policy(mock::retries(3));
+        let data = mock::pattern(2 * SECTOR_SIZE as usize);
+        let manifest = upload(&sdk, &data, UploadOptions::new(1, 1)).await;
+
+        mock.fail_next(2);
+        assert_eq!(
+            download(&sdk, &manifest, 0, manifest.size).await.unwrap(),
+            data
+        );
+
+        let sdk = Client::from_backend(mock.clone());
+        mock.fail_next(1);
+        let err = download(&sdk, &manifest, 0, manifest.size)
+            .await
+            .unwrap_err();
+        assert!(err.is_retryable(), "{err}");
+    }
+
+    #[tokio::test]
+    async fn lost_shards() {
+        let mock = Arc::new(MockBackend::new(3));
+        let sdk = Client::from_backend(mock.clone());
+        let data = mock::pattern(1000);
+        let manifest = upload(&sdk, &data, UploadOptions::new(1, 2)).await;
+        let slab = &manifest.slabs[0];
+
+        // any one shard is enough
+        mock.lose_shard(slab, 0);
+        mock.set_up(&slab.sectors[1].host_key.to_string(), false);
+        assert_eq!(download(&sdk, &manifest, 0, 1000).await.unwrap(), data);
+
+        mock.lose_shard(slab, 2);
+        assert!(download(&sdk, &manifest, 0, 1000).await.is_err());
+        mock.set_up(&slab.sectors[1].host_key.to_string(), true);
+        assert_eq!(download(&sdk, &manifest, 0, 1000).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn corruption_is_caught_in_sealed_objects() {
+        let mock = Arc::new(MockBackend::new(2));
+        let options = UploadOptions::new(1, 1).with_aead(Some(aead::Algorithm::XChaCha20Poly1305));
+        let data = mock::pattern(100_000);
+        let manifest = upload(&Client::from_backend(mock.clone()), &data, options).await;
+
+        let chaos = "corrupt=1".parse().unwrap();
+        let sdk = Client::from_backend(mock.clone()).with_chaos(Some(chaos));
+        match download(&sdk, &manifest, 0, manifest.size).await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            result => panic!("corrupt download returned {result:?}"),
+        }
+    }
+}
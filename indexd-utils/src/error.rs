@@ -0,0 +1,297 @@
+use std::error::Error as StdError;
+use std::io;
+
+use reqwest::StatusCode;
+use serde::Serialize;
+use thiserror::Error;
+use tokio::task::JoinError;
+
+use crate::shutdown;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("indexd: {0}")]
+    Indexd(indexd::Error),
+
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("http: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("catalog: {0}")]
+    Catalog(#[from] rusqlite::Error),
+
+    #[error("config: {0}")]
+    Config(String),
+
+    #[error("checkpoint: {0}")]
+    Checkpoint(String),
+
+    #[error("manifest: {0}")]
+    Manifest(String),
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("crypto: {0}")]
+    Crypto(String),
+
+    #[error("usage: {0}")]
+    Usage(String),
+
+    #[error("host: {0}")]
+    Host(String),
+
+    #[error("timed out: {0}")]
+    Timeout(String),
+
+    #[error("app unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+
+    #[error("the app was not approved in time, approve it at {0}")]
+    ApprovalTimedOut(String),
+
+    #[error("the app is not approved: {0}")]
+    ApprovalRequired(String),
+
+    #[error("insufficient funds: {0}")]
+    InsufficientFunds(String),
+
+    #[error("shard integrity: {0}")]
+    Shard(String),
+
+    #[error("placement: {0}")]
+    Placement(String),
+
+    #[error("budget: {0}")]
+    Budget(String),
+
+    #[error("integrity: {0}")]
+    Integrity(String),
+
+    #[error("daemon: {0}")]
+    Daemon(String),
+
+    #[error("keychain: {0}")]
+    Keychain(String),
+
+    #[error("interrupted: {0}")]
+    Interrupted(String),
+
+    #[error("partial failure: {0}")]
+    Partial(String),
+}
+
+/// What kind of failure an error is, so that scripts can tell a failure
+/// worth retrying later from one that needs fixing or data that is gone.
+/// Each kind exits the CLI with a status of its own, which stays the same
+/// across releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Anything not covered below, such as local I/O errors. Exits 1.
+    Failure,
+    /// Invalid arguments, or naming something that doesn't exist. Exits 2,
+    /// as argument parsing errors do.
+    Usage,
+    /// A configuration, keychain or budget problem to fix before trying
+    /// again. Exits 3.
+    Config,
+    /// The app isn't approved or its account can't pay. Exits 4.
+    Account,
+    /// The app or hosts failed or were unreachable; trying again later may
+    /// succeed. Exits 5.
+    Network,
+    /// Stored data is damaged or lost, or has less redundancy left than
+    /// required. Exits 6.
+    Integrity,
+    /// Some of the work succeeded and the rest can be run again. Exits 7.
+    Partial,
+    /// Stopped by a signal before finishing. Exits 130.
+    Interrupted,
+}
+
+impl Kind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Kind::Failure => 1,
+            Kind::Usage => 2,
+            Kind::Config => 3,
+            Kind::Account => 4,
+            Kind::Network => 5,
+            Kind::Integrity => 6,
+            Kind::Partial => 7,
+            Kind::Interrupted => shutdown::INTERRUPTED,
+        }
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Error::Usage(_) | Error::NotFound(_) => Kind::Usage,
+            Error::Config(_) | Error::Keychain(_) | Error::Budget(_) => Kind::Config,
+            Error::ApprovalRequired(_)
+            | Error::ApprovalTimedOut(_)
+            | Error::InsufficientFunds(_) => Kind::Account,
+            Error::Integrity(_) => Kind::Integrity,
+            Error::Partial(_) => Kind::Partial,
+            Error::Interrupted(_) => Kind::Interrupted,
+            Error::Http(_) => Kind::Network,
+            e if e.is_retryable() => Kind::Network,
+            _ => Kind::Failure,
+        }
+    }
+
+    /// Whether the operation that failed with this error may succeed if it
+    /// is attempted again: the app or hosts failing, being slow or rate
+    /// limiting, shards that failed their checks, which another attempt
+    /// fetches from other hosts. Uploads that landed on ruled out hosts
+    /// are retried by the client a bounded number of times, since every
+    /// attempt leaves its slabs stored, and not again here. Local I/O, configuration, approval and funding errors need
+    /// someone to act first, and SDK errors of unknown cause aren't
+    /// guessed at.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Error::Host(_)
+                | Error::Shard(_)
+                | Error::Timeout(_)
+                | Error::Unavailable(_)
+                | Error::RateLimited(_)
+        )
+    }
+}
+
+/// Sorts the SDK's errors into the variants callers act on by what caused
+/// them: an HTTP status of the app's API, a timeout or failure to connect,
+/// or a host's QUIC connection failing. The status is taken from the
+/// `reqwest` error behind the SDK's, or else from a message that states it
+/// outright, as `status 429` or `429 Too Many Requests` do. An error with no
+/// such cause stays [`Error::Indexd`], which isn't retried.
+impl From<indexd::Error> for Error {
+    fn from(e: indexd::Error) -> Self {
+        match classify(&e) {
+            Some(variant) => variant(e.to_string()),
+            None => Error::Indexd(e),
+        }
+    }
+}
+
+/// A task that panicked or was cancelled, such as a slab upload running on
+/// a task of its own.
+impl From<JoinError> for Error {
+    fn from(e: JoinError) -> Self {
+        Error::Io(io::Error::other(e))
+    }
+}
+
+/// Returns the variant the cause of `e` calls for, if it can be told.
+fn classify(e: &(dyn StdError + 'static)) -> Option<fn(String) -> Error> {
+    let causes = || std::iter::successors(Some(e), |e| e.source());
+    let status = causes().find_map(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status)
+            .or_else(|| stated_status(&cause.to_string()))
+    });
+    if let Some(status) = status {
+        return match status {
+            StatusCode::TOO_MANY_REQUESTS => Some(Error::RateLimited),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Some(Error::ApprovalRequired),
+            StatusCode::PAYMENT_REQUIRED => Some(Error::InsufficientFunds),
+            StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => Some(Error::Timeout),
+            status if status.is_server_error() => Some(Error::Unavailable),
+            _ => None,
+        };
+    }
+    for cause in causes() {
+        if let Some(http) = cause.downcast_ref::<reqwest::Error>() {
+            if http.is_timeout() {
+                return Some(Error::Timeout);
+            }
+            if http.is_connect() {
+                return Some(Error::Unavailable);
+            }
+        }
+        if cause.downcast_ref::<quinn::ConnectionError>().is_some()
+            || cause.downcast_ref::<quinn::ConnectError>().is_some()
+            || cause.downcast_ref::<quinn::WriteError>().is_some()
+            || cause.downcast_ref::<quinn::ReadError>().is_some()
+        {
+            return Some(Error::Host);
+        }
+        if let Some(io) = cause.downcast_ref::<io::Error>() {
+            match io.kind() {
+                io::ErrorKind::TimedOut => return Some(Error::Timeout),
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe => return Some(Error::Host),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// Returns the HTTP status `message` states outright: a code after
+/// `status`, `status code` or `HTTP`, or followed by its reason phrase, as in
+/// `429 Too Many Requests`. Numbers that are only part of the text, such as
+/// byte counts, ports or the digits of a hash, aren't taken for one.
+pub(crate) fn stated_status(message: &str) -> Option<StatusCode> {
+    let lower = message.to_ascii_lowercase();
+    let bytes = lower.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        let standalone = i - start == 3
+            && (start == 0 || !bytes[start - 1].is_ascii_alphanumeric())
+            && (i == bytes.len() || !bytes[i].is_ascii_alphanumeric());
+        if !standalone {
+            continue;
+        }
+        let Some(status) = lower[start..i]
+            .parse()
+            .ok()
+            .and_then(|code| StatusCode::from_u16(code).ok())
+        else {
+            continue;
+        };
+        let before = lower[..start].trim_end_matches([' ', ':', '=']);
+        let labelled = ["status", "status code", "http"].iter().any(|label| {
+            before.strip_suffix(label).is_some_and(|rest| {
+                !rest
+                    .as_bytes()
+                    .last()
+                    .is_some_and(u8::is_ascii_alphanumeric)
+            })
+        });
+        let reason = status.canonical_reason().is_some_and(|reason| {
+            lower[i..]
+                .trim_start()
+                .starts_with(&reason.to_ascii_lowercase())
+        });
+        if labelled || reason {
+            return Some(status);
+        }
+    }
+    None
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
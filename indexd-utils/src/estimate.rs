@@ -0,0 +1,73 @@
+use serde::Deserialize;
+
+use crate::redundancy::Redundancy;
+use crate::upload::SECTOR_SIZE;
+
+/// The resources an upload takes, before compression or deduplication,
+/// which can only reduce them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Estimate {
+    pub files: usize,
+    pub size: u64,
+    pub slabs: u64,
+    /// The bytes held by hosts, parity included. Every shard fills a whole
+    /// sector, so this is also what the upload sends.
+    pub stored: u64,
+    /// The bytes fetched to download everything again.
+    pub download: u64,
+}
+
+impl Estimate {
+    /// Estimates the upload of files with the given sizes. Files up to
+    /// `pack_threshold` bytes share slabs, as in a directory upload; pass 0
+    /// for a single file.
+    pub fn new(sizes: &[u64], redundancy: Redundancy, pack_threshold: u64) -> Self {
+        let slab_size = redundancy.data_shards as u64 * SECTOR_SIZE;
+        let (packed, alone): (Vec<u64>, Vec<u64>) =
+            sizes.iter().partition(|&&size| size <= pack_threshold);
+        let packed_size: u64 = packed.iter().sum();
+        let slabs = packed_size.div_ceil(slab_size)
+            + alone
+                .iter()
+                .map(|size| size.div_ceil(slab_size))
+                .sum::<u64>();
+        let size = packed_size + alone.iter().sum::<u64>();
+        Self {
+            files: sizes.len(),
+            size,
+            slabs,
+            stored: slabs * redundancy.total_shards() as u64 * SECTOR_SIZE,
+            download: size,
+        }
+    }
+
+    pub fn cost(&self, pricing: &Pricing) -> Cost {
+        let tb = |bytes: u64| bytes as f64 / 1e12;
+        Cost {
+            storage_per_month: tb(self.stored) * pricing.storage_per_tb_month,
+            upload: tb(self.stored) * pricing.upload_per_tb,
+            download: tb(self.download) * pricing.download_per_tb,
+        }
+    }
+}
+
+/// Host prices per TB, in whatever currency they are given in.
+///
+/// The SDK doesn't report host pricing, so these come from the profile.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pricing {
+    #[serde(default)]
+    pub storage_per_tb_month: f64,
+    #[serde(default)]
+    pub upload_per_tb: f64,
+    #[serde(default)]
+    pub download_per_tb: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Cost {
+    pub storage_per_month: f64,
+    pub upload: f64,
+    pub download: f64,
+}
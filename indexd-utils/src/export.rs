@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::catalog::Catalog;
+use crate::error::{Error, Result};
+use crate::keys::{self, Kdf};
+
+/// Marks a catalog archive and the version of its format.
+const MAGIC: &[u8] = b"indexd-catalog-v1\n";
+
+/// What follows the magic, on a line of its own, before the ciphertext.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    kdf: Kdf,
+    #[serde(with = "hex")]
+    nonce: [u8; 24],
+}
+
+/// What an export or import moved.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+/// Writes the whole catalog, with every manifest, version, snapshot,
+/// bucket reference and deduplicated chunk in it, to `path` as a single
+/// archive sealed under a key derived from `passphrase`.
+///
+/// The catalog holds the keys of every unsealed upload, so the archive is
+/// what makes the stored data recoverable on another machine; without the
+/// passphrase it reveals nothing but its size.
+pub async fn export(catalog: &Catalog, path: &Path, passphrase: &str) -> Result<Summary> {
+    let objects = catalog.list("")?.len() as u64;
+    let db = catalog.dump()?;
+    let compressed = zstd::encode_all(db.as_slice(), 3)?;
+
+    let kdf = Kdf::default();
+    let key = kdf.derive(passphrase).await?;
+    let nonce: [u8; 24] = rand::random();
+    let ciphertext = cipher(&key)
+        .encrypt(XNonce::from_slice(&nonce), compressed.as_slice())
+        .map_err(|_| Error::Crypto("failed to seal the catalog".into()))?;
+
+    let mut archive = MAGIC.to_vec();
+    serde_json::to_writer(&mut archive, &Header { kdf, nonce })?;
+    archive.push(b'\n');
+    archive.extend(ciphertext);
+    let bytes = archive.len() as u64;
+    let tmp = with_suffix(path, ".tmp");
+    fs::write(&tmp, archive).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(Summary { objects, bytes })
+}
+
+/// Opens the archive at `path` and makes it the catalog at `dest`. A
+/// catalog already there is kept as `<dest>.bak`.
+pub async fn import(path: &Path, dest: &Path, passphrase: &str) -> Result<Summary> {
+    let archive = fs::read(path).await?;
+    let rest = archive
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| Error::Usage(format!("{} is not a catalog archive", path.display())))?;
+    let newline = rest
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| Error::Manifest("the catalog archive is truncated".into()))?;
+    let header: Header = serde_json::from_slice(&rest[..newline])?;
+    let key = header.kdf.derive(passphrase).await?;
+    let compressed = cipher(&key)
+        .decrypt(XNonce::from_slice(&header.nonce), &rest[newline + 1..])
+        .map_err(|_| {
+            Error::Crypto("failed to open the catalog archive: wrong passphrase?".into())
+        })?;
+    let db = zstd::decode_all(compressed.as_slice())?;
+
+    if let Some(dir) = dest.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    let tmp = with_suffix(dest, ".import");
+    fs::write(&tmp, &db).await?;
+    // opening it checks that it is a catalog, and brings an older one up
+    // to date
+    let objects = match Catalog::open(&tmp).and_then(|catalog| catalog.list("")) {
+        Ok(entries) => entries.len() as u64,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+    };
+    if fs::try_exists(dest).await? {
+        fs::rename(dest, with_suffix(dest, ".bak")).await?;
+    }
+    fs::rename(&tmp, dest).await?;
+    Ok(Summary {
+        objects,
+        bytes: archive.len() as u64,
+    })
+}
+
+/// The archive key is kept separate from the other keys derived from the
+/// same passphrase.
+fn cipher(key: &[u8; 32]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(Key::from_slice(&keys::subkey(key, "catalog-archive")))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
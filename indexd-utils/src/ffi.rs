@@ -0,0 +1,286 @@
+//! A C interface to the upload and download pipeline, for applications
+//! that aren't written in Rust. Build it as a shared library with
+//!
+//! ```sh
+//! cargo rustc -p indexd-utils --release --lib --features ffi --crate-type cdylib
+//! ```
+//!
+//! which also writes its header to `include/indexd_utils.h`.
+//!
+//! Every function blocks until the transfer is done and reports how it
+//! went as an [`IndexdUtilsStatus`]; the message of the last failure on the
+//! calling thread is returned by [`indexd_utils_last_error`]. Transfers
+//! are catalogued like those of the command line tool, in the catalog the
+//! profile names, so either can download what the other uploaded.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::pin;
+use std::ptr;
+use std::sync::OnceLock;
+
+use tokio::fs;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::client;
+use crate::config::{Config, Settings};
+use crate::error::{Error, Kind, Result};
+use crate::progress::{Event, Progress};
+use crate::transfer;
+
+/// How a call went. The failures match the exit statuses of the command
+/// line tool.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexdUtilsStatus {
+    Ok = 0,
+    Failure = 1,
+    /// An argument was missing or invalid, or the object isn't catalogued.
+    Usage = 2,
+    Config = 3,
+    /// The app has to be approved or the account funded.
+    Account = 4,
+    /// The app or the hosts couldn't be reached; trying again may help.
+    Network = 5,
+    Integrity = 6,
+    Partial = 7,
+    Interrupted = 130,
+}
+
+impl From<Kind> for IndexdUtilsStatus {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Failure => Self::Failure,
+            Kind::Usage => Self::Usage,
+            Kind::Config => Self::Config,
+            Kind::Account => Self::Account,
+            Kind::Network => Self::Network,
+            Kind::Integrity => Self::Integrity,
+            Kind::Partial => Self::Partial,
+            Kind::Interrupted => Self::Interrupted,
+        }
+    }
+}
+
+/// What a transfer runs with. Every field may be null.
+#[repr(C)]
+pub struct IndexdUtilsOptions {
+    /// The config file; null for the default path.
+    pub config: *const c_char,
+    /// The profile in the config; null for its default profile.
+    pub profile: *const c_char,
+    /// Seals uploaded manifests under this passphrase, and opens sealed
+    /// ones on download; null to store them plain.
+    pub passphrase: *const c_char,
+}
+
+/// Called with the bytes transferred so far and the size of the object,
+/// on the thread that started the transfer, along with the `user_data`
+/// given to it.
+pub type IndexdUtilsProgress =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, transferred: u64, total: u64)>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// The runtime every transfer runs on, started by the first one.
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("starting the tokio runtime"))
+}
+
+/// Uploads the file at `input` and catalogues it as `name`, or as the
+/// file's name if `name` is null. `options` may be null to use the
+/// defaults, and `progress` null to not report progress.
+///
+/// # Safety
+///
+/// Every non-null pointer must point to a NUL-terminated string or, for
+/// `options`, to an `IndexdUtilsOptions`, valid for the whole call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn indexd_utils_upload(
+    options: *const IndexdUtilsOptions,
+    input: *const c_char,
+    name: *const c_char,
+    progress: IndexdUtilsProgress,
+    user_data: *mut c_void,
+) -> IndexdUtilsStatus {
+    call(|| {
+        let options = unsafe { Options::read(options) }?;
+        let input: PathBuf = unsafe { string(input) }?
+            .ok_or_else(|| Error::Usage("no input given".into()))?
+            .into();
+        let name = match unsafe { string(name) }? {
+            Some(name) => name,
+            None => input
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| Error::Usage("the input has no file name".into()))?,
+        };
+        runtime().block_on(upload(options, input, name, progress, user_data))
+    })
+}
+
+/// Downloads the file catalogued as `name` to `output`, which is only
+/// replaced once the whole file has been downloaded and checked. `options`
+/// and `progress` may be null as for `indexd_utils_upload`.
+///
+/// # Safety
+///
+/// As for `indexd_utils_upload`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn indexd_utils_download(
+    options: *const IndexdUtilsOptions,
+    name: *const c_char,
+    output: *const c_char,
+    progress: IndexdUtilsProgress,
+    user_data: *mut c_void,
+) -> IndexdUtilsStatus {
+    call(|| {
+        let options = unsafe { Options::read(options) }?;
+        let name = unsafe { string(name) }?.ok_or_else(|| Error::Usage("no name given".into()))?;
+        let output: PathBuf = unsafe { string(output) }?
+            .ok_or_else(|| Error::Usage("no output given".into()))?
+            .into();
+        runtime().block_on(download(options, name, output, progress, user_data))
+    })
+}
+
+/// Returns the message of the last failed call on this thread, or null if
+/// none failed. It stays valid until the next call on the thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn indexd_utils_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Runs a call, recording its error for `indexd_utils_last_error`. A panic
+/// is reported as a failure instead of unwinding into the caller.
+fn call(f: impl FnOnce() -> Result<()>) -> IndexdUtilsStatus {
+    let (status, message) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (IndexdUtilsStatus::Ok, None),
+        Ok(Err(e)) => (e.kind().into(), Some(e.to_string())),
+        Err(_) => (IndexdUtilsStatus::Failure, Some("internal error".into())),
+    };
+    LAST_ERROR.with(|last| {
+        *last.borrow_mut() = message.map(|m| CString::new(m.replace('\0', "")).expect("no NULs"));
+    });
+    status
+}
+
+/// Reads a nullable C string.
+unsafe fn string(s: *const c_char) -> Result<Option<String>> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    let s = unsafe { CStr::from_ptr(s) };
+    s.to_str()
+        .map(|s| Some(s.to_string()))
+        .map_err(|_| Error::Usage("strings must be UTF-8".into()))
+}
+
+/// `IndexdUtilsOptions`, copied out of the caller's memory.
+struct Options {
+    config: Option<PathBuf>,
+    profile: Option<String>,
+    passphrase: Option<String>,
+}
+
+impl Options {
+    unsafe fn read(options: *const IndexdUtilsOptions) -> Result<Self> {
+        let Some(options) = (unsafe { options.as_ref() }) else {
+            return Ok(Options {
+                config: None,
+                profile: None,
+                passphrase: None,
+            });
+        };
+        Ok(Options {
+            config: unsafe { string(options.config) }?.map(PathBuf::from),
+            profile: unsafe { string(options.profile) }?,
+            passphrase: unsafe { string(options.passphrase) }?,
+        })
+    }
+
+    async fn settings(&self) -> Result<Settings> {
+        Config::load(self.config.as_deref())
+            .await?
+            .settings(self.profile.as_deref(), None)
+    }
+}
+
+async fn upload(
+    options: Options,
+    input: PathBuf,
+    name: String,
+    callback: IndexdUtilsProgress,
+    user_data: *mut c_void,
+) -> Result<()> {
+    let settings = options.settings().await?;
+    let catalog = settings.catalog()?;
+    let total = fs::metadata(&input).await?.len();
+    let sdk = client::connect(&settings).await?;
+
+    let (progress, events) = Progress::channel();
+    let upload = transfer::upload_file(
+        &sdk,
+        &catalog,
+        settings.upload_options(),
+        &input,
+        &name,
+        options.passphrase.as_deref(),
+        progress,
+    );
+    report(callback, user_data, total, events, upload).await?;
+    Ok(())
+}
+
+async fn download(
+    options: Options,
+    name: String,
+    output: PathBuf,
+    callback: IndexdUtilsProgress,
+    user_data: *mut c_void,
+) -> Result<()> {
+    let settings = options.settings().await?;
+    let catalog = settings.catalog()?;
+    let manifest = transfer::open_file(&catalog, &name, options.passphrase.as_deref()).await?;
+    let sdk = client::connect(&settings).await?;
+
+    let (progress, events) = Progress::channel();
+    let download = transfer::download_file(&sdk, &manifest, &output, progress);
+    report(callback, user_data, manifest.size, events, download).await
+}
+
+/// Runs a transfer, passing the bytes it reports to `callback` as they
+/// come. The callback runs on the caller's thread since the transfer is
+/// driven from it.
+async fn report<T>(
+    callback: IndexdUtilsProgress,
+    user_data: *mut c_void,
+    total: u64,
+    mut events: UnboundedReceiver<Event>,
+    transfer: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let mut transfer = pin!(transfer);
+    let mut transferred = 0;
+    loop {
+        tokio::select! {
+            result = &mut transfer => return result,
+            Some(event) = events.recv() => {
+                if let (Event::BytesTransferred { bytes }, Some(callback)) = (event, callback) {
+                    transferred += bytes;
+                    unsafe { callback(user_data, transferred, total) };
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::mem::MaybeUninit;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use bytes::BytesMut;
+use tokio::io::{AsyncSeek, AsyncWrite};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::task::{self, JoinHandle};
+
+/// The most one io_uring read asks for, so a slab is read in chunks the
+/// kernel can work on side by side.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+const URING_CHUNK: usize = 1 << 20;
+
+/// The reads submitted to a ring at once; larger reads are made in batches.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+const URING_ENTRIES: usize = 64;
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+thread_local! {
+    /// Each blocking thread keeps one ring for all the reads it makes.
+    static RING: std::cell::RefCell<Option<io_uring::IoUring>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Reads up to `len` bytes at `offset` of `file` onto the end of `buf`,
+/// fewer only at the end of the file.
+///
+/// The read goes from the page cache straight into `buf` on a blocking
+/// thread, where a tokio `File` would read into a buffer of its own and
+/// copy that into `buf`, 2 MiB at a time. With the `io-uring` feature on
+/// Linux, the chunks of the read are submitted to io_uring together.
+pub async fn read_at(
+    file: Arc<File>,
+    offset: u64,
+    mut buf: BytesMut,
+    len: usize,
+) -> io::Result<BytesMut> {
+    task::spawn_blocking(move || {
+        buf.reserve(len);
+        let read = fill(&file, offset, &mut buf.spare_capacity_mut()[..len])?;
+        // SAFETY: fill initialized the first `read` bytes of the spare
+        // capacity
+        unsafe { buf.set_len(buf.len() + read) };
+        Ok(buf)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Reads into `buf` at `offset` until it is full or the file ends,
+/// returning the bytes read. Only the bytes read are initialized.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn fill(file: &File, offset: u64, buf: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+    use io_uring::IoUring;
+
+    let chunks: Vec<(usize, usize)> = (0..buf.len())
+        .step_by(URING_CHUNK)
+        .map(|start| (start, URING_CHUNK.min(buf.len() - start)))
+        .collect();
+    if chunks.is_empty() {
+        return Ok(0);
+    }
+    let mut read = vec![0; chunks.len()];
+    RING.with_borrow_mut(|ring| {
+        if ring.is_none() {
+            *ring = Some(IoUring::new(URING_ENTRIES as u32)?);
+        }
+        let result = submit(
+            ring.as_mut().expect("ring created"),
+            file,
+            offset,
+            buf,
+            &chunks,
+            &mut read,
+        );
+        // a ring left in an unknown state by an error isn't used again
+        if result.is_err() {
+            *ring = None;
+        }
+        result
+    })?;
+    // the chunks are whole up to the first short one, past which the rest
+    // is read in order
+    let mut done = 0;
+    for (&(_, len), &n) in chunks.iter().zip(&read) {
+        done += n;
+        if n < len {
+            return Ok(done + fill_from(file, offset + done as u64, &mut buf[done..])?);
+        }
+    }
+    Ok(done)
+}
+
+/// Reads each of `chunks` of `buf` through `ring`, recording the bytes
+/// each read in `read`.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn submit(
+    ring: &mut io_uring::IoUring,
+    file: &File,
+    offset: u64,
+    buf: &mut [MaybeUninit<u8>],
+    chunks: &[(usize, usize)],
+    read: &mut [usize],
+) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    use io_uring::{opcode, types};
+
+    let fd = types::Fd(file.as_raw_fd());
+    for (batch, batch_chunks) in chunks.chunks(URING_ENTRIES).enumerate() {
+        let first = batch * URING_ENTRIES;
+        for (i, &(start, len)) in batch_chunks.iter().enumerate() {
+            let entry = opcode::Read::new(fd, buf[start..].as_mut_ptr().cast(), len as u32)
+                .offset(offset + start as u64)
+                .build()
+                .user_data((first + i) as u64);
+            // SAFETY: `buf` outlives the reads, which are all waited on
+            // before the batch ends
+            unsafe { ring.submission().push(&entry) }
+                .map_err(|_| io::Error::other("the io_uring submission queue is full"))?;
+        }
+        ring.submit_and_wait(batch_chunks.len())?;
+        // the whole batch is taken off the queue before an error is
+        // returned, so none is left for the next read to find
+        let mut failed = None;
+        for completion in ring.completion() {
+            let result = completion.result();
+            if result < 0 {
+                failed = Some(io::Error::from_raw_os_error(-result));
+            } else {
+                read[completion.user_data() as usize] = result as usize;
+            }
+        }
+        if let Some(e) = failed {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+fn fill(file: &File, offset: u64, buf: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+    fill_from(file, offset, buf)
+}
+
+#[cfg(unix)]
+fn fill_from(file: &File, offset: u64, buf: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    let mut done = 0;
+    while done < buf.len() {
+        let rest = &mut buf[done..];
+        // SAFETY: pread writes at most `rest.len()` bytes into `rest` and
+        // never reads it
+        let n = unsafe {
+            libc::pread(
+                file.as_raw_fd(),
+                rest.as_mut_ptr().cast(),
+                rest.len(),
+                (offset + done as u64) as libc::off_t,
+            )
+        };
+        match n {
+            0 => break,
+            n if n > 0 => done += n as usize,
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    Ok(done)
+}
+
+#[cfg(windows)]
+fn fill_from(file: &File, offset: u64, buf: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+
+    // reads need initialized memory
+    buf.fill(MaybeUninit::new(0));
+    // SAFETY: every byte was just initialized
+    let buf = unsafe { &mut *(buf as *mut [MaybeUninit<u8>] as *mut [u8]) };
+    // each read names its offset, so reads of the same handle from other
+    // threads can't move it
+    let mut done = 0;
+    while done < buf.len() {
+        match file.seek_read(&mut buf[done..], offset + done as u64) {
+            Ok(0) => break,
+            Ok(n) => done += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(done)
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::write_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_write(file, buf, offset)
+}
+
+/// A file written with positioned writes, for downloads into local files.
+///
+/// On a multi-threaded runtime each write goes straight from the caller's
+/// buffer to the kernel, with the worker handing its other tasks off while
+/// it blocks, where a tokio `File` copies every write into a buffer of its
+/// own first. On a current-thread runtime, which can't block in place, the
+/// writes are copied and made on a blocking thread like a tokio `File`'s.
+/// Seeking only moves the position writes are made at, so the writes of
+/// one handle never wait on a seek.
+pub struct PositionedFile {
+    file: Arc<File>,
+    position: u64,
+    in_place: bool,
+    pending: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl PositionedFile {
+    pub fn new(file: File) -> Self {
+        Self {
+            file: Arc::new(file),
+            position: 0,
+            in_place: Handle::try_current()
+                .is_ok_and(|handle| handle.runtime_flavor() == RuntimeFlavor::MultiThread),
+            pending: None,
+        }
+    }
+
+    /// The file being written, to size or inspect.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Waits for the write made on a blocking thread, if there is one.
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(pending) = &mut self.pending {
+            let result = ready!(Pin::new(pending).poll(cx)).map_err(io::Error::other);
+            self.pending = None;
+            result??;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for PositionedFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if this.in_place {
+            let n = task::block_in_place(|| write_at(&this.file, buf, this.position))?;
+            this.position += n as u64;
+            return Poll::Ready(Ok(n));
+        }
+        let (file, data, offset) = (this.file.clone(), buf.to_vec(), this.position);
+        this.pending = Some(task::spawn_blocking(move || {
+            let mut done = 0;
+            while done < data.len() {
+                match write_at(&file, &data[done..], offset + done as u64) {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(n) => done += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }));
+        this.position += buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+impl AsyncSeek for PositionedFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => {
+                this.position = offset;
+                return Ok(());
+            }
+            SeekFrom::Current(offset) => (this.position, offset),
+            SeekFrom::End(offset) => (this.file.metadata()?.len(), offset),
+        };
+        this.position = base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the file",
+            )
+        })?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
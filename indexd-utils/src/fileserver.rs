@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::Router;
+use axum::extract::State;
+use axum::http::{HeaderMap, Method, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+
+use crate::catalog::{self, Catalog};
+use crate::client::Client;
+use crate::http::{Span, StatusError};
+use crate::manifest::{AnyManifest, Manifest, StoredManifest};
+
+/// The characters left unescaped in links.
+const HREF: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Serves the catalogued objects read-only over plain HTTP, so they can be
+/// fetched or streamed from a browser without a local copy.
+///
+/// `/<name>` is the catalogued object `name`, and the files of a directory
+/// upload are at `/<name>/<path>`. Paths ending in `/` list what is under
+/// them. Range requests only download the slabs covering the range, so
+/// media players can seek.
+pub struct FileServer {
+    sdk: Client,
+    catalog: Mutex<Catalog>,
+    /// Opens sealed manifests, if any are catalogued.
+    passphrase: Option<String>,
+    /// The `Authorization` header clients must send, if any.
+    authorization: Option<String>,
+    /// Opened manifests by name, with the time their object was updated.
+    opened: Mutex<HashMap<String, (u64, Arc<AnyManifest>)>>,
+}
+
+impl FileServer {
+    /// `credentials` is the user name and password required with HTTP
+    /// basic authentication, or `None` to allow anyone.
+    pub fn new(
+        sdk: Client,
+        catalog: Catalog,
+        passphrase: Option<String>,
+        credentials: Option<(String, String)>,
+    ) -> Self {
+        Self {
+            sdk,
+            catalog: Mutex::new(catalog),
+            passphrase,
+            authorization: credentials.map(|(user, password)| {
+                format!("Basic {}", BASE64.encode(format!("{user}:{password}")))
+            }),
+            opened: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new().fallback(handle).with_state(self)
+    }
+
+    /// Returns the catalogued object called `name` and its manifest, opening
+    /// it if it is sealed.
+    async fn lookup(&self, name: &str) -> Result<Option<(catalog::Entry, Arc<AnyManifest>)>> {
+        let (entry, stored) = {
+            let catalog = self.catalog.lock().unwrap();
+            let Some(entry) = catalog.list(name)?.into_iter().find(|e| e.name == name) else {
+                return Ok(None);
+            };
+            let cached = self
+                .opened
+                .lock()
+                .unwrap()
+                .get(name)
+                .filter(|(updated_at, _)| *updated_at == entry.updated_at)
+                .map(|(_, manifest)| manifest.clone());
+            if let Some(manifest) = cached {
+                return Ok(Some((entry, manifest)));
+            }
+            match catalog.get(name)? {
+                Some(stored) => (entry, stored),
+                None => return Ok(None),
+            }
+        };
+        let manifest = match stored {
+            StoredManifest::Plain(manifest) => manifest,
+            StoredManifest::Sealed(sealed) => {
+                let Some(passphrase) = &self.passphrase else {
+                    return Err(StatusError(StatusCode::FORBIDDEN));
+                };
+                sealed.open_with_passphrase(passphrase).await?
+            }
+        };
+        let manifest = Arc::new(manifest);
+        self.opened
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (entry.updated_at, manifest.clone()));
+        Ok(Some((entry, manifest)))
+    }
+}
+
+type Result<T> = std::result::Result<T, StatusError>;
+
+/// A file to send: a catalogued file upload or a file of a directory
+/// upload.
+struct File {
+    name: String,
+    manifest: Manifest,
+    /// Seconds since the Unix epoch.
+    modified: u64,
+}
+
+async fn handle(
+    State(server): State<Arc<FileServer>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(expected) = &server.authorization {
+        let given = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok());
+        if given != Some(expected.as_str()) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"indexd\"")],
+            )
+                .into_response();
+        }
+    }
+    let head = match method.as_str() {
+        "GET" => false,
+        "HEAD" => true,
+        _ => {
+            return (
+                StatusCode::METHOD_NOT_ALLOWED,
+                [(header::ALLOW, "GET, HEAD")],
+            )
+                .into_response();
+        }
+    };
+    let path = percent_decode_str(uri.path().trim_start_matches('/'))
+        .decode_utf8_lossy()
+        .into_owned();
+    let result = if path.is_empty() || path.ends_with('/') {
+        index(&server, &path).await
+    } else {
+        get(server.clone(), &path, &headers, head).await
+    };
+    result.unwrap_or_else(|StatusError(status)| status.into_response())
+}
+
+/// Sends the object or directory upload file at `path`, or redirects to
+/// the listing if `path` is a directory.
+async fn get(
+    server: Arc<FileServer>,
+    path: &str,
+    headers: &HeaderMap,
+    head: bool,
+) -> Result<Response> {
+    let file = match find(&server, path).await? {
+        Found::File(file) => file,
+        Found::Directory => return Ok(redirect(&format!("/{}/", encode_path(path)))),
+    };
+    let span = Span::parse(headers, file.manifest.size)
+        .map_err(|_| StatusError(StatusCode::RANGE_NOT_SATISFIABLE))?;
+    let response = span
+        .response()
+        .header(header::CONTENT_TYPE, content_type(&file.name))
+        .header("x-content-type-options", "nosniff")
+        .header(
+            header::ETAG,
+            format!("\"{}\"", hex::encode(file.manifest.checksum)),
+        )
+        .header(
+            header::LAST_MODIFIED,
+            httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(file.modified)),
+        );
+    Ok(span.send(&server.sdk, file.manifest, response, head, file.name))
+}
+
+/// What a path without a trailing `/` names.
+enum Found {
+    File(File),
+    Directory,
+}
+
+/// Finds the object called `path`, or the file of a directory upload whose
+/// name and path within it make up `path`.
+async fn find(server: &FileServer, path: &str) -> Result<Found> {
+    if let Some((entry, manifest)) = server.lookup(path).await? {
+        return Ok(match manifest.as_ref() {
+            AnyManifest::File(manifest) => Found::File(File {
+                name: entry.name,
+                manifest: manifest.clone(),
+                modified: entry.updated_at,
+            }),
+            AnyManifest::Directory(_) => Found::Directory,
+        });
+    }
+    // the longest catalogued name wins, as names may contain `/` too
+    for (i, _) in path.rmatch_indices('/') {
+        let (name, rest) = (&path[..i], &path[i + 1..]);
+        let Some((_, manifest)) = server.lookup(name).await? else {
+            continue;
+        };
+        let AnyManifest::Directory(dir) = manifest.as_ref() else {
+            continue;
+        };
+        if let Some(entry) = dir.files.iter().find(|f| f.path == rest) {
+            return Ok(Found::File(File {
+                name: path.to_string(),
+                manifest: entry.manifest.clone(),
+                modified: entry.modified.map_or(0, |nanos| nanos / 1_000_000_000),
+            }));
+        }
+        let inside = format!("{rest}/");
+        if dir.files.iter().any(|f| f.path.starts_with(&inside)) {
+            return Ok(Found::Directory);
+        }
+    }
+    Err(StatusError(StatusCode::NOT_FOUND))
+}
+
+/// Lists what is under `prefix`: a level of catalog names, or a level of
+/// the files of the directory upload `prefix` is in.
+async fn index(server: &FileServer, prefix: &str) -> Result<Response> {
+    let sizes: Vec<(String, u64)> = match directory_files(server, prefix).await? {
+        Some(files) => files,
+        None => server
+            .catalog
+            .lock()
+            .unwrap()
+            .list(prefix)?
+            .into_iter()
+            .map(|entry| (entry.name, entry.size))
+            .collect(),
+    };
+    if sizes.is_empty() && !prefix.is_empty() {
+        return Err(StatusError(StatusCode::NOT_FOUND));
+    }
+
+    // the immediate children, with the sizes of the files among them
+    let mut children = BTreeMap::new();
+    for (name, size) in &sizes {
+        let rest = &name[prefix.len()..];
+        match rest.find('/') {
+            Some(i) => {
+                children.entry(format!("{}/", &rest[..i])).or_insert(None);
+            }
+            None => {
+                children.insert(rest.to_string(), Some(*size));
+            }
+        }
+    }
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>/{0}</title></head>\n<body><h1>/{0}</h1><ul>\n",
+        escape(prefix)
+    );
+    if !prefix.is_empty() {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (child, size) in children {
+        let href = encode_path(&child);
+        let size = size
+            .map(|size| format!(" ({size} bytes)"))
+            .unwrap_or_default();
+        html.push_str(&format!(
+            "<li><a href=\"{href}\">{}</a>{size}</li>\n",
+            escape(&child)
+        ));
+    }
+    html.push_str("</ul></body></html>\n");
+    Ok(([(header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response())
+}
+
+/// Returns the full names and sizes of the files under `prefix` if it is a
+/// directory upload or a directory within one.
+async fn directory_files(server: &FileServer, prefix: &str) -> Result<Option<Vec<(String, u64)>>> {
+    // `prefix` ends in `/`, so every `/` in it ends a candidate name
+    for (i, _) in prefix.rmatch_indices('/').filter(|&(i, _)| i > 0) {
+        let name = &prefix[..i];
+        let Some((_, manifest)) = server.lookup(name).await? else {
+            continue;
+        };
+        let AnyManifest::Directory(dir) = manifest.as_ref() else {
+            continue;
+        };
+        let rest = &prefix[i + 1..];
+        let files = dir
+            .files
+            .iter()
+            .filter(|f| f.path.starts_with(rest))
+            .map(|f| (format!("{name}/{}", f.path), f.manifest.size))
+            .collect();
+        return Ok(Some(files));
+    }
+    Ok(None)
+}
+
+fn redirect(location: &str) -> Response {
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(header::LOCATION, location.to_string())],
+    )
+        .into_response()
+}
+
+/// Guesses the media type from the extension, so browsers play media and
+/// show images and documents instead of saving them. Pages and scripts are
+/// sent as plain text, since the objects are served from one origin.
+fn content_type(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "mov" => "video/quicktime",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "pdf" => "application/pdf",
+        "txt" | "md" | "log" | "csv" | "html" | "htm" | "js" | "svg" => "text/plain; charset=utf-8",
+        "json" => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|part| utf8_percent_encode(part, HREF).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::error::{Error, Result};
+
+/// Gitignore-style rules deciding which files under a directory are
+/// uploaded.
+///
+/// Rules are matched against paths relative to the directory, with later
+/// rules taking precedence, so `!pattern` re-includes files an earlier rule
+/// excluded. As with gitignore, nothing under an excluded directory can be
+/// re-included because the directory is never read.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    rules: Gitignore,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            rules: Gitignore::empty(),
+        }
+    }
+}
+
+impl Filter {
+    pub fn new<'a>(rules: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new("");
+        for rule in rules {
+            builder
+                .add_line(None, rule)
+                .map_err(|e| Error::Usage(format!("invalid filter rule {rule:?}: {e}")))?;
+        }
+        let rules = builder
+            .build()
+            .map_err(|e| Error::Usage(format!("invalid filter rules: {e}")))?;
+        Ok(Self { rules })
+    }
+
+    /// Returns whether the rules exclude `rel` itself.
+    pub fn excludes(&self, rel: &Path, is_dir: bool) -> bool {
+        self.rules.matched(rel, is_dir).is_ignore()
+    }
+
+    /// Returns whether the rules exclude the file at `rel`, either directly
+    /// or through one of its directories.
+    pub fn excludes_file(&self, rel: &Path) -> bool {
+        self.rules
+            .matched_path_or_any_parents(rel, false)
+            .is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter() -> Filter {
+        Filter::new(["*.tmp", "!keep.tmp", "build/", "/target", "docs/**/*.pdf"]).unwrap()
+    }
+
+    #[test]
+    fn default_excludes_nothing() {
+        let filter = Filter::default();
+        assert!(!filter.excludes_file(Path::new("a/b.tmp")));
+        assert!(!filter.excludes(Path::new("build"), true));
+    }
+
+    #[test]
+    fn later_rules_win() {
+        let filter = filter();
+        assert!(filter.excludes_file(Path::new("scratch.tmp")));
+        assert!(filter.excludes_file(Path::new("src/scratch.tmp")));
+        assert!(!filter.excludes_file(Path::new("keep.tmp")));
+        assert!(!filter.excludes_file(Path::new("src/keep.tmp")));
+    }
+
+    #[test]
+    fn directories() {
+        let filter = filter();
+        // `build/` only matches directories
+        assert!(filter.excludes(Path::new("build"), true));
+        assert!(!filter.excludes(Path::new("build"), false));
+        assert!(filter.excludes_file(Path::new("build/out.o")));
+        assert!(filter.excludes_file(Path::new("src/build/out.o")));
+        assert!(!filter.excludes_file(Path::new("src/build.rs")));
+    }
+
+    #[test]
+    fn anchored() {
+        let filter = filter();
+        assert!(filter.excludes_file(Path::new("target/debug/app")));
+        assert!(!filter.excludes_file(Path::new("src/target/app")));
+        assert!(filter.excludes_file(Path::new("docs/a/b/manual.pdf")));
+        assert!(!filter.excludes_file(Path::new("manual.pdf")));
+    }
+}
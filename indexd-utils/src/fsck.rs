@@ -0,0 +1,108 @@
+use std::collections::BTreeSet;
+
+use indexd::Slab;
+use serde::Serialize;
+
+use crate::catalog::{self, Catalog, Owner};
+use crate::error::Result;
+use crate::manifest::StoredManifest;
+
+/// What a consistency check of the catalog's slab references found.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    /// The objects, versions, snapshots and chunks checked.
+    pub checked: u64,
+    /// Sealed manifests that weren't opened, whose references are left as
+    /// they are.
+    pub unreadable: u64,
+    pub mismatches: Vec<Mismatch>,
+    /// References held for objects, versions, snapshots or chunks that are
+    /// gone.
+    pub dangling: u64,
+    /// Whether what was found was fixed.
+    pub repaired: bool,
+}
+
+impl Report {
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty() && self.dangling == 0
+    }
+}
+
+/// A holder of slabs whose recorded references don't match its manifest.
+#[derive(Debug, Clone, Serialize)]
+pub struct Mismatch {
+    pub owner: String,
+    /// The slabs the manifest refers to that weren't counted.
+    pub missing: u64,
+    /// The slabs counted that the manifest doesn't refer to.
+    pub extra: u64,
+}
+
+/// Checks the slab references the catalog counts against the manifests it
+/// holds, and with `repair` rewrites them to match. Sealed manifests are
+/// opened with `passphrase` if one is given.
+///
+/// References are counted as manifests are catalogued, so they only drift
+/// when a write was interrupted or the catalog predates them; catalogs from
+/// before slab references were counted start out with every owner missing
+/// its references.
+pub async fn fsck(catalog: &Catalog, passphrase: Option<&str>, repair: bool) -> Result<Report> {
+    let mut report = Report::default();
+    for (owner, stored) in catalog.owners()? {
+        report.checked += 1;
+        let manifest = match stored {
+            StoredManifest::Plain(manifest) => manifest,
+            StoredManifest::Sealed(sealed) => {
+                let opened = match passphrase {
+                    Some(passphrase) => sealed.open_with_passphrase(passphrase).await.ok(),
+                    None => None,
+                };
+                let Some(manifest) = opened else {
+                    report.unreadable += 1;
+                    continue;
+                };
+                manifest
+            }
+        };
+        let slabs = catalog::slabs(&manifest);
+        check(catalog, &owner, &slabs, repair, &mut report)?;
+    }
+    for (owner, slabs) in catalog.chunk_owners()? {
+        report.checked += 1;
+        let slabs: Vec<_> = slabs.iter().collect();
+        check(catalog, &owner, &slabs, repair, &mut report)?;
+    }
+    report.dangling = catalog.dangling_refs()?;
+    if repair {
+        catalog.drop_dangling_refs()?;
+        report.repaired = true;
+    }
+    Ok(report)
+}
+
+fn check(
+    catalog: &Catalog,
+    owner: &Owner,
+    slabs: &[&Slab],
+    repair: bool,
+    report: &mut Report,
+) -> Result<()> {
+    let expected = slabs
+        .iter()
+        .map(|slab| catalog::slab_key(slab))
+        .collect::<Result<BTreeSet<_>>>()?;
+    let recorded = catalog.refs(owner)?;
+    if expected == recorded {
+        return Ok(());
+    }
+    report.mismatches.push(Mismatch {
+        owner: owner.to_string(),
+        missing: expected.difference(&recorded).count() as u64,
+        extra: recorded.difference(&expected).count() as u64,
+    });
+    if repair {
+        catalog.set_refs(owner, slabs.iter().copied())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use indexd::Slab;
+use log::debug;
+use serde::Serialize;
+use tokio::fs;
+
+use crate::catalog::Catalog;
+use crate::checkpoint::Checkpoint;
+use crate::error::Result;
+use crate::manifest::{AnyManifest, StoredManifest};
+use crate::upload::SECTOR_SIZE;
+
+/// What a scan for orphaned slabs found.
+///
+/// The SDK can neither list what an app has stored nor release slabs, so
+/// orphans are found from what is left behind locally: an upload that
+/// crashed or was abandoned keeps the slabs it stored in its checkpoint,
+/// and those no manifest refers to are held by hosts for nothing.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    /// The distinct slabs the catalog and manifest files refer to.
+    pub referenced: u64,
+    /// Catalogued manifests that are sealed and weren't opened, so the
+    /// slabs they refer to may be counted as orphaned.
+    pub unreadable: u64,
+    pub orphans: Vec<Orphan>,
+    /// Checkpoints whose slabs are all referenced, left behind by uploads
+    /// that finished.
+    pub stale: Vec<PathBuf>,
+}
+
+/// An abandoned upload whose slabs no manifest refers to.
+#[derive(Debug, Clone, Serialize)]
+pub struct Orphan {
+    pub checkpoint: PathBuf,
+    pub input: PathBuf,
+    pub slabs: u64,
+    /// The bytes held by hosts for them, parity included.
+    pub stored: u64,
+}
+
+impl Report {
+    pub fn orphaned_slabs(&self) -> u64 {
+        self.orphans.iter().map(|o| o.slabs).sum()
+    }
+
+    pub fn orphaned_bytes(&self) -> u64 {
+        self.orphans.iter().map(|o| o.stored).sum()
+    }
+}
+
+/// Looks for checkpoints under `paths` and reports the slabs they hold that
+/// neither the catalog nor a manifest file under `paths` refers to. Sealed
+/// manifests are opened with `passphrase` if one is given.
+pub async fn scan(
+    catalog: &Catalog,
+    paths: &[PathBuf],
+    passphrase: Option<&str>,
+) -> Result<Report> {
+    let mut report = Report::default();
+    let mut referenced = HashSet::new();
+    let (manifests, chunks) = catalog.all_manifests()?;
+    for stored in manifests {
+        match open(stored, passphrase).await {
+            Some(manifest) => reference(&manifest, &mut referenced)?,
+            None => report.unreadable += 1,
+        }
+    }
+    for slab in &chunks {
+        referenced.insert(slab_id(slab)?);
+    }
+
+    let mut checkpoints = Vec::new();
+    for path in paths {
+        for file in walk(path).await? {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            if name.ends_with(".manifest.json") {
+                match StoredManifest::load(&file).await {
+                    Ok(stored) => match open(stored, passphrase).await {
+                        Some(manifest) => reference(&manifest, &mut referenced)?,
+                        None => report.unreadable += 1,
+                    },
+                    Err(e) => debug!("skipping {}: {e}", file.display()),
+                }
+            } else if is_checkpoint(&file) {
+                match Checkpoint::load(&file).await {
+                    Ok(checkpoint) => checkpoints.push((file, checkpoint)),
+                    Err(e) => debug!("skipping {}: {e}", file.display()),
+                }
+            }
+        }
+    }
+    report.referenced = referenced.len() as u64;
+
+    // checkpoints of the same upload may share slabs; each is counted once
+    let mut seen = HashSet::new();
+    for (path, checkpoint) in checkpoints {
+        let mut orphan = Orphan {
+            checkpoint: path,
+            input: checkpoint.input,
+            slabs: 0,
+            stored: 0,
+        };
+        for slab in &checkpoint.slabs {
+            let id = slab_id(slab)?;
+            if !referenced.contains(&id) && seen.insert(id) {
+                orphan.slabs += 1;
+                orphan.stored += slab.sectors.len() as u64 * SECTOR_SIZE;
+            }
+        }
+        if orphan.slabs > 0 {
+            report.orphans.push(orphan);
+        } else if !checkpoint.slabs.is_empty() {
+            report.stale.push(orphan.checkpoint);
+        }
+    }
+    Ok(report)
+}
+
+async fn open(stored: StoredManifest, passphrase: Option<&str>) -> Option<AnyManifest> {
+    match stored {
+        StoredManifest::Plain(manifest) => Some(manifest),
+        StoredManifest::Sealed(sealed) => sealed.open_with_passphrase(passphrase?).await.ok(),
+    }
+}
+
+fn reference(manifest: &AnyManifest, referenced: &mut HashSet<String>) -> Result<()> {
+    let slabs: Vec<&Slab> = match manifest {
+        AnyManifest::File(manifest) => manifest.slabs.iter().collect(),
+        AnyManifest::Directory(manifest) => manifest
+            .files
+            .iter()
+            .flat_map(|f| &f.manifest.slabs)
+            .collect(),
+    };
+    for slab in slabs {
+        referenced.insert(slab_id(slab)?);
+    }
+    Ok(())
+}
+
+/// Slices of a slab keep its sectors, which identify it.
+fn slab_id(slab: &Slab) -> Result<String> {
+    Ok(serde_json::to_string(&slab.sectors)?)
+}
+
+/// Single file uploads checkpoint to `<input>.checkpoint`; directory
+/// uploads and the daemon keep one JSON file per upload in a directory.
+fn is_checkpoint(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if name.ends_with(".checkpoint") {
+        return true;
+    }
+    name.ends_with(".json")
+        && path
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .is_some_and(|dir| {
+                let dir = dir.to_string_lossy();
+                dir.ends_with(".checkpoints") || dir == "upload-rs-daemon"
+            })
+}
+
+/// Lists the files under `root`, or `root` itself if it is a file, without
+/// following symlinks.
+async fn walk(root: &Path) -> Result<Vec<PathBuf>> {
+    if !fs::symlink_metadata(root).await?.is_dir() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let kind = entry.file_type().await?;
+            if kind.is_dir() {
+                dirs.push(entry.path());
+            } else if kind.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::client::Client;
+    use crate::keys::Kdf;
+    use crate::manifest::{Manifest, Sealing};
+    use crate::mock::{self, MockBackend};
+    use crate::upload::{ResumableUpload, UploadOptions};
+
+    /// Uploads a file of two slabs, leaving its checkpoint behind as a
+    /// crashed upload would.
+    async fn upload(dir: &Path) -> (PathBuf, Manifest) {
+        let input = dir.join("input");
+        let checkpoint = dir.join("input.checkpoint");
+        fs::write(&input, mock::pattern(2 * SECTOR_SIZE as usize))
+            .await
+            .unwrap();
+        let sdk = Client::from_backend(Arc::new(MockBackend::new(2)));
+        let upload =
+            ResumableUpload::new(&input, &checkpoint, [1; 32], None, UploadOptions::new(1, 1))
+                .await
+                .unwrap();
+        let manifest = upload.run(&sdk).await.unwrap();
+        (checkpoint, manifest)
+    }
+
+    fn put(catalog: &Catalog, manifest: &Manifest, sealing: Option<&Sealing>) {
+        let manifest = AnyManifest::File(manifest.clone());
+        let stored = StoredManifest::new(manifest.clone(), sealing).unwrap();
+        catalog.put("input", None, &manifest, &stored).unwrap();
+    }
+
+    #[tokio::test]
+    async fn finds_orphans_and_stale_checkpoints() {
+        let dir = mock::temp_dir();
+        let catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let (checkpoint, manifest) = upload(&dir).await;
+        let paths = [dir.clone()];
+
+        // nothing refers to the uploaded slabs yet
+        let report = scan(&catalog, &paths, None).await.unwrap();
+        assert_eq!(report.referenced, 0);
+        assert_eq!(report.orphans.len(), 1);
+        assert_eq!(report.orphans[0].checkpoint, checkpoint);
+        assert_eq!(report.orphaned_slabs(), 2);
+        assert_eq!(report.orphaned_bytes(), 2 * 2 * SECTOR_SIZE);
+
+        // a manifest file refers to them
+        manifest
+            .save(dir.join("input.manifest.json"))
+            .await
+            .unwrap();
+        let report = scan(&catalog, &paths, None).await.unwrap();
+        assert_eq!(report.referenced, 2);
+        assert!(report.orphans.is_empty());
+        assert_eq!(report.stale, [checkpoint.clone()]);
+        fs::remove_file(dir.join("input.manifest.json"))
+            .await
+            .unwrap();
+
+        // and so does the catalog
+        put(&catalog, &manifest, None);
+        let report = scan(&catalog, &paths, None).await.unwrap();
+        assert!(report.orphans.is_empty());
+        assert_eq!(report.stale, [checkpoint]);
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sealed_manifests_need_the_passphrase() {
+        let dir = mock::temp_dir();
+        let catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let (_, manifest) = upload(&dir).await;
+        let kdf = Kdf {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+            ..Kdf::default()
+        };
+        let key = kdf.derive("passphrase").await.unwrap();
+        put(&catalog, &manifest, Some(&Sealing::new(kdf, key)));
+        let paths = [dir.clone()];
+
+        // without it the slabs look orphaned
+        let report = scan(&catalog, &paths, None).await.unwrap();
+        assert_eq!(report.unreadable, 1);
+        assert_eq!(report.orphaned_slabs(), 2);
+
+        let report = scan(&catalog, &paths, Some("passphrase")).await.unwrap();
+        assert_eq!(report.unreadable, 0);
+        assert!(report.orphans.is_empty());
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use log::info;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_util::io::StreamReader;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::catalog::Entry;
+use crate::daemon::{Daemon, UploadRequest};
+use crate::error::{Error, Result};
+use crate::progress::{Event, Progress};
+use crate::uploader::{Job, JobState};
+
+/// The types generated from `proto/transfers.proto`.
+pub mod proto {
+    tonic::include_proto!("indexd.v1");
+}
+
+use proto::transfers_server::{Transfers, TransfersServer};
+use proto::{upload_request, upload_response};
+
+/// The size of the data messages of a download.
+const CHUNK_SIZE: usize = 256 << 10;
+
+/// Binds `addr` and returns the server of the daemon's API over gRPC on
+/// it, which runs until it fails. Binding is done up front so a daemon
+/// that can't serve gRPC fails to start rather than running without it.
+///
+/// As with the HTTP API, every call must carry the daemon's token, as
+/// `authorization: Bearer <token>` metadata, and without a token only a
+/// loopback address is served.
+pub async fn bind(
+    daemon: Arc<Daemon>,
+    addr: SocketAddr,
+) -> Result<impl Future<Output = Result<()>> + Send + 'static> {
+    daemon.check_listen(addr)?;
+    let listener = TcpListener::bind(addr).await?;
+    info!("serving gRPC on {}", listener.local_addr()?);
+    let guard = daemon.clone();
+    let service =
+        TransfersServer::with_interceptor(Service { daemon }, move |request: Request<()>| {
+            let authorization = request
+                .metadata()
+                .get("authorization")
+                .and_then(|v| v.to_str().ok());
+            if guard.authorized(authorization) {
+                Ok(request)
+            } else {
+                Err(Status::unauthenticated("missing or wrong token"))
+            }
+        });
+    Ok(async move {
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+            .map_err(|e| Error::Io(io::Error::other(e)))
+    })
+}
+
+struct Service {
+    daemon: Arc<Daemon>,
+}
+
+#[tonic::async_trait]
+impl Transfers for Service {
+    async fn enqueue(
+        &self,
+        request: Request<proto::EnqueueRequest>,
+    ) -> std::result::Result<Response<proto::Job>, Status> {
+        let request = request.into_inner();
+        let job = self
+            .daemon
+            .enqueue(UploadRequest {
+                path: request.path.into(),
+                name: request.name,
+            })
+            .await
+            .map_err(status)?;
+        Ok(Response::new(job.into()))
+    }
+
+    async fn get_job(
+        &self,
+        request: Request<proto::GetJobRequest>,
+    ) -> std::result::Result<Response<proto::Job>, Status> {
+        let id = request.into_inner().id;
+        match self.daemon.job(id) {
+            Some(job) => Ok(Response::new(job.into())),
+            None => Err(Status::not_found(format!("upload {id}"))),
+        }
+    }
+
+    type WatchJobStream = ReceiverStream<std::result::Result<proto::Job, Status>>;
+
+    async fn watch_job(
+        &self,
+        request: Request<proto::GetJobRequest>,
+    ) -> std::result::Result<Response<Self::WatchJobStream>, Status> {
+        let id = request.into_inner().id;
+        if self.daemon.job(id).is_none() {
+            return Err(Status::not_found(format!("upload {id}")));
+        }
+        let mut events = self.daemon.uploader().subscribe();
+        let (tx, rx) = mpsc::channel(16);
+        let daemon = self.daemon.clone();
+        tokio::spawn(async move {
+            while let Some(job) = daemon.job(id) {
+                let finished = matches!(
+                    job.state,
+                    JobState::Done | JobState::Failed | JobState::Cancelled
+                );
+                if tx.send(Ok(job.into())).await.is_err() || finished {
+                    return;
+                }
+                // wait for the next event of this job; a lagging receiver
+                // just sends the latest state
+                loop {
+                    match events.recv().await {
+                        Ok(event) if event.id != id => continue,
+                        Ok(_) | Err(RecvError::Lagged(_)) => break,
+                        Err(RecvError::Closed) => return,
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn pause_job(
+        &self,
+        request: Request<proto::GetJobRequest>,
+    ) -> std::result::Result<Response<proto::Job>, Status> {
+        let id = request.into_inner().id;
+        let job = self.daemon.uploader().pause(id).map_err(status)?;
+        Ok(Response::new(job.into()))
+    }
+
+    async fn resume_job(
+        &self,
+        request: Request<proto::GetJobRequest>,
+    ) -> std::result::Result<Response<proto::Job>, Status> {
+        let id = request.into_inner().id;
+        let job = self.daemon.uploader().resume(id).map_err(status)?;
+        Ok(Response::new(job.into()))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<proto::GetJobRequest>,
+    ) -> std::result::Result<Response<proto::Job>, Status> {
+        let id = request.into_inner().id;
+        let job = self.daemon.uploader().cancel(id).await.map_err(status)?;
+        Ok(Response::new(job.into()))
+    }
+
+    async fn list_objects(
+        &self,
+        request: Request<proto::ListObjectsRequest>,
+    ) -> std::result::Result<Response<proto::ListObjectsResponse>, Status> {
+        let prefix = request.into_inner().prefix;
+        let objects = self.daemon.objects(&prefix).map_err(status)?;
+        Ok(Response::new(proto::ListObjectsResponse {
+            objects: objects.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    type UploadStream = ReceiverStream<std::result::Result<proto::UploadResponse, Status>>;
+
+    async fn upload(
+        &self,
+        request: Request<Streaming<proto::UploadRequest>>,
+    ) -> std::result::Result<Response<Self::UploadStream>, Status> {
+        let mut messages = request.into_inner();
+        let name = match messages.message().await? {
+            Some(proto::UploadRequest {
+                message: Some(upload_request::Message::Name(name)),
+            }) => name,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "the first message must name the object",
+                ));
+            }
+        };
+        // the size isn't known up front, so only a spent budget stops it
+        self.daemon.check(&name, 0).map_err(status)?;
+
+        let data = messages.map(|message| match message {
+            Ok(proto::UploadRequest {
+                message: Some(upload_request::Message::Data(data)),
+            }) => Ok(Bytes::from(data)),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected a data message",
+            )),
+            Err(status) => Err(io::Error::other(status)),
+        });
+        let reader = StreamReader::new(data);
+
+        let (tx, rx) = mpsc::channel(16);
+        let daemon = self.daemon.clone();
+        tokio::spawn(async move {
+            let (progress, mut events) = Progress::channel();
+            let upload = daemon.store(&name, None, reader, progress);
+            let forward = async {
+                let mut read = 0;
+                while let Some(event) = events.recv().await {
+                    if let Event::BytesTransferred { bytes } = event {
+                        read += bytes;
+                        let message = upload_response::Message::Progress(read);
+                        let _ = tx
+                            .send(Ok(proto::UploadResponse {
+                                message: Some(message),
+                            }))
+                            .await;
+                    }
+                }
+            };
+            let (result, ()) = tokio::join!(upload, forward);
+            let response = result.map_err(status).map(|manifest| {
+                let done = proto::Uploaded {
+                    name,
+                    size: manifest.size,
+                };
+                proto::UploadResponse {
+                    message: Some(upload_response::Message::Done(done)),
+                }
+            });
+            let _ = tx.send(response).await;
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type DownloadStream = ReceiverStream<std::result::Result<proto::DownloadResponse, Status>>;
+
+    async fn download(
+        &self,
+        request: Request<proto::DownloadRequest>,
+    ) -> std::result::Result<Response<Self::DownloadStream>, Status> {
+        let name = request.into_inner().name;
+        let manifest = self.daemon.manifest(&name).map_err(status)?;
+        let size = manifest.size;
+
+        let (tx, rx) = mpsc::channel(16);
+        let daemon = self.daemon.clone();
+        tokio::spawn(async move {
+            let (writer, mut reader) = tokio::io::duplex(CHUNK_SIZE);
+            let download = async move {
+                let mut writer = writer;
+                daemon.download(&name, &manifest, &mut writer).await
+            };
+            let tx = &tx;
+            // dropping the reader once the client is gone ends the download
+            let forward = async move {
+                let mut buf = vec![0; CHUNK_SIZE];
+                let mut offset = 0;
+                loop {
+                    let n = match reader.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => n,
+                    };
+                    let response = proto::DownloadResponse {
+                        data: buf[..n].to_vec(),
+                        offset,
+                        size,
+                    };
+                    if tx.send(Ok(response)).await.is_err() {
+                        return;
+                    }
+                    offset += n as u64;
+                }
+            };
+            let (result, ()) = tokio::join!(download, forward);
+            if let Err(e) = result {
+                let _ = tx.send(Err(status(e))).await;
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+fn status(e: Error) -> Status {
+    match e {
+        Error::NotFound(message) => Status::not_found(message),
+        Error::Usage(message) => Status::invalid_argument(message),
+        Error::Budget(message) | Error::InsufficientFunds(message) => {
+            Status::resource_exhausted(message)
+        }
+        Error::ApprovalRequired(message) => Status::permission_denied(message),
+        e if e.is_retryable() => Status::unavailable(e.to_string()),
+        e => Status::internal(e.to_string()),
+    }
+}
+
+impl From<Job> for proto::Job {
+    fn from(job: Job) -> Self {
+        let state = match job.state {
+            JobState::Queued => proto::JobState::Queued,
+            JobState::Running => proto::JobState::Running,
+            JobState::Paused => proto::JobState::Paused,
+            JobState::Done => proto::JobState::Done,
+            JobState::Failed => proto::JobState::Failed,
+            JobState::Cancelled => proto::JobState::Cancelled,
+        };
+        Self {
+            id: job.id,
+            path: job.path.display().to_string(),
+            name: job.name,
+            state: state.into(),
+            size: job.size,
+            uploaded: job.uploaded,
+            error: job.error,
+        }
+    }
+}
+
+impl From<Entry> for proto::Object {
+    fn from(entry: Entry) -> Self {
+        Self {
+            name: entry.name,
+            size: entry.size,
+            files: entry.files,
+            sealed: entry.sealed,
+            source: entry.source,
+            created_at: entry.created_at,
+        }
+    }
+}
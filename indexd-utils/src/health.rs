@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::hosts::MIN_TRANSFERS;
+use crate::manifest::Manifest;
+use crate::telemetry::HostStats;
+use crate::verify::{self, Health};
+
+/// The failure rate above which a host's shards are counted as lost.
+const FAILING: f64 = 0.5;
+
+/// The fewest healthy shards every slab of an object should keep: a number
+/// such as `12`, or `data+3` for three more than the object's data shards,
+/// which adapts to each object's redundancy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawThreshold")]
+pub enum Threshold {
+    Shards(usize),
+    AboveData(usize),
+}
+
+impl Threshold {
+    /// Returns the healthy shards required of an object with
+    /// `data_shards` data shards per slab.
+    pub fn shards(self, data_shards: u8) -> usize {
+        match self {
+            Threshold::Shards(n) => n,
+            Threshold::AboveData(n) => data_shards as usize + n,
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            Error::Usage(format!(
+                "invalid health threshold {s:?}; expected a number of shards or data+N"
+            ))
+        };
+        match s.trim().strip_prefix("data") {
+            Some("") => Ok(Threshold::AboveData(0)),
+            Some(rest) => {
+                let n = rest.trim().strip_prefix('+').ok_or_else(invalid)?;
+                Ok(Threshold::AboveData(
+                    n.trim().parse().map_err(|_| invalid())?,
+                ))
+            }
+            None => Ok(Threshold::Shards(s.trim().parse().map_err(|_| invalid())?)),
+        }
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Threshold::Shards(n) => write!(f, "{n}"),
+            Threshold::AboveData(n) => write!(f, "data+{n}"),
+        }
+    }
+}
+
+/// A threshold as written in the config, a number or a string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawThreshold {
+    Shards(usize),
+    Spec(String),
+}
+
+impl TryFrom<RawThreshold> for Threshold {
+    type Error = String;
+
+    fn try_from(raw: RawThreshold) -> std::result::Result<Self, String> {
+        match raw {
+            RawThreshold::Shards(n) => Ok(Threshold::Shards(n)),
+            RawThreshold::Spec(s) => s.parse().map_err(|e: Error| e.to_string()),
+        }
+    }
+}
+
+/// The thresholds of a profile: one for every object, and others for the
+/// objects under given name prefixes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Thresholds {
+    pub default: Option<Threshold>,
+    pub by_prefix: BTreeMap<String, Threshold>,
+}
+
+impl Thresholds {
+    /// Returns the threshold of the object called `name`: that of the
+    /// longest prefix it starts with, falling back to the default.
+    pub fn get(&self, name: &str) -> Option<Threshold> {
+        self.by_prefix
+            .iter()
+            .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, threshold)| *threshold)
+            .or(self.default)
+    }
+}
+
+/// How a verified object measures up to its threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Assessment {
+    /// The healthy shards of the object's weakest slab.
+    pub min_healthy_shards: usize,
+    /// The shards its threshold requires, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required_shards: Option<usize>,
+    /// Whether some slab has fewer healthy shards than required.
+    pub breached: bool,
+}
+
+/// Estimates how many healthy shards the weakest slab of a verified file
+/// keeps and checks it against `threshold`.
+///
+/// The SDK fetches a slab's shards together and doesn't say which hosts it
+/// reached, so shards are counted from what is known: a shard on a host
+/// the recorded stats show failing over half its transfers counts as lost,
+/// a slab that needed retrying lost at least one shard and an
+/// unrecoverable one has none to spare.
+pub fn assess(
+    manifest: &Manifest,
+    report: &verify::Report,
+    hosts: &BTreeMap<String, HostStats>,
+    threshold: Option<Threshold>,
+) -> Assessment {
+    let min_healthy_shards = manifest
+        .slabs
+        .iter()
+        .zip(&report.slabs)
+        .map(|(slab, verified)| {
+            let total = slab.sectors.len();
+            let failing = slab
+                .sectors
+                .iter()
+                .filter(|sector| {
+                    hosts
+                        .get(&sector.host_key.to_string())
+                        .is_some_and(|stats| {
+                            stats.transfers >= MIN_TRANSFERS && stats.failure_rate() > FAILING
+                        })
+                })
+                .count();
+            match verified.health {
+                Health::Healthy => total - failing,
+                Health::Degraded => total - failing.max(1),
+                Health::Unrecoverable => 0,
+            }
+        })
+        .min()
+        .unwrap_or(0);
+    let required_shards = threshold.map(|t| t.shards(manifest.data_shards));
+    Assessment {
+        min_healthy_shards,
+        required_shards,
+        breached: !manifest.slabs.is_empty()
+            && required_shards.is_some_and(|required| min_healthy_shards < required),
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use indexd::Slab;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::telemetry::HostStats;
+
+/// The transfers a host has to have taken part in before its failure rate
+/// is trusted.
+pub(crate) const MIN_TRANSFERS: u64 = 20;
+
+/// Which hosts an object's shards may be stored on, identified by their
+/// public keys (`ed25519:...`).
+///
+/// The SDK chooses the hosts for every upload itself, so the policy can
+/// only be checked once each slab has landed. Misplaced uploads are kept
+/// and logged unless the client is set to reject them, with
+/// [`Client::with_placement_attempts`]; each rejection leaves its slabs
+/// stored. The policy is recorded in the manifest so that repair can move
+/// slabs off hosts it no longer permits.
+///
+/// [`Client::with_placement_attempts`]: crate::client::Client::with_placement_attempts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostPolicy {
+    /// If not empty, the only hosts shards may be stored on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// Hosts shards may never be stored on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub block: Vec<String>,
+    /// How widely each slab's shards have to be spread.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spread: Option<Spread>,
+}
+
+/// Keeps a slab's shards from piling up with one operator or in one
+/// region.
+///
+/// The SDK only identifies hosts by their keys, so it can't tell which
+/// share a subnet, operator or region. `groups` supplies that by naming the
+/// group of each host; a host not listed is a group of its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Spread {
+    /// The fewest distinct groups each slab's shards have to be on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_groups: Option<usize>,
+    /// The most shards of a slab any one group may hold.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_shards_per_group: Option<usize>,
+    /// The group of each host, by public key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, String>,
+}
+
+impl Spread {
+    fn group<'a>(&'a self, host_key: &'a str) -> &'a str {
+        self.groups.get(host_key).map_or(host_key, String::as_str)
+    }
+
+    /// Returns the number of the slab's shards short of `min_groups` or
+    /// over `max_shards_per_group`.
+    fn violations(&self, slab: &Slab) -> usize {
+        let keys: Vec<String> = slab
+            .sectors
+            .iter()
+            .map(|sector| sector.host_key.to_string())
+            .collect();
+        let mut shards = BTreeMap::<&str, usize>::new();
+        for key in &keys {
+            *shards.entry(self.group(key)).or_default() += 1;
+        }
+        let short = self
+            .min_groups
+            .map_or(0, |min| min.saturating_sub(shards.len()));
+        let over: usize = self.max_shards_per_group.map_or(0, |max| {
+            shards.values().map(|n| n.saturating_sub(max)).sum()
+        });
+        short + over
+    }
+}
+
+impl HostPolicy {
+    /// Builds a policy, returning `None` if it permits every host.
+    pub fn new(
+        allow: Vec<String>,
+        block: Vec<String>,
+        spread: Option<Spread>,
+    ) -> Result<Option<Self>> {
+        let grouped = spread.iter().flat_map(|spread| spread.groups.keys());
+        for host in allow.iter().chain(&block).chain(grouped) {
+            if !host.starts_with("ed25519:") {
+                return Err(Error::Usage(format!(
+                    "{host:?} is not a host public key; hosts are identified as ed25519:<hex>"
+                )));
+            }
+        }
+        if allow.is_empty() && block.is_empty() && spread.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            allow,
+            block,
+            spread,
+        }))
+    }
+
+    pub fn permits(&self, host_key: &str) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|h| h == host_key))
+            && !self.block.iter().any(|h| h == host_key)
+    }
+
+    /// Returns the number of the slab's shards on hosts the policy rules
+    /// out, or too concentrated for its spread.
+    pub fn violations(&self, slab: &Slab) -> usize {
+        let excluded = slab
+            .sectors
+            .iter()
+            .filter(|sector| !self.permits(&sector.host_key.to_string()))
+            .count();
+        excluded
+            + self
+                .spread
+                .as_ref()
+                .map_or(0, |spread| spread.violations(slab))
+    }
+
+    /// Returns the number of shards of `slabs` stored on hosts the policy
+    /// rules out, or too concentrated for its spread.
+    pub fn misplaced(&self, slabs: &[Slab]) -> usize {
+        slabs.iter().map(|slab| self.violations(slab)).sum()
+    }
+}
+
+/// Ranks hosts by what has been measured of them.
+///
+/// The SDK picks each slab's hosts itself and can't be given a preference,
+/// so a ranking can't steer uploads or downloads; `hosts stats` lists the
+/// hosts by it, for picking the ones to allow or block. Applications that
+/// know more about hosts than the catalog does, such as the region each one
+/// is in, can rank by that instead.
+pub trait HostStrategy: fmt::Debug + Send + Sync {
+    /// Returns the score of `host`, lower being better, or `None` if too
+    /// little is known of it to rank it.
+    fn score(&self, host: &str, stats: &HostStats) -> Option<f64>;
+}
+
+/// Ranks hosts by the mean time their transfers took, once they have taken
+/// part in enough of them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Latency;
+
+impl HostStrategy for Latency {
+    fn score(&self, _host: &str, stats: &HostStats) -> Option<f64> {
+        (stats.transfers >= MIN_TRANSFERS).then(|| stats.latency_ms())
+    }
+}
+
+/// Returns the hosts of `stats` worst first by `strategy`'s score, followed
+/// by those too little is known of to rank, with their scores.
+pub fn rank<'a>(
+    stats: &'a BTreeMap<String, HostStats>,
+    strategy: &dyn HostStrategy,
+) -> Vec<(&'a String, &'a HostStats, Option<f64>)> {
+    let mut ranked: Vec<_> = stats
+        .iter()
+        .map(|(host, stats)| (host, stats, strategy.score(host, stats)))
+        .collect();
+    ranked.sort_by(|a, b| match (a.2, b.2) {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_slowest_first_then_unranked() {
+        let stats = |transfers, busy_ms| HostStats {
+            transfers,
+            busy_ms,
+            ..HostStats::default()
+        };
+        let stats = BTreeMap::from([
+            ("a".to_string(), stats(5, 5000)),
+            ("b".to_string(), stats(20, 200)),
+            ("c".to_string(), stats(40, 4000)),
+        ]);
+        let ranked: Vec<_> = rank(&stats, &Latency)
+            .into_iter()
+            .map(|(host, _, score)| (host.as_str(), score))
+            .collect();
+        assert_eq!(ranked, [("c", Some(100.0)), ("b", Some(10.0)), ("a", None)]);
+    }
+}
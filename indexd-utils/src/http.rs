@@ -0,0 +1,156 @@
+//! What the HTTP servers share: errors reported as a bare status code, and
+//! `GET` and `HEAD` responses that stream an object or a range of it.
+
+use axum::body::Body;
+use axum::http::response::Builder;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use log::warn;
+use tokio_util::io::ReaderStream;
+
+use crate::client::Client;
+use crate::download;
+use crate::error::Error;
+use crate::manifest::Manifest;
+
+/// An error reported as a bare status code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusError(pub StatusCode);
+
+impl From<Error> for StatusError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::NotFound(_) => StatusError(StatusCode::NOT_FOUND),
+            Error::Crypto(_) => StatusError(StatusCode::FORBIDDEN),
+            e => {
+                warn!("request failed: {e}");
+                StatusError(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+impl IntoResponse for StatusError {
+    fn into_response(self) -> Response {
+        self.0.into_response()
+    }
+}
+
+/// The bytes of an object a request asks for: all of them, or the single
+/// range its `Range` header names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub offset: u64,
+    pub length: u64,
+    /// The size of the whole object.
+    pub size: u64,
+    /// Whether the request named a range.
+    pub partial: bool,
+}
+
+impl Span {
+    /// Returns the span of an object of `size` bytes that `headers` ask
+    /// for, or the `Range` header if it can't be satisfied.
+    pub fn parse(headers: &HeaderMap, size: u64) -> Result<Self, String> {
+        let Some(range) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+            return Ok(Self {
+                offset: 0,
+                length: size,
+                size,
+                partial: false,
+            });
+        };
+        let (offset, length) =
+            download::parse_http_range(range, size).ok_or_else(|| range.to_string())?;
+        Ok(Self {
+            offset,
+            length,
+            size,
+            partial: true,
+        })
+    }
+
+    /// Starts the response that sends the span: its status, length and, for
+    /// a range, where the range lies in the object.
+    pub fn response(&self) -> Builder {
+        let mut response = Response::builder()
+            .header(header::CONTENT_LENGTH, self.length)
+            .header(header::ACCEPT_RANGES, "bytes");
+        if self.partial {
+            response = response.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!(
+                    "bytes {}-{}/{}",
+                    self.offset,
+                    self.offset + self.length - 1,
+                    self.size
+                ),
+            );
+        }
+        response
+    }
+
+    /// Finishes `response` with the span of the object `manifest`
+    /// describes, downloaded on a task of its own as the body is read, or
+    /// with no body for a `HEAD` request. A download that fails ends the
+    /// body early, which clients detect from its length; the failure is
+    /// logged under `name`.
+    pub fn send(
+        &self,
+        sdk: &Client,
+        manifest: Manifest,
+        response: Builder,
+        head: bool,
+        name: String,
+    ) -> Response {
+        if head || self.length == 0 {
+            return response.body(Body::empty()).expect("valid response");
+        }
+        let (mut writer, reader) = tokio::io::duplex(1 << 20);
+        let sdk = sdk.clone();
+        let Span { offset, length, .. } = *self;
+        tokio::spawn(async move {
+            if let Err(e) =
+                download::download_object(&sdk, &mut writer, &manifest, offset, length).await
+            {
+                warn!("download of {name} failed: {e}");
+            }
+        });
+        response
+            .body(Body::from_stream(ReaderStream::new(reader)))
+            .expect("valid response")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn span(range: Option<&str>, size: u64) -> Result<Span, String> {
+        let mut headers = HeaderMap::new();
+        if let Some(range) = range {
+            headers.insert(header::RANGE, HeaderValue::from_str(range).unwrap());
+        }
+        Span::parse(&headers, size)
+    }
+
+    #[test]
+    fn spans() {
+        let whole = span(None, 100).unwrap();
+        assert_eq!((whole.offset, whole.length, whole.partial), (0, 100, false));
+        let response = whole.response().body(()).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "100");
+        assert!(!response.headers().contains_key(header::CONTENT_RANGE));
+
+        let tail = span(Some("bytes=-10"), 100).unwrap();
+        let response = tail.response().body(()).unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 90-99/100");
+
+        assert_eq!(span(Some("bytes=200-"), 100), Err("bytes=200-".into()));
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use indexd::Slab;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::middleware::{self, Direction, Middleware, Outcome, Shard, Transfer};
+
+/// Returns `$XDG_DATA_HOME/indexd-utils/sessions`, falling back to
+/// `~/.local/share`.
+pub fn default_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(base.join("indexd-utils").join("sessions"))
+}
+
+/// One line of a journal. Times are milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Record {
+    /// The first line, with the command line that ran.
+    Start { at: u64, command: Vec<String> },
+    /// A transfer was sent: one attempt at an upload or at downloading a
+    /// run of slabs. Retries and hedged requests are attempts of their own.
+    Attempt {
+        at: u64,
+        transfer: u64,
+        direction: Direction,
+    },
+    /// A transfer finished, with the shards it moved: those the upload
+    /// stored, or those of the slabs the download fetched.
+    Finish {
+        at: u64,
+        transfer: u64,
+        direction: Direction,
+        bytes: u64,
+        elapsed_ms: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        shards: Vec<Shard>,
+    },
+    /// The last line, written when the session's client is dropped. A
+    /// session that crashed has none.
+    End {
+        at: u64,
+        transfers: u64,
+        failed: u64,
+        bytes: u64,
+    },
+}
+
+/// Records every transfer of a session in an append-only journal, one
+/// JSON line each, so a slow or failed transfer can be looked into after
+/// the fact with `session show`.
+///
+/// The SDK moves a slab's shards together, so the journal has one line per
+/// attempt at a run of slabs rather than per shard; the hosts a slow or
+/// failing attempt involved are the shards listed with it. The file is
+/// only created by the first transfer, and writing is given up on after a
+/// failure rather than failing the transfers.
+pub struct Journal {
+    id: String,
+    dir: PathBuf,
+    path: PathBuf,
+    command: Vec<String>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    file: Option<File>,
+    broken: bool,
+    transfers: u64,
+    failed: u64,
+    bytes: u64,
+}
+
+impl Journal {
+    /// Starts a session journal in `dir` for `command`.
+    pub fn new(dir: &Path, command: Vec<String>) -> Self {
+        // ids sort in the order the sessions started
+        let id = format!("{}-{:04x}", now_ms() / 1000, rand::random::<u16>());
+        Self {
+            dir: dir.to_path_buf(),
+            path: dir.join(format!("{id}.jsonl")),
+            id,
+            command,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn write(&self, state: &mut State, record: &Record) {
+        if state.broken {
+            return;
+        }
+        if let Err(e) = self.try_write(state, record) {
+            warn!(
+                "giving up on the transfer journal {}: {e}",
+                self.path.display()
+            );
+            state.broken = true;
+        }
+    }
+
+    fn try_write(&self, state: &mut State, record: &Record) -> Result<()> {
+        if state.file.is_none() {
+            fs::create_dir_all(&self.dir)?;
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            let start = Record::Start {
+                at: now_ms(),
+                command: self.command.clone(),
+            };
+            file.write_all(&line(&start)?)?;
+            state.file = Some(file);
+        }
+        let file = state.file.as_mut().expect("journal opened");
+        // a whole line per write, so a crash leaves no torn records
+        file.write_all(&line(record)?)?;
+        Ok(())
+    }
+
+    fn finish(&self, transfer: Transfer, slabs: &[Slab], outcome: Outcome<'_>) {
+        let mut state = self.state.lock().unwrap();
+        state.transfers += 1;
+        state.bytes += outcome.bytes;
+        if outcome.error.is_some() {
+            state.failed += 1;
+        }
+        let record = Record::Finish {
+            at: now_ms(),
+            transfer: transfer.id,
+            direction: transfer.direction,
+            bytes: outcome.bytes,
+            elapsed_ms: outcome.elapsed.as_millis() as u64,
+            error: outcome.error.map(Error::to_string),
+            shards: middleware::shards(slabs).collect(),
+        };
+        self.write(&mut state, &record);
+    }
+
+    fn attempt(&self, transfer: Transfer) {
+        let record = Record::Attempt {
+            at: now_ms(),
+            transfer: transfer.id,
+            direction: transfer.direction,
+        };
+        self.write(&mut self.state.lock().unwrap(), &record);
+    }
+}
+
+impl Middleware for Journal {
+    fn before_upload(&self, transfer: Transfer) -> Result<()> {
+        self.attempt(transfer);
+        Ok(())
+    }
+
+    fn after_upload(&self, transfer: Transfer, slabs: &[Slab], outcome: Outcome<'_>) {
+        self.finish(transfer, slabs, outcome);
+    }
+
+    fn before_download(&self, transfer: Transfer, _slabs: &[Slab]) -> Result<()> {
+        self.attempt(transfer);
+        Ok(())
+    }
+
+    fn after_download(&self, transfer: Transfer, slabs: &[Slab], outcome: Outcome<'_>) {
+        self.finish(transfer, slabs, outcome);
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if state.file.is_none() {
+            return;
+        }
+        let record = Record::End {
+            at: now_ms(),
+            transfers: state.transfers,
+            failed: state.failed,
+            bytes: state.bytes,
+        };
+        self.write(&mut state, &record);
+        if state.failed > 0 {
+            info!(
+                "{} transfers failed; see session show {}",
+                state.failed, self.id
+            );
+        }
+    }
+}
+
+/// A journalled session, as `session list` shows it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub started_at: u64,
+    pub command: Vec<String>,
+    pub transfers: u64,
+    pub failed: u64,
+    pub bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<u64>,
+}
+
+/// What the transfers a host took part in came to over a session. Every
+/// transfer counts against each host holding one of its shards.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct HostSummary {
+    pub transfers: u64,
+    pub failed: u64,
+    pub elapsed_ms: u64,
+    pub slowest_ms: u64,
+}
+
+impl HostSummary {
+    pub fn average_ms(&self) -> u64 {
+        self.elapsed_ms / self.transfers.max(1)
+    }
+}
+
+impl Session {
+    /// Sums up a session's records.
+    pub fn new(id: &str, records: &[Record]) -> Self {
+        let mut session = Session {
+            id: id.to_string(),
+            started_at: 0,
+            command: Vec::new(),
+            transfers: 0,
+            failed: 0,
+            bytes: 0,
+            ended_at: None,
+        };
+        for record in records {
+            match record {
+                Record::Start { at, command } => {
+                    session.started_at = *at;
+                    session.command = command.clone();
+                }
+                Record::Finish { bytes, error, .. } => {
+                    session.transfers += 1;
+                    session.bytes += bytes;
+                    if error.is_some() {
+                        session.failed += 1;
+                    }
+                }
+                Record::End { at, .. } => session.ended_at = Some(*at),
+                Record::Attempt { .. } => {}
+            }
+        }
+        session
+    }
+}
+
+/// Sums up the transfers of a session by host.
+pub fn hosts(records: &[Record]) -> BTreeMap<String, HostSummary> {
+    let mut hosts: BTreeMap<String, HostSummary> = BTreeMap::new();
+    for record in records {
+        let Record::Finish {
+            elapsed_ms,
+            error,
+            shards,
+            ..
+        } = record
+        else {
+            continue;
+        };
+        let keys: BTreeSet<&str> = shards.iter().map(|s| s.host_key.as_str()).collect();
+        for key in keys {
+            let host = hosts.entry(key.to_string()).or_default();
+            host.transfers += 1;
+            host.elapsed_ms += elapsed_ms;
+            host.slowest_ms = host.slowest_ms.max(*elapsed_ms);
+            if error.is_some() {
+                host.failed += 1;
+            }
+        }
+    }
+    hosts
+}
+
+/// Reads the journal of session `id`. A torn last line, left by a crash
+/// mid-write, is skipped.
+pub fn read(dir: &Path, id: &str) -> Result<Vec<Record>> {
+    let path = dir.join(format!("{id}.jsonl"));
+    let buf = match fs::read_to_string(&path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(Error::NotFound(format!("session {id}")));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut records = Vec::new();
+    let lines: Vec<&str> = buf.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            Err(_) if i + 1 == lines.len() => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(records)
+}
+
+/// Returns the ids of every journalled session, oldest first.
+pub fn ids(dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut ids = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name();
+        if let Some(id) = name.to_string_lossy().strip_suffix(".jsonl") {
+            ids.push(id.to_string());
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Lists every journalled session, oldest first.
+pub fn list(dir: &Path) -> Result<Vec<Session>> {
+    ids(dir)?
+        .iter()
+        .map(|id| Ok(Session::new(id, &read(dir, id)?)))
+        .collect()
+}
+
+/// Removes the oldest journals beyond the `keep` most recent, returning
+/// how many were removed.
+pub fn prune(dir: &Path, keep: usize) -> Result<usize> {
+    let ids = ids(dir)?;
+    let excess = ids.len().saturating_sub(keep);
+    for id in &ids[..excess] {
+        fs::remove_file(dir.join(format!("{id}.jsonl")))?;
+    }
+    Ok(excess)
+}
+
+fn line(record: &Record) -> Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
@@ -0,0 +1,58 @@
+use keyring::Entry;
+
+use crate::error::{Error, Result};
+use crate::keys::SoftwareKeys;
+
+/// The service every key is stored under.
+const SERVICE: &str = "indexd-utils";
+
+/// Returns the account the app key of `identity` is stored under, as
+/// named by [`Settings::identity`](crate::config::Settings::identity).
+pub fn app_account(identity: &str) -> String {
+    format!("app/{identity}")
+}
+
+/// Returns the account the master key `name` is stored under.
+pub fn object_account(name: &str) -> String {
+    format!("object/{name}")
+}
+
+/// Reads the key stored under `account` in the OS keychain: the macOS
+/// Keychain, the Secret Service on Linux or the Windows Credential Manager.
+/// Keys are stored as hex.
+///
+/// The keychain may ask the user to unlock it, so this blocks.
+pub fn load(account: &str) -> Result<Option<[u8; 32]>> {
+    let hex = match entry(account)?.get_password() {
+        Ok(hex) => hex,
+        Err(keyring::Error::NoEntry) => return Ok(None),
+        Err(e) => return Err(keychain_error(account, e)),
+    };
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(hex.trim(), &mut key)
+        .map_err(|_| Error::Keychain(format!("{account} does not hold a 32-byte hex key")))?;
+    Ok(Some(key))
+}
+
+/// Stores `key` under `account`, replacing any key already there.
+pub fn store(account: &str, key: &[u8; 32]) -> Result<()> {
+    entry(account)?
+        .set_password(&hex::encode(key))
+        .map_err(|e| keychain_error(account, e))
+}
+
+/// Returns the provider of the keys derived from the master key `name`.
+pub fn object_keys(name: &str) -> Result<SoftwareKeys> {
+    let account = object_account(name);
+    let root =
+        load(&account)?.ok_or_else(|| Error::NotFound(format!("{account} in the keychain")))?;
+    Ok(SoftwareKeys::new(root))
+}
+
+fn entry(account: &str) -> Result<Entry> {
+    Entry::new(SERVICE, account).map_err(|e| keychain_error(account, e))
+}
+
+fn keychain_error(account: &str, e: keyring::Error) -> Error {
+    Error::Keychain(format!("{account}: {e}"))
+}
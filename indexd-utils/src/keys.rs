@@ -0,0 +1,211 @@
+use std::env;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use futures::future::{self, BoxFuture};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::error::{Error, Result};
+
+/// The Argon2id parameters and salt used to derive a key from a
+/// passphrase. Stored alongside whatever the key protects so the key can be
+/// re-derived from the passphrase alone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Kdf {
+    pub algorithm: KdfAlgorithm,
+    #[serde(with = "hex")]
+    pub salt: [u8; 16],
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KdfAlgorithm {
+    Argon2id,
+}
+
+impl Default for Kdf {
+    /// Returns the default parameters with a fresh random salt.
+    fn default() -> Self {
+        Self {
+            algorithm: KdfAlgorithm::Argon2id,
+            salt: rand::random(),
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 4,
+        }
+    }
+}
+
+impl Kdf {
+    /// Derives a 32-byte key from `passphrase`. Argon2 is deliberately
+    /// expensive, so this runs on the blocking pool.
+    pub async fn derive(&self, passphrase: &str) -> Result<[u8; 32]> {
+        let kdf = self.clone();
+        let passphrase = passphrase.to_string();
+        tokio::task::spawn_blocking(move || {
+            let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32))
+                .map_err(|e| Error::Crypto(format!("invalid argon2 parameters: {e}")))?;
+            let mut key = [0u8; 32];
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(passphrase.as_bytes(), &kdf.salt, &mut key)
+                .map_err(|e| Error::Crypto(format!("failed to derive key: {e}")))?;
+            Ok(key)
+        })
+        .await
+        .expect("key derivation panicked")
+    }
+}
+
+/// Derives an independent key for `context` from `key`.
+pub fn subkey(key: &[u8; 32], context: &str) -> [u8; 32] {
+    let h = blake2b_simd::Params::new()
+        .hash_length(32)
+        .key(key)
+        .to_state()
+        .update(context.as_bytes())
+        .finalize();
+    let mut subkey = [0u8; 32];
+    subkey.copy_from_slice(h.as_bytes());
+    subkey
+}
+
+/// Derives the key of slab `slab` of segment `segment` from an object's
+/// master key with HKDF-SHA256. Manifests only record the master key's
+/// fingerprint; the SDK keeps each slab's own key with the slab.
+pub fn slab_key(master: &[u8; 32], segment: u64, slab: u64) -> [u8; 32] {
+    hkdf(
+        master,
+        &[
+            b"indexd-utils slab",
+            &segment.to_le_bytes(),
+            &slab.to_le_bytes(),
+        ],
+    )
+}
+
+/// HKDF-SHA256 without a salt, for 32 bytes of output keyed by the
+/// concatenation of `info`.
+fn hkdf(ikm: &[u8], info: &[&[u8]]) -> [u8; 32] {
+    // HKDF-Extract without a salt keys the HMAC with zeros
+    let prk = Hmac::<Sha256>::new_from_slice(&[0; 32])
+        .expect("HMAC accepts any key length")
+        .chain_update(ikm)
+        .finalize()
+        .into_bytes();
+    // a single HKDF-Expand block is all 32 bytes need
+    let mut expand = Hmac::<Sha256>::new_from_slice(&prk).expect("HMAC accepts any key length");
+    for part in info {
+        expand.update(part);
+    }
+    expand.chain_update([1]).finalize().into_bytes().into()
+}
+
+/// Hands out the keys objects are encrypted with.
+///
+/// The SDK encrypts shards with plain 32-byte keys, so a provider derives
+/// one per object from a root key that never has to leave it: a TPM, a
+/// YubiKey through PKCS#11 or a KMS can compute the keys without exposing
+/// the root. The same context must always give the same key, so uploads
+/// can be resumed and their keys derived again.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the key for `context`, such as `file/3` or `pack/0`.
+    fn key<'a>(&'a self, context: &'a str) -> BoxFuture<'a, Result<[u8; 32]>>;
+}
+
+/// The default provider, deriving keys from a root key held in memory.
+pub struct SoftwareKeys {
+    root: [u8; 32],
+}
+
+impl SoftwareKeys {
+    pub fn new(root: [u8; 32]) -> Self {
+        Self { root }
+    }
+}
+
+impl KeyProvider for SoftwareKeys {
+    fn key<'a>(&'a self, context: &'a str) -> BoxFuture<'a, Result<[u8; 32]>> {
+        Box::pin(future::ready(Ok(subkey(&self.root, context))))
+    }
+}
+
+/// Reads the passphrase from `INDEXD_PASSPHRASE`, prompting on the
+/// terminal if it is unset. New passphrases are prompted for twice.
+pub fn read_passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = env::var("INDEXD_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("passphrase: ")?;
+    if confirm && rpassword::prompt_password("confirm passphrase: ")? != passphrase {
+        return Err(Error::Usage("passphrases do not match".into()));
+    }
+    if passphrase.is_empty() {
+        return Err(Error::Usage("passphrase must not be empty".into()));
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hkdf_matches_rfc5869() {
+        // test case 3 of RFC 5869, which has no salt or info, cut to 32 bytes
+        let okm = hkdf(&[0x0b; 22], &[]);
+        assert_eq!(
+            hex::encode(okm),
+            "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d"
+        );
+    }
+
+    #[test]
+    fn slab_keys_are_distinct() {
+        let master = [1; 32];
+        let key = slab_key(&master, 0, 0);
+        assert_eq!(key, slab_key(&master, 0, 0));
+        assert_ne!(key, slab_key(&master, 0, 1));
+        assert_ne!(key, slab_key(&master, 1, 0));
+        assert_ne!(key, slab_key(&[2; 32], 0, 0));
+    }
+
+    #[test]
+    fn subkeys_are_distinct() {
+        let root = [3; 32];
+        assert_eq!(subkey(&root, "file/0"), subkey(&root, "file/0"));
+        assert_ne!(subkey(&root, "file/0"), subkey(&root, "file/1"));
+        assert_ne!(subkey(&root, "file/0"), subkey(&[4; 32], "file/0"));
+    }
+
+    #[tokio::test]
+    async fn kdf_is_deterministic() {
+        let kdf = Kdf {
+            salt: [5; 16],
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+            ..Kdf::default()
+        };
+        let key = kdf.derive("passphrase").await.unwrap();
+        assert_eq!(key, kdf.derive("passphrase").await.unwrap());
+        assert_ne!(key, kdf.derive("other").await.unwrap());
+        let salted = Kdf {
+            salt: [6; 16],
+            ..kdf.clone()
+        };
+        assert_ne!(key, salted.derive("passphrase").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn kdf_rejects_bad_parameters() {
+        let kdf = Kdf {
+            memory_kib: 0,
+            ..Kdf::default()
+        };
+        assert!(matches!(kdf.derive("x").await, Err(Error::Crypto(_))));
+    }
+}
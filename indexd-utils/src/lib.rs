@@ -0,0 +1,89 @@
+//! Storing files with indexd: the upload and download pipeline, manifests,
+//! the catalog and progress reporting behind the `upload-rs` and
+//! `indexd-s3` tools, for applications that embed transfers rather than
+//! running the tools.
+
+pub mod aead;
+pub mod approvals;
+pub mod archive;
+pub mod backend;
+pub mod backup;
+pub mod bench;
+#[cfg(feature = "tui")]
+pub mod browse;
+pub mod bucket;
+pub mod budget;
+pub mod catalog;
+pub mod chaos;
+pub mod checkpoint;
+pub mod checksum;
+pub mod client;
+pub mod compression;
+pub mod config;
+pub mod daemon;
+pub mod dedup;
+pub mod diff;
+pub mod directory;
+pub mod doctor;
+pub mod download;
+pub mod error;
+pub mod estimate;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fileio;
+pub mod fileserver;
+pub mod filter;
+pub mod fsck;
+pub mod gc;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod health;
+pub mod hosts;
+pub mod http;
+pub mod journal;
+#[cfg(feature = "keyring")]
+pub mod keychain;
+pub mod keys;
+pub mod lifecycle;
+pub mod locator;
+pub mod maintain;
+pub mod manifest;
+pub mod metrics;
+pub mod middleware;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+#[cfg(feature = "fuse")]
+pub mod mount;
+pub mod net;
+pub mod normalize;
+pub mod objectstore;
+pub mod output;
+pub mod pack;
+pub mod progress;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod recipients;
+pub mod redundancy;
+pub mod rekey;
+pub mod repair;
+pub mod restic;
+pub mod retry;
+pub mod schedule;
+pub mod scheduler;
+pub mod selection;
+pub mod share;
+pub mod shutdown;
+pub mod source;
+pub mod sparse;
+pub mod status;
+pub mod sync;
+pub mod tags;
+pub mod telemetry;
+pub mod throttle;
+pub mod transfer;
+pub mod upload;
+pub mod uploader;
+pub mod verify;
+pub mod webdav;
+pub mod webhook;
@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::{Catalog, Entry};
+use crate::error::Result;
+use crate::tags::Tag;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// A lifecycle rule from the config: what happens to the catalogued
+/// objects under a prefix, or carrying some tags, as they age. Every
+/// matching rule applies, so the strictest limit wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Names the rule in reports, defaults to its prefix and tags.
+    pub name: Option<String>,
+    /// Only objects whose names start with this.
+    #[serde(default)]
+    pub prefix: String,
+    /// Only objects carrying every one of these tags, written `key=value`.
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+    /// Replaced versions older than this many days are removed.
+    pub expire_versions_days: Option<u64>,
+    /// The most replaced versions kept of each object.
+    pub keep_versions: Option<usize>,
+    /// Objects not uploaded again for this many days go to the trash.
+    pub expire_days: Option<u64>,
+    /// Objects in the trash this many days are purged, sooner than the
+    /// profile's trash retention.
+    pub purge_trash_days: Option<u64>,
+    /// Objects are verified by `maintain` once every this many days rather
+    /// than on every run.
+    pub verify_every_days: Option<u64>,
+}
+
+impl Rule {
+    pub fn label(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let mut label = format!("{}*", self.prefix);
+        for tag in &self.tags {
+            label.push_str(&format!(" {tag}"));
+        }
+        label
+    }
+
+    fn covers(&self, name: &str, tags: &BTreeMap<String, String>) -> bool {
+        name.starts_with(&self.prefix) && self.tags.iter().all(|tag| tag.matches(tags))
+    }
+}
+
+/// A replaced version of an object.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct VersionRef {
+    pub name: String,
+    pub version: u64,
+}
+
+/// What one rule would do to the catalog now.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleStatus {
+    pub rule: String,
+    /// The catalogued objects the rule covers.
+    pub objects: usize,
+    pub expired_versions: Vec<VersionRef>,
+    /// The objects due to go to the trash.
+    pub expired_objects: Vec<String>,
+    /// The trashed objects due to be purged.
+    pub purged_trash: Vec<String>,
+    /// The objects due to be verified.
+    pub verify_due: Vec<String>,
+    /// The objects verified recently enough to skip.
+    pub verify_not_due: usize,
+}
+
+/// What the lifecycle rules would do to the catalog now.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Plan {
+    pub rules: Vec<RuleStatus>,
+    /// Objects some rule schedules verification for that aren't due, and
+    /// none finds due.
+    #[serde(skip)]
+    not_due: BTreeSet<String>,
+}
+
+impl Plan {
+    /// Whether verifying the object called `name` can wait for a later run.
+    pub fn skips_verify(&self, name: &str) -> bool {
+        self.not_due.contains(name)
+    }
+}
+
+/// What applying a plan changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Applied {
+    pub versions: usize,
+    pub trashed: usize,
+    pub purged: usize,
+}
+
+/// Works out what `rules` would do to the catalog now, without changing
+/// it.
+pub fn plan(catalog: &Catalog, rules: &[Rule]) -> Result<Plan> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let cutoff = |days: u64| now.saturating_sub(days * DAY);
+    let entries = catalog.list("")?;
+    let trash = catalog.list_trash()?;
+    let checks = catalog.checks()?;
+    let versions = catalog.list_versions("")?;
+
+    let mut plan = Plan::default();
+    let mut due = BTreeSet::new();
+    for rule in rules {
+        let covered: Vec<&Entry> = entries
+            .iter()
+            .filter(|entry| rule.covers(&entry.name, &entry.tags))
+            .collect();
+        let names: BTreeSet<&str> = covered.iter().map(|entry| entry.name.as_str()).collect();
+        let mut status = RuleStatus {
+            rule: rule.label(),
+            objects: covered.len(),
+            expired_versions: Vec::new(),
+            expired_objects: Vec::new(),
+            purged_trash: Vec::new(),
+            verify_due: Vec::new(),
+            verify_not_due: 0,
+        };
+
+        if rule.keep_versions.is_some() || rule.expire_versions_days.is_some() {
+            let mut replaced: BTreeMap<&str, Vec<(u64, u64)>> = BTreeMap::new();
+            for version in &versions {
+                let Some(replaced_at) = version.replaced_at else {
+                    continue;
+                };
+                if names.contains(version.name.as_str()) {
+                    replaced
+                        .entry(version.name.as_str())
+                        .or_default()
+                        .push((version.version, replaced_at));
+                }
+            }
+            for (name, mut kept) in replaced {
+                // newest first, so the ones past keep_versions are the oldest
+                kept.sort_by(|a, b| b.0.cmp(&a.0));
+                for (i, (version, replaced_at)) in kept.into_iter().enumerate() {
+                    let over = rule.keep_versions.is_some_and(|keep| i >= keep);
+                    let old = rule
+                        .expire_versions_days
+                        .is_some_and(|days| replaced_at < cutoff(days));
+                    if over || old {
+                        status.expired_versions.push(VersionRef {
+                            name: name.to_string(),
+                            version,
+                        });
+                    }
+                }
+            }
+        }
+        if let Some(days) = rule.expire_days {
+            status.expired_objects = covered
+                .iter()
+                .filter(|entry| entry.updated_at < cutoff(days))
+                .map(|entry| entry.name.clone())
+                .collect();
+        }
+        if let Some(days) = rule.purge_trash_days {
+            status.purged_trash = trash
+                .iter()
+                .filter(|trashed| rule.covers(&trashed.name, &trashed.tags))
+                .filter(|trashed| trashed.deleted_at <= cutoff(days))
+                .map(|trashed| trashed.name.clone())
+                .collect();
+        }
+        if let Some(days) = rule.verify_every_days {
+            for entry in &covered {
+                let checked = checks.get(&entry.name).map(|check| check.checked_at);
+                if checked.is_some_and(|at| at > cutoff(days)) {
+                    status.verify_not_due += 1;
+                    plan.not_due.insert(entry.name.clone());
+                } else {
+                    status.verify_due.push(entry.name.clone());
+                    due.insert(entry.name.clone());
+                }
+            }
+        }
+        plan.rules.push(status);
+    }
+    // an object one rule finds due is verified whatever the others say
+    plan.not_due.retain(|name| !due.contains(name));
+    Ok(plan)
+}
+
+/// Removes the versions, trashes the objects and purges the trash a plan
+/// lists. Objects several rules list are only counted once.
+pub fn apply(catalog: &Catalog, plan: &Plan) -> Result<Applied> {
+    let mut applied = Applied::default();
+    let versions: BTreeSet<&VersionRef> = plan
+        .rules
+        .iter()
+        .flat_map(|status| &status.expired_versions)
+        .collect();
+    for version in versions {
+        if catalog.remove_version(&version.name, version.version)? {
+            applied.versions += 1;
+        }
+    }
+    let expired: BTreeSet<&String> = plan
+        .rules
+        .iter()
+        .flat_map(|status| &status.expired_objects)
+        .collect();
+    for name in expired {
+        if catalog.trash(name)? {
+            applied.trashed += 1;
+        }
+    }
+    let purged: BTreeSet<&String> = plan
+        .rules
+        .iter()
+        .flat_map(|status| &status.purged_trash)
+        .collect();
+    for name in purged {
+        if catalog.remove(name)? {
+            applied.purged += 1;
+        }
+    }
+    Ok(applied)
+}
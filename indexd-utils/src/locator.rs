@@ -0,0 +1,89 @@
+use std::io::Cursor;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+
+use crate::checksum::ChecksumWriter;
+use crate::client::Client;
+use crate::download;
+use crate::error::{Error, Result};
+use crate::manifest::{self, Manifest, SealedManifest};
+use crate::progress::Progress;
+use crate::upload::{self, UploadOptions};
+
+/// Marks a locator and the version of its encoding.
+const PREFIX: &str = "indexd-locator-v1.";
+
+/// Where a sealed manifest stored on indexd lives, so an upload can be
+/// found again from any machine with just the locator and its passphrase.
+///
+/// The manifest is uploaded as a small object of its own, still sealed, and
+/// the locator holds the manifest of that object. Its slab keys only open
+/// the sealed manifest, so a locator on its own reveals nothing of the data;
+/// the passphrase is the secret. The SDK has no way to look objects up, so
+/// the locator carries the slabs themselves and is a few kilobytes long.
+#[derive(Debug, Clone)]
+pub struct Locator {
+    manifest: Manifest,
+}
+
+impl Locator {
+    /// Whether `s` looks like a locator rather than a path or a name.
+    pub fn is_locator(s: &str) -> bool {
+        s.trim().starts_with(PREFIX)
+    }
+
+    /// Uploads `sealed` with the given redundancy and returns its locator.
+    pub async fn store(
+        sdk: &Client,
+        sealed: &SealedManifest,
+        options: UploadOptions,
+    ) -> Result<Self> {
+        let json = serde_json::to_vec(sealed)?;
+        let manifest = upload::upload_reader(
+            sdk,
+            Cursor::new(json),
+            rand::random(),
+            options,
+            Progress::default(),
+        )
+        .await?;
+        Ok(Self { manifest })
+    }
+
+    /// Downloads the sealed manifest the locator points at.
+    pub async fn fetch(&self, sdk: &Client) -> Result<SealedManifest> {
+        let mut w = ChecksumWriter::new(Vec::new());
+        download::download_object(sdk, &mut w, &self.manifest, 0, self.manifest.size).await?;
+        if w.checksum() != self.manifest.checksum {
+            return Err(Error::Manifest(
+                "the located manifest does not match its checksum".into(),
+            ));
+        }
+        let sealed: SealedManifest = serde_json::from_slice(&w.into_inner())?;
+        manifest::check_version(sealed.version)?;
+        Ok(sealed)
+    }
+
+    /// Encodes the locator as a single line of URL-safe text.
+    pub fn encode(&self) -> Result<String> {
+        let json = serde_json::to_vec(&self.manifest)?;
+        let compressed = zstd::encode_all(json.as_slice(), 19)?;
+        Ok(format!("{PREFIX}{}", BASE64.encode(compressed)))
+    }
+
+    pub fn decode(locator: &str) -> Result<Self> {
+        let encoded = locator
+            .trim()
+            .strip_prefix(PREFIX)
+            .ok_or_else(|| Error::Usage("not a locator".into()))?;
+        let compressed = BASE64
+            .decode(encoded)
+            .map_err(|e| Error::Usage(format!("malformed locator: {e}")))?;
+        let json = zstd::decode_all(compressed.as_slice())
+            .map_err(|e| Error::Usage(format!("malformed locator: {e}")))?;
+        let manifest: Manifest = serde_json::from_slice(&json)?;
+        manifest::check_version(manifest.version)?;
+        Ok(Self { manifest })
+    }
+}
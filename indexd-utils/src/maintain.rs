@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::catalog::{Catalog, Check};
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::gc;
+use crate::lifecycle::{self, Rule};
+use crate::manifest::{AnyManifest, Manifest, Sealing, StoredManifest};
+use crate::progress::Progress;
+use crate::repair::{self, RepairOptions};
+use crate::shutdown;
+use crate::telemetry::HostStats;
+use crate::verify::Health;
+
+/// What a maintenance run may spend. Without either limit every object is
+/// checked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// No object is started once the run has taken this long; the one in
+    /// progress is finished.
+    pub time: Option<Duration>,
+    /// The most slab data to recover, which skips the objects that would
+    /// go over it.
+    pub bytes: Option<u64>,
+}
+
+/// What maintenance found of one object.
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectReport {
+    pub name: String,
+    pub healthy: usize,
+    pub degraded: usize,
+    pub unrecoverable: usize,
+    /// The slabs replaced with fresh uploads.
+    pub replaced: usize,
+    /// The slabs that were recovered but couldn't be stored again.
+    pub failed: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ObjectReport {
+    /// The slabs still needing attention after the run.
+    pub fn damaged(&self) -> usize {
+        self.unrecoverable + self.failed
+    }
+}
+
+/// What a maintenance run did.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// The objects checked, in the order they were.
+    pub objects: Vec<ObjectReport>,
+    /// The objects left for a later run by the limits or an interrupt.
+    pub deferred: Vec<String>,
+    /// The sealed objects skipped for want of a passphrase.
+    pub sealed: usize,
+    /// The objects a lifecycle rule lets go unverified this run.
+    pub not_due: usize,
+    /// What the lifecycle rules removed before checking.
+    pub lifecycle: lifecycle::Applied,
+    /// The slab data recovered.
+    pub bytes: u64,
+    pub gc: gc::Report,
+}
+
+/// A catalogued object waiting to be checked.
+struct Candidate {
+    name: String,
+    manifest: AnyManifest,
+    sealing: Option<Sealing>,
+    check: Option<Check>,
+    /// The fewest parity shards any slab has left once the shards on hosts
+    /// that often fail are written off.
+    margin: f64,
+    stored: u64,
+}
+
+/// Applies the lifecycle `rules`, verifies and repairs every catalogued
+/// object they don't let wait, then scans `gc_paths` for orphaned slabs,
+/// within `limits`.
+///
+/// The least healthy objects go first: those the last run left damaged,
+/// then those whose slabs have the least parity to spare once the shards
+/// on hosts the catalog has seen fail are written off, then the ones
+/// checked longest ago. Repair works on whole objects, so an object is
+/// ranked by its weakest slab. Sealed objects are opened with `passphrase`
+/// and skipped without one.
+pub async fn maintain(
+    sdk: &Client,
+    catalog: &Catalog,
+    passphrase: Option<&str>,
+    gc_paths: &[PathBuf],
+    rules: &[Rule],
+    limits: Limits,
+    progress: &Progress,
+) -> Result<Report> {
+    let started = Instant::now();
+    let plan = lifecycle::plan(catalog, rules)?;
+    let applied = lifecycle::apply(catalog, &plan)?;
+    if applied != lifecycle::Applied::default() {
+        info!(
+            "lifecycle rules removed {} versions, trashed {} objects and purged {}",
+            applied.versions, applied.trashed, applied.purged
+        );
+    }
+    let (mut candidates, sealed) = candidates(catalog, passphrase).await?;
+    let total = candidates.len();
+    candidates.retain(|candidate| !plan.skips_verify(&candidate.name));
+    let not_due = total - candidates.len();
+    // damage found before outranks the host stats' guess, which outranks
+    // age; objects never checked count as the oldest
+    candidates.sort_by(|a, b| {
+        let damaged = |c: &Candidate| c.check.map_or(0, |check| check.damaged);
+        let checked_at = |c: &Candidate| c.check.map(|check| check.checked_at);
+        damaged(b)
+            .cmp(&damaged(a))
+            .then(a.margin.total_cmp(&b.margin))
+            .then(checked_at(a).cmp(&checked_at(b)))
+    });
+
+    let mut report = Report {
+        objects: Vec::new(),
+        deferred: Vec::new(),
+        sealed,
+        not_due,
+        lifecycle: applied,
+        bytes: 0,
+        gc: gc::Report::default(),
+    };
+    for candidate in candidates {
+        let out_of_time = limits.time.is_some_and(|time| started.elapsed() >= time);
+        let out_of_bytes = limits
+            .bytes
+            .is_some_and(|bytes| report.bytes + candidate.stored > bytes);
+        if out_of_time || out_of_bytes || shutdown::requested() {
+            report.deferred.push(candidate.name);
+            continue;
+        }
+        report.bytes += candidate.stored;
+        let object = maintain_object(sdk, catalog, candidate, progress).await?;
+        report.objects.push(object);
+    }
+    if !report.deferred.is_empty() {
+        info!("{} objects left for the next run", report.deferred.len());
+    }
+
+    report.gc = gc::scan(catalog, gc_paths, passphrase).await?;
+    Ok(report)
+}
+
+/// Loads the catalogued objects, with the number of sealed ones that
+/// couldn't be opened.
+async fn candidates(
+    catalog: &Catalog,
+    passphrase: Option<&str>,
+) -> Result<(Vec<Candidate>, usize)> {
+    let checks = catalog.checks()?;
+    let hosts = catalog.host_stats()?;
+    let mut candidates = Vec::new();
+    let mut sealed = 0;
+    for entry in catalog.list("")? {
+        let Some(stored) = catalog.get(&entry.name)? else {
+            continue;
+        };
+        let (manifest, sealing) = match stored {
+            StoredManifest::Plain(manifest) => (manifest, None),
+            StoredManifest::Sealed(manifest) => {
+                let Some(passphrase) = passphrase else {
+                    sealed += 1;
+                    continue;
+                };
+                let key = manifest.kdf.derive(passphrase).await?;
+                match manifest.open(&key) {
+                    Ok(opened) => (opened, Some(manifest.sealing(key))),
+                    Err(e) => {
+                        warn!("skipping {}: {e}", entry.name);
+                        sealed += 1;
+                        continue;
+                    }
+                }
+            }
+        };
+        let files = files(&manifest);
+        let margin = files
+            .iter()
+            .map(|file| margin(file, &hosts))
+            .fold(f64::INFINITY, f64::min);
+        let stored = files.iter().map(|file| file.stored_size()).sum();
+        candidates.push(Candidate {
+            check: checks.get(&entry.name).copied(),
+            name: entry.name,
+            manifest,
+            sealing,
+            margin,
+            stored,
+        });
+    }
+    Ok((candidates, sealed))
+}
+
+fn files(manifest: &AnyManifest) -> Vec<&Manifest> {
+    match manifest {
+        AnyManifest::File(manifest) => vec![manifest],
+        AnyManifest::Directory(manifest) => manifest.files.iter().map(|f| &f.manifest).collect(),
+    }
+}
+
+/// Returns the parity a file's weakest slab has to spare, counting each
+/// shard as lost with its host's failure rate.
+fn margin(manifest: &Manifest, hosts: &BTreeMap<String, HostStats>) -> f64 {
+    manifest
+        .slabs
+        .iter()
+        .map(|slab| {
+            let at_risk: f64 = slab
+                .sectors
+                .iter()
+                .filter_map(|sector| hosts.get(&sector.host_key.to_string()))
+                .map(HostStats::failure_rate)
+                .sum();
+            manifest.parity_shards as f64 - at_risk
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Repairs every file of an object, writing the manifest back if any slab
+/// was replaced and recording what was found. An object that no longer
+/// matches its checksum is reported as such; any other error fails the
+/// run.
+async fn maintain_object(
+    sdk: &Client,
+    catalog: &Catalog,
+    mut candidate: Candidate,
+    progress: &Progress,
+) -> Result<ObjectReport> {
+    let name = candidate.name;
+    let mut report = ObjectReport {
+        name: name.clone(),
+        healthy: 0,
+        degraded: 0,
+        unrecoverable: 0,
+        replaced: 0,
+        failed: 0,
+        error: None,
+    };
+    let files: Vec<&mut Manifest> = match &mut candidate.manifest {
+        AnyManifest::File(manifest) => vec![manifest],
+        AnyManifest::Directory(manifest) => {
+            manifest.files.iter_mut().map(|f| &mut f.manifest).collect()
+        }
+    };
+    for file in files {
+        let repair = match repair::repair(sdk, file, RepairOptions::new(file), progress).await {
+            Ok(repair) => repair,
+            Err(e) if matches!(e, Error::Manifest(_)) => {
+                warn!("{name}: {e}");
+                report.unrecoverable += file.slabs.len();
+                report.error = Some(e.to_string());
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for slab in &repair.slabs {
+            match slab.health {
+                Health::Healthy => report.healthy += 1,
+                Health::Degraded => report.degraded += 1,
+                Health::Unrecoverable => report.unrecoverable += 1,
+            }
+        }
+        report.replaced += repair.replaced();
+        report.failed += repair.failed();
+        *file = repair.manifest;
+    }
+
+    if report.replaced > 0 {
+        let stored = StoredManifest::new(candidate.manifest.clone(), candidate.sealing.as_ref())?;
+        catalog.replace(&name, None, &candidate.manifest, &stored)?;
+        info!("{name}: replaced {} slabs", report.replaced);
+    }
+    catalog.record_check(&name, report.damaged() as u64)?;
+    Ok(report)
+}
@@ -0,0 +1,640 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use indexd::Slab;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::aead::Cipher;
+use crate::compression::Compression;
+use crate::dedup::Chunk;
+use crate::error::{Error, Result};
+use crate::hosts::HostPolicy;
+use crate::keys::{self, Kdf};
+use crate::recipients::{Recipient, RecipientKey, Stanza};
+use crate::sparse::Hole;
+
+/// The version written by this release. Version 2 added compression,
+/// version 3 authenticated encryption, version 4 sparse files and version 5
+/// sealed objects whose last block is marked; older releases refuse them
+/// rather than writing out compressed or sealed bytes, or a sparse file
+/// without its holes.
+pub const MANIFEST_VERSION: u32 = 5;
+
+/// The on-disk encoding of a manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Cbor,
+}
+
+impl Format {
+    /// Picks the format from the file extension, defaulting to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("cbor") => Format::Cbor,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// Everything needed to download an uploaded file again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub size: u64,
+    /// The BLAKE2b-256 checksum of the plaintext.
+    #[serde(with = "hex")]
+    pub checksum: [u8; 32],
+    /// The SHA-256 of the plaintext, recorded on request for tools that
+    /// only know SHA-256.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "hex_option")]
+    pub sha256: Option<[u8; 32]>,
+    /// Identifies the encryption key without revealing it.
+    pub key_fingerprint: String,
+    /// The redundancy the file was uploaded with, which a slab sizing may
+    /// have chosen for its size. Each slab also records its own, as its
+    /// `min_shards` data shards of its sectors.
+    pub data_shards: u8,
+    pub parity_shards: u8,
+    /// Set when the plaintext was compressed before encryption, in which
+    /// case the slabs hold the compressed stream and `size` is the
+    /// decompressed size.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// Set when the plaintext was sealed in authenticated blocks before
+    /// encryption, in which case the slabs hold the sealed stream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<Cipher>,
+    pub slabs: Vec<Slab>,
+    /// The content-defined chunks of a deduplicated upload, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<Chunk>,
+    /// The hosts the slabs were restricted to when uploaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hosts: Option<HostPolicy>,
+    /// The holes of a sparse file, in order. They aren't stored: the slabs
+    /// hold the data between them back to back.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holes: Vec<Hole>,
+}
+
+impl Manifest {
+    pub fn new(
+        size: u64,
+        checksum: [u8; 32],
+        encryption_key: &[u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+        slabs: Vec<Slab>,
+    ) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            size,
+            checksum,
+            sha256: None,
+            key_fingerprint: key_fingerprint(encryption_key),
+            data_shards,
+            parity_shards,
+            compression: None,
+            cipher: None,
+            slabs,
+            chunks: Vec::new(),
+            hosts: None,
+            holes: Vec::new(),
+        }
+    }
+
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_cipher(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    pub fn with_sha256(mut self, sha256: Option<[u8; 32]>) -> Self {
+        self.sha256 = sha256;
+        self
+    }
+
+    pub fn with_hosts(mut self, hosts: Option<HostPolicy>) -> Self {
+        self.hosts = hosts;
+        self
+    }
+
+    pub fn with_holes(mut self, holes: Vec<Hole>) -> Self {
+        self.holes = holes;
+        self
+    }
+
+    /// Returns the number of bytes stored in the slabs, which differs from
+    /// `size` for compressed, sealed and sparse objects.
+    pub fn stored_size(&self) -> u64 {
+        self.slabs.iter().map(|slab| slab.length as u64).sum()
+    }
+
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let manifest: Self = read(path.as_ref()).await?;
+        check_version(manifest.version)?;
+        Ok(manifest)
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        write(path.as_ref(), self).await
+    }
+}
+
+/// A file within a directory manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// The path relative to the uploaded directory, `/` separated.
+    pub path: String,
+    pub manifest: Manifest,
+    /// The file's modification time in nanoseconds since the Unix epoch,
+    /// restored on download.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    /// The file's Unix permission bits, restored on download.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// The file's extended attributes by name, their values hex encoded,
+    /// restored on download.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+}
+
+impl FileEntry {
+    pub fn new(path: String, manifest: Manifest, modified: Option<u64>) -> Self {
+        Self {
+            path,
+            manifest,
+            modified,
+            mode: None,
+            xattrs: BTreeMap::new(),
+        }
+    }
+}
+
+/// A symbolic link of an uploaded directory, recreated on download.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkEntry {
+    /// The path relative to the uploaded directory, `/` separated.
+    pub path: String,
+    /// What the link points to, as read from it.
+    pub target: String,
+}
+
+/// Maps the relative paths of an uploaded directory tree to their slabs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryManifest {
+    pub version: u32,
+    pub files: Vec<FileEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<LinkEntry>,
+}
+
+impl DirectoryManifest {
+    pub fn new(files: Vec<FileEntry>) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            files,
+            links: Vec::new(),
+        }
+    }
+
+    pub fn with_links(mut self, links: Vec<LinkEntry>) -> Self {
+        self.links = links;
+        self
+    }
+
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let manifest: Self = read(path.as_ref()).await?;
+        check_version(manifest.version)?;
+        Ok(manifest)
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        write(path.as_ref(), self).await
+    }
+
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|f| f.manifest.size).sum()
+    }
+}
+
+/// Either kind of manifest, for commands that accept both.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AnyManifest {
+    Directory(DirectoryManifest),
+    File(Manifest),
+}
+
+impl AnyManifest {
+    /// Loads an unsealed manifest of either kind.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match StoredManifest::load(path).await? {
+            StoredManifest::Plain(manifest) => Ok(manifest),
+            StoredManifest::Sealed(_) => Err(Error::Manifest(format!(
+                "{} is sealed and requires a passphrase",
+                path.display()
+            ))),
+        }
+    }
+
+    fn check_version(&self) -> Result<()> {
+        match self {
+            AnyManifest::Directory(m) => check_version(m.version),
+            AnyManifest::File(m) => check_version(m.version),
+        }
+    }
+}
+
+/// What a manifest is sealed with: the key derived from a passphrase with
+/// `kdf`, which object keys are derived from too, and the recipients it is
+/// shared with.
+#[derive(Debug, Clone)]
+pub struct Sealing {
+    pub kdf: Kdf,
+    pub key: [u8; 32],
+    pub recipients: Vec<Recipient>,
+}
+
+impl Sealing {
+    pub fn new(kdf: Kdf, key: [u8; 32]) -> Self {
+        Self {
+            kdf,
+            key,
+            recipients: Vec::new(),
+        }
+    }
+}
+
+/// A manifest encrypted under a key derived from a passphrase.
+///
+/// Slabs carry the keys needed to decrypt them, so a plaintext manifest is
+/// as sensitive as the data it describes. Sealing the whole manifest means
+/// the passphrase, not the manifest file, is the secret.
+///
+/// The key the manifest is encrypted with can also be wrapped for
+/// recipients, each of whom can then open it with their own recipient key
+/// without knowing the passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedManifest {
+    pub version: u32,
+    pub kdf: Kdf,
+    #[serde(with = "hex")]
+    pub nonce: [u8; 24],
+    #[serde(with = "hex")]
+    pub ciphertext: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<Stanza>,
+}
+
+impl SealedManifest {
+    /// Encrypts `manifest` as `sealing` says, wrapping the key for each of
+    /// its recipients.
+    pub fn seal(manifest: &AnyManifest, sealing: &Sealing) -> Result<Self> {
+        let plaintext = serde_json::to_vec(manifest)?;
+        let nonce: [u8; 24] = rand::random();
+        let key = manifest_key(&sealing.key);
+        let ciphertext = cipher(&key)
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| Error::Crypto("failed to seal manifest".into()))?;
+        let recipients = sealing
+            .recipients
+            .iter()
+            .map(|recipient| Stanza::wrap(&key, *recipient))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            kdf: sealing.kdf.clone(),
+            nonce,
+            ciphertext,
+            recipients,
+        })
+    }
+
+    /// Opens the manifest with the key derived from its passphrase.
+    pub fn open(&self, key: &[u8; 32]) -> Result<AnyManifest> {
+        self.decrypt(&manifest_key(key), "wrong passphrase?")
+    }
+
+    pub async fn open_with_passphrase(&self, passphrase: &str) -> Result<AnyManifest> {
+        let key = self.kdf.derive(passphrase).await?;
+        self.open(&key)
+    }
+
+    /// Opens the manifest with a recipient key, or returns `None` if it
+    /// wasn't shared with that key's recipient.
+    pub fn open_as(&self, recipient_key: &RecipientKey) -> Result<Option<AnyManifest>> {
+        for stanza in &self.recipients {
+            if let Some(key) = recipient_key.unwrap(stanza)? {
+                return self.decrypt(&key, "corrupt manifest?").map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns how to seal the manifest again as it is sealed now, given
+    /// the key derived from its passphrase.
+    pub fn sealing(&self, key: [u8; 32]) -> Sealing {
+        Sealing {
+            kdf: self.kdf.clone(),
+            key,
+            recipients: self
+                .recipients
+                .iter()
+                .map(|stanza| stanza.recipient)
+                .collect(),
+        }
+    }
+
+    fn decrypt(&self, key: &[u8; 32], hint: &str) -> Result<AnyManifest> {
+        let plaintext = cipher(key)
+            .decrypt(XNonce::from_slice(&self.nonce), self.ciphertext.as_slice())
+            .map_err(|_| Error::Crypto(format!("failed to open manifest: {hint}")))?;
+        let manifest: AnyManifest = serde_json::from_slice(&plaintext)?;
+        manifest.check_version()?;
+        Ok(manifest)
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        write(path.as_ref(), self).await
+    }
+}
+
+/// The key the manifest is sealed with, kept separate from the object
+/// encryption key derived from the same passphrase. It is what recipients
+/// are given, so they can't derive the object keys.
+fn manifest_key(key: &[u8; 32]) -> [u8; 32] {
+    keys::subkey(key, "manifest")
+}
+
+fn cipher(key: &[u8; 32]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+/// A manifest as stored on disk, sealed or not.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StoredManifest {
+    Sealed(SealedManifest),
+    Plain(AnyManifest),
+}
+
+impl StoredManifest {
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let manifest: Self = read(path.as_ref()).await?;
+        manifest.check_version()?;
+        Ok(manifest)
+    }
+
+    /// Wraps `manifest`, sealing it if `sealing` is set.
+    pub fn new(manifest: AnyManifest, sealing: Option<&Sealing>) -> Result<Self> {
+        match sealing {
+            Some(sealing) => Ok(StoredManifest::Sealed(SealedManifest::seal(
+                &manifest, sealing,
+            )?)),
+            None => Ok(StoredManifest::Plain(manifest)),
+        }
+    }
+
+    pub fn check_version(&self) -> Result<()> {
+        match self {
+            StoredManifest::Sealed(m) => check_version(m.version),
+            StoredManifest::Plain(m) => m.check_version(),
+        }
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        write(path.as_ref(), self).await
+    }
+}
+
+pub(crate) fn check_version(version: u32) -> Result<()> {
+    if !(1..=MANIFEST_VERSION).contains(&version) {
+        return Err(Error::Manifest(format!("unsupported version {version}")));
+    }
+    Ok(())
+}
+
+pub(crate) async fn read<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let buf = fs::read(path).await?;
+    match Format::from_path(path) {
+        Format::Json => Ok(serde_json::from_slice(&buf)?),
+        Format::Cbor => {
+            ciborium::from_reader(buf.as_slice()).map_err(|e| Error::Manifest(e.to_string()))
+        }
+    }
+}
+
+pub(crate) async fn write<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let buf = match Format::from_path(path) {
+        Format::Json => serde_json::to_vec_pretty(value)?,
+        Format::Cbor => {
+            let mut buf = Vec::new();
+            ciborium::into_writer(value, &mut buf).map_err(|e| Error::Manifest(e.to_string()))?;
+            buf
+        }
+    };
+    // written beside the destination and renamed so an existing manifest is
+    // never left half-written
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    // plaintext manifests hold the slab keys, so only the owner may read one
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&tmp).await?;
+    file.write_all(&buf).await?;
+    file.flush().await?;
+    drop(file);
+    // a temporary file left by an earlier run keeps the mode it was made with
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600)).await?;
+    }
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// Returns a short, stable identifier for an encryption key.
+pub fn key_fingerprint(key: &[u8; 32]) -> String {
+    let h = blake2b_simd::Params::new()
+        .hash_length(8)
+        .personal(b"key-fingerprint")
+        .to_state()
+        .update(key)
+        .finalize();
+    hex::encode(h.as_bytes())
+}
+
+/// Serializes an optional checksum as hex, like `hex` does for required
+/// ones.
+mod hex_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<[u8; 32]>, s: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => s.serialize_some(&hex::encode(value)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<[u8; 32]>, D::Error> {
+        let Some(value) = Option::<String>::deserialize(d)? else {
+            return Ok(None);
+        };
+        let mut checksum = [0u8; 32];
+        hex::decode_to_slice(&value, &mut checksum).map_err(serde::de::Error::custom)?;
+        Ok(Some(checksum))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::aead;
+    use crate::mock::temp_dir;
+
+    /// A manifest as version 1 wrote it, before compression, sealing and
+    /// sparse files.
+    fn v1() -> Value {
+        json!({
+            "version": 1,
+            "size": 5,
+            "checksum": hex::encode([9; 32]),
+            "key_fingerprint": key_fingerprint(&[1; 32]),
+            "data_shards": 10,
+            "parity_shards": 20,
+            "slabs": [],
+        })
+    }
+
+    fn current() -> Manifest {
+        Manifest::new(1 << 20, [9; 32], &[1; 32], 10, 20, Vec::new())
+            .with_compression(Some(Compression::zstd(3)))
+            .with_cipher(Some(Cipher::new(aead::Algorithm::XChaCha20Poly1305)))
+            .with_sha256(Some([7; 32]))
+            .with_holes(vec![Hole {
+                offset: 4096,
+                length: 1 << 16,
+            }])
+    }
+
+    #[tokio::test]
+    async fn old_versions_round_trip() {
+        let dir = temp_dir();
+        for version in 1..=MANIFEST_VERSION {
+            let mut value = v1();
+            value["version"] = version.into();
+            let path = dir.join(format!("v{version}.json"));
+            fs::write(&path, value.to_string()).await.unwrap();
+
+            let manifest = Manifest::load(&path).await.unwrap();
+            assert_eq!(manifest.version, version);
+            assert!(manifest.compression.is_none() && manifest.cipher.is_none());
+            assert!(manifest.holes.is_empty() && manifest.sha256.is_none());
+            // saving doesn't upgrade the version or add fields
+            manifest.save(&path).await.unwrap();
+            let saved: Value = serde_json::from_slice(&fs::read(&path).await.unwrap()).unwrap();
+            assert_eq!(saved, value);
+        }
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unknown_versions_are_refused() {
+        let dir = temp_dir();
+        for version in [0, MANIFEST_VERSION + 1] {
+            let mut value = v1();
+            value["version"] = version.into();
+            let path = dir.join("manifest.json");
+            fs::write(&path, value.to_string()).await.unwrap();
+            assert!(matches!(
+                Manifest::load(&path).await,
+                Err(Error::Manifest(_))
+            ));
+            assert!(StoredManifest::load(&path).await.is_err());
+        }
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn current_version_round_trips() {
+        let dir = temp_dir();
+        let manifest = current();
+        let expected = serde_json::to_value(&manifest).unwrap();
+        for name in ["manifest.json", "manifest.cbor"] {
+            let path = dir.join(name);
+            manifest.save(&path).await.unwrap();
+            let loaded = Manifest::load(&path).await.unwrap();
+            assert_eq!(serde_json::to_value(&loaded).unwrap(), expected);
+        }
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn either_kind_loads() {
+        let dir = temp_dir();
+        let path = dir.join("file.json");
+        current().save(&path).await.unwrap();
+        assert!(matches!(
+            AnyManifest::load(&path).await.unwrap(),
+            AnyManifest::File(_)
+        ));
+
+        let entry = FileEntry::new("a/b".into(), current(), Some(1));
+        let directory = DirectoryManifest::new(vec![entry]).with_links(vec![LinkEntry {
+            path: "c".into(),
+            target: "a/b".into(),
+        }]);
+        let path = dir.join("dir.json");
+        directory.save(&path).await.unwrap();
+        match AnyManifest::load(&path).await.unwrap() {
+            AnyManifest::Directory(loaded) => {
+                assert_eq!(loaded.files.len(), 1);
+                assert_eq!(loaded.links[0].target, "a/b");
+                assert_eq!(loaded.size(), 1 << 20);
+            }
+            AnyManifest::File(_) => panic!("directory loaded as a file"),
+        }
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sealed_round_trips() {
+        let dir = temp_dir();
+        let key = [2; 32];
+        let sealing = Sealing::new(Kdf::default(), key);
+        let manifest = AnyManifest::File(current());
+        let stored = StoredManifest::new(manifest.clone(), Some(&sealing)).unwrap();
+        let path = dir.join("sealed.json");
+        stored.save(&path).await.unwrap();
+
+        let StoredManifest::Sealed(sealed) = StoredManifest::load(&path).await.unwrap() else {
+            panic!("sealed manifest loaded as plain");
+        };
+        let opened = sealed.open(&key).unwrap();
+        assert_eq!(
+            serde_json::to_value(&opened).unwrap(),
+            serde_json::to_value(&manifest).unwrap()
+        );
+        assert!(matches!(sealed.open(&[3; 32]), Err(Error::Crypto(_))));
+        assert!(AnyManifest::load(&path).await.is_err());
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::Router;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use log::{info, warn};
+use tokio::net::TcpListener;
+
+use crate::error::Result;
+use crate::retry::ErrorClass;
+
+/// The counters of this process, shared by every client in it.
+pub static METRICS: Metrics = Metrics::new();
+
+/// Counters for monitoring long-running processes, served in the
+/// Prometheus text format.
+#[derive(Debug)]
+pub struct Metrics {
+    uploaded: AtomicU64,
+    downloaded: AtomicU64,
+    app_retries: AtomicU64,
+    host_retries: AtomicU64,
+    host_errors: AtomicU64,
+    queue_depth: AtomicU64,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            uploaded: AtomicU64::new(0),
+            downloaded: AtomicU64::new(0),
+            app_retries: AtomicU64::new(0),
+            host_retries: AtomicU64::new(0),
+            host_errors: AtomicU64::new(0),
+            queue_depth: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts bytes read for upload, before erasure coding.
+    pub fn uploaded(&self, bytes: u64) {
+        self.uploaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn downloaded(&self, bytes: u64) {
+        self.downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn retried(&self, class: ErrorClass) {
+        let counter = match class {
+            ErrorClass::App => &self.app_retries,
+            ErrorClass::Host => &self.host_retries,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a failed transfer to or from hosts.
+    pub fn host_error(&self) {
+        self.host_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sets the number of changed paths and unread change events waiting
+    /// to be synced.
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{name}{labels} {value}");
+            }
+        };
+        metric(
+            "indexd_uploaded_bytes_total",
+            "counter",
+            "Bytes uploaded, before erasure coding.",
+            &[("", get(&self.uploaded))],
+        );
+        metric(
+            "indexd_downloaded_bytes_total",
+            "counter",
+            "Bytes downloaded and decrypted.",
+            &[("", get(&self.downloaded))],
+        );
+        metric(
+            "indexd_retries_total",
+            "counter",
+            "Failed requests that were retried.",
+            &[
+                ("{class=\"app\"}", get(&self.app_retries)),
+                ("{class=\"host\"}", get(&self.host_retries)),
+            ],
+        );
+        metric(
+            "indexd_host_errors_total",
+            "counter",
+            "Failed transfers to or from hosts.",
+            &[("", get(&self.host_errors))],
+        );
+        metric(
+            "indexd_sync_queue_depth",
+            "gauge",
+            "Changed paths and change events waiting to be synced.",
+            &[("", get(&self.queue_depth))],
+        );
+        out
+    }
+}
+
+/// Serves `GET /metrics` on `addr` in the background.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let app = Router::new().route("/metrics", get(metrics));
+    info!("serving metrics on {addr}");
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            warn!("metrics server stopped: {e}");
+        }
+    });
+    Ok(())
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+}
@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use indexd::Slab;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Numbers transfers so a hook can pair the two calls it gets for each.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Which way a transfer moves data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// A single request to the backend: one attempt at uploading a stream or
+/// downloading a run of slabs. Retries and hedged requests are transfers of
+/// their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transfer {
+    pub id: u64,
+    pub direction: Direction,
+}
+
+impl Transfer {
+    pub(crate) fn new(direction: Direction) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            direction,
+        }
+    }
+}
+
+/// How a transfer went.
+#[derive(Debug, Clone, Copy)]
+pub struct Outcome<'a> {
+    /// The plaintext bytes read by an upload or written by a download.
+    pub bytes: u64,
+    pub elapsed: Duration,
+    pub error: Option<&'a Error>,
+}
+
+/// Where one shard of a slab is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    /// The position of the shard's slab among the transfer's slabs.
+    pub slab: usize,
+    /// The position of the shard within its slab; data shards come first.
+    pub index: usize,
+    pub host_key: String,
+    /// The Merkle root of the sector holding the shard.
+    pub root: String,
+}
+
+/// Lists the shards of `slabs`.
+pub fn shards(slabs: &[Slab]) -> impl Iterator<Item = Shard> + '_ {
+    slabs.iter().enumerate().flat_map(|(slab, s)| {
+        s.sectors
+            .iter()
+            .enumerate()
+            .map(move |(index, sector)| Shard {
+                slab,
+                index,
+                host_key: sector.host_key.to_string(),
+                root: sector.root.to_string(),
+            })
+    })
+}
+
+/// Hooks run around every transfer a [`Client`](crate::client::Client)
+/// makes, for logging, client-side accounting or auditing where each
+/// shard went without changing the pipeline. Every hook does nothing by
+/// default.
+///
+/// The SDK moves a slab's shards together and only says where they are
+/// once it is done, so hooks see shards through the slabs of a finished
+/// upload, or the slabs a download is about to fetch, rather than one
+/// shard at a time. Hooks run inline with the transfer and should return
+/// quickly; slow work such as reporting to an outside service belongs on
+/// a task of its own.
+pub trait Middleware: Send + Sync {
+    /// Runs before an upload starts. An error fails the upload without
+    /// sending anything.
+    fn before_upload(&self, transfer: Transfer) -> Result<()> {
+        let _ = transfer;
+        Ok(())
+    }
+
+    /// Runs once an upload finishes, with the slabs it stored if it
+    /// succeeded.
+    fn after_upload(&self, transfer: Transfer, slabs: &[Slab], outcome: Outcome<'_>) {
+        let _ = (transfer, slabs, outcome);
+    }
+
+    /// Runs before the slabs are downloaded. An error fails the download
+    /// without fetching anything.
+    fn before_download(&self, transfer: Transfer, slabs: &[Slab]) -> Result<()> {
+        let _ = (transfer, slabs);
+        Ok(())
+    }
+
+    /// Runs once a download finishes, whether or not it succeeded.
+    fn after_download(&self, transfer: Transfer, slabs: &[Slab], outcome: Outcome<'_>) {
+        let _ = (transfer, slabs, outcome);
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use indexd::{Sector, Slab};
+use sia::signing::PublicKey;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::backend::{Backend, UploadReader};
+use crate::error::{Error, Result};
+use crate::upload::SECTOR_SIZE;
+
+/// An in-memory stand-in for the SDK and the hosts behind it, so the
+/// upload, download and repair pipelines can be exercised without a
+/// network.
+///
+/// Slabs aren't really erasure coded: each one's data is kept once, and
+/// each of its shards is a sector recorded on one of the mock hosts. A slab
+/// can be downloaded while at least its data shards are on hosts that are
+/// up, which is what the real network guarantees too. Hosts can be taken
+/// down, slowed down or made to lose sectors, and transfers can be failed
+/// outright, to see how the client copes.
+pub struct MockBackend {
+    state: Mutex<State>,
+}
+
+struct Host {
+    key: PublicKey,
+    up: bool,
+    delay: Duration,
+    sectors: HashSet<String>,
+}
+
+struct StoredSlab {
+    data: Bytes,
+    data_shards: usize,
+}
+
+#[derive(Default)]
+struct State {
+    hosts: Vec<Host>,
+    /// Keyed by the root of each slab's first sector.
+    slabs: HashMap<String, StoredSlab>,
+    uploads: u64,
+    /// Transfers still to fail before any succeeds again.
+    failures: u32,
+}
+
+impl MockBackend {
+    /// Returns a backend with `hosts` hosts, all up and without delay.
+    pub fn new(hosts: usize) -> Self {
+        let hosts = (0..hosts)
+            .map(|i| Host {
+                key: PublicKey::new(hash(&[b"host", &(i as u64).to_le_bytes()[..]])),
+                up: true,
+                delay: Duration::ZERO,
+                sectors: HashSet::new(),
+            })
+            .collect();
+        Self {
+            state: Mutex::new(State {
+                hosts,
+                ..State::default()
+            }),
+        }
+    }
+
+    /// The public keys of the hosts, as `ed25519:...`.
+    pub fn host_keys(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.hosts.iter().map(|h| h.key.to_string()).collect()
+    }
+
+    /// Takes a host down or brings it back. A host that is down holds on
+    /// to its sectors but can't serve them or take new ones.
+    pub fn set_up(&self, host: &str, up: bool) {
+        if let Some(host) = self.state.lock().unwrap().host_mut(host) {
+            host.up = up;
+        }
+    }
+
+    /// Makes every transfer a host takes part in take at least `delay`.
+    pub fn set_delay(&self, host: &str, delay: Duration) {
+        if let Some(host) = self.state.lock().unwrap().host_mut(host) {
+            host.delay = delay;
+        }
+    }
+
+    /// Drops the sector of shard `shard` of `slab` from the host holding
+    /// it, as if the host had lost it.
+    pub fn lose_shard(&self, slab: &Slab, shard: usize) {
+        let Some(sector) = slab.sectors.get(shard) else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        if let Some(host) = state.host_mut(&sector.host_key.to_string()) {
+            host.sectors.remove(&sector.root.to_string());
+        }
+    }
+
+    /// Fails the next `count` uploads and downloads, whatever the hosts.
+    pub fn fail_next(&self, count: u32) {
+        self.state.lock().unwrap().failures = count;
+    }
+
+    /// The number of slabs uploaded so far.
+    pub fn slabs(&self) -> usize {
+        self.state.lock().unwrap().slabs.len()
+    }
+
+    /// Stores one slab of `data` on the first hosts up after the previous
+    /// slab's, so slabs are spread over every host.
+    fn store(
+        &self,
+        data: Vec<u8>,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> Result<Slab> {
+        let mut state = self.state.lock().unwrap();
+        state.take_failure("upload")?;
+        let total = data_shards as usize + parity_shards as usize;
+        let up: Vec<usize> = (0..state.hosts.len())
+            .filter(|&i| state.hosts[i].up)
+            .collect();
+        if up.len() < total {
+            return Err(Error::Host(format!(
+                "only {} hosts are up to store {total} shards",
+                up.len()
+            )));
+        }
+        state.uploads += 1;
+        let upload = state.uploads;
+        let start = upload as usize % up.len();
+        let mut sectors = Vec::with_capacity(total);
+        for shard in 0..total {
+            let host = &mut state.hosts[up[(start + shard) % up.len()]];
+            let root = hash(&[
+                b"sector",
+                &upload.to_le_bytes()[..],
+                &(shard as u64).to_le_bytes()[..],
+            ]);
+            let sector = Sector {
+                root: root.into(),
+                host_key: host.key.clone(),
+            };
+            host.sectors.insert(sector.root.to_string());
+            sectors.push(sector);
+        }
+        let length = data.len() as u32;
+        state.slabs.insert(
+            sectors[0].root.to_string(),
+            StoredSlab {
+                data: data.into(),
+                data_shards: data_shards as usize,
+            },
+        );
+        Ok(Slab {
+            encryption_key: encryption_key.into(),
+            min_shards: data_shards,
+            sectors,
+            offset: 0,
+            length,
+        })
+    }
+
+    /// Returns the part of a slab's data it covers and how long fetching
+    /// it takes from the fastest hosts able to serve it.
+    fn fetch(&self, slab: &Slab) -> Result<(Bytes, Duration)> {
+        let mut state = self.state.lock().unwrap();
+        state.take_failure("download")?;
+        let stored = slab
+            .sectors
+            .first()
+            .and_then(|sector| state.slabs.get(&sector.root.to_string()))
+            .ok_or_else(|| Error::Host("the slab isn't stored on any host".into()))?;
+        let mut delays: Vec<Duration> = slab
+            .sectors
+            .iter()
+            .filter_map(|sector| {
+                let host = state.host(&sector.host_key.to_string())?;
+                (host.up && host.sectors.contains(&sector.root.to_string())).then_some(host.delay)
+            })
+            .collect();
+        if delays.len() < stored.data_shards {
+            return Err(Error::Host(format!(
+                "only {} of the {} shards needed are available",
+                delays.len(),
+                stored.data_shards
+            )));
+        }
+        delays.sort();
+        let delay = delays[stored.data_shards - 1];
+        let start = (slab.offset as usize).min(stored.data.len());
+        let end = (start + slab.length as usize).min(stored.data.len());
+        Ok((stored.data.slice(start..end), delay))
+    }
+}
+
+impl State {
+    fn host(&self, key: &str) -> Option<&Host> {
+        self.hosts.iter().find(|h| h.key.to_string() == key)
+    }
+
+    fn host_mut(&mut self, key: &str) -> Option<&mut Host> {
+        self.hosts.iter_mut().find(|h| h.key.to_string() == key)
+    }
+
+    fn take_failure(&mut self, what: &str) -> Result<()> {
+        if self.failures == 0 {
+            return Ok(());
+        }
+        self.failures -= 1;
+        Err(Error::Host(format!("injected {what} failure")))
+    }
+}
+
+impl Backend for MockBackend {
+    fn upload(
+        &self,
+        mut reader: UploadReader,
+        encryption_key: [u8; 32],
+        data_shards: u8,
+        parity_shards: u8,
+    ) -> BoxFuture<'_, Result<Vec<Slab>>> {
+        Box::pin(async move {
+            let slab_size = data_shards as u64 * SECTOR_SIZE;
+            let mut slabs = Vec::new();
+            loop {
+                let mut data = Vec::new();
+                (&mut reader).take(slab_size).read_to_end(&mut data).await?;
+                if data.is_empty() {
+                    break;
+                }
+                slabs.push(self.store(data, encryption_key, data_shards, parity_shards)?);
+            }
+            Ok(slabs)
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        w: &'a mut (dyn AsyncWrite + Unpin + Send),
+        slabs: &'a [Slab],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            for slab in slabs {
+                let (data, delay) = self.fetch(slab)?;
+                tokio::time::sleep(delay).await;
+                w.write_all(&data).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Returns a fresh directory under the system temporary directory for a
+/// test to put its files in.
+#[cfg(test)]
+pub(crate) fn temp_dir() -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("indexd-utils-{:016x}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Returns `length` bytes that don't repeat within a slab, so misplaced
+/// data is caught.
+#[cfg(test)]
+pub(crate) fn pattern(length: usize) -> Vec<u8> {
+    (0..length as u64)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect()
+}
+
+/// A retry policy making `max_attempts` attempts without waiting between
+/// them.
+#[cfg(test)]
+pub(crate) fn retries(max_attempts: u32) -> crate::retry::RetryPolicy {
+    crate::retry::RetryPolicy {
+        max_attempts,
+        base_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+        ..Default::default()
+    }
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 32] {
+    let mut state = blake2b_simd::Params::new().hash_length(32).to_state();
+    for part in parts {
+        state.update(part);
+    }
+    state.finalize().as_bytes().try_into().unwrap()
+}
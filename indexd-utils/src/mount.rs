@@ -0,0 +1,348 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen,
+    Request,
+};
+use log::warn;
+use tokio::runtime::Handle;
+
+use crate::client::Client;
+use crate::download;
+use crate::manifest::{AnyManifest, Manifest};
+
+/// The unit objects are fetched and cached in.
+pub const BLOCK_SIZE: u64 = 1 << 20;
+
+const ROOT: u64 = 1;
+const TTL: Duration = Duration::from_secs(60);
+
+enum Node {
+    Dir(BTreeMap<String, u64>),
+    File(Arc<Manifest>),
+}
+
+struct Inode {
+    parent: u64,
+    node: Node,
+    modified: SystemTime,
+}
+
+/// An in-memory tree of catalogued objects.
+pub struct Tree {
+    inodes: Vec<Inode>,
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tree {
+    pub fn new() -> Self {
+        // inode numbers start at 1, so slot 0 is unused
+        let root = || Inode {
+            parent: ROOT,
+            node: Node::Dir(BTreeMap::new()),
+            modified: UNIX_EPOCH,
+        };
+        Self {
+            inodes: vec![root(), root()],
+        }
+    }
+
+    /// Adds a catalogued object at `name`. `/` separated names become
+    /// nested directories and directory manifests become directories.
+    /// Entries that collide with an existing one are skipped.
+    pub fn insert(&mut self, name: &str, manifest: AnyManifest, modified: u64) {
+        let modified = UNIX_EPOCH + Duration::from_secs(modified);
+        match manifest {
+            AnyManifest::File(manifest) => self.insert_file(ROOT, name, manifest, modified),
+            AnyManifest::Directory(dir) => {
+                let Some(root) = self.make_dirs(ROOT, name, modified) else {
+                    warn!("skipping {name}: conflicts with another entry");
+                    return;
+                };
+                for entry in dir.files {
+                    self.insert_file(root, &entry.path, entry.manifest, modified);
+                }
+            }
+        }
+    }
+
+    fn insert_file(&mut self, dir: u64, path: &str, manifest: Manifest, modified: SystemTime) {
+        let (parent, name) = match path.rsplit_once('/') {
+            Some((parent, name)) => match self.make_dirs(dir, parent, modified) {
+                Some(parent) => (parent, name),
+                None => {
+                    warn!("skipping {path}: conflicts with another entry");
+                    return;
+                }
+            },
+            None => (dir, path),
+        };
+        if name.is_empty() || self.child(parent, name).is_some() {
+            warn!("skipping {path}: conflicts with another entry");
+            return;
+        }
+        self.push(parent, name, Node::File(Arc::new(manifest)), modified);
+    }
+
+    /// Returns the directory at `path` under `dir`, creating it and its
+    /// parents as needed, or `None` if a file is in the way.
+    fn make_dirs(&mut self, mut dir: u64, path: &str, modified: SystemTime) -> Option<u64> {
+        for part in path.split('/').filter(|part| !part.is_empty()) {
+            dir = match self.child(dir, part) {
+                Some(ino) if matches!(self.inodes[ino as usize].node, Node::Dir(_)) => ino,
+                Some(_) => return None,
+                None => self.push(dir, part, Node::Dir(BTreeMap::new()), modified),
+            };
+        }
+        Some(dir)
+    }
+
+    fn push(&mut self, parent: u64, name: &str, node: Node, modified: SystemTime) -> u64 {
+        let ino = self.inodes.len() as u64;
+        self.inodes.push(Inode {
+            parent,
+            node,
+            modified,
+        });
+        if let Node::Dir(children) = &mut self.inodes[parent as usize].node {
+            children.insert(name.to_string(), ino);
+        }
+        ino
+    }
+
+    fn child(&self, dir: u64, name: &str) -> Option<u64> {
+        match &self.inodes.get(dir as usize)?.node {
+            Node::Dir(children) => children.get(name).copied(),
+            Node::File(_) => None,
+        }
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let inode = self.inodes.get(ino as usize).filter(|_| ino >= ROOT)?;
+        let (kind, size, perm, nlink) = match &inode.node {
+            Node::Dir(_) => (FileType::Directory, 0, 0o555, 2),
+            Node::File(manifest) => (FileType::RegularFile, manifest.size, 0o444, 1),
+        };
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: inode.modified,
+            mtime: inode.modified,
+            ctime: inode.modified,
+            crtime: inode.modified,
+            kind,
+            perm,
+            nlink,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            rdev: 0,
+            blksize: BLOCK_SIZE as u32,
+            flags: 0,
+        })
+    }
+}
+
+/// Caches the most recently read blocks of every file, up to a total size.
+struct BlockCache {
+    capacity: u64,
+    used: u64,
+    tick: u64,
+    blocks: HashMap<(u64, u64), (u64, Arc<Vec<u8>>)>,
+    /// Maps each block's last use to the block, oldest first.
+    order: BTreeMap<u64, (u64, u64)>,
+}
+
+impl BlockCache {
+    fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            tick: 0,
+            blocks: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: (u64, u64)) -> Option<Arc<Vec<u8>>> {
+        let (last_used, block) = self.blocks.get_mut(&key)?;
+        self.order.remove(last_used);
+        self.tick += 1;
+        *last_used = self.tick;
+        self.order.insert(self.tick, key);
+        Some(block.clone())
+    }
+
+    fn insert(&mut self, key: (u64, u64), block: Arc<Vec<u8>>) {
+        self.used += block.len() as u64;
+        self.tick += 1;
+        if let Some((last_used, old)) = self.blocks.insert(key, (self.tick, block)) {
+            self.order.remove(&last_used);
+            self.used -= old.len() as u64;
+        }
+        self.order.insert(self.tick, key);
+        while self.used > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((_, block)) = self.blocks.remove(&oldest) {
+                self.used -= block.len() as u64;
+            }
+        }
+    }
+}
+
+/// A read-only filesystem over a `Tree`, fetching file contents on demand
+/// with ranged downloads.
+///
+/// FUSE calls are served one at a time, so a read waits for any download
+/// in progress.
+pub struct CatalogFs {
+    tree: Tree,
+    sdk: Client,
+    runtime: Handle,
+    cache: BlockCache,
+}
+
+impl CatalogFs {
+    /// `runtime` drives the downloads, since FUSE calls are made from a
+    /// blocking thread. `cache_size` bounds the block cache in bytes.
+    pub fn new(tree: Tree, sdk: Client, runtime: Handle, cache_size: u64) -> Self {
+        Self {
+            tree,
+            sdk,
+            runtime,
+            cache: BlockCache::new(cache_size),
+        }
+    }
+
+    fn block(
+        &mut self,
+        ino: u64,
+        manifest: &Manifest,
+        index: u64,
+    ) -> crate::error::Result<Arc<Vec<u8>>> {
+        if let Some(block) = self.cache.get((ino, index)) {
+            return Ok(block);
+        }
+        let offset = index * BLOCK_SIZE;
+        let length = BLOCK_SIZE.min(manifest.size - offset);
+        let mut buf = Vec::with_capacity(length as usize);
+        self.runtime.block_on(download::download_object(
+            &self.sdk, &mut buf, manifest, offset, length,
+        ))?;
+        let block = Arc::new(buf);
+        self.cache.insert((ino, index), block.clone());
+        Ok(block)
+    }
+}
+
+impl Filesystem for CatalogFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match name
+            .to_str()
+            .and_then(|name| self.tree.child(parent, name))
+            .and_then(|ino| self.tree.attr(ino))
+        {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.tree.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            reply.error(libc::EROFS);
+        } else if self.tree.attr(ino).is_none() {
+            reply.error(libc::ENOENT);
+        } else {
+            reply.opened(0, 0);
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let manifest = match self.tree.inodes.get(ino as usize).map(|inode| &inode.node) {
+            Some(Node::File(manifest)) => manifest.clone(),
+            Some(Node::Dir(_)) => return reply.error(libc::EISDIR),
+            None => return reply.error(libc::ENOENT),
+        };
+        let start = (offset.max(0) as u64).min(manifest.size);
+        let end = (start + size as u64).min(manifest.size);
+        let mut data = Vec::with_capacity((end - start) as usize);
+        let mut pos = start;
+        while pos < end {
+            let index = pos / BLOCK_SIZE;
+            let block = match self.block(ino, &manifest, index) {
+                Ok(block) => block,
+                Err(e) => {
+                    warn!("read of inode {ino} at {pos} failed: {e}");
+                    return reply.error(libc::EIO);
+                }
+            };
+            let from = (pos - index * BLOCK_SIZE) as usize;
+            let to = ((end - index * BLOCK_SIZE) as usize).min(block.len());
+            data.extend_from_slice(&block[from..to]);
+            pos = index * BLOCK_SIZE + to as u64;
+        }
+        reply.data(&data);
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(inode) = self.tree.inodes.get(ino as usize) else {
+            return reply.error(libc::ENOENT);
+        };
+        let Node::Dir(children) = &inode.node else {
+            return reply.error(libc::ENOTDIR);
+        };
+        let entries = [
+            (ino, FileType::Directory, "."),
+            (inode.parent, FileType::Directory, ".."),
+        ]
+        .into_iter()
+        .chain(children.iter().map(|(name, &child)| {
+            let kind = match self.tree.inodes[child as usize].node {
+                Node::Dir(_) => FileType::Directory,
+                Node::File(_) => FileType::RegularFile,
+            };
+            (child, kind, name.as_str())
+        }));
+        for (i, (child, kind, name)) in entries.enumerate().skip(offset.max(0) as usize) {
+            // the offset passed back is that of the next entry
+            if reply.add(child, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use url::Url;
+
+use crate::error::{Error, Result};
+
+/// A proxy to send outbound connections through: `http://`, `https://`,
+/// `socks5://` or `socks5h://`, the last resolving names on the proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proxy(Url);
+
+impl FromStr for Proxy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let url = Url::parse(s).map_err(|e| Error::Usage(format!("invalid proxy {s:?}: {e}")))?;
+        if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+            return Err(Error::Usage(format!(
+                "unsupported proxy scheme {:?}; expected http, https, socks5 or socks5h",
+                url.scheme()
+            )));
+        }
+        Ok(Self(url))
+    }
+}
+
+/// Shows the proxy without its credentials, for logs.
+impl fmt::Display for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut url = self.0.clone();
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+        write!(f, "{url}")
+    }
+}
+
+/// Parses an address to listen on. A bare `:port` listens on every
+/// interface.
+pub fn parse_listen(s: &str) -> std::result::Result<SocketAddr, String> {
+    let s = match s.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{port}"),
+        None => s.to_string(),
+    };
+    s.parse().map_err(|e| format!("invalid address {s:?}: {e}"))
+}
+
+/// How the TLS connections made by [`Network::http_client`]'s clients are
+/// verified and authenticated, for self-hosted deployments with their own
+/// PKI. The SDK's connection to the app doesn't use them.
+#[derive(Debug, Clone, Default)]
+pub struct Tls {
+    /// PEM certificates trusted in addition to the system's roots.
+    pub ca_bundle: Option<PathBuf>,
+    /// A PEM file holding the client certificate, its chain and its private
+    /// key.
+    pub client_cert: Option<PathBuf>,
+    /// Accept any server certificate. Only for lab setups: it lets anyone
+    /// on the path read and change the traffic.
+    pub insecure_skip_verify: bool,
+}
+
+impl Tls {
+    pub fn is_default(&self) -> bool {
+        self.ca_bundle.is_none() && self.client_cert.is_none() && !self.insecure_skip_verify
+    }
+}
+
+/// How outbound connections are made.
+#[derive(Debug, Clone, Default)]
+pub struct Network {
+    /// Sends the connections made by [`Network::http_client`]'s clients
+    /// through this proxy. Without one, they follow `HTTPS_PROXY`,
+    /// `ALL_PROXY` and `NO_PROXY` if they are set.
+    pub proxy: Option<Proxy>,
+    pub tls: Tls,
+}
+
+impl Network {
+    /// Returns a builder for HTTP clients that connect as configured, for
+    /// callers to add their own timeouts and redirect policy to. Only
+    /// webhooks, URL sources and doctor's checks use them; the SDK makes the
+    /// app connection and dials hosts itself.
+    pub fn http_client(&self) -> Result<reqwest::ClientBuilder> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(Proxy(url)) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(url.as_str())?);
+        }
+        if let Some(path) = &self.tls.ca_bundle {
+            let pem = fs::read(path)?;
+            let certs = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| Error::Config(format!("invalid CA bundle {}: {e}", path.display())))?;
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(path) = &self.tls.client_cert {
+            let pem = fs::read(path)?;
+            let identity = reqwest::Identity::from_pem(&pem).map_err(|e| {
+                Error::Config(format!(
+                    "invalid client certificate {}: {e}",
+                    path.display()
+                ))
+            })?;
+            builder = builder.identity(identity);
+        }
+        if self.tls.insecure_skip_verify {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder)
+    }
+}
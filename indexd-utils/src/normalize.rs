@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use log::warn;
+use tokio::fs;
+use unicode_normalization::{UnicodeNormalization, is_nfc};
+
+use crate::error::{Error, Result};
+
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Returns a file name in the composed form (NFC) manifests store.
+///
+/// macOS hands out names decomposed (NFD) while Linux and Windows keep
+/// what they were given, so the same name can arrive in either form.
+/// Storing one form means a file keeps its name, and its catalog entry,
+/// whichever system uploads or restores it.
+pub fn name(part: &str) -> String {
+    if is_nfc(part) {
+        part.to_string()
+    } else {
+        part.nfc().collect()
+    }
+}
+
+/// Why a manifest path can't be written on Windows, if it can't.
+pub fn windows_problem(path: &str) -> Option<String> {
+    for part in path.split('/') {
+        let stem = part.split('.').next().unwrap_or(part).trim_end();
+        if RESERVED.contains(&stem.to_lowercase().as_str()) {
+            return Some(format!("{part:?} is a reserved name"));
+        }
+        if part.ends_with('.') || part.ends_with(' ') {
+            return Some(format!("{part:?} ends with a dot or a space"));
+        }
+        if let Some(c) = part
+            .chars()
+            .find(|c| c.is_control() || r#"<>:"\|?*"#.contains(*c))
+        {
+            return Some(format!("{part:?} contains {c:?}"));
+        }
+    }
+    None
+}
+
+/// Checks the manifest paths of a directory upload, each paired with the
+/// path it was read from. Files whose names only differ in their Unicode
+/// form would be stored under the same name, so they fail the upload;
+/// names that differ only in case, or that Windows can't write, are stored
+/// as they are with a warning that restoring them there will fail.
+pub fn check_upload(paths: &[(String, &Path)]) -> Result<()> {
+    let mut seen: HashMap<&str, &Path> = HashMap::new();
+    for (path, rel) in paths {
+        if let Some(other) = seen.insert(path.as_str(), *rel) {
+            return Err(Error::Usage(format!(
+                "{} and {} have the same name in different Unicode forms; rename one of them",
+                other.display(),
+                rel.display()
+            )));
+        }
+    }
+    let names: Vec<&str> = paths.iter().map(|(path, _)| path.as_str()).collect();
+    if let Some((a, b)) = case_collision(&names) {
+        warn!("{a} and {b} differ only in case and can't both be restored on Windows or macOS");
+    }
+    for path in names {
+        if let Some(problem) = windows_problem(path) {
+            warn!("{path} can't be restored on Windows: {problem}");
+        }
+    }
+    Ok(())
+}
+
+/// Checks, before anything is written, that every path of a directory
+/// restore can be written under `root` without one file clobbering
+/// another: on a filesystem that ignores case, as Windows and macOS do by
+/// default, no two may differ only in case or Unicode form, and on Windows
+/// none may be a name it reserves.
+pub async fn check_restore(root: &Path, paths: &[&str]) -> Result<()> {
+    if cfg!(windows) {
+        for path in paths {
+            if let Some(problem) = windows_problem(path) {
+                return Err(Error::Usage(format!(
+                    "{path} can't be restored on Windows: {problem}"
+                )));
+            }
+        }
+    }
+    if !ignores_case(root).await? {
+        return Ok(());
+    }
+    match case_collision(paths) {
+        Some((a, b)) => Err(Error::Usage(format!(
+            "{a} and {b} would be restored as the same file under {}, which ignores case",
+            root.display()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Returns the first two paths that are the same once case and Unicode
+/// form are ignored.
+fn case_collision<'a>(paths: &[&'a str]) -> Option<(&'a str, &'a str)> {
+    let mut seen = HashMap::new();
+    for path in paths {
+        let folded = name(path).to_lowercase();
+        if let Some(other) = seen.insert(folded, *path) {
+            return Some((other, *path));
+        }
+    }
+    None
+}
+
+/// Whether the filesystem at `root` treats names that differ in case as
+/// the same, found by looking up a probe file under another case.
+async fn ignores_case(root: &Path) -> Result<bool> {
+    fs::create_dir_all(root).await?;
+    let probe = format!(".upload-rs-case-probe-{:016x}", rand::random::<u64>());
+    let path = root.join(&probe);
+    fs::write(&path, b"").await?;
+    let ignores = fs::try_exists(root.join(probe.to_uppercase())).await;
+    fs::remove_file(&path).await?;
+    Ok(ignores?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_composed() {
+        let decomposed = "cafe\u{301}";
+        assert_eq!(name(decomposed), "caf\u{e9}");
+        assert_eq!(name("caf\u{e9}"), "caf\u{e9}");
+        assert_eq!(name("plain"), "plain");
+    }
+
+    #[test]
+    fn windows_problems() {
+        assert_eq!(windows_problem("photos/2024/a.jpg"), None);
+        assert!(windows_problem("con").is_some());
+        assert!(windows_problem("dir/NUL.txt").is_some());
+        assert!(windows_problem("dir/lpt1 .log").is_some());
+        assert!(windows_problem("trailing./a").is_some());
+        assert!(windows_problem("a/trailing ").is_some());
+        assert!(windows_problem("a:b").is_some());
+        assert!(windows_problem("what?").is_some());
+        assert!(windows_problem("tab\there").is_some());
+        // only whole names are reserved
+        assert_eq!(windows_problem("console/aux1.txt"), None);
+    }
+
+    #[test]
+    fn upload_rejects_unicode_collisions() {
+        let nfc = Path::new("caf\u{e9}");
+        let nfd = Path::new("cafe\u{301}");
+        let paths = [(name("caf\u{e9}"), nfc), (name("cafe\u{301}"), nfd)];
+        assert!(matches!(check_upload(&paths), Err(Error::Usage(_))));
+
+        // names differing in case only warn
+        let paths = [
+            ("Readme".to_string(), Path::new("Readme")),
+            ("README".to_string(), Path::new("README")),
+        ];
+        assert!(check_upload(&paths).is_ok());
+    }
+
+    #[test]
+    fn case_collisions() {
+        assert_eq!(case_collision(&["a/B", "c", "A/b"]), Some(("a/B", "A/b")));
+        assert_eq!(
+            case_collision(&["caf\u{e9}", "CAFE\u{301}"]),
+            Some(("caf\u{e9}", "CAFE\u{301}"))
+        );
+        assert_eq!(case_collision(&["a", "b"]), None);
+    }
+
+    #[tokio::test]
+    async fn restore_without_collisions() {
+        let root = crate::mock::temp_dir();
+        check_restore(&root, &["a", "b/c"]).await.unwrap();
+        // the probe is cleaned up
+        let mut entries = fs::read_dir(&root).await.unwrap();
+        assert!(entries.next_entry().await.unwrap().is_none());
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+}
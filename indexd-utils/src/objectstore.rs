@@ -0,0 +1,461 @@
+//! Reading objects straight from S3-compatible stores and Google Cloud
+//! Storage, with credentials found the way their own tools find them.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL};
+use hmac::{Hmac, Mac};
+use log::{debug, info};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Client, Response};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::error::{Error, Result};
+
+/// What SigV4 leaves unescaped in paths: the unreserved characters.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The region S3 requests are signed for when none is configured.
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// The scope a Google token is asked for.
+const GCS_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_only";
+
+const GOOGLE_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+
+/// The bucket and key of an `s3://` or `gs://` URL.
+fn bucket_and_key(url: &Url) -> Result<(&str, String)> {
+    let bucket = url
+        .host_str()
+        .filter(|bucket| !bucket.is_empty())
+        .ok_or_else(|| Error::Usage(format!("{url} has no bucket")))?;
+    let key = percent_decode_str(url.path().trim_start_matches('/'))
+        .decode_utf8()
+        .map_err(|_| Error::Usage(format!("{url} has a key that isn't UTF-8")))?;
+    if key.is_empty() {
+        return Err(Error::Usage(format!("{url} has no object key")));
+    }
+    Ok((bucket, key.into_owned()))
+}
+
+/// Escapes an object key for a request path, keeping its slashes.
+fn encode_key(key: &str) -> String {
+    key.split('/')
+        .map(|segment| utf8_percent_encode(segment, UNRESERVED).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// An AWS access key, with the session token of temporary credentials.
+struct AwsCredentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+/// Reads the sections of an AWS-style INI file. Missing files read as
+/// empty.
+fn read_ini(path: &Path) -> Result<HashMap<String, HashMap<String, String>>> {
+    let buf = match fs::read_to_string(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut section = None;
+    for line in buf.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = Some(name.trim().to_string());
+            continue;
+        }
+        let (Some(section), Some((key, value))) = (&section, line.split_once('=')) else {
+            continue;
+        };
+        sections
+            .entry(section.clone())
+            .or_default()
+            .insert(key.trim().to_lowercase(), value.trim().to_string());
+    }
+    Ok(sections)
+}
+
+fn home_path(rel: &str) -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| Path::new(&home).join(rel))
+}
+
+/// The AWS settings of the selected profile: `$AWS_PROFILE`, or `default`.
+struct AwsProfile {
+    credentials: HashMap<String, String>,
+    config: HashMap<String, String>,
+}
+
+impl AwsProfile {
+    fn load() -> Result<Self> {
+        let name = env::var("AWS_PROFILE").unwrap_or_else(|_| "default".into());
+        let credentials_path = env::var_os("AWS_SHARED_CREDENTIALS_FILE")
+            .map(PathBuf::from)
+            .or_else(|| home_path(".aws/credentials"));
+        let config_path = env::var_os("AWS_CONFIG_FILE")
+            .map(PathBuf::from)
+            .or_else(|| home_path(".aws/config"));
+        let mut credentials = match credentials_path {
+            Some(path) => read_ini(&path)?,
+            None => HashMap::new(),
+        };
+        let mut config = match config_path {
+            Some(path) => read_ini(&path)?,
+            None => HashMap::new(),
+        };
+        // the config file names every profile but the default `profile NAME`
+        let config_section = if name == "default" {
+            name.clone()
+        } else {
+            format!("profile {name}")
+        };
+        Ok(Self {
+            credentials: credentials.remove(&name).unwrap_or_default(),
+            config: config.remove(&config_section).unwrap_or_default(),
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.credentials
+            .get(key)
+            .or_else(|| self.config.get(key))
+            .map(String::as_str)
+    }
+}
+
+/// Finds AWS credentials in the environment, then in the shared
+/// credentials and config files. Without any, requests go unsigned, which
+/// public buckets allow.
+fn aws_credentials(profile: &AwsProfile) -> Option<AwsCredentials> {
+    if let (Ok(access_key), Ok(secret_key)) = (
+        env::var("AWS_ACCESS_KEY_ID"),
+        env::var("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        return Some(AwsCredentials {
+            access_key,
+            secret_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        });
+    }
+    Some(AwsCredentials {
+        access_key: profile.get("aws_access_key_id")?.to_string(),
+        secret_key: profile.get("aws_secret_access_key")?.to_string(),
+        session_token: profile.get("aws_session_token").map(str::to_string),
+    })
+}
+
+/// Where an S3 object is fetched from: the endpoint in
+/// `$AWS_ENDPOINT_URL_S3` or `$AWS_ENDPOINT_URL` with the bucket in the
+/// path, as S3-compatible stores expect, or AWS itself with the bucket in
+/// the host name.
+fn s3_url(bucket: &str, key: &str, region: &str) -> Result<Url> {
+    let key = encode_key(key);
+    let endpoint = env::var("AWS_ENDPOINT_URL_S3").or_else(|_| env::var("AWS_ENDPOINT_URL"));
+    let url = match endpoint {
+        Ok(endpoint) => format!("{}/{bucket}/{key}", endpoint.trim_end_matches('/')),
+        // a bucket with dots doesn't match the wildcard certificate
+        Err(_) if bucket.contains('.') => {
+            format!("https://s3.{region}.amazonaws.com/{bucket}/{key}")
+        }
+        Err(_) => format!("https://{bucket}.s3.{region}.amazonaws.com/{key}"),
+    };
+    Url::parse(&url).map_err(|e| Error::Config(format!("invalid S3 endpoint {url}: {e}")))
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Formats a time as SigV4 wants it, such as `20250101T120000Z`.
+fn amz_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Returns the date `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Signs a GET of `url` with SigV4, leaving the body unsigned, and returns
+/// the headers to send.
+fn sign_get(url: &Url, region: &str, credentials: &AwsCredentials) -> Vec<(&'static str, String)> {
+    let date = amz_date(SystemTime::now());
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let mut headers = vec![
+        ("host", host),
+        ("x-amz-content-sha256", "UNSIGNED-PAYLOAD".to_string()),
+        ("x-amz-date", date.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let canonical_request = format!(
+        "GET\n{}\n\n{canonical_headers}\n{signed_headers}\nUNSIGNED-PAYLOAD",
+        url.path()
+    );
+    let scope = format!("{}/{region}/s3/aws4_request", &date[..8]);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = hmac(
+        format!("AWS4{}", credentials.secret_key).as_bytes(),
+        &date[..8],
+    );
+    let key = hmac(&key, region);
+    let key = hmac(&key, "s3");
+    let key = hmac(&key, "aws4_request");
+    let signature = hex::encode(hmac(&key, &string_to_sign));
+    // reqwest sets the host header itself
+    headers.remove(0);
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credentials.access_key
+        ),
+    ));
+    headers
+}
+
+async fn s3_get(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    region: &str,
+    credentials: Option<&AwsCredentials>,
+) -> Result<Response> {
+    let url = s3_url(bucket, key, region)?;
+    debug!("fetching s3://{bucket}/{key} from {url}");
+    let mut request = client.get(url.clone());
+    if let Some(credentials) = credentials {
+        for (name, value) in sign_get(&url, region, credentials) {
+            request = request.header(name, value);
+        }
+    }
+    Ok(request.send().await?)
+}
+
+/// Starts a GET of the object an `s3://bucket/key` URL names. The region
+/// comes from `$AWS_REGION`, `$AWS_DEFAULT_REGION` or the profile, and a
+/// bucket found to be in another region is asked again there.
+pub async fn get_s3(url: &Url, client: &Client) -> Result<Response> {
+    let (bucket, key) = bucket_and_key(url)?;
+    let profile = AwsProfile::load()?;
+    let credentials = aws_credentials(&profile);
+    let region = env::var("AWS_REGION")
+        .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+        .ok()
+        .or_else(|| profile.get("region").map(str::to_string))
+        .unwrap_or_else(|| DEFAULT_REGION.to_string());
+    let response = s3_get(client, bucket, &key, &region, credentials.as_ref()).await?;
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let Some(actual) = response
+        .headers()
+        .get("x-amz-bucket-region")
+        .and_then(|value| value.to_str().ok())
+        .filter(|actual| *actual != region)
+        .map(str::to_string)
+    else {
+        return Ok(response.error_for_status()?);
+    };
+    info!("{bucket} is in {actual}; retrying there");
+    Ok(s3_get(client, bucket, &key, &actual, credentials.as_ref())
+        .await?
+        .error_for_status()?)
+}
+
+/// Application default credentials, as `gcloud auth application-default
+/// login` or a service account key file leaves them.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum GoogleCredentials {
+    AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    },
+    ServiceAccount {
+        client_email: String,
+        private_key: String,
+        #[serde(default)]
+        token_uri: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// Finds a Google access token: `$GOOGLE_OAUTH_ACCESS_TOKEN`, or one
+/// exchanged for the application default credentials in
+/// `$GOOGLE_APPLICATION_CREDENTIALS` or gcloud's own file. Without any,
+/// requests go unauthenticated, which public objects allow.
+async fn google_token(client: &Client) -> Result<Option<String>> {
+    if let Ok(token) = env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+        return Ok(Some(token));
+    }
+    let path = match env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let Some(path) = home_path(".config/gcloud/application_default_credentials.json")
+            else {
+                return Ok(None);
+            };
+            if !path.exists() {
+                return Ok(None);
+            }
+            path
+        }
+    };
+    let buf = fs::read(&path)?;
+    let credentials: GoogleCredentials = serde_json::from_slice(&buf).map_err(|e| {
+        Error::Config(format!(
+            "unsupported Google credentials {}: {e}",
+            path.display()
+        ))
+    })?;
+    let (token_uri, form) = match credentials {
+        GoogleCredentials::AuthorizedUser {
+            client_id,
+            client_secret,
+            refresh_token,
+        } => (
+            GOOGLE_TOKEN_URI.to_string(),
+            url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("grant_type", "refresh_token")
+                .append_pair("client_id", &client_id)
+                .append_pair("client_secret", &client_secret)
+                .append_pair("refresh_token", &refresh_token)
+                .finish(),
+        ),
+        GoogleCredentials::ServiceAccount {
+            client_email,
+            private_key,
+            token_uri,
+        } => {
+            let token_uri = token_uri.unwrap_or_else(|| GOOGLE_TOKEN_URI.to_string());
+            let assertion = service_account_jwt(&client_email, &private_key, &token_uri)?;
+            (
+                token_uri,
+                url::form_urlencoded::Serializer::new(String::new())
+                    .append_pair("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer")
+                    .append_pair("assertion", &assertion)
+                    .finish(),
+            )
+        }
+    };
+    let response = client
+        .post(&token_uri)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(form)
+        .send()
+        .await?
+        .error_for_status()?;
+    let token: TokenResponse = serde_json::from_slice(&response.bytes().await?)?;
+    Ok(Some(token.access_token))
+}
+
+/// Signs the JWT a service account trades for an access token.
+fn service_account_jwt(email: &str, private_key: &str, token_uri: &str) -> Result<String> {
+    let der = private_key
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect::<String>();
+    let der = BASE64
+        .decode(der.trim())
+        .map_err(|_| Error::Config("malformed service account private key".into()))?;
+    let key = ring::signature::RsaKeyPair::from_pkcs8(&der)
+        .map_err(|e| Error::Config(format!("invalid service account private key: {e}")))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let header = BASE64_URL.encode(br#"{"alg":"RS256","typ":"JWT"}"#);
+    let claims = BASE64_URL.encode(serde_json::to_vec(&serde_json::json!({
+        "iss": email,
+        "scope": GCS_SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + 3600,
+    }))?);
+    let message = format!("{header}.{claims}");
+    let mut signature = vec![0; key.public().modulus_len()];
+    key.sign(
+        &ring::signature::RSA_PKCS1_SHA256,
+        &ring::rand::SystemRandom::new(),
+        message.as_bytes(),
+        &mut signature,
+    )
+    .map_err(|_| Error::Crypto("failed to sign the service account token".into()))?;
+    Ok(format!("{message}.{}", BASE64_URL.encode(signature)))
+}
+
+/// Starts a GET of the object a `gs://bucket/object` URL names.
+pub async fn get_gcs(url: &Url, client: &Client) -> Result<Response> {
+    let (bucket, key) = bucket_and_key(url)?;
+    let endpoint = format!(
+        "https://storage.googleapis.com/{}/{}",
+        utf8_percent_encode(bucket, UNRESERVED),
+        encode_key(&key)
+    );
+    let mut request = client.get(&endpoint);
+    if let Some(token) = google_token(client).await? {
+        request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+    }
+    Ok(request.send().await?.error_for_status()?)
+}
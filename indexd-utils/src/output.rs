@@ -0,0 +1,25 @@
+use serde::Serialize;
+
+use crate::error::Result;
+
+/// How results are printed on stdout. Logs, prompts and progress bars
+/// always go to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Output {
+    #[default]
+    Human,
+    /// Every result as one JSON object per line.
+    Json,
+}
+
+impl Output {
+    /// Prints `value` as a line of JSON, or runs `human` to print it for
+    /// people.
+    pub fn print<T: Serialize + ?Sized>(self, value: &T, human: impl FnOnce()) -> Result<()> {
+        match self {
+            Output::Json => println!("{}", serde_json::to_string(value)?),
+            Output::Human => human(),
+        }
+        Ok(())
+    }
+}
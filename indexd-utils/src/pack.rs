@@ -0,0 +1,251 @@
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::aead::Cipher;
+use crate::checksum;
+use crate::client::Client;
+use crate::compression::Compression;
+use crate::download;
+use crate::error::{Error, Result};
+use crate::keys::KeyProvider;
+use crate::manifest::{FileEntry, Manifest};
+use crate::progress::{Event, Progress};
+use crate::upload::{SECTOR_SIZE, UploadOptions};
+
+/// Files up to this size are packed by default, a quarter of a sector.
+pub const DEFAULT_THRESHOLD: u64 = 1 << 20;
+
+/// A packed file waiting for its pack to be uploaded.
+struct Pending {
+    path: String,
+    size: u64,
+    checksum: [u8; 32],
+    sha256: Option<[u8; 32]>,
+    cipher: Option<Cipher>,
+    modified: Option<u64>,
+    /// Where the file's stored bytes start in the pack.
+    offset: u64,
+    length: u64,
+}
+
+/// Packs small files of a directory upload into shared slabs.
+///
+/// Each file would otherwise take up at least a whole slab. Instead the
+/// files are concatenated into packs of one slab's worth of data, and each
+/// file's manifest holds the slices of the pack's slabs covering it, so it
+/// downloads, verifies and repairs like any other. With compression every
+/// file is compressed on its own into a frame of the pack.
+pub struct Packer<'a> {
+    sdk: &'a Client,
+    options: UploadOptions,
+    /// Where the keys of packs come from when sealing. Without it every
+    /// pack gets a random key.
+    keys: Option<&'a dyn KeyProvider>,
+    progress: Progress,
+    data: Vec<u8>,
+    pending: Vec<Pending>,
+    packs: usize,
+}
+
+impl<'a> Packer<'a> {
+    pub fn new(
+        sdk: &'a Client,
+        options: UploadOptions,
+        keys: Option<&'a dyn KeyProvider>,
+        progress: Progress,
+    ) -> Self {
+        Self {
+            sdk,
+            options,
+            keys,
+            progress,
+            data: Vec::new(),
+            pending: Vec::new(),
+            packs: 0,
+        }
+    }
+
+    /// Adds a file to the current pack, returning the entries of the files
+    /// in the pack if it filled up and was uploaded.
+    pub async fn add(
+        &mut self,
+        path: String,
+        data: Vec<u8>,
+        modified: Option<u64>,
+    ) -> Result<Vec<FileEntry>> {
+        let size = data.len() as u64;
+        let checksum = checksum::checksum_bytes(&data);
+        let sha256 = self.options.sha256.then(|| Sha256::digest(&data).into());
+        // each file is sealed on its own so its slice opens by itself
+        let cipher = self.options.aead.map(Cipher::new);
+        let stored = match (self.options.compression, cipher) {
+            (Some(_), Some(_)) => {
+                return Err(Error::Usage(
+                    "compressed uploads can't be sealed with authenticated encryption".into(),
+                ));
+            }
+            (Some(compression), None) => compress(compression, &data)?,
+            (None, Some(cipher)) => cipher.seal_bytes(&data)?,
+            (None, None) => data,
+        };
+        self.pending.push(Pending {
+            path,
+            size,
+            checksum,
+            sha256,
+            cipher,
+            modified,
+            offset: self.data.len() as u64,
+            length: stored.len() as u64,
+        });
+        self.data.extend_from_slice(&stored);
+
+        let pack_size = self.options.data_shards as u64 * SECTOR_SIZE;
+        if (self.data.len() as u64) < pack_size {
+            return Ok(Vec::new());
+        }
+        self.flush().await
+    }
+
+    /// Uploads the last pack and returns the entries of its files.
+    pub async fn finish(mut self) -> Result<Vec<FileEntry>> {
+        self.flush().await
+    }
+
+    async fn flush(&mut self) -> Result<Vec<FileEntry>> {
+        if self.pending.is_empty() {
+            return Ok(Vec::new());
+        }
+        let key = match self.keys {
+            Some(keys) => keys.key(&format!("pack/{}", self.packs)).await?,
+            None => rand::random(),
+        };
+        self.packs += 1;
+        let data = std::mem::take(&mut self.data);
+        let plaintext: u64 = self.pending.iter().map(|p| p.size).sum();
+        debug!(
+            "uploading a pack of {} files in {} bytes",
+            self.pending.len(),
+            data.len()
+        );
+        let slabs = self
+            .sdk
+            .upload_bytes(
+                data.into(),
+                key,
+                self.options.data_shards,
+                self.options.parity_shards,
+            )
+            .await?;
+        self.progress
+            .emit(Event::BytesTransferred { bytes: plaintext });
+
+        Ok(self
+            .pending
+            .drain(..)
+            .map(|pending| {
+                let slices = download::slice_slabs(&slabs, pending.offset, pending.length);
+                let manifest = Manifest::new(
+                    pending.size,
+                    pending.checksum,
+                    &key,
+                    self.options.data_shards,
+                    self.options.parity_shards,
+                    slices,
+                )
+                .with_compression(self.options.compression)
+                .with_cipher(pending.cipher)
+                .with_sha256(pending.sha256)
+                .with_hosts(self.sdk.host_policy().cloned());
+                FileEntry::new(pending.path, manifest, pending.modified)
+            })
+            .collect())
+    }
+}
+
+fn compress(compression: Compression, data: &[u8]) -> Result<Vec<u8>> {
+    Ok(zstd::bulk::compress(data, compression.level)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::aead;
+    use crate::mock::{self, MockBackend};
+
+    async fn download(sdk: &Client, manifest: &Manifest) -> Vec<u8> {
+        let mut data = Vec::new();
+        download::download_object(sdk, &mut data, manifest, 0, manifest.size)
+            .await
+            .unwrap();
+        data
+    }
+
+    fn files() -> Vec<(String, Vec<u8>)> {
+        (0..20)
+            .map(|i| (format!("file{i}"), mock::pattern(i * 997)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn small_files_share_a_slab() {
+        for options in [
+            UploadOptions::new(1, 1),
+            UploadOptions::new(1, 1).with_compression(Some(Compression::zstd(1))),
+            UploadOptions::new(1, 1).with_aead(Some(aead::Algorithm::XChaCha20Poly1305)),
+        ] {
+            let mock = Arc::new(MockBackend::new(2));
+            let sdk = Client::from_backend(mock.clone());
+            let mut packer = Packer::new(&sdk, options, None, Progress::default());
+            for (path, data) in files() {
+                assert!(packer.add(path, data, Some(1)).await.unwrap().is_empty());
+            }
+            let entries = packer.finish().await.unwrap();
+            assert_eq!(mock.slabs(), 1);
+            assert_eq!(entries.len(), 20);
+            for ((path, data), entry) in files().into_iter().zip(&entries) {
+                assert_eq!(entry.path, path);
+                assert_eq!(entry.modified, Some(1));
+                assert_eq!(entry.manifest.checksum, checksum::checksum_bytes(&data));
+                assert_eq!(download(&sdk, &entry.manifest).await, data);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn full_packs_are_uploaded() {
+        let mock = Arc::new(MockBackend::new(2));
+        let sdk = Client::from_backend(mock.clone());
+        let mut packer = Packer::new(&sdk, UploadOptions::new(1, 1), None, Progress::default());
+        let half = mock::pattern(SECTOR_SIZE as usize / 2);
+        assert!(
+            packer
+                .add("a".into(), half.clone(), None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let entries = packer.add("b".into(), half.clone(), None).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(mock.slabs(), 1);
+        let entries = packer.add("c".into(), b"c".to_vec(), None).await.unwrap();
+        assert!(entries.is_empty());
+        let entries = packer.finish().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(mock.slabs(), 2);
+        assert_eq!(download(&sdk, &entries[0].manifest).await, b"c");
+    }
+
+    #[tokio::test]
+    async fn compressed_packs_cant_be_sealed() {
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let options = UploadOptions::new(1, 1)
+            .with_compression(Some(Compression::zstd(1)))
+            .with_aead(Some(aead::Algorithm::XChaCha20Poly1305));
+        let mut packer = Packer::new(&sdk, options, None, Progress::default());
+        let added = packer.add("a".into(), b"a".to_vec(), None).await;
+        assert!(matches!(added, Err(Error::Usage(_))));
+    }
+}
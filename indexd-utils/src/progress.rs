@@ -0,0 +1,115 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+
+use crate::uploader::JobState;
+
+/// A progress update emitted during a transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// More plaintext was read by the uploader or written by the
+    /// downloader. Uploads read ahead of the network, so this leads the
+    /// stored data by up to one segment.
+    BytesTransferred { bytes: u64 },
+    /// A slab was stored; `index` is its position within the object.
+    SlabUploaded { index: usize, length: u64 },
+    /// A transfer failed and is being attempted again.
+    Retrying { attempt: u32, error: String },
+    /// A queued upload changed state.
+    State(JobState),
+    /// The app has to be approved at `url` before connecting.
+    ApprovalRequired { url: String },
+    /// Still waiting for the app to be approved.
+    AwaitingApproval { waited_secs: u64 },
+    /// The app is rate limiting requests, so the transfer is paused for
+    /// `wait_secs` before it carries on.
+    RateLimited { wait_secs: u64 },
+}
+
+/// An optional sink for progress events. Sending never blocks and events
+/// are dropped if nobody is listening.
+#[derive(Debug, Clone, Default)]
+pub struct Progress(Option<mpsc::UnboundedSender<Event>>);
+
+impl Progress {
+    /// Returns a progress sink and the stream of events sent to it.
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<Event>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self(Some(tx)), rx)
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(tx) = &self.0 {
+            let _ = tx.send(event);
+        }
+    }
+}
+
+/// Reports the bytes read through it as `BytesTransferred` events.
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, progress: Progress) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let n = buf.filled().len() - before;
+        if n > 0 {
+            this.progress
+                .emit(Event::BytesTransferred { bytes: n as u64 });
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reports the bytes written through it as `BytesTransferred` events.
+pub struct ProgressWriter<W> {
+    inner: W,
+    progress: Progress,
+}
+
+impl<W> ProgressWriter<W> {
+    pub fn new(inner: W, progress: Progress) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ProgressWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        if n > 0 {
+            this.progress
+                .emit(Event::BytesTransferred { bytes: n as u64 });
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,238 @@
+//! The `pyindexd` Python module, built with maturin from `pyproject.toml`.
+//! Its transfers are coroutines run on a tokio runtime beside the asyncio
+//! loop, and its failures are raised as subclasses of `IndexdError`
+//! chosen by the error's [`Kind`].
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyboardInterrupt};
+use pyo3::prelude::*;
+use pyo3_async_runtimes::tokio::future_into_py;
+
+use crate::client::{self, Client as Sdk};
+use crate::config::{Config, Settings};
+use crate::error::{Error, Kind};
+use crate::progress::{Event, Progress};
+use crate::transfer;
+use crate::verify::{self, Health};
+
+create_exception!(pyindexd, IndexdError, PyException, "A transfer failed.");
+create_exception!(
+    pyindexd,
+    UsageError,
+    IndexdError,
+    "An argument was invalid, or the object isn't catalogued."
+);
+create_exception!(
+    pyindexd,
+    ConfigError,
+    IndexdError,
+    "The config, keychain or budget is in the way."
+);
+create_exception!(
+    pyindexd,
+    AccountError,
+    IndexdError,
+    "The app has to be approved or the account funded."
+);
+create_exception!(
+    pyindexd,
+    NetworkError,
+    IndexdError,
+    "The app or the hosts couldn't be reached; trying again may help."
+);
+create_exception!(
+    pyindexd,
+    IntegrityError,
+    IndexdError,
+    "Stored data is damaged or lost."
+);
+
+/// Raises an error as the exception of its kind.
+fn raise(e: Error) -> PyErr {
+    let message = e.to_string();
+    match e.kind() {
+        Kind::Usage => UsageError::new_err(message),
+        Kind::Config => ConfigError::new_err(message),
+        Kind::Account => AccountError::new_err(message),
+        Kind::Network => NetworkError::new_err(message),
+        Kind::Integrity => IntegrityError::new_err(message),
+        Kind::Interrupted => PyKeyboardInterrupt::new_err(message),
+        Kind::Failure | Kind::Partial => IndexdError::new_err(message),
+    }
+}
+
+/// Connects to the app with the profile's settings and returns a
+/// `Client`. `config` is the config file, the default one if not given.
+#[pyfunction]
+#[pyo3(signature = (config=None, profile=None))]
+fn connect(
+    py: Python<'_>,
+    config: Option<PathBuf>,
+    profile: Option<String>,
+) -> PyResult<Bound<'_, PyAny>> {
+    future_into_py(py, async move {
+        let settings = Config::load(config.as_deref())
+            .await
+            .and_then(|config| config.settings(profile.as_deref(), None))
+            .map_err(raise)?;
+        let sdk = client::connect(&settings).await.map_err(raise)?;
+        Ok(Client(Arc::new((settings, sdk))))
+    })
+}
+
+/// A connection to the app, shared by the transfers started from it.
+#[pyclass(frozen)]
+struct Client(Arc<(Settings, Sdk)>);
+
+#[pymethods]
+impl Client {
+    /// Uploads the file at `path` and catalogues it as `name`, or as the
+    /// file's name, sealing its manifest under `passphrase` if given.
+    /// `progress` is called with the bytes uploaded so far and the file's
+    /// size. Returns the name it was catalogued as.
+    #[pyo3(signature = (path, name=None, passphrase=None, progress=None))]
+    fn upload<'py>(
+        &self,
+        py: Python<'py>,
+        path: PathBuf,
+        name: Option<String>,
+        passphrase: Option<String>,
+        progress: Option<PyObject>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.0.clone();
+        future_into_py(py, async move {
+            let (settings, sdk) = &*client;
+            let name = match name {
+                Some(name) => name,
+                None => path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .ok_or_else(|| UsageError::new_err("the path has no file name"))?,
+            };
+            let total = tokio::fs::metadata(&path).await?.len();
+            let catalog = settings.catalog().map_err(raise)?;
+            transfer::upload_file(
+                sdk,
+                &catalog,
+                settings.upload_options(),
+                &path,
+                &name,
+                passphrase.as_deref(),
+                report(progress, total),
+            )
+            .await
+            .map_err(raise)?;
+            Ok(name)
+        })
+    }
+
+    /// Downloads the file catalogued as `name` to `output`, which is only
+    /// replaced once the file has been downloaded and checked. `progress`
+    /// is called as for `upload`.
+    #[pyo3(signature = (name, output, passphrase=None, progress=None))]
+    fn download<'py>(
+        &self,
+        py: Python<'py>,
+        name: String,
+        output: PathBuf,
+        passphrase: Option<String>,
+        progress: Option<PyObject>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.0.clone();
+        future_into_py(py, async move {
+            let (settings, sdk) = &*client;
+            let catalog = settings.catalog().map_err(raise)?;
+            let manifest = transfer::open_file(&catalog, &name, passphrase.as_deref())
+                .await
+                .map_err(raise)?;
+            let progress = report(progress, manifest.size);
+            transfer::download_file(sdk, &manifest, &output, progress)
+                .await
+                .map_err(raise)
+        })
+    }
+
+    /// Fetches every slab of the file catalogued as `name` and checks it
+    /// against its checksum without writing it anywhere. Damage is
+    /// reported in the returned `VerifyReport` rather than raised.
+    #[pyo3(signature = (name, passphrase=None))]
+    fn verify<'py>(
+        &self,
+        py: Python<'py>,
+        name: String,
+        passphrase: Option<String>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.0.clone();
+        future_into_py(py, async move {
+            let (settings, sdk) = &*client;
+            let catalog = settings.catalog().map_err(raise)?;
+            let manifest = transfer::open_file(&catalog, &name, passphrase.as_deref())
+                .await
+                .map_err(raise)?;
+            let report = verify::verify(sdk, &manifest, &Progress::default())
+                .await
+                .map_err(raise)?;
+            Ok(VerifyReport {
+                healthy: report.count(Health::Healthy),
+                degraded: report.count(Health::Degraded),
+                unrecoverable: report.count(Health::Unrecoverable),
+                checksum_ok: report.checksum_ok,
+                ok: report.is_ok(),
+            })
+        })
+    }
+}
+
+/// How many of a file's slabs were recovered, and whether the whole of it
+/// matched its checksum.
+#[pyclass(frozen, get_all)]
+struct VerifyReport {
+    healthy: usize,
+    degraded: usize,
+    unrecoverable: usize,
+    checksum_ok: bool,
+    ok: bool,
+}
+
+/// Returns a progress sink calling `callback` with the bytes transferred
+/// so far and `total`. Exceptions raised by the callback are printed and
+/// otherwise ignored, so they can't fail the transfer.
+fn report(callback: Option<PyObject>, total: u64) -> Progress {
+    let Some(callback) = callback else {
+        return Progress::default();
+    };
+    let (progress, mut events) = Progress::channel();
+    tokio::spawn(async move {
+        let mut transferred = 0;
+        while let Some(event) = events.recv().await {
+            if let Event::BytesTransferred { bytes } = event {
+                transferred += bytes;
+                Python::with_gil(|py| {
+                    if let Err(e) = callback.call1(py, (transferred, total)) {
+                        e.print(py);
+                    }
+                });
+            }
+        }
+    });
+    progress
+}
+
+#[pymodule]
+#[pyo3(name = "pyindexd")]
+fn module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add_function(wrap_pyfunction!(connect, m)?)?;
+    m.add_class::<Client>()?;
+    m.add_class::<VerifyReport>()?;
+    m.add("IndexdError", py.get_type::<IndexdError>())?;
+    m.add("UsageError", py.get_type::<UsageError>())?;
+    m.add("ConfigError", py.get_type::<ConfigError>())?;
+    m.add("AccountError", py.get_type::<AccountError>())?;
+    m.add("NetworkError", py.get_type::<NetworkError>())?;
+    m.add("IntegrityError", py.get_type::<IntegrityError>())?;
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::error::{Error, Result};
+
+/// Marks a recipient and the version of its encoding.
+const RECIPIENT_PREFIX: &str = "indexd-recipient-v1.";
+/// Marks a recipient key, the secret half of a recipient.
+const KEY_PREFIX: &str = "indexd-recipient-key-v1.";
+
+/// Someone a sealed manifest is shared with: the public half of an X25519
+/// key, written as `indexd-recipient-v1.` and 43 characters of base64.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Recipient([u8; 32]);
+
+impl FromStr for Recipient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        decode(s, RECIPIENT_PREFIX, "recipient").map(Recipient)
+    }
+}
+
+impl TryFrom<String> for Recipient {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Recipient> for String {
+    fn from(recipient: Recipient) -> Self {
+        recipient.to_string()
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{RECIPIENT_PREFIX}{}", BASE64.encode(self.0))
+    }
+}
+
+impl fmt::Debug for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// The secret half of a recipient, which opens the manifests sealed for
+/// it.
+#[derive(Clone)]
+pub struct RecipientKey([u8; 32]);
+
+impl RecipientKey {
+    /// Returns a fresh random key.
+    pub fn generate() -> Self {
+        RecipientKey(rand::random())
+    }
+
+    /// Reads a key from a file holding nothing else.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .await?
+            .parse()
+            .map_err(|e| Error::Config(format!("{}: {e}", path.display())))
+    }
+
+    /// Writes the key to a new file that only its owner can read.
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(path).await?;
+        file.write_all(format!("{}\n", self.encode()).as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient(MontgomeryPoint::mul_base_clamped(self.0).to_bytes())
+    }
+
+    /// Returns the encoding of the key, which is as secret as the key.
+    pub fn encode(&self) -> String {
+        format!("{KEY_PREFIX}{}", BASE64.encode(self.0))
+    }
+
+    /// Unwraps the key `stanza` carries, if it was wrapped for this key.
+    pub fn unwrap(&self, stanza: &Stanza) -> Result<Option<[u8; 32]>> {
+        let recipient = self.recipient();
+        if stanza.recipient != recipient {
+            return Ok(None);
+        }
+        let shared = MontgomeryPoint(stanza.ephemeral).mul_clamped(self.0);
+        let cipher = wrap_cipher(&shared, &stanza.ephemeral, &recipient)?;
+        let key = cipher
+            .decrypt(XNonce::from_slice(&[0; 24]), stanza.wrapped.as_slice())
+            .map_err(|_| Error::Crypto("failed to unwrap the manifest key".into()))?;
+        key.try_into()
+            .map(Some)
+            .map_err(|_| Error::Crypto("the wrapped manifest key is not 32 bytes".into()))
+    }
+}
+
+impl FromStr for RecipientKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        decode(s, KEY_PREFIX, "recipient key").map(RecipientKey)
+    }
+}
+
+impl fmt::Debug for RecipientKey {
+    /// Shows the recipient only, so the key never ends up in a log.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RecipientKey({})", self.recipient())
+    }
+}
+
+/// A manifest key wrapped for one recipient, as age does: under a key
+/// agreed between the recipient and a fresh ephemeral key, so only the
+/// recipient's own key can unwrap it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stanza {
+    pub recipient: Recipient,
+    #[serde(with = "hex")]
+    pub ephemeral: [u8; 32],
+    #[serde(with = "hex")]
+    pub wrapped: Vec<u8>,
+}
+
+impl Stanza {
+    /// Wraps `key` for `recipient`.
+    pub fn wrap(key: &[u8; 32], recipient: Recipient) -> Result<Self> {
+        let secret: [u8; 32] = rand::random();
+        let ephemeral = MontgomeryPoint::mul_base_clamped(secret).to_bytes();
+        let shared = MontgomeryPoint(recipient.0).mul_clamped(secret);
+        let wrapped = wrap_cipher(&shared, &ephemeral, &recipient)?
+            // the wrapping key is only ever used once
+            .encrypt(XNonce::from_slice(&[0; 24]), key.as_slice())
+            .map_err(|_| Error::Crypto("failed to wrap the manifest key".into()))?;
+        Ok(Self {
+            recipient,
+            ephemeral,
+            wrapped,
+        })
+    }
+}
+
+/// Derives the key a stanza is wrapped with from the shared secret, bound
+/// to both public keys.
+fn wrap_cipher(
+    shared: &MontgomeryPoint,
+    ephemeral: &[u8; 32],
+    recipient: &Recipient,
+) -> Result<XChaCha20Poly1305> {
+    // a low-order point gives an all-zero secret anyone could compute
+    if shared.to_bytes() == [0; 32] {
+        return Err(Error::Crypto("invalid recipient key".into()));
+    }
+    let h = blake2b_simd::Params::new()
+        .hash_length(32)
+        .key(shared.as_bytes())
+        .personal(b"indexd-recipient")
+        .to_state()
+        .update(ephemeral)
+        .update(&recipient.0)
+        .finalize();
+    Ok(XChaCha20Poly1305::new(Key::from_slice(h.as_bytes())))
+}
+
+fn decode(s: &str, prefix: &str, what: &str) -> Result<[u8; 32]> {
+    let encoded = s
+        .trim()
+        .strip_prefix(prefix)
+        .ok_or_else(|| Error::Usage(format!("not a {what}; expected {prefix}...")))?;
+    BASE64
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::Usage(format!("malformed {what}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Kdf;
+    use crate::manifest::{AnyManifest, Manifest, SealedManifest, Sealing};
+
+    #[test]
+    fn encodings_round_trip() {
+        let key = RecipientKey::generate();
+        let recipient = key.recipient();
+        let encoded = recipient.to_string();
+        assert!(encoded.starts_with(RECIPIENT_PREFIX));
+        assert_eq!(encoded.parse::<Recipient>().unwrap(), recipient);
+        let parsed: RecipientKey = key.encode().parse().unwrap();
+        assert_eq!(parsed.recipient(), recipient);
+        // the key never shows in a log
+        assert!(!format!("{key:?}").contains(&key.encode()));
+        assert!(key.encode().parse::<Recipient>().is_err());
+        assert!("indexd-recipient-v1.short".parse::<Recipient>().is_err());
+    }
+
+    #[test]
+    fn only_the_recipient_unwraps() {
+        let key = RecipientKey::generate();
+        let other = RecipientKey::generate();
+        let secret = [9; 32];
+        let stanza = Stanza::wrap(&secret, key.recipient()).unwrap();
+        assert_eq!(key.unwrap(&stanza).unwrap(), Some(secret));
+        assert_eq!(other.unwrap(&stanza).unwrap(), None);
+
+        let mut altered = stanza.clone();
+        altered.wrapped[0] ^= 1;
+        assert!(matches!(key.unwrap(&altered), Err(Error::Crypto(_))));
+        // a low-order point would agree on a secret anyone can compute
+        assert!(Stanza::wrap(&secret, Recipient([0; 32])).is_err());
+    }
+
+    #[test]
+    fn sealed_manifests_open_for_each_recipient() {
+        let (alice, bob, eve) = (
+            RecipientKey::generate(),
+            RecipientKey::generate(),
+            RecipientKey::generate(),
+        );
+        let manifest = AnyManifest::File(Manifest::new(5, [3; 32], &[4; 32], 1, 1, Vec::new()));
+        let passphrase_key = [5; 32];
+        let sealing = Sealing {
+            recipients: vec![alice.recipient(), bob.recipient()],
+            ..Sealing::new(Kdf::default(), passphrase_key)
+        };
+        let sealed = SealedManifest::seal(&manifest, &sealing).unwrap();
+        let expected = serde_json::to_value(&manifest).unwrap();
+        for key in [&alice, &bob] {
+            let opened = sealed.open_as(key).unwrap().unwrap();
+            assert_eq!(serde_json::to_value(&opened).unwrap(), expected);
+        }
+        assert!(sealed.open_as(&eve).unwrap().is_none());
+        let opened = sealed.open(&passphrase_key).unwrap();
+        assert_eq!(serde_json::to_value(&opened).unwrap(), expected);
+
+        // sealing again keeps the recipients
+        let resealed = sealed.sealing(passphrase_key);
+        assert_eq!(resealed.recipients, sealing.recipients);
+    }
+}
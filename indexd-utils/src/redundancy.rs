@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::hosts::MIN_TRANSFERS;
+use crate::telemetry::HostStats;
+use crate::throttle;
+use crate::upload::SECTOR_SIZE;
+
+/// The number of data shards the expansion presets split each slab into.
+const PRESET_DATA_SHARDS: u8 = 10;
+
+/// How long a slab should take to upload at the measured throughput, so a
+/// slab that fails costs little to send again.
+const SLAB_UPLOAD_SECS: u64 = 60;
+
+/// How a slab is erasure coded: any `data_shards` of its
+/// `data_shards + parity_shards` shards recover it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Redundancy {
+    pub data_shards: u8,
+    pub parity_shards: u8,
+}
+
+impl Redundancy {
+    /// 3x expansion, tolerating the loss of two thirds of the hosts.
+    pub const STANDARD: Self = Self {
+        data_shards: 10,
+        parity_shards: 20,
+    };
+    /// 2x expansion for data that can be recreated.
+    pub const ECONOMY: Self = Self {
+        data_shards: 10,
+        parity_shards: 10,
+    };
+    /// 5x expansion for data that must survive years of host churn.
+    pub const ARCHIVAL: Self = Self {
+        data_shards: 10,
+        parity_shards: 40,
+    };
+
+    /// Validates a redundancy: every slab needs at least one data and one
+    /// parity shard, and all of a slab's shards must fit in a slab.
+    pub fn new(data_shards: u8, parity_shards: u8) -> Result<Self> {
+        if data_shards == 0 {
+            return Err(Error::Usage("data shards must be at least 1".into()));
+        }
+        if parity_shards == 0 {
+            return Err(Error::Usage(
+                "parity shards must be at least 1, or losing any host loses data".into(),
+            ));
+        }
+        if data_shards.checked_add(parity_shards).is_none() {
+            return Err(Error::Usage(format!(
+                "{data_shards} data and {parity_shards} parity shards are more than the 255 a slab can hold"
+            )));
+        }
+        Ok(Self {
+            data_shards,
+            parity_shards,
+        })
+    }
+
+    pub fn total_shards(&self) -> u8 {
+        self.data_shards + self.parity_shards
+    }
+
+    /// Returns how many bytes are stored per byte uploaded.
+    pub fn expansion(&self) -> f64 {
+        self.total_shards() as f64 / self.data_shards as f64
+    }
+}
+
+impl Default for Redundancy {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+/// Parses a preset name (`standard`, `economy`, `archival`), an expansion
+/// such as `1.5x` or explicit shards such as `10+20` (or `10/20`).
+impl FromStr for Redundancy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "standard" => return Ok(Self::STANDARD),
+            "economy" => return Ok(Self::ECONOMY),
+            "archival" => return Ok(Self::ARCHIVAL),
+            _ => {}
+        }
+        let invalid = || {
+            Error::Usage(format!(
+                "invalid redundancy {s:?}; expected standard, economy, archival, an expansion such as 1.5x or shards such as 10+20"
+            ))
+        };
+        if let Some((data, parity)) = s.split_once(['+', '/']) {
+            let data = data.trim().parse().map_err(|_| invalid())?;
+            let parity = parity.trim().parse().map_err(|_| invalid())?;
+            return Self::new(data, parity);
+        }
+        let expansion: f64 = s
+            .strip_suffix('x')
+            .ok_or_else(invalid)?
+            .parse()
+            .map_err(|_| invalid())?;
+        if !expansion.is_finite() || expansion <= 1.0 {
+            return Err(Error::Usage(format!(
+                "expansion {s} must be more than 1x to leave room for parity"
+            )));
+        }
+        let parity = (PRESET_DATA_SHARDS as f64 * (expansion - 1.0)).round();
+        if parity > u8::MAX as f64 {
+            return Err(invalid());
+        }
+        Self::new(PRESET_DATA_SHARDS, parity as u8)
+    }
+}
+
+impl fmt::Display for Redundancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}+{} ({:.1}x)",
+            self.data_shards,
+            self.parity_shards,
+            self.expansion()
+        )
+    }
+}
+
+/// The slab sizes an upload may choose from by the size of its file and
+/// the throughput uploads were measured at, instead of always splitting
+/// slabs into the redundancy's data shards.
+///
+/// A slab holds one 4 MiB sector per data shard. A file that fits in the
+/// largest slab gets one just big enough to hold it, so little is wasted on
+/// padding; a larger one is split evenly into as few slabs as fit, so
+/// there are few slabs to track and repair and the last isn't mostly
+/// padding. When uploads have been slow, slabs are kept small enough to
+/// upload within a minute. Parity shards scale with the data shards, so
+/// the expansion stays the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabSizing {
+    pub min_data_shards: u8,
+    pub max_data_shards: u8,
+    /// The plaintext bytes per second slabs were measured to upload at.
+    pub throughput: Option<u64>,
+}
+
+impl SlabSizing {
+    /// Validates bounds given in bytes, rounded up to whole sectors.
+    pub fn new(min: u64, max: u64) -> Result<Self> {
+        let sectors = |size: u64| size.div_ceil(SECTOR_SIZE).max(1);
+        let (min_data_shards, max_data_shards) = (sectors(min), sectors(max));
+        if min_data_shards > max_data_shards {
+            return Err(Error::Usage(format!(
+                "the smallest slab size {min} is larger than the largest {max}"
+            )));
+        }
+        // a slab needs room for at least one parity shard
+        if max_data_shards >= u8::MAX as u64 {
+            return Err(Error::Usage(format!(
+                "slabs can't be larger than {} bytes",
+                (u8::MAX as u64 - 1) * SECTOR_SIZE
+            )));
+        }
+        Ok(Self {
+            min_data_shards: min_data_shards as u8,
+            max_data_shards: max_data_shards as u8,
+            throughput: None,
+        })
+    }
+
+    pub fn with_throughput(mut self, throughput: Option<u64>) -> Self {
+        self.throughput = throughput;
+        self
+    }
+
+    /// Returns the redundancy to upload a file of `size` bytes with:
+    /// `redundancy` with its data shards chosen within the bounds and its
+    /// parity shards scaled to match.
+    pub fn choose(&self, size: u64, redundancy: Redundancy) -> Redundancy {
+        let (min, mut max) = (self.min_data_shards as u64, self.max_data_shards as u64);
+        if let Some(throughput) = self.throughput {
+            let fit = throughput * SLAB_UPLOAD_SECS / SECTOR_SIZE;
+            max = fit.clamp(min, max);
+        }
+        let sectors = size.div_ceil(SECTOR_SIZE).max(1);
+        let slabs = sectors.div_ceil(max);
+        let data_shards = sectors.div_ceil(slabs).clamp(min, max) as u8;
+        let parity = data_shards as f64 * (redundancy.expansion() - 1.0);
+        let parity_shards = (parity.round() as u64).clamp(1, (u8::MAX - data_shards) as u64) as u8;
+        Redundancy {
+            data_shards,
+            parity_shards,
+        }
+    }
+}
+
+/// Parses bounds such as `4M-120M`, or a single size to always use.
+impl FromStr for SlabSizing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (min, max) = s.split_once('-').unwrap_or((s, s));
+        let parse = |size: &str| {
+            throttle::parse_size(size)
+                .map_err(|e| Error::Usage(format!("invalid slab sizes {s:?}: {e}")))
+        };
+        Self::new(parse(min)?, parse(max)?)
+    }
+}
+
+/// Estimates the plaintext bytes per second a slab of `total_shards`
+/// shards uploads at from the transfers `hosts` took part in: the median
+/// host's share of a slab over the time the slab took, times the shards
+/// sharing it. Nothing is estimated until some host has enough transfers
+/// to go by.
+pub fn measured_throughput(
+    hosts: &BTreeMap<String, HostStats>,
+    total_shards: usize,
+) -> Option<u64> {
+    let mut rates: Vec<f64> = hosts
+        .values()
+        .filter(|stats| stats.transfers >= MIN_TRANSFERS)
+        .map(HostStats::bytes_per_sec)
+        .collect();
+    if rates.is_empty() {
+        return None;
+    }
+    rates.sort_by(f64::total_cmp);
+    Some((rates[rates.len() / 2] * total_shards as f64) as u64)
+}
@@ -0,0 +1,64 @@
+use log::info;
+
+use crate::client::Client;
+use crate::compression::PlaintextChecksum;
+use crate::download;
+use crate::error::{Error, Result};
+use crate::keys;
+use crate::manifest::{self, Manifest};
+use crate::progress::{Event, Progress};
+
+/// Re-encrypts every slab of an object under keys derived from
+/// `master_key` and returns the manifest pointing at the new slabs.
+///
+/// The object is rotated one slab at a time: each slab is recovered into
+/// memory and uploaded again with the manifest's redundancy and hosts, so
+/// nothing touches the disk. Compressed or sealed data is carried over as
+/// it is stored. The old slabs are left in place. A slab that can't be
+/// recovered or uploaded again fails the whole rekey, since a half-rotated
+/// object would still depend on the old key, and so does recovered data
+/// that no longer matches the manifest's checksum.
+pub async fn rekey(
+    sdk: &Client,
+    manifest: &Manifest,
+    master_key: &[u8; 32],
+    progress: &Progress,
+) -> Result<Manifest> {
+    let mut hasher = PlaintextChecksum::new(manifest)?;
+    let hosts = sdk.host_policy().or(manifest.hosts.as_ref());
+    let mut slabs = Vec::with_capacity(manifest.slabs.len());
+    for (index, slab) in manifest.slabs.iter().enumerate() {
+        let data = download::fetch_slab(sdk, slab)
+            .await
+            .0
+            .map_err(|e| Error::Manifest(format!("slab {index} is unrecoverable: {e}")))?;
+        progress.emit(Event::BytesTransferred {
+            bytes: slab.length as u64,
+        });
+        hasher.write(&data).await;
+        let key = keys::slab_key(master_key, 0, index as u64);
+        let replacement = sdk
+            .upload_bytes_within(
+                data.into(),
+                key,
+                manifest.data_shards,
+                manifest.parity_shards,
+                hosts,
+            )
+            .await?;
+        info!("re-encrypted slab {index}");
+        slabs.extend(replacement);
+    }
+
+    if hasher.finish().await != Some((manifest.size, manifest.checksum)) {
+        return Err(Error::Manifest(
+            "recovered data does not match the manifest's checksum".into(),
+        ));
+    }
+
+    let mut rekeyed = manifest.clone();
+    rekeyed.slabs = slabs;
+    rekeyed.key_fingerprint = manifest::key_fingerprint(master_key);
+    rekeyed.hosts = hosts.cloned();
+    Ok(rekeyed)
+}
@@ -0,0 +1,303 @@
+use log::warn;
+use serde::Serialize;
+
+use crate::client::Client;
+use crate::compression::PlaintextChecksum;
+use crate::download;
+use crate::error::{Error, Result};
+use crate::manifest::Manifest;
+use crate::progress::{Event, Progress};
+use crate::verify::Health;
+
+/// Controls which slabs are re-uploaded and how.
+#[derive(Debug, Clone, Copy)]
+pub struct RepairOptions {
+    pub data_shards: u8,
+    pub parity_shards: u8,
+    /// Re-upload every recoverable slab, not just the degraded ones.
+    pub migrate: bool,
+}
+
+impl RepairOptions {
+    /// Returns options that repair degraded slabs of `manifest` with its
+    /// current redundancy.
+    pub fn new(manifest: &Manifest) -> Self {
+        Self {
+            data_shards: manifest.data_shards,
+            parity_shards: manifest.parity_shards,
+            migrate: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlabRepair {
+    pub index: usize,
+    pub health: Health,
+    /// Whether the slab was replaced by a fresh upload.
+    pub replaced: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Repair {
+    /// The manifest with the replaced slabs swapped in.
+    pub manifest: Manifest,
+    pub slabs: Vec<SlabRepair>,
+}
+
+impl Repair {
+    pub fn replaced(&self) -> usize {
+        self.slabs.iter().filter(|s| s.replaced).count()
+    }
+
+    pub fn unrecoverable(&self) -> usize {
+        self.slabs
+            .iter()
+            .filter(|s| s.health == Health::Unrecoverable)
+            .count()
+    }
+
+    /// The slabs that were recovered but couldn't be stored again, which a
+    /// later repair may replace.
+    pub fn failed(&self) -> usize {
+        self.slabs
+            .iter()
+            .filter(|s| !s.replaced && s.error.is_some() && s.health != Health::Unrecoverable)
+            .count()
+    }
+}
+
+/// Recovers every slab of an object and uploads a fresh copy of each
+/// degraded one, each one with shards on hosts the host policy rules out,
+/// or every slab when migrating. The client's host policy takes precedence
+/// over the one recorded in the manifest.
+///
+/// The SDK uploads whole slabs rather than individual shards, so a repaired
+/// slab is re-encoded and stored on a new set of hosts; the original shards
+/// are left in place. Unrecoverable slabs are kept as they are. If every
+/// slab was recovered but the plaintext no longer matches the manifest's
+/// checksum, nothing is returned so a bad copy is never written back.
+pub async fn repair(
+    sdk: &Client,
+    manifest: &Manifest,
+    options: RepairOptions,
+    progress: &Progress,
+) -> Result<Repair> {
+    let mut hasher = PlaintextChecksum::new(manifest)?;
+    let mut slabs = Vec::with_capacity(manifest.slabs.len());
+    let mut reports = Vec::with_capacity(manifest.slabs.len());
+    let mut complete = true;
+    let hosts = sdk.host_policy().or(manifest.hosts.as_ref());
+    for (index, slab) in manifest.slabs.iter().enumerate() {
+        let (result, attempts) = download::fetch_slab(sdk, slab).await;
+        progress.emit(Event::BytesTransferred {
+            bytes: slab.length as u64,
+        });
+        let data = match result {
+            Ok(data) => data,
+            Err(e) => {
+                complete = false;
+                slabs.push(slab.clone());
+                reports.push(SlabRepair {
+                    index,
+                    health: Health::Unrecoverable,
+                    replaced: false,
+                    error: Some(e.to_string()),
+                });
+                continue;
+            }
+        };
+        hasher.write(&data).await;
+
+        let health = if attempts == 1 {
+            Health::Healthy
+        } else {
+            Health::Degraded
+        };
+        let mut report = SlabRepair {
+            index,
+            health,
+            replaced: false,
+            error: None,
+        };
+        let misplaced = hosts.is_some_and(|hosts| hosts.violations(slab) > 0);
+        if misplaced {
+            warn!("slab {index} has shards on hosts the host policy rules out");
+        }
+        if health == Health::Degraded || misplaced || options.migrate {
+            let key: [u8; 32] = rand::random();
+            match sdk
+                .upload_bytes_within(
+                    data.into(),
+                    key,
+                    options.data_shards,
+                    options.parity_shards,
+                    hosts,
+                )
+                .await
+            {
+                Ok(replacement) => {
+                    slabs.extend(replacement);
+                    report.replaced = true;
+                }
+                Err(e) => {
+                    warn!("failed to re-upload slab {index}: {e}");
+                    slabs.push(slab.clone());
+                    report.error = Some(e.to_string());
+                }
+            }
+        } else {
+            slabs.push(slab.clone());
+        }
+        reports.push(report);
+    }
+
+    if complete && hasher.finish().await != Some((manifest.size, manifest.checksum)) {
+        return Err(Error::Manifest(
+            "recovered data does not match the manifest's checksum".into(),
+        ));
+    }
+
+    let mut repaired = manifest.clone();
+    repaired.slabs = slabs;
+    repaired.hosts = hosts.cloned();
+    // once every slab has been re-encoded the object has the new redundancy
+    if options.migrate && complete && reports.iter().all(|r| r.replaced) {
+        repaired.data_shards = options.data_shards;
+        repaired.parity_shards = options.parity_shards;
+    }
+    Ok(Repair {
+        manifest: repaired,
+        slabs: reports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use indexd::Slab;
+
+    use super::*;
+    use crate::mock::{self, MockBackend};
+    use crate::upload::{self, SECTOR_SIZE, UploadOptions};
+
+    /// Uploads two slabs with one parity shard each.
+    async fn setup(hosts: usize) -> (Arc<MockBackend>, Client, Manifest, Vec<u8>) {
+        let mock = Arc::new(MockBackend::new(hosts));
+        let sdk = Client::from_backend(mock.clone()).with_retry_policy(mock::retries(2));
+        let data = mock::pattern(2 * SECTOR_SIZE as usize);
+        let manifest = upload::upload_reader(
+            &sdk,
+            Cursor::new(data.clone()),
+            [1; 32],
+            UploadOptions::new(1, 1),
+            Progress::default(),
+        )
+        .await
+        .unwrap();
+        (mock, sdk, manifest, data)
+    }
+
+    /// Identifies a slab by its sectors, as gc does.
+    fn sectors(slab: &Slab) -> String {
+        serde_json::to_string(&slab.sectors).unwrap()
+    }
+
+    async fn download(sdk: &Client, manifest: &Manifest) -> Vec<u8> {
+        let mut data = Vec::new();
+        download::download_object(sdk, &mut data, manifest, 0, manifest.size)
+            .await
+            .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn healthy_slabs_are_kept() {
+        let (mock, sdk, manifest, _) = setup(4).await;
+        let options = RepairOptions::new(&manifest);
+        let repair = repair(&sdk, &manifest, options, &Progress::default())
+            .await
+            .unwrap();
+        assert_eq!(repair.replaced(), 0);
+        assert!(repair.slabs.iter().all(|s| s.health == Health::Healthy));
+        assert_eq!(mock.slabs(), 2);
+    }
+
+    #[tokio::test]
+    async fn degraded_slabs_are_replaced() {
+        let (mock, sdk, manifest, data) = setup(4).await;
+        // the first slab only downloads on its second attempt
+        mock.fail_next(1);
+        let options = RepairOptions::new(&manifest);
+        let repair = repair(&sdk, &manifest, options, &Progress::default())
+            .await
+            .unwrap();
+        assert_eq!(repair.slabs[0].health, Health::Degraded);
+        assert_eq!(repair.slabs[1].health, Health::Healthy);
+        assert_eq!(repair.replaced(), 1);
+        assert_eq!(mock.slabs(), 3);
+        let repaired = &repair.manifest;
+        assert_ne!(sectors(&repaired.slabs[0]), sectors(&manifest.slabs[0]));
+        assert_eq!(sectors(&repaired.slabs[1]), sectors(&manifest.slabs[1]));
+        assert_eq!(download(&sdk, repaired).await, data);
+    }
+
+    #[tokio::test]
+    async fn unrecoverable_slabs_are_kept() {
+        let (mock, sdk, manifest, _) = setup(4).await;
+        mock.lose_shard(&manifest.slabs[1], 0);
+        mock.lose_shard(&manifest.slabs[1], 1);
+        let options = RepairOptions::new(&manifest);
+        let repair = repair(&sdk, &manifest, options, &Progress::default())
+            .await
+            .unwrap();
+        assert_eq!(repair.unrecoverable(), 1);
+        assert_eq!(repair.replaced(), 0);
+        assert_eq!(repair.slabs[1].health, Health::Unrecoverable);
+        assert_eq!(
+            sectors(&repair.manifest.slabs[1]),
+            sectors(&manifest.slabs[1])
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_replacements_are_reported() {
+        let (mock, sdk, manifest, _) = setup(2).await;
+        // the slabs still download from the host left, but can't be stored again
+        mock.set_up(&mock.host_keys()[0], false);
+        mock.fail_next(1);
+        let options = RepairOptions::new(&manifest);
+        let repair = repair(&sdk, &manifest, options, &Progress::default())
+            .await
+            .unwrap();
+        assert_eq!(repair.slabs[0].health, Health::Degraded);
+        assert_eq!(repair.failed(), 1);
+        assert_eq!(repair.replaced(), 0);
+        assert_eq!(
+            sectors(&repair.manifest.slabs[0]),
+            sectors(&manifest.slabs[0])
+        );
+    }
+
+    #[tokio::test]
+    async fn migration_changes_the_redundancy() {
+        let (mock, sdk, manifest, data) = setup(4).await;
+        let options = RepairOptions {
+            parity_shards: 2,
+            migrate: true,
+            ..RepairOptions::new(&manifest)
+        };
+        let repair = repair(&sdk, &manifest, options, &Progress::default())
+            .await
+            .unwrap();
+        assert_eq!(repair.replaced(), 2);
+        assert_eq!(repair.manifest.parity_shards, 2);
+        assert!(repair.manifest.slabs.iter().all(|s| s.sectors.len() == 3));
+        assert_eq!(mock.slabs(), 4);
+        assert_eq!(download(&sdk, &repair.manifest).await, data);
+    }
+}
@@ -0,0 +1,252 @@
+use std::io;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{HeaderMap, Method, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::TryStreamExt;
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+use tokio_util::io::StreamReader;
+
+use crate::bucket::Buckets;
+use crate::client::Client;
+use crate::http::{Span, StatusError};
+use crate::progress::Progress;
+use crate::upload;
+
+/// The media type of version 2 of the protocol, whose listings carry sizes.
+const V2: &str = "application/vnd.x.restic.rest.v2";
+
+/// The kinds of files a restic repository holds besides its config.
+const TYPES: &[&str] = &["data", "keys", "locks", "snapshots", "index"];
+
+/// Serves buckets as restic repositories over restic's REST protocol, as
+/// `rest-server` and `rclone serve restic` do, so restic and rclone can
+/// back up to indexd. `/<bucket>/` is a repository, its config is the
+/// object `config` and every other file the object `<type>/<name>`.
+///
+/// restic names its files by their hash and never rewrites one, so each
+/// upload is committed to the bucket on its own and a crashed backup leaves
+/// nothing half written.
+pub struct Restic {
+    sdk: Client,
+    buckets: Buckets,
+    /// The `Authorization` header clients must send, if any.
+    authorization: Option<String>,
+    append_only: bool,
+}
+
+impl Restic {
+    /// `credentials` is the user name and password required with HTTP
+    /// basic authentication, or `None` to allow anyone. An `append_only`
+    /// server refuses to delete anything but locks, so a compromised client
+    /// can't destroy earlier backups.
+    pub fn new(
+        sdk: Client,
+        buckets: Buckets,
+        credentials: Option<(String, String)>,
+        append_only: bool,
+    ) -> Self {
+        Self {
+            sdk,
+            buckets,
+            authorization: credentials.map(|(user, password)| {
+                format!("Basic {}", BASE64.encode(format!("{user}:{password}")))
+            }),
+            append_only,
+        }
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new().fallback(handle).with_state(self)
+    }
+}
+
+type Result<T> = std::result::Result<T, StatusError>;
+
+/// What a request path names within a repository.
+enum Resource {
+    /// The repository itself, created with `POST /<bucket>/?create=true`.
+    Repository,
+    Config,
+    /// The listing of a type of file.
+    Type(String),
+    File(String, String),
+}
+
+impl Resource {
+    /// Splits a path into a bucket and what it names in the bucket.
+    fn parse(path: &str) -> Option<(String, Self)> {
+        let path = percent_decode_str(path.trim_start_matches('/'))
+            .decode_utf8_lossy()
+            .into_owned();
+        let mut parts = path.split('/');
+        let bucket = parts.next().filter(|b| !b.is_empty())?.to_string();
+        let resource = match (parts.next(), parts.next(), parts.next()) {
+            (None | Some(""), None, None) => Resource::Repository,
+            (Some("config"), None, None) => Resource::Config,
+            (Some(kind), None | Some(""), None) if TYPES.contains(&kind) => {
+                Resource::Type(kind.to_string())
+            }
+            (Some(kind), Some(name), None) if TYPES.contains(&kind) && is_name(name) => {
+                Resource::File(kind.to_string(), name.to_string())
+            }
+            _ => return None,
+        };
+        Some((bucket, resource))
+    }
+
+    /// The key of the object holding the file.
+    fn key(&self) -> Option<String> {
+        match self {
+            Resource::Config => Some("config".into()),
+            Resource::File(kind, name) => Some(format!("{kind}/{name}")),
+            _ => None,
+        }
+    }
+}
+
+/// restic only names files by their hex encoded hash.
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+async fn handle(
+    State(restic): State<Arc<Restic>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    if let Some(expected) = &restic.authorization {
+        let given = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok());
+        if given != Some(expected.as_str()) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"indexd\"")],
+            )
+                .into_response();
+        }
+    }
+    let Some((bucket, resource)) = Resource::parse(uri.path()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let result = match (method.as_str(), &resource) {
+        ("POST", Resource::Repository) => create(&restic, &bucket, uri.query()).await,
+        ("GET", Resource::Type(kind)) => list(&restic, &bucket, kind, &headers).await,
+        ("GET" | "HEAD", Resource::Config | Resource::File(..)) => {
+            let head = method == Method::HEAD;
+            get(restic.clone(), &bucket, &resource, &headers, head).await
+        }
+        ("POST", Resource::Config | Resource::File(..)) => {
+            put(&restic, &bucket, &resource, body).await
+        }
+        ("DELETE", Resource::Config | Resource::File(..)) => {
+            delete(&restic, &bucket, &resource).await
+        }
+        _ => Err(StatusError(StatusCode::METHOD_NOT_ALLOWED)),
+    };
+    result.unwrap_or_else(|StatusError(status)| status.into_response())
+}
+
+async fn create(restic: &Restic, bucket: &str, query: Option<&str>) -> Result<Response> {
+    if !query
+        .unwrap_or_default()
+        .split('&')
+        .any(|q| q == "create=true")
+    {
+        return Err(StatusError(StatusCode::BAD_REQUEST));
+    }
+    if !restic.buckets.exists(bucket)? {
+        restic.buckets.create(&restic.sdk, bucket).await?;
+    }
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn list(restic: &Restic, bucket: &str, kind: &str, headers: &HeaderMap) -> Result<Response> {
+    let bucket = restic.buckets.get(&restic.sdk, bucket).await?;
+    let bucket = bucket.lock().await;
+    let prefix = format!("{kind}/");
+    let files = bucket
+        .list(&prefix)
+        .map(|(key, object)| (&key[prefix.len()..], object.manifest.size));
+    let v2 = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains(V2));
+    let (body, content_type) = if v2 {
+        let files: Vec<_> = files
+            .map(|(name, size)| json!({ "name": name, "size": size }))
+            .collect();
+        (json!(files), V2)
+    } else {
+        let names: Vec<_> = files.map(|(name, _)| name).collect();
+        (json!(names), "application/vnd.x.restic.rest.v1")
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], body.to_string()).into_response())
+}
+
+async fn get(
+    restic: Arc<Restic>,
+    bucket: &str,
+    resource: &Resource,
+    headers: &HeaderMap,
+    head: bool,
+) -> Result<Response> {
+    let key = resource.key().expect("a file");
+    let object = {
+        let bucket = restic.buckets.get(&restic.sdk, bucket).await?;
+        let bucket = bucket.lock().await;
+        bucket.stat(&key)?.clone()
+    };
+    let span = Span::parse(headers, object.manifest.size)
+        .map_err(|_| StatusError(StatusCode::RANGE_NOT_SATISFIABLE))?;
+    let response = span
+        .response()
+        .header(header::CONTENT_TYPE, "application/octet-stream");
+    Ok(span.send(&restic.sdk, object.manifest, response, head, key))
+}
+
+async fn put(restic: &Restic, bucket: &str, resource: &Resource, body: Body) -> Result<Response> {
+    let key = resource.key().expect("a file");
+    let bucket = restic.buckets.get(&restic.sdk, bucket).await?;
+    let (options, exists) = {
+        let bucket = bucket.lock().await;
+        (bucket.options(), bucket.stat(&key).is_ok())
+    };
+    // files are written once, as rest-server does
+    if exists {
+        return Err(StatusError(StatusCode::FORBIDDEN));
+    }
+    let reader = StreamReader::new(body.into_data_stream().map_err(io::Error::other));
+    let manifest = upload::upload_reader(
+        &restic.sdk,
+        reader,
+        rand::random(),
+        options,
+        Progress::default(),
+    )
+    .await?;
+
+    bucket.lock().await.insert(&key, manifest);
+    restic.buckets.commit(&restic.sdk, &bucket).await?;
+    Ok(StatusCode::OK.into_response())
+}
+
+async fn delete(restic: &Restic, bucket: &str, resource: &Resource) -> Result<Response> {
+    if restic.append_only && !matches!(resource, Resource::File(kind, _) if kind == "locks") {
+        return Err(StatusError(StatusCode::FORBIDDEN));
+    }
+    let key = resource.key().expect("a file");
+    let bucket = restic.buckets.get(&restic.sdk, bucket).await?;
+    bucket.lock().await.delete(&key)?;
+    restic.buckets.commit(&restic.sdk, &bucket).await?;
+    Ok(StatusCode::OK.into_response())
+}
@@ -0,0 +1,277 @@
+use std::future::Future;
+use std::time::Duration;
+
+use log::warn;
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::metrics::METRICS;
+use crate::progress::{Event, Progress};
+
+/// What a failed request was talking to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorClass {
+    /// Requests to the app URL, such as connecting.
+    App,
+    /// Shard uploads and downloads.
+    Host,
+}
+
+/// How failed requests are retried.
+///
+/// Only errors that [`Error::is_retryable`] accepts are retried; local
+/// I/O, approval and funding errors are returned immediately.
+/// The delay before attempt `n + 1` grows as `base_delay * 2^(n - 1)` up to
+/// `max_delay`, and is then varied by up to `jitter` of itself so that
+/// concurrent transfers don't retry in step.
+///
+/// The app turning requests away with 429 Too Many Requests is waited out
+/// rather than retried: the request is paused for as long as the app's
+/// `Retry-After` asks, or the backoff delay if it doesn't say, without using
+/// up an attempt, until `max_rate_limit_wait` has been spent paused.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// The number of attempts, including the first.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// A fraction between 0 and 1.
+    pub jitter: f64,
+    pub retry_on: Vec<ErrorClass>,
+    /// The longest a request is paused for in total while the app rate
+    /// limits it, before the rate limit fails it like any other error.
+    pub max_rate_limit_wait: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.2,
+            retry_on: vec![ErrorClass::App, ErrorClass::Host],
+            max_rate_limit_wait: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            max_rate_limit_wait: Duration::ZERO,
+            ..Self::default()
+        }
+    }
+
+    /// Returns whether a failed `attempt` should be followed by another.
+    pub fn should_retry(&self, class: ErrorClass, attempt: u32, error: &Error) -> bool {
+        attempt < self.max_attempts && self.retry_on.contains(&class) && error.is_retryable()
+    }
+
+    /// Returns how long to wait after a failed `attempt`, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0) * (rand::random::<f64>() * 2.0 - 1.0);
+        delay.mul_f64(1.0 + jitter)
+    }
+
+    /// Waits before the attempt after a failed `attempt`, counting the
+    /// retry in the metrics.
+    pub async fn backoff(&self, class: ErrorClass, attempt: u32) {
+        METRICS.retried(class);
+        tokio::time::sleep(self.delay(attempt)).await;
+    }
+
+    /// Waits out the app's rate limit if `result` failed with one,
+    /// reporting the pause to `progress` as [`Event::RateLimited`]. Returns
+    /// whether it did, so the request can be made again; `paused` keeps
+    /// count of the pauses made for the same request.
+    pub async fn pause_for_rate_limit<T>(
+        &self,
+        what: &str,
+        result: &Result<T>,
+        paused: &mut Paused,
+        progress: &Progress,
+    ) -> bool {
+        let Some(limit) = result.as_ref().err().and_then(RateLimit::from_error) else {
+            return false;
+        };
+        let left = self.max_rate_limit_wait.saturating_sub(paused.waited);
+        if left.is_zero() {
+            return false;
+        }
+        paused.count += 1;
+        let wait = limit
+            .retry_after
+            .unwrap_or_else(|| self.delay(paused.count))
+            .min(left);
+        warn!(
+            "{what} was turned away by the app's rate limit; pausing for {}s",
+            wait.as_secs_f64().ceil()
+        );
+        progress.emit(Event::RateLimited {
+            wait_secs: wait.as_secs_f64().ceil() as u64,
+        });
+        tokio::time::sleep(wait).await;
+        paused.waited += wait;
+        true
+    }
+
+    /// Runs `op` until it succeeds, fails with an error that isn't
+    /// retried, or runs out of attempts, waiting out the app's rate limit
+    /// in between. `op` is passed the attempt number.
+    pub async fn run<T, F, Fut>(
+        &self,
+        class: ErrorClass,
+        what: &str,
+        progress: &Progress,
+        mut op: F,
+    ) -> Result<T>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        let mut paused = Paused::default();
+        loop {
+            let result = op(attempt).await;
+            if self
+                .pause_for_rate_limit(what, &result, &mut paused, progress)
+                .await
+            {
+                continue;
+            }
+            match result {
+                Err(e) if self.should_retry(class, attempt, &e) => {
+                    warn!("{what} failed on attempt {attempt}: {e}");
+                    self.backoff(class, attempt).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// The pauses made for the app's rate limit while making one request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Paused {
+    count: u32,
+    waited: Duration,
+}
+
+/// The app turning a request away with 429 Too Many Requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// How long the app asked for requests to stop, if it said.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimit {
+    /// Returns the rate limit `error` reports, if it is one.
+    ///
+    /// The SDK doesn't expose response headers, so a `Retry-After` is only
+    /// taken from its message when the message carries the header itself,
+    /// as `Retry-After: 30`; otherwise the backoff delay is waited.
+    pub fn from_error(error: &Error) -> Option<Self> {
+        let Error::RateLimited(message) = error else {
+            return None;
+        };
+        let message = message.to_ascii_lowercase();
+        let retry_after = message
+            .match_indices("retry-after")
+            .find_map(|(at, key)| {
+                let rest = message[at + key.len()..].trim_start();
+                let rest = rest.strip_prefix([':', '='])?.trim_start();
+                let end = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                rest[..end].parse().ok()
+            })
+            .map(Duration::from_secs);
+        Some(Self { retry_after })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Runs an operation failing with `error` the first `failures` times,
+    /// returning what it returned and how often it ran.
+    async fn run(policy: &RetryPolicy, failures: u32, error: fn() -> Error) -> (Result<u32>, u32) {
+        let runs = Cell::new(0);
+        let result = policy
+            .run(ErrorClass::Host, "test", &Progress::default(), |attempt| {
+                runs.set(runs.get() + 1);
+                let fails = runs.get() <= failures;
+                async move { if fails { Err(error()) } else { Ok(attempt) } }
+            })
+            .await;
+        (result, runs.get())
+    }
+
+    #[test]
+    fn delays_grow_up_to_the_limit() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        let delays: Vec<u64> = (1..=7).map(|n| policy.delay(n).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(30));
+
+        let jittered = RetryPolicy::default();
+        for _ in 0..100 {
+            let delay = jittered.delay(3);
+            assert!(delay >= Duration::from_millis(3200) && delay <= Duration::from_millis(4800));
+        }
+    }
+
+    #[test]
+    fn only_retryable_errors_are_retried() {
+        let host = Error::Host("connection reset".into());
+        let policy = RetryPolicy::default();
+        assert!(policy.should_retry(ErrorClass::Host, 1, &host));
+        assert!(!policy.should_retry(ErrorClass::Host, 3, &host));
+        assert!(!policy.should_retry(ErrorClass::Host, 1, &Error::Usage("bad".into())));
+        let app_only = RetryPolicy {
+            retry_on: vec![ErrorClass::App],
+            ..RetryPolicy::default()
+        };
+        assert!(!app_only.should_retry(ErrorClass::Host, 1, &host));
+        assert!(!RetryPolicy::none().should_retry(ErrorClass::Host, 1, &host));
+    }
+
+    #[tokio::test]
+    async fn run_stops_at_success_or_the_last_attempt() {
+        let host = || Error::Host("connection reset".into());
+        let (result, runs) = run(&policy(3), 2, host).await;
+        assert_eq!((result.unwrap(), runs), (3, 3));
+        let (result, runs) = run(&policy(3), 3, host).await;
+        assert!(matches!(result, Err(Error::Host(_))));
+        assert_eq!(runs, 3);
+        let (result, runs) = run(&policy(3), 1, || Error::Usage("bad".into())).await;
+        assert!(matches!(result, Err(Error::Usage(_))));
+        assert_eq!(runs, 1);
+    }
+}
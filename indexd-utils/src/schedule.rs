@@ -0,0 +1,240 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+
+/// How far ahead to look for the next run; every valid schedule runs
+/// within four years.
+const HORIZON_DAYS: u64 = 4 * 366;
+
+/// When to run, as the five fields of a crontab line: minute, hour, day of
+/// the month, month and day of the week, in UTC.
+///
+/// Each field is `*`, a number, a range such as `1-5`, any of those with a
+/// step such as `*/15`, or a comma separated list of them. Days of the
+/// week run from 0 for Sunday to 6, with 7 also meaning Sunday. As in
+/// cron, when both the day of the month and the day of the week are
+/// restricted, a day matching either one runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    spec: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    /// Returns the first time after `t`, to the minute, that the schedule
+    /// runs.
+    pub fn next_after(&self, t: SystemTime) -> Option<SystemTime> {
+        let secs = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut minute = secs / 60 + 1;
+        for _ in 0..HORIZON_DAYS {
+            let day = minute / (24 * 60);
+            if self.runs_on(day) {
+                for m in minute..(day + 1) * 24 * 60 {
+                    let in_day = m % (24 * 60);
+                    if bit(self.hours, in_day / 60) && bit(self.minutes, in_day % 60) {
+                        return Some(UNIX_EPOCH + Duration::from_secs(m * 60));
+                    }
+                }
+            }
+            minute = (day + 1) * 24 * 60;
+        }
+        None
+    }
+
+    fn runs_on(&self, day: u64) -> bool {
+        let (month, day_of_month) = civil(day);
+        // 1970-01-01 was a Thursday
+        let weekday = (day + 4) % 7;
+        if !bit(self.months, month) {
+            return false;
+        }
+        let by_day = bit(self.days, day_of_month);
+        let by_weekday = bit(self.weekdays, weekday);
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => by_weekday,
+            (false, true) => by_day,
+            (false, false) => by_day || by_weekday,
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(Error::Usage(format!(
+                "invalid schedule {s:?}; expected five fields, such as \"0 3 * * *\""
+            )));
+        };
+        let mut weekdays = field(weekday, 0, 7, "day of the week")?;
+        // 7 is another name for Sunday
+        if bit(weekdays, 7) {
+            weekdays |= 1;
+        }
+        let schedule = Schedule {
+            spec: fields.join(" "),
+            minutes: field(minute, 0, 59, "minute")?,
+            hours: field(hour, 0, 23, "hour")?,
+            days: field(day, 1, 31, "day of the month")?,
+            months: field(month, 1, 12, "month")?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        };
+        if schedule.next_after(SystemTime::now()).is_none() {
+            return Err(Error::Usage(format!("the schedule {s:?} never runs")));
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+/// Parses one field into a mask with a bit set for every value it covers.
+fn field(s: &str, min: u64, max: u64, what: &str) -> Result<u64> {
+    let invalid = || Error::Usage(format!("invalid {what} {s:?} in schedule"));
+    let mut mask = 0;
+    for part in s.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            let start = start.parse().map_err(|_| invalid())?;
+            (start, end.parse().map_err(|_| invalid())?)
+        } else {
+            let start = range.parse().map_err(|_| invalid())?;
+            // `5/10` runs from 5 to the end in steps of 10
+            (start, if part.contains('/') { max } else { start })
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+fn bit(mask: u64, value: u64) -> bool {
+    mask & (1 << value) != 0
+}
+
+/// Returns the month and day of the month of a day counted from the Unix
+/// epoch.
+fn civil(day: u64) -> (u64, u64) {
+    // days since 0000-03-01, so leap days fall at the end of each year
+    let z = day + 719_468;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let m = (5 * day_of_year + 2) / 153;
+    let day_of_month = day_of_year - (153 * m + 2) / 5 + 1;
+    let month = if m < 10 { m + 3 } else { m - 9 };
+    (month, day_of_month)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2025-01-01T00:00:00Z, a Wednesday.
+    const NEW_YEAR: u64 = 1_735_689_600;
+
+    fn next(spec: &str, after: u64) -> u64 {
+        let schedule: Schedule = spec.parse().unwrap();
+        schedule
+            .next_after(UNIX_EPOCH + Duration::from_secs(after))
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn daily() {
+        assert_eq!(next("0 3 * * *", NEW_YEAR), NEW_YEAR + 3 * 3600);
+        // a run is strictly after the time given
+        assert_eq!(
+            next("0 3 * * *", NEW_YEAR + 3 * 3600),
+            NEW_YEAR + 86400 + 3 * 3600
+        );
+    }
+
+    #[test]
+    fn steps_and_lists() {
+        assert_eq!(next("*/15 * * * *", NEW_YEAR + 60), NEW_YEAR + 15 * 60);
+        assert_eq!(next("5/20 * * * *", NEW_YEAR + 30 * 60), NEW_YEAR + 45 * 60);
+        assert_eq!(
+            next("10,40 9-17 * * *", NEW_YEAR),
+            NEW_YEAR + 9 * 3600 + 600
+        );
+    }
+
+    #[test]
+    fn weekdays() {
+        // the Monday after
+        assert_eq!(
+            next("30 2 * * 1", NEW_YEAR),
+            NEW_YEAR + 5 * 86400 + 2 * 3600 + 1800
+        );
+        // 7 is Sunday as well as 0
+        assert_eq!(next("0 0 * * 7", NEW_YEAR), next("0 0 * * 0", NEW_YEAR));
+        assert_eq!(next("0 0 * * 0", NEW_YEAR), NEW_YEAR + 4 * 86400);
+    }
+
+    #[test]
+    fn day_of_month_or_weekday() {
+        // the 13th, or any Friday, whichever comes first
+        assert_eq!(next("0 0 13 * 5", NEW_YEAR), NEW_YEAR + 2 * 86400);
+    }
+
+    #[test]
+    fn leap_day() {
+        // 2028-02-29
+        assert_eq!(next("0 0 29 2 *", NEW_YEAR), 21_243 * 86400);
+    }
+
+    #[test]
+    fn invalid() {
+        for spec in [
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "0 0 31 2 *",
+        ] {
+            assert!(spec.parse::<Schedule>().is_err(), "{spec} parsed");
+        }
+    }
+
+    #[test]
+    fn display() {
+        let schedule: Schedule = " 0  3 * * 1-5 ".parse().unwrap();
+        assert_eq!(schedule.to_string(), "0 3 * * 1-5");
+    }
+}
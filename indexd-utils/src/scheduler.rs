@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+/// Shares a limit on the shards in flight between the files of a transfer
+/// of many files, so one huge file can't starve thousands of small ones or
+/// the other way around.
+///
+/// Each file transfers through a [`Lane`], whose client asks for a permit
+/// before every SDK transfer. Whenever shards free up, the waiting lane
+/// with the fewest shards in flight goes next, the one served longest ago
+/// breaking ties, so every active file gets an equal share of the limit
+/// however many slabs it has queued.
+pub struct Scheduler {
+    max_shards: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    in_flight: usize,
+    lanes: BTreeMap<u64, LaneState>,
+    next_id: u64,
+    /// Counts grants, to tell which lane was served longest ago.
+    turn: u64,
+}
+
+struct LaneState {
+    name: String,
+    size: u64,
+    transferred: u64,
+    in_flight: usize,
+    last_turn: u64,
+    done: bool,
+    waiting: VecDeque<(usize, oneshot::Sender<Permit>)>,
+}
+
+/// One file's progress through a [`Scheduler`].
+#[derive(Debug, Clone, Serialize)]
+pub struct FileProgress {
+    pub name: String,
+    pub size: u64,
+    pub transferred: u64,
+    /// The shards of the file in flight.
+    pub in_flight: usize,
+    /// Whether the file's transfer is over, successfully or not.
+    pub done: bool,
+}
+
+impl Scheduler {
+    /// Returns a scheduler keeping at most `max_shards` shards in flight. A
+    /// transfer of more shards than that runs alone.
+    pub fn new(max_shards: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max_shards: max_shards.max(1),
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Adds a file of `size` bytes. Its transfer is over once every clone
+    /// of the returned lane is dropped.
+    pub fn lane(self: &Arc<Self>, name: impl Into<String>, size: u64) -> Lane {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.lanes.insert(
+            id,
+            LaneState {
+                name: name.into(),
+                size,
+                transferred: 0,
+                in_flight: 0,
+                last_turn: 0,
+                done: false,
+                waiting: VecDeque::new(),
+            },
+        );
+        Lane(Arc::new(LaneInner {
+            scheduler: self.clone(),
+            id,
+        }))
+    }
+
+    /// Returns the progress of every file added so far, in the order they
+    /// were.
+    pub fn files(&self) -> Vec<FileProgress> {
+        let state = self.state.lock().unwrap();
+        state
+            .lanes
+            .values()
+            .map(|lane| FileProgress {
+                name: lane.name.clone(),
+                size: lane.size,
+                transferred: lane.transferred,
+                in_flight: lane.in_flight,
+                done: lane.done,
+            })
+            .collect()
+    }
+
+    /// Grants permits until the next in line doesn't fit.
+    fn dispatch(self: &Arc<Self>, state: &mut State) {
+        loop {
+            let next = state
+                .lanes
+                .iter()
+                .filter(|(_, lane)| !lane.waiting.is_empty())
+                .min_by_key(|(_, lane)| (lane.in_flight, lane.last_turn))
+                .map(|(id, _)| *id);
+            let Some(id) = next else {
+                return;
+            };
+            let free = self.max_shards - state.in_flight;
+            state.turn += 1;
+            let turn = state.turn;
+            let lane = state.lanes.get_mut(&id).expect("waiting lane");
+            let (shards, tx) = match lane.waiting.front() {
+                Some((shards, _)) if *shards > free => return,
+                _ => lane.waiting.pop_front().expect("waiting lane"),
+            };
+            lane.in_flight += shards;
+            lane.last_turn = turn;
+            state.in_flight += shards;
+            let permit = Permit {
+                scheduler: self.clone(),
+                lane: id,
+                shards,
+            };
+            // the lock is held, so a permit nobody took back is returned
+            // here rather than by its drop
+            if let Err(permit) = tx.send(permit) {
+                mem::forget(permit);
+                lane.in_flight -= shards;
+                state.in_flight -= shards;
+            }
+        }
+    }
+}
+
+/// A file's place in a [`Scheduler`], shared by the clients transferring
+/// it.
+#[derive(Clone)]
+pub struct Lane(Arc<LaneInner>);
+
+struct LaneInner {
+    scheduler: Arc<Scheduler>,
+    id: u64,
+}
+
+impl Lane {
+    /// Waits for the file's turn to put `shards` shards in flight.
+    pub async fn acquire(&self, shards: usize) -> Permit {
+        let scheduler = &self.0.scheduler;
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = scheduler.state.lock().unwrap();
+            let shards = shards.clamp(1, scheduler.max_shards);
+            let lane = state
+                .lanes
+                .get_mut(&self.0.id)
+                .expect("lane of its scheduler");
+            lane.waiting.push_back((shards, tx));
+            scheduler.dispatch(&mut state);
+        }
+        rx.await.expect("waiters are only dropped with their lane")
+    }
+
+    /// Counts `bytes` more of the file as transferred.
+    pub fn record(&self, bytes: u64) {
+        let mut state = self.0.scheduler.state.lock().unwrap();
+        if let Some(lane) = state.lanes.get_mut(&self.0.id) {
+            lane.transferred += bytes;
+        }
+    }
+}
+
+impl Drop for LaneInner {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        if let Some(lane) = state.lanes.get_mut(&self.id) {
+            lane.done = true;
+        }
+    }
+}
+
+/// Shards a lane may keep in flight until it is dropped.
+pub struct Permit {
+    scheduler: Arc<Scheduler>,
+    lane: u64,
+    shards: usize,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let scheduler = self.scheduler.clone();
+        let mut state = scheduler.state.lock().unwrap();
+        if let Some(lane) = state.lanes.get_mut(&self.lane) {
+            lane.in_flight -= self.shards;
+        }
+        state.in_flight -= self.shards;
+        scheduler.dispatch(&mut state);
+    }
+}
+
+/// Orders files by taking the smallest and the largest left in turn, so
+/// the files transferring side by side are a mix of both and neither kind
+/// waits for all of the other to finish.
+pub fn interleave<T>(mut files: Vec<(u64, T)>) -> Vec<T> {
+    files.sort_by_key(|(size, _)| *size);
+    let mut files = VecDeque::from(files);
+    let mut ordered = Vec::with_capacity(files.len());
+    while let Some((_, small)) = files.pop_front() {
+        ordered.push(small);
+        if let Some((_, large)) = files.pop_back() {
+            ordered.push(large);
+        }
+    }
+    ordered
+}
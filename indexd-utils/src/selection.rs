@@ -0,0 +1,145 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::error::{Error, Result};
+use crate::manifest::{DirectoryManifest, FileEntry};
+use crate::scheduler;
+
+/// The order the files of a partial restore are started in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    /// The smallest and the largest left in turn, which keeps the
+    /// transfers busiest.
+    #[default]
+    Interleave,
+    /// The smallest first, so the most files are back soonest.
+    Smallest,
+    /// The largest first.
+    Largest,
+    /// By path.
+    Path,
+}
+
+impl FromStr for Order {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "interleave" => Ok(Self::Interleave),
+            "smallest" => Ok(Self::Smallest),
+            "largest" => Ok(Self::Largest),
+            "path" => Ok(Self::Path),
+            _ => Err(Error::Usage(format!(
+                "invalid restore order {s:?}; expected interleave, smallest, largest or path"
+            ))),
+        }
+    }
+}
+
+/// Which files of a directory manifest to restore, and in what order.
+///
+/// Patterns are gitignore-style and matched against the manifest's paths,
+/// so `photos/2023/**` or `photos/2023` selects everything under that
+/// directory and `!*.tmp` leaves out what an earlier pattern selected.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    include: Option<Gitignore>,
+    priority: Vec<Gitignore>,
+    order: Order,
+}
+
+impl Selection {
+    /// Only restores the files matching `patterns`; without any, every
+    /// file is.
+    pub fn with_include(mut self, patterns: &[String]) -> Result<Self> {
+        self.include = if patterns.is_empty() {
+            None
+        } else {
+            Some(build(patterns)?)
+        };
+        Ok(self)
+    }
+
+    /// Restores the files matching each of `patterns` before those matching
+    /// the next, and all of them before the rest.
+    pub fn with_priority(mut self, patterns: &[String]) -> Result<Self> {
+        self.priority = patterns
+            .iter()
+            .map(|pattern| build(std::slice::from_ref(pattern)))
+            .collect::<Result<_>>()?;
+        Ok(self)
+    }
+
+    /// Sets the order of the files within each priority.
+    pub fn with_order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Whether `path` is among the files to restore.
+    pub fn includes(&self, path: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| matches(include, path))
+    }
+
+    /// Returns the files and links of `manifest` to restore, with the
+    /// files in the order to start them.
+    pub fn apply(&self, manifest: &DirectoryManifest) -> DirectoryManifest {
+        let mut groups: Vec<Vec<&FileEntry>> = vec![Vec::new(); self.priority.len() + 1];
+        for entry in manifest.files.iter().filter(|f| self.includes(&f.path)) {
+            let rank = self
+                .priority
+                .iter()
+                .position(|priority| matches(priority, &entry.path))
+                .unwrap_or(self.priority.len());
+            groups[rank].push(entry);
+        }
+        let mut files = Vec::new();
+        for mut group in groups {
+            match self.order {
+                Order::Interleave => {
+                    group = scheduler::interleave(
+                        group.into_iter().map(|f| (f.manifest.size, f)).collect(),
+                    );
+                }
+                Order::Smallest => group.sort_by_key(|f| f.manifest.size),
+                Order::Largest => group.sort_by_key(|f| std::cmp::Reverse(f.manifest.size)),
+                Order::Path => group.sort_by(|a, b| a.path.cmp(&b.path)),
+            }
+            files.extend(group.into_iter().cloned());
+        }
+        let links = manifest
+            .links
+            .iter()
+            .filter(|link| self.includes(&link.path))
+            .cloned()
+            .collect();
+        DirectoryManifest {
+            version: manifest.version,
+            files,
+            links,
+        }
+    }
+}
+
+fn build(patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| Error::Usage(format!("invalid pattern {pattern:?}: {e}")))?;
+    }
+    builder
+        .build()
+        .map_err(|e| Error::Usage(format!("invalid patterns: {e}")))
+}
+
+/// Whether the patterns match `path` or one of its directories.
+fn matches(patterns: &Gitignore, path: &str) -> bool {
+    patterns
+        .matched_path_or_any_parents(Path::new(path), false)
+        .is_ignore()
+}
@@ -0,0 +1,231 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use serde::{Deserialize, Serialize};
+
+use crate::download;
+use crate::error::{Error, Result};
+use crate::manifest::{self, Manifest};
+
+/// Marks a share token and the version of its encoding.
+const PREFIX: &str = "indexd-share-v1.";
+
+/// The part of an object a share grants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Range {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// A read-only grant to download an object, or a range of it, for anyone
+/// with an approved app key of their own.
+///
+/// A share holds a copy of the manifest narrowed to what it grants: a
+/// range share only carries the slices of the slabs covering the range, so
+/// it reveals nothing else of the object. The keys of those slices are in
+/// the token itself, so the expiry is honoured by `fetch` but can't stop
+/// someone who decodes the token by hand; treat a token like the data it
+/// unlocks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Share {
+    /// Seconds since the Unix epoch after which `fetch` refuses the share.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+    pub manifest: Manifest,
+}
+
+impl Share {
+    /// Shares the whole object, or only `range` of it, for `lifetime`.
+    pub fn new(
+        manifest: &Manifest,
+        range: Option<Range>,
+        lifetime: Option<Duration>,
+    ) -> Result<Self> {
+        let expires_at = lifetime.map(|lifetime| {
+            (SystemTime::now() + lifetime)
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+        let mut manifest = manifest.clone();
+        if let Some(range) = range {
+            if manifest.compression.is_some()
+                || manifest.cipher.is_some()
+                || !manifest.holes.is_empty()
+            {
+                return Err(Error::Usage(
+                    "only whole compressed, sealed or sparse objects can be shared".into(),
+                ));
+            }
+            if range.offset.saturating_add(range.length) > manifest.size {
+                return Err(Error::Usage(
+                    "the range is past the end of the object".into(),
+                ));
+            }
+            manifest.slabs = download::slice_slabs(&manifest.slabs, range.offset, range.length);
+            manifest.size = range.length;
+            // the object's checksums don't describe the range
+            manifest.checksum = [0; 32];
+            manifest.sha256 = None;
+            manifest.chunks.clear();
+        }
+        Ok(Self {
+            expires_at,
+            range,
+            manifest,
+        })
+    }
+
+    /// Encodes the share as a single line of URL-safe text.
+    pub fn encode(&self) -> Result<String> {
+        Ok(format!(
+            "{PREFIX}{}",
+            BASE64.encode(serde_json::to_vec(self)?)
+        ))
+    }
+
+    pub fn decode(token: &str) -> Result<Self> {
+        let encoded = token
+            .trim()
+            .strip_prefix(PREFIX)
+            .ok_or_else(|| Error::Usage("not a share token".into()))?;
+        let json = BASE64
+            .decode(encoded)
+            .map_err(|e| Error::Usage(format!("malformed share token: {e}")))?;
+        let share: Self = serde_json::from_slice(&json)?;
+        manifest::check_version(share.manifest.version)?;
+        Ok(share)
+    }
+
+    /// Fails once the share has expired.
+    pub fn check_expiry(&self) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        match self.expires_at {
+            Some(expires_at) if now >= expires_at => Err(Error::Usage(format!(
+                "the share expired {}",
+                httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(expires_at))
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses a lifetime such as `90s`, `30m`, `12h` or `7d`.
+pub fn parse_lifetime(s: &str) -> std::result::Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n.parse().map_err(|_| format!("invalid lifetime {s:?}"))?;
+    let secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid lifetime unit {unit:?}, expected s, m, h or d"
+            ));
+        }
+    };
+    Ok(Duration::from_secs(n * secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::aead;
+    use crate::client::Client;
+    use crate::mock::{self, MockBackend};
+    use crate::progress::Progress;
+    use crate::upload::{self, SECTOR_SIZE, UploadOptions};
+
+    async fn upload(sdk: &Client, data: &[u8], options: UploadOptions) -> Manifest {
+        let reader = Cursor::new(data.to_vec());
+        upload::upload_reader(sdk, reader, [1; 32], options, Progress::default())
+            .await
+            .unwrap()
+    }
+
+    async fn download(sdk: &Client, manifest: &Manifest) -> Vec<u8> {
+        let mut data = Vec::new();
+        download::download_object(sdk, &mut data, manifest, 0, manifest.size)
+            .await
+            .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn shares_round_trip() {
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let data = mock::pattern(2 * SECTOR_SIZE as usize);
+        let manifest = upload(&sdk, &data, UploadOptions::new(1, 1)).await;
+
+        let whole = Share::new(&manifest, None, None).unwrap().encode().unwrap();
+        assert!(whole.starts_with(PREFIX));
+        let whole = Share::decode(&whole).unwrap();
+        whole.check_expiry().unwrap();
+        assert_eq!(download(&sdk, &whole.manifest).await, data);
+
+        // a range across the slab boundary only carries the slices it needs
+        let (offset, length) = (SECTOR_SIZE - 100, 300);
+        let range = Range { offset, length };
+        let share = Share::new(&manifest, Some(range), None).unwrap();
+        let share = Share::decode(&share.encode().unwrap()).unwrap();
+        assert_eq!(share.range, Some(range));
+        assert_eq!(share.manifest.size, length);
+        assert_eq!(share.manifest.slabs.len(), 2);
+        assert_eq!(
+            download(&sdk, &share.manifest).await,
+            &data[offset as usize..(offset + length) as usize]
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_shares() {
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let manifest = upload(&sdk, b"data", UploadOptions::new(1, 1)).await;
+        let past_the_end = Range {
+            offset: 2,
+            length: 3,
+        };
+        assert!(matches!(
+            Share::new(&manifest, Some(past_the_end), None),
+            Err(Error::Usage(_))
+        ));
+
+        let options = UploadOptions::new(1, 1).with_aead(Some(aead::Algorithm::XChaCha20Poly1305));
+        let sealed = upload(&sdk, b"data", options).await;
+        let range = Range {
+            offset: 0,
+            length: 2,
+        };
+        assert!(matches!(
+            Share::new(&sealed, Some(range), None),
+            Err(Error::Usage(_))
+        ));
+        assert!(Share::new(&sealed, None, None).is_ok());
+
+        let expired = Share::new(&manifest, None, Some(Duration::ZERO)).unwrap();
+        assert!(matches!(expired.check_expiry(), Err(Error::Usage(_))));
+        assert!(matches!(Share::decode("hello"), Err(Error::Usage(_))));
+        assert!(Share::decode(&format!("{PREFIX}!!!")).is_err());
+    }
+
+    #[test]
+    fn lifetimes() {
+        assert_eq!(parse_lifetime("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_lifetime("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_lifetime("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(
+            parse_lifetime("7d"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert!(parse_lifetime("7w").is_err());
+        assert!(parse_lifetime("d").is_err());
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::warn;
+
+/// The exit status of a process stopped by a signal before it finished,
+/// as shells report for SIGINT.
+pub const INTERRUPTED: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Takes over SIGINT and SIGTERM for the rest of the process. The first
+/// signal asks work in progress to stop once what's in flight is durable,
+/// which long running work checks with [`requested`]; a second one exits
+/// at once.
+///
+/// Only work that can pick up where it stopped should listen: everything
+/// else is better off with the default of exiting on the first signal.
+pub fn listen() {
+    tokio::spawn(async {
+        loop {
+            signal().await;
+            if REQUESTED.swap(true, Ordering::SeqCst) {
+                warn!("interrupted again, aborting");
+                std::process::exit(INTERRUPTED);
+            }
+            warn!("finishing the slabs in flight; interrupt again to abort");
+        }
+    });
+}
+
+/// Whether a signal has asked work in progress to stop.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,88 @@
+use std::io;
+use std::pin::Pin;
+
+use bytes::Bytes;
+use futures::{Stream, TryStreamExt};
+use log::info;
+use tokio_util::io::StreamReader;
+use url::Url;
+
+use crate::error::{Error, Result};
+use crate::net::Network;
+use crate::objectstore;
+
+/// The most redirects followed before a URL is given up on.
+const MAX_REDIRECTS: usize = 10;
+
+/// The body of an HTTP response, read as it arrives.
+pub type Body = StreamReader<Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>, Bytes>;
+
+/// An object being read from a URL.
+pub struct UrlSource {
+    pub reader: Body,
+    /// The length the server announced, if it did.
+    pub size: Option<u64>,
+    /// Where the object was read from after following redirects.
+    pub url: Url,
+}
+
+/// Whether `url` names an object in a store `open` reads from directly.
+pub fn is_object_store(url: &Url) -> bool {
+    matches!(url.scheme(), "s3" | "gs")
+}
+
+/// Starts reading the object at `url`: an HTTP(S) URL, an `s3://bucket/key`
+/// of an S3-compatible store or a `gs://bucket/object` of Google Cloud
+/// Storage. The body is streamed, so nothing is buffered beyond what the
+/// reader asks for.
+pub async fn open(url: &Url, network: &Network) -> Result<UrlSource> {
+    match url.scheme() {
+        "http" | "https" => open_url(url, network).await,
+        "s3" | "gs" => {
+            // signed requests are never followed to another host
+            let client = network
+                .http_client()?
+                .redirect(reqwest::redirect::Policy::none())
+                .build()?;
+            let response = if url.scheme() == "s3" {
+                objectstore::get_s3(url, &client).await?
+            } else {
+                objectstore::get_gcs(url, &client).await?
+            };
+            Ok(from_response(response))
+        }
+        scheme => Err(Error::Usage(format!(
+            "unsupported URL scheme {scheme:?}; expected http, https, s3 or gs"
+        ))),
+    }
+}
+
+/// Starts reading the object at an HTTP(S) URL, following redirects.
+pub async fn open_url(url: &Url, network: &Network) -> Result<UrlSource> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Error::Usage(format!(
+            "unsupported URL scheme {:?}; expected http or https",
+            url.scheme()
+        )));
+    }
+    let client = network
+        .http_client()?
+        .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+        .build()?;
+    let response = client.get(url.clone()).send().await?.error_for_status()?;
+    if response.url() != url {
+        info!("redirected to {}", response.url());
+    }
+    Ok(from_response(response))
+}
+
+fn from_response(response: reqwest::Response) -> UrlSource {
+    let size = response.content_length();
+    let url = response.url().clone();
+    let body = response.bytes_stream().map_err(io::Error::other);
+    UrlSource {
+        reader: StreamReader::new(Box::pin(body)),
+        size,
+        url,
+    }
+}
@@ -0,0 +1,315 @@
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt, TryStreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::error::Result;
+
+/// Holes smaller than this are uploaded as zeros rather than recorded, so
+/// a fragmented file doesn't fill its manifest with tiny holes.
+const MIN_HOLE: u64 = 64 << 10;
+
+/// A range of a sparse file the filesystem doesn't store, which reads as
+/// zeros. Holes aren't uploaded; the manifest records them instead and the
+/// slabs hold only the data between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hole {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// A run of a sparse file, either data or a hole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Run {
+    /// Where the run starts in the file.
+    pub offset: u64,
+    pub length: u64,
+    /// Where the run's data starts in the slabs, `None` for a hole.
+    pub stored: Option<u64>,
+}
+
+/// Returns the holes the filesystem reports in the file at `path`, in
+/// order. Filesystems without holes, and platforms without a way to find
+/// them, report none.
+pub async fn find_holes(path: &Path) -> Result<Vec<Hole>> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || seek_holes(&path))
+        .await
+        .map_err(io::Error::other)?
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+fn seek_holes(path: &Path) -> Result<Vec<Hole>> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let fd = file.as_raw_fd();
+    let mut holes = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        // SAFETY: lseek only moves the offset of a descriptor we own
+        let start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_HOLE) };
+        if start < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let start = start as u64;
+        if start >= size {
+            break;
+        }
+        // SAFETY: as above
+        let end = unsafe { libc::lseek(fd, start as libc::off_t, libc::SEEK_DATA) };
+        // no data past the hole means it runs to the end of the file
+        let end = if end < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ENXIO) {
+                return Err(err.into());
+            }
+            size
+        } else {
+            end as u64
+        };
+        if end - start >= MIN_HOLE {
+            holes.push(Hole {
+                offset: start,
+                length: end - start,
+            });
+        }
+        offset = end;
+    }
+    Ok(holes)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+)))]
+fn seek_holes(_path: &Path) -> Result<Vec<Hole>> {
+    Ok(Vec::new())
+}
+
+/// Returns the number of bytes of a file of `size` bytes that aren't in
+/// its holes.
+pub fn stored_size(holes: &[Hole], size: u64) -> u64 {
+    size - holes.iter().map(|hole| hole.length).sum::<u64>()
+}
+
+/// Splits a file of `size` bytes into its runs of data and holes.
+fn all_runs(holes: &[Hole], size: u64) -> Vec<Run> {
+    let mut runs = Vec::with_capacity(holes.len() * 2 + 1);
+    let (mut offset, mut stored) = (0, 0);
+    for hole in holes {
+        if hole.offset > offset {
+            runs.push(Run {
+                offset,
+                length: hole.offset - offset,
+                stored: Some(stored),
+            });
+            stored += hole.offset - offset;
+        }
+        runs.push(Run {
+            offset: hole.offset,
+            length: hole.length,
+            stored: None,
+        });
+        offset = hole.offset + hole.length;
+    }
+    if size > offset {
+        runs.push(Run {
+            offset,
+            length: size - offset,
+            stored: Some(stored),
+        });
+    }
+    runs
+}
+
+/// Returns the runs covering `length` bytes of the file at `offset`.
+pub fn runs(holes: &[Hole], size: u64, offset: u64, length: u64) -> Vec<Run> {
+    let end = offset.saturating_add(length).min(size);
+    all_runs(holes, size)
+        .into_iter()
+        .filter(|run| run.offset < end && run.offset + run.length > offset)
+        .map(|run| {
+            let skip = offset.saturating_sub(run.offset);
+            Run {
+                offset: run.offset + skip,
+                length: (run.offset + run.length).min(end) - run.offset - skip,
+                stored: run.stored.map(|stored| stored + skip),
+            }
+        })
+        .collect()
+}
+
+/// Returns the data runs holding `length` bytes of the slabs starting at
+/// `offset`.
+pub fn stored_runs(holes: &[Hole], size: u64, offset: u64, length: u64) -> Vec<Run> {
+    let end = offset.saturating_add(length);
+    all_runs(holes, size)
+        .into_iter()
+        .filter_map(|run| {
+            let stored = run.stored?;
+            if stored >= end || stored + run.length <= offset {
+                return None;
+            }
+            let skip = offset.saturating_sub(stored);
+            Some(Run {
+                offset: run.offset + skip,
+                length: (stored + run.length).min(end) - stored - skip,
+                stored: Some(stored + skip),
+            })
+        })
+        .collect()
+}
+
+/// The data of a sparse file, read run by run.
+pub type RunReader = StreamReader<Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>, Bytes>;
+
+/// Reads the data `runs` of the file at `path` back to back.
+pub fn read_runs(path: &Path, runs: Vec<Run>) -> RunReader {
+    let path = path.to_path_buf();
+    let chunks = stream::iter(runs)
+        .then(move |run| {
+            let path = path.clone();
+            async move {
+                let mut file = File::open(&path).await?;
+                file.seek(SeekFrom::Start(run.offset)).await?;
+                Ok::<_, io::Error>(ReaderStream::new(file.take(run.length)))
+            }
+        })
+        .try_flatten();
+    StreamReader::new(Box::pin(chunks))
+}
+
+/// Returns a reader of `length` zeros.
+pub fn zeros(length: u64) -> impl AsyncRead + Unpin {
+    tokio::io::repeat(0).take(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::client::Client;
+    use crate::download;
+    use crate::mock::{self, MockBackend};
+    use crate::upload::{ResumableUpload, UploadOptions};
+
+    const HOLES: [Hole; 2] = [
+        Hole {
+            offset: 100,
+            length: 50,
+        },
+        Hole {
+            offset: 300,
+            length: 100,
+        },
+    ];
+
+    fn run(offset: u64, length: u64, stored: Option<u64>) -> Run {
+        Run {
+            offset,
+            length,
+            stored,
+        }
+    }
+
+    #[test]
+    fn runs_of_a_range() {
+        assert_eq!(stored_size(&HOLES, 500), 350);
+        assert_eq!(
+            runs(&HOLES, 500, 0, 500),
+            [
+                run(0, 100, Some(0)),
+                run(100, 50, None),
+                run(150, 150, Some(100)),
+                run(300, 100, None),
+                run(400, 100, Some(250)),
+            ]
+        );
+        assert_eq!(
+            runs(&HOLES, 500, 120, 200),
+            [
+                run(120, 30, None),
+                run(150, 150, Some(100)),
+                run(300, 20, None)
+            ]
+        );
+        // ranges past the end are cut short
+        assert_eq!(runs(&HOLES, 500, 450, 100), [run(450, 50, Some(300))]);
+        assert!(runs(&HOLES, 500, 500, 10).is_empty());
+        assert_eq!(runs(&[], 10, 2, 5), [run(2, 5, Some(2))]);
+    }
+
+    #[test]
+    fn runs_of_the_stored_bytes() {
+        assert_eq!(
+            stored_runs(&HOLES, 500, 90, 20),
+            [run(90, 10, Some(90)), run(150, 10, Some(100))]
+        );
+        assert_eq!(
+            stored_runs(&HOLES, 500, 200, 150),
+            [run(250, 50, Some(200)), run(400, 100, Some(250))]
+        );
+    }
+
+    #[tokio::test]
+    async fn sparse_files_round_trip() {
+        use std::io::{Seek, Write};
+
+        let dir = mock::temp_dir();
+        let input = dir.join("sparse");
+        let size = 4 * MIN_HOLE;
+        {
+            let mut file = std::fs::File::create(&input).unwrap();
+            file.set_len(size).unwrap();
+            file.write_all(&mock::pattern(4096)).unwrap();
+            file.seek(SeekFrom::Start(size - 4096)).unwrap();
+            file.write_all(&mock::pattern(4096)).unwrap();
+        }
+        let mut data = vec![0; size as usize];
+        data[..4096].copy_from_slice(&mock::pattern(4096));
+        data[size as usize - 4096..].copy_from_slice(&mock::pattern(4096));
+
+        // filesystems without holes report none, which uploads the zeros
+        let holes = find_holes(&input).await.unwrap();
+        let mut reader = read_runs(&input, all_runs(&holes, size));
+        let mut stored = Vec::new();
+        reader.read_to_end(&mut stored).await.unwrap();
+        assert_eq!(stored.len() as u64, stored_size(&holes, size));
+
+        let sdk = Client::from_backend(Arc::new(MockBackend::new(2)));
+        let checkpoint = dir.join("sparse.checkpoint");
+        let upload =
+            ResumableUpload::new(&input, &checkpoint, [1; 32], None, UploadOptions::new(1, 1))
+                .await
+                .unwrap();
+        let manifest = upload.run(&sdk).await.unwrap();
+        assert_eq!(manifest.holes, holes);
+        let mut downloaded = Vec::new();
+        download::download_object(&sdk, &mut downloaded, &manifest, 0, size)
+            .await
+            .unwrap();
+        assert_eq!(downloaded, data);
+        let mut tail = Vec::new();
+        download::download_object(&sdk, &mut tail, &manifest, size - 5000, 5000)
+            .await
+            .unwrap();
+        assert_eq!(tail, &data[size as usize - 5000..]);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::budget::{Budget, MONTH};
+use crate::catalog::Catalog;
+use crate::error::Result;
+use crate::manifest::{AnyManifest, Manifest, StoredManifest};
+use crate::upload::SECTOR_SIZE;
+
+/// What the catalogued uploads take up on indexd.
+///
+/// The SDK doesn't report an app's usage or allowance, so this is worked
+/// out from the catalog: only uploads made with it are counted, and the
+/// slabs of sealed manifests can't be counted without their passphrase.
+/// Slabs shared between objects, like packs and deduplicated chunks, are
+/// counted once.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub objects: u64,
+    pub snapshots: u64,
+    /// Objects and snapshots whose slabs weren't counted.
+    pub sealed: u64,
+    /// The size of the catalogued objects, before redundancy. Snapshots
+    /// mostly reuse data already stored, so they aren't included.
+    pub size: u64,
+    pub slabs: u64,
+    /// The bytes held by hosts, parity included.
+    pub stored: u64,
+    /// The size of the objects catalogued within the last 30 days.
+    pub uploaded_recently: u64,
+    /// What was charged against the budget within the last 30 days.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spent: Option<f64>,
+    /// What is left of the monthly budget.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<f64>,
+}
+
+impl Usage {
+    pub fn new(catalog: &Catalog, budget: Option<&Budget>) -> Result<Self> {
+        let mut usage = Usage {
+            uploaded_recently: catalog.uploaded_since(MONTH)?,
+            ..Usage::default()
+        };
+        let mut seen = HashSet::new();
+
+        for entry in catalog.list("")? {
+            usage.objects += 1;
+            usage.size += entry.size;
+            match catalog.get(&entry.name)? {
+                Some(StoredManifest::Plain(manifest)) => usage.count(&manifest, &mut seen)?,
+                Some(StoredManifest::Sealed(_)) => usage.sealed += 1,
+                None => {}
+            }
+        }
+        for snapshot in catalog.list_snapshots(None)? {
+            usage.snapshots += 1;
+            match catalog.get_snapshot(snapshot.id)? {
+                Some(StoredManifest::Plain(manifest)) => usage.count(&manifest, &mut seen)?,
+                Some(StoredManifest::Sealed(_)) => usage.sealed += 1,
+                None => {}
+            }
+        }
+
+        if let Some(budget) = budget {
+            let spent = catalog.spent_since(MONTH)?;
+            usage.spent = Some(spent);
+            usage.remaining = budget.per_month.map(|limit| (limit - spent).max(0.0));
+        }
+        Ok(usage)
+    }
+
+    /// Counts the slabs of `manifest` not seen before. Slices of a slab
+    /// keep its sectors, which identify it.
+    fn count(&mut self, manifest: &AnyManifest, seen: &mut HashSet<String>) -> Result<()> {
+        let files: Vec<&Manifest> = match manifest {
+            AnyManifest::File(manifest) => vec![manifest],
+            AnyManifest::Directory(manifest) => {
+                manifest.files.iter().map(|f| &f.manifest).collect()
+            }
+        };
+        for file in files {
+            for slab in &file.slabs {
+                if seen.insert(serde_json::to_string(&slab.sectors)?) {
+                    self.slabs += 1;
+                    self.stored += slab.sectors.len() as u64 * SECTOR_SIZE;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::Serialize;
+use tokio::fs::{self, File};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::budget::Budget;
+use crate::catalog::{Catalog, Entry};
+use crate::checksum;
+use crate::client::Client;
+use crate::directory;
+use crate::error::{Error, Result};
+use crate::estimate::Estimate;
+use crate::filter::Filter;
+use crate::manifest::{AnyManifest, Sealing, StoredManifest};
+use crate::metrics::METRICS;
+use crate::output::Output;
+use crate::progress::Progress;
+use crate::redundancy::Redundancy;
+use crate::upload::{self, UploadOptions};
+
+/// Why a path is part of a sync plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    /// The file has no catalogued object.
+    New,
+    /// The file differs from its catalogued object.
+    Modified,
+    /// The catalogued object has no local file.
+    Deleted,
+}
+
+/// A step of a sync plan.
+#[derive(Debug, Clone, Serialize)]
+pub struct Action {
+    pub change: Change,
+    /// The catalog name of the object.
+    pub name: String,
+    /// The path relative to the synced directory.
+    pub rel: PathBuf,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.change {
+            Change::New => '+',
+            Change::Modified => '~',
+            Change::Deleted => '-',
+        };
+        write!(f, "{sign} {}", self.name)
+    }
+}
+
+/// Mirrors a local directory into the catalog, one object per file named
+/// `<prefix>/<path>`.
+///
+/// A file is unchanged if its catalogued object has the same size and
+/// modification time. When only the time differs the file is hashed and
+/// compared with the object's checksum, which needs the sealing key for
+/// sealed objects; sealed objects it can't open are treated as changed.
+pub struct Syncer {
+    root: PathBuf,
+    prefix: String,
+    options: UploadOptions,
+    /// Seals every uploaded manifest with one key, so the objects of a
+    /// sync can be compared without deriving a key for each.
+    sealing: Option<Sealing>,
+    /// Excluded files are neither uploaded nor removed.
+    filter: Filter,
+    /// What every file uploaded is charged against.
+    budget: Option<Budget>,
+    /// How the changes applied while watching are printed.
+    output: Output,
+}
+
+impl Syncer {
+    pub fn new(
+        root: impl Into<PathBuf>,
+        prefix: &str,
+        options: UploadOptions,
+        sealing: Option<Sealing>,
+    ) -> Self {
+        Self {
+            root: root.into(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+            options,
+            sealing,
+            filter: Filter::default(),
+            budget: None,
+            output: Output::default(),
+        }
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_budget(mut self, budget: Option<Budget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the catalog name of the file at `rel`.
+    pub fn name(&self, rel: &Path) -> Result<String> {
+        let path = directory::to_manifest_path(rel)?;
+        Ok(match self.prefix.as_str() {
+            "" => path,
+            prefix => format!("{prefix}/{path}"),
+        })
+    }
+
+    /// Compares the directory against the catalog. With `delete`, objects
+    /// under the prefix without a local file are removed as well, unless
+    /// the filter excludes them.
+    pub async fn plan(&self, catalog: &Catalog, delete: bool) -> Result<Vec<Action>> {
+        let mut remote: HashMap<String, Entry> = catalog
+            .list(&self.list_prefix())?
+            .into_iter()
+            .map(|entry| (entry.name.clone(), entry))
+            .collect();
+
+        let mut actions = Vec::new();
+        for rel in directory::walk_filtered(&self.root, &self.filter).await? {
+            let name = self.name(&rel)?;
+            let entry = remote.remove(&name);
+            if let Some(change) = self.compare(catalog, &rel, entry.as_ref()).await? {
+                actions.push(Action { change, name, rel });
+            }
+        }
+        if delete {
+            let mut deleted: Vec<String> = remote.into_keys().collect();
+            deleted.sort();
+            for name in deleted {
+                let rel = PathBuf::from(&name[self.list_prefix().len()..]);
+                if self.filter.excludes_file(&rel) {
+                    continue;
+                }
+                actions.push(Action {
+                    change: Change::Deleted,
+                    name,
+                    rel,
+                });
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Compares a single path against the catalog, including files that
+    /// no longer exist.
+    pub async fn check(&self, catalog: &Catalog, rel: &Path) -> Result<Option<Action>> {
+        if self.filter.excludes_file(rel) {
+            return Ok(None);
+        }
+        let name = self.name(rel)?;
+        let entry = catalog
+            .list(&name)?
+            .into_iter()
+            .find(|entry| entry.name == name);
+        let exists = fs::metadata(self.root.join(rel))
+            .await
+            .is_ok_and(|m| m.is_file());
+        let change = match (exists, entry) {
+            (true, entry) => self.compare(catalog, rel, entry.as_ref()).await?,
+            (false, Some(_)) => Some(Change::Deleted),
+            (false, None) => None,
+        };
+        Ok(change.map(|change| Action {
+            change,
+            name,
+            rel: rel.to_path_buf(),
+        }))
+    }
+
+    /// Returns the estimated charge of uploading the files at `rels`.
+    pub async fn charge(&self, rels: &[&Path]) -> Result<Option<f64>> {
+        let Some(budget) = &self.budget else {
+            return Ok(None);
+        };
+        let mut sizes = Vec::with_capacity(rels.len());
+        for rel in rels {
+            sizes.push(fs::metadata(self.root.join(rel)).await?.len());
+        }
+        let redundancy = Redundancy {
+            data_shards: self.options.data_shards,
+            parity_shards: self.options.parity_shards,
+        };
+        Ok(Some(budget.charge(&Estimate::new(&sizes, redundancy, 0))))
+    }
+
+    /// Uploads or removes an object as planned. Removing moves the object
+    /// to the trash; its slabs stay stored.
+    pub async fn apply(&self, sdk: &Client, catalog: &Catalog, action: &Action) -> Result<()> {
+        if action.change == Change::Deleted {
+            catalog.trash(&action.name)?;
+            return Ok(());
+        }
+        let path = self.root.join(&action.rel);
+        let file = File::open(&path).await?;
+        let modified = directory::modified(&file.metadata().await?);
+        let manifest =
+            upload::upload_reader(sdk, file, rand::random(), self.options, Progress::default())
+                .await?;
+        let manifest = AnyManifest::File(manifest);
+        let stored = StoredManifest::new(manifest.clone(), self.sealing.as_ref())?;
+        let source = path.display().to_string();
+        catalog.put(&action.name, Some(&source), &manifest, &stored)?;
+        catalog.set_modified(&action.name, modified)?;
+        if let Some(charge) = self.charge(&[&action.rel]).await? {
+            catalog.record_spending(&action.name, charge)?;
+        }
+        Ok(())
+    }
+
+    /// Applies changes as they happen until the watcher fails.
+    ///
+    /// Events for a path are debounced: it is only compared against the
+    /// catalog once it has been quiet for `debounce`, so a file still being
+    /// written is uploaded once. Changes are applied one at a time while up
+    /// to `queue` events wait; when the queue is full the watcher stops
+    /// reading events until an upload finishes. Files that would go over
+    /// the budget are skipped.
+    pub async fn watch(
+        &self,
+        sdk: &Client,
+        catalog: &Catalog,
+        delete: bool,
+        debounce: Duration,
+        queue: usize,
+    ) -> Result<()> {
+        let root = fs::canonicalize(&self.root).await?;
+        let (tx, mut events) = mpsc::channel(queue.max(1));
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.blocking_send(event);
+        })
+        .map_err(watch_error)?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(watch_error)?;
+        info!("watching {}", root.display());
+
+        let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+        loop {
+            METRICS.set_queue_depth(pending.len() + events.len());
+            // sleeps until the path seen longest ago has been quiet long enough
+            let next = pending.values().min().map(|seen| *seen + debounce);
+            tokio::select! {
+                event = events.recv() => {
+                    let Some(event) = event else {
+                        return Err(std::io::Error::other("the watcher stopped").into());
+                    };
+                    let event = event.map_err(watch_error)?;
+                    if matches!(event.kind, EventKind::Access(_)) {
+                        continue;
+                    }
+                    for path in event.paths {
+                        if let Ok(rel) = path.strip_prefix(&root) {
+                            pending.insert(rel.to_path_buf(), Instant::now());
+                        }
+                    }
+                }
+                _ = sleep_until(next), if next.is_some() => {
+                    let now = Instant::now();
+                    let quiet: Vec<PathBuf> = pending
+                        .iter()
+                        .filter(|(_, seen)| now >= **seen + debounce)
+                        .map(|(rel, _)| rel.clone())
+                        .collect();
+                    for rel in quiet {
+                        pending.remove(&rel);
+                        for action in self.changes(catalog, &rel, delete).await? {
+                            if let Some(reason) = self.over_budget(catalog, &action).await? {
+                                warn!("skipping {}: {reason}", action.name);
+                                continue;
+                            }
+                            self.output.print(&action, || println!("{action}"))?;
+                            if let Err(e) = self.apply(sdk, catalog, &action).await {
+                                warn!("failed to sync {}: {e}", action.name);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the changes at `rel`, which may be a file, a directory or a
+    /// path that no longer exists.
+    async fn changes(&self, catalog: &Catalog, rel: &Path, delete: bool) -> Result<Vec<Action>> {
+        let path = self.root.join(rel);
+        let mut actions = Vec::new();
+        match fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_dir() => {
+                for file in directory::walk(&path).await? {
+                    actions.extend(self.check(catalog, &rel.join(file)).await?);
+                }
+            }
+            Ok(_) => actions.extend(self.check(catalog, rel).await?),
+            Err(_) if delete => {
+                // a removed directory takes every object under it along
+                let name = self.name(rel)?;
+                for entry in catalog.list(&format!("{name}/"))? {
+                    let rel = PathBuf::from(&entry.name[self.list_prefix().len()..]);
+                    if !self.filter.excludes_file(&rel) {
+                        actions.push(Action {
+                            change: Change::Deleted,
+                            rel,
+                            name: entry.name,
+                        });
+                    }
+                }
+                actions.extend(self.check(catalog, rel).await?);
+            }
+            Err(_) => {}
+        }
+        Ok(actions)
+    }
+
+    /// Returns why applying `action` would go over the budget, if it would.
+    async fn over_budget(&self, catalog: &Catalog, action: &Action) -> Result<Option<String>> {
+        let Some(budget) = &self.budget else {
+            return Ok(None);
+        };
+        if action.change == Change::Deleted {
+            return Ok(None);
+        }
+        match self.charge(&[&action.rel]).await? {
+            Some(charge) => budget.exceeded(catalog, charge),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the prefix every synced name starts with.
+    fn list_prefix(&self) -> String {
+        match self.prefix.as_str() {
+            "" => String::new(),
+            prefix => format!("{prefix}/"),
+        }
+    }
+
+    async fn compare(
+        &self,
+        catalog: &Catalog,
+        rel: &Path,
+        entry: Option<&Entry>,
+    ) -> Result<Option<Change>> {
+        let Some(entry) = entry else {
+            return Ok(Some(Change::New));
+        };
+        let path = self.root.join(rel);
+        let metadata = fs::metadata(&path).await?;
+        if entry.files.is_some() || entry.size != metadata.len() {
+            return Ok(Some(Change::Modified));
+        }
+        let modified = directory::modified(&metadata);
+        if modified.is_some() && entry.modified == modified {
+            return Ok(None);
+        }
+
+        // the time changed, so compare the contents
+        let checksum = match catalog.get(&entry.name)? {
+            Some(StoredManifest::Plain(AnyManifest::File(manifest))) => Some(manifest.checksum),
+            Some(StoredManifest::Sealed(sealed)) => match &self.sealing {
+                Some(sealing) if sealing.kdf == sealed.kdf => match sealed.open(&sealing.key) {
+                    Ok(AnyManifest::File(manifest)) => Some(manifest.checksum),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        if checksum.is_some() && checksum == Some(checksum::checksum_file(&path).await?) {
+            debug!("{} is unchanged apart from its time", entry.name);
+            catalog.set_modified(&entry.name, modified)?;
+            return Ok(None);
+        }
+        Ok(Some(Change::Modified))
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(deadline).await;
+    }
+}
+
+fn watch_error(e: notify::Error) -> Error {
+    Error::Io(std::io::Error::other(format!("watching for changes: {e}")))
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use indexd::Slab;
+use serde::{Deserialize, Serialize};
+
+/// What this process has seen of each host, shared by every client in it.
+pub static HOSTS: Telemetry = Telemetry::new();
+
+/// The transfers a host took part in.
+///
+/// The SDK reports transfers per slab, not per host, so every slab's
+/// outcome is counted against each host holding one of its shards. A
+/// single slab says little about any one host, but over many slabs the
+/// hosts that keep turning up in slow or failed ones stand out. Failed
+/// uploads don't say where they were headed, so only downloads count
+/// failures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostStats {
+    pub transfers: u64,
+    pub failures: u64,
+    /// The host's share of the plaintext of the slabs it took part in.
+    pub bytes: u64,
+    /// The time its transfers took, in milliseconds.
+    pub busy_ms: u64,
+}
+
+impl HostStats {
+    pub fn failure_rate(&self) -> f64 {
+        self.failures as f64 / self.transfers.max(1) as f64
+    }
+
+    /// The mean time a transfer took, in milliseconds.
+    pub fn latency_ms(&self) -> f64 {
+        self.busy_ms as f64 / self.transfers.max(1) as f64
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 * 1000.0 / self.busy_ms.max(1) as f64
+    }
+
+    /// Returns what was recorded after `earlier`, an older copy of the same
+    /// host's stats.
+    pub fn since(&self, earlier: &HostStats) -> HostStats {
+        HostStats {
+            transfers: self.transfers.saturating_sub(earlier.transfers),
+            failures: self.failures.saturating_sub(earlier.failures),
+            bytes: self.bytes.saturating_sub(earlier.bytes),
+            busy_ms: self.busy_ms.saturating_sub(earlier.busy_ms),
+        }
+    }
+
+    pub fn add(&mut self, other: &HostStats) {
+        self.transfers += other.transfers;
+        self.failures += other.failures;
+        self.bytes += other.bytes;
+        self.busy_ms += other.busy_ms;
+    }
+}
+
+#[derive(Debug)]
+pub struct Telemetry {
+    hosts: Mutex<BTreeMap<String, HostStats>>,
+}
+
+impl Telemetry {
+    const fn new() -> Self {
+        Self {
+            hosts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Counts a transfer of `slabs` that took `elapsed` against their
+    /// hosts.
+    pub fn record(&self, slabs: &[Slab], elapsed: Duration, ok: bool) {
+        let mut hosts = self.hosts.lock().unwrap();
+        for slab in slabs {
+            let shards = slab.sectors.len().max(1) as u64;
+            for sector in &slab.sectors {
+                let stats = hosts.entry(sector.host_key.to_string()).or_default();
+                stats.transfers += 1;
+                stats.failures += u64::from(!ok);
+                stats.bytes += slab.length as u64 / shards;
+                stats.busy_ms += elapsed.as_millis() as u64;
+            }
+        }
+    }
+
+    /// Returns what was recorded since the last `take` without clearing it.
+    pub fn snapshot(&self) -> BTreeMap<String, HostStats> {
+        self.hosts.lock().unwrap().clone()
+    }
+
+    /// Returns what was recorded since the last call and starts over.
+    pub fn take(&self) -> BTreeMap<String, HostStats> {
+        std::mem::take(&mut *self.hosts.lock().unwrap())
+    }
+}
@@ -0,0 +1,230 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+/// A token bucket shared by every transfer in one direction.
+///
+/// Transfers take tokens for the bytes they moved and wait out any debt
+/// before moving more, so the long-run rate stays at the limit while up to
+/// a second's worth of bytes can go out in a burst.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Bytes per second.
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` tokens and returns how long to wait before the next
+    /// transfer.
+    pub fn take(&self, bytes: u64) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate) - bytes as f64;
+        bucket.refilled = now;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        }
+    }
+}
+
+/// Parses a rate such as `500K`, `5M` or `1G` in bytes per second, with
+/// binary multiples.
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    let value: f64 = digits
+        .parse()
+        .map_err(|_| format!("invalid rate {s:?}, expected e.g. 500K or 5M"))?;
+    if !value.is_finite() || value <= 0.0 {
+        return Err(format!("rate {s:?} must be positive"));
+    }
+    Ok((value * multiplier as f64) as u64)
+}
+
+/// Parses a size such as `512M`, `2G` or `1GiB` in bytes, with binary
+/// multiples.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let digits = s
+        .strip_suffix("iB")
+        .or_else(|| s.strip_suffix('B'))
+        .unwrap_or(s);
+    parse_rate(digits).map_err(|_| format!("invalid size {s:?}, expected e.g. 512M or 2G"))
+}
+
+/// Limits the rate bytes are read through it. Each byte read is charged
+/// `weight` tokens, so an upload can be charged for the parity shards its
+/// data turns into.
+pub struct ThrottledReader<R> {
+    inner: R,
+    limiter: Option<Arc<RateLimiter>>,
+    weight: f64,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> ThrottledReader<R> {
+    pub fn new(inner: R, limiter: Option<Arc<RateLimiter>>, weight: f64) -> Self {
+        Self {
+            inner,
+            limiter,
+            weight,
+            sleep: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ThrottledReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(sleep) = &mut this.sleep {
+            ready!(sleep.as_mut().poll(cx));
+            this.sleep = None;
+        }
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let n = buf.filled().len() - before;
+        if let Some(limiter) = &this.limiter {
+            this.sleep = delay(limiter.take((n as f64 * this.weight) as u64));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Limits the rate bytes are written through it.
+pub struct ThrottledWriter<W> {
+    inner: W,
+    limiter: Option<Arc<RateLimiter>>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<W> ThrottledWriter<W> {
+    pub fn new(inner: W, limiter: Option<Arc<RateLimiter>>) -> Self {
+        Self {
+            inner,
+            limiter,
+            sleep: None,
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ThrottledWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if let Some(sleep) = &mut this.sleep {
+            ready!(sleep.as_mut().poll(cx));
+            this.sleep = None;
+        }
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        if let Some(limiter) = &this.limiter {
+            this.sleep = delay(limiter.take(n as u64));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+fn delay(wait: Duration) -> Option<Pin<Box<Sleep>>> {
+    (!wait.is_zero()).then(|| Box::pin(tokio::time::sleep(wait)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn rates_and_sizes() {
+        assert_eq!(parse_rate("500"), Ok(500));
+        assert_eq!(parse_rate("500K"), Ok(500 << 10));
+        assert_eq!(parse_rate(" 1.5m "), Ok(3 << 19));
+        assert_eq!(parse_rate("2G"), Ok(2 << 30));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("-5M").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("1GiB"), Ok(1 << 30));
+        assert_eq!(parse_size("4KB"), Ok(4 << 10));
+        assert!(parse_size("1TB").is_err());
+    }
+
+    #[test]
+    fn bursts_then_debt() {
+        let limiter = RateLimiter::new(1000);
+        assert_eq!(limiter.take(1000), Duration::ZERO);
+        let wait = limiter.take(500);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn transfers_keep_to_the_rate() {
+        let limiter = Arc::new(RateLimiter::new(100_000));
+        let data = vec![7; 150_000];
+        let start = Instant::now();
+        let mut read = Vec::new();
+        ThrottledReader::new(data.as_slice(), Some(limiter.clone()), 1.0)
+            .read_to_end(&mut read)
+            .await
+            .unwrap();
+        assert_eq!(read, data);
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        // the debt is shared with writes
+        let start = Instant::now();
+        let mut writer = ThrottledWriter::new(Vec::new(), Some(limiter));
+        writer.write_all(&data[..50_000]).await.unwrap();
+        writer.write_all(&data[..1]).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        let start = Instant::now();
+        let mut unlimited = ThrottledReader::new(data.as_slice(), None, 1.0);
+        unlimited.read_to_end(&mut read).await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+}
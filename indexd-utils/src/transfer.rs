@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+use crate::catalog::Catalog;
+use crate::checksum::{self, ChecksumWriter};
+use crate::client::Client;
+use crate::download;
+use crate::error::{Error, Result};
+use crate::keys::Kdf;
+use crate::manifest::{AnyManifest, Manifest, Sealing, StoredManifest};
+use crate::progress::{Progress, ProgressWriter};
+use crate::upload::{ResumableUpload, UploadOptions};
+
+/// Uploads the file at `input` and catalogues it as `name`, sealing its
+/// manifest under `passphrase` if one is given. This is the whole of a
+/// file upload for the bindings that embed the pipeline in other
+/// languages; the command line tool adds manifest files, deduplication and
+/// budgets on top.
+pub async fn upload_file(
+    sdk: &Client,
+    catalog: &Catalog,
+    options: UploadOptions,
+    input: &Path,
+    name: &str,
+    passphrase: Option<&str>,
+    progress: Progress,
+) -> Result<Manifest> {
+    let sealing = match passphrase {
+        Some(passphrase) => {
+            let kdf = Kdf::default();
+            let key = kdf.derive(passphrase).await?;
+            Some(Sealing::new(kdf, key))
+        }
+        None => None,
+    };
+    let checkpoint_path = with_suffix(input, ".checkpoint");
+    let upload = ResumableUpload::new(
+        input,
+        &checkpoint_path,
+        sealing
+            .as_ref()
+            .map_or_else(rand::random, |sealing| sealing.key),
+        sealing.as_ref().map(|sealing| sealing.kdf.clone()),
+        options,
+    )
+    .await?
+    .with_progress(progress);
+    let manifest = upload.run(sdk).await?;
+
+    let any = AnyManifest::File(manifest.clone());
+    let stored = StoredManifest::new(any.clone(), sealing.as_ref())?;
+    let source = input.display().to_string();
+    catalog.put(name, Some(&source), &any, &stored)?;
+    fs::remove_file(checkpoint_path).await?;
+    Ok(manifest)
+}
+
+/// Returns the manifest of the file catalogued as `name`, opening it with
+/// `passphrase` if it is sealed.
+pub async fn open_file(
+    catalog: &Catalog,
+    name: &str,
+    passphrase: Option<&str>,
+) -> Result<Manifest> {
+    let stored = catalog
+        .get(name)?
+        .ok_or_else(|| Error::NotFound(format!("{name} is not in the catalog")))?;
+    let manifest = match stored {
+        StoredManifest::Plain(manifest) => manifest,
+        StoredManifest::Sealed(sealed) => {
+            let passphrase = passphrase.ok_or_else(|| {
+                Error::Usage(format!("{name} is sealed and no passphrase was given"))
+            })?;
+            sealed.open_with_passphrase(passphrase).await?
+        }
+    };
+    match manifest {
+        AnyManifest::File(manifest) => Ok(manifest),
+        AnyManifest::Directory(_) => Err(Error::Usage(format!(
+            "{name} is a directory; only files can be transferred"
+        ))),
+    }
+}
+
+/// Downloads a file to `output`, which is only replaced once the whole
+/// file has been downloaded and matches its checksum.
+pub async fn download_file(
+    sdk: &Client,
+    manifest: &Manifest,
+    output: &Path,
+    progress: Progress,
+) -> Result<()> {
+    let partial = with_suffix(output, ".part");
+    let mut writer =
+        ChecksumWriter::new(ProgressWriter::new(File::create(&partial).await?, progress));
+    let result = async {
+        download::download_object(sdk, &mut writer, manifest, 0, manifest.size).await?;
+        writer.flush().await?;
+        checksum::verify_written(&writer, manifest)
+    }
+    .await;
+    drop(writer);
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial).await;
+        return Err(e);
+    }
+    fs::rename(&partial, output).await?;
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
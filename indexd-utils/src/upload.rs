@@ -0,0 +1,1005 @@
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use bytes::{Bytes, BytesMut};
+use futures::{StreamExt, future, stream};
+use indexd::Slab;
+use log::{debug, info};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::either::Either;
+use tokio_util::task::AbortOnDropHandle;
+
+use crate::aead::{self, Cipher, SealReader};
+use crate::checkpoint::Checkpoint;
+use crate::checksum::{self, ChecksumReader};
+use crate::client::Client;
+use crate::compression::{Compression, ZstdReader};
+use crate::error::{Error, Result};
+use crate::fileio;
+use crate::keys::{self, Kdf};
+use crate::manifest::Manifest;
+use crate::progress::{Event, Progress, ProgressReader};
+use crate::redundancy::{Redundancy, SlabSizing};
+use crate::shutdown;
+use crate::sparse::{self, Run};
+
+pub const SECTOR_SIZE: u64 = 1 << 22;
+
+/// The number of slabs uploaded between checkpoints.
+const SEGMENT_SLABS: u64 = 8;
+
+/// How an upload is encoded and how much of it may be in flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadOptions {
+    pub data_shards: u8,
+    pub parity_shards: u8,
+    /// The maximum number of shards being encoded or uploaded at once,
+    /// which bounds memory use to roughly this many sectors.
+    pub max_inflight_shards: usize,
+    /// Compresses the plaintext before it is encrypted.
+    pub compression: Option<Compression>,
+    /// Also records the SHA-256 of the plaintext, for tools that don't
+    /// know BLAKE2b.
+    pub sha256: bool,
+    /// Seals the plaintext with this algorithm and a fresh key before it
+    /// is encrypted, so tampering is detected on download.
+    pub aead: Option<aead::Algorithm>,
+    /// The number of slabs read and encoded ahead of the one uploading,
+    /// each held in memory until its turn.
+    pub read_ahead: usize,
+    /// The memory the slab and shard buffers may take. Fewer segments
+    /// upload at once, and fewer slabs are read ahead, to stay within it.
+    pub max_memory: Option<u64>,
+    /// Lets uploads of local files choose their slab size within these
+    /// bounds instead of using `data_shards` and `parity_shards` as they
+    /// are.
+    pub slab_sizing: Option<SlabSizing>,
+}
+
+impl UploadOptions {
+    /// Returns options that keep a single slab in flight.
+    pub fn new(data_shards: u8, parity_shards: u8) -> Self {
+        Self {
+            data_shards,
+            parity_shards,
+            max_inflight_shards: data_shards as usize + parity_shards as usize,
+            compression: None,
+            sha256: false,
+            aead: None,
+            read_ahead: 1,
+            max_memory: None,
+            slab_sizing: None,
+        }
+    }
+
+    pub fn with_max_inflight_shards(mut self, max_inflight_shards: usize) -> Self {
+        self.max_inflight_shards = max_inflight_shards;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_sha256(mut self, sha256: bool) -> Self {
+        self.sha256 = sha256;
+        self
+    }
+
+    pub fn with_aead(mut self, aead: Option<aead::Algorithm>) -> Self {
+        self.aead = aead;
+        self
+    }
+
+    pub fn with_read_ahead(mut self, read_ahead: usize) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    pub fn with_max_memory(mut self, max_memory: Option<u64>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    pub fn with_slab_sizing(mut self, slab_sizing: Option<SlabSizing>) -> Self {
+        self.slab_sizing = slab_sizing;
+        self
+    }
+
+    /// Returns the options to upload `size` bytes with: the shards the
+    /// slab sizing chooses for that size, if there is one.
+    pub fn sized_for(mut self, size: u64) -> Self {
+        let Some(sizing) = self.slab_sizing else {
+            return self;
+        };
+        let redundancy = Redundancy {
+            data_shards: self.data_shards,
+            parity_shards: self.parity_shards,
+        };
+        let chosen = sizing.choose(size, redundancy);
+        if chosen != redundancy {
+            debug!("uploading {size} bytes as {chosen} slabs");
+        }
+        self.data_shards = chosen.data_shards;
+        self.parity_shards = chosen.parity_shards;
+        self
+    }
+
+    /// The plaintext bytes of a full slab.
+    pub fn slab_size(&self) -> usize {
+        self.data_shards as usize * SECTOR_SIZE as usize
+    }
+
+    pub fn total_shards(&self) -> usize {
+        self.data_shards as usize + self.parity_shards as usize
+    }
+
+    /// Returns how many segments may upload concurrently, or how many
+    /// slabs of a single segment, such as a stream's, may. Each slab is a
+    /// separate SDK upload that encodes and uploads it, so running them
+    /// side by side overlaps the encoding of one slab with the upload of
+    /// another.
+    ///
+    /// Never more than fit in the memory budget, though always at least
+    /// one.
+    pub fn inflight_segments(&self) -> usize {
+        let segments = (self.max_inflight_shards / self.total_shards()).max(1);
+        match self.max_memory {
+            Some(max_memory) => {
+                let fit = max_memory / self.segment_memory(self.slabs_read_ahead());
+                segments.min(fit as usize).max(1)
+            }
+            None => segments,
+        }
+    }
+
+    /// Returns how many slabs each segment reads ahead: as many as asked,
+    /// fewer if a single segment would go over the memory budget.
+    pub fn slabs_read_ahead(&self) -> usize {
+        let mut read_ahead = self.read_ahead.max(1);
+        if let Some(max_memory) = self.max_memory {
+            while read_ahead > 1 && self.segment_memory(read_ahead) > max_memory {
+                read_ahead -= 1;
+            }
+        }
+        read_ahead
+    }
+
+    /// Returns the bytes a segment holds at its peak: the slabs read ahead,
+    /// the one being read and the one uploading, and the shards it is
+    /// coded into.
+    fn segment_memory(&self, read_ahead: usize) -> u64 {
+        let slab = self.slab_size() as u64;
+        let shards = self.total_shards() as u64 * SECTOR_SIZE;
+        (read_ahead as u64 + 2) * slab + shards
+    }
+}
+
+/// An upload of a local file that persists its progress to a checkpoint
+/// file after every segment so it can be resumed after an interruption.
+pub struct ResumableUpload {
+    checkpoint_path: PathBuf,
+    checkpoint: Checkpoint,
+    max_inflight_shards: Option<usize>,
+    read_ahead: usize,
+    max_memory: Option<u64>,
+    progress: Progress,
+}
+
+impl ResumableUpload {
+    /// Starts a new upload of `input`, writing checkpoints to
+    /// `checkpoint_path`. With a slab sizing in `options`, the slab size is
+    /// chosen for the size of `input`.
+    pub async fn new(
+        input: impl Into<PathBuf>,
+        checkpoint_path: impl Into<PathBuf>,
+        encryption_key: [u8; 32],
+        kdf: Option<Kdf>,
+        options: UploadOptions,
+    ) -> Result<Self> {
+        let input = input.into();
+        let input_size = fs::metadata(&input).await?.len();
+        // the checkpoint records the choice, so a resumed upload keeps it
+        let options = options.sized_for(input_size);
+        // the holes of a compressed or sealed input are coded like its
+        // data, so only plain uploads leave them out
+        let holes = if options.compression.is_none() && options.aead.is_none() {
+            sparse::find_holes(&input).await?
+        } else {
+            Vec::new()
+        };
+        if !holes.is_empty() {
+            info!(
+                "leaving out {} bytes of holes",
+                input_size - sparse::stored_size(&holes, input_size)
+            );
+        }
+        let checkpoint = Checkpoint::new(
+            input,
+            input_size,
+            encryption_key,
+            kdf,
+            options.data_shards,
+            options.parity_shards,
+        )
+        .with_compression(options.compression)
+        .with_sha256(options.sha256)
+        .with_cipher(options.aead.map(Cipher::new))
+        .with_holes(holes);
+        let checkpoint_path = checkpoint_path.into();
+        checkpoint.save(&checkpoint_path).await?;
+        Ok(Self {
+            checkpoint_path,
+            checkpoint,
+            max_inflight_shards: Some(options.max_inflight_shards),
+            read_ahead: options.read_ahead,
+            max_memory: options.max_memory,
+            progress: Progress::default(),
+        })
+    }
+
+    /// Resumes an upload from an existing checkpoint.
+    pub async fn resume(checkpoint_path: impl Into<PathBuf>) -> Result<Self> {
+        let checkpoint_path = checkpoint_path.into();
+        let checkpoint = Checkpoint::load(&checkpoint_path).await?;
+        let input_size = fs::metadata(&checkpoint.input).await?.len();
+        if input_size != checkpoint.input_size {
+            return Err(Error::Checkpoint(format!(
+                "input size changed from {} to {input_size}",
+                checkpoint.input_size
+            )));
+        }
+        Ok(Self {
+            checkpoint_path,
+            checkpoint,
+            max_inflight_shards: None,
+            read_ahead: 1,
+            max_memory: None,
+            progress: Progress::default(),
+        })
+    }
+
+    /// Overrides the number of shards in flight. The redundancy of a
+    /// resumed upload comes from its checkpoint, but its concurrency can be
+    /// changed freely.
+    pub fn with_max_inflight_shards(mut self, max_inflight_shards: usize) -> Self {
+        self.max_inflight_shards = Some(max_inflight_shards);
+        self
+    }
+
+    /// Overrides the number of slabs read ahead, which like the shards in
+    /// flight isn't part of the checkpoint.
+    pub fn with_read_ahead(mut self, read_ahead: usize) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    pub fn with_max_memory(mut self, max_memory: Option<u64>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    pub fn options(&self) -> UploadOptions {
+        let options =
+            UploadOptions::new(self.checkpoint.data_shards, self.checkpoint.parity_shards)
+                .with_compression(self.checkpoint.compression)
+                .with_sha256(self.checkpoint.sha256)
+                .with_aead(self.checkpoint.cipher.map(|cipher| cipher.algorithm))
+                .with_read_ahead(self.read_ahead)
+                .with_max_memory(self.max_memory);
+        match self.max_inflight_shards {
+            Some(n) => options.with_max_inflight_shards(n),
+            None => options,
+        }
+    }
+
+    /// Sends progress events for this upload to `progress`.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn encryption_key(&self) -> &[u8; 32] {
+        &self.checkpoint.encryption_key
+    }
+
+    /// The passphrase parameters the master key was derived with, if any.
+    pub fn kdf(&self) -> Option<&Kdf> {
+        self.checkpoint.kdf.as_ref()
+    }
+
+    pub fn input(&self) -> &Path {
+        &self.checkpoint.input
+    }
+
+    pub fn checkpoint_path(&self) -> &Path {
+        &self.checkpoint_path
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.checkpoint.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.checkpoint.input_size
+    }
+
+    /// Uploads the remaining segments of the input, as many at a time as
+    /// the shard budget allows, and returns a manifest covering the whole
+    /// file.
+    ///
+    /// Segments complete in order so the checkpoint always describes a
+    /// contiguous prefix of the input.
+    pub async fn run(mut self, sdk: &Client) -> Result<Manifest> {
+        let sdk = &sdk.clone().with_progress(self.progress.clone());
+        let options = self.options();
+        let segment_size = self.checkpoint.data_shards as u64 * SECTOR_SIZE * SEGMENT_SLABS;
+
+        let stored_size = self.checkpoint.stored_size();
+        let mut segments = Vec::new();
+        let mut segment = self.checkpoint.next_segment;
+        let mut offset = self.checkpoint.offset;
+        while offset < stored_size {
+            let length = segment_size.min(stored_size - offset);
+            segments.push((segment, offset, length));
+            segment += 1;
+            offset += length;
+        }
+
+        // holes count as transferred, since they download without a fetch
+        let input_size = self.checkpoint.input_size;
+        if input_size > stored_size {
+            self.progress.emit(Event::BytesTransferred {
+                bytes: input_size - stored_size,
+            });
+        }
+
+        let input = self.checkpoint.input.clone();
+        let holes = self.checkpoint.holes.clone();
+        let progress = self.progress.clone();
+        let pool = BufferPool::new(options.slab_size());
+        let key = self.checkpoint.encryption_key;
+        let cipher = self.checkpoint.cipher;
+        // once a shutdown is requested no more segments are started, but
+        // those in flight are finished and checkpointed
+        let mut uploads = stream::iter(segments)
+            .take_while(|_| future::ready(!shutdown::requested()))
+            .map(|(segment, offset, length)| {
+                let runs = (!holes.is_empty())
+                    .then(|| sparse::stored_runs(&holes, input_size, offset, length));
+                let segment = Segment {
+                    master_key: key,
+                    index: segment,
+                    offset,
+                    length,
+                    last: offset + length == stored_size,
+                    runs,
+                };
+                // each slab is retried on its own, so segments aren't; the
+                // segments side by side keep the slabs in flight, so each
+                // uploads one at a time
+                let options = options.with_max_inflight_shards(options.total_shards());
+                upload_segment(sdk, &input, &progress, segment, options, cipher, &pool)
+            })
+            .buffered(options.inflight_segments());
+
+        while let Some(result) = uploads.next().await {
+            let (length, slabs) = result?;
+            for slab in slabs {
+                self.progress.emit(Event::SlabUploaded {
+                    index: self.checkpoint.slabs.len(),
+                    length: slab.length as u64,
+                });
+                self.checkpoint.slabs.push(slab);
+            }
+            self.checkpoint.offset += length;
+            self.checkpoint.next_segment += 1;
+            self.checkpoint.save(&self.checkpoint_path).await?;
+            info!("uploaded {}/{stored_size} bytes", self.checkpoint.offset);
+        }
+        if self.checkpoint.offset < stored_size {
+            return Err(Error::Interrupted(format!(
+                "{} of {stored_size} bytes uploaded; run `upload --resume {}` to finish",
+                self.checkpoint.offset,
+                self.checkpoint_path.display()
+            )));
+        }
+
+        // the input is read again since segments upload out of order
+        let checksum = checksum::checksum_file(&self.checkpoint.input).await?;
+        let sha256 = if self.checkpoint.sha256 {
+            Some(checksum::sha256_file(&self.checkpoint.input).await?)
+        } else {
+            None
+        };
+        Ok(Manifest::new(
+            self.checkpoint.input_size,
+            checksum,
+            &self.checkpoint.encryption_key,
+            self.checkpoint.data_shards,
+            self.checkpoint.parity_shards,
+            self.checkpoint.slabs,
+        )
+        .with_compression(self.checkpoint.compression)
+        .with_cipher(self.checkpoint.cipher)
+        .with_sha256(sha256)
+        .with_hosts(sdk.host_policy().cloned())
+        .with_holes(self.checkpoint.holes))
+    }
+}
+
+/// Uploads an unbounded stream, such as stdin, in a single pass. Unlike
+/// [`ResumableUpload`] this cannot be resumed since the input cannot be
+/// re-read.
+pub async fn upload_reader<R>(
+    sdk: &Client,
+    reader: R,
+    encryption_key: [u8; 32],
+    options: UploadOptions,
+    progress: Progress,
+) -> Result<Manifest>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let sdk = &sdk.clone().with_progress(progress.clone());
+    let (reader, checksum) = ChecksumReader::new(reader, options.sha256);
+    let reader = ProgressReader::new(reader, progress.clone());
+    let cipher = options.aead.map(Cipher::new);
+    let reader = encode(reader, options.compression, cipher, 0, true)?;
+    let pool = BufferPool::new(options.slab_size());
+    let slabs = upload_slabs(sdk, reader, &encryption_key, 0, options, &pool).await?;
+    for (index, slab) in slabs.iter().enumerate() {
+        progress.emit(Event::SlabUploaded {
+            index,
+            length: slab.length as u64,
+        });
+    }
+    Ok(Manifest::new(
+        checksum.read(),
+        checksum.checksum(),
+        &encryption_key,
+        options.data_shards,
+        options.parity_shards,
+        slabs,
+    )
+    .with_compression(options.compression)
+    .with_cipher(cipher)
+    .with_sha256(checksum.sha256())
+    .with_hosts(sdk.host_policy().cloned()))
+}
+
+/// Extends an uploaded file with the data appended to it since.
+///
+/// `reader` reads the whole grown file. Its first `manifest.size` bytes
+/// are only hashed, to check they are still what was uploaded, and the rest
+/// is uploaded as new slabs after the manifest's, keyed from `master_key`
+/// and coded with the manifest's redundancy whatever `options` say. The
+/// existing slabs are kept as they are, so a partly filled last slab stays
+/// partly filled. Compressed, sealed, deduplicated and sparse uploads can't
+/// be extended since their slabs don't map directly onto the file.
+pub async fn append<R>(
+    sdk: &Client,
+    manifest: &Manifest,
+    reader: R,
+    master_key: &[u8; 32],
+    options: UploadOptions,
+    progress: Progress,
+) -> Result<Manifest>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    if manifest.compression.is_some()
+        || manifest.cipher.is_some()
+        || !manifest.chunks.is_empty()
+        || !manifest.holes.is_empty()
+    {
+        return Err(Error::Usage(
+            "only uploads that aren't compressed, sealed, deduplicated or sparse can be appended to"
+                .into(),
+        ));
+    }
+    // the manifest records one redundancy for all of its slabs
+    let options = UploadOptions {
+        data_shards: manifest.data_shards,
+        parity_shards: manifest.parity_shards,
+        slab_sizing: None,
+        ..options
+    };
+    let (mut reader, checksum) = ChecksumReader::new(reader, manifest.sha256.is_some());
+    let prefix = tokio::io::copy(
+        &mut (&mut reader).take(manifest.size),
+        &mut tokio::io::sink(),
+    )
+    .await?;
+    if prefix < manifest.size || checksum.checksum() != manifest.checksum {
+        return Err(Error::Integrity(
+            "the input no longer starts with the uploaded data".into(),
+        ));
+    }
+    progress.emit(Event::BytesTransferred { bytes: prefix });
+
+    let sdk = &sdk.clone().with_progress(progress.clone());
+    let reader = ProgressReader::new(reader, progress.clone());
+    let pool = BufferPool::new(options.slab_size());
+    // every segment so far held at least one slab, so the slab count is a
+    // segment index no earlier upload or append derived keys from
+    let segment = manifest.slabs.len() as u64;
+    let slabs = upload_slabs(sdk, reader, master_key, segment, options, &pool).await?;
+
+    let mut appended = manifest.clone();
+    for slab in slabs {
+        progress.emit(Event::SlabUploaded {
+            index: appended.slabs.len(),
+            length: slab.length as u64,
+        });
+        appended.slabs.push(slab);
+    }
+    appended.size = checksum.read();
+    appended.checksum = checksum.checksum();
+    appended.sha256 = checksum.sha256();
+    Ok(appended)
+}
+
+/// A contiguous range of the input uploaded between checkpoints. Offsets
+/// count only the data of a sparse input, whose data runs in the range are
+/// read in place of the range itself.
+struct Segment {
+    master_key: [u8; 32],
+    index: u64,
+    offset: u64,
+    length: u64,
+    /// Whether the segment is the input's last.
+    last: bool,
+    runs: Option<Vec<Run>>,
+}
+
+async fn upload_segment(
+    sdk: &Client,
+    input: &Path,
+    progress: &Progress,
+    segment: Segment,
+    options: UploadOptions,
+    cipher: Option<Cipher>,
+    pool: &Arc<BufferPool>,
+) -> Result<(u64, Vec<Slab>)> {
+    let Segment {
+        master_key,
+        index,
+        offset,
+        length,
+        last,
+        runs,
+    } = segment;
+    debug!("uploading {length} bytes at {offset}");
+    // plain data is read straight into the slab buffers
+    if runs.is_none() && options.compression.is_none() && cipher.is_none() {
+        let file = Arc::new(File::open(input).await?.into_std().await);
+        let range = FileRange {
+            file,
+            offset,
+            length,
+            progress: progress.clone(),
+        };
+        let slabs = upload_range(sdk, range, &master_key, index, options, pool).await?;
+        return Ok((length, slabs));
+    }
+    let reader = match runs {
+        Some(runs) => Either::Right(sparse::read_runs(input, runs)),
+        None => {
+            let mut file = File::open(input).await?;
+            file.seek(SeekFrom::Start(offset)).await?;
+            Either::Left(file.take(length))
+        }
+    };
+    let reader = ProgressReader::new(reader, progress.clone());
+    // segments are whole blocks, so their blocks continue the previous ones
+    // and only the last segment ends with the object's last block
+    let first_block = cipher.map_or(0, |cipher| cipher.block(offset));
+    let reader = encode(reader, options.compression, cipher, first_block, last)?;
+    let slabs = upload_slabs(sdk, reader, &master_key, index, options, pool).await?;
+    Ok((length, slabs))
+}
+
+/// Uploads everything read from `reader` with one SDK call per slab, each
+/// under its own key derived from the master key, the segment and the
+/// slab's index in it. Knowing one slab's key exposes nothing else, and a
+/// resumed upload derives the same keys again.
+///
+/// The input is read into memory a slab at a time by a separate task, up to
+/// `read_ahead` slabs ahead, so reading and encoding the next slab overlaps
+/// the upload of the current one. Holding each slab also means a failed
+/// slab can be retried, even when the input is a stream.
+///
+/// The SDK erasure codes a slab on whichever thread polls its upload, so
+/// each slab uploads on a task of its own, and as many slabs as
+/// `options` keep in flight upload at once: the next slabs are coded on
+/// other cores while the first one's shards are still on their way to
+/// hosts.
+async fn upload_slabs<R>(
+    sdk: &Client,
+    reader: R,
+    master_key: &[u8; 32],
+    segment: u64,
+    options: UploadOptions,
+    pool: &Arc<BufferPool>,
+) -> Result<Vec<Slab>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(options.slabs_read_ahead());
+    // stops at the next send once the receiver is gone, after a failure
+    let reading = tokio::spawn({
+        let pool = pool.clone();
+        async move {
+            let mut reader = reader;
+            loop {
+                let slab = read_slab(&mut reader, pool.take(), pool.size).await?;
+                if slab.is_empty() || tx.send(slab).await.is_err() {
+                    return Ok::<_, Error>(());
+                }
+            }
+        }
+    });
+    upload_read(sdk, rx, reading, master_key, segment, options, pool).await
+}
+
+/// A range of a local file to upload as it is.
+struct FileRange {
+    file: Arc<std::fs::File>,
+    offset: u64,
+    length: u64,
+    progress: Progress,
+}
+
+/// Like [`upload_slabs`], but reads each slab of `range` straight into its
+/// buffer with [`fileio::read_at`] instead of copying it out of a reader.
+async fn upload_range(
+    sdk: &Client,
+    range: FileRange,
+    master_key: &[u8; 32],
+    segment: u64,
+    options: UploadOptions,
+    pool: &Arc<BufferPool>,
+) -> Result<Vec<Slab>> {
+    let (tx, rx) = mpsc::channel(options.slabs_read_ahead());
+    let reading = tokio::spawn({
+        let pool = pool.clone();
+        async move {
+            let mut read = 0;
+            while read < range.length {
+                let len = (pool.size as u64).min(range.length - read) as usize;
+                let buf = pool.take();
+                let slab =
+                    fileio::read_at(range.file.clone(), range.offset + read, buf, len).await?;
+                if slab.is_empty() {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the input shrank while it was uploading",
+                    )));
+                }
+                read += slab.len() as u64;
+                range.progress.emit(Event::BytesTransferred {
+                    bytes: slab.len() as u64,
+                });
+                if tx.send(slab.freeze()).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    });
+    upload_read(sdk, rx, reading, master_key, segment, options, pool).await
+}
+
+/// Uploads the slabs `reading` sends over `rx` as they come, as many at
+/// once as `options` have shards in flight for, then waits for `reading`
+/// to finish. The slabs are returned in the order they were read.
+async fn upload_read(
+    sdk: &Client,
+    rx: mpsc::Receiver<Bytes>,
+    reading: JoinHandle<Result<()>>,
+    master_key: &[u8; 32],
+    segment: u64,
+    options: UploadOptions,
+    pool: &Arc<BufferPool>,
+) -> Result<Vec<Slab>> {
+    let (data_shards, parity_shards) = (options.data_shards, options.parity_shards);
+    let received = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|data| (data, rx))
+    });
+    let mut uploads = received
+        .enumerate()
+        .map(|(index, data)| {
+            let key = keys::slab_key(master_key, segment, index as u64);
+            let sdk = sdk.clone();
+            // spawned as soon as there is room, and stopped if an earlier
+            // slab fails and the handle is dropped
+            let upload = AbortOnDropHandle::new(tokio::spawn({
+                let data = data.clone();
+                async move {
+                    sdk.upload_bytes(data, key, data_shards, parity_shards)
+                        .await
+                }
+            }));
+            async move { (data, upload.await) }
+        })
+        .buffered(options.inflight_segments());
+    let mut slabs = Vec::new();
+    while let Some((data, uploaded)) = uploads.next().await {
+        slabs.extend(uploaded??);
+        pool.give(data);
+    }
+    reading.await??;
+    Ok(slabs)
+}
+
+/// Reads up to `size` bytes into `buf`, fewer only at the end of the input.
+async fn read_slab<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    mut buf: BytesMut,
+    size: usize,
+) -> Result<Bytes> {
+    let mut reader = reader.take(size as u64);
+    while reader.read_buf(&mut buf).await? > 0 {}
+    Ok(buf.freeze())
+}
+
+/// Slab buffers kept for reuse, so an upload allocates no more of them
+/// than it holds at once however many slabs it has.
+struct BufferPool {
+    size: usize,
+    free: Mutex<Vec<BytesMut>>,
+}
+
+impl BufferPool {
+    fn new(size: usize) -> Arc<Self> {
+        Arc::new(Self {
+            size,
+            free: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn take(&self) -> BytesMut {
+        self.free
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| BytesMut::with_capacity(self.size))
+    }
+
+    /// Returns a slab's buffer to the pool, unless it is still referenced.
+    fn give(&self, data: Bytes) {
+        if let Ok(mut buf) = data.try_into_mut() {
+            buf.clear();
+            self.free.lock().unwrap().push(buf);
+        }
+    }
+}
+
+/// Compresses or seals the plaintext read from `reader`, as the upload's
+/// settings ask. Sealed plaintext starts at block `first_block`, and `end`
+/// says whether it runs to the end of the object.
+fn encode<R: AsyncRead + Unpin>(
+    reader: R,
+    compression: Option<Compression>,
+    cipher: Option<Cipher>,
+    first_block: u64,
+    end: bool,
+) -> Result<Either<Either<ZstdReader<R>, SealReader<R>>, R>> {
+    match (compression, cipher) {
+        (Some(_), Some(_)) => Err(Error::Usage(
+            "compressed uploads can't be sealed with authenticated encryption".into(),
+        )),
+        (Some(compression), None) => Ok(Either::Left(Either::Left(compression.compress(reader)?))),
+        (None, Some(cipher)) => Ok(Either::Left(Either::Right(
+            cipher.seal(reader, first_block).with_end(end),
+        ))),
+        (None, None) => Ok(Either::Right(reader)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::future::BoxFuture;
+    use tokio::io::AsyncWrite;
+
+    use super::*;
+    use crate::backend::{Backend, UploadReader};
+    use crate::download;
+    use crate::mock::{self, MockBackend};
+
+    async fn upload(sdk: &Client, data: &[u8], options: UploadOptions) -> Result<Manifest> {
+        let reader = Cursor::new(data.to_vec());
+        upload_reader(sdk, reader, [1; 32], options, Progress::default()).await
+    }
+
+    async fn download(sdk: &Client, manifest: &Manifest) -> Vec<u8> {
+        let mut data = Vec::new();
+        download::download_object(sdk, &mut data, manifest, 0, manifest.size)
+            .await
+            .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn round_trips() {
+        // a little over two slabs of a single data shard
+        let data = mock::pattern(2 * SECTOR_SIZE as usize + 1000);
+        for options in [
+            UploadOptions::new(1, 1),
+            UploadOptions::new(1, 2).with_max_inflight_shards(9),
+            UploadOptions::new(1, 1).with_compression(Some(Compression::zstd(1))),
+            UploadOptions::new(1, 1).with_aead(Some(aead::Algorithm::XChaCha20Poly1305)),
+            UploadOptions::new(1, 1)
+                .with_sha256(true)
+                .with_read_ahead(3),
+        ] {
+            let mock = Arc::new(MockBackend::new(4));
+            let sdk = Client::from_backend(mock.clone());
+            let manifest = upload(&sdk, &data, options).await.unwrap();
+            assert_eq!(manifest.size, data.len() as u64);
+            assert_eq!(manifest.checksum, checksum::checksum_bytes(&data));
+            assert_eq!(manifest.sha256.is_some(), options.sha256);
+            if options.compression.is_none() {
+                assert_eq!(mock.slabs(), 3);
+            }
+            assert_eq!(download(&sdk, &manifest).await, data);
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_input() {
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let manifest = upload(&sdk, &[], UploadOptions::new(1, 1)).await.unwrap();
+        assert_eq!(manifest.size, 0);
+        assert!(manifest.slabs.is_empty());
+        assert!(download(&sdk, &manifest).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_slabs_are_retried() {
+        let data = mock::pattern(2 * SECTOR_SIZE as usize);
+        let mock = Arc::new(MockBackend::new(4));
+        let sdk = Client::from_backend(mock.clone()).with_retry_policy(mock::retries(3));
+        mock.fail_next(2);
+        let manifest = upload(&sdk, &data, UploadOptions::new(1, 1)).await.unwrap();
+        assert_eq!(mock.slabs(), 2);
+        assert_eq!(download(&sdk, &manifest).await, data);
+
+        // without retries the failure is returned
+        let sdk = Client::from_backend(mock.clone());
+        mock.fail_next(1);
+        let err = upload(&sdk, &data, UploadOptions::new(1, 1))
+            .await
+            .unwrap_err();
+        assert!(err.is_retryable(), "{err}");
+    }
+
+    #[tokio::test]
+    async fn too_few_hosts() {
+        let mock = Arc::new(MockBackend::new(3));
+        for host in &mock.host_keys()[1..] {
+            mock.set_up(host, false);
+        }
+        let sdk = Client::from_backend(mock.clone()).with_retry_policy(mock::retries(2));
+        let err = upload(&sdk, b"data", UploadOptions::new(1, 1))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Host(_)), "{err}");
+        assert_eq!(mock.slabs(), 0);
+    }
+
+    #[tokio::test]
+    async fn injected_failures() {
+        let chaos = "fail=1".parse().unwrap();
+        let sdk = Client::from_backend(MockBackend::new(4))
+            .with_retry_policy(mock::retries(3))
+            .with_chaos(Some(chaos));
+        let err = upload(&sdk, b"data", UploadOptions::new(1, 1))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("chaos"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn compressed_uploads_cant_be_sealed() {
+        let sdk = Client::from_backend(MockBackend::new(2));
+        let options = UploadOptions::new(1, 1)
+            .with_compression(Some(Compression::zstd(1)))
+            .with_aead(Some(aead::Algorithm::XChaCha20Poly1305));
+        let err = upload(&sdk, b"data", options).await.unwrap_err();
+        assert!(matches!(err, Error::Usage(_)), "{err}");
+    }
+
+    /// Fails every upload once `budget` of them have gone through.
+    struct Flaky {
+        inner: Arc<MockBackend>,
+        budget: AtomicUsize,
+    }
+
+    impl Backend for Flaky {
+        fn upload(
+            &self,
+            reader: UploadReader,
+            encryption_key: [u8; 32],
+            data_shards: u8,
+            parity_shards: u8,
+        ) -> BoxFuture<'_, Result<Vec<Slab>>> {
+            let spent = self
+                .budget
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+            if spent.is_err() {
+                return Box::pin(future::ready(Err(Error::Host("flaky upload".into()))));
+            }
+            self.inner
+                .upload(reader, encryption_key, data_shards, parity_shards)
+        }
+
+        fn download<'a>(
+            &'a self,
+            w: &'a mut (dyn AsyncWrite + Unpin + Send),
+            slabs: &'a [Slab],
+        ) -> BoxFuture<'a, Result<()>> {
+            self.inner.download(w, slabs)
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_after_a_failed_segment() {
+        let dir = mock::temp_dir();
+        let input = dir.join("input");
+        let checkpoint = dir.join("input.checkpoint");
+        // a full segment and one slab more
+        let slabs = SEGMENT_SLABS as usize + 1;
+        let data = mock::pattern(slabs * SECTOR_SIZE as usize - 10);
+        fs::write(&input, &data).await.unwrap();
+
+        let mock = Arc::new(MockBackend::new(4));
+        let flaky = Client::from_backend(Flaky {
+            inner: mock.clone(),
+            budget: AtomicUsize::new(SEGMENT_SLABS as usize),
+        });
+        let options = UploadOptions::new(1, 1);
+        let upload = ResumableUpload::new(&input, &checkpoint, [1; 32], None, options)
+            .await
+            .unwrap();
+        assert!(upload.run(&flaky).await.is_err());
+
+        let upload = ResumableUpload::resume(&checkpoint).await.unwrap();
+        assert_eq!(upload.offset(), SEGMENT_SLABS * SECTOR_SIZE);
+        assert_eq!(upload.options().data_shards, 1);
+        let sdk = Client::from_backend(mock.clone());
+        let manifest = upload.run(&sdk).await.unwrap();
+        // the first segment wasn't uploaded again
+        assert_eq!(mock.slabs(), slabs);
+        assert_eq!(manifest.slabs.len(), slabs);
+        assert_eq!(manifest.checksum, checksum::checksum_bytes(&data));
+        assert_eq!(download(&sdk, &manifest).await, data);
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resume_refuses_a_changed_input() {
+        let dir = mock::temp_dir();
+        let input = dir.join("input");
+        let checkpoint = dir.join("input.checkpoint");
+        fs::write(&input, b"data").await.unwrap();
+        ResumableUpload::new(&input, &checkpoint, [1; 32], None, UploadOptions::new(1, 1))
+            .await
+            .unwrap();
+        fs::write(&input, b"more data").await.unwrap();
+        assert!(matches!(
+            ResumableUpload::resume(&checkpoint).await,
+            Err(Error::Checkpoint(_))
+        ));
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
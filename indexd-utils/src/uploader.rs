@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::sync::{Semaphore, broadcast};
+use tokio_util::sync::CancellationToken;
+
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::manifest::Manifest;
+use crate::progress::{Event, Progress};
+use crate::upload::{ResumableUpload, UploadOptions};
+
+/// The number of events a subscriber may fall behind by before it misses
+/// some.
+const EVENT_BUFFER: usize = 1024;
+
+/// Where an upload is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Paused => "paused",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        };
+        f.write_str(state)
+    }
+}
+
+/// An upload added to the queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub path: PathBuf,
+    /// The name the file is catalogued under.
+    pub name: String,
+    pub state: JobState,
+    pub size: u64,
+    /// The plaintext bytes read so far.
+    pub uploaded: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A progress event of one job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobEvent {
+    pub id: u64,
+    pub event: Event,
+}
+
+/// Called with every completed upload, for example to catalog it. A job
+/// that fails here is marked as failed.
+pub type Finish = Box<dyn Fn(&Job, &Manifest) -> Result<()> + Send + Sync>;
+
+struct Task {
+    job: Job,
+    /// Stops the task currently running the job, if any.
+    cancel: CancellationToken,
+}
+
+/// Returns `$XDG_STATE_HOME/indexd-utils/checkpoints`, falling back to
+/// `~/.local/state`.
+pub fn default_checkpoint_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(base.join("indexd-utils").join("checkpoints"))
+}
+
+/// Runs queued uploads, a limited number at a time, and lets each be
+/// paused, resumed or cancelled.
+///
+/// Every job checkpoints to `<checkpoints>/<id>.json`, so a paused or
+/// failed job resumes from its last complete segment. Checkpoints hold the
+/// upload's keys, so the directory is created readable by its owner only,
+/// and one owned by another user is refused. State changes and
+/// the bytes read are broadcast to [`Uploader::subscribe`] as
+/// [`Event::State`] and [`Event::BytesTransferred`] events, along with
+/// [`Event::RateLimited`] while a job waits out the app's rate limit.
+pub struct Uploader {
+    sdk: Client,
+    options: UploadOptions,
+    checkpoints: PathBuf,
+    finish: Finish,
+    tasks: Mutex<BTreeMap<u64, Task>>,
+    next_id: AtomicU64,
+    /// Limits how many jobs run at once.
+    slots: Semaphore,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl Uploader {
+    pub fn new(
+        sdk: Client,
+        options: UploadOptions,
+        concurrency: usize,
+        checkpoints: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            sdk,
+            options,
+            checkpoints: checkpoints.into(),
+            finish: Box::new(|_, _| Ok(())),
+            tasks: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            slots: Semaphore::new(concurrency.max(1)),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+
+    /// Calls `finish` with the manifest of every completed upload.
+    pub fn with_finish(mut self, finish: Finish) -> Self {
+        self.finish = finish;
+        self
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.sdk
+    }
+
+    pub fn options(&self) -> UploadOptions {
+        self.options
+    }
+
+    /// Queues the upload of the `size` byte file at `path` as `name`.
+    pub fn add(self: &Arc<Self>, path: PathBuf, name: String, size: u64) -> Job {
+        let job = Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            path,
+            name,
+            state: JobState::Queued,
+            size,
+            uploaded: 0,
+            error: None,
+        };
+        let cancel = CancellationToken::new();
+        let task = Task {
+            job: job.clone(),
+            cancel: cancel.clone(),
+        };
+        self.tasks.lock().unwrap().insert(job.id, task);
+        info!("queued upload {} of {}", job.id, job.path.display());
+        self.spawn(job.id, cancel, true);
+        job
+    }
+
+    pub fn job(&self, id: u64) -> Option<Job> {
+        self.tasks.lock().unwrap().get(&id).map(|t| t.job.clone())
+    }
+
+    pub fn jobs(&self) -> Vec<Job> {
+        let tasks = self.tasks.lock().unwrap();
+        tasks.values().map(|t| t.job.clone()).collect()
+    }
+
+    /// Returns the events of every job from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+
+    /// Stops a queued or running job, keeping its checkpoint.
+    pub fn pause(&self, id: u64) -> Result<Job> {
+        self.transition(id, |task| match task.job.state {
+            JobState::Queued | JobState::Running => {
+                task.cancel.cancel();
+                task.job.state = JobState::Paused;
+                Ok(())
+            }
+            state => Err(Error::Usage(format!("upload {id} is {state}"))),
+        })
+    }
+
+    /// Queues a paused or failed job again, continuing from its checkpoint.
+    pub fn resume(self: &Arc<Self>, id: u64) -> Result<Job> {
+        let cancel = CancellationToken::new();
+        let job = self.transition(id, |task| match task.job.state {
+            JobState::Paused | JobState::Failed => {
+                task.cancel = cancel.clone();
+                task.job.state = JobState::Queued;
+                task.job.error = None;
+                Ok(())
+            }
+            state => Err(Error::Usage(format!("upload {id} is {state}"))),
+        })?;
+        info!("resumed upload {id}");
+        self.spawn(id, cancel, false);
+        Ok(job)
+    }
+
+    /// Stops a job for good and removes its checkpoint.
+    pub async fn cancel(&self, id: u64) -> Result<Job> {
+        let mut idle = false;
+        let job = self.transition(id, |task| match task.job.state {
+            JobState::Queued | JobState::Running | JobState::Paused | JobState::Failed => {
+                // a job without a task can't clean up after itself
+                idle = matches!(task.job.state, JobState::Paused | JobState::Failed);
+                task.cancel.cancel();
+                task.job.state = JobState::Cancelled;
+                Ok(())
+            }
+            state => Err(Error::Usage(format!("upload {id} is {state}"))),
+        })?;
+        if idle {
+            remove_checkpoint(&self.checkpoint_path(id)).await;
+        }
+        info!("cancelled upload {id}");
+        Ok(job)
+    }
+
+    fn spawn(self: &Arc<Self>, id: u64, cancel: CancellationToken, fresh: bool) {
+        let uploader = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => uploader.stopped(id).await,
+                () = uploader.clone().run(id, fresh) => {}
+            }
+        });
+    }
+
+    /// Cleans up after a job whose task was cancelled.
+    async fn stopped(&self, id: u64) {
+        if self
+            .job(id)
+            .is_some_and(|job| job.state == JobState::Cancelled)
+        {
+            remove_checkpoint(&self.checkpoint_path(id)).await;
+        }
+    }
+
+    async fn run(self: Arc<Self>, id: u64, fresh: bool) {
+        let Ok(_slot) = self.slots.acquire().await else {
+            return;
+        };
+        let started = self.transition(id, |task| {
+            if task.job.state == JobState::Queued {
+                task.job.state = JobState::Running;
+            }
+            Ok(())
+        });
+        let Ok(job) = started else {
+            return;
+        };
+        // the upload is over by the time this returns, so a pause or cancel
+        // racing with it is overridden
+        match self.upload(&job, fresh).await {
+            Ok(()) => {
+                self.set_state(id, JobState::Done);
+                remove_checkpoint(&self.checkpoint_path(id)).await;
+            }
+            Err(e) => {
+                warn!("upload {id} of {} failed: {e}", job.path.display());
+                let _ = self.transition(id, |task| {
+                    task.job.state = JobState::Failed;
+                    task.job.error = Some(e.to_string());
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    async fn upload(&self, job: &Job, fresh: bool) -> Result<()> {
+        // a checkpoint someone else could write would choose what is uploaded
+        private_dir(&self.checkpoints).await?;
+        let checkpoint = self.checkpoint_path(job.id);
+        let upload = if fresh || !fs::try_exists(&checkpoint).await? {
+            ResumableUpload::new(&job.path, &checkpoint, rand::random(), None, self.options).await?
+        } else {
+            ResumableUpload::resume(&checkpoint)
+                .await?
+                .with_max_inflight_shards(self.options.max_inflight_shards)
+                .with_read_ahead(self.options.read_ahead)
+                .with_max_memory(self.options.max_memory)
+        };
+        let offset = upload.offset();
+        self.update(job.id, |job| job.uploaded = offset);
+
+        let (progress, mut events) = Progress::channel();
+        let manifest = async { upload.with_progress(progress).run(&self.sdk).await };
+        let track = async {
+            while let Some(event) = events.recv().await {
+                match event {
+                    Event::BytesTransferred { bytes } => {
+                        self.update(job.id, |job| job.uploaded += bytes);
+                        self.emit(job.id, event);
+                    }
+                    Event::RateLimited { .. } => self.emit(job.id, event),
+                    _ => {}
+                }
+            }
+        };
+        let (manifest, ()) = tokio::join!(manifest, track);
+        (self.finish)(job, &manifest?)
+    }
+
+    fn checkpoint_path(&self, id: u64) -> PathBuf {
+        self.checkpoints.join(format!("{id}.json"))
+    }
+
+    /// Applies `f` to a job and broadcasts its new state if it changed.
+    fn transition(&self, id: u64, f: impl FnOnce(&mut Task) -> Result<()>) -> Result<Job> {
+        let mut tasks = self.tasks.lock().unwrap();
+        let task = tasks
+            .get_mut(&id)
+            .ok_or_else(|| Error::NotFound(format!("upload {id}")))?;
+        let before = task.job.state;
+        f(task)?;
+        if task.job.state != before {
+            self.emit(id, Event::State(task.job.state));
+        }
+        Ok(task.job.clone())
+    }
+
+    fn set_state(&self, id: u64, state: JobState) {
+        let _ = self.transition(id, |task| {
+            task.job.state = state;
+            Ok(())
+        });
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut Job)) {
+        if let Some(task) = self.tasks.lock().unwrap().get_mut(&id) {
+            f(&mut task.job);
+        }
+    }
+
+    fn emit(&self, id: u64, event: Event) {
+        // fails only when nobody is subscribed
+        let _ = self.events.send(JobEvent { id, event });
+    }
+}
+
+/// Creates `dir` readable by its owner only, or checks that the directory
+/// already there is owned by this user and closed to everyone else.
+async fn private_dir(dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(dir).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = fs::symlink_metadata(dir).await?;
+        let uid = unsafe { libc::getuid() };
+        if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+            return Err(Error::Config(format!(
+                "{} must be a directory owned by this user that no one else can access",
+                dir.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+async fn remove_checkpoint(path: &Path) {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            warn!("failed to remove checkpoint {}: {e}", path.display());
+        }
+        _ => {}
+    }
+}
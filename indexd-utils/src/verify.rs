@@ -0,0 +1,89 @@
+use serde::Serialize;
+
+use crate::client::Client;
+use crate::compression::PlaintextChecksum;
+use crate::download;
+use crate::error::Result;
+use crate::manifest::Manifest;
+use crate::progress::{Event, Progress};
+
+/// The recoverability of a slab.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    /// The slab was recovered on the first attempt.
+    Healthy,
+    /// The slab was recovered, but only after retrying. The SDK reports
+    /// failures per slab rather than per shard, so a slab that needs more
+    /// than one attempt is the best available signal that some of its
+    /// hosts are unreachable.
+    Degraded,
+    /// The slab could not be recovered.
+    Unrecoverable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlabReport {
+    pub index: usize,
+    pub health: Health,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub slabs: Vec<SlabReport>,
+    /// Whether the recovered data matches the manifest's size and checksum.
+    /// Always false if any slab is unrecoverable.
+    pub checksum_ok: bool,
+}
+
+impl Report {
+    pub fn count(&self, health: Health) -> usize {
+        self.slabs.iter().filter(|s| s.health == health).count()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.checksum_ok && self.count(Health::Unrecoverable) == 0
+    }
+}
+
+/// Recovers every slab of an object in memory, without writing anything to
+/// disk, and checks the result against the manifest's checksum.
+pub async fn verify(sdk: &Client, manifest: &Manifest, progress: &Progress) -> Result<Report> {
+    let mut hasher = PlaintextChecksum::new(manifest)?;
+    let mut slabs = Vec::with_capacity(manifest.slabs.len());
+    let mut complete = true;
+    for (index, slab) in manifest.slabs.iter().enumerate() {
+        let (result, attempts) = download::fetch_slab(sdk, slab).await;
+        let report = match result {
+            Ok(data) => {
+                hasher.write(&data).await;
+                SlabReport {
+                    index,
+                    health: if attempts == 1 {
+                        Health::Healthy
+                    } else {
+                        Health::Degraded
+                    },
+                    error: None,
+                }
+            }
+            Err(e) => {
+                complete = false;
+                SlabReport {
+                    index,
+                    health: Health::Unrecoverable,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        progress.emit(Event::BytesTransferred {
+            bytes: slab.length as u64,
+        });
+        slabs.push(report);
+    }
+
+    let checksum_ok = complete && hasher.finish().await == Some((manifest.size, manifest.checksum));
+    Ok(Report { slabs, checksum_ok })
+}
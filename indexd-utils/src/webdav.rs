@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{HeaderMap, Method, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::TryStreamExt;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use tokio_util::io::StreamReader;
+
+use crate::bucket::{Buckets, Object};
+use crate::checksum::ChecksumWriter;
+use crate::client::Client;
+use crate::http::{Span, StatusError};
+use crate::manifest::Manifest;
+use crate::progress::Progress;
+use crate::upload;
+
+/// The characters left unescaped in hrefs.
+const HREF: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Serves buckets over WebDAV: the root lists the buckets, and `/` in
+/// object keys separates collections.
+///
+/// Only class 1 is implemented, so clients that insist on locking (Finder
+/// among them) mount the share read-only. Empty collections made with MKCOL
+/// are kept as zero-length objects whose keys end in `/`, as on S3.
+pub struct WebDav {
+    sdk: Client,
+    buckets: Buckets,
+    /// The `Authorization` header clients must send, if any.
+    authorization: Option<String>,
+}
+
+impl WebDav {
+    /// `credentials` is the user name and password required with HTTP
+    /// basic authentication, or `None` to allow anyone.
+    pub fn new(sdk: Client, buckets: Buckets, credentials: Option<(String, String)>) -> Self {
+        Self {
+            sdk,
+            buckets,
+            authorization: credentials.map(|(user, password)| {
+                format!("Basic {}", BASE64.encode(format!("{user}:{password}")))
+            }),
+        }
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new().fallback(handle).with_state(self)
+    }
+}
+
+type Result<T> = std::result::Result<T, StatusError>;
+
+/// What a request path names.
+enum Resource {
+    Root,
+    Bucket(String),
+    /// A key within a bucket. Collections are the keys with a trailing `/`.
+    Key(String, String),
+}
+
+impl Resource {
+    fn parse(path: &str) -> Self {
+        let path = percent_decode_str(path.trim_start_matches('/'))
+            .decode_utf8_lossy()
+            .into_owned();
+        match path.split_once('/') {
+            None if path.is_empty() => Resource::Root,
+            None => Resource::Bucket(path),
+            Some((bucket, "")) => Resource::Bucket(bucket.to_string()),
+            Some((bucket, key)) => Resource::Key(bucket.to_string(), key.to_string()),
+        }
+    }
+}
+
+/// A resource in a PROPFIND response.
+struct Entry {
+    href: String,
+    name: String,
+    /// `None` for collections.
+    object: Option<Object>,
+}
+
+async fn handle(
+    State(dav): State<Arc<WebDav>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    if let Some(expected) = &dav.authorization {
+        let given = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok());
+        if given != Some(expected.as_str()) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"indexd\"")],
+            )
+                .into_response();
+        }
+    }
+    let resource = Resource::parse(uri.path());
+    let result = match method.as_str() {
+        "OPTIONS" => Ok((
+            StatusCode::OK,
+            [
+                ("dav", "1"),
+                ("allow", "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, MKCOL"),
+            ],
+        )
+            .into_response()),
+        "PROPFIND" => propfind(&dav, resource, &headers).await,
+        "GET" => get(dav.clone(), resource, &headers, false).await,
+        "HEAD" => get(dav.clone(), resource, &headers, true).await,
+        "PUT" => put(&dav, resource, body).await,
+        "DELETE" => delete(&dav, resource).await,
+        "MKCOL" => mkcol(&dav, resource).await,
+        _ => Err(StatusError(StatusCode::NOT_IMPLEMENTED)),
+    };
+    result.unwrap_or_else(|StatusError(status)| status.into_response())
+}
+
+/// Returns the object with `key`, if any.
+async fn stat(dav: &WebDav, bucket: &str, key: &str) -> Result<Option<Object>> {
+    let bucket = dav.buckets.get(&dav.sdk, bucket).await?;
+    let bucket = bucket.lock().await;
+    Ok(bucket.stat(key).ok().cloned())
+}
+
+async fn propfind(dav: &WebDav, resource: Resource, headers: &HeaderMap) -> Result<Response> {
+    let depth_one = headers
+        .get("depth")
+        .and_then(|v| v.to_str().ok())
+        .is_none_or(|depth| depth != "0");
+    let mut entries = Vec::new();
+    match resource {
+        Resource::Root => {
+            entries.push(collection("/".into(), String::new()));
+            if depth_one {
+                for name in dav.buckets.names()? {
+                    entries.push(collection(format!("/{}/", encode(&name)), name));
+                }
+            }
+        }
+        Resource::Bucket(name) => {
+            let bucket = dav.buckets.get(&dav.sdk, &name).await?;
+            let bucket = bucket.lock().await;
+            entries.push(collection(format!("/{}/", encode(&name)), name.clone()));
+            if depth_one {
+                entries.extend(children(&name, "", bucket.list("")));
+            }
+        }
+        Resource::Key(name, key) => {
+            let bucket = dav.buckets.get(&dav.sdk, &name).await?;
+            let bucket = bucket.lock().await;
+            let prefix = if key.ends_with('/') {
+                key.clone()
+            } else {
+                format!("{key}/")
+            };
+            if let (false, Ok(object)) = (key.ends_with('/'), bucket.stat(&key)) {
+                entries.push(Entry {
+                    href: href(&name, &key),
+                    name: base_name(&key).to_string(),
+                    object: Some(object.clone()),
+                });
+            } else if bucket.list(&prefix).next().is_some() {
+                entries.push(collection(
+                    href(&name, &prefix),
+                    base_name(&prefix).to_string(),
+                ));
+                if depth_one {
+                    entries.extend(children(&name, &prefix, bucket.list(&prefix)));
+                }
+            } else {
+                return Err(StatusError(StatusCode::NOT_FOUND));
+            }
+        }
+    }
+    Ok((
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        multistatus(&entries),
+    )
+        .into_response())
+}
+
+/// Returns the immediate children of `prefix`: its objects and the
+/// collections that hold the rest.
+fn children<'a>(
+    bucket: &str,
+    prefix: &str,
+    objects: impl Iterator<Item = (&'a str, &'a Object)>,
+) -> Vec<Entry> {
+    let mut children = BTreeMap::new();
+    for (key, object) in objects {
+        let rest = &key[prefix.len()..];
+        match rest.find('/') {
+            // the collection marker itself
+            _ if rest.is_empty() => {}
+            Some(i) => {
+                let dir = &key[..prefix.len() + i + 1];
+                children
+                    .entry(dir.to_string())
+                    .or_insert_with(|| collection(href(bucket, dir), rest[..i].to_string()));
+            }
+            None => {
+                children.insert(
+                    key.to_string(),
+                    Entry {
+                        href: href(bucket, key),
+                        name: rest.to_string(),
+                        object: Some(object.clone()),
+                    },
+                );
+            }
+        }
+    }
+    children.into_values().collect()
+}
+
+async fn get(
+    dav: Arc<WebDav>,
+    resource: Resource,
+    headers: &HeaderMap,
+    head: bool,
+) -> Result<Response> {
+    let Resource::Key(bucket, key) = resource else {
+        return Err(StatusError(StatusCode::METHOD_NOT_ALLOWED));
+    };
+    let object = stat(&dav, &bucket, &key)
+        .await?
+        .filter(|_| !key.ends_with('/'))
+        .ok_or(StatusError(StatusCode::NOT_FOUND))?;
+    let span = Span::parse(headers, object.manifest.size)
+        .map_err(|_| StatusError(StatusCode::RANGE_NOT_SATISFIABLE))?;
+    let response = span
+        .response()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::ETAG, etag(&object))
+        .header(header::LAST_MODIFIED, http_date(object.modified));
+    Ok(span.send(
+        &dav.sdk,
+        object.manifest,
+        response,
+        head,
+        format!("{bucket}/{key}"),
+    ))
+}
+
+async fn put(dav: &WebDav, resource: Resource, body: Body) -> Result<Response> {
+    let Resource::Key(name, key) = resource else {
+        return Err(StatusError(StatusCode::METHOD_NOT_ALLOWED));
+    };
+    if key.ends_with('/') {
+        return Err(StatusError(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let bucket = dav.buckets.get(&dav.sdk, &name).await?;
+    let options = bucket.lock().await.options();
+    let reader = StreamReader::new(body.into_data_stream().map_err(io::Error::other));
+    let manifest = upload::upload_reader(
+        &dav.sdk,
+        reader,
+        rand::random(),
+        options,
+        Progress::default(),
+    )
+    .await?;
+
+    let existed = {
+        let mut locked = bucket.lock().await;
+        let existed = locked.stat(&key).is_ok();
+        locked.insert(&key, manifest);
+        existed
+    };
+    dav.buckets.commit(&dav.sdk, &bucket).await?;
+    Ok(if existed {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    }
+    .into_response())
+}
+
+async fn delete(dav: &WebDav, resource: Resource) -> Result<Response> {
+    let Resource::Key(name, key) = resource else {
+        // buckets can't be deleted while their slabs can't be released
+        return Err(StatusError(StatusCode::FORBIDDEN));
+    };
+    let bucket = dav.buckets.get(&dav.sdk, &name).await?;
+    let mut locked = bucket.lock().await;
+    let prefix = if key.ends_with('/') {
+        key.clone()
+    } else {
+        format!("{key}/")
+    };
+    let mut keys: Vec<String> = locked.list(&prefix).map(|(k, _)| k.to_string()).collect();
+    if !key.ends_with('/') && locked.stat(&key).is_ok() {
+        keys = vec![key];
+    }
+    if keys.is_empty() {
+        return Err(StatusError(StatusCode::NOT_FOUND));
+    }
+    for key in keys {
+        locked.delete(&key)?;
+    }
+    drop(locked);
+    dav.buckets.commit(&dav.sdk, &bucket).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn mkcol(dav: &WebDav, resource: Resource) -> Result<Response> {
+    match resource {
+        Resource::Root => Err(StatusError(StatusCode::METHOD_NOT_ALLOWED)),
+        Resource::Bucket(name) => {
+            if dav.buckets.exists(&name)? {
+                return Err(StatusError(StatusCode::METHOD_NOT_ALLOWED));
+            }
+            dav.buckets.create(&dav.sdk, &name).await?;
+            Ok(StatusCode::CREATED.into_response())
+        }
+        Resource::Key(name, key) => {
+            let marker = format!("{}/", key.trim_end_matches('/'));
+            let bucket = dav.buckets.get(&dav.sdk, &name).await?;
+            let mut locked = bucket.lock().await;
+            if locked.list(&marker).next().is_some()
+                || locked.stat(marker.trim_end_matches('/')).is_ok()
+            {
+                return Err(StatusError(StatusCode::METHOD_NOT_ALLOWED));
+            }
+            let options = locked.options();
+            let empty = ChecksumWriter::new(tokio::io::sink()).checksum();
+            let manifest = Manifest::new(
+                0,
+                empty,
+                &rand::random(),
+                options.data_shards,
+                options.parity_shards,
+                Vec::new(),
+            );
+            locked.insert(&marker, manifest);
+            drop(locked);
+            dav.buckets.commit(&dav.sdk, &bucket).await?;
+            Ok(StatusCode::CREATED.into_response())
+        }
+    }
+}
+
+fn collection(href: String, name: String) -> Entry {
+    Entry {
+        href,
+        name,
+        object: None,
+    }
+}
+
+fn encode(s: &str) -> String {
+    utf8_percent_encode(s, HREF).to_string()
+}
+
+fn href(bucket: &str, key: &str) -> String {
+    let mut href = format!("/{}", encode(bucket));
+    for part in key.split('/') {
+        href.push('/');
+        href.push_str(&encode(part));
+    }
+    href
+}
+
+fn base_name(key: &str) -> &str {
+    let key = key.trim_end_matches('/');
+    key.rsplit('/').next().unwrap_or(key)
+}
+
+fn etag(object: &Object) -> String {
+    format!("\"{}\"", hex::encode(object.manifest.checksum))
+}
+
+fn http_date(secs: u64) -> String {
+    httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn multistatus(entries: &[Entry]) -> String {
+    let mut out =
+        String::from(r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#);
+    for entry in entries {
+        let _ = write!(
+            out,
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>",
+            escape(&entry.href),
+            escape(&entry.name)
+        );
+        match &entry.object {
+            Some(object) => {
+                let _ = write!(
+                    out,
+                    "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>application/octet-stream</D:getcontenttype><D:getetag>{}</D:getetag><D:getlastmodified>{}</D:getlastmodified>",
+                    object.manifest.size,
+                    escape(&etag(object)),
+                    http_date(object.modified)
+                );
+            }
+            None => out.push_str("<D:resourcetype><D:collection/></D:resourcetype>"),
+        }
+        out.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>");
+    }
+    out.push_str("</D:multistatus>");
+    out
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::Sha256;
+use url::Url;
+
+use crate::error::{Error, Result};
+use crate::net::Network;
+
+/// How many times a notification is sent before it is given up on.
+const ATTEMPTS: u32 = 3;
+
+/// How long an endpoint may take to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a webhook is told about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Event {
+    #[serde(rename = "upload.complete")]
+    UploadComplete,
+    #[serde(rename = "download.complete")]
+    DownloadComplete,
+    /// A download found data damaged or lost, which `repair` may restore.
+    #[serde(rename = "repair.needed")]
+    RepairNeeded,
+    /// A verified file has fewer healthy shards than its threshold.
+    #[serde(rename = "health.breached")]
+    ThresholdBreached,
+    /// An upload was refused because it would go over the budget.
+    #[serde(rename = "budget.exceeded")]
+    BudgetExceeded,
+}
+
+/// An endpoint notified of events, configured as a `[[webhooks]]` table
+/// of a profile.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    /// Signs every payload with HMAC-SHA256 under this secret.
+    pub secret: Option<String>,
+    /// The events sent to it, all of them if empty.
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+/// Sends events to the configured webhooks.
+///
+/// Each notification is a `POST` of `{"event", "timestamp", "data"}` with
+/// the event repeated in `X-Indexd-Event`. A webhook with a secret gets
+/// `X-Indexd-Signature: sha256=<hex>`, the HMAC-SHA256 of the body under
+/// the secret, so it can check the payload came from here; the timestamp
+/// is in the signed body so an old payload can't be replayed as new.
+#[derive(Clone, Default)]
+pub struct Webhooks {
+    http: Option<reqwest::Client>,
+    hooks: Arc<[(Url, Webhook)]>,
+}
+
+impl Webhooks {
+    /// Checks the webhooks' URLs and prepares a client reaching them the
+    /// way `network` says.
+    pub fn new(hooks: Vec<Webhook>, network: &Network) -> Result<Self> {
+        if hooks.is_empty() {
+            return Ok(Self::default());
+        }
+        let hooks = hooks
+            .into_iter()
+            .map(|hook| {
+                let url = Url::parse(&hook.url)
+                    .ok()
+                    .filter(|url| matches!(url.scheme(), "http" | "https"))
+                    .ok_or_else(|| Error::Config(format!("invalid webhook URL {:?}", hook.url)))?;
+                Ok((url, hook))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let http = network.http_client()?.timeout(TIMEOUT).build()?;
+        Ok(Self {
+            http: Some(http),
+            hooks: hooks.into(),
+        })
+    }
+
+    /// Sends `event` with `data` to every webhook subscribed to it. The
+    /// notifications are sent in the background and retried with backoff;
+    /// failures are only logged, so an endpoint that is down never holds up
+    /// or fails a transfer.
+    pub fn notify(&self, event: Event, data: Value) {
+        let Some(http) = &self.http else {
+            return;
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let body = json!({ "event": event, "timestamp": timestamp, "data": data }).to_string();
+        for (url, hook) in self.hooks.iter() {
+            if !hook.events.is_empty() && !hook.events.contains(&event) {
+                continue;
+            }
+            let mut request = http
+                .post(url.clone())
+                .header("content-type", "application/json")
+                .header("x-indexd-event", name(event));
+            if let Some(secret) = &hook.secret {
+                request = request.header("x-indexd-signature", signature(secret, body.as_bytes()));
+            }
+            let request = request.body(body.clone());
+            let url = url.clone();
+            tokio::spawn(async move { deliver(request, &url, event).await });
+        }
+    }
+}
+
+/// Returns the `X-Indexd-Signature` of `body` under `secret`, for
+/// endpoints to compare with the header they received.
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn name(event: Event) -> &'static str {
+    match event {
+        Event::UploadComplete => "upload.complete",
+        Event::DownloadComplete => "download.complete",
+        Event::RepairNeeded => "repair.needed",
+        Event::ThresholdBreached => "health.breached",
+        Event::BudgetExceeded => "budget.exceeded",
+    }
+}
+
+async fn deliver(request: reqwest::RequestBuilder, url: &Url, event: Event) {
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=ATTEMPTS {
+        let request = request.try_clone().expect("a buffered body");
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!("sent {} to {url}", name(event));
+                return;
+            }
+            Ok(response) => format!("answered {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+        if attempt == ATTEMPTS {
+            warn!("giving up on sending {} to {url}: {error}", name(event));
+            return;
+        }
+        debug!(
+            "sending {} to {url} failed on attempt {attempt}: {error}",
+            name(event)
+        );
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
@@ -0,0 +1,151 @@
+//! Decoding of `aws-chunked` request bodies, which clients stream as
+//! `<hex size>[;chunk-signature=<signature>]\r\n<data>\r\n` chunks ending
+//! with an empty one and any trailing headers.
+
+use std::io;
+
+use axum::body::Bytes;
+use futures::{Stream, stream};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use crate::auth::ChunkSigner;
+
+/// The largest chunk accepted; clients send 64 KiB by default.
+const MAX_CHUNK: usize = 16 << 20;
+
+/// The longest chunk header or trailing header accepted.
+const MAX_LINE: u64 = 4096;
+
+/// Decodes the chunks of `reader`, checking each one's signature with
+/// `signer` and that they add up to the `length` the request declared.
+/// Trailing headers are skipped, so trailing checksums aren't checked, as
+/// with any other unsigned payload.
+pub fn decode<R>(
+    reader: R,
+    signer: Option<ChunkSigner>,
+    length: u64,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    let decoder = Decoder {
+        reader,
+        signer,
+        remaining: length,
+    };
+    stream::try_unfold(Some(decoder), |decoder| async move {
+        let Some(mut decoder) = decoder else {
+            return Ok(None);
+        };
+        Ok(match decoder.next_chunk().await? {
+            Some(chunk) => Some((chunk, Some(decoder))),
+            None => None,
+        })
+    })
+}
+
+struct Decoder<R> {
+    reader: R,
+    signer: Option<ChunkSigner>,
+    remaining: u64,
+}
+
+impl<R: AsyncBufRead + Unpin> Decoder<R> {
+    /// Returns the data of the next chunk, or `None` once the last chunk
+    /// and the trailing headers have been read.
+    async fn next_chunk(&mut self) -> io::Result<Option<Bytes>> {
+        let line = self.line().await?;
+        let (size, signature) = match line.split_once(';') {
+            Some((size, extension)) => (size, extension.strip_prefix("chunk-signature=")),
+            None => (line.as_str(), None),
+        };
+        let size = usize::from_str_radix(size.trim(), 16)
+            .ok()
+            .filter(|&size| size <= MAX_CHUNK)
+            .ok_or_else(|| invalid("malformed chunk size"))?;
+        let mut data = vec![0; size];
+        self.reader.read_exact(&mut data).await?;
+        if let Some(signer) = &mut self.signer {
+            let signature = signature.ok_or_else(|| invalid("missing chunk signature"))?;
+            if !signer.verify(&data, signature) {
+                return Err(invalid("the chunk signature does not match"));
+            }
+        }
+
+        if size == 0 {
+            // the trailing headers, if any, end with an empty line
+            while !self.line().await?.is_empty() {}
+            if self.remaining != 0 {
+                return Err(invalid(
+                    "the body is shorter than x-amz-decoded-content-length",
+                ));
+            }
+            return Ok(None);
+        }
+        self.remaining = self
+            .remaining
+            .checked_sub(size as u64)
+            .ok_or_else(|| invalid("the body is longer than x-amz-decoded-content-length"))?;
+        if !self.line().await?.is_empty() {
+            return Err(invalid("chunk data is not followed by CRLF"));
+        }
+        Ok(Some(Bytes::from(data)))
+    }
+
+    /// Reads a CRLF-terminated line, without the CRLF.
+    async fn line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        (&mut self.reader)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut line)
+            .await?;
+        let Some(line) = line.strip_suffix(b"\r\n") else {
+            return Err(invalid("truncated or overlong chunk header"));
+        };
+        String::from_utf8(line.to_vec()).map_err(|_| invalid("malformed chunk header"))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+
+    async fn decode_all(body: &'static [u8], length: u64) -> io::Result<Vec<u8>> {
+        let chunks: Vec<Bytes> = decode(body, None, length).try_collect().await?;
+        Ok(chunks.concat())
+    }
+
+    #[tokio::test]
+    async fn unsigned_chunks() {
+        let body = b"5\r\nhello\r\n6\r\n world\r\n0\r\nx-amz-checksum-crc32:AAAAAA==\r\n\r\n";
+        assert_eq!(decode_all(body, 11).await.unwrap(), b"hello world");
+        assert_eq!(decode_all(b"0\r\n\r\n", 0).await.unwrap(), b"");
+    }
+
+    #[tokio::test]
+    async fn malformed_bodies() {
+        for (body, length) in [
+            // shorter and longer than declared
+            (&b"5\r\nhello\r\n0\r\n\r\n"[..], 6),
+            (b"5\r\nhello\r\n0\r\n\r\n", 4),
+            (b"zz\r\nhello\r\n0\r\n\r\n", 5),
+            (b"5\r\nhello0\r\n\r\n", 5),
+            (b"5\r\nhel", 5),
+        ] {
+            let error = decode_all(body, length).await.unwrap_err();
+            assert!(
+                matches!(
+                    error.kind(),
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                ),
+                "{body:?}: {error}"
+            );
+        }
+    }
+}
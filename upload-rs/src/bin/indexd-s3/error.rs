@@ -0,0 +1,96 @@
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use indexd_utils::error::Error;
+use log::warn;
+
+use crate::xml;
+
+/// An error reported to the client in the S3 XML format.
+#[derive(Debug)]
+pub struct S3Error {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl S3Error {
+    pub fn new(status: u16, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::from_u16(status).expect("valid status code"),
+            code,
+            message: message.into(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn access_denied(message: impl Into<String>) -> Self {
+        Self::new(403, "AccessDenied", message)
+    }
+
+    pub fn not_implemented(message: impl Into<String>) -> Self {
+        Self::new(501, "NotImplemented", message)
+    }
+
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(400, "InvalidRequest", message)
+    }
+
+    pub fn no_such_bucket(bucket: &str) -> Self {
+        Self::new(404, "NoSuchBucket", format!("no bucket named {bucket}"))
+    }
+
+    pub fn no_such_key(key: &str) -> Self {
+        Self::new(404, "NoSuchKey", format!("no object named {key}"))
+    }
+
+    pub fn no_such_upload(upload_id: &str) -> Self {
+        Self::new(
+            404,
+            "NoSuchUpload",
+            format!("no multipart upload {upload_id}"),
+        )
+    }
+}
+
+impl From<Error> for S3Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::NotFound(key) => S3Error::no_such_key(&key),
+            Error::Usage(message) => S3Error::invalid_request(message),
+            Error::Io(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                S3Error::new(400, "BadDigest", e.to_string())
+            }
+            // S3 clients retry a 503 on their own
+            e if e.is_retryable() => {
+                warn!("request failed, the client may retry: {e}");
+                S3Error::new(503, "ServiceUnavailable", e.to_string())
+            }
+            e => {
+                warn!("request failed: {e}");
+                S3Error::new(500, "InternalError", e.to_string())
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for S3Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::from(e).into()
+    }
+}
+
+impl IntoResponse for S3Error {
+    fn into_response(self) -> Response {
+        let body = xml::error(self.code, &self.message);
+        (
+            self.status,
+            [(header::CONTENT_TYPE, "application/xml")],
+            body,
+        )
+            .into_response()
+    }
+}
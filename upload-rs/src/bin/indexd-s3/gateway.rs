@@ -0,0 +1,599 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::body::Body;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use futures::{StreamExt, TryStreamExt, stream};
+use indexd_utils::bucket::{Bucket, Buckets, Object};
+use indexd_utils::checksum::ChecksumWriter;
+use indexd_utils::client::Client;
+use indexd_utils::error::Error;
+use indexd_utils::http::Span;
+use indexd_utils::progress::Progress;
+use indexd_utils::upload;
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+use tokio_util::either::Either;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::auth::{self, Payload};
+use crate::chunked;
+use crate::error::S3Error;
+use crate::xml;
+
+type Result<T> = std::result::Result<T, S3Error>;
+
+/// The default and maximum number of keys returned by `ListObjectsV2`, and
+/// the most a `DeleteObjects` request may name.
+const MAX_KEYS: usize = 1000;
+
+/// The largest XML body accepted.
+const MAX_BODY: u64 = 1 << 20;
+
+/// Where a multipart upload's bucket and key are recorded, next to its
+/// spooled parts.
+const UPLOAD_FILE: &str = "upload.json";
+
+/// A multipart upload whose parts are spooled to disk until it completes.
+/// It is recorded in the spool directory rather than in memory, so uploads
+/// survive a restart of the gateway.
+#[derive(Debug, Serialize, Deserialize)]
+struct MultipartUpload {
+    bucket: String,
+    key: String,
+}
+
+/// The state shared by every request.
+pub struct Gateway {
+    sdk: Client,
+    buckets: Buckets,
+    spool: PathBuf,
+}
+
+impl Gateway {
+    pub fn new(sdk: Client, buckets: Buckets, spool: PathBuf) -> Self {
+        Self {
+            sdk,
+            buckets,
+            spool,
+        }
+    }
+
+    async fn bucket(&self, name: &str) -> Result<Arc<tokio::sync::Mutex<Bucket>>> {
+        self.buckets
+            .get(&self.sdk, name)
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => S3Error::no_such_bucket(name),
+                e => e.into(),
+            })
+    }
+
+    async fn commit(&self, bucket: &tokio::sync::Mutex<Bucket>) -> Result<()> {
+        Ok(self.buckets.commit(&self.sdk, bucket).await?)
+    }
+
+    pub fn list_buckets(&self) -> Result<Response> {
+        let names = self.buckets.names()?;
+        Ok(xml_response(StatusCode::OK, xml::list_buckets(&names)))
+    }
+
+    pub async fn create_bucket(&self, name: &str) -> Result<Response> {
+        self.buckets
+            .create(&self.sdk, name)
+            .await
+            .map_err(|e| match e {
+                Error::Usage(message) => S3Error::new(409, "BucketAlreadyOwnedByYou", message),
+                e => e.into(),
+            })?;
+        info!("created bucket {name}");
+        Ok(StatusCode::OK.into_response())
+    }
+
+    pub fn head_bucket(&self, name: &str) -> Result<Response> {
+        if !self.buckets.exists(name)? {
+            return Err(S3Error::no_such_bucket(name));
+        }
+        Ok(StatusCode::OK.into_response())
+    }
+
+    pub async fn list_objects(
+        &self,
+        name: &str,
+        query: &HashMap<String, String>,
+    ) -> Result<Response> {
+        let bucket = self.bucket(name).await?;
+        let bucket = bucket.lock().await;
+        let prefix = query.get("prefix").map(String::as_str).unwrap_or_default();
+        let delimiter = query
+            .get("delimiter")
+            .map(String::as_str)
+            .filter(|d| !d.is_empty());
+        let max_keys = query
+            .get("max-keys")
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(MAX_KEYS)
+            .min(MAX_KEYS);
+        let continuation_token = query.get("continuation-token").map(String::as_str);
+        // continuation tokens are the hex-encoded last key or prefix returned
+        let after = match continuation_token {
+            Some(token) => Some(
+                hex::decode(token)
+                    .ok()
+                    .and_then(|key| String::from_utf8(key).ok())
+                    .ok_or_else(|| S3Error::invalid_request("invalid continuation token"))?,
+            ),
+            None => query.get("start-after").cloned(),
+        };
+
+        let mut listing = xml::Listing {
+            bucket: name,
+            prefix,
+            delimiter,
+            max_keys,
+            continuation_token,
+            next_continuation_token: None,
+            contents: Vec::new(),
+            common_prefixes: Vec::new(),
+        };
+        let mut last = None;
+        for (key, object) in bucket.list(prefix) {
+            if let Some(after) = &after {
+                let inside_prefix = delimiter.is_some_and(|d| after.ends_with(d))
+                    && key.starts_with(after.as_str());
+                if key <= after.as_str() || inside_prefix {
+                    continue;
+                }
+            }
+            let common = delimiter.and_then(|d| {
+                key[prefix.len()..]
+                    .find(d)
+                    .map(|i| key[..prefix.len() + i + d.len()].to_string())
+            });
+            if common.is_some() && listing.common_prefixes.last() == common.as_ref() {
+                continue;
+            }
+            if listing.contents.len() + listing.common_prefixes.len() == max_keys {
+                listing.next_continuation_token = last.map(hex::encode);
+                break;
+            }
+            match common {
+                Some(common) => {
+                    last = Some(common.clone());
+                    listing.common_prefixes.push(common);
+                }
+                None => {
+                    last = Some(key.to_string());
+                    listing.contents.push(xml::ListEntry {
+                        key,
+                        size: object.manifest.size,
+                        etag: etag(object),
+                        modified: object.modified,
+                    });
+                }
+            }
+        }
+        Ok(xml_response(StatusCode::OK, xml::list_objects(&listing)))
+    }
+
+    pub async fn put_object(
+        &self,
+        name: &str,
+        key: &str,
+        body: Body,
+        payload: Payload,
+    ) -> Result<Response> {
+        let bucket = self.bucket(name).await?;
+        let options = bucket.lock().await.options();
+        let manifest = match payload {
+            // the digest can only be checked once the whole body is read, so
+            // the body is spooled and checked before any of it is uploaded
+            Payload::Sha256(_) => {
+                let path = self.spool_body(body, payload).await?;
+                let uploaded = match File::open(&path).await {
+                    Ok(file) => {
+                        upload::upload_reader(
+                            &self.sdk,
+                            file,
+                            rand::random(),
+                            options,
+                            Progress::default(),
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e.into()),
+                };
+                let _ = fs::remove_file(&path).await;
+                uploaded?
+            }
+            // chunks are checked as they arrive, and unsigned bodies not at all
+            payload => {
+                upload::upload_reader(
+                    &self.sdk,
+                    body_reader(body, payload),
+                    rand::random(),
+                    options,
+                    Progress::default(),
+                )
+                .await?
+            }
+        };
+
+        let etag = etag(bucket.lock().await.insert(key, manifest));
+        self.commit(&bucket).await?;
+        Ok((StatusCode::OK, [(header::ETAG, format!("\"{etag}\""))]).into_response())
+    }
+
+    /// Writes a body to a file of its own in the spool directory, checked
+    /// as `body_reader` checks it, and returns the file's path.
+    async fn spool_body(&self, body: Body, payload: Payload) -> Result<PathBuf> {
+        fs::create_dir_all(&self.spool).await?;
+        // unlike an upload id, so it never names an upload's directory
+        let path = self
+            .spool
+            .join(format!("put-{}", hex::encode(rand::random::<[u8; 16]>())));
+        let mut file = File::create(&path).await?;
+        let copied = tokio::io::copy(&mut body_reader(body, payload), &mut file).await;
+        if let Err(e) = copied {
+            drop(file);
+            let _ = fs::remove_file(&path).await;
+            return Err(e.into());
+        }
+        Ok(path)
+    }
+
+    /// Copies the object named by `source`, a URL-encoded `/bucket/key`, by
+    /// giving the copy the same manifest, so no data is transferred and both
+    /// objects keep the same slabs.
+    pub async fn copy_object(&self, name: &str, key: &str, source: &str) -> Result<Response> {
+        let (source, version) = source.split_once('?').unwrap_or((source, ""));
+        if !version.is_empty() {
+            return Err(S3Error::not_implemented(
+                "copying an object version is not supported",
+            ));
+        }
+        let source = auth::decode(source);
+        let (source_name, source_key) = source
+            .trim_start_matches('/')
+            .split_once('/')
+            .filter(|(_, key)| !key.is_empty())
+            .ok_or_else(|| {
+                S3Error::invalid_request(format!("invalid x-amz-copy-source {source}"))
+            })?;
+        let object = {
+            let bucket = self.bucket(source_name).await?;
+            let bucket = bucket.lock().await;
+            bucket
+                .stat(source_key)
+                .map_err(|_| S3Error::no_such_key(source_key))?
+                .clone()
+        };
+
+        let bucket = self.bucket(name).await?;
+        let (etag, modified) = {
+            let mut bucket = bucket.lock().await;
+            let copy = bucket.insert(key, object.manifest);
+            (etag(copy), copy.modified)
+        };
+        self.commit(&bucket).await?;
+        Ok(xml_response(
+            StatusCode::OK,
+            xml::copy_object(&etag, modified),
+        ))
+    }
+
+    /// Serves an object, or just its headers for `HEAD`, honouring a single
+    /// byte range.
+    pub async fn get_object(
+        &self,
+        name: &str,
+        key: &str,
+        headers: &HeaderMap,
+        head: bool,
+    ) -> Result<Response> {
+        let object = {
+            let bucket = self.bucket(name).await?;
+            let bucket = bucket.lock().await;
+            bucket
+                .stat(key)
+                .map_err(|_| S3Error::no_such_key(key))?
+                .clone()
+        };
+        let span = Span::parse(headers, object.manifest.size)
+            .map_err(|range| S3Error::new(416, "InvalidRange", format!("invalid range {range}")))?;
+        let response = span
+            .response()
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::ETAG, format!("\"{}\"", etag(&object)))
+            .header(
+                header::LAST_MODIFIED,
+                httpdate::fmt_http_date(
+                    UNIX_EPOCH + std::time::Duration::from_secs(object.modified),
+                ),
+            );
+        Ok(span.send(&self.sdk, object.manifest, response, head, key.to_string()))
+    }
+
+    pub async fn delete_object(&self, name: &str, key: &str) -> Result<Response> {
+        let bucket = self.bucket(name).await?;
+        // deleting a missing object succeeds, as on S3
+        let deleted = bucket.lock().await.delete(key).is_ok();
+        if deleted {
+            self.commit(&bucket).await?;
+        }
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+
+    /// Deletes the objects a `DeleteObjects` body lists, committing them
+    /// together.
+    pub async fn delete_objects(
+        &self,
+        name: &str,
+        body: Body,
+        payload: Payload,
+    ) -> Result<Response> {
+        let body = read_body(body, payload).await?;
+        let (keys, quiet) = std::str::from_utf8(&body)
+            .ok()
+            .and_then(xml::parse_delete)
+            .filter(|(keys, _)| (1..=MAX_KEYS).contains(&keys.len()))
+            .ok_or_else(|| S3Error::new(400, "MalformedXML", "invalid object list"))?;
+        let bucket = self.bucket(name).await?;
+        let mut deleted = 0;
+        {
+            let mut bucket = bucket.lock().await;
+            for key in &keys {
+                // deleting a missing object succeeds, as on S3
+                if bucket.delete(key).is_ok() {
+                    deleted += 1;
+                }
+            }
+        }
+        if deleted > 0 {
+            self.commit(&bucket).await?;
+        }
+        Ok(xml_response(
+            StatusCode::OK,
+            xml::delete_objects(&keys, quiet),
+        ))
+    }
+
+    pub async fn create_multipart(&self, name: &str, key: &str) -> Result<Response> {
+        // make sure the bucket exists before accepting parts
+        self.bucket(name).await?;
+        let upload_id = hex::encode(rand::random::<[u8; 16]>());
+        let dir = self.spool.join(&upload_id);
+        fs::create_dir_all(&dir).await?;
+        let upload = MultipartUpload {
+            bucket: name.to_string(),
+            key: key.to_string(),
+        };
+        let data = serde_json::to_vec(&upload).map_err(io::Error::other)?;
+        fs::write(dir.join(UPLOAD_FILE), data).await?;
+        Ok(xml_response(
+            StatusCode::OK,
+            xml::initiate_multipart(name, key, &upload_id),
+        ))
+    }
+
+    /// Returns the spool directory of the upload, which must be of `key` in
+    /// the bucket `name`.
+    async fn upload_dir(&self, name: &str, key: &str, upload_id: &str) -> Result<PathBuf> {
+        // ids are generated above, so nothing else may name a directory
+        if upload_id.len() != 32 || !upload_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(S3Error::no_such_upload(upload_id));
+        }
+        let dir = self.spool.join(upload_id);
+        let data = match fs::read(dir.join(UPLOAD_FILE)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(S3Error::no_such_upload(upload_id));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let upload: MultipartUpload = serde_json::from_slice(&data).map_err(|e| {
+            S3Error::new(
+                500,
+                "InternalError",
+                format!("invalid record of multipart upload {upload_id}: {e}"),
+            )
+        })?;
+        if upload.bucket != name || upload.key != key {
+            return Err(S3Error::no_such_upload(upload_id));
+        }
+        Ok(dir)
+    }
+
+    pub async fn upload_part(
+        &self,
+        name: &str,
+        key: &str,
+        query: &HashMap<String, String>,
+        body: Body,
+        payload: Payload,
+    ) -> Result<Response> {
+        let upload_id = query
+            .get("uploadId")
+            .map(String::as_str)
+            .unwrap_or_default();
+        let dir = self.upload_dir(name, key, upload_id).await?;
+        let part: u32 = query
+            .get("partNumber")
+            .and_then(|n| n.parse().ok())
+            .filter(|n| (1..=10000).contains(n))
+            .ok_or_else(|| S3Error::invalid_request("invalid part number"))?;
+
+        let path = dir.join(format!("{part:05}"));
+        let mut writer = ChecksumWriter::new(File::create(&path).await?);
+        let copied = tokio::io::copy(&mut body_reader(body, payload), &mut writer).await;
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&path).await;
+            return Err(e.into());
+        }
+        let etag = hex::encode(writer.checksum());
+        Ok((StatusCode::OK, [(header::ETAG, format!("\"{etag}\""))]).into_response())
+    }
+
+    pub async fn complete_multipart(
+        &self,
+        name: &str,
+        key: &str,
+        upload_id: &str,
+        body: Body,
+        payload: Payload,
+    ) -> Result<Response> {
+        let dir = self.upload_dir(name, key, upload_id).await?;
+        let body = read_body(body, payload).await?;
+        let parts = std::str::from_utf8(&body)
+            .ok()
+            .and_then(xml::parse_part_numbers)
+            .filter(|parts| !parts.is_empty() && parts.is_sorted_by(|a, b| a < b))
+            .ok_or_else(|| S3Error::new(400, "MalformedXML", "invalid part list"))?;
+        let mut paths = Vec::with_capacity(parts.len());
+        for part in parts {
+            let path = dir.join(format!("{part:05}"));
+            if !fs::try_exists(&path).await? {
+                return Err(S3Error::new(400, "InvalidPart", format!("no part {part}")));
+            }
+            paths.push(path);
+        }
+
+        let bucket = self.bucket(name).await?;
+        let options = bucket.lock().await.options();
+        let chunks = stream::iter(paths)
+            .then(|path| File::open(path))
+            .map_ok(ReaderStream::new)
+            .try_flatten();
+        let manifest = upload::upload_reader(
+            &self.sdk,
+            StreamReader::new(Box::pin(chunks)),
+            rand::random(),
+            options,
+            Progress::default(),
+        )
+        .await?;
+
+        let etag = etag(bucket.lock().await.insert(key, manifest));
+        self.commit(&bucket).await?;
+        fs::remove_dir_all(&dir).await?;
+        Ok(xml_response(
+            StatusCode::OK,
+            xml::complete_multipart(name, key, &etag),
+        ))
+    }
+
+    pub async fn abort_multipart(
+        &self,
+        name: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<Response> {
+        let dir = self.upload_dir(name, key, upload_id).await?;
+        fs::remove_dir_all(&dir).await?;
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+}
+
+fn xml_response(status: StatusCode, body: String) -> Response {
+    (status, [(header::CONTENT_TYPE, "application/xml")], body).into_response()
+}
+
+/// Objects are tagged with their BLAKE2b checksum rather than an MD5.
+fn etag(object: &Object) -> String {
+    hex::encode(object.manifest.checksum)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Adapts a request body into a reader, decoding `aws-chunked` bodies and
+/// failing if a chunk's signature or, at the end of the body, the signed
+/// digest doesn't match. Everything before the end has been read by then,
+/// so bodies signed with their digest are spooled rather than uploaded as
+/// they are read.
+fn body_reader(
+    body: Body,
+    payload: Payload,
+) -> DigestReader<impl AsyncRead + Unpin + Send + 'static> {
+    let raw = StreamReader::new(body.into_data_stream().map_err(io::Error::other));
+    let (inner, expected) = match payload {
+        Payload::Chunked { signer, length } => (
+            Either::Left(StreamReader::new(Box::pin(chunked::decode(
+                raw, signer, length,
+            )))),
+            None,
+        ),
+        Payload::Sha256(digest) => (Either::Right(raw), Some(digest)),
+        Payload::Unsigned => (Either::Right(raw), None),
+    };
+    DigestReader {
+        inner,
+        hasher: Sha256::new(),
+        expected,
+    }
+}
+
+/// Reads a small body, such as an XML document, checked as `body_reader`
+/// checks any other.
+async fn read_body(body: Body, payload: Payload) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    body_reader(body, payload)
+        .take(MAX_BODY + 1)
+        .read_to_end(&mut data)
+        .await?;
+    if data.len() as u64 > MAX_BODY {
+        return Err(S3Error::new(
+            400,
+            "MaxMessageLengthExceeded",
+            "the request body is too large",
+        ));
+    }
+    Ok(data)
+}
+
+struct DigestReader<R> {
+    inner: R,
+    hasher: Sha256,
+    expected: Option<[u8; 32]>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DigestReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = &buf.filled()[before..];
+        if this.expected.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+        if !read.is_empty() {
+            this.hasher.update(read);
+        } else if buf.remaining() > 0 {
+            let digest: [u8; 32] = this.hasher.clone().finalize().into();
+            if Some(digest) != this.expected {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the body does not match x-amz-content-sha256",
+                )));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,192 @@
+//! Serves indexd buckets over a minimal S3 API so existing S3 tooling can
+//! store objects on indexd.
+
+mod auth;
+mod chunked;
+mod error;
+mod gateway;
+mod time;
+mod xml;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{HeaderMap, Method, Uri};
+use axum::response::{IntoResponse, Response};
+use clap::Parser;
+use indexd_utils::bucket::Buckets;
+use indexd_utils::client;
+use indexd_utils::config::Config;
+use indexd_utils::error::Error;
+use indexd_utils::keys;
+use indexd_utils::metrics;
+use log::info;
+use tokio::net::TcpListener;
+
+use crate::auth::Credentials;
+use crate::error::S3Error;
+use crate::gateway::Gateway;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "indexd-s3",
+    version,
+    about = "Serve indexd buckets over a minimal S3 API"
+)]
+struct Args {
+    /// The config file, defaults to `~/.config/indexd-utils/config.toml`
+    #[arg(long, env = "INDEXD_UTILS_CONFIG")]
+    config: Option<PathBuf>,
+    /// The config profile to use instead of the default profile
+    #[arg(long, env = "INDEXD_PROFILE")]
+    profile: Option<String>,
+    /// The app identity to connect as instead of the profile's
+    #[arg(long, env = "INDEXD_APP")]
+    app: Option<String>,
+    /// The address to listen on
+    #[arg(long, default_value = "127.0.0.1:9000")]
+    listen: SocketAddr,
+    /// The access key clients sign requests with
+    #[arg(long, env = "INDEXD_S3_ACCESS_KEY")]
+    access_key: String,
+    /// The secret key clients sign requests with
+    #[arg(long, env = "INDEXD_S3_SECRET_KEY", hide_env_values = true)]
+    secret_key: String,
+    /// Where the parts of multipart uploads are kept until they complete,
+    /// and objects signed with their digest until it is checked; defaults
+    /// to the system temporary directory
+    #[arg(long)]
+    spool_dir: Option<PathBuf>,
+    /// Serve Prometheus metrics on this address at /metrics
+    #[arg(long, value_name = "ADDR")]
+    metrics: Option<SocketAddr>,
+}
+
+struct AppState {
+    gateway: Arc<Gateway>,
+    credentials: Credentials,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    pretty_env_logger::init();
+
+    let args = Args::parse();
+    let config = Config::load(args.config.as_deref()).await?;
+    let settings = config.settings(args.profile.as_deref(), args.app.as_deref())?;
+    // every bucket's key is derived from the same passphrase
+    let passphrase = keys::read_passphrase(false)?;
+    let catalog = settings.catalog()?;
+    let spool = args
+        .spool_dir
+        .unwrap_or_else(|| std::env::temp_dir().join("indexd-s3"));
+    let sdk = client::connect(&settings).await?;
+
+    let buckets = Buckets::new(catalog, passphrase, settings.upload_options());
+    let state = Arc::new(AppState {
+        gateway: Arc::new(Gateway::new(sdk, buckets, spool)),
+        credentials: Credentials {
+            access_key: args.access_key,
+            secret_key: args.secret_key,
+        },
+    });
+    let app = Router::new().fallback(handle).with_state(state);
+    if let Some(addr) = args.metrics {
+        metrics::serve(addr).await?;
+    }
+    let listener = TcpListener::bind(args.listen).await?;
+    info!("listening on {}", args.listen);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn handle(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    match dispatch(&state, method, uri, headers, body).await {
+        Ok(response) => response,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Routes a path-style request to the matching S3 operation.
+async fn dispatch(
+    state: &AppState,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let payload = auth::verify(&state.credentials, &method, &uri, &headers, gateway::now())?;
+    let query: HashMap<String, String> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (auth::decode(name), auth::decode(value))
+        })
+        .collect();
+    let path = uri.path().trim_start_matches('/');
+    let (bucket, key) = match path.split_once('/') {
+        Some((bucket, key)) => (bucket, auth::decode(key)),
+        None => (path, String::new()),
+    };
+    let gateway = &state.gateway;
+
+    match (&method, bucket.is_empty(), key.is_empty()) {
+        (&Method::GET, true, _) => gateway.list_buckets(),
+        (_, true, _) => Err(S3Error::not_implemented("unsupported service operation")),
+        (&Method::PUT, false, true) => gateway.create_bucket(bucket).await,
+        (&Method::HEAD, false, true) => gateway.head_bucket(bucket),
+        (&Method::GET, false, true) => gateway.list_objects(bucket, &query).await,
+        (&Method::POST, false, true) if query.contains_key("delete") => {
+            gateway.delete_objects(bucket, body, payload).await
+        }
+        (&Method::PUT, false, false) if headers.contains_key("x-amz-copy-source") => {
+            if query.contains_key("uploadId") {
+                return Err(S3Error::not_implemented("UploadPartCopy is not supported"));
+            }
+            let source = headers["x-amz-copy-source"]
+                .to_str()
+                .map_err(|_| S3Error::invalid_request("invalid x-amz-copy-source"))?;
+            gateway.copy_object(bucket, &key, source).await
+        }
+        (&Method::PUT, false, false) if query.contains_key("uploadId") => {
+            gateway
+                .upload_part(bucket, &key, &query, body, payload)
+                .await
+        }
+        (&Method::PUT, false, false) => gateway.put_object(bucket, &key, body, payload).await,
+        (&Method::GET, false, false) => gateway.get_object(bucket, &key, &headers, false).await,
+        (&Method::HEAD, false, false) => gateway.get_object(bucket, &key, &headers, true).await,
+        (&Method::POST, false, false) if query.contains_key("uploads") => {
+            gateway.create_multipart(bucket, &key).await
+        }
+        (&Method::POST, false, false) if query.contains_key("uploadId") => {
+            gateway
+                .complete_multipart(bucket, &key, &query["uploadId"], body, payload)
+                .await
+        }
+        (&Method::DELETE, false, false) if query.contains_key("uploadId") => {
+            gateway
+                .abort_multipart(bucket, &key, &query["uploadId"])
+                .await
+        }
+        (&Method::DELETE, false, false) => gateway.delete_object(bucket, &key).await,
+        _ => Err(S3Error::not_implemented(format!(
+            "unsupported operation {method} {}",
+            uri.path()
+        ))),
+    }
+}
@@ -0,0 +1,215 @@
+//! Renders the XML bodies of S3 responses.
+
+use std::fmt::Write;
+
+use crate::time;
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+const NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Undoes `escape`, along with numeric character references, returning
+/// `None` for an unknown entity.
+pub fn unescape(s: &str) -> Option<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest.find(';')?;
+        let c = match &rest[..end] {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            entity => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => entity.strip_prefix('#')?.parse().ok()?,
+                };
+                char::from_u32(code)?
+            }
+        };
+        out.push(c);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+pub fn error(code: &str, message: &str) -> String {
+    format!(
+        "{HEADER}<Error><Code>{code}</Code><Message>{}</Message></Error>",
+        escape(message)
+    )
+}
+
+pub fn list_buckets(names: &[String]) -> String {
+    let mut out = format!(
+        "{HEADER}<ListAllMyBucketsResult xmlns=\"{NAMESPACE}\"><Owner><ID>indexd</ID><DisplayName>indexd</DisplayName></Owner><Buckets>"
+    );
+    for name in names {
+        // creation times aren't tracked
+        let _ = write!(
+            out,
+            "<Bucket><Name>{}</Name><CreationDate>{}</CreationDate></Bucket>",
+            escape(name),
+            time::iso8601(0)
+        );
+    }
+    out.push_str("</Buckets></ListAllMyBucketsResult>");
+    out
+}
+
+/// An object in a `ListObjectsV2` response.
+pub struct ListEntry<'a> {
+    pub key: &'a str,
+    pub size: u64,
+    pub etag: String,
+    pub modified: u64,
+}
+
+/// The parameters and results of a `ListObjectsV2` request.
+pub struct Listing<'a> {
+    pub bucket: &'a str,
+    pub prefix: &'a str,
+    pub delimiter: Option<&'a str>,
+    pub max_keys: usize,
+    pub continuation_token: Option<&'a str>,
+    pub next_continuation_token: Option<String>,
+    pub contents: Vec<ListEntry<'a>>,
+    pub common_prefixes: Vec<String>,
+}
+
+pub fn list_objects(listing: &Listing<'_>) -> String {
+    let mut out = format!(
+        "{HEADER}<ListBucketResult xmlns=\"{NAMESPACE}\"><Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+        escape(listing.bucket),
+        escape(listing.prefix),
+        listing.contents.len() + listing.common_prefixes.len(),
+        listing.max_keys,
+        listing.next_continuation_token.is_some(),
+    );
+    if let Some(delimiter) = listing.delimiter {
+        let _ = write!(out, "<Delimiter>{}</Delimiter>", escape(delimiter));
+    }
+    if let Some(token) = listing.continuation_token {
+        let _ = write!(
+            out,
+            "<ContinuationToken>{}</ContinuationToken>",
+            escape(token)
+        );
+    }
+    if let Some(token) = &listing.next_continuation_token {
+        let _ = write!(
+            out,
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            escape(token)
+        );
+    }
+    for entry in &listing.contents {
+        let _ = write!(
+            out,
+            "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>&quot;{}&quot;</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+            escape(entry.key),
+            time::iso8601(entry.modified),
+            entry.etag,
+            entry.size
+        );
+    }
+    for prefix in &listing.common_prefixes {
+        let _ = write!(
+            out,
+            "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+            escape(prefix)
+        );
+    }
+    out.push_str("</ListBucketResult>");
+    out
+}
+
+pub fn initiate_multipart(bucket: &str, key: &str, upload_id: &str) -> String {
+    format!(
+        "{HEADER}<InitiateMultipartUploadResult xmlns=\"{NAMESPACE}\"><Bucket>{}</Bucket><Key>{}</Key><UploadId>{upload_id}</UploadId></InitiateMultipartUploadResult>",
+        escape(bucket),
+        escape(key)
+    )
+}
+
+pub fn complete_multipart(bucket: &str, key: &str, etag: &str) -> String {
+    format!(
+        "{HEADER}<CompleteMultipartUploadResult xmlns=\"{NAMESPACE}\"><Location>/{}/{}</Location><Bucket>{}</Bucket><Key>{}</Key><ETag>&quot;{etag}&quot;</ETag></CompleteMultipartUploadResult>",
+        escape(bucket),
+        escape(key),
+        escape(bucket),
+        escape(key)
+    )
+}
+
+pub fn copy_object(etag: &str, modified: u64) -> String {
+    format!(
+        "{HEADER}<CopyObjectResult xmlns=\"{NAMESPACE}\"><LastModified>{}</LastModified><ETag>&quot;{etag}&quot;</ETag></CopyObjectResult>",
+        time::iso8601(modified)
+    )
+}
+
+/// Lists the keys a `DeleteObjects` request deleted, which in quiet mode
+/// are left out.
+pub fn delete_objects(keys: &[String], quiet: bool) -> String {
+    let mut out = format!("{HEADER}<DeleteResult xmlns=\"{NAMESPACE}\">");
+    if !quiet {
+        for key in keys {
+            let _ = write!(out, "<Deleted><Key>{}</Key></Deleted>", escape(key));
+        }
+    }
+    out.push_str("</DeleteResult>");
+    out
+}
+
+/// Returns the keys listed in a `DeleteObjects` body, in order, and
+/// whether it asked for quiet mode.
+pub fn parse_delete(body: &str) -> Option<(Vec<String>, bool)> {
+    let mut keys = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("<Key>") {
+        rest = &rest[start + "<Key>".len()..];
+        let end = rest.find("</Key>")?;
+        keys.push(unescape(&rest[..end])?);
+        rest = &rest[end..];
+    }
+    let quiet = body.find("<Quiet>").is_some_and(|start| {
+        body[start + "<Quiet>".len()..]
+            .trim_start()
+            .starts_with("true")
+    });
+    Some((keys, quiet))
+}
+
+/// Returns the part numbers listed in a `CompleteMultipartUpload` body, in
+/// order.
+pub fn parse_part_numbers(body: &str) -> Option<Vec<u32>> {
+    let mut parts = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("<PartNumber>") {
+        rest = &rest[start + "<PartNumber>".len()..];
+        let end = rest.find("</PartNumber>")?;
+        parts.push(rest[..end].trim().parse().ok()?);
+        rest = &rest[end..];
+    }
+    Some(parts)
+}
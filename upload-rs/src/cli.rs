@@ -0,0 +1,1091 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use indexd_utils::chaos::Chaos;
+use indexd_utils::directory::Links;
+use indexd_utils::health::Threshold;
+use indexd_utils::net::{self, Proxy};
+use indexd_utils::recipients::Recipient;
+use indexd_utils::redundancy::{Redundancy, SlabSizing};
+use indexd_utils::schedule::Schedule;
+use indexd_utils::selection::Order;
+use indexd_utils::share;
+use indexd_utils::tags::Tag;
+use indexd_utils::throttle;
+use url::Url;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "upload-rs",
+    version,
+    about = "Store and retrieve files using indexd"
+)]
+pub struct Cli {
+    /// The config file, defaults to `~/.config/indexd-utils/config.toml`
+    #[arg(long, global = true, env = "INDEXD_UTILS_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// The config profile to use instead of the default profile
+    #[arg(long, global = true, env = "INDEXD_PROFILE")]
+    pub profile: Option<String>,
+    /// The app identity from the config to connect as instead of the
+    /// profile's
+    #[arg(long, global = true, env = "INDEXD_APP")]
+    pub app: Option<String>,
+    /// Limit uploads to this rate, e.g. `5M` for 5 MiB/s, counting parity
+    #[arg(long, global = true, value_name = "RATE", value_parser = throttle::parse_rate)]
+    pub bwlimit_up: Option<u64>,
+    /// Limit downloads to this rate, e.g. `500K`
+    #[arg(long, global = true, value_name = "RATE", value_parser = throttle::parse_rate)]
+    pub bwlimit_down: Option<u64>,
+    /// Request a slab again if it hasn't downloaded after this many
+    /// milliseconds, keeping whichever copy arrives first
+    #[arg(long, global = true, value_name = "MS")]
+    pub hedge_after: Option<u64>,
+    /// Only store shards on this host, given by public key; repeatable
+    #[arg(long, global = true, value_name = "KEY")]
+    pub host_allow: Vec<String>,
+    /// Never store shards on this host, given by public key; repeatable
+    #[arg(long, global = true, value_name = "KEY")]
+    pub host_block: Vec<String>,
+    /// Reject uploads that land on hosts ruled out, uploading again up to
+    /// this many times; every rejected attempt stays stored
+    #[arg(long, global = true, value_name = "N")]
+    pub placement_attempts: Option<u32>,
+    /// Print results as JSON, one object per line, keeping logs on stderr
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Give up if the app hasn't been approved after this many seconds
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECS",
+        env = "INDEXD_APPROVAL_TIMEOUT"
+    )]
+    pub approval_timeout: Option<u64>,
+    /// Inject faults into transfers to test how they're handled, such as
+    /// fail=0.05,delay=0.1,delay_ms=2000,corrupt=0.01; also INDEXD_CHAOS
+    #[arg(long, global = true, value_name = "SPEC")]
+    pub chaos: Option<Chaos>,
+    /// Send webhooks, --from-url and s3:// or gs:// downloads and doctor's
+    /// app URL check through this proxy, such as socks5://127.0.0.1:1080,
+    /// instead of HTTPS_PROXY or ALL_PROXY; also INDEXD_PROXY. The app
+    /// connection and host transfers don't go through it
+    #[arg(long, global = true, value_name = "URL")]
+    pub proxy: Option<Proxy>,
+    /// Trust the PEM certificates in this file as well as the system's
+    /// roots on the connections --proxy covers. The app connection only
+    /// trusts the system's roots
+    #[arg(long, global = true, value_name = "PATH")]
+    pub ca_bundle: Option<PathBuf>,
+    /// Present the certificate and private key in this PEM file to webhook
+    /// receivers, --from-url servers and doctor's app URL check, but not to
+    /// the app itself
+    #[arg(long, global = true, value_name = "PATH")]
+    pub client_cert: Option<PathBuf>,
+    /// Accept any certificate from webhook receivers, --from-url servers
+    /// and doctor's app URL check, for lab setups only. The app connection
+    /// is always verified
+    #[arg(long, global = true)]
+    pub insecure_skip_verify: bool,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Upload a file or directory and write its manifest
+    Upload(UploadArgs),
+    /// Estimate what uploading a file or directory would store and cost
+    Estimate(EstimateArgs),
+    /// Download a file or directory described by a manifest
+    Download(DownloadArgs),
+    /// Check that a manifest's file can be fully recovered
+    Verify(VerifyArgs),
+    /// Re-upload the degraded slabs of a manifest and rewrite it
+    Repair(RepairArgs),
+    /// Re-encrypt the slabs of a manifest under a new key and rewrite it
+    Rekey(RekeyArgs),
+    /// Upload only what was appended to a file since its upload and extend
+    /// its manifest
+    Append(AppendArgs),
+    /// Print a token that lets someone else download a file or part of it
+    Share(ShareArgs),
+    /// Download a file from a share token
+    Fetch(FetchArgs),
+    /// Seal a manifest for recipients too, so each can open it with their
+    /// own recipient key
+    Grant(GrantArgs),
+    /// Stop sealing a manifest for recipients, sealing it under a fresh key
+    Revoke(RevokeArgs),
+    /// Upload a directory as a single tar archive
+    Archive(ArchiveArgs),
+    /// Download an archive uploaded with archive and unpack it
+    Extract(ExtractArgs),
+    /// Capture a directory into a new snapshot of a backup set
+    Backup(BackupArgs),
+    /// Recreate a directory from a snapshot
+    Restore(RestoreArgs),
+    /// List the snapshots in the catalog
+    Snapshots(SnapshotsArgs),
+    /// Show the files added, removed and modified between two snapshots or
+    /// directory manifests, or from one to a local directory
+    Diff(DiffArgs),
+    /// Upload the new and changed files of a directory to the catalog
+    Sync(SyncArgs),
+    /// List the catalog or the manifests in a directory
+    Ls(LsArgs),
+    /// Catalog an object under another name too, sharing its slabs
+    /// instead of uploading it again
+    Cp(CpArgs),
+    /// Rename a catalogued object and its versions
+    Mv(MvArgs),
+    /// Remove a manifest or move a catalogued object to the trash. Its slabs
+    /// stay stored: releasing them is blocked on the SDK
+    Rm(RmArgs),
+    /// List, restore or empty the catalogued objects removed with rm
+    Trash(TrashArgs),
+    /// Export the catalog to an encrypted archive, or import one
+    Catalog(CatalogArgs),
+    /// Report what the catalogued uploads take up and what was spent
+    Status,
+    /// Find slabs left stored by abandoned uploads that no manifest refers
+    /// to. Only reports them: deleting them is blocked on the SDK
+    Gc(GcArgs),
+    /// Verify and repair the whole catalog, least healthy objects first,
+    /// and look for orphaned slabs, once or on a schedule
+    Maintain(MaintainArgs),
+    /// Show what the profile's lifecycle rules would expire, purge and
+    /// verify now
+    Lifecycle(LifecycleArgs),
+    /// Check the connection to the app, the hosts and this machine's
+    /// encoding speed, and suggest fixes for what's wrong
+    Doctor(DoctorArgs),
+    /// Upload and download random data to measure throughput and latency
+    Bench(BenchArgs),
+    /// Store named objects in a bucket whose index lives on indexd
+    Bucket(BucketArgs),
+    /// Mount the catalog as a read-only filesystem
+    #[cfg(feature = "fuse")]
+    Mount(MountArgs),
+    /// Browse the catalog interactively: inspect objects and verify,
+    /// repair or download them
+    #[cfg(feature = "tui")]
+    Browse(BrowseArgs),
+    /// List the app identities, and manage the keys kept in the OS
+    /// keychain
+    #[command(visible_alias = "keys")]
+    Key(KeyArgs),
+    /// Inspect what transfers have shown of each host
+    Hosts(HostsArgs),
+    /// List the journalled transfer sessions, or show one's transfers and
+    /// hosts to find what made it slow or fail
+    Session(SessionArgs),
+    /// Serve the buckets over a network protocol
+    Serve(ServeArgs),
+    /// Stay connected to the app and upload files for local clients over
+    /// HTTP
+    Daemon(DaemonArgs),
+    /// Add, list and control the uploads queued with a running daemon
+    Queue(QueueArgs),
+}
+
+#[derive(Debug, Default, Args)]
+pub struct RedundancyArgs {
+    /// A redundancy preset: standard (3x), economy (2x), archival (5x), an
+    /// expansion such as 1.5x, or shards such as 10+20
+    #[arg(long, value_name = "PRESET")]
+    pub redundancy: Option<Redundancy>,
+    /// The number of data shards per slab, overriding the preset
+    #[arg(long)]
+    pub data_shards: Option<u8>,
+    /// The number of parity shards per slab, overriding the preset
+    #[arg(long)]
+    pub parity_shards: Option<u8>,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct FilterArgs {
+    /// Skip files and directories matching this gitignore-style pattern
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+    /// Upload files matching this pattern even if an exclude matched them
+    #[arg(long, value_name = "PATTERN")]
+    pub include: Vec<String>,
+    /// Read gitignore-style rules from a file, one per line
+    #[arg(long, value_name = "FILE")]
+    pub filter_from: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct UploadArgs {
+    /// The file or directory to upload, `-` to read from stdin, or an
+    /// `s3://bucket/key` or `gs://bucket/object` to stream from an object
+    /// store
+    #[arg(required_unless_present_any = ["resume", "from_url"])]
+    pub input: Option<PathBuf>,
+    /// Stream the object at this HTTP(S), s3:// or gs:// URL into the
+    /// upload instead of reading a local file
+    #[arg(long, value_name = "URL", conflicts_with_all = ["input", "resume"])]
+    pub from_url: Option<Url>,
+    /// Where to write the manifest, defaults to `<input>.manifest.json`
+    #[arg(short, long)]
+    pub manifest: Option<PathBuf>,
+    /// The name to record the upload under in the catalog, defaults to the
+    /// input's file name
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Resume an interrupted upload from its checkpoint
+    #[arg(long, conflicts_with = "input")]
+    pub resume: Option<PathBuf>,
+    /// Derive the encryption key from a passphrase and seal the manifest
+    /// with it. Read from INDEXD_PASSPHRASE or prompted for.
+    #[arg(long, conflicts_with = "resume")]
+    pub passphrase: bool,
+    /// Also store the sealed manifest on indexd and print a locator that,
+    /// with the passphrase, downloads the upload from any machine
+    #[arg(long, conflicts_with = "dry_run")]
+    pub locator: bool,
+    #[command(flatten)]
+    pub redundancy: RedundancyArgs,
+    /// The maximum number of shards to encode or upload at once, defaults to
+    /// one slab's worth
+    #[arg(short = 'j', long)]
+    pub jobs: Option<usize>,
+    /// The memory the upload may buffer, such as 512M or 2G; fewer slabs
+    /// are uploaded at once to stay within it
+    #[arg(long, value_name = "SIZE", value_parser = throttle::parse_size)]
+    pub max_memory: Option<u64>,
+    /// Choose each file's slab size within these bounds, such as 4M-120M,
+    /// by its size and the throughput measured so far, scaling the data and
+    /// parity shards together
+    #[arg(long, value_name = "MIN-MAX", conflicts_with = "resume")]
+    pub slab_size: Option<SlabSizing>,
+    /// Compress the data with zstd before encrypting it, optionally at the
+    /// given level
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        num_args = 0..=1,
+        default_missing_value = "3",
+        conflicts_with = "resume"
+    )]
+    pub compress: Option<i32>,
+    /// Split the input into content-defined chunks and only upload the
+    /// chunks not already in the catalog
+    #[arg(long, conflicts_with_all = ["resume", "compress"])]
+    pub dedup: bool,
+    /// Record the SHA-256 of every file in its manifest as well as its
+    /// BLAKE2b checksum
+    #[arg(long, conflicts_with = "resume")]
+    pub sha256: bool,
+    /// Seal the data in authenticated XChaCha20-Poly1305 blocks before the
+    /// SDK encrypts it, so altered or reordered data fails to download
+    #[arg(long, conflicts_with_all = ["resume", "compress", "dedup"])]
+    pub aead: bool,
+    /// Pack the files of a directory up to this many bytes into shared
+    /// slabs, defaults to 1 MiB; 0 uploads every file on its own
+    #[arg(long, value_name = "BYTES")]
+    pub pack_threshold: Option<u64>,
+    /// Print what would be uploaded and an estimate of its cost without
+    /// uploading anything
+    #[arg(short = 'n', long, conflicts_with = "resume")]
+    pub dry_run: bool,
+    /// Don't record the permission bits and extended attributes of a
+    /// directory's files
+    #[arg(long)]
+    pub no_preserve: bool,
+    /// What to do with the symbolic links of a directory: follow them,
+    /// preserve them as links, or skip them
+    #[arg(long, value_name = "MODE", default_value = "skip")]
+    pub links: Links,
+    /// Tag the catalogued object, such as project=alpha; may be repeated
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    pub tags: Vec<Tag>,
+    /// Record a key/value pair with the catalogued object; may be repeated
+    #[arg(long = "meta", value_name = "KEY=VALUE")]
+    pub metadata: Vec<Tag>,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct EstimateArgs {
+    /// The file or directory to estimate
+    pub input: PathBuf,
+    #[command(flatten)]
+    pub redundancy: RedundancyArgs,
+    /// The size up to which the files of a directory are packed together
+    #[arg(long, value_name = "BYTES")]
+    pub pack_threshold: Option<u64>,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct DownloadArgs {
+    /// The manifest of the file or directory to download, its name in the
+    /// catalog, or a locator printed by upload --locator
+    pub manifest: PathBuf,
+    /// Where to write the file or directory, or - to stream a file to
+    /// stdout
+    pub output: PathBuf,
+    /// The number of files to download concurrently
+    #[arg(short = 'j', long)]
+    pub concurrency: Option<usize>,
+    /// Keep the complete slabs already present in the output and download
+    /// the rest
+    #[arg(long, conflicts_with_all = ["offset", "length"])]
+    pub resume: bool,
+    /// Download a file's slabs in this many concurrent runs, each writing
+    /// at its own offset
+    #[arg(long, default_value_t = 1, conflicts_with_all = ["resume", "offset", "length"])]
+    pub parts: usize,
+    /// Only download the file starting at this byte offset
+    #[arg(long)]
+    pub offset: Option<u64>,
+    /// Only download this many bytes of the file
+    #[arg(long)]
+    pub length: Option<u64>,
+    /// Don't read the downloaded files back to check them against their
+    /// manifests' checksums
+    #[arg(long)]
+    pub no_verify: bool,
+    /// Don't restore the modification times, permission bits and extended
+    /// attributes of a directory's files
+    #[arg(long)]
+    pub no_preserve: bool,
+    /// Download this version of a catalogued object instead of the latest,
+    /// as numbered by ls --versions
+    #[arg(long)]
+    pub version: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// The manifest of the file or directory to verify, or its name in
+    /// the catalog
+    #[arg(required_unless_present = "tags", conflicts_with = "tags")]
+    pub manifest: Option<PathBuf>,
+    /// Verify every catalogued object with this tag instead; may be
+    /// repeated to require several
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    pub tags: Vec<Tag>,
+    /// Fail if any slab has fewer healthy shards than this, such as 12 or
+    /// data+3, overriding the profile's min_healthy_shards
+    #[arg(long, value_name = "THRESHOLD")]
+    pub min_healthy_shards: Option<Threshold>,
+}
+
+#[derive(Debug, Args)]
+pub struct RepairArgs {
+    /// The manifest of the file or directory to repair, or its name in
+    /// the catalog
+    #[arg(required_unless_present = "tags", conflicts_with = "tags")]
+    pub manifest: Option<PathBuf>,
+    /// Repair every catalogued object with this tag instead; may be
+    /// repeated to require several
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    pub tags: Vec<Tag>,
+    /// Re-upload every slab, not just the degraded ones. Combine with
+    /// --data-shards and --parity-shards to change the redundancy.
+    #[arg(long)]
+    pub all: bool,
+    /// Defaults to the redundancy each file was uploaded with
+    #[command(flatten)]
+    pub redundancy: RedundancyArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct RekeyArgs {
+    /// The manifest of the file or directory to rekey, or its name in the
+    /// catalog
+    pub manifest: PathBuf,
+    /// The new master key as 64 hex characters, random by default
+    #[arg(
+        long,
+        value_name = "HEX",
+        env = "INDEXD_NEW_KEY",
+        hide_env_values = true
+    )]
+    pub new_key: Option<String>,
+    /// Derive the new key from a new passphrase and seal the manifest with
+    /// it. Sealed manifests are always rekeyed this way.
+    #[arg(long, conflicts_with = "new_key")]
+    pub passphrase: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct AppendArgs {
+    /// The manifest of the file, or its name in the catalog
+    pub manifest: PathBuf,
+    /// The grown file, or `-` to read it from stdin
+    pub input: PathBuf,
+    /// The maximum number of shards to encode or upload at once
+    #[arg(short = 'j', long)]
+    pub jobs: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+pub struct ShareArgs {
+    /// The manifest of the file to share, or its name in the catalog
+    pub manifest: PathBuf,
+    /// How long the share can be fetched for, such as 30m, 12h or 7d
+    #[arg(long, value_name = "LIFETIME", value_parser = share::parse_lifetime)]
+    pub expires: Option<Duration>,
+    /// Only share the file starting at this byte offset
+    #[arg(long, requires = "length")]
+    pub offset: Option<u64>,
+    /// Only share this many bytes of the file
+    #[arg(long)]
+    pub length: Option<u64>,
+    /// Write the token to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct FetchArgs {
+    /// The share token, or a file containing it
+    pub token: String,
+    /// Where to write the file
+    pub output: PathBuf,
+}
+
+/// The passphrase is read from INDEXD_PASSPHRASE or prompted for.
+#[derive(Debug, Args)]
+pub struct GrantArgs {
+    /// The sealed manifest, or its name in the catalog
+    pub manifest: PathBuf,
+    /// The recipients to add, as printed by key recipient
+    #[arg(required = true)]
+    pub recipients: Vec<Recipient>,
+}
+
+/// The passphrase is read from INDEXD_PASSPHRASE or prompted for.
+#[derive(Debug, Args)]
+pub struct RevokeArgs {
+    /// The sealed manifest, or its name in the catalog
+    pub manifest: PathBuf,
+    /// The recipients to remove
+    #[arg(required = true)]
+    pub recipients: Vec<Recipient>,
+}
+
+#[derive(Debug, Args)]
+pub struct ArchiveArgs {
+    /// The directory to archive
+    pub dir: PathBuf,
+    /// Where to write the manifest, defaults to `<dir>.tar.manifest.json`
+    #[arg(short, long)]
+    pub manifest: Option<PathBuf>,
+    /// The name to record the archive under in the catalog, defaults to
+    /// `<dir>.tar`
+    #[arg(short, long)]
+    pub name: Option<String>,
+    #[command(flatten)]
+    pub redundancy: RedundancyArgs,
+    /// Compress the archive with zstd at this level, 3 if none is given
+    #[arg(long, value_name = "LEVEL", num_args = 0..=1, default_missing_value = "3")]
+    pub compress: Option<i32>,
+    /// The maximum number of shards to encode or upload at once
+    #[arg(short = 'j', long)]
+    pub jobs: Option<usize>,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ExtractArgs {
+    /// The manifest of the archive, or its name in the catalog
+    pub manifest: PathBuf,
+    /// The directory to unpack into
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct BackupArgs {
+    /// The directory to back up
+    pub dir: PathBuf,
+    /// The backup set to add the snapshot to, defaults to the directory's
+    /// name
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Seal the snapshot with a passphrase. Snapshots following a sealed
+    /// one are always sealed with the same passphrase.
+    #[arg(long)]
+    pub passphrase: bool,
+    /// Read every file instead of reusing the unchanged files of the
+    /// previous snapshot
+    #[arg(long)]
+    pub full: bool,
+    #[command(flatten)]
+    pub redundancy: RedundancyArgs,
+    /// The maximum number of shards to encode or upload at once
+    #[arg(short = 'j', long)]
+    pub jobs: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    /// The id of the snapshot to restore, as listed by `snapshots`
+    #[arg(long)]
+    pub snapshot: i64,
+    /// Where to recreate the directory
+    pub output: PathBuf,
+    /// The number of files to download concurrently
+    #[arg(short = 'j', long)]
+    pub concurrency: Option<usize>,
+    /// Only restore the files matching this gitignore-style pattern, such
+    /// as `photos/2023/**`
+    #[arg(long, value_name = "PATTERN")]
+    pub include: Vec<String>,
+    /// Restore the files matching this pattern before the rest; repeated,
+    /// each goes before the next
+    #[arg(long, value_name = "PATTERN")]
+    pub priority: Vec<String>,
+    /// The order of the files within each priority: interleave, smallest,
+    /// largest or path
+    #[arg(long, value_name = "ORDER", default_value = "interleave")]
+    pub order: Order,
+    /// Leave any file already at its path alone
+    #[arg(long, conflicts_with = "skip_matching")]
+    pub skip_existing: bool,
+    /// Leave the files already at their paths alone when their checksum
+    /// matches the snapshot's
+    #[arg(long)]
+    pub skip_matching: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SnapshotsArgs {
+    /// Only list the snapshots of this backup set
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// The older snapshot, by id, or directory manifest or catalog name
+    pub old: String,
+    /// The newer snapshot or directory manifest
+    #[arg(required_unless_present = "local", conflicts_with = "local")]
+    pub new: Option<String>,
+    /// Compare `old` with this directory instead, previewing what a backup
+    /// or sync of it would transfer
+    #[arg(long, value_name = "DIR")]
+    pub local: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct SyncArgs {
+    /// The directory to sync
+    pub dir: PathBuf,
+    /// The catalog prefix to sync into; each file is catalogued as
+    /// `<prefix>/<path>`
+    pub prefix: String,
+    /// Remove catalogued objects under the prefix that have no local file
+    #[arg(long)]
+    pub delete: bool,
+    /// Print the plan without uploading or removing anything
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+    /// Seal the uploaded manifests with a passphrase
+    #[arg(long)]
+    pub passphrase: bool,
+    /// Keep running and sync changes as they happen
+    #[arg(long, conflicts_with = "dry_run")]
+    pub watch: bool,
+    /// How long a path must be left alone before a change is synced, in
+    /// milliseconds
+    #[arg(long, default_value_t = 2000, requires = "watch")]
+    pub debounce: u64,
+    /// The number of change events to hold while an upload runs
+    #[arg(long, default_value_t = 1024, requires = "watch")]
+    pub queue: usize,
+    /// Serve Prometheus metrics on this address at /metrics while watching
+    #[arg(long, value_name = "ADDR", requires = "watch")]
+    pub metrics: Option<SocketAddr>,
+    #[command(flatten)]
+    pub redundancy: RedundancyArgs,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct LsArgs {
+    /// The directory to search for manifests; lists the catalog if omitted
+    pub dir: Option<PathBuf>,
+    /// Only list catalogued objects whose names start with this prefix
+    #[arg(long, conflicts_with = "dir")]
+    pub prefix: Option<String>,
+    /// List every kept version of each catalogued object
+    #[arg(long, conflicts_with = "dir")]
+    pub versions: bool,
+    /// Only list catalogued objects with this tag, such as project=alpha;
+    /// may be repeated to require several
+    #[arg(long = "tag", value_name = "KEY=VALUE", conflicts_with_all = ["dir", "versions"])]
+    pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Args)]
+pub struct CpArgs {
+    /// The catalogued object to copy
+    pub src: String,
+    /// The name to catalog the copy under; an object already there becomes
+    /// its previous version
+    pub dst: String,
+}
+
+#[derive(Debug, Args)]
+pub struct MvArgs {
+    /// The catalogued object to rename
+    pub src: String,
+    /// Its new name, which mustn't be catalogued yet
+    pub dst: String,
+}
+
+#[derive(Debug, Args)]
+pub struct RmArgs {
+    /// The manifest file or catalog name to remove
+    #[arg(required_unless_present = "tags", conflicts_with = "tags")]
+    pub manifest: Option<PathBuf>,
+    /// Remove every catalogued object with this tag instead; may be
+    /// repeated to require several
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    pub tags: Vec<Tag>,
+    /// Remove a catalogued object and its versions for good instead of
+    /// moving it to the trash
+    #[arg(long)]
+    pub purge: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TrashArgs {
+    #[command(subcommand)]
+    pub command: TrashCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TrashCommand {
+    /// List the removed objects still in the trash
+    List,
+    /// Bring a removed object back into the catalog
+    Restore {
+        /// The name of the object
+        name: String,
+    },
+    /// Remove everything in the trash for good
+    Empty,
+}
+
+#[derive(Debug, Args)]
+pub struct CatalogArgs {
+    #[command(subcommand)]
+    pub command: CatalogCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CatalogCommand {
+    /// Write the catalog and its manifests to an archive sealed with a
+    /// passphrase
+    Export {
+        /// The archive to write
+        path: PathBuf,
+    },
+    /// Replace the catalog with one exported on another machine
+    Import {
+        /// The archive to read
+        path: PathBuf,
+        /// Replace a catalog that already holds objects; it is kept as
+        /// `<catalog>.bak`
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check the slab references the catalog counts against its manifests
+    Fsck {
+        /// Rewrite the references that don't match
+        #[arg(long)]
+        repair: bool,
+        /// Open sealed manifests with a passphrase to check them too
+        #[arg(long)]
+        passphrase: bool,
+    },
+}
+
+#[cfg(feature = "fuse")]
+#[derive(Debug, Args)]
+pub struct MountArgs {
+    /// The directory to mount the catalog on
+    pub mountpoint: PathBuf,
+    /// The size of the block cache in MiB
+    #[arg(long, default_value_t = 256)]
+    pub cache_size: u64,
+}
+
+#[cfg(feature = "tui")]
+#[derive(Debug, Args)]
+pub struct BrowseArgs {
+    /// Only list objects whose names start with this prefix
+    #[arg(default_value = "")]
+    pub prefix: String,
+    /// The directory downloads are saved to
+    #[arg(short, long, default_value = ".")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct GcArgs {
+    /// Where to look for the checkpoints and manifest files of uploads
+    #[arg(default_value = ".")]
+    pub paths: Vec<PathBuf>,
+    /// Open sealed manifests with a passphrase, so their slabs count as
+    /// referenced
+    #[arg(long)]
+    pub passphrase: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct LifecycleArgs {
+    #[command(subcommand)]
+    pub command: LifecycleCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LifecycleCommand {
+    /// Report what each rule covers and would do on the next maintain run
+    Status,
+}
+
+#[derive(Debug, Args)]
+pub struct MaintainArgs {
+    /// Run at the times of this crontab-style schedule, in UTC, such as
+    /// "0 3 * * *", until interrupted
+    #[arg(
+        long,
+        value_name = "CRON",
+        required_unless_present = "once",
+        conflicts_with = "once"
+    )]
+    pub schedule: Option<Schedule>,
+    /// Run once and exit, for running from cron or a systemd timer
+    #[arg(long)]
+    pub once: bool,
+    /// Start no more objects once a run has taken this long, such as 2h
+    #[arg(long, value_name = "DURATION", value_parser = share::parse_lifetime)]
+    pub max_time: Option<Duration>,
+    /// Recover at most this much slab data in a run, such as 500G; combine
+    /// with --bwlimit-down to cap the rate
+    #[arg(long, value_name = "SIZE", value_parser = throttle::parse_size)]
+    pub max_bytes: Option<u64>,
+    /// Where to look for the checkpoints of abandoned uploads
+    #[arg(long = "gc-path", value_name = "PATH")]
+    pub gc_paths: Vec<PathBuf>,
+    /// Open sealed objects with a passphrase instead of skipping them
+    #[arg(long)]
+    pub passphrase: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// The most hosts to reach through the catalogued slabs
+    #[arg(long, default_value_t = 10)]
+    pub hosts: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// The amount of random data to upload and download, such as 256M or
+    /// 1GiB
+    #[arg(long, value_name = "SIZE", value_parser = throttle::parse_size, default_value = "256M")]
+    pub size: u64,
+    #[command(flatten)]
+    pub redundancy: RedundancyArgs,
+    /// The number of slabs transferred at once
+    #[arg(short = 'j', long, default_value_t = 4)]
+    pub jobs: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct HostsArgs {
+    #[command(subcommand)]
+    pub command: HostsCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HostsCommand {
+    /// List the transfers, failure rate, latency and throughput recorded
+    /// for each host, slowest first
+    Stats,
+}
+
+#[derive(Debug, Args)]
+pub struct SessionArgs {
+    #[command(subcommand)]
+    pub command: SessionCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SessionCommand {
+    /// List the journalled sessions, oldest first
+    List,
+    /// Show every transfer of a session and how each host it used fared,
+    /// slowest and most failing first
+    Show {
+        /// The session's id, or `last` for the most recent
+        id: String,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct KeyArgs {
+    #[command(subcommand)]
+    pub command: KeyCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeyCommand {
+    /// List the app identities in the config and whether they were
+    /// approved
+    List,
+    /// Create a recipient key, which opens the sealed manifests granted to
+    /// its recipient, and print the recipient
+    Recipient(KeyRecipientArgs),
+    /// Store a new random key in the keychain
+    #[cfg(feature = "keyring")]
+    Generate(KeyWriteArgs),
+    /// Store a key read from stdin as 64 hex characters in the keychain
+    #[cfg(feature = "keyring")]
+    Import(KeyImportArgs),
+    /// Print a key stored in the keychain as hex
+    #[cfg(feature = "keyring")]
+    Export(KeyTargetArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct KeyRecipientArgs {
+    /// Write the key to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Print the recipient of this existing key instead
+    #[arg(long, value_name = "PATH", conflicts_with = "output")]
+    pub show: Option<PathBuf>,
+}
+
+/// Without --object the key is the app key of the selected profile.
+#[cfg(feature = "keyring")]
+#[derive(Debug, Args)]
+pub struct KeyTargetArgs {
+    /// Manage the object master key with this name instead
+    #[arg(long, value_name = "NAME")]
+    pub object: Option<String>,
+}
+
+#[cfg(feature = "keyring")]
+#[derive(Debug, Args)]
+pub struct KeyWriteArgs {
+    #[command(flatten)]
+    pub target: KeyTargetArgs,
+    /// Replace a key already stored; whatever it protects is lost
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[cfg(feature = "keyring")]
+#[derive(Debug, Args)]
+pub struct KeyImportArgs {
+    #[command(flatten)]
+    pub key: KeyWriteArgs,
+    /// Read an app secret instead and store the key derived from it, to
+    /// move a profile's app_secret into the keychain
+    #[arg(long)]
+    pub secret: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct BucketArgs {
+    #[command(subcommand)]
+    pub command: BucketCommand,
+}
+
+/// Bucket keys are derived from a passphrase, read from INDEXD_PASSPHRASE or
+/// prompted for.
+#[derive(Debug, Subcommand)]
+pub enum BucketCommand {
+    /// Create an empty bucket
+    Create(BucketNameArgs),
+    /// List the known buckets
+    List,
+    /// Upload a file as an object, replacing any object with the same key
+    Put(BucketPutArgs),
+    /// Download an object
+    Get(BucketGetArgs),
+    /// List the objects in a bucket
+    Ls(BucketLsArgs),
+    /// Remove an object from a bucket
+    Rm(BucketKeyArgs),
+    /// Write a bucket's reference to a file so another machine can import it
+    Export(BucketExportArgs),
+    /// Import a bucket reference exported on another machine
+    Import(BucketImportArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct BucketNameArgs {
+    pub bucket: String,
+}
+
+#[derive(Debug, Args)]
+pub struct BucketKeyArgs {
+    pub bucket: String,
+    pub key: String,
+}
+
+#[derive(Debug, Args)]
+pub struct BucketPutArgs {
+    pub bucket: String,
+    pub key: String,
+    /// The file to upload, or `-` to read from stdin
+    pub input: PathBuf,
+    #[command(flatten)]
+    pub redundancy: RedundancyArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct BucketGetArgs {
+    pub bucket: String,
+    pub key: String,
+    /// Where to write the object, or `-` for stdout
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct BucketLsArgs {
+    pub bucket: String,
+    /// Only list objects whose keys start with this prefix
+    #[arg(default_value = "")]
+    pub prefix: String,
+}
+
+#[derive(Debug, Args)]
+pub struct BucketExportArgs {
+    pub bucket: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct BucketImportArgs {
+    pub path: PathBuf,
+    /// Replace a newer generation of the bucket already in the catalog
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Serve the buckets over WebDAV
+    #[arg(long, conflicts_with = "restic")]
+    pub webdav: bool,
+    /// Serve the buckets as restic repositories over restic's REST protocol
+    #[arg(long)]
+    pub restic: bool,
+    /// Refuse to delete anything but locks from the restic repositories
+    #[arg(long, requires = "restic")]
+    pub append_only: bool,
+    /// Serve the catalogued objects read-only over HTTP on this address,
+    /// such as :8080
+    #[arg(long, value_name = "ADDR", value_parser = net::parse_listen, conflicts_with_all = ["webdav", "restic", "listen"])]
+    pub http: Option<SocketAddr>,
+    /// The address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+    /// Serve Prometheus metrics on this address at /metrics
+    #[arg(long, value_name = "ADDR")]
+    pub metrics: Option<SocketAddr>,
+    /// The user name clients must authenticate with
+    #[arg(long, env = "INDEXD_SERVE_USER", requires = "password")]
+    pub user: Option<String>,
+    /// The password clients must authenticate with
+    #[arg(
+        long,
+        env = "INDEXD_SERVE_PASSWORD",
+        hide_env_values = true,
+        requires = "user"
+    )]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct DaemonArgs {
+    /// The address to listen on, which must be a loopback address unless
+    /// there is a token
+    #[arg(long, default_value = "127.0.0.1:7070")]
+    pub listen: SocketAddr,
+    /// The bearer token every request must carry
+    #[arg(long, env = "INDEXD_DAEMON_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    /// The directory queued files must be under, defaults to the current
+    /// directory
+    #[arg(long)]
+    pub root: Option<PathBuf>,
+    /// Also serve the API over gRPC on this address, which must be a
+    /// loopback address unless there is a token
+    #[cfg(feature = "grpc")]
+    #[arg(long, value_name = "ADDR")]
+    pub grpc: Option<SocketAddr>,
+    /// The number of files uploaded at once, defaults to the profile's
+    /// concurrency
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+    /// Where queued uploads keep their checkpoints so paused uploads can
+    /// resume, defaults to $XDG_STATE_HOME/indexd-utils/checkpoints; it
+    /// must be private to this user
+    #[arg(long)]
+    pub checkpoint_dir: Option<PathBuf>,
+    /// Serve Prometheus metrics on this address at /metrics
+    #[arg(long, value_name = "ADDR")]
+    pub metrics: Option<SocketAddr>,
+    #[command(flatten)]
+    pub redundancy: RedundancyArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct QueueArgs {
+    /// The address of the daemon's HTTP API
+    #[arg(long, default_value = "127.0.0.1:7070", env = "INDEXD_DAEMON")]
+    pub daemon: SocketAddr,
+    /// The daemon's token, if it has one
+    #[arg(long, env = "INDEXD_DAEMON_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    #[command(subcommand)]
+    pub command: QueueCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QueueCommand {
+    /// Queue the upload of a file the daemon can read
+    Add(QueueAddArgs),
+    /// List the queued uploads
+    List,
+    /// Stop an upload, keeping what it uploaded so far
+    Pause(QueueJobArgs),
+    /// Continue a paused or failed upload
+    Resume(QueueJobArgs),
+    /// Stop an upload for good
+    Cancel(QueueJobArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct QueueAddArgs {
+    /// The file to upload; relative paths are resolved before sending them
+    pub path: PathBuf,
+    /// The name to catalog the file under, defaults to its file name
+    #[arg(short, long)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct QueueJobArgs {
+    /// The id of the upload, as listed by `queue list`
+    pub id: u64,
+}
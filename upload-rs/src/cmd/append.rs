@@ -0,0 +1,65 @@
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::manifest::AnyManifest;
+use indexd_utils::upload;
+use log::info;
+use serde_json::json;
+use tokio::fs::File;
+use tokio::io::{self, AsyncRead};
+
+use super::{open_manifest, progress_bar, store_manifest};
+use crate::cli::AppendArgs;
+
+pub async fn run(settings: &Settings, args: AppendArgs) -> Result<()> {
+    let (manifest, sealing, location) = open_manifest(settings, &args.manifest).await?;
+    let AnyManifest::File(manifest) = manifest else {
+        return Err(Error::Usage(
+            "only a file manifest can be appended to".into(),
+        ));
+    };
+    let (reader, size): (Box<dyn AsyncRead + Unpin + Send>, u64) = if args.input.as_os_str() == "-"
+    {
+        (Box::new(io::stdin()), manifest.size)
+    } else {
+        let file = File::open(&args.input).await?;
+        let size = file.metadata().await?.len();
+        (Box::new(file), size)
+    };
+    if size < manifest.size {
+        return Err(Error::Usage(format!(
+            "{} is smaller than the uploaded {} bytes",
+            args.input.display(),
+            manifest.size
+        )));
+    }
+
+    // upload::append codes the new slabs with the manifest's redundancy
+    let mut options = settings.upload_options();
+    if let Some(jobs) = args.jobs {
+        options = options.with_max_inflight_shards(jobs);
+    }
+    // a sealed manifest's keys come from its passphrase, as at upload
+    let master_key = sealing
+        .as_ref()
+        .map_or_else(rand::random, |sealing| sealing.key);
+
+    let sdk = client::connect(settings).await?;
+    let (progress, bar) = progress_bar(size, 0);
+    let result = upload::append(&sdk, &manifest, reader, &master_key, options, progress).await;
+    let _ = bar.await;
+    let appended = result?;
+
+    let result = json!({
+        "manifest": location.to_string(),
+        "appended": appended.size - manifest.size,
+        "size": appended.size,
+        "slabs": appended.slabs.len() - manifest.slabs.len(),
+    });
+    let (appended_bytes, size) = (appended.size - manifest.size, appended.size);
+    store_manifest(AnyManifest::File(appended), &location, sealing.as_ref()).await?;
+    info!("appended {appended_bytes} bytes, manifest saved to {location}");
+    settings.output.print(&result, || {
+        println!("{location}: {appended_bytes} bytes appended, {size} bytes in all");
+    })
+}
@@ -0,0 +1,101 @@
+use std::time::Instant;
+
+use indexd_utils::archive;
+use indexd_utils::client;
+use indexd_utils::compression::Compression;
+use indexd_utils::config::Settings;
+use indexd_utils::directory::{self, Links};
+use indexd_utils::error::{Error, Result};
+use indexd_utils::manifest::{AnyManifest, StoredManifest};
+use indexd_utils::upload::{self, UploadOptions};
+use log::info;
+use serde_json::json;
+
+use super::{filter, progress_bar, redundancy, with_suffix};
+use crate::cli::ArchiveArgs;
+
+/// Uploads a directory as a single tar object, streamed straight from the
+/// directory without writing the archive to disk.
+pub async fn run(settings: &Settings, args: ArchiveArgs) -> Result<()> {
+    let name = match args.name {
+        Some(name) => name,
+        None => args
+            .dir
+            .file_name()
+            .map(|name| format!("{}.tar", name.to_string_lossy()))
+            .ok_or_else(|| {
+                Error::Usage("--name is required when the directory has no name".into())
+            })?,
+    };
+    let manifest_path = args
+        .manifest
+        .unwrap_or_else(|| with_suffix(&args.dir, ".tar.manifest.json"));
+
+    let filter = filter(&args.filter).await?;
+    let tree = directory::walk_tree(&args.dir, &filter, Links::Preserve).await?;
+    let mut total = 0;
+    for rel in &tree.files {
+        total += tokio::fs::metadata(args.dir.join(rel)).await?.len();
+    }
+    let mut paths = tree.files;
+    paths.extend(tree.links);
+    paths.sort();
+    info!(
+        "archiving {} entries from {}",
+        paths.len(),
+        args.dir.display()
+    );
+
+    let (data_shards, parity_shards) = redundancy(settings, &args.redundancy)?;
+    let mut options = UploadOptions {
+        data_shards,
+        parity_shards,
+        ..settings.upload_options()
+    };
+    if let Some(level) = args.compress {
+        options = options.with_compression(Some(Compression::zstd(level)));
+    }
+    if let Some(jobs) = args.jobs {
+        options = options.with_max_inflight_shards(jobs);
+    }
+
+    let sdk = client::connect(settings).await?;
+    let (progress, bar) = progress_bar(total, 0);
+    let start = Instant::now();
+    let (reader, tarring) = archive::tar(args.dir.clone(), paths);
+    let (uploaded, tarred) = tokio::join!(
+        upload::upload_reader(&sdk, reader, rand::random(), options, progress),
+        tarring
+    );
+    let _ = bar.await;
+    let manifest = uploaded?;
+    // a failed tar ends the stream early, so the upload is short
+    tarred.expect("tar task panicked")?;
+    info!(
+        "archive of {} bytes uploaded in {}ms",
+        manifest.size,
+        start.elapsed().as_millis()
+    );
+
+    let manifest = AnyManifest::File(manifest);
+    let stored = StoredManifest::new(manifest.clone(), None)?;
+    stored.save(&manifest_path).await?;
+    let source = args.dir.display().to_string();
+    settings
+        .catalog()?
+        .put(&name, Some(source.as_str()), &manifest, &stored)?;
+    info!("catalogued as {name}");
+    let AnyManifest::File(manifest) = manifest else {
+        unreachable!("an archive is a single file");
+    };
+    let result = json!({
+        "name": name,
+        "manifest": manifest_path,
+        "size": manifest.size,
+        "slabs": manifest.slabs.len(),
+        "elapsed_ms": start.elapsed().as_millis() as u64,
+    });
+    settings
+        .output
+        .print(&result, || println!("{}", manifest_path.display()))
+}
@@ -0,0 +1,124 @@
+use std::time::Instant;
+
+use indexd_utils::backup;
+use indexd_utils::catalog::Catalog;
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::directory;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::keys::{self, Kdf};
+use indexd_utils::manifest::{AnyManifest, DirectoryManifest, Sealing, StoredManifest};
+use indexd_utils::upload::UploadOptions;
+use log::info;
+use serde_json::json;
+
+use super::{progress_bar, redundancy};
+use crate::cli::BackupArgs;
+
+pub async fn run(settings: &Settings, args: BackupArgs) -> Result<()> {
+    let name = match args.name {
+        Some(name) => name,
+        None => args
+            .dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| {
+                Error::Usage("--name is required when the directory has no name".into())
+            })?,
+    };
+    let catalog = settings.catalog()?;
+
+    let mut sealing = None;
+    let previous = match catalog.latest_snapshot(&name)? {
+        Some(id) if !args.full => {
+            info!("comparing against snapshot {id}");
+            let (manifest, previous_sealing) = open_snapshot(&catalog, id).await?;
+            sealing = previous_sealing;
+            Some(manifest)
+        }
+        _ => None,
+    };
+    if args.passphrase && sealing.is_none() {
+        let kdf = Kdf::default();
+        let passphrase = keys::read_passphrase(true)?;
+        let key = kdf.derive(&passphrase).await?;
+        sealing = Some(Sealing::new(kdf, key));
+    }
+
+    let (data_shards, parity_shards) = redundancy(settings, &args.redundancy)?;
+    let mut options = UploadOptions {
+        data_shards,
+        parity_shards,
+        ..settings.upload_options()
+    };
+    if let Some(jobs) = args.jobs {
+        options = options.with_max_inflight_shards(jobs);
+    }
+
+    let mut total = 0;
+    for rel in directory::walk(&args.dir).await? {
+        total += tokio::fs::metadata(args.dir.join(rel)).await?.len();
+    }
+    let sdk = client::connect(settings).await?;
+    let (progress, bar) = progress_bar(total, 0);
+    let start = Instant::now();
+    let (manifest, stats) = backup::backup(
+        &sdk,
+        &catalog,
+        &args.dir,
+        previous.as_ref(),
+        options,
+        progress,
+    )
+    .await?;
+    let _ = bar.await;
+
+    let stored = StoredManifest::new(AnyManifest::Directory(manifest.clone()), sealing.as_ref())?;
+    let source = args.dir.display().to_string();
+    let id = catalog.put_snapshot(&name, &source, &manifest, &stored)?;
+    info!(
+        "snapshot {id} of {name}: {} files, {} unchanged, {} new chunks ({} bytes), {} bytes reused, in {}ms",
+        stats.files,
+        stats.unchanged_files,
+        stats.chunks.new_chunks,
+        stats.chunks.new_bytes,
+        stats.chunks.reused_bytes,
+        start.elapsed().as_millis()
+    );
+    let result = json!({
+        "snapshot": id,
+        "name": name,
+        "files": stats.files,
+        "unchanged_files": stats.unchanged_files,
+        "new_chunks": stats.chunks.new_chunks,
+        "new_bytes": stats.chunks.new_bytes,
+        "reused_bytes": stats.chunks.reused_bytes,
+        "elapsed_ms": start.elapsed().as_millis() as u64,
+    });
+    settings.output.print(&result, || println!("{id}"))
+}
+
+/// Loads a snapshot's manifest, prompting for the passphrase if it is
+/// sealed. Returns the key it was sealed with, if any.
+pub(super) async fn open_snapshot(
+    catalog: &Catalog,
+    id: i64,
+) -> Result<(DirectoryManifest, Option<Sealing>)> {
+    let stored = catalog
+        .get_snapshot(id)?
+        .ok_or_else(|| Error::NotFound(format!("snapshot {id}")))?;
+    let (manifest, sealing) = match stored {
+        StoredManifest::Plain(manifest) => (manifest, None),
+        StoredManifest::Sealed(sealed) => {
+            let passphrase = keys::read_passphrase(false)?;
+            let key = sealed.kdf.derive(&passphrase).await?;
+            (sealed.open(&key)?, Some(sealed.sealing(key)))
+        }
+    };
+    match manifest {
+        AnyManifest::Directory(manifest) => Ok((manifest, sealing)),
+        AnyManifest::File(_) => Err(Error::Manifest(format!(
+            "snapshot {id} is not a directory manifest"
+        ))),
+    }
+}
@@ -0,0 +1,55 @@
+use indexd_utils::bench;
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::redundancy::Redundancy;
+
+use super::{progress_bar, redundancy_or};
+use crate::cli::BenchArgs;
+
+pub async fn run(settings: &Settings, args: BenchArgs) -> Result<()> {
+    if args.size == 0 {
+        return Err(Error::Usage("--size must be more than 0".into()));
+    }
+    let default = Redundancy::new(settings.data_shards, settings.parity_shards)?;
+    let redundancy = redundancy_or(&args.redundancy, default)?;
+    let sdk = client::connect(settings).await?;
+
+    // the bar counts the data once up and once down
+    let (progress, bar) = progress_bar(args.size * 2, 0);
+    let result = bench::run(&sdk, args.size, redundancy, args.jobs, &progress).await;
+    drop(progress);
+    let _ = bar.await;
+    let report = result?;
+
+    let mib = |rate: f64| rate / (1 << 20) as f64;
+    settings.output.print(&report, || {
+        println!("redundancy: {redundancy}");
+        println!(
+            "encoding:   {:.1} MiB/s on one thread",
+            mib(report.encode_bytes_per_sec)
+        );
+        for (name, phase) in [("upload", &report.upload), ("download", &report.download)] {
+            let latency = phase.latency_ms;
+            println!(
+                "{:<11} {:.1} MiB/s in {}ms; per slab p50 {}ms, p90 {}ms, p99 {}ms, max {}ms",
+                format!("{name}:"),
+                mib(phase.bytes_per_sec),
+                phase.elapsed_ms,
+                latency.p50,
+                latency.p90,
+                latency.p99,
+                latency.max,
+            );
+        }
+        for host in &report.hosts {
+            println!(
+                "{}\t{:.1} MiB/s up\t{:.1} MiB/s down\t{} failed",
+                host.host,
+                mib(host.upload_bytes_per_sec),
+                mib(host.download_bytes_per_sec),
+                host.failures,
+            );
+        }
+    })
+}
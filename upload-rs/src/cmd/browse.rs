@@ -0,0 +1,347 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use indexd_utils::browse::{Browser, Object, Summary};
+use indexd_utils::catalog::Catalog;
+use indexd_utils::client::{self, Client};
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+use indexd_utils::keys;
+use indexd_utils::manifest::{AnyManifest, StoredManifest};
+use indexd_utils::progress::{Event, Progress};
+use indexd_utils::repair::{self, RepairOptions};
+use indexd_utils::verify;
+use log::{LevelFilter, warn};
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind};
+use tokio::sync::mpsc;
+
+use super::download::{DirectoryOptions, download_directory, download_file};
+use crate::cli::BrowseArgs;
+
+/// How often the screen is redrawn while nothing else happens.
+const TICK: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy)]
+enum Job {
+    Verify,
+    Repair,
+    Download,
+}
+
+/// What a finished job found.
+enum Outcome {
+    Verified(Summary),
+    Repaired {
+        manifest: AnyManifest,
+        replaced: usize,
+        unrecoverable: usize,
+    },
+    Downloaded(PathBuf),
+}
+
+enum Update {
+    Bytes {
+        id: usize,
+        bytes: u64,
+    },
+    Retrying {
+        id: usize,
+    },
+    Finished {
+        id: usize,
+        name: String,
+        outcome: Result<Outcome>,
+    },
+}
+
+/// Browses the catalog until quit. Sealed objects are opened with the
+/// passphrase up front; those it doesn't open are listed but can't be
+/// acted on.
+pub async fn run(settings: &Settings, args: BrowseArgs) -> Result<()> {
+    let catalog = settings.catalog()?;
+    let objects = load_objects(&catalog, &args.prefix).await?;
+    let sdk = client::connect(settings).await?;
+
+    // log lines would land in the middle of the screen
+    let level = log::max_level();
+    log::set_max_level(LevelFilter::Off);
+    let mut terminal = ratatui::init();
+    let result = browse(&mut terminal, Browser::new(objects), &catalog, &sdk, &args).await;
+    ratatui::restore();
+    log::set_max_level(level);
+    result
+}
+
+async fn load_objects(catalog: &Catalog, prefix: &str) -> Result<Vec<Object>> {
+    let mut objects = Vec::new();
+    let mut passphrase = None;
+    for entry in catalog.list(prefix)? {
+        let Some(stored) = catalog.get(&entry.name)? else {
+            continue;
+        };
+        let (manifest, sealing) = match stored {
+            StoredManifest::Plain(manifest) => (Some(manifest), None),
+            StoredManifest::Sealed(sealed) => {
+                if passphrase.is_none() {
+                    passphrase = Some(keys::read_passphrase(false)?);
+                }
+                let passphrase = passphrase.as_deref().unwrap_or_default();
+                let key = sealed.kdf.derive(passphrase).await?;
+                match sealed.open(&key) {
+                    Ok(manifest) => (Some(manifest), Some(sealed.sealing(key))),
+                    Err(e) => {
+                        warn!("can't open {}: {e}", entry.name);
+                        (None, None)
+                    }
+                }
+            }
+        };
+        objects.push(Object {
+            entry,
+            manifest,
+            sealing,
+            health: None,
+        });
+    }
+    Ok(objects)
+}
+
+async fn browse(
+    terminal: &mut DefaultTerminal,
+    mut browser: Browser,
+    catalog: &Catalog,
+    sdk: &Client,
+    args: &BrowseArgs,
+) -> Result<()> {
+    let (keys_tx, mut keys) = mpsc::unbounded_channel();
+    // crossterm only reads the terminal by blocking
+    tokio::task::spawn_blocking(move || {
+        while !keys_tx.is_closed() {
+            match event::poll(TICK) {
+                Ok(true) => match event::read() {
+                    Ok(TermEvent::Key(key)) if key.kind == KeyEventKind::Press => {
+                        let _ = keys_tx.send(key);
+                    }
+                    Ok(_) => {}
+                    Err(_) => break,
+                },
+                Ok(false) => {}
+                Err(_) => break,
+            }
+        }
+    });
+    let (updates_tx, mut updates) = mpsc::unbounded_channel();
+    let mut ticks = tokio::time::interval(TICK);
+
+    loop {
+        terminal.draw(|frame| browser.draw(frame))?;
+        tokio::select! {
+            key = keys.recv() => {
+                let Some(key) = key else {
+                    return Ok(());
+                };
+                let job = match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        browser.select_next();
+                        continue;
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        browser.select_previous();
+                        continue;
+                    }
+                    KeyCode::Char('v') => Job::Verify,
+                    KeyCode::Char('r') => Job::Repair,
+                    KeyCode::Char('d') => Job::Download,
+                    _ if is_interrupt(&key) => return Ok(()),
+                    _ => continue,
+                };
+                start(&mut browser, job, sdk, args, &updates_tx);
+            }
+            Some(update) = updates.recv() => apply(&mut browser, catalog, update),
+            _ = ticks.tick() => {}
+        }
+    }
+}
+
+fn is_interrupt(key: &KeyEvent) -> bool {
+    key.code == KeyCode::Char('c') && key.modifiers.contains(event::KeyModifiers::CONTROL)
+}
+
+/// Starts `job` on the selected object in the background.
+fn start(
+    browser: &mut Browser,
+    job: Job,
+    sdk: &Client,
+    args: &BrowseArgs,
+    updates: &mpsc::UnboundedSender<Update>,
+) {
+    let Some(index) = browser.selected() else {
+        return;
+    };
+    let object = &browser.objects[index];
+    let name = object.entry.name.clone();
+    let Some(manifest) = object.manifest.clone() else {
+        browser.status = format!("{name} didn't open with the passphrase");
+        return;
+    };
+    let files = match &manifest {
+        AnyManifest::File(manifest) => vec![manifest],
+        AnyManifest::Directory(dir) => dir.files.iter().map(|f| &f.manifest).collect(),
+    };
+    let (verb, total) = match job {
+        Job::Verify => ("verify", files.iter().map(|m| m.stored_size()).sum()),
+        Job::Repair => ("repair", files.iter().map(|m| m.stored_size()).sum()),
+        Job::Download => ("download", files.iter().map(|m| m.size).sum()),
+    };
+    let output = args.output.join(name.rsplit('/').next().unwrap_or(&name));
+    if matches!(job, Job::Download) && output.exists() {
+        browser.status = format!("{} already exists", output.display());
+        return;
+    }
+    let id = browser.start(format!("{verb} {name}"), total);
+    browser.status.clear();
+
+    let (progress, mut events) = Progress::channel();
+    let forward = updates.clone();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let update = match event {
+                Event::BytesTransferred { bytes } => Update::Bytes { id, bytes },
+                Event::Retrying { .. } => Update::Retrying { id },
+                _ => continue,
+            };
+            let _ = forward.send(update);
+        }
+    });
+    let (sdk, updates) = (sdk.clone(), updates.clone());
+    tokio::spawn(async move {
+        let outcome = match job {
+            Job::Verify => verify_object(&sdk, &manifest, &progress).await,
+            Job::Repair => repair_object(&sdk, manifest, &progress).await,
+            Job::Download => download_object(&sdk, &manifest, output, progress).await,
+        };
+        let _ = updates.send(Update::Finished { id, name, outcome });
+    });
+}
+
+async fn verify_object(
+    sdk: &Client,
+    manifest: &AnyManifest,
+    progress: &Progress,
+) -> Result<Outcome> {
+    let mut reports = Vec::new();
+    match manifest {
+        AnyManifest::File(manifest) => reports.push(verify::verify(sdk, manifest, progress).await?),
+        AnyManifest::Directory(dir) => {
+            for entry in &dir.files {
+                reports.push(verify::verify(sdk, &entry.manifest, progress).await?);
+            }
+        }
+    }
+    Ok(Outcome::Verified(Summary::new(&reports)))
+}
+
+async fn repair_object(
+    sdk: &Client,
+    mut manifest: AnyManifest,
+    progress: &Progress,
+) -> Result<Outcome> {
+    let files = match &mut manifest {
+        AnyManifest::File(manifest) => vec![manifest],
+        AnyManifest::Directory(dir) => dir.files.iter_mut().map(|f| &mut f.manifest).collect(),
+    };
+    let (mut replaced, mut unrecoverable) = (0, 0);
+    for file in files {
+        let repair = repair::repair(sdk, file, RepairOptions::new(file), progress).await?;
+        replaced += repair.replaced();
+        unrecoverable += repair.unrecoverable();
+        *file = repair.manifest;
+    }
+    Ok(Outcome::Repaired {
+        manifest,
+        replaced,
+        unrecoverable,
+    })
+}
+
+async fn download_object(
+    sdk: &Client,
+    manifest: &AnyManifest,
+    output: PathBuf,
+    progress: Progress,
+) -> Result<Outcome> {
+    match manifest {
+        AnyManifest::File(manifest) => {
+            download_file(sdk, manifest, &output, false, 1, true, progress).await?
+        }
+        AnyManifest::Directory(dir) => {
+            let options = DirectoryOptions {
+                concurrency: 1,
+                resume: false,
+                verify: true,
+                preserve: true,
+                in_order: false,
+            };
+            download_directory(sdk, dir, &output, &options, progress).await?
+        }
+    }
+    Ok(Outcome::Downloaded(output))
+}
+
+fn apply(browser: &mut Browser, catalog: &Catalog, update: Update) {
+    match update {
+        Update::Bytes { id, bytes } => browser.advance(id, bytes),
+        Update::Retrying { id } => browser.retrying(id),
+        Update::Finished { id, name, outcome } => {
+            let result = outcome
+                .and_then(|outcome| finished(browser, catalog, &name, outcome))
+                .map_err(|e| e.to_string());
+            browser.finish(id, result);
+        }
+    }
+}
+
+/// Records what a job found and returns the message to show for it.
+fn finished(
+    browser: &mut Browser,
+    catalog: &Catalog,
+    name: &str,
+    outcome: Outcome,
+) -> Result<String> {
+    let Some(object) = browser.object_mut(name) else {
+        return Ok("done".into());
+    };
+    match outcome {
+        Outcome::Verified(summary) => {
+            object.health = Some(summary);
+            Ok(if summary.unrecoverable > 0 {
+                format!("{} slabs unrecoverable", summary.unrecoverable)
+            } else if summary.degraded > 0 {
+                format!("{} slabs degraded", summary.degraded)
+            } else if !summary.checksum_ok {
+                "checksum mismatch".into()
+            } else {
+                "healthy".into()
+            })
+        }
+        Outcome::Repaired {
+            manifest,
+            replaced,
+            unrecoverable,
+        } => {
+            if replaced > 0 {
+                let stored = StoredManifest::new(manifest.clone(), object.sealing.as_ref())?;
+                catalog.replace(name, None, &manifest, &stored)?;
+                object.manifest = Some(manifest);
+                // the slabs verified before were replaced
+                object.health = None;
+            }
+            Ok(format!(
+                "{replaced} replaced, {unrecoverable} unrecoverable"
+            ))
+        }
+        Outcome::Downloaded(path) => Ok(format!("saved to {}", path.display())),
+    }
+}
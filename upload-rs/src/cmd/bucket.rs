@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use indexd_utils::bucket::{self, Bucket, BucketRef};
+use indexd_utils::catalog::Catalog;
+use indexd_utils::client::{self, Client};
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::keys::{self, Kdf};
+use indexd_utils::upload::UploadOptions;
+use log::{info, warn};
+use serde_json::json;
+use tokio::fs::File;
+use tokio::io::{self, AsyncWriteExt};
+
+use super::redundancy;
+use crate::cli::{BucketArgs, BucketCommand, RedundancyArgs};
+
+pub async fn run(settings: &Settings, args: BucketArgs) -> Result<()> {
+    let catalog = settings.catalog()?;
+    let output = settings.output;
+    match args.command {
+        BucketCommand::Create(args) => {
+            if catalog.get_bucket(&args.bucket)?.is_some() {
+                return Err(Error::Usage(format!(
+                    "bucket {} already exists",
+                    args.bucket
+                )));
+            }
+            let kdf = Kdf::default();
+            let passphrase = keys::read_passphrase(true)?;
+            let key = kdf.derive(&passphrase).await?;
+            let sdk = client::connect(settings).await?;
+            let options = upload_options(settings, &RedundancyArgs::default())?;
+            let mut bucket = Bucket::create(&args.bucket, kdf, key, options);
+            commit(&sdk, &catalog, &mut bucket).await?;
+            info!("created bucket {}", args.bucket);
+            output.print(&json!({ "bucket": args.bucket }), || {})?;
+        }
+        BucketCommand::List => {
+            for name in catalog.list_buckets()? {
+                output.print(&json!({ "bucket": name }), || println!("{name}"))?;
+            }
+        }
+        BucketCommand::Put(args) => {
+            let sdk = client::connect(settings).await?;
+            let options = upload_options(settings, &args.redundancy)?;
+            let mut bucket = open(&sdk, &catalog, &args.bucket, options).await?;
+            let object = if args.input == Path::new("-") {
+                bucket.put(&sdk, &args.key, io::stdin()).await?
+            } else {
+                bucket
+                    .put(&sdk, &args.key, File::open(&args.input).await?)
+                    .await?
+            };
+            info!(
+                "uploaded {} bytes to {}/{}",
+                object.manifest.size, args.bucket, args.key
+            );
+            commit(&sdk, &catalog, &mut bucket).await?;
+            let result = json!({
+                "bucket": args.bucket,
+                "key": args.key,
+                "size": object.manifest.size,
+            });
+            output.print(&result, || {})?;
+        }
+        BucketCommand::Get(args) => {
+            let sdk = client::connect(settings).await?;
+            let options = upload_options(settings, &RedundancyArgs::default())?;
+            let bucket = open(&sdk, &catalog, &args.bucket, options).await?;
+            if args.output == Path::new("-") {
+                let mut stdout = io::stdout();
+                bucket.get(&sdk, &args.key, &mut stdout).await?;
+                stdout.flush().await?;
+            } else {
+                let mut file = File::create(&args.output).await?;
+                bucket.get(&sdk, &args.key, &mut file).await?;
+                file.flush().await?;
+                let result = json!({
+                    "bucket": args.bucket,
+                    "key": args.key,
+                    "output": args.output,
+                });
+                output.print(&result, || {})?;
+            }
+        }
+        BucketCommand::Ls(args) => {
+            let sdk = client::connect(settings).await?;
+            let options = upload_options(settings, &RedundancyArgs::default())?;
+            let bucket = open(&sdk, &catalog, &args.bucket, options).await?;
+            for (key, object) in bucket.list(&args.prefix) {
+                let size = object.manifest.size;
+                output.print(&json!({ "key": key, "size": size }), || {
+                    println!("{key}\t{size}")
+                })?;
+            }
+        }
+        BucketCommand::Rm(args) => {
+            let sdk = client::connect(settings).await?;
+            let options = upload_options(settings, &RedundancyArgs::default())?;
+            let mut bucket = open(&sdk, &catalog, &args.bucket, options).await?;
+            let object = bucket.delete(&args.key)?;
+            commit(&sdk, &catalog, &mut bucket).await?;
+            warn!(
+                "{} slabs are still stored; they are not released by rm",
+                object.manifest.slabs.len()
+            );
+            let result = json!({ "bucket": args.bucket, "removed": args.key });
+            output.print(&result, || {})?;
+        }
+        BucketCommand::Export(args) => {
+            let reference = catalog
+                .get_bucket(&args.bucket)?
+                .ok_or_else(|| Error::NotFound(format!("bucket {}", args.bucket)))?;
+            reference.save(&args.path).await?;
+            info!(
+                "exported generation {} of {} to {}",
+                reference.generation,
+                args.bucket,
+                args.path.display()
+            );
+            let result = json!({
+                "bucket": args.bucket,
+                "generation": reference.generation,
+                "path": args.path,
+            });
+            output.print(&result, || {})?;
+        }
+        BucketCommand::Import(args) => {
+            let reference = BucketRef::load(&args.path).await?;
+            let existing = catalog
+                .get_bucket(&reference.name)?
+                .map_or(0, |existing| existing.generation);
+            if existing > reference.generation && !args.force {
+                return Err(Error::Usage(format!(
+                    "the catalog has a newer generation of {} ({existing} > {}); pass --force to replace it",
+                    reference.name, reference.generation
+                )));
+            }
+            catalog.put_bucket(&reference)?;
+            info!(
+                "imported generation {} of {}",
+                reference.generation, reference.name
+            );
+            let result = json!({
+                "bucket": reference.name,
+                "generation": reference.generation,
+            });
+            output.print(&result, || {})?;
+        }
+    }
+    Ok(())
+}
+
+/// Opens a catalogued bucket, prompting for its passphrase.
+async fn open(
+    sdk: &Client,
+    catalog: &Catalog,
+    name: &str,
+    options: UploadOptions,
+) -> Result<Bucket> {
+    let reference = catalog
+        .get_bucket(name)?
+        .ok_or_else(|| Error::NotFound(format!("bucket {name}")))?;
+    let passphrase = keys::read_passphrase(false)?;
+    let key = reference.kdf.derive(&passphrase).await?;
+    Bucket::open(sdk, reference, key, options).await
+}
+
+/// Commits `bucket` and records its new reference, unless the catalog's
+/// reference moved on since the bucket was opened.
+async fn commit(sdk: &Client, catalog: &Catalog, bucket: &mut Bucket) -> Result<()> {
+    let name = bucket.reference().name.clone();
+    // checked before uploading too, so a stale bucket costs no upload
+    if !catalog.bucket_is_current(&name, bucket.reference().generation)?
+        || !catalog.swap_bucket(bucket.commit(sdk).await?)?
+    {
+        return Err(bucket::stale(&name));
+    }
+    Ok(())
+}
+
+fn upload_options(settings: &Settings, args: &RedundancyArgs) -> Result<UploadOptions> {
+    let (data_shards, parity_shards) = redundancy(settings, args)?;
+    Ok(UploadOptions {
+        data_shards,
+        parity_shards,
+        ..settings.upload_options()
+    })
+}
@@ -0,0 +1,83 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::export;
+use indexd_utils::fsck;
+use indexd_utils::keys;
+use log::{info, warn};
+
+use crate::cli::{CatalogArgs, CatalogCommand};
+
+pub async fn run(settings: &Settings, args: CatalogArgs) -> Result<()> {
+    match args.command {
+        CatalogCommand::Export { path } => {
+            let catalog = settings.catalog()?;
+            let passphrase = keys::read_passphrase(true)?;
+            let summary = export::export(&catalog, &path, &passphrase).await?;
+            info!(
+                "exported {} objects to {} ({} bytes)",
+                summary.objects,
+                path.display(),
+                summary.bytes
+            );
+            settings.output.print(&summary, || {})
+        }
+        CatalogCommand::Import { path, force } => {
+            let dest = settings.catalog_path()?;
+            // the current catalog is dropped before it is replaced
+            let objects = settings.catalog()?.list("")?.len();
+            if objects > 0 && !force {
+                return Err(Error::Usage(format!(
+                    "the catalog at {} already holds {objects} objects; pass --force to replace it",
+                    dest.display()
+                )));
+            }
+            let passphrase = keys::read_passphrase(false)?;
+            let summary = export::import(&path, &dest, &passphrase).await?;
+            if objects > 0 {
+                info!("the previous catalog is kept as {}.bak", dest.display());
+            }
+            info!("imported {} objects", summary.objects);
+            settings.output.print(&summary, || {})
+        }
+        CatalogCommand::Fsck { repair, passphrase } => {
+            let catalog = settings.catalog()?;
+            let passphrase = if passphrase {
+                Some(keys::read_passphrase(false)?)
+            } else {
+                None
+            };
+            let report = fsck::fsck(&catalog, passphrase.as_deref(), repair).await?;
+            if report.unreadable > 0 {
+                warn!(
+                    "{} sealed manifests were skipped; pass --passphrase to check them",
+                    report.unreadable
+                );
+            }
+            settings.output.print(&report, || {
+                for mismatch in &report.mismatches {
+                    println!(
+                        "{}: {} slabs not counted, {} counted too many",
+                        mismatch.owner, mismatch.missing, mismatch.extra
+                    );
+                }
+                println!(
+                    "{} checked, {} mismatched, {} dangling references",
+                    report.checked,
+                    report.mismatches.len(),
+                    report.dangling
+                );
+            })?;
+            if report.is_consistent() || report.repaired {
+                if !report.is_consistent() {
+                    info!("repaired the catalog's slab references");
+                }
+                return Ok(());
+            }
+            Err(Error::Integrity(
+                "the catalog's slab references don't match its manifests; run catalog fsck \
+                 --repair"
+                    .into(),
+            ))
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use log::info;
+use serde_json::json;
+
+use crate::cli::CpArgs;
+
+/// Catalogs an object under a second name. The copy's manifest is the
+/// original's, so nothing is uploaded and both refer to the same slabs.
+pub fn run(settings: &Settings, args: CpArgs) -> Result<()> {
+    if args.src == args.dst {
+        return Err(Error::Usage(format!(
+            "{} can't be copied onto itself",
+            args.src
+        )));
+    }
+    let catalog = settings.catalog()?;
+    if !catalog.copy(&args.src, &args.dst)? {
+        return Err(Error::Manifest(format!(
+            "{} is not in the catalog",
+            args.src
+        )));
+    }
+    info!("copied {} to {}", args.src, args.dst);
+    settings
+        .output
+        .print(&json!({ "copied": args.src, "to": args.dst }), || {})
+}
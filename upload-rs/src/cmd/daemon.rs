@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::daemon::Daemon;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::metrics;
+use indexd_utils::upload::UploadOptions;
+use indexd_utils::uploader::{self, Uploader};
+use indexd_utils::webhook::Webhooks;
+use log::info;
+use tokio::net::TcpListener;
+
+use super::redundancy;
+use crate::cli::DaemonArgs;
+
+/// Serves the control API until interrupted.
+pub async fn run(settings: &Settings, args: DaemonArgs) -> Result<()> {
+    let (data_shards, parity_shards) = redundancy(settings, &args.redundancy)?;
+    let options = UploadOptions {
+        data_shards,
+        parity_shards,
+        ..settings.upload_options()
+    };
+    let concurrency = args.concurrency.unwrap_or(settings.concurrency);
+    let checkpoints = args
+        .checkpoint_dir
+        .or_else(uploader::default_checkpoint_dir)
+        .ok_or_else(|| Error::Config("no checkpoint directory; set --checkpoint-dir".into()))?;
+    let catalog = settings.catalog()?;
+    let sdk = client::connect(settings).await?;
+    let uploader = Uploader::new(sdk, options, concurrency, checkpoints);
+    let webhooks = Webhooks::new(settings.webhooks.clone(), &settings.network)?;
+    let root = match args.root {
+        Some(root) => root,
+        None => std::env::current_dir()?,
+    };
+    let daemon = Daemon::new(uploader, catalog, settings.budget, webhooks)
+        .with_thresholds(settings.thresholds.clone())
+        .with_token(args.token)
+        .with_root(root)
+        .await?;
+    daemon.check_listen(args.listen)?;
+
+    if let Some(addr) = args.metrics {
+        metrics::serve(addr).await?;
+    }
+    let daemon = Arc::new(daemon);
+    // both servers are bound before either runs, so the daemon doesn't
+    // start without one, and it stops if either fails
+    #[cfg(feature = "grpc")]
+    let grpc = match args.grpc {
+        Some(addr) => Some(indexd_utils::grpc::bind(daemon.clone(), addr).await?),
+        None => None,
+    };
+    let listener = TcpListener::bind(args.listen).await?;
+    let addr = listener.local_addr()?;
+    info!("listening on {addr}");
+    let router = daemon.router(addr)?;
+    let http = async { axum::serve(listener, router).await.map_err(Error::from) };
+    #[cfg(feature = "grpc")]
+    if let Some(grpc) = grpc {
+        tokio::try_join!(http, grpc)?;
+        return Ok(());
+    }
+    http.await
+}
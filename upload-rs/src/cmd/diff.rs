@@ -0,0 +1,50 @@
+use std::path::Path;
+
+use indexd_utils::config::Settings;
+use indexd_utils::diff::{self, Change};
+use indexd_utils::error::{Error, Result};
+use indexd_utils::manifest::{AnyManifest, DirectoryManifest};
+
+use super::backup::open_snapshot;
+use super::load_manifest;
+use crate::cli::DiffArgs;
+
+pub async fn run(settings: &Settings, args: DiffArgs) -> Result<()> {
+    let old = resolve(settings, &args.old).await?;
+    let diff = match (&args.local, &args.new) {
+        (Some(dir), _) => diff::diff_local(dir, &old).await?,
+        (None, Some(new)) => diff::diff(&old, &resolve(settings, new).await?),
+        (None, None) => unreachable!("clap requires new or --local"),
+    };
+    settings.output.print(&diff, || {
+        for change in &diff.changes {
+            println!("{change}");
+        }
+        println!(
+            "{} added, {} removed, {} modified, {} unchanged; {:+} bytes, {} to transfer",
+            diff.count(Change::Added),
+            diff.count(Change::Removed),
+            diff.count(Change::Modified),
+            diff.unchanged,
+            diff.delta,
+            diff.transfer
+        );
+    })
+}
+
+/// Loads the snapshot with the id `target`, or the directory manifest or
+/// catalogued object it names.
+async fn resolve(settings: &Settings, target: &str) -> Result<DirectoryManifest> {
+    if let Ok(id) = target.parse::<i64>() {
+        let catalog = settings.catalog()?;
+        if catalog.get_snapshot(id)?.is_some() {
+            return Ok(open_snapshot(&catalog, id).await?.0);
+        }
+    }
+    match load_manifest(settings, Path::new(target)).await? {
+        AnyManifest::Directory(manifest) => Ok(manifest),
+        AnyManifest::File(_) => Err(Error::Usage(format!(
+            "{target} is a file manifest; diff compares directories"
+        ))),
+    }
+}
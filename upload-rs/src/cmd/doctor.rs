@@ -0,0 +1,31 @@
+use indexd_utils::config::Settings;
+use indexd_utils::doctor::{self, Outcome};
+use indexd_utils::error::{Error, Result};
+
+use crate::cli::DoctorArgs;
+
+pub async fn run(settings: &Settings, args: DoctorArgs) -> Result<()> {
+    let checks = doctor::diagnose(settings, args.hosts).await;
+    settings.output.print(&checks, || {
+        for check in &checks {
+            let outcome = match check.outcome {
+                Outcome::Ok => "ok",
+                Outcome::Warn => "warn",
+                Outcome::Fail => "FAIL",
+                Outcome::Skipped => "skip",
+            };
+            println!("{outcome:<5} {:<10} {}", check.name, check.detail);
+            if let Some(hint) = &check.hint {
+                println!("{:<16} {hint}", "");
+            }
+        }
+    })?;
+    let failed = checks
+        .iter()
+        .filter(|check| check.outcome == Outcome::Fail)
+        .count();
+    if failed > 0 {
+        return Err(Error::Config(format!("{failed} checks failed")));
+    }
+    Ok(())
+}
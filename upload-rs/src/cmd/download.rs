@@ -0,0 +1,324 @@
+use std::path::Path;
+use std::time::Instant;
+
+use futures::{StreamExt, TryStreamExt, stream};
+use indexd_utils::checksum::{self, ChecksumWriter};
+use indexd_utils::client::{self, Client};
+use indexd_utils::config::Settings;
+use indexd_utils::directory;
+use indexd_utils::download;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::fileio::PositionedFile;
+use indexd_utils::manifest::{AnyManifest, DirectoryManifest, Manifest};
+use indexd_utils::normalize;
+use indexd_utils::progress::{Progress, ProgressWriter};
+use indexd_utils::scheduler::{self, Scheduler};
+use log::{info, warn};
+use serde_json::json;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{self, AsyncWriteExt};
+
+use super::{file_bars, load_manifest, load_version, progress_bar};
+use crate::cli::DownloadArgs;
+
+pub async fn run(settings: &Settings, args: DownloadArgs) -> Result<()> {
+    if args.output.as_os_str() == "-" {
+        return download_stdout(settings, args).await;
+    }
+    let manifest = requested_manifest(settings, &args).await?;
+    let sdk = client::connect(settings).await?;
+
+    let start = Instant::now();
+    let size = match &manifest {
+        AnyManifest::File(manifest) => manifest.size,
+        AnyManifest::Directory(manifest) => manifest.size(),
+    };
+    match manifest {
+        AnyManifest::File(manifest) if args.offset.is_some() || args.length.is_some() => {
+            let offset = args.offset.unwrap_or(0);
+            let length = args
+                .length
+                .unwrap_or(u64::MAX)
+                .min(manifest.size.saturating_sub(offset));
+            info!("downloading {length} bytes at {offset}");
+            let (progress, bar) = progress_bar(length, 0);
+            let mut output = ProgressWriter::new(File::create(&args.output).await?, progress);
+            download::download_object(&sdk, &mut output, &manifest, offset, length).await?;
+            output.flush().await?;
+            drop(output);
+            let _ = bar.await;
+        }
+        AnyManifest::File(manifest) => {
+            info!("downloading file");
+            let (progress, bar) = progress_bar(manifest.size, 0);
+            let verify = !args.no_verify;
+            download_file(
+                &sdk,
+                &manifest,
+                &args.output,
+                args.resume,
+                args.parts,
+                verify,
+                progress,
+            )
+            .await?;
+            let _ = bar.await;
+        }
+        AnyManifest::Directory(_) if args.offset.is_some() || args.length.is_some() => {
+            return Err(Error::Usage(
+                "--offset and --length only apply to file manifests".into(),
+            ));
+        }
+        AnyManifest::Directory(manifest) => {
+            info!("downloading {} files", manifest.files.len());
+            let (progress, bar) = progress_bar(manifest.size(), 0);
+            let concurrency = args.concurrency.unwrap_or(settings.concurrency);
+            let options = DirectoryOptions {
+                concurrency,
+                resume: args.resume,
+                verify: !args.no_verify,
+                preserve: !args.no_preserve,
+                in_order: false,
+            };
+            download_directory(&sdk, &manifest, &args.output, &options, progress).await?;
+            let _ = bar.await;
+        }
+    }
+    let elapsed = start.elapsed();
+    let bytes_per_sec = (size as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+    info!(
+        "download complete in {}ms, {:.1} MiB/s",
+        elapsed.as_millis(),
+        bytes_per_sec as f64 / (1 << 20) as f64
+    );
+    let result = json!({
+        "output": args.output,
+        "size": size,
+        "elapsed_ms": elapsed.as_millis() as u64,
+        "bytes_per_sec": bytes_per_sec,
+    });
+    settings.output.print(&result, || {})
+}
+
+/// Loads the manifest to download: the version asked for, or the latest.
+async fn requested_manifest(settings: &Settings, args: &DownloadArgs) -> Result<AnyManifest> {
+    match args.version {
+        Some(version) => load_version(settings, &args.manifest, version).await,
+        None => load_manifest(settings, &args.manifest).await,
+    }
+}
+
+/// Streams a file's plaintext to stdout. The slabs are written strictly in
+/// order and a failed slab is picked up where it stopped rather than
+/// rewound, so memory stays bounded by the slabs in flight. A whole file is
+/// checked against its checksum once written, which can only fail the
+/// command after the fact.
+async fn download_stdout(settings: &Settings, args: DownloadArgs) -> Result<()> {
+    let AnyManifest::File(manifest) = requested_manifest(settings, &args).await? else {
+        return Err(Error::Usage(
+            "only a file manifest can be downloaded to stdout".into(),
+        ));
+    };
+    if args.resume {
+        return Err(Error::Usage("--resume needs an output file".into()));
+    }
+    let sdk = client::connect(settings).await?;
+
+    let offset = args.offset.unwrap_or(0);
+    let length = args
+        .length
+        .unwrap_or(u64::MAX)
+        .min(manifest.size.saturating_sub(offset));
+    let (progress, bar) = progress_bar(length, 0);
+    let mut output = ChecksumWriter::new(ProgressWriter::new(io::stdout(), progress));
+    download::download_object(&sdk, &mut output, &manifest, offset, length).await?;
+    output.flush().await?;
+    if offset == 0 && length == manifest.size && !args.no_verify {
+        checksum::verify_written(&output, &manifest)?;
+    }
+    drop(output);
+    let _ = bar.await;
+    Ok(())
+}
+
+/// Downloads a file slab by slab. With `resume`, the slabs already fully
+/// present in an existing output file are kept. Compressed and sealed files
+/// are always downloaded from the start since their slabs don't line up
+/// with the output, as are sparse files, whose holes are left unwritten.
+/// Otherwise more than one of `parts` downloads the
+/// file's slabs in that many concurrent runs. With `verify`, the file is
+/// read back and checked against the manifest's checksums.
+pub(super) async fn download_file(
+    sdk: &Client,
+    manifest: &Manifest,
+    output: &Path,
+    resume: bool,
+    parts: usize,
+    verify: bool,
+    progress: Progress,
+) -> Result<()> {
+    download_unverified(sdk, manifest, output, resume, parts, progress).await?;
+    if verify {
+        checksum::verify_file(output, manifest).await?;
+    }
+    Ok(())
+}
+
+async fn download_unverified(
+    sdk: &Client,
+    manifest: &Manifest,
+    output: &Path,
+    resume: bool,
+    parts: usize,
+    progress: Progress,
+) -> Result<()> {
+    if manifest.compression.is_some() || manifest.cipher.is_some() {
+        if resume || parts > 1 {
+            warn!(
+                "{} is compressed or sealed and can only be downloaded in order",
+                output.display()
+            );
+        }
+        let file = File::create(output).await?;
+        let mut output = ProgressWriter::new(file, progress);
+        download::download_object(sdk, &mut output, manifest, 0, manifest.size).await?;
+        output.flush().await?;
+        return Ok(());
+    }
+    if !manifest.holes.is_empty() {
+        if resume {
+            warn!(
+                "{} is sparse and is downloaded from the start",
+                output.display()
+            );
+        }
+        return download::download_sparse(sdk, output, manifest, parts, &progress).await;
+    }
+    if parts > 1 && !resume {
+        return download::download_parts(sdk, output, &manifest.slabs, parts, &progress).await;
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(!resume)
+        .open(output)
+        .await?;
+    let written = if resume {
+        file.metadata().await?.len()
+    } else {
+        0
+    };
+    let mut file = PositionedFile::new(file.into_std().await);
+    download::download_slabs(sdk, &mut file, &manifest.slabs, written, &progress).await?;
+    file.flush().await?;
+    file.file().set_len(manifest.size)?;
+    Ok(())
+}
+
+/// How `download_directory` recreates a tree.
+pub(super) struct DirectoryOptions {
+    pub concurrency: usize,
+    pub resume: bool,
+    pub verify: bool,
+    /// Give each file back the modification time, permission bits and
+    /// extended attributes it was uploaded with.
+    pub preserve: bool,
+    /// Start the files in the manifest's order rather than small and large
+    /// ones alternately.
+    pub in_order: bool,
+}
+
+/// Recreates the tree described by a directory manifest under `root`.
+///
+/// Files download one slab at a time, twice `concurrency` of them open at
+/// once, taking turns for `concurrency` slabs' worth of shards; unless
+/// `in_order` is set, small and large files are started alternately, so
+/// neither kind waits for all of the other.
+pub(super) async fn download_directory(
+    sdk: &Client,
+    manifest: &DirectoryManifest,
+    root: &Path,
+    options: &DirectoryOptions,
+    progress: Progress,
+) -> Result<()> {
+    let DirectoryOptions {
+        concurrency,
+        resume,
+        verify,
+        preserve,
+        in_order,
+    } = *options;
+    // resolve every path up front so a bad entry fails before any writes
+    let targets = manifest
+        .files
+        .iter()
+        .map(|entry| Ok((directory::resolve(root, &entry.path)?, entry)))
+        .collect::<Result<Vec<_>>>()?;
+    let paths: Vec<&str> = manifest
+        .files
+        .iter()
+        .map(|f| f.path.as_str())
+        .chain(manifest.links.iter().map(|l| l.path.as_str()))
+        .collect();
+    normalize::check_restore(root, &paths).await?;
+    // files are written before any link is created, so none can be written
+    // through one unless a resumed download finds it already there
+    for link in &manifest.links {
+        let inside = format!("{}/", link.path);
+        if let Some(entry) = manifest.files.iter().find(|f| f.path.starts_with(&inside)) {
+            return Err(Error::Manifest(format!(
+                "{} is inside the link {}",
+                entry.path, link.path
+            )));
+        }
+    }
+
+    let concurrency = concurrency.max(1);
+    let widest = manifest
+        .files
+        .iter()
+        .map(|f| f.manifest.data_shards as usize + f.manifest.parity_shards as usize)
+        .max()
+        .unwrap_or(1);
+    let scheduler = Scheduler::new(concurrency * widest);
+    let bars = (concurrency > 1).then(|| file_bars(&scheduler));
+    let targets = if in_order {
+        targets
+    } else {
+        scheduler::interleave(
+            targets
+                .into_iter()
+                .map(|target| (target.1.manifest.size, target))
+                .collect(),
+        )
+    };
+    stream::iter(targets)
+        .map(|(path, entry)| {
+            let progress = progress.clone();
+            let sdk = sdk
+                .clone()
+                .with_lane(scheduler.lane(entry.path.clone(), entry.manifest.size));
+            async move {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                download_file(&sdk, &entry.manifest, &path, resume, 1, verify, progress).await?;
+                if preserve {
+                    directory::restore_attributes(&path, entry).await?;
+                }
+                info!("downloaded {}", path.display());
+                Ok(())
+            }
+        })
+        .buffer_unordered(2 * concurrency)
+        .try_collect::<()>()
+        .await?;
+    drop(scheduler);
+    if let Some(bars) = bars {
+        let _ = bars.await;
+    }
+    for link in &manifest.links {
+        directory::create_link(root, link).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use indexd_utils::config::Settings;
+use indexd_utils::directory;
+use indexd_utils::error::Result;
+use indexd_utils::estimate::{Estimate, Pricing};
+use indexd_utils::filter::Filter;
+use indexd_utils::pack;
+use indexd_utils::redundancy::Redundancy;
+use serde_json::json;
+use tokio::fs;
+
+use super::{filter, redundancy};
+use crate::cli::EstimateArgs;
+
+pub async fn run(settings: &Settings, args: EstimateArgs) -> Result<()> {
+    let (data_shards, parity_shards) = redundancy(settings, &args.redundancy)?;
+    let redundancy = Redundancy::new(data_shards, parity_shards)?;
+    let filter = filter(&args.filter).await?;
+    let pack_threshold = args.pack_threshold.unwrap_or(pack::DEFAULT_THRESHOLD);
+    let (_, estimate) = plan(&args.input, &filter, redundancy, pack_threshold).await?;
+    let pricing = settings.pricing.as_ref();
+    let result = summary(&estimate, redundancy, pricing);
+    settings
+        .output
+        .print(&result, || print_estimate(&estimate, redundancy, pricing))
+}
+
+/// Lists the files an upload of `input` would read, relative to it, and
+/// estimates the upload.
+pub(super) async fn plan(
+    input: &Path,
+    filter: &Filter,
+    redundancy: Redundancy,
+    pack_threshold: u64,
+) -> Result<(Vec<(String, u64)>, Estimate)> {
+    let metadata = fs::metadata(input).await?;
+    if !metadata.is_dir() {
+        let name = input.display().to_string();
+        let estimate = Estimate::new(&[metadata.len()], redundancy, 0);
+        return Ok((vec![(name, metadata.len())], estimate));
+    }
+    let mut files = Vec::new();
+    for rel in directory::walk_filtered(input, filter).await? {
+        let size = fs::metadata(input.join(&rel)).await?.len();
+        files.push((directory::to_manifest_path(&rel)?, size));
+    }
+    let sizes: Vec<u64> = files.iter().map(|(_, size)| *size).collect();
+    let estimate = Estimate::new(&sizes, redundancy, pack_threshold);
+    Ok((files, estimate))
+}
+
+/// Returns the estimate as JSON, with its costs if there is pricing.
+pub(super) fn summary(
+    estimate: &Estimate,
+    redundancy: Redundancy,
+    pricing: Option<&Pricing>,
+) -> serde_json::Value {
+    json!({
+        "files": estimate.files,
+        "size": estimate.size,
+        "data_shards": redundancy.data_shards,
+        "parity_shards": redundancy.parity_shards,
+        "slabs": estimate.slabs,
+        "stored": estimate.stored,
+        "download": estimate.download,
+        "cost": pricing.map(|pricing| {
+            let cost = estimate.cost(pricing);
+            json!({
+                "storage_per_month": cost.storage_per_month,
+                "upload": cost.upload,
+                "download": cost.download,
+            })
+        }),
+    })
+}
+
+pub(super) fn print_estimate(
+    estimate: &Estimate,
+    redundancy: Redundancy,
+    pricing: Option<&Pricing>,
+) {
+    println!("files:      {}", estimate.files);
+    println!("size:       {} bytes", estimate.size);
+    println!("redundancy: {redundancy}");
+    println!("slabs:      {}", estimate.slabs);
+    println!("stored:     {} bytes, uploaded once", estimate.stored);
+    println!("download:   {} bytes", estimate.download);
+    if let Some(pricing) = pricing {
+        let cost = estimate.cost(pricing);
+        println!("storage:    {:.4} per month", cost.storage_per_month);
+        println!("upload:     {:.4}", cost.upload);
+        println!("download:   {:.4}", cost.download);
+    }
+}
@@ -0,0 +1,47 @@
+use indexd_utils::archive;
+use indexd_utils::checksum::{self, ChecksumWriter};
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::download;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::manifest::AnyManifest;
+use indexd_utils::progress::ProgressWriter;
+use log::info;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+
+use super::{load_manifest, progress_bar};
+use crate::cli::ExtractArgs;
+
+/// Downloads an archive uploaded with `archive` and unpacks it into a
+/// directory as it arrives.
+pub async fn run(settings: &Settings, args: ExtractArgs) -> Result<()> {
+    let AnyManifest::File(manifest) = load_manifest(settings, &args.manifest).await? else {
+        return Err(Error::Usage(
+            "an archive has a file manifest; directory manifests are downloaded with download"
+                .into(),
+        ));
+    };
+    tokio::fs::create_dir_all(&args.output).await?;
+    let sdk = client::connect(settings).await?;
+
+    let (progress, bar) = progress_bar(manifest.size, 0);
+    let (writer, untarring) = archive::untar(args.output.clone());
+    let mut writer = ChecksumWriter::new(ProgressWriter::new(writer, progress));
+    let downloaded = async {
+        download::download_object(&sdk, &mut writer, &manifest, 0, manifest.size).await?;
+        writer.flush().await?;
+        checksum::verify_written(&writer, &manifest)
+    }
+    .await;
+    // the pipe ends once the writer is dropped, letting the unpacking finish
+    drop(writer);
+    let untarred = untarring.await.expect("untar task panicked");
+    let _ = bar.await;
+    downloaded?;
+    untarred?;
+
+    info!("extracted into {}", args.output.display());
+    let result = json!({ "output": args.output, "size": manifest.size });
+    settings.output.print(&result, || {})
+}
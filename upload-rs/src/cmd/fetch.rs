@@ -0,0 +1,43 @@
+use std::path::Path;
+use std::time::Instant;
+
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+use indexd_utils::share::Share;
+use log::info;
+use serde_json::json;
+use tokio::fs;
+
+use super::download::download_file;
+use super::progress_bar;
+use crate::cli::FetchArgs;
+
+pub async fn run(settings: &Settings, args: FetchArgs) -> Result<()> {
+    // a token is long enough that it's often easier passed as a file
+    let token = if Path::new(&args.token).is_file() {
+        fs::read_to_string(&args.token).await?
+    } else {
+        args.token
+    };
+    let share = Share::decode(&token)?;
+    share.check_expiry()?;
+    // the share carries the slab keys, the app key only has to be approved
+    let sdk = client::connect(settings).await?;
+
+    let start = Instant::now();
+    let manifest = &share.manifest;
+    info!("fetching {} bytes", manifest.size);
+    let (progress, bar) = progress_bar(manifest.size, 0);
+    // a range has no checksum of its own to verify against
+    let verify = share.range.is_none();
+    download_file(&sdk, manifest, &args.output, false, 1, verify, progress).await?;
+    let _ = bar.await;
+    info!("fetch complete in {}ms", start.elapsed().as_millis());
+    let result = json!({
+        "output": args.output,
+        "size": manifest.size,
+        "elapsed_ms": start.elapsed().as_millis() as u64,
+    });
+    settings.output.print(&result, || {})
+}
@@ -0,0 +1,53 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+use indexd_utils::gc;
+use indexd_utils::keys;
+use log::warn;
+
+use crate::cli::GcArgs;
+
+/// Reports the slabs abandoned uploads left stored without any manifest
+/// referring to them.
+///
+/// Like rm, this can't release them yet: the indexd SDK has no way to
+/// delete or unpin slabs. Once it does, gc should ask for confirmation and
+/// release the orphans, reporting the bytes reclaimed, before removing
+/// their checkpoints.
+pub async fn run(settings: &Settings, args: GcArgs) -> Result<()> {
+    let passphrase = if args.passphrase {
+        Some(keys::read_passphrase(false)?)
+    } else {
+        None
+    };
+    let catalog = settings.catalog()?;
+    let report = gc::scan(&catalog, &args.paths, passphrase.as_deref()).await?;
+    if report.unreadable > 0 {
+        warn!(
+            "{} sealed manifests weren't opened; slabs they refer to may be reported as orphaned",
+            report.unreadable
+        );
+    }
+    if report.orphaned_slabs() > 0 {
+        warn!("orphaned slabs are still stored; the SDK can't release them yet");
+    }
+    settings.output.print(&report, || {
+        for orphan in &report.orphans {
+            println!(
+                "{}\t{} slabs\t{} bytes\tfrom {}",
+                orphan.checkpoint.display(),
+                orphan.slabs,
+                orphan.stored,
+                orphan.input.display()
+            );
+        }
+        for stale in &report.stale {
+            println!("{}\tfinished, can be removed", stale.display());
+        }
+        println!(
+            "{} orphaned slabs holding {} bytes; {} slabs referenced",
+            report.orphaned_slabs(),
+            report.orphaned_bytes(),
+            report.referenced
+        );
+    })
+}
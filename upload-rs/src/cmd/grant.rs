@@ -0,0 +1,40 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::manifest::Sealing;
+use log::info;
+use serde_json::json;
+
+use super::{open_manifest, store_manifest};
+use crate::cli::GrantArgs;
+
+/// Seals a manifest for more recipients. The manifest key stays the same,
+/// so recipients already granted keep their access.
+pub async fn run(settings: &Settings, args: GrantArgs) -> Result<()> {
+    let (manifest, sealing, location) = open_manifest(settings, &args.manifest).await?;
+    let Some(mut sealing) = sealing else {
+        return Err(Error::Usage(format!(
+            "{location} is not sealed; upload it with --passphrase to share it with recipients"
+        )));
+    };
+    for recipient in args.recipients {
+        if !sealing.recipients.contains(&recipient) {
+            sealing.recipients.push(recipient);
+        }
+    }
+    store_manifest(manifest, &location, Some(&sealing)).await?;
+    info!(
+        "{location} is shared with {} recipients",
+        sealing.recipients.len()
+    );
+    print_recipients(settings, &location.to_string(), &sealing)
+}
+
+/// Prints the recipients a manifest is now sealed for.
+pub(super) fn print_recipients(settings: &Settings, name: &str, sealing: &Sealing) -> Result<()> {
+    let result = json!({ "name": name, "recipients": sealing.recipients });
+    settings.output.print(&result, || {
+        for recipient in &sealing.recipients {
+            println!("{recipient}");
+        }
+    })
+}
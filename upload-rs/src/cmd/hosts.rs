@@ -0,0 +1,37 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+use indexd_utils::hosts;
+use serde_json::json;
+
+use crate::cli::{HostsArgs, HostsCommand};
+
+pub fn run(settings: &Settings, args: HostsArgs) -> Result<()> {
+    match args.command {
+        HostsCommand::Stats => stats(settings),
+    }
+}
+
+fn stats(settings: &Settings) -> Result<()> {
+    let stats = settings.catalog()?.host_stats()?;
+    for (host, stats, score) in hosts::rank(&stats, settings.host_strategy.as_ref()) {
+        let result = json!({
+            "host": host,
+            "score": score,
+            "transfers": stats.transfers,
+            "failures": stats.failures,
+            "failure_rate": stats.failure_rate(),
+            "latency_ms": stats.latency_ms(),
+            "bytes_per_sec": stats.bytes_per_sec(),
+        });
+        settings.output.print(&result, || {
+            println!(
+                "{host}\t{} transfers\t{:.1}% failed\t{:.0}ms\t{:.1} MiB/s",
+                stats.transfers,
+                stats.failure_rate() * 100.0,
+                stats.latency_ms(),
+                stats.bytes_per_sec() / (1 << 20) as f64,
+            );
+        })?;
+    }
+    Ok(())
+}
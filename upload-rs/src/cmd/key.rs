@@ -0,0 +1,182 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use indexd_utils::approvals::{self, Approvals};
+use indexd_utils::config::{Config, KeySource, Settings};
+use indexd_utils::error::Result;
+use indexd_utils::recipients::RecipientKey;
+use log::info;
+use serde_json::json;
+
+use crate::cli::{KeyArgs, KeyCommand, KeyRecipientArgs};
+
+pub async fn run(config: &Config, settings: &Settings, args: KeyArgs) -> Result<()> {
+    match args.command {
+        KeyCommand::List => list(config, settings).await,
+        KeyCommand::Recipient(args) => recipient(settings, args).await,
+        #[cfg(feature = "keyring")]
+        command => {
+            let settings = settings.clone();
+            // the keychain may block on an unlock prompt
+            tokio::task::spawn_blocking(move || stored::run(&settings, command))
+                .await
+                .expect("keychain access panicked")
+        }
+    }
+}
+
+/// Lists every app identity in the config, along with the profile's own
+/// when it doesn't select one, and when each was last approved here.
+async fn list(config: &Config, settings: &Settings) -> Result<()> {
+    let approvals = match approvals::default_path() {
+        Some(path) => Approvals::load(&path).await?,
+        None => Approvals::default(),
+    };
+    let mut identities: Vec<(String, Option<String>, &str)> = config
+        .apps
+        .iter()
+        .map(|(name, app)| {
+            let source = match (&app.app_secret, &app.app_secret_file) {
+                (Some(_), _) => "secret",
+                (_, Some(_)) => "secret file",
+                _ => "profile",
+            };
+            (name.clone(), app.app_url.clone(), source)
+        })
+        .collect();
+    if settings.app.is_none() {
+        let source = match &settings.key_source {
+            Some(KeySource::Secret(_)) => "secret",
+            Some(KeySource::SecretFile(_)) => "secret file",
+            #[cfg(feature = "keyring")]
+            Some(KeySource::Keychain(_)) => "keychain",
+            None => "none",
+        };
+        let url = Some(settings.app_url.clone());
+        identities.push((settings.identity().to_string(), url, source));
+    }
+    identities.sort();
+
+    for (name, url, source) in &identities {
+        let approved = approvals.get(name);
+        let selected = name == settings.identity();
+        let result = json!({
+            "name": name,
+            "app_url": url,
+            "key": source,
+            "selected": selected,
+            "approved_at": approved.map(|a| a.approved_at),
+            "key_fingerprint": approved.map(|a| &a.key_fingerprint),
+        });
+        settings.output.print(&result, || {
+            let marker = if selected { "*" } else { " " };
+            let approved = approved.map_or("never approved here".to_string(), |a| {
+                let at = UNIX_EPOCH + Duration::from_secs(a.approved_at);
+                format!(
+                    "approved {} with key {}",
+                    httpdate::fmt_http_date(at),
+                    a.key_fingerprint
+                )
+            });
+            let url = url.as_deref().unwrap_or("(the profile's app url)");
+            println!("{marker} {name}\t{url}\t{source}\t{approved}");
+        })?;
+    }
+    Ok(())
+}
+
+/// Creates a recipient key, or reads one, and prints its recipient. A new
+/// key goes to stdout unless written to a file.
+async fn recipient(settings: &Settings, args: KeyRecipientArgs) -> Result<()> {
+    let key = match &args.show {
+        Some(path) => RecipientKey::load(path).await?,
+        None => RecipientKey::generate(),
+    };
+    let recipient = key.recipient();
+    if let Some(path) = &args.output {
+        key.save(path).await?;
+        info!("wrote the recipient key to {}", path.display());
+    } else if args.show.is_none() {
+        println!("{}", key.encode());
+    }
+    let result = json!({ "recipient": recipient });
+    settings.output.print(&result, || {
+        if args.show.is_some() || args.output.is_some() {
+            println!("{recipient}");
+        } else {
+            eprintln!("recipient: {recipient}");
+        }
+    })
+}
+
+/// The keys kept in the OS keychain.
+#[cfg(feature = "keyring")]
+mod stored {
+    use std::io;
+
+    use log::info;
+    use serde_json::json;
+
+    use crate::cli::{KeyCommand, KeyTargetArgs, KeyWriteArgs};
+    use indexd_utils::config::{self, Settings};
+    use indexd_utils::error::{Error, Result};
+    use indexd_utils::keychain;
+
+    pub fn run(settings: &Settings, command: KeyCommand) -> Result<()> {
+        match command {
+            KeyCommand::List | KeyCommand::Recipient(_) => {
+                unreachable!("listing and recipient keys don't need the keychain")
+            }
+            KeyCommand::Generate(args) => store(settings, &args, rand::random()),
+            KeyCommand::Import(args) => {
+                let line = read_line()?;
+                let key = if args.secret {
+                    config::seed_from_secret(&line)
+                } else {
+                    let mut key = [0u8; 32];
+                    hex::decode_to_slice(&line, &mut key)
+                        .map_err(|_| Error::Usage("expected 64 hex characters on stdin".into()))?;
+                    key
+                };
+                store(settings, &args.key, key)
+            }
+            KeyCommand::Export(args) => {
+                let account = account(settings, &args);
+                let key = keychain::load(&account)?
+                    .ok_or_else(|| Error::NotFound(format!("{account} in the keychain")))?;
+                let key = hex::encode(key);
+                let result = json!({ "account": account, "key": key });
+                settings.output.print(&result, || println!("{key}"))
+            }
+        }
+    }
+
+    /// Stores `key` under the targeted account, refusing to replace a key
+    /// unless forced since whatever the old one protects becomes unreadable.
+    fn store(settings: &Settings, args: &KeyWriteArgs, key: [u8; 32]) -> Result<()> {
+        let account = account(settings, &args.target);
+        if !args.force && keychain::load(&account)?.is_some() {
+            return Err(Error::Usage(format!(
+                "{account} already holds a key; pass --force to replace it"
+            )));
+        }
+        keychain::store(&account, &key)?;
+        info!("stored {account} in the keychain");
+        let result = json!({ "account": account });
+        settings
+            .output
+            .print(&result, || println!("stored {account}"))
+    }
+
+    fn account(settings: &Settings, args: &KeyTargetArgs) -> String {
+        match &args.object {
+            Some(name) => keychain::object_account(name),
+            None => keychain::app_account(settings.identity()),
+        }
+    }
+
+    fn read_line() -> Result<String> {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        Ok(line.trim().to_string())
+    }
+}
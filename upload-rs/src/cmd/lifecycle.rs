@@ -0,0 +1,34 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+use indexd_utils::lifecycle;
+use log::info;
+
+use crate::cli::{LifecycleArgs, LifecycleCommand};
+
+pub fn run(settings: &Settings, args: LifecycleArgs) -> Result<()> {
+    match args.command {
+        LifecycleCommand::Status => {
+            if settings.lifecycle.is_empty() {
+                info!("the profile has no lifecycle rules");
+            }
+            let catalog = settings.catalog()?;
+            let plan = lifecycle::plan(&catalog, &settings.lifecycle)?;
+            for status in &plan.rules {
+                settings.output.print(status, || {
+                    println!(
+                        "{}: {} objects, {} versions and {} objects to expire, {} to purge \
+                         from the trash, {} to verify ({} not due)",
+                        status.rule,
+                        status.objects,
+                        status.expired_versions.len(),
+                        status.expired_objects.len(),
+                        status.purged_trash.len(),
+                        status.verify_due.len(),
+                        status.verify_not_due
+                    );
+                })?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+use indexd_utils::manifest::{AnyManifest, StoredManifest};
+use indexd_utils::output::Output;
+use indexd_utils::tags::Tag;
+use log::debug;
+use serde_json::json;
+use tokio::fs;
+
+use crate::cli::LsArgs;
+
+pub async fn run(settings: &Settings, args: LsArgs) -> Result<()> {
+    let prefix = args.prefix.as_deref().unwrap_or("");
+    match &args.dir {
+        Some(dir) => list_dir(dir, settings.output).await,
+        None if args.versions => list_versions(settings, prefix),
+        None => list_catalog(settings, prefix, &args.tags),
+    }
+}
+
+fn list_versions(settings: &Settings, prefix: &str) -> Result<()> {
+    for version in settings.catalog()?.list_versions(prefix)? {
+        settings.output.print(&version, || {
+            println!(
+                "{}\tv{}\t{}{}\t{}",
+                version.name,
+                version.version,
+                version.size,
+                if version.sealed { "\tsealed" } else { "" },
+                if version.replaced_at.is_none() {
+                    "latest"
+                } else {
+                    "replaced"
+                },
+            );
+        })?;
+    }
+    Ok(())
+}
+
+fn list_catalog(settings: &Settings, prefix: &str, tags: &[Tag]) -> Result<()> {
+    for entry in settings.catalog()?.list_tagged(prefix, tags)? {
+        settings.output.print(&entry, || {
+            let kind = match entry.files {
+                Some(files) => format!("{files} files"),
+                None => "file".to_string(),
+            };
+            let tags: Vec<String> = entry.tags.iter().map(|(k, v)| format!("{k}={v}")).collect();
+            println!(
+                "{}\t{}\t{kind}{}\t{}{}",
+                entry.name,
+                entry.size,
+                if entry.sealed { ", sealed" } else { "" },
+                entry.source.as_deref().unwrap_or("-"),
+                if tags.is_empty() {
+                    String::new()
+                } else {
+                    format!("\t{}", tags.join(","))
+                },
+            );
+        })?;
+    }
+    Ok(())
+}
+
+async fn list_dir(dir: &Path, output: Output) -> Result<()> {
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if !matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("json" | "cbor")
+        ) {
+            continue;
+        }
+        match StoredManifest::load(&path).await {
+            Ok(StoredManifest::Plain(AnyManifest::File(manifest))) => {
+                let result = json!({
+                    "path": path,
+                    "size": manifest.size,
+                    "slabs": manifest.slabs.len(),
+                    "data_shards": manifest.data_shards,
+                    "parity_shards": manifest.parity_shards,
+                });
+                output.print(&result, || {
+                    println!(
+                        "{}\t{}\t{} slabs\t{}/{}",
+                        path.display(),
+                        manifest.size,
+                        manifest.slabs.len(),
+                        manifest.data_shards,
+                        manifest.parity_shards
+                    )
+                })?
+            }
+            Ok(StoredManifest::Plain(AnyManifest::Directory(manifest))) => {
+                let result = json!({
+                    "path": path,
+                    "size": manifest.size(),
+                    "files": manifest.files.len(),
+                });
+                output.print(&result, || {
+                    println!(
+                        "{}\t{}\t{} files",
+                        path.display(),
+                        manifest.size(),
+                        manifest.files.len()
+                    )
+                })?
+            }
+            Ok(StoredManifest::Sealed(_)) => {
+                let result = json!({ "path": path, "sealed": true });
+                output.print(&result, || println!("{}\tsealed", path.display()))?
+            }
+            Err(e) => debug!("skipping {}: {e}", path.display()),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::time::{Duration, SystemTime};
+
+use indexd_utils::client::{self, Client};
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::keys;
+use indexd_utils::maintain::{self, Limits};
+use indexd_utils::progress::Progress;
+use indexd_utils::shutdown;
+use log::{info, warn};
+
+use crate::cli::MaintainArgs;
+
+/// How often a scheduled wait checks for an interrupt.
+const POLL: Duration = Duration::from_secs(1);
+
+/// Runs maintenance once, or at every time of the schedule until
+/// interrupted. An interrupt lets the object being repaired finish.
+pub async fn run(settings: &Settings, args: MaintainArgs) -> Result<()> {
+    let passphrase = if args.passphrase {
+        Some(keys::read_passphrase(false)?)
+    } else {
+        None
+    };
+    let limits = Limits {
+        time: args.max_time,
+        bytes: args.max_bytes,
+    };
+    let sdk = client::connect(settings).await?;
+    shutdown::listen();
+    let job = Job {
+        settings,
+        sdk: &sdk,
+        passphrase: passphrase.as_deref(),
+        args: &args,
+        limits,
+    };
+
+    let Some(schedule) = &args.schedule else {
+        let damaged = job.run().await?;
+        if damaged > 0 {
+            return Err(Error::Integrity(format!(
+                "{damaged} slabs could not be repaired"
+            )));
+        }
+        return Ok(());
+    };
+    loop {
+        let next = schedule
+            .next_after(SystemTime::now())
+            .ok_or_else(|| Error::Usage(format!("the schedule {schedule} never runs")))?;
+        info!("next maintenance at {}", httpdate::fmt_http_date(next));
+        while let Ok(wait) = next.duration_since(SystemTime::now()) {
+            if shutdown::requested() {
+                return Ok(());
+            }
+            tokio::time::sleep(wait.min(POLL)).await;
+        }
+        match job.run().await {
+            Ok(0) => {}
+            Ok(damaged) => warn!("{damaged} slabs could not be repaired"),
+            // the next run may find the app or hosts back
+            Err(e) if e.is_retryable() => warn!("maintenance failed: {e}"),
+            Err(e) => return Err(e),
+        }
+        if shutdown::requested() {
+            return Ok(());
+        }
+    }
+}
+
+struct Job<'a> {
+    settings: &'a Settings,
+    sdk: &'a Client,
+    passphrase: Option<&'a str>,
+    args: &'a MaintainArgs,
+    limits: Limits,
+}
+
+impl Job<'_> {
+    /// Runs maintenance over the catalog and prints what it did, returning
+    /// the number of slabs still needing attention.
+    async fn run(&self) -> Result<usize> {
+        let catalog = self.settings.catalog()?;
+        let report = maintain::maintain(
+            self.sdk,
+            &catalog,
+            self.passphrase,
+            &self.args.gc_paths,
+            &self.settings.lifecycle,
+            self.limits,
+            &Progress::default(),
+        )
+        .await?;
+        if report.sealed > 0 {
+            warn!(
+                "{} sealed objects were skipped; pass --passphrase to check them",
+                report.sealed
+            );
+        }
+        let damaged: usize = report.objects.iter().map(|o| o.damaged()).sum();
+        let replaced: usize = report.objects.iter().map(|o| o.replaced).sum();
+        self.settings.output.print(&report, || {
+            for object in &report.objects {
+                if let Some(e) = &object.error {
+                    println!("{}: {e}", object.name);
+                } else if object.replaced > 0 || object.damaged() > 0 {
+                    println!(
+                        "{}: {} replaced, {} unrecoverable, {} not replaced",
+                        object.name, object.replaced, object.unrecoverable, object.failed
+                    );
+                }
+            }
+            println!(
+                "{} objects checked, {} slabs replaced, {} need attention; {} left for the next run",
+                report.objects.len(),
+                replaced,
+                damaged,
+                report.deferred.len()
+            );
+            if report.not_due > 0 {
+                println!(
+                    "{} objects not due for verification",
+                    report.not_due
+                );
+            }
+            let applied = report.lifecycle;
+            if applied.versions + applied.trashed + applied.purged > 0 {
+                println!(
+                    "lifecycle: {} versions removed, {} objects trashed, {} purged",
+                    applied.versions, applied.trashed, applied.purged
+                );
+            }
+            println!(
+                "{} orphaned slabs holding {} bytes",
+                report.gc.orphaned_slabs(),
+                report.gc.orphaned_bytes()
+            );
+        })?;
+        Ok(damaged)
+    }
+}
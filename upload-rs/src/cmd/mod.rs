@@ -0,0 +1,460 @@
+mod append;
+mod archive;
+mod backup;
+mod bench;
+#[cfg(feature = "tui")]
+mod browse;
+mod bucket;
+mod catalog;
+mod cp;
+mod daemon;
+mod diff;
+mod doctor;
+mod download;
+mod estimate;
+mod extract;
+mod fetch;
+mod gc;
+mod grant;
+mod hosts;
+mod key;
+mod lifecycle;
+mod ls;
+mod maintain;
+#[cfg(feature = "fuse")]
+mod mount;
+mod mv;
+mod queue;
+mod rekey;
+mod repair;
+mod restore;
+mod revoke;
+mod rm;
+mod serve;
+mod session;
+mod share;
+mod snapshots;
+mod status;
+mod sync;
+mod trash;
+mod upload;
+mod verify;
+
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use indexd_utils::budget::Budget;
+use indexd_utils::catalog::Catalog;
+use indexd_utils::client;
+use indexd_utils::config::{Config, Settings};
+use indexd_utils::error::{Error, Result};
+use indexd_utils::filter::Filter;
+use indexd_utils::hosts::HostPolicy;
+use indexd_utils::keys;
+use indexd_utils::locator::Locator;
+use indexd_utils::manifest::{AnyManifest, Sealing, StoredManifest};
+use indexd_utils::output::Output;
+use indexd_utils::progress::{Event, Progress};
+use indexd_utils::recipients::RecipientKey;
+use indexd_utils::redundancy::Redundancy;
+use indexd_utils::scheduler::Scheduler;
+use indexd_utils::tags::Tag;
+use indexd_utils::telemetry::HOSTS;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::warn;
+use tokio::task::JoinHandle;
+
+use crate::cli::{Cli, Command, FilterArgs, RedundancyArgs};
+
+pub async fn run(cli: Cli) -> Result<()> {
+    let config = Config::load(cli.config.as_deref()).await?;
+    let mut settings = config.settings(cli.profile.as_deref(), cli.app.as_deref())?;
+    settings.upload_limit = cli.bwlimit_up.or(settings.upload_limit);
+    settings.download_limit = cli.bwlimit_down.or(settings.download_limit);
+    if let Some(ms) = cli.hedge_after {
+        settings.hedge_after = Some(Duration::from_millis(ms));
+    }
+    if !cli.host_allow.is_empty() || !cli.host_block.is_empty() {
+        let spread = settings.hosts.take().and_then(|hosts| hosts.spread);
+        settings.hosts = HostPolicy::new(cli.host_allow, cli.host_block, spread)?;
+    }
+    settings.placement_attempts = cli
+        .placement_attempts
+        .unwrap_or(settings.placement_attempts);
+    if cli.json {
+        settings.output = Output::Json;
+    }
+    if cli.chaos.is_some() {
+        settings.chaos = cli.chaos;
+    }
+    if cli.proxy.is_some() {
+        settings.network.proxy = cli.proxy;
+    }
+    let tls = &mut settings.network.tls;
+    tls.ca_bundle = cli.ca_bundle.or(tls.ca_bundle.take());
+    tls.client_cert = cli.client_cert.or(tls.client_cert.take());
+    tls.insecure_skip_verify |= cli.insecure_skip_verify;
+    if let Some(secs) = cli.approval_timeout {
+        settings.approval.deadline = (secs > 0).then(|| Duration::from_secs(secs));
+    }
+
+    let result = match cli.command {
+        Command::Upload(args) => upload::run(&settings, args).await,
+        Command::Estimate(args) => estimate::run(&settings, args).await,
+        Command::Download(args) => download::run(&settings, args).await,
+        Command::Verify(args) => verify::run(&settings, args).await,
+        Command::Repair(args) => repair::run(&settings, args).await,
+        Command::Rekey(args) => rekey::run(&settings, args).await,
+        Command::Append(args) => append::run(&settings, args).await,
+        Command::Share(args) => share::run(&settings, args).await,
+        Command::Fetch(args) => fetch::run(&settings, args).await,
+        Command::Grant(args) => grant::run(&settings, args).await,
+        Command::Revoke(args) => revoke::run(&settings, args).await,
+        Command::Archive(args) => archive::run(&settings, args).await,
+        Command::Extract(args) => extract::run(&settings, args).await,
+        Command::Backup(args) => backup::run(&settings, args).await,
+        Command::Restore(args) => restore::run(&settings, args).await,
+        Command::Snapshots(args) => snapshots::run(&settings, args),
+        Command::Diff(args) => diff::run(&settings, args).await,
+        Command::Sync(args) => sync::run(&settings, args).await,
+        Command::Ls(args) => ls::run(&settings, args).await,
+        Command::Cp(args) => cp::run(&settings, args),
+        Command::Mv(args) => mv::run(&settings, args),
+        Command::Rm(args) => rm::run(&settings, args).await,
+        Command::Trash(args) => trash::run(&settings, args),
+        Command::Catalog(args) => catalog::run(&settings, args).await,
+        Command::Status => status::run(&settings),
+        Command::Gc(args) => gc::run(&settings, args).await,
+        Command::Maintain(args) => maintain::run(&settings, args).await,
+        Command::Lifecycle(args) => lifecycle::run(&settings, args),
+        Command::Doctor(args) => doctor::run(&settings, args).await,
+        Command::Bench(args) => bench::run(&settings, args).await,
+        Command::Bucket(args) => bucket::run(&settings, args).await,
+        #[cfg(feature = "fuse")]
+        Command::Mount(args) => mount::run(&settings, args).await,
+        #[cfg(feature = "tui")]
+        Command::Browse(args) => browse::run(&settings, args).await,
+        Command::Key(args) => key::run(&config, &settings, args).await,
+        Command::Hosts(args) => hosts::run(&settings, args),
+        Command::Session(args) => session::run(&settings, args),
+        Command::Serve(args) => serve::run(&settings, args).await,
+        Command::Daemon(args) => daemon::run(&settings, args).await,
+        Command::Queue(args) => queue::run(&settings, args).await,
+    };
+    save_host_stats(&settings);
+    result
+}
+
+/// Adds what this run saw of each host to the catalog. The stats only
+/// inform later runs, so failing to save them never fails the command.
+fn save_host_stats(settings: &Settings) {
+    let hosts = HOSTS.take();
+    if hosts.is_empty() {
+        return;
+    }
+    if let Err(e) = settings
+        .catalog()
+        .and_then(|catalog| catalog.record_host_stats(&hosts))
+    {
+        warn!("failed to save host stats: {e}");
+    }
+}
+
+/// Stops an upload that would go over the budget, unless it is
+/// `interactive` and the user chooses to go ahead.
+fn check_budget(budget: &Budget, catalog: &Catalog, charge: f64, interactive: bool) -> Result<()> {
+    let Some(reason) = budget.exceeded(catalog, charge)? else {
+        return Ok(());
+    };
+    if interactive && io::stdin().is_terminal() {
+        eprint!("{reason}; upload anyway? [y/N] ");
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if answer.trim().eq_ignore_ascii_case("y") {
+            return Ok(());
+        }
+    }
+    Err(Error::Budget(reason))
+}
+
+/// Returns the redundancy from the command line, falling back to the
+/// profile.
+fn redundancy(settings: &Settings, args: &RedundancyArgs) -> Result<(u8, u8)> {
+    let default = Redundancy::new(settings.data_shards, settings.parity_shards)?;
+    let redundancy = redundancy_or(args, default)?;
+    Ok((redundancy.data_shards, redundancy.parity_shards))
+}
+
+/// Returns the redundancy from the command line: the preset, if any, with
+/// the shard counts given overriding it, falling back to `default`.
+fn redundancy_or(args: &RedundancyArgs, default: Redundancy) -> Result<Redundancy> {
+    let base = args.redundancy.unwrap_or(default);
+    Redundancy::new(
+        args.data_shards.unwrap_or(base.data_shards),
+        args.parity_shards.unwrap_or(base.parity_shards),
+    )
+}
+
+/// Builds the filter from the command line: the rules files in order, then
+/// the excludes, then the includes, so an include overrides any exclude.
+async fn filter(args: &FilterArgs) -> Result<Filter> {
+    let mut rules = Vec::new();
+    for path in &args.filter_from {
+        let contents = tokio::fs::read_to_string(path).await?;
+        rules.extend(contents.lines().map(str::to_string));
+    }
+    rules.extend(args.exclude.iter().cloned());
+    rules.extend(args.include.iter().map(|pattern| format!("!{pattern}")));
+    Filter::new(rules.iter().map(String::as_str))
+}
+
+/// Where a manifest given on the command line lives.
+enum Location {
+    File(PathBuf),
+    Catalog(Catalog, String),
+    /// A sealed manifest stored on indexd.
+    Locator(Locator),
+}
+
+impl Location {
+    /// Resolves a manifest argument: a locator is decoded, an existing file
+    /// is used as is and anything else is looked up by name in the catalog.
+    fn resolve(settings: &Settings, target: &Path) -> Result<Self> {
+        let target_str = target.to_string_lossy();
+        if Locator::is_locator(&target_str) {
+            return Ok(Location::Locator(Locator::decode(&target_str)?));
+        }
+        if target.exists() {
+            return Ok(Location::File(target.to_path_buf()));
+        }
+        let name = target.to_string_lossy().into_owned();
+        let catalog = settings.catalog()?;
+        if !catalog.contains(&name)? {
+            return Err(Error::Manifest(format!(
+                "{name} is neither a manifest file nor in the catalog"
+            )));
+        }
+        Ok(Location::Catalog(catalog, name))
+    }
+
+    async fn load(&self, settings: &Settings) -> Result<StoredManifest> {
+        match self {
+            Location::File(path) => StoredManifest::load(path).await,
+            Location::Catalog(catalog, name) => catalog
+                .get(name)?
+                .ok_or_else(|| Error::Manifest(format!("{name} is not in the catalog"))),
+            Location::Locator(locator) => {
+                let sdk = client::connect(settings).await?;
+                Ok(StoredManifest::Sealed(locator.fetch(&sdk).await?))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::File(path) => write!(f, "{}", path.display()),
+            Location::Catalog(_, name) => write!(f, "{name}"),
+            Location::Locator(_) => write!(f, "the located manifest"),
+        }
+    }
+}
+
+/// Returns what a command selects: the manifest or catalog name given, or
+/// the names of the catalogued objects carrying every one of `tags`.
+fn targets(settings: &Settings, manifest: Option<&Path>, tags: &[Tag]) -> Result<Vec<PathBuf>> {
+    if tags.is_empty() {
+        // clap requires a manifest without tags
+        return Ok(manifest.into_iter().map(Path::to_path_buf).collect());
+    }
+    let entries = settings.catalog()?.list_tagged("", tags)?;
+    if entries.is_empty() {
+        let tags: Vec<String> = tags.iter().map(Tag::to_string).collect();
+        return Err(Error::NotFound(format!(
+            "no catalogued objects are tagged {}",
+            tags.join(", ")
+        )));
+    }
+    Ok(entries
+        .into_iter()
+        .map(|entry| PathBuf::from(entry.name))
+        .collect())
+}
+
+/// Loads a manifest from a file or the catalog, prompting for the
+/// passphrase if it is sealed.
+async fn load_manifest(settings: &Settings, target: &Path) -> Result<AnyManifest> {
+    let location = Location::resolve(settings, target)?;
+    read_sealed(settings, location.load(settings).await?).await
+}
+
+/// Loads a manifest along with where it came from and the key it was
+/// sealed with, if any, so it can be written back with `store_manifest`.
+async fn open_manifest(
+    settings: &Settings,
+    target: &Path,
+) -> Result<(AnyManifest, Option<Sealing>, Location)> {
+    let location = Location::resolve(settings, target)?;
+    let (manifest, sealing) = unseal(location.load(settings).await?).await?;
+    Ok((manifest, sealing, location))
+}
+
+/// Loads an older version of a catalogued object, prompting for the
+/// passphrase if it is sealed.
+async fn load_version(settings: &Settings, name: &Path, version: u64) -> Result<AnyManifest> {
+    let name = name.to_string_lossy();
+    let stored = settings
+        .catalog()?
+        .get_version(&name, version)?
+        .ok_or_else(|| Error::Manifest(format!("{name} has no version {version}")))?;
+    read_sealed(settings, stored).await
+}
+
+/// Opens a stored manifest to read it, with the profile's recipient key if
+/// the manifest is shared with it and the passphrase otherwise. A manifest
+/// opened with a recipient key can't be sealed again, so commands writing
+/// manifests back use `open_manifest` instead.
+async fn read_sealed(settings: &Settings, stored: StoredManifest) -> Result<AnyManifest> {
+    if let (StoredManifest::Sealed(sealed), Some(path)) = (&stored, &settings.recipient_key_file) {
+        let key = RecipientKey::load(path).await?;
+        if let Some(manifest) = sealed.open_as(&key)? {
+            return Ok(manifest);
+        }
+    }
+    Ok(unseal(stored).await?.0)
+}
+
+/// Opens a stored manifest along with the key it was sealed with, if any.
+async fn unseal(stored: StoredManifest) -> Result<(AnyManifest, Option<Sealing>)> {
+    match stored {
+        StoredManifest::Plain(manifest) => Ok((manifest, None)),
+        StoredManifest::Sealed(sealed) => {
+            let passphrase = keys::read_passphrase(false)?;
+            let key = sealed.kdf.derive(&passphrase).await?;
+            let manifest = sealed.open(&key)?;
+            Ok((manifest, Some(sealed.sealing(key))))
+        }
+    }
+}
+
+/// Writes a manifest back to where it was loaded from, sealing it if
+/// `sealing` is set.
+async fn store_manifest(
+    manifest: AnyManifest,
+    location: &Location,
+    sealing: Option<&Sealing>,
+) -> Result<()> {
+    let stored = StoredManifest::new(manifest.clone(), sealing)?;
+    match location {
+        Location::File(path) => stored.save(path).await,
+        Location::Catalog(catalog, name) => catalog.replace(name, None, &manifest, &stored),
+        // the locator would have to change to point at a new copy
+        Location::Locator(_) => Err(Error::Usage(
+            "a manifest found through a locator can't be updated in place; download it and \
+             upload it again"
+                .into(),
+        )),
+    }
+}
+
+/// Appends `suffix` to the file name of `path`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    // strips any trailing separator so directories get a sibling path
+    let mut path = path.components().as_path().as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// The most files given a bar of their own at once.
+const MAX_FILE_BARS: usize = 8;
+
+/// Lets the bars of a transfer and of its files share stderr.
+static BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// Renders progress events as a bar on stderr until every clone of the
+/// returned `Progress` is dropped.
+fn progress_bar(total: u64, position: u64) -> (Progress, JoinHandle<()>) {
+    let (progress, mut events) = Progress::channel();
+    let bar = BARS.add(ProgressBar::new(total).with_position(position));
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{bar:40} {bytes}/{total_bytes} {binary_bytes_per_sec} eta {eta} {msg}",
+        )
+        .expect("valid template"),
+    );
+    let handle = tokio::spawn(async move {
+        let mut paused = false;
+        while let Some(event) = events.recv().await {
+            match event {
+                Event::BytesTransferred { bytes } => {
+                    bar.inc(bytes);
+                    if paused {
+                        bar.set_message("");
+                        paused = false;
+                    }
+                }
+                Event::RateLimited { wait_secs } => {
+                    bar.set_message(format!("(rate limited, paused for {wait_secs}s)"));
+                    paused = true;
+                }
+                _ => {}
+            }
+        }
+        bar.finish_and_clear();
+    });
+    (progress, handle)
+}
+
+/// Draws a bar for each file `scheduler` is transferring, up to
+/// [`MAX_FILE_BARS`] of them, below the bar of the whole transfer, until
+/// the scheduler is dropped.
+fn file_bars(scheduler: &Arc<Scheduler>) -> JoinHandle<()> {
+    let scheduler = Arc::downgrade(scheduler);
+    let style = ProgressStyle::with_template("  {bar:20} {bytes}/{total_bytes} {msg}")
+        .expect("valid template");
+    tokio::spawn(async move {
+        let mut bars: Vec<(String, ProgressBar)> = Vec::new();
+        let mut ticks = tokio::time::interval(Duration::from_millis(250));
+        loop {
+            ticks.tick().await;
+            let Some(scheduler) = scheduler.upgrade() else {
+                break;
+            };
+            let files = scheduler.files();
+            drop(scheduler);
+            let active: Vec<_> = files
+                .iter()
+                .filter(|file| !file.done && (file.in_flight > 0 || file.transferred > 0))
+                .collect();
+            bars.retain(|(name, bar)| {
+                let keep = active.iter().any(|file| &file.name == name);
+                if !keep {
+                    bar.finish_and_clear();
+                    BARS.remove(bar);
+                }
+                keep
+            });
+            for file in active {
+                match bars.iter().find(|(name, _)| *name == file.name) {
+                    Some((_, bar)) => bar.set_position(file.transferred),
+                    None if bars.len() < MAX_FILE_BARS => {
+                        let bar = BARS.add(ProgressBar::new(file.size));
+                        bar.set_style(style.clone());
+                        bar.set_message(file.name.clone());
+                        bar.set_position(file.transferred);
+                        bars.push((file.name.clone(), bar));
+                    }
+                    None => {}
+                }
+            }
+        }
+        for (_, bar) in bars {
+            bar.finish_and_clear();
+            BARS.remove(&bar);
+        }
+    })
+}
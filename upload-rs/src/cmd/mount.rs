@@ -0,0 +1,56 @@
+use fuser::MountOption;
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+use indexd_utils::keys;
+use indexd_utils::manifest::StoredManifest;
+use indexd_utils::mount::{CatalogFs, Tree};
+use log::{info, warn};
+use tokio::runtime::Handle;
+
+use crate::cli::MountArgs;
+
+/// Mounts every catalogued object until the filesystem is unmounted.
+/// Sealed objects are included if they open with the passphrase.
+pub async fn run(settings: &Settings, args: MountArgs) -> Result<()> {
+    let catalog = settings.catalog()?;
+    let mut tree = Tree::new();
+    let mut passphrase = None;
+    for entry in catalog.list("")? {
+        let Some(stored) = catalog.get(&entry.name)? else {
+            continue;
+        };
+        let manifest = match stored {
+            StoredManifest::Plain(manifest) => manifest,
+            StoredManifest::Sealed(sealed) => {
+                if passphrase.is_none() {
+                    passphrase = Some(keys::read_passphrase(false)?);
+                }
+                let passphrase = passphrase.as_deref().unwrap_or_default();
+                match sealed.open_with_passphrase(passphrase).await {
+                    Ok(manifest) => manifest,
+                    Err(e) => {
+                        warn!("skipping {}: {e}", entry.name);
+                        continue;
+                    }
+                }
+            }
+        };
+        tree.insert(&entry.name, manifest, entry.updated_at);
+    }
+
+    let sdk = client::connect(settings).await?;
+    let fs = CatalogFs::new(tree, sdk, Handle::current(), args.cache_size << 20);
+    let options = [
+        MountOption::RO,
+        MountOption::FSName("indexd".into()),
+        MountOption::DefaultPermissions,
+    ];
+    info!("mounted on {}", args.mountpoint.display());
+    let mountpoint = args.mountpoint.clone();
+    tokio::task::spawn_blocking(move || fuser::mount2(fs, &mountpoint, &options))
+        .await
+        .expect("mount panicked")?;
+    info!("unmounted {}", args.mountpoint.display());
+    Ok(())
+}
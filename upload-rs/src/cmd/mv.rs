@@ -0,0 +1,25 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use log::info;
+use serde_json::json;
+
+use crate::cli::MvArgs;
+
+/// Renames a catalogued object along with its previous versions, leaving
+/// its slabs where they are.
+pub fn run(settings: &Settings, args: MvArgs) -> Result<()> {
+    if args.src == args.dst {
+        return Ok(());
+    }
+    let catalog = settings.catalog()?;
+    if !catalog.rename(&args.src, &args.dst)? {
+        return Err(Error::Manifest(format!(
+            "{} is not in the catalog",
+            args.src
+        )));
+    }
+    info!("renamed {} to {}", args.src, args.dst);
+    settings
+        .output
+        .print(&json!({ "renamed": args.src, "to": args.dst }), || {})
+}
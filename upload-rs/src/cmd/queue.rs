@@ -0,0 +1,98 @@
+use std::net::SocketAddr;
+
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::uploader::Job;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::cli::{QueueArgs, QueueCommand};
+
+pub async fn run(settings: &Settings, args: QueueArgs) -> Result<()> {
+    let (daemon, token) = (args.daemon, args.token.as_deref());
+    let jobs: Vec<Job> = match args.command {
+        QueueCommand::Add(add) => {
+            // the daemon resolves paths against its own working directory
+            let path = std::path::absolute(&add.path)?;
+            let body = json!({ "path": path, "name": add.name });
+            vec![request(daemon, token, "POST", "/uploads", Some(body)).await?]
+        }
+        QueueCommand::List => request(daemon, token, "GET", "/uploads", None).await?,
+        QueueCommand::Pause(job) => vec![control(daemon, token, job.id, "pause").await?],
+        QueueCommand::Resume(job) => vec![control(daemon, token, job.id, "resume").await?],
+        QueueCommand::Cancel(job) => vec![control(daemon, token, job.id, "cancel").await?],
+    };
+    for job in &jobs {
+        settings.output.print(job, || {
+            let error = job.error.as_deref().unwrap_or("");
+            println!(
+                "{}\t{}\t{}/{}\t{}\t{error}",
+                job.id, job.state, job.uploaded, job.size, job.name
+            );
+        })?;
+    }
+    Ok(())
+}
+
+async fn control(daemon: SocketAddr, token: Option<&str>, id: u64, action: &str) -> Result<Job> {
+    request(
+        daemon,
+        token,
+        "POST",
+        &format!("/uploads/{id}/{action}"),
+        None,
+    )
+    .await
+}
+
+/// Sends one request to the daemon's HTTP API, with its token if it has one,
+/// and decodes the JSON response. The API only ever serves small JSON
+/// bodies, so a single HTTP/1.1 exchange on a fresh connection is enough.
+async fn request<T: DeserializeOwned>(
+    daemon: SocketAddr,
+    token: Option<&str>,
+    method: &str,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> Result<T> {
+    let body = match body {
+        Some(body) => serde_json::to_vec(&body)?,
+        None => Vec::new(),
+    };
+    let mut stream = TcpStream::connect(daemon)
+        .await
+        .map_err(|e| Error::Daemon(format!("can't connect to {daemon}, is it running? {e}")))?;
+    let authorization = token
+        .map(|token| format!("authorization: Bearer {token}\r\n"))
+        .unwrap_or_default();
+    let head = format!(
+        "{method} {path} HTTP/1.1\r\nhost: {daemon}\r\n{authorization}connection: close\r\n\
+         content-type: application/json\r\ncontent-length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| Error::Daemon("malformed response".into()))?;
+    let status = std::str::from_utf8(&response[..split])
+        .ok()
+        .and_then(|head| head.split(' ').nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| Error::Daemon("malformed status line".into()))?;
+    let body = &response[split + 4..];
+    if !(200..300).contains(&status) {
+        let message = serde_json::from_slice::<serde_json::Value>(body)
+            .ok()
+            .and_then(|body| body["error"].as_str().map(str::to_string))
+            .unwrap_or_else(|| format!("status {status}"));
+        return Err(Error::Daemon(message));
+    }
+    Ok(serde_json::from_slice(body)?)
+}
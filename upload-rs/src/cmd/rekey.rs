@@ -0,0 +1,104 @@
+use indexd_utils::client::{self, Client};
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::keys::{self, Kdf};
+use indexd_utils::manifest::{AnyManifest, Manifest, Sealing};
+use indexd_utils::output::Output;
+use indexd_utils::rekey;
+use log::info;
+use serde_json::json;
+
+use super::{open_manifest, progress_bar, store_manifest};
+use crate::cli::RekeyArgs;
+
+pub async fn run(settings: &Settings, args: RekeyArgs) -> Result<()> {
+    let (mut manifest, sealing, location) = open_manifest(settings, &args.manifest).await?;
+
+    // a sealed manifest's object keys come from its passphrase, so the new
+    // ones have to come from a new passphrase too; it stays shared with the
+    // same recipients
+    let (master_key, sealing) = match (&sealing, args.new_key, args.passphrase) {
+        (_, _, true) => {
+            let kdf = Kdf::default();
+            let passphrase = keys::read_passphrase(true)?;
+            let key = kdf.derive(&passphrase).await?;
+            let recipients = sealing.as_ref().map(|s| s.recipients.clone());
+            let sealing = Sealing {
+                recipients: recipients.unwrap_or_default(),
+                ..Sealing::new(kdf, key)
+            };
+            (key, Some(sealing))
+        }
+        (Some(_), _, false) => {
+            return Err(Error::Usage(
+                "a sealed manifest is rekeyed with a new passphrase, pass --passphrase".into(),
+            ));
+        }
+        (None, Some(key), false) => (parse_key(&key)?, None),
+        (None, None, false) => (rand::random(), None),
+    };
+
+    let sdk = client::connect(settings).await?;
+    match &mut manifest {
+        AnyManifest::File(file) => {
+            let name = location.to_string();
+            rekey_file(&sdk, file, &master_key, &name, settings.output).await?;
+        }
+        AnyManifest::Directory(dir) => {
+            // each file gets its own key, as a directory upload derives them
+            for (i, entry) in dir.files.iter_mut().enumerate() {
+                let key = keys::subkey(&master_key, &format!("file/{i}"));
+                rekey_file(
+                    &sdk,
+                    &mut entry.manifest,
+                    &key,
+                    &entry.path,
+                    settings.output,
+                )
+                .await?;
+            }
+        }
+    }
+
+    // the manifest is only replaced once every slab has a new copy
+    store_manifest(manifest, &location, sealing.as_ref()).await?;
+    info!("rekeyed, manifest saved to {location}");
+    Ok(())
+}
+
+/// Rotates one file's slabs to keys derived from `master_key`.
+async fn rekey_file(
+    sdk: &Client,
+    manifest: &mut Manifest,
+    master_key: &[u8; 32],
+    name: &str,
+    output: Output,
+) -> Result<()> {
+    let (progress, bar) = progress_bar(manifest.stored_size(), 0);
+    let result = rekey::rekey(sdk, manifest, master_key, &progress).await;
+    drop(progress);
+    let _ = bar.await;
+    let rekeyed = result.map_err(|e| Error::Manifest(format!("{name}: {e}")))?;
+
+    let result = json!({
+        "name": name,
+        "slabs": rekeyed.slabs.len(),
+        "key_fingerprint": rekeyed.key_fingerprint,
+    });
+    output.print(&result, || {
+        println!(
+            "{name}: {} slabs re-encrypted, key {}",
+            rekeyed.slabs.len(),
+            rekeyed.key_fingerprint
+        );
+    })?;
+    *manifest = rekeyed;
+    Ok(())
+}
+
+fn parse_key(key: &str) -> Result<[u8; 32]> {
+    let mut bytes = [0u8; 32];
+    hex::decode_to_slice(key, &mut bytes)
+        .map_err(|_| Error::Usage("--new-key must be 32 bytes of hex".into()))?;
+    Ok(bytes)
+}
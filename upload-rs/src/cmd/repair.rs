@@ -0,0 +1,132 @@
+use std::path::Path;
+
+use indexd_utils::client::{self, Client};
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::manifest::{AnyManifest, Manifest};
+use indexd_utils::output::Output;
+use indexd_utils::redundancy::Redundancy;
+use indexd_utils::repair::{self, RepairOptions};
+use indexd_utils::verify::Health;
+use log::info;
+use serde_json::json;
+
+use super::{open_manifest, progress_bar, redundancy_or, store_manifest, targets};
+use crate::cli::RepairArgs;
+
+pub async fn run(settings: &Settings, args: RepairArgs) -> Result<()> {
+    let targets = targets(settings, args.manifest.as_deref(), &args.tags)?;
+    let sdk = client::connect(settings).await?;
+    let (mut unrecoverable, mut failed) = (0, 0);
+    for target in &targets {
+        let (u, f) = repair_target(settings, &sdk, target, &args).await?;
+        unrecoverable += u;
+        failed += f;
+    }
+    if unrecoverable > 0 {
+        return Err(Error::Integrity(format!(
+            "{unrecoverable} slabs are unrecoverable"
+        )));
+    }
+    if failed > 0 {
+        return Err(Error::Partial(format!(
+            "{failed} slabs could not be replaced; run repair again to retry them"
+        )));
+    }
+    Ok(())
+}
+
+/// Repairs one manifest or catalogued object and saves it if any slab was
+/// replaced, returning the number of slabs that could not be recovered and
+/// the number that were recovered but could not be replaced.
+async fn repair_target(
+    settings: &Settings,
+    sdk: &Client,
+    target: &Path,
+    args: &RepairArgs,
+) -> Result<(usize, usize)> {
+    let (mut manifest, sealing, location) = open_manifest(settings, target).await?;
+    let (replaced, unrecoverable, failed) = match &mut manifest {
+        AnyManifest::File(file) => {
+            let name = location.to_string();
+            repair_file(sdk, file, args, &name, settings.output).await?
+        }
+        AnyManifest::Directory(dir) => {
+            let mut totals = (0, 0, 0);
+            for entry in &mut dir.files {
+                let (r, u, f) =
+                    repair_file(sdk, &mut entry.manifest, args, &entry.path, settings.output)
+                        .await?;
+                totals.0 += r;
+                totals.1 += u;
+                totals.2 += f;
+            }
+            totals
+        }
+    };
+
+    if replaced > 0 {
+        store_manifest(manifest, &location, sealing.as_ref()).await?;
+        info!("replaced {replaced} slabs, manifest saved to {location}");
+    }
+    Ok((unrecoverable, failed))
+}
+
+/// Repairs one file in place, returning the number of slabs replaced, the
+/// number that could not be recovered and the number that were recovered
+/// but could not be replaced.
+async fn repair_file(
+    sdk: &Client,
+    manifest: &mut Manifest,
+    args: &RepairArgs,
+    name: &str,
+    output: Output,
+) -> Result<(usize, usize, usize)> {
+    // slabs are repaired with the redundancy recorded in the manifest
+    // unless another is given
+    let mut options = RepairOptions::new(manifest);
+    let recorded = Redundancy {
+        data_shards: manifest.data_shards,
+        parity_shards: manifest.parity_shards,
+    };
+    let redundancy = redundancy_or(&args.redundancy, recorded)?;
+    options.data_shards = redundancy.data_shards;
+    options.parity_shards = redundancy.parity_shards;
+    options.migrate = args.all;
+
+    let (progress, bar) = progress_bar(manifest.stored_size(), 0);
+    let result = repair::repair(sdk, manifest, options, &progress).await;
+    drop(progress);
+    let _ = bar.await;
+    let repair = result.map_err(|e| Error::Manifest(format!("{name}: {e}")))?;
+
+    let result = json!({
+        "name": name,
+        "replaced": repair.replaced(),
+        "unrecoverable": repair.unrecoverable(),
+        "slabs": repair.slabs,
+    });
+    output.print(&result, || {
+        for slab in &repair.slabs {
+            match (slab.health, slab.replaced, &slab.error) {
+                (Health::Unrecoverable, _, Some(e)) => {
+                    println!("{name}: slab {}: unrecoverable: {e}", slab.index)
+                }
+                (_, false, Some(e)) => {
+                    println!("{name}: slab {}: repair failed: {e}", slab.index)
+                }
+                (Health::Degraded, true, _) => println!("{name}: slab {}: repaired", slab.index),
+                _ => {}
+            }
+        }
+        println!(
+            "{name}: {} replaced, {} unrecoverable",
+            repair.replaced(),
+            repair.unrecoverable()
+        );
+    })?;
+
+    let counts = (repair.replaced(), repair.unrecoverable(), repair.failed());
+    *manifest = repair.manifest;
+    Ok(counts)
+}
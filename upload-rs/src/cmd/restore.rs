@@ -0,0 +1,93 @@
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Instant;
+
+use indexd_utils::checksum;
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::directory;
+use indexd_utils::error::Result;
+use indexd_utils::manifest::FileEntry;
+use indexd_utils::selection::Selection;
+use log::info;
+use serde_json::json;
+use tokio::fs;
+
+use super::backup::open_snapshot;
+use super::download::{DirectoryOptions, download_directory};
+use super::progress_bar;
+use crate::cli::RestoreArgs;
+
+/// Recreates the directory captured by a snapshot under the output path,
+/// including the files' modification times, or the part of it `--include`
+/// selects, in the order `--priority` and `--order` give.
+pub async fn run(settings: &Settings, args: RestoreArgs) -> Result<()> {
+    let catalog = settings.catalog()?;
+    let (manifest, _) = open_snapshot(&catalog, args.snapshot).await?;
+    let selection = Selection::default()
+        .with_include(&args.include)?
+        .with_priority(&args.priority)?
+        .with_order(args.order);
+    let mut selected = selection.apply(&manifest);
+    let mut skipped = 0;
+    if args.skip_existing || args.skip_matching {
+        let mut files = Vec::with_capacity(selected.files.len());
+        for entry in selected.files {
+            let path = directory::resolve(&args.output, &entry.path)?;
+            if skip(&path, &entry, args.skip_matching).await? {
+                info!("skipping {}", path.display());
+                skipped += 1;
+            } else {
+                files.push(entry);
+            }
+        }
+        selected.files = files;
+    }
+    let sdk = client::connect(settings).await?;
+
+    info!(
+        "restoring {} of {} files from snapshot {}",
+        selected.files.len(),
+        manifest.files.len(),
+        args.snapshot
+    );
+    let start = Instant::now();
+    let (progress, bar) = progress_bar(selected.size(), 0);
+    let options = DirectoryOptions {
+        concurrency: args.concurrency.unwrap_or(settings.concurrency),
+        resume: false,
+        verify: true,
+        preserve: true,
+        // the selection has already put the files in order
+        in_order: true,
+    };
+    download_directory(&sdk, &selected, &args.output, &options, progress).await?;
+    let _ = bar.await;
+    info!("restore complete in {}ms", start.elapsed().as_millis());
+    let result = json!({
+        "snapshot": args.snapshot,
+        "output": args.output,
+        "files": selected.files.len(),
+        "skipped": skipped,
+        "size": selected.size(),
+        "elapsed_ms": start.elapsed().as_millis() as u64,
+    });
+    settings.output.print(&result, || {})
+}
+
+/// Whether the file already at `path` lets `entry` be skipped: any file
+/// does, or with `matching` only one with the entry's size and checksum.
+async fn skip(path: &Path, entry: &FileEntry, matching: bool) -> Result<bool> {
+    let metadata = match fs::symlink_metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    if !matching {
+        return Ok(true);
+    }
+    if !metadata.is_file() || metadata.len() != entry.manifest.size {
+        return Ok(false);
+    }
+    Ok(checksum::checksum_file(path).await? == entry.manifest.checksum)
+}
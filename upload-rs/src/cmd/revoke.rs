@@ -0,0 +1,52 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::keys::{self, Kdf};
+use indexd_utils::manifest::{Sealing, StoredManifest};
+use log::{info, warn};
+
+use super::grant::print_recipients;
+use super::{Location, store_manifest};
+use crate::cli::RevokeArgs;
+
+/// Seals a manifest again without the revoked recipients.
+///
+/// A revoked recipient may have kept the manifest key, so the manifest is
+/// sealed under a key derived from the same passphrase with a fresh salt,
+/// which the remaining recipients are given instead.
+pub async fn run(settings: &Settings, args: RevokeArgs) -> Result<()> {
+    let location = Location::resolve(settings, &args.manifest)?;
+    let StoredManifest::Sealed(sealed) = location.load(settings).await? else {
+        return Err(Error::Usage(format!("{location} is not sealed")));
+    };
+    let passphrase = keys::read_passphrase(false)?;
+    let manifest = sealed.open_with_passphrase(&passphrase).await?;
+
+    let mut recipients: Vec<_> = sealed
+        .recipients
+        .iter()
+        .map(|stanza| stanza.recipient)
+        .collect();
+    let shared = recipients.len();
+    recipients.retain(|recipient| !args.recipients.contains(recipient));
+    if recipients.len() == shared {
+        return Err(Error::Usage(format!(
+            "{location} is not shared with any of the recipients given"
+        )));
+    }
+    let kdf = Kdf::default();
+    let key = kdf.derive(&passphrase).await?;
+    let sealing = Sealing {
+        recipients,
+        ..Sealing::new(kdf, key)
+    };
+    store_manifest(manifest, &location, Some(&sealing)).await?;
+    info!(
+        "revoked {} recipients of {location}",
+        shared - sealing.recipients.len()
+    );
+    warn!(
+        "revoked recipients can still download the data of any copy of the manifest they \
+         opened; rekey it to rotate the slabs too"
+    );
+    print_recipients(settings, &location.to_string(), &sealing)
+}
@@ -0,0 +1,93 @@
+use indexd_utils::catalog::Catalog;
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::manifest::{AnyManifest, StoredManifest};
+use log::{info, warn};
+use serde_json::json;
+use tokio::fs;
+
+use super::targets;
+use crate::cli::RmArgs;
+
+/// Removes a manifest file, or an object from the catalog if no such file
+/// exists, or every catalogued object with the given tags. A catalogued
+/// object goes to the trash, to be purged once the trash retention passes,
+/// unless `--purge` removes it right away.
+///
+/// The indexd SDK does not yet expose a way to delete or unpin slabs, so
+/// the slabs the manifest refers to stay stored and keep counting against
+/// the account; the JSON reports how many were left behind as `retained`.
+/// Once the SDK can release them this should do so before the manifest is
+/// removed, reporting each slab's outcome.
+pub async fn run(settings: &Settings, args: RmArgs) -> Result<()> {
+    let path = match &args.manifest {
+        Some(path) if path.exists() => path,
+        _ => {
+            let catalog = settings.catalog()?;
+            super::trash::expire(settings, &catalog)?;
+            // tagged objects are always catalog names, whatever files exist
+            for name in targets(settings, args.manifest.as_deref(), &args.tags)? {
+                remove_object(settings, &catalog, &name.to_string_lossy(), args.purge)?;
+            }
+            return Ok(());
+        }
+    };
+
+    // make sure it is a manifest before removing it
+    let manifest = StoredManifest::load(path).await?;
+    fs::remove_file(path).await?;
+    // the slabs of a sealed manifest can't be counted without its passphrase
+    let retained = match manifest {
+        StoredManifest::Plain(AnyManifest::File(manifest)) => Some(manifest.slabs.len()),
+        StoredManifest::Plain(AnyManifest::Directory(manifest)) => Some(
+            manifest
+                .files
+                .iter()
+                .map(|f| f.manifest.slabs.len())
+                .sum::<usize>(),
+        ),
+        StoredManifest::Sealed(_) => None,
+    };
+    match retained {
+        Some(slabs) => warn!("{slabs} slabs are still stored; they are not released by rm"),
+        None => warn!("slabs are still stored; they are not released by rm"),
+    }
+    info!("removed {}", path.display());
+    settings
+        .output
+        .print(&json!({ "removed": path, "retained": retained }), || {})
+}
+
+/// Moves a catalogued object to the trash, or with `purge` removes it and
+/// its versions for good.
+fn remove_object(settings: &Settings, catalog: &Catalog, name: &str, purge: bool) -> Result<()> {
+    if purge {
+        // copies and snapshots may share the object's slabs
+        let release = catalog.release(name)?;
+        if !catalog.remove(name)? {
+            return Err(Error::Manifest(format!("{name} is not in the catalog")));
+        }
+        if release.shared > 0 {
+            info!(
+                "{} slabs are kept for the other objects that refer to them",
+                release.shared
+            );
+        }
+        if release.released > 0 {
+            warn!(
+                "{} slabs ({} bytes) nothing else refers to are still stored; they are not \
+                 released by rm",
+                release.released, release.released_bytes
+            );
+        }
+        info!("removed {name} from the catalog");
+        return settings
+            .output
+            .print(&json!({ "removed": name, "release": release }), || {});
+    }
+    if !catalog.trash(name)? {
+        return Err(Error::Manifest(format!("{name} is not in the catalog")));
+    }
+    info!("moved {name} to the trash; restore it with trash restore {name}");
+    settings.output.print(&json!({ "trashed": name }), || {})
+}
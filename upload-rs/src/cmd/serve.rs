@@ -0,0 +1,72 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use indexd_utils::bucket::Buckets;
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::fileserver::FileServer;
+use indexd_utils::keys;
+use indexd_utils::metrics;
+use indexd_utils::restic::Restic;
+use indexd_utils::webdav::WebDav;
+use log::info;
+use tokio::net::TcpListener;
+
+use crate::cli::ServeArgs;
+
+/// Serves the catalogued buckets until interrupted. Every bucket is
+/// unlocked with the same passphrase.
+pub async fn run(settings: &Settings, args: ServeArgs) -> Result<()> {
+    if let Some(addr) = args.http {
+        return serve_http(settings, addr, args).await;
+    }
+    if !args.webdav && !args.restic {
+        return Err(Error::Usage(
+            "choose a protocol to serve: --webdav, --restic or --http".into(),
+        ));
+    }
+    let passphrase = keys::read_passphrase(false)?;
+    let buckets = Buckets::new(settings.catalog()?, passphrase, settings.upload_options());
+    let sdk = client::connect(settings).await?;
+
+    let credentials = args.user.zip(args.password);
+    let (app, protocol) = if args.restic {
+        let restic = Restic::new(sdk, buckets, credentials, args.append_only);
+        (Arc::new(restic).router(), "restic's REST protocol")
+    } else {
+        (
+            Arc::new(WebDav::new(sdk, buckets, credentials)).router(),
+            "WebDAV",
+        )
+    };
+    if let Some(addr) = args.metrics {
+        metrics::serve(addr).await?;
+    }
+    let listener = TcpListener::bind(args.listen).await?;
+    info!("serving {protocol} on {}", args.listen);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Serves the catalogued objects read-only over HTTP until interrupted. A
+/// passphrase is only asked for if some of them are sealed.
+async fn serve_http(settings: &Settings, addr: SocketAddr, args: ServeArgs) -> Result<()> {
+    let catalog = settings.catalog()?;
+    let passphrase = if catalog.list("")?.iter().any(|entry| entry.sealed) {
+        Some(keys::read_passphrase(false)?)
+    } else {
+        None
+    };
+    let sdk = client::connect(settings).await?;
+
+    let credentials = args.user.zip(args.password);
+    let app = Arc::new(FileServer::new(sdk, catalog, passphrase, credentials)).router();
+    if let Some(addr) = args.metrics {
+        metrics::serve(addr).await?;
+    }
+    let listener = TcpListener::bind(addr).await?;
+    info!("serving the catalog over HTTP on {addr}");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
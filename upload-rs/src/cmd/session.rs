@@ -0,0 +1,117 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::journal::{self, Record, Session};
+use serde_json::json;
+
+use crate::cli::{SessionArgs, SessionCommand};
+
+pub fn run(settings: &Settings, args: SessionArgs) -> Result<()> {
+    let dir = journal::default_dir()
+        .ok_or_else(|| Error::Config("no journal directory; set HOME".into()))?;
+    match args.command {
+        SessionCommand::List => {
+            for session in journal::list(&dir)? {
+                settings.output.print(&session, || {
+                    println!(
+                        "{}\t{}\t{} transfers\t{} failed\t{} bytes\t{}{}",
+                        session.id,
+                        date(session.started_at),
+                        session.transfers,
+                        session.failed,
+                        session.bytes,
+                        session.command.join(" "),
+                        if session.ended_at.is_none() {
+                            " (unfinished)"
+                        } else {
+                            ""
+                        }
+                    );
+                })?;
+            }
+            Ok(())
+        }
+        SessionCommand::Show { id } => {
+            let id = if id == "last" {
+                journal::ids(&dir)?
+                    .pop()
+                    .ok_or_else(|| Error::NotFound("no journalled sessions".into()))?
+            } else {
+                id
+            };
+            let records = journal::read(&dir, &id)?;
+            show(settings, &Session::new(&id, &records), &records)
+        }
+    }
+}
+
+fn show(settings: &Settings, session: &Session, records: &[Record]) -> Result<()> {
+    let mut hosts: Vec<_> = journal::hosts(records).into_iter().collect();
+    hosts.sort_by(|(_, a), (_, b)| {
+        b.failed
+            .cmp(&a.failed)
+            .then(b.average_ms().cmp(&a.average_ms()))
+    });
+    let transfers: Vec<&Record> = records
+        .iter()
+        .filter(|record| matches!(record, Record::Finish { .. }))
+        .collect();
+    let result = json!({
+        "session": session,
+        "transfers": transfers,
+        "hosts": hosts
+            .iter()
+            .map(|(host, summary)| json!({
+                "host": host,
+                "transfers": summary.transfers,
+                "failed": summary.failed,
+                "average_ms": summary.average_ms(),
+                "slowest_ms": summary.slowest_ms,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    settings.output.print(&result, || {
+        println!(
+            "{}: {} at {}, {} transfers, {} failed, {} bytes",
+            session.id,
+            session.command.join(" "),
+            date(session.started_at),
+            session.transfers,
+            session.failed,
+            session.bytes
+        );
+        for record in &transfers {
+            let Record::Finish {
+                transfer,
+                direction,
+                bytes,
+                elapsed_ms,
+                error,
+                shards,
+                ..
+            } = record
+            else {
+                continue;
+            };
+            println!(
+                "#{transfer}\t{direction:?}\t{bytes} bytes\t{elapsed_ms}ms\t{} shards\t{}",
+                shards.len(),
+                error.as_deref().unwrap_or("ok")
+            );
+        }
+        for (host, summary) in &hosts {
+            println!(
+                "{host}\t{} transfers\t{} failed\t{}ms average\t{}ms slowest",
+                summary.transfers,
+                summary.failed,
+                summary.average_ms(),
+                summary.slowest_ms
+            );
+        }
+    })
+}
+
+fn date(ms: u64) -> String {
+    httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_millis(ms))
+}
@@ -0,0 +1,40 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::manifest::AnyManifest;
+use indexd_utils::share::{Range, Share};
+use log::info;
+use serde_json::json;
+use tokio::fs;
+
+use super::load_manifest;
+use crate::cli::ShareArgs;
+
+pub async fn run(settings: &Settings, args: ShareArgs) -> Result<()> {
+    let AnyManifest::File(manifest) = load_manifest(settings, &args.manifest).await? else {
+        return Err(Error::Usage("only file manifests can be shared".into()));
+    };
+    let range = args.length.map(|length| {
+        let offset = args.offset.unwrap_or(0);
+        Range {
+            offset,
+            length: length.min(manifest.size.saturating_sub(offset)),
+        }
+    });
+    let share = Share::new(&manifest, range, args.expires)?;
+    let token = share.encode()?;
+
+    if let Some(path) = &args.output {
+        fs::write(path, format!("{token}\n")).await?;
+        info!("share written to {}", path.display());
+    }
+    let result = json!({
+        "token": token,
+        "size": share.manifest.size,
+        "expires_at": share.expires_at,
+    });
+    settings.output.print(&result, || {
+        if args.output.is_none() {
+            println!("{token}");
+        }
+    })
+}
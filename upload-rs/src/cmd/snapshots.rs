@@ -0,0 +1,24 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+
+use crate::cli::SnapshotsArgs;
+
+pub fn run(settings: &Settings, args: SnapshotsArgs) -> Result<()> {
+    for snapshot in settings.catalog()?.list_snapshots(args.name.as_deref())? {
+        settings.output.print(&snapshot, || {
+            println!(
+                "{}\t{}\t{}\t{}\t{} files{}\t{}",
+                snapshot.id,
+                snapshot.name,
+                httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(snapshot.created_at)),
+                snapshot.size,
+                snapshot.files,
+                if snapshot.sealed { ", sealed" } else { "" },
+                snapshot.source,
+            )
+        })?;
+    }
+    Ok(())
+}
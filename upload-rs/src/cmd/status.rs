@@ -0,0 +1,27 @@
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+use indexd_utils::status::Usage;
+
+pub fn run(settings: &Settings) -> Result<()> {
+    let usage = Usage::new(&settings.catalog()?, settings.budget.as_ref())?;
+    settings.output.print(&usage, || {
+        println!("objects:    {}", usage.objects);
+        println!("snapshots:  {}", usage.snapshots);
+        println!("size:       {} bytes", usage.size);
+        println!("slabs:      {}", usage.slabs);
+        println!("stored:     {} bytes", usage.stored);
+        if usage.sealed > 0 {
+            println!(
+                "            not counting the slabs of {} sealed manifests",
+                usage.sealed
+            );
+        }
+        println!("30 days:    {} bytes uploaded", usage.uploaded_recently);
+        if let Some(spent) = usage.spent {
+            println!("spent:      {spent:.4} in the last 30 days");
+        }
+        if let Some(remaining) = usage.remaining {
+            println!("remaining:  {remaining:.4} of the monthly budget");
+        }
+    })
+}
@@ -0,0 +1,94 @@
+use std::path::Path;
+use std::time::Duration;
+
+use indexd_utils::catalog::Catalog;
+use indexd_utils::client;
+use indexd_utils::config::Settings;
+use indexd_utils::error::Result;
+use indexd_utils::keys::{self, Kdf};
+use indexd_utils::manifest::{Sealing, StoredManifest};
+use indexd_utils::metrics;
+use indexd_utils::sync::{Change, Syncer};
+use indexd_utils::upload::UploadOptions;
+use log::info;
+
+use super::{check_budget, filter, redundancy};
+use crate::cli::SyncArgs;
+
+pub async fn run(settings: &Settings, args: SyncArgs) -> Result<()> {
+    let catalog = settings.catalog()?;
+    let sealing = if args.passphrase {
+        let kdf = sealing_kdf(&catalog, &args.prefix)?;
+        let passphrase = keys::read_passphrase(kdf.is_none())?;
+        let kdf = kdf.unwrap_or_default();
+        let key = kdf.derive(&passphrase).await?;
+        Some(Sealing::new(kdf, key))
+    } else {
+        None
+    };
+    let (data_shards, parity_shards) = redundancy(settings, &args.redundancy)?;
+    let options = UploadOptions {
+        data_shards,
+        parity_shards,
+        ..settings.upload_options()
+    };
+    let syncer = Syncer::new(&args.dir, &args.prefix, options, sealing)
+        .with_filter(filter(&args.filter).await?)
+        .with_budget(settings.budget)
+        .with_output(settings.output);
+
+    let plan = syncer.plan(&catalog, args.delete).await?;
+    for action in &plan {
+        settings.output.print(action, || println!("{action}"))?;
+    }
+    if args.dry_run || (plan.is_empty() && !args.watch) {
+        return Ok(());
+    }
+
+    if let Some(budget) = &settings.budget {
+        let uploads: Vec<&Path> = plan
+            .iter()
+            .filter(|a| a.change != Change::Deleted)
+            .map(|a| a.rel.as_path())
+            .collect();
+        let charge = syncer.charge(&uploads).await?.unwrap_or_default();
+        check_budget(budget, &catalog, charge, true)?;
+    }
+
+    let sdk = client::connect(settings).await?;
+    for action in &plan {
+        syncer.apply(&sdk, &catalog, action).await?;
+        info!("synced {}", action.name);
+    }
+    let uploaded = plan.iter().filter(|a| a.change != Change::Deleted).count();
+    info!(
+        "uploaded {uploaded} files and removed {}",
+        plan.len() - uploaded
+    );
+    if args.watch {
+        if let Some(addr) = args.metrics {
+            metrics::serve(addr).await?;
+        }
+        let debounce = Duration::from_millis(args.debounce);
+        return syncer
+            .watch(&sdk, &catalog, args.delete, debounce, args.queue)
+            .await;
+    }
+    Ok(())
+}
+
+/// Returns the key parameters of the sealed objects already under
+/// `prefix`, so a sync keeps sealing with the same key and can compare
+/// against them.
+fn sealing_kdf(catalog: &Catalog, prefix: &str) -> Result<Option<Kdf>> {
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    for entry in catalog.list(prefix.trim_start_matches('/'))? {
+        if !entry.sealed {
+            continue;
+        }
+        if let Some(StoredManifest::Sealed(sealed)) = catalog.get(&entry.name)? {
+            return Ok(Some(sealed.kdf));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,58 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use indexd_utils::catalog::Catalog;
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use log::info;
+use serde_json::json;
+
+use crate::cli::{TrashArgs, TrashCommand};
+
+pub fn run(settings: &Settings, args: TrashArgs) -> Result<()> {
+    let catalog = settings.catalog()?;
+    expire(settings, &catalog)?;
+    match args.command {
+        TrashCommand::List => {
+            for trashed in catalog.list_trash()? {
+                let expires_at = trashed.deleted_at + settings.trash_retention.as_secs();
+                settings.output.print(&trashed, || {
+                    println!(
+                        "{}\t{}\tpurged {}",
+                        trashed.name,
+                        trashed.size,
+                        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(expires_at)),
+                    );
+                })?;
+            }
+            Ok(())
+        }
+        TrashCommand::Restore { name } => {
+            if catalog.contains(&name)? {
+                return Err(Error::Usage(format!(
+                    "{name} was uploaded again since it was removed; its old manifest is a \
+                     previous version, see ls --versions"
+                )));
+            }
+            if !catalog.untrash(&name)? {
+                return Err(Error::Manifest(format!("{name} is not in the trash")));
+            }
+            info!("restored {name}");
+            settings.output.print(&json!({ "restored": name }), || {})
+        }
+        TrashCommand::Empty => {
+            let purged = catalog.expire_trash(Duration::ZERO)?;
+            info!("purged {} objects", purged.len());
+            settings.output.print(&json!({ "purged": purged }), || {})
+        }
+    }
+}
+
+/// Purges the objects that have been in the trash longer than the
+/// retention.
+pub(super) fn expire(settings: &Settings, catalog: &Catalog) -> Result<()> {
+    let expired = catalog.expire_trash(settings.trash_retention)?;
+    if !expired.is_empty() {
+        info!("purged {} objects from the trash", expired.len());
+    }
+    Ok(())
+}
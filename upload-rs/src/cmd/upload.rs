@@ -0,0 +1,729 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use futures::{StreamExt, TryStreamExt, stream};
+use indexd_utils::aead;
+use indexd_utils::budget::Budget;
+use indexd_utils::catalog::Catalog;
+use indexd_utils::client::{self, Client};
+use indexd_utils::compression::Compression;
+use indexd_utils::config::Settings;
+use indexd_utils::dedup;
+use indexd_utils::directory::{self, Links};
+use indexd_utils::error::{Error, Result};
+use indexd_utils::estimate::Estimate;
+use indexd_utils::filter::Filter;
+use indexd_utils::keys::{self, Kdf, KeyProvider, SoftwareKeys};
+use indexd_utils::locator::Locator;
+use indexd_utils::manifest::{
+    AnyManifest, DirectoryManifest, FileEntry, Manifest, Sealing, StoredManifest,
+};
+use indexd_utils::net::Network;
+use indexd_utils::normalize;
+use indexd_utils::output::Output;
+use indexd_utils::pack::{self, Packer};
+use indexd_utils::progress::Progress;
+use indexd_utils::redundancy::{Redundancy, measured_throughput};
+use indexd_utils::scheduler::{self, Scheduler};
+use indexd_utils::shutdown;
+use indexd_utils::source;
+use indexd_utils::tags;
+use indexd_utils::upload::{self, ResumableUpload, UploadOptions};
+use log::info;
+use serde_json::json;
+use tokio::fs::File;
+use tokio::io::AsyncRead;
+use tokio::{fs, io};
+use url::Url;
+
+use super::{check_budget, estimate, file_bars, filter, progress_bar, redundancy, with_suffix};
+use crate::cli::UploadArgs;
+
+/// Settings shared by every file in an upload.
+struct Options {
+    upload: UploadOptions,
+    /// The passphrase-derived master key and the parameters it was derived
+    /// with. When set, the manifest is sealed.
+    sealing: Option<Sealing>,
+    /// The name the upload is catalogued under.
+    name: String,
+    /// The path the upload was read from, if any.
+    source: Option<String>,
+    catalog: Catalog,
+    /// Only upload the chunks not already in the catalog.
+    dedup: bool,
+    /// Which files of a directory are uploaded.
+    filter: Filter,
+    /// Files of a directory up to this size are packed into shared slabs.
+    pack_threshold: u64,
+    /// Record the permission bits and extended attributes of a directory's
+    /// files.
+    preserve: bool,
+    /// What a directory's symbolic links are uploaded as.
+    links: Links,
+    /// What uploads are charged against, if anything.
+    budget: Option<Budget>,
+    /// Also store the sealed manifest on indexd and print its locator.
+    locator: bool,
+    /// Added to the catalogued object.
+    tags: BTreeMap<String, String>,
+    metadata: BTreeMap<String, String>,
+    network: Network,
+    output: Output,
+}
+
+impl Options {
+    /// Returns the master key for the upload: derived from the passphrase
+    /// when sealing, random otherwise.
+    fn master_key(&self) -> [u8; 32] {
+        match &self.sealing {
+            Some(sealing) => sealing.key,
+            None => rand::random(),
+        }
+    }
+
+    /// Returns the provider the keys of a directory's files are derived
+    /// from, if they aren't random.
+    fn keys(&self) -> Option<SoftwareKeys> {
+        self.sealing
+            .as_ref()
+            .map(|sealing| SoftwareKeys::new(sealing.key))
+    }
+
+    fn kdf(&self) -> Option<Kdf> {
+        self.sealing.as_ref().map(|sealing| sealing.kdf.clone())
+    }
+
+    /// Estimates uploading files of the given sizes.
+    fn estimate(&self, sizes: &[u64]) -> Estimate {
+        let redundancy = Redundancy {
+            data_shards: self.upload.data_shards,
+            parity_shards: self.upload.parity_shards,
+        };
+        // deduplicated uploads share slabs between files without packing
+        let pack_threshold = if self.dedup { 0 } else { self.pack_threshold };
+        Estimate::new(sizes, redundancy, pack_threshold)
+    }
+}
+
+pub async fn run(settings: &Settings, mut args: UploadArgs) -> Result<()> {
+    // an object store URL given as the input is read as --from-url is
+    let object_url = args
+        .input
+        .as_deref()
+        .and_then(Path::to_str)
+        .and_then(|input| Url::parse(input).ok())
+        .filter(source::is_object_store);
+    if let Some(url) = object_url {
+        args.input = None;
+        args.from_url = Some(url);
+    }
+    let (data_shards, parity_shards) = redundancy(settings, &args.redundancy)?;
+    // sealed data doesn't compress, so --aead overrides the profile's level
+    let compression = args
+        .compress
+        .map(Compression::zstd)
+        .or(settings.compression)
+        .filter(|_| !args.aead);
+    let mut upload_options = UploadOptions::new(data_shards, parity_shards)
+        .with_compression(compression)
+        .with_aead(args.aead.then_some(aead::Algorithm::XChaCha20Poly1305))
+        .with_sha256(args.sha256 || settings.sha256);
+    if let Some(jobs) = args.jobs.or(settings.jobs) {
+        upload_options = upload_options.with_max_inflight_shards(jobs);
+    }
+    if let Some(read_ahead) = settings.read_ahead {
+        upload_options = upload_options.with_read_ahead(read_ahead);
+    }
+    upload_options = upload_options.with_max_memory(args.max_memory.or(settings.max_memory));
+    let catalog = settings.catalog()?;
+    if let Some(sizing) = args.slab_size.or(settings.slab_sizing) {
+        let throughput = measured_throughput(&catalog.host_stats()?, upload_options.total_shards());
+        upload_options = upload_options.with_slab_sizing(Some(sizing.with_throughput(throughput)));
+    }
+    let mut opts = Options {
+        upload: upload_options,
+        sealing: None,
+        name: String::new(),
+        source: None,
+        catalog,
+        dedup: args.dedup,
+        filter: filter(&args.filter).await?,
+        pack_threshold: args.pack_threshold.unwrap_or(pack::DEFAULT_THRESHOLD),
+        preserve: !args.no_preserve,
+        links: args.links,
+        budget: settings.budget,
+        locator: args.locator,
+        tags: tags::to_map(&args.tags),
+        metadata: tags::to_map(&args.metadata),
+        network: settings.network.clone(),
+        output: settings.output,
+    };
+
+    if args.locator && !args.passphrase && args.resume.is_none() {
+        return Err(Error::Usage(
+            "--locator stores the manifest sealed, so it needs --passphrase".into(),
+        ));
+    }
+
+    if let Some(checkpoint_path) = args.resume {
+        let upload = ResumableUpload::resume(checkpoint_path)
+            .await?
+            .with_max_inflight_shards(opts.upload.max_inflight_shards)
+            .with_read_ahead(opts.upload.read_ahead)
+            .with_max_memory(opts.upload.max_memory);
+        info!("resuming upload at offset {}", upload.offset());
+        opts.sealing = upload
+            .kdf()
+            .cloned()
+            .map(|kdf| Sealing::new(kdf, *upload.encryption_key()));
+        if opts.locator && opts.sealing.is_none() {
+            return Err(Error::Usage(
+                "--locator needs a sealed upload, and this one has no passphrase".into(),
+            ));
+        }
+        opts.source = Some(upload.input().display().to_string());
+        opts.name = catalog_name(&opts.catalog, args.name, Some(upload.input()))?;
+        let manifest_path = args
+            .manifest
+            .unwrap_or_else(|| with_suffix(upload.input(), ".manifest.json"));
+        if let Some(budget) = &opts.budget {
+            let charge = budget.charge(&opts.estimate(&[upload.size()]));
+            check_budget(budget, &opts.catalog, charge, true)?;
+        }
+        let sdk = client::connect(settings).await?;
+        shutdown::listen();
+        return upload_file(&sdk, upload, &manifest_path, &opts).await;
+    }
+
+    if args.dry_run {
+        if args.from_url.is_some() {
+            return Err(Error::Usage(
+                "a URL can't be estimated before it's read".into(),
+            ));
+        }
+        let input = args
+            .input
+            .as_deref()
+            .expect("clap requires input, resume or from_url");
+        if input == Path::new("-") {
+            return Err(Error::Usage(
+                "stdin can't be estimated before it's read".into(),
+            ));
+        }
+        let redundancy = Redundancy::new(data_shards, parity_shards)?;
+        let pack_threshold = if opts.dedup { 0 } else { opts.pack_threshold };
+        let (files, estimate) =
+            estimate::plan(input, &opts.filter, redundancy, pack_threshold).await?;
+        if settings.output == Output::Json {
+            let files: Vec<_> = files
+                .iter()
+                .map(|(path, size)| json!({ "path": path, "size": size }))
+                .collect();
+            let result = json!({
+                "files": files,
+                "estimate": estimate::summary(&estimate, redundancy, settings.pricing.as_ref()),
+            });
+            return settings.output.print(&result, || {});
+        }
+        for (path, size) in &files {
+            println!("+ {path}\t{size}");
+        }
+        estimate::print_estimate(&estimate, redundancy, settings.pricing.as_ref());
+        return Ok(());
+    }
+
+    if let Some(budget) = &opts.budget {
+        let input = args.input.as_deref().unwrap_or(Path::new("-"));
+        // streams are charged once they have been read, and can't be asked
+        // on
+        if input == Path::new("-") {
+            check_budget(budget, &opts.catalog, 0.0, false)?;
+        } else {
+            let redundancy = Redundancy::new(data_shards, parity_shards)?;
+            let pack_threshold = if opts.dedup { 0 } else { opts.pack_threshold };
+            let (_, estimate) =
+                estimate::plan(input, &opts.filter, redundancy, pack_threshold).await?;
+            check_budget(budget, &opts.catalog, budget.charge(&estimate), true)?;
+        }
+    }
+
+    if args.passphrase {
+        let kdf = Kdf::default();
+        let passphrase = keys::read_passphrase(true)?;
+        let key = kdf.derive(&passphrase).await?;
+        opts.sealing = Some(Sealing::new(kdf, key));
+    }
+
+    if let Some(url) = args.from_url {
+        // the URL's last path segment names the upload, as a file name does
+        let file_name = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|segment| !segment.is_empty())
+            .map(PathBuf::from);
+        let manifest_path = match (args.manifest, &file_name) {
+            (Some(path), _) => path,
+            (None, Some(file_name)) => with_suffix(file_name, ".manifest.json"),
+            (None, None) => {
+                return Err(Error::Usage(
+                    "--manifest is required when the URL has no file name".into(),
+                ));
+            }
+        };
+        opts.source = Some(url.to_string());
+        opts.name = catalog_name(&opts.catalog, args.name, file_name.as_deref())?;
+        let sdk = client::connect(settings).await?;
+        return upload_url(&sdk, &url, &manifest_path, &opts).await;
+    }
+
+    let input = args.input.expect("clap requires input, resume or from_url");
+    if input == Path::new("-") {
+        let manifest_path = args.manifest.ok_or_else(|| {
+            Error::Usage("--manifest is required when uploading from stdin".into())
+        })?;
+        opts.name = catalog_name(&opts.catalog, args.name, None)?;
+        let sdk = client::connect(settings).await?;
+        return upload_stdin(&sdk, &manifest_path, &opts).await;
+    }
+
+    let manifest_path = args
+        .manifest
+        .unwrap_or_else(|| with_suffix(&input, ".manifest.json"));
+    opts.source = Some(input.display().to_string());
+    opts.name = catalog_name(&opts.catalog, args.name, Some(&input))?;
+    let sdk = client::connect(settings).await?;
+    if fs::metadata(&input).await?.is_dir() {
+        return upload_directory(&sdk, &input, &manifest_path, &opts).await;
+    }
+
+    if opts.dedup {
+        return upload_file_dedup(&sdk, &input, &manifest_path, &opts).await;
+    }
+
+    let checkpoint_path = with_suffix(&input, ".checkpoint");
+    info!(
+        "uploading file, checkpoint at {}",
+        checkpoint_path.display()
+    );
+    let upload = ResumableUpload::new(
+        input,
+        checkpoint_path,
+        opts.master_key(),
+        opts.kdf(),
+        opts.upload,
+    )
+    .await?;
+    shutdown::listen();
+    upload_file(&sdk, upload, &manifest_path, &opts).await
+}
+
+/// Returns the name to catalog an upload under: `name` if given, otherwise
+/// the input's file name. An object already catalogued under the name
+/// becomes a previous version once the upload is saved.
+fn catalog_name(catalog: &Catalog, name: Option<String>, input: Option<&Path>) -> Result<String> {
+    let name = match name {
+        Some(name) => name,
+        None => input
+            .and_then(|input| input.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| {
+                Error::Usage("--name is required when the input has no file name".into())
+            })?,
+    };
+    if catalog.contains(&name)? {
+        info!("{name} is already in the catalog; its manifest is kept as a previous version");
+    }
+    Ok(name)
+}
+
+/// Writes the manifest, sealing it if the upload used a passphrase, and
+/// records the upload in the catalog along with what it was charged.
+async fn save_manifest(
+    sdk: &Client,
+    manifest: AnyManifest,
+    path: &Path,
+    opts: &Options,
+    start: Instant,
+) -> Result<()> {
+    let stored = StoredManifest::new(manifest.clone(), opts.sealing.as_ref())?;
+    stored.save(path).await?;
+    info!("manifest saved to {}", path.display());
+    opts.catalog
+        .put(&opts.name, opts.source.as_deref(), &manifest, &stored)?;
+    opts.catalog
+        .add_labels(&opts.name, &opts.tags, &opts.metadata)?;
+    info!("catalogued as {}", opts.name);
+    let locator = match &stored {
+        StoredManifest::Sealed(sealed) if opts.locator => {
+            let options = UploadOptions::new(opts.upload.data_shards, opts.upload.parity_shards);
+            let locator = Locator::store(sdk, sealed, options).await?.encode()?;
+            info!("manifest stored on indexd");
+            Some(locator)
+        }
+        _ => None,
+    };
+    if let Some(budget) = &opts.budget {
+        let sizes: Vec<u64> = match &manifest {
+            AnyManifest::File(manifest) => vec![manifest.size],
+            AnyManifest::Directory(manifest) => {
+                manifest.files.iter().map(|f| f.manifest.size).collect()
+            }
+        };
+        let charge = budget.charge(&opts.estimate(&sizes));
+        opts.catalog.record_spending(&opts.name, charge)?;
+    }
+    let mut result = match &manifest {
+        AnyManifest::File(manifest) => json!({
+            "name": opts.name,
+            "manifest": path,
+            "size": manifest.size,
+            "slabs": manifest.slabs.len(),
+            "elapsed_ms": start.elapsed().as_millis() as u64,
+        }),
+        AnyManifest::Directory(manifest) => json!({
+            "name": opts.name,
+            "manifest": path,
+            "size": manifest.size(),
+            "files": manifest.files.len(),
+            "slabs": manifest.files.iter().map(|f| f.manifest.slabs.len()).sum::<usize>(),
+            "elapsed_ms": start.elapsed().as_millis() as u64,
+        }),
+    };
+    if let Some(locator) = &locator {
+        result["locator"] = json!(locator);
+    }
+    opts.output.print(&result, || {
+        if let Some(locator) = &locator {
+            println!("{locator}");
+        }
+    })
+}
+
+async fn upload_file(
+    sdk: &Client,
+    upload: ResumableUpload,
+    manifest_path: &Path,
+    opts: &Options,
+) -> Result<()> {
+    let checkpoint_path = upload.checkpoint_path().to_path_buf();
+    let (progress, bar) = progress_bar(upload.size(), upload.offset());
+
+    let start = Instant::now();
+    let manifest = upload.with_progress(progress).run(sdk).await?;
+    let _ = bar.await;
+    info!(
+        "upload of {} bytes complete in {}ms",
+        manifest.size,
+        start.elapsed().as_millis()
+    );
+
+    save_manifest(sdk, AnyManifest::File(manifest), manifest_path, opts, start).await?;
+    fs::remove_file(checkpoint_path).await?;
+    Ok(())
+}
+
+/// Uploads a file in one pass, skipping the chunks already stored. Unlike
+/// regular uploads this keeps no checkpoint: rerunning an interrupted
+/// upload reuses every chunk that was stored before it stopped.
+async fn upload_file_dedup(
+    sdk: &Client,
+    input: &Path,
+    manifest_path: &Path,
+    opts: &Options,
+) -> Result<()> {
+    info!("uploading file with deduplication");
+    let file = File::open(input).await?;
+    let (progress, bar) = progress_bar(file.metadata().await?.len(), 0);
+    let start = Instant::now();
+    let manifest = upload_dedup(sdk, file, opts.master_key(), opts, progress).await?;
+    let _ = bar.await;
+    info!(
+        "upload of {} bytes complete in {}ms",
+        manifest.size,
+        start.elapsed().as_millis()
+    );
+    save_manifest(sdk, AnyManifest::File(manifest), manifest_path, opts, start).await
+}
+
+async fn upload_dedup<R>(
+    sdk: &Client,
+    reader: R,
+    encryption_key: [u8; 32],
+    opts: &Options,
+    progress: Progress,
+) -> Result<Manifest>
+where
+    R: AsyncRead + Unpin,
+{
+    let (manifest, stats) = dedup::upload_dedup(
+        sdk,
+        &opts.catalog,
+        reader,
+        encryption_key,
+        opts.upload,
+        progress,
+    )
+    .await?;
+    info!(
+        "uploaded {} of {} chunks: {} new bytes, {} reused",
+        stats.new_chunks, stats.chunks, stats.new_bytes, stats.reused_bytes
+    );
+    Ok(manifest)
+}
+
+async fn upload_stdin(sdk: &Client, manifest_path: &Path, opts: &Options) -> Result<()> {
+    info!("uploading from stdin");
+    let start = Instant::now();
+    let manifest = if opts.dedup {
+        upload_dedup(
+            sdk,
+            io::stdin(),
+            opts.master_key(),
+            opts,
+            Progress::default(),
+        )
+        .await?
+    } else {
+        upload::upload_reader(
+            sdk,
+            io::stdin(),
+            opts.master_key(),
+            opts.upload,
+            Progress::default(),
+        )
+        .await?
+    };
+    info!(
+        "upload of {} bytes complete in {}ms",
+        manifest.size,
+        start.elapsed().as_millis()
+    );
+
+    save_manifest(sdk, AnyManifest::File(manifest), manifest_path, opts, start).await
+}
+
+/// Streams the object at `url`, over HTTP(S) or from an object store, into
+/// the upload without writing it to disk. Like stdin it is read in a single
+/// pass, so it can't be resumed.
+async fn upload_url(sdk: &Client, url: &Url, manifest_path: &Path, opts: &Options) -> Result<()> {
+    let source = source::open(url, &opts.network).await?;
+    info!("uploading from {}", source.url);
+    let (progress, bar) = progress_bar(source.size.unwrap_or(0), 0);
+    let start = Instant::now();
+    let manifest = if opts.dedup {
+        upload_dedup(sdk, source.reader, opts.master_key(), opts, progress).await?
+    } else {
+        upload::upload_reader(sdk, source.reader, opts.master_key(), opts.upload, progress).await?
+    };
+    let _ = bar.await;
+    // a connection dropped early can look like the end of the body
+    if let Some(size) = source.size.filter(|size| *size != manifest.size) {
+        return Err(Error::Integrity(format!(
+            "{url} announced {size} bytes but sent {}",
+            manifest.size
+        )));
+    }
+    info!(
+        "upload of {} bytes complete in {}ms",
+        manifest.size,
+        start.elapsed().as_millis()
+    );
+    save_manifest(sdk, AnyManifest::File(manifest), manifest_path, opts, start).await
+}
+
+/// Uploads every file under `root` and writes a single directory manifest.
+/// Files are uploaded one segment at a time, with as many files in flight
+/// as the shard budget allows. The files and the packs of small files take
+/// turns for the shards in flight, and small and large files are started
+/// alternately, so neither kind waits for all of the other.
+async fn upload_directory(
+    sdk: &Client,
+    root: &Path,
+    manifest_path: &Path,
+    opts: &Options,
+) -> Result<()> {
+    let tree = directory::walk_tree(root, &opts.filter, opts.links).await?;
+    let files = tree.files;
+    info!("uploading {} files from {}", files.len(), root.display());
+    // names are stored composed, so keep where each was read from
+    let paths = files
+        .iter()
+        .map(|rel| Ok((directory::to_manifest_path(rel)?, rel.as_path())))
+        .collect::<Result<Vec<_>>>()?;
+    normalize::check_upload(&paths)?;
+    let origins: HashMap<String, PathBuf> = paths
+        .into_iter()
+        .map(|(path, rel)| (path, rel.to_path_buf()))
+        .collect();
+    let mut links = Vec::with_capacity(tree.links.len());
+    for rel in &tree.links {
+        links.push(directory::read_link(root, rel).await?);
+    }
+    let mut total = 0;
+    let mut small = Vec::new();
+    let mut small_total = 0;
+    let mut large = Vec::new();
+    for (i, rel) in files.into_iter().enumerate() {
+        let size = fs::metadata(root.join(&rel)).await?.len();
+        total += size;
+        // deduplicated uploads already share slabs between files
+        if size <= opts.pack_threshold && !opts.dedup {
+            small.push(rel);
+            small_total += size;
+        } else {
+            large.push((size, (i, rel, size)));
+        }
+    }
+    let large = scheduler::interleave(large);
+    let (progress, bar) = progress_bar(total, 0);
+    let scheduler = Scheduler::new(opts.upload.max_inflight_shards);
+    let bars = (opts.upload.inflight_segments() > 1).then(|| file_bars(&scheduler));
+
+    let checkpoints = with_suffix(manifest_path, ".checkpoints");
+    fs::create_dir_all(&checkpoints).await?;
+
+    // every file gets its own key; with a passphrase they are derived from
+    // the master key so the sealed manifest is the only thing to protect
+    let keys = opts.keys();
+    let keys = keys.as_ref().map(|keys| keys as &dyn KeyProvider);
+    let start = Instant::now();
+    let packer = sdk
+        .clone()
+        .with_lane(scheduler.lane("small files", small_total));
+    let packed = pack_files(&packer, root, small, opts, keys, progress.clone());
+    let uploaded = stream::iter(large)
+        .map(|(i, rel, size)| {
+            let progress = progress.clone();
+            let checkpoint_path = checkpoints.join(format!("{i}.json"));
+            let sdk = sdk
+                .clone()
+                .with_lane(scheduler.lane(rel.display().to_string(), size));
+            async move {
+                let encryption_key = match keys {
+                    Some(keys) => keys.key(&format!("file/{i}")).await?,
+                    None => rand::random(),
+                };
+                let entry = Entry {
+                    encryption_key,
+                    checkpoint_path,
+                    rel,
+                    progress,
+                };
+                upload_entry(&sdk, root, entry, opts).await
+            }
+        })
+        .buffered(opts.upload.inflight_segments())
+        .try_collect::<Vec<FileEntry>>();
+    let (mut entries, packed) = futures::try_join!(uploaded, packed)?;
+    drop(progress);
+    let _ = bar.await;
+    drop((packer, scheduler));
+    if let Some(bars) = bars {
+        let _ = bars.await;
+    }
+
+    entries.extend(packed);
+    if opts.preserve {
+        for entry in &mut entries {
+            let path = match origins.get(&entry.path) {
+                Some(rel) => root.join(rel),
+                None => directory::resolve(root, &entry.path)?,
+            };
+            directory::record_attributes(&path, entry).await?;
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let manifest = DirectoryManifest::new(entries).with_links(links);
+    info!(
+        "upload of {} files ({} bytes) complete in {}ms",
+        manifest.files.len(),
+        manifest.size(),
+        start.elapsed().as_millis()
+    );
+    save_manifest(
+        sdk,
+        AnyManifest::Directory(manifest),
+        manifest_path,
+        opts,
+        start,
+    )
+    .await?;
+    fs::remove_dir_all(&checkpoints).await?;
+    Ok(())
+}
+
+/// Uploads the small files of a directory into shared packs.
+async fn pack_files(
+    sdk: &Client,
+    root: &Path,
+    files: Vec<PathBuf>,
+    opts: &Options,
+    keys: Option<&dyn KeyProvider>,
+    progress: Progress,
+) -> Result<Vec<FileEntry>> {
+    if !files.is_empty() {
+        info!("packing {} small files", files.len());
+    }
+    let mut packer = Packer::new(sdk, opts.upload, keys, progress);
+    let mut entries = Vec::with_capacity(files.len());
+    for rel in files {
+        let path = directory::to_manifest_path(&rel)?;
+        let data = fs::read(root.join(&rel)).await?;
+        let modified = directory::modified(&fs::metadata(root.join(&rel)).await?);
+        entries.extend(packer.add(path, data, modified).await?);
+    }
+    entries.extend(packer.finish().await?);
+    Ok(entries)
+}
+
+/// A file queued for upload as part of a directory.
+struct Entry {
+    rel: PathBuf,
+    encryption_key: [u8; 32],
+    checkpoint_path: PathBuf,
+    progress: Progress,
+}
+
+async fn upload_entry(
+    sdk: &Client,
+    root: &Path,
+    entry: Entry,
+    opts: &Options,
+) -> Result<FileEntry> {
+    let Entry {
+        rel,
+        encryption_key,
+        checkpoint_path,
+        progress,
+    } = entry;
+    let path = directory::to_manifest_path(&rel)?;
+    let modified = directory::modified(&fs::metadata(root.join(&rel)).await?);
+    if opts.dedup {
+        let file = File::open(root.join(&rel)).await?;
+        let manifest = upload_dedup(sdk, file, encryption_key, opts, progress).await?;
+        info!("uploaded {path}");
+        return Ok(FileEntry::new(path, manifest, modified));
+    }
+    // as many files upload at once as there are segments in flight, one
+    // segment each, so each gets one segment's shards and its share of the
+    // memory; the rest of the options apply to every file as asked
+    let files = opts.upload.inflight_segments() as u64;
+    let options = opts
+        .upload
+        .with_max_inflight_shards(opts.upload.total_shards())
+        .with_max_memory(opts.upload.max_memory.map(|max_memory| max_memory / files));
+    let upload = ResumableUpload::new(
+        root.join(&rel),
+        checkpoint_path,
+        encryption_key,
+        None,
+        options,
+    )
+    .await?
+    .with_progress(progress);
+    let manifest = upload.run(sdk).await?;
+    info!("uploaded {path}");
+    Ok(FileEntry::new(path, manifest, modified))
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use indexd_utils::client::{self, Client};
+use indexd_utils::config::Settings;
+use indexd_utils::error::{Error, Result};
+use indexd_utils::health::{self, Threshold};
+use indexd_utils::manifest::{AnyManifest, Manifest};
+use indexd_utils::output::Output;
+use indexd_utils::telemetry::HostStats;
+use indexd_utils::verify::{self, Health};
+use log::{info, warn};
+use serde_json::json;
+
+use super::{load_manifest, progress_bar, targets};
+use crate::cli::VerifyArgs;
+
+pub async fn run(settings: &Settings, args: VerifyArgs) -> Result<()> {
+    let targets = targets(settings, args.manifest.as_deref(), &args.tags)?;
+    let sdk = client::connect(settings).await?;
+    let mut check = Check {
+        // without a catalog there are no host stats to estimate shards with
+        hosts: settings
+            .catalog()
+            .and_then(|catalog| catalog.host_stats())
+            .unwrap_or_default(),
+        threshold: None,
+        output: settings.output,
+    };
+
+    let (mut failed, mut breached) = (false, 0);
+    for target in &targets {
+        let manifest = load_manifest(settings, target).await?;
+        let name = target.display().to_string();
+        check.threshold = args
+            .min_healthy_shards
+            .or_else(|| settings.thresholds.get(&name));
+        match manifest {
+            AnyManifest::File(manifest) => {
+                let (ok, below) = verify_file(&sdk, &manifest, &name, &check).await?;
+                failed |= !ok;
+                breached += below as usize;
+            }
+            AnyManifest::Directory(manifest) => {
+                for entry in &manifest.files {
+                    let (ok, below) =
+                        verify_file(&sdk, &entry.manifest, &entry.path, &check).await?;
+                    failed |= !ok;
+                    breached += below as usize;
+                }
+            }
+        }
+    }
+    if failed {
+        return Err(Error::Integrity("verification failed".into()));
+    }
+    if breached > 0 {
+        return Err(Error::Integrity(format!(
+            "{breached} files have fewer healthy shards than required"
+        )));
+    }
+    Ok(())
+}
+
+/// What each file is checked against.
+struct Check {
+    hosts: BTreeMap<String, HostStats>,
+    threshold: Option<Threshold>,
+    output: Output,
+}
+
+/// Recovers every slab of the file without storing it, printing the health
+/// of each slab that is not healthy. Returns whether the file is intact and
+/// whether it has fewer healthy shards than the threshold requires.
+async fn verify_file(
+    sdk: &Client,
+    manifest: &Manifest,
+    name: &str,
+    check: &Check,
+) -> Result<(bool, bool)> {
+    let (progress, bar) = progress_bar(manifest.stored_size(), 0);
+    let report = verify::verify(sdk, manifest, &progress).await?;
+    drop(progress);
+    let _ = bar.await;
+    let assessment = health::assess(manifest, &report, &check.hosts, check.threshold);
+
+    let result = json!({
+        "name": name,
+        "healthy": report.count(Health::Healthy),
+        "degraded": report.count(Health::Degraded),
+        "unrecoverable": report.count(Health::Unrecoverable),
+        "checksum_ok": report.checksum_ok,
+        "health": assessment,
+        "slabs": report.slabs,
+    });
+    check.output.print(&result, || {
+        for slab in &report.slabs {
+            match (&slab.health, &slab.error) {
+                (Health::Healthy, _) => {}
+                (Health::Degraded, _) => println!("{name}: slab {}: degraded", slab.index),
+                (Health::Unrecoverable, Some(e)) => {
+                    println!("{name}: slab {}: unrecoverable: {e}", slab.index)
+                }
+                (Health::Unrecoverable, None) => {
+                    println!("{name}: slab {}: unrecoverable", slab.index)
+                }
+            }
+        }
+        println!(
+            "{name}: {} healthy, {} degraded, {} unrecoverable",
+            report.count(Health::Healthy),
+            report.count(Health::Degraded),
+            report.count(Health::Unrecoverable),
+        );
+        if let Some(required) = assessment.required_shards {
+            println!(
+                "{name}: {} healthy shards in the weakest slab, {required} required",
+                assessment.min_healthy_shards
+            );
+        }
+    })?;
+
+    if assessment.breached {
+        warn!("{name} has fewer healthy shards than required");
+    }
+
+    if report.count(Health::Unrecoverable) == 0 && !report.checksum_ok {
+        warn!("{name} does not match its checksum");
+    }
+    if report.is_ok() {
+        info!("{name} verified");
+    }
+    Ok((report.is_ok(), assessment.breached))
+}